@@ -3,11 +3,41 @@ use std::fs;
 use std::path::PathBuf;
 use std::process::Command;
 
+/// User window size when CRABV6_USER_WINDOW_KIB is unset
+const DEFAULT_USER_WINDOW_KIB: usize = 256;
+/// `user_bin/memory.x` puts text, data and bss in the first 192 KiB; the
+/// stack needs room above that
+const MIN_USER_WINDOW_KIB: usize = 256;
+
 fn main() {
+    println!("cargo:rerun-if-env-changed=CRABV6_USER_WINDOW_KIB");
+    println!("cargo:rerun-if-changed=user_bin/memory.x");
     println!("cargo:rerun-if-changed=user_bin/src/lib.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/raw.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/heap.rs");
     println!("cargo:rerun-if-changed=user_bin/src/bin/cat.rs");
     println!("cargo:rerun-if-changed=user_bin/src/bin/wc.rs");
     println!("cargo:rerun-if-changed=user_bin/src/bin/sh.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/init.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/ps.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/date.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/ls.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/echo.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/grep.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/head.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/tail.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/cp.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/mv.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/mkdir.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/rm.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/rmdir.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/hexdump.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/sort.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/uniq.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/kill.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/ed.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/fs.rs");
+    println!("cargo:rerun-if-changed=user_bin/src/bin/bench.rs");
     println!("cargo:rerun-if-changed=user_bin/Cargo.toml");
     println!("cargo:rerun-if-changed=user_bin/.cargo/config.toml");
 
@@ -15,7 +45,7 @@ fn main() {
     let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
     let target = "riscv64gc-unknown-none-elf";
 
-    // Build all user binaries (cat, wc, sh)
+    // Build all user binaries (cat, wc, sh, init, ps, date, ls, echo, grep, head, tail, cp, mv, mkdir, rm, rmdir, hexdump, sort, uniq, kill, ed, fs, bench)
     let user_manifest = manifest_dir.join("user_bin/Cargo.toml");
     let status = Command::new(&cargo)
         .current_dir(&manifest_dir)
@@ -27,7 +57,7 @@ fn main() {
             "--target",
             target,
             "-Z",
-            "build-std=core,compiler_builtins",
+            "build-std=core,alloc,compiler_builtins",
             "-Z",
             "build-std-features=compiler-builtins-mem",
         ])
@@ -41,6 +71,25 @@ fn main() {
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
     fs::create_dir_all(&out_dir).expect("failed to create OUT_DIR");
 
+    // Size of the window user programs run in, for src/process.rs
+    let window_kib = match env::var("CRABV6_USER_WINDOW_KIB") {
+        Ok(value) => value
+            .parse::<usize>()
+            .expect("CRABV6_USER_WINDOW_KIB must be a number of KiB"),
+        Err(_) => DEFAULT_USER_WINDOW_KIB,
+    };
+    if window_kib < MIN_USER_WINDOW_KIB || !window_kib.is_multiple_of(4) {
+        panic!(
+            "CRABV6_USER_WINDOW_KIB must be a multiple of 4 and at least {}",
+            MIN_USER_WINDOW_KIB
+        );
+    }
+    fs::write(
+        out_dir.join("user_window.rs"),
+        format!("pub const USER_WINDOW_KIB: usize = {};\n", window_kib),
+    )
+    .expect("failed to write user_window.rs");
+
     // Copy cat binary
     let cat_binary = manifest_dir
         .join("user_bin")
@@ -70,4 +119,204 @@ fn main() {
         .join("sh");
     let sh_out = out_dir.join("sh.bin");
     fs::copy(&sh_binary, &sh_out).expect("failed to copy sh binary");
+
+    // Copy init binary
+    let init_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("init");
+    let init_out = out_dir.join("init.bin");
+    fs::copy(&init_binary, &init_out).expect("failed to copy init binary");
+
+    // Copy ps binary
+    let ps_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("ps");
+    let ps_out = out_dir.join("ps.bin");
+    fs::copy(&ps_binary, &ps_out).expect("failed to copy ps binary");
+
+    // Copy date binary
+    let date_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("date");
+    let date_out = out_dir.join("date.bin");
+    fs::copy(&date_binary, &date_out).expect("failed to copy date binary");
+
+    // Copy ls binary
+    let ls_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("ls");
+    let ls_out = out_dir.join("ls.bin");
+    fs::copy(&ls_binary, &ls_out).expect("failed to copy ls binary");
+
+    // Copy echo binary
+    let echo_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("echo");
+    let echo_out = out_dir.join("echo.bin");
+    fs::copy(&echo_binary, &echo_out).expect("failed to copy echo binary");
+
+    // Copy grep binary
+    let grep_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("grep");
+    let grep_out = out_dir.join("grep.bin");
+    fs::copy(&grep_binary, &grep_out).expect("failed to copy grep binary");
+
+    // Copy head binary
+    let head_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("head");
+    let head_out = out_dir.join("head.bin");
+    fs::copy(&head_binary, &head_out).expect("failed to copy head binary");
+
+    // Copy tail binary
+    let tail_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("tail");
+    let tail_out = out_dir.join("tail.bin");
+    fs::copy(&tail_binary, &tail_out).expect("failed to copy tail binary");
+
+    // Copy cp binary
+    let cp_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("cp");
+    let cp_out = out_dir.join("cp.bin");
+    fs::copy(&cp_binary, &cp_out).expect("failed to copy cp binary");
+
+    // Copy mv binary
+    let mv_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("mv");
+    let mv_out = out_dir.join("mv.bin");
+    fs::copy(&mv_binary, &mv_out).expect("failed to copy mv binary");
+
+    // Copy mkdir binary
+    let mkdir_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("mkdir");
+    let mkdir_out = out_dir.join("mkdir.bin");
+    fs::copy(&mkdir_binary, &mkdir_out).expect("failed to copy mkdir binary");
+
+    // Copy rm binary
+    let rm_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("rm");
+    let rm_out = out_dir.join("rm.bin");
+    fs::copy(&rm_binary, &rm_out).expect("failed to copy rm binary");
+
+    // Copy rmdir binary
+    let rmdir_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("rmdir");
+    let rmdir_out = out_dir.join("rmdir.bin");
+    fs::copy(&rmdir_binary, &rmdir_out).expect("failed to copy rmdir binary");
+
+    // Copy hexdump binary
+    let hexdump_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("hexdump");
+    let hexdump_out = out_dir.join("hexdump.bin");
+    fs::copy(&hexdump_binary, &hexdump_out).expect("failed to copy hexdump binary");
+
+    // Copy sort binary
+    let sort_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("sort");
+    let sort_out = out_dir.join("sort.bin");
+    fs::copy(&sort_binary, &sort_out).expect("failed to copy sort binary");
+
+    // Copy uniq binary
+    let uniq_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("uniq");
+    let uniq_out = out_dir.join("uniq.bin");
+    fs::copy(&uniq_binary, &uniq_out).expect("failed to copy uniq binary");
+
+    // Copy kill binary
+    let kill_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("kill");
+    let kill_out = out_dir.join("kill.bin");
+    fs::copy(&kill_binary, &kill_out).expect("failed to copy kill binary");
+
+    // Copy ed binary
+    let ed_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("ed");
+    let ed_out = out_dir.join("ed.bin");
+    fs::copy(&ed_binary, &ed_out).expect("failed to copy ed binary");
+
+    // Copy fs binary
+    let fs_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("fs");
+    let fs_out = out_dir.join("fs.bin");
+    fs::copy(&fs_binary, &fs_out).expect("failed to copy fs binary");
+
+    // Copy bench binary
+    let bench_binary = manifest_dir
+        .join("user_bin")
+        .join("target")
+        .join(target)
+        .join("release")
+        .join("bench");
+    let bench_out = out_dir.join("bench.bin");
+    fs::copy(&bench_binary, &bench_out).expect("failed to copy bench binary");
 }
@@ -1,3 +1,6 @@
+/* User programs run in a window at 0x81200000 (USER_IMAGE_BASE in
+   src/process.rs), directly above RAM, so the kernel image, its heap and
+   its stack cannot grow into it; the linker fails first */
 MEMORY
 {
 	RAM : ORIGIN = 0x80200000, LENGTH = 16M
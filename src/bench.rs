@@ -0,0 +1,243 @@
+use alloc::{format, string::String, vec};
+use core::fmt::Write;
+
+use crate::fs::{self, BlockDevice, FsError};
+use crate::proc::PROCESS_TABLE;
+use crate::process::{self, USER_WINDOW_SIZE};
+use crate::rtc::TIMEBASE_HZ;
+
+/// Bytes moved by each sequential pass
+const SEQUENTIAL_BYTES: usize = 4 * 1024 * 1024;
+/// Bytes per sequential request
+const CHUNK: usize = 64 * 1024;
+/// Requests in each random pass
+const RANDOM_OPS: usize = 256;
+/// Bytes per random request through the filesystem
+const RANDOM_IO: usize = 4096;
+/// Times the caller is copied out of the user window and back
+const SWITCH_ROUNDS: usize = 64;
+
+/// Time and cycles spent on one kind of request
+#[derive(Default)]
+struct Sample {
+    bytes: usize,
+    ops: usize,
+    ticks: u64,
+    cycles: u64,
+}
+
+impl Sample {
+    /// Run `op` and charge it to this sample
+    fn time<T>(&mut self, bytes: usize, op: impl FnOnce() -> T) -> T {
+        let (start, start_cycles) = now();
+        let result = op();
+        let (end, end_cycles) = now();
+        self.ticks += end - start;
+        self.cycles += end_cycles - start_cycles;
+        self.bytes += bytes;
+        // Flushes add time but are not requests of their own
+        if bytes > 0 {
+            self.ops += 1;
+        }
+        result
+    }
+
+    fn report(&self, out: &mut impl Write, name: &str) {
+        let micros = self.ticks * 1_000_000 / TIMEBASE_HZ;
+        let kib_per_sec = (self.bytes as u64 / 1024) * TIMEBASE_HZ / self.ticks.max(1);
+        let _ = writeln!(
+            out,
+            "  {:<10} {:>6} KiB {:>8} us {:>8} KiB/s {:>10} cycles/op",
+            name,
+            self.bytes / 1024,
+            micros,
+            kib_per_sec,
+            self.cycles / self.ops.max(1) as u64
+        );
+    }
+}
+
+fn now() -> (u64, u64) {
+    (
+        riscv::register::time::read64(),
+        riscv::register::cycle::read64(),
+    )
+}
+
+/// xorshift64; a fixed seed keeps runs comparable
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+/// `bench switch`: copy the caller out of the user window and back, once
+/// the whole window at a time as every switch used to, and once only its
+/// live ranges as switches do now. The window is left as it was.
+pub fn switch(out: &mut impl Write) {
+    let (image_end, sp) = {
+        let mut table = PROCESS_TABLE.lock();
+        let Some(caller) = table.current_mut() else {
+            return;
+        };
+        (caller.image_end, unsafe { (*caller.kernel_stack.context()).sp })
+    };
+    let mut buf = vec![0u8; USER_WINDOW_SIZE];
+    let whole = [0..USER_WINDOW_SIZE];
+    let live = process::live_ranges(image_end, sp);
+    let live_bytes: usize = live.iter().map(|range| range.len()).sum();
+
+    let mut before = Sample::default();
+    let mut after = Sample::default();
+    for _ in 0..SWITCH_ROUNDS {
+        before.time(2 * USER_WINDOW_SIZE, || {
+            process::snapshot_user_ranges(&mut buf, &whole);
+            process::restore_user_ranges(&buf, &whole);
+        });
+        after.time(2 * live_bytes, || {
+            process::snapshot_user_ranges(&mut buf, &live);
+            process::restore_user_ranges(&buf, &live);
+        });
+    }
+    let _ = writeln!(out, "user window, out and back in {} times:", SWITCH_ROUNDS);
+    before.report(out, "whole");
+    after.report(out, "live");
+}
+
+/// `bench disk [device]`: sequential and random throughput on the raw
+/// device, then through a scratch file on the filesystem it holds,
+/// written to `out` as a table. The scratch file stays between runs.
+pub fn disk(device: &str, out: &mut impl Write) {
+    let device = device.strip_prefix("/dev/").unwrap_or(device);
+    let block = match crate::virtio::block::open(device) {
+        Ok(block) => block,
+        Err(err) => {
+            let _ = writeln!(out, "bench: cannot open /dev/{}: {:?}", device, err);
+            return;
+        }
+    };
+    let _ = writeln!(out, "{} block layer:", device);
+    if let Err(err) = block_layer(&block, out) {
+        let _ = writeln!(out, "bench: {}", err);
+    }
+
+    let Some(mount) = fs::mounts()
+        .into_iter()
+        .find(|mount| mount.device == device)
+    else {
+        let _ = writeln!(out, "{} is not mounted; skipping the filesystem pass", device);
+        return;
+    };
+    if mount.read_only {
+        let _ = writeln!(
+            out,
+            "{} is mounted read-only; skipping the filesystem pass",
+            device
+        );
+        return;
+    }
+    let path = if mount.point == "/" {
+        String::from("/.bench")
+    } else {
+        format!("{}/.bench", mount.point)
+    };
+    let _ = writeln!(out, "{} filesystem ({}):", device, path);
+    if let Err(err) = fs_layer(&path, out) {
+        let _ = writeln!(out, "bench: {}", err);
+    }
+}
+
+/// Writes put back the bytes just read, so the filesystem on the device
+/// is left as it was
+fn block_layer(device: &impl BlockDevice, out: &mut impl Write) -> Result<(), FsError> {
+    let block_size = device.block_size();
+    let per_chunk = (CHUNK / block_size).max(1);
+    let total = device.total_blocks() as usize;
+    if total == 0 {
+        return Ok(());
+    }
+    let chunks = (SEQUENTIAL_BYTES / (per_chunk * block_size)).min(total / per_chunk);
+    let writable = !device.read_only();
+    let mut buf = vec![0u8; per_chunk * block_size];
+
+    let mut read = Sample::default();
+    let mut write = Sample::default();
+    for chunk in 0..chunks {
+        let index = (chunk * per_chunk) as u32;
+        read.time(buf.len(), || device.read_blocks(index, &mut buf))?;
+        if writable {
+            write.time(buf.len(), || device.write_blocks(index, &buf))?;
+        }
+    }
+    read.report(out, "seq read");
+    if writable {
+        write.time(0, || device.flush())?;
+        write.report(out, "seq write");
+    }
+
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let mut read = Sample::default();
+    let mut write = Sample::default();
+    let block = &mut buf[..block_size];
+    for _ in 0..RANDOM_OPS {
+        let index = rng.below(total) as u32;
+        read.time(block_size, || device.read_block(index, block))?;
+        if writable {
+            write.time(block_size, || device.write_block(index, block))?;
+        }
+    }
+    read.report(out, "rand read");
+    if writable {
+        write.time(0, || device.flush())?;
+        write.report(out, "rand write");
+    }
+    Ok(())
+}
+
+/// TinyFs never reuses the blocks of a removed file, so a fresh scratch
+/// file each run would cost the disk 4 MiB a time. The file is kept
+/// instead: the first run grows it, later ones overwrite it in place.
+fn fs_layer(path: &str, out: &mut impl Write) -> Result<(), FsError> {
+    if fs::file_size(path).is_err() {
+        fs::create_file(path)?;
+    }
+    let mut buf = vec![0xa5u8; CHUNK];
+
+    let mut write = Sample::default();
+    for chunk in 0..SEQUENTIAL_BYTES / CHUNK {
+        write.time(CHUNK, || fs::write_at(path, chunk * CHUNK, &buf))?;
+    }
+    write.time(0, || fs::sync(path))?;
+    write.report(out, "seq write");
+
+    let mut handle = fs::open(path)?;
+    let mut read = Sample::default();
+    for chunk in 0..SEQUENTIAL_BYTES / CHUNK {
+        read.time(CHUNK, || fs::read_at(&mut handle, chunk * CHUNK, &mut buf))?;
+    }
+    read.report(out, "seq read");
+
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let slots = SEQUENTIAL_BYTES / RANDOM_IO;
+    let io = &mut buf[..RANDOM_IO];
+    let mut read = Sample::default();
+    for _ in 0..RANDOM_OPS {
+        let offset = rng.below(slots) * RANDOM_IO;
+        read.time(RANDOM_IO, || fs::read_at(&mut handle, offset, io))?;
+    }
+    read.report(out, "rand read");
+
+    let mut write = Sample::default();
+    for _ in 0..RANDOM_OPS {
+        let offset = rng.below(slots) * RANDOM_IO;
+        write.time(RANDOM_IO, || fs::write_at(path, offset, io))?;
+    }
+    write.time(0, || fs::sync(path))?;
+    write.report(out, "rand write");
+    Ok(())
+}
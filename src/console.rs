@@ -0,0 +1,195 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+use crate::fd::FdError;
+use crate::proc::Pid;
+use crate::scheduler::Scheduler;
+
+/// Number of terminals, for tables indexed by `Port::index`
+pub const PORTS: usize = crate::vt::TERMINALS + 2;
+
+/// A terminal the kernel can talk to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    /// The 16550 UART, always present
+    Uart,
+    /// Port 0 of the virtio console
+    Virtio,
+    /// Virtual terminal `n` (2 and up) sharing the UART with it
+    Vt(usize),
+    /// The second 16550 (`-device pci-serial`), if there is one
+    Uart1,
+}
+
+impl Port {
+    pub fn index(self) -> usize {
+        match self {
+            Port::Uart => 0,
+            Port::Virtio => 1,
+            Port::Vt(number) => number,
+            Port::Uart1 => PORTS - 1,
+        }
+    }
+
+    pub fn from_index(index: usize) -> Option<Port> {
+        match index {
+            0 => Some(Port::Uart),
+            1 => Some(Port::Virtio),
+            2..=crate::vt::TERMINALS => Some(Port::Vt(index)),
+            _ if index == PORTS - 1 => Some(Port::Uart1),
+            _ => None,
+        }
+    }
+
+    /// Device name under /dev
+    pub fn name(self) -> &'static str {
+        match self {
+            Port::Uart => "ttyS0",
+            Port::Virtio => "hvc0",
+            Port::Vt(number) => crate::vt::name(number),
+            Port::Uart1 => "ttyS1",
+        }
+    }
+}
+
+/// Where kernel messages go; the UART until `set_log_port` moves them
+static LOG_PORT: AtomicUsize = AtomicUsize::new(0);
+
+/// Typed on the virtio console and not yet read
+static VIRTIO_INPUT: Mutex<crate::vt::Input> = Mutex::new(crate::vt::Input::new());
+/// Typed on the second UART and not yet read
+static UART1_INPUT: Mutex<crate::vt::Input> = Mutex::new(crate::vt::Input::new());
+
+/// Bring up the virtio console if QEMU has one. Returns whether it is there.
+pub fn init() -> bool {
+    crate::virtio::console::init().is_ok()
+}
+
+pub fn is_present(port: Port) -> bool {
+    match port {
+        Port::Uart | Port::Vt(_) => true,
+        Port::Virtio => crate::virtio::console::is_present(),
+        Port::Uart1 => crate::uart::secondary().is_some(),
+    }
+}
+
+pub fn log_port() -> Port {
+    Port::from_index(LOG_PORT.load(Ordering::Relaxed)).unwrap_or(Port::Uart)
+}
+
+/// Send kernel messages to `port`; false if it is not present
+pub fn set_log_port(port: Port) -> bool {
+    if !is_present(port) {
+        return false;
+    }
+    LOG_PORT.store(port.index(), Ordering::Relaxed);
+    true
+}
+
+/// Print a kernel message on the log port
+pub fn write_log(s: &str) {
+    write(log_port(), s.as_bytes());
+}
+
+/// Write to `port`, turning `\n` into `\r\n`. If the virtio console or
+/// second UART is missing, or the virtio console is busy, the bytes go
+/// to the UART's first terminal instead of being lost.
+pub fn write(port: Port, bytes: &[u8]) {
+    if port == Port::Virtio {
+        let mut converted = Vec::with_capacity(bytes.len());
+        for &byte in bytes {
+            if byte == b'\n' {
+                converted.push(b'\r');
+            }
+            converted.push(byte);
+        }
+        if crate::virtio::console::write(&converted) {
+            return;
+        }
+    }
+    if port == Port::Uart1 && crate::uart::write_secondary(bytes) {
+        return;
+    }
+    match port {
+        Port::Vt(_) => crate::vt::write(port, bytes),
+        _ => crate::vt::write(Port::Uart, bytes),
+    }
+}
+
+/// The terminal a `/dev` path names, if it is one that exists:
+/// `/dev/console` and `/dev/ttyS0` are the UART, `/dev/ttyS1` the second
+/// UART, `/dev/hvc0` the virtio console and `/dev/tty2` and up the UART's
+/// other virtual terminals
+pub fn device_node(path: &str) -> Option<Port> {
+    let port = match path {
+        "/dev/console" | "/dev/ttyS0" => Port::Uart,
+        "/dev/ttyS1" => Port::Uart1,
+        "/dev/hvc0" => Port::Virtio,
+        _ => (2..=crate::vt::TERMINALS)
+            .map(Port::Vt)
+            .find(|port| path.strip_prefix("/dev/") == Some(port.name()))?,
+    };
+    is_present(port).then_some(port)
+}
+
+/// Read typed bytes from `port`; WouldBlock if there are none, 0 at a
+/// Ctrl-D
+pub fn read(port: Port, buf: &mut [u8]) -> Result<usize, FdError> {
+    match port {
+        Port::Virtio => VIRTIO_INPUT.lock().read(buf),
+        Port::Uart1 => UART1_INPUT.lock().read(buf),
+        _ => crate::vt::read(port, buf),
+    }
+}
+
+/// Wake `pid` when `port` next has input
+pub fn mark_waiting(port: Port, pid: Pid) {
+    match port {
+        Port::Virtio => VIRTIO_INPUT.lock().mark_waiting(pid),
+        Port::Uart1 => UART1_INPUT.lock().mark_waiting(pid),
+        _ => crate::vt::mark_waiting(port, pid),
+    }
+}
+
+/// Interrupt from the virtio console on `transport`: queue what was typed,
+/// with Ctrl-C/Ctrl-Z going to the console's foreground group as on the
+/// UART, and wake blocked readers
+pub fn handle_virtio_interrupt(transport: crate::virtio::Transport) {
+    let received = crate::virtio::console::handle_interrupt(transport);
+    receive(Port::Virtio, &VIRTIO_INPUT, received);
+}
+
+/// Interrupt on the second UART's line, which PCI devices may share
+pub fn handle_uart1_interrupt() {
+    receive(Port::Uart1, &UART1_INPUT, crate::uart::receive_secondary());
+}
+
+fn receive(port: Port, input: &Mutex<crate::vt::Input>, received: Vec<u8>) {
+    if received.is_empty() {
+        return;
+    }
+    let mut queued = Vec::new();
+    for byte in received {
+        let sig = match byte {
+            crate::signal::CTRL_C => Some(crate::signal::SIGINT),
+            crate::signal::CTRL_Z => Some(crate::signal::SIGTSTP),
+            _ => None,
+        };
+        // Without a foreground process group these are ordinary input
+        if !sig.is_some_and(|sig| crate::signal::signal_foreground(port, sig)) {
+            queued.push(byte);
+        }
+    }
+    let waiting = {
+        let mut input = input.lock();
+        for byte in queued {
+            input.push(byte);
+        }
+        input.take_waiting()
+    };
+    for pid in waiting {
+        Scheduler::unblock(pid);
+    }
+    crate::interrupts::signal_event();
+}
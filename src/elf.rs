@@ -1,5 +1,4 @@
-use alloc::vec::Vec;
-use const_default::ConstDefault;
+use alloc::{string::String, vec, vec::Vec};
 use core::mem::size_of;
 
 const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
@@ -7,7 +6,45 @@ const ELF_CLASS_64: u8 = 2;
 const ELF_DATA_LSB: u8 = 1;
 const ELF_VERSION: u8 = 1;
 
+const ET_EXEC: u16 = 2;
+/// Position-independent executable
+const ET_DYN: u16 = 3;
+const EM_RISCV: u16 = 243;
+
 const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const PT_TLS: u32 = 7;
+
+// Dynamic section tags
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_REL: u64 = 17;
+const DT_JMPREL: u64 = 23;
+
+const R_RISCV_NONE: u32 = 0;
+const R_RISCV_RELATIVE: u32 = 3;
+/// Size of an Elf64_Rela entry
+const RELA_SIZE: usize = 24;
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+/// Sizes of an Elf64_Shdr and an Elf64_Sym
+const SECTION_HEADER_SIZE: usize = 64;
+const SYMBOL_SIZE: usize = 24;
+/// Symbols read per chunk while searching the table
+const SYMBOL_CHUNK: usize = 64;
+/// Longest symbol name returned; longer ones are cut short
+const SYMBOL_NAME_MAX: usize = 128;
+
+// Program header flags
+pub const PF_X: u32 = 0x1;
+pub const PF_W: u32 = 0x2;
+
+/// Granularity segment permissions will be mapped at
+const PAGE_SIZE: u64 = 4096;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ElfError {
@@ -16,7 +53,51 @@ pub enum ElfError {
     UnsupportedEncoding,
     UnsupportedVersion,
     Truncated,
+    /// Not a static executable: a relocatable object, core file or
+    /// something that needs a dynamic linker
     UnsupportedType,
+    /// Built for another architecture
+    UnsupportedMachine,
+    /// A segment is both writable and executable, or a writable and an
+    /// executable segment share a page
+    WritableExecutable,
+    /// A PIE relocation other than R_RISCV_RELATIVE, which would need
+    /// symbol lookup
+    UnsupportedRelocation,
+    /// A relocation patches memory outside the loaded segments
+    RelocationOutOfRange,
+    /// A segment's bytes run past the end of the file, it has more file
+    /// bytes than memory bytes, or its addresses wrap around
+    SegmentOutOfBounds,
+    /// Two loadable segments claim the same addresses
+    OverlappingSegments,
+    /// The image reaches into the user stack at the top of the window
+    OverlapsStack,
+    /// More than one PT_TLS, an alignment that is not a power of two, or
+    /// initial values outside the loaded segments
+    BadTls,
+}
+
+impl core::fmt::Display for ElfError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let msg = match self {
+            ElfError::BadMagic => "not an ELF file",
+            ElfError::UnsupportedClass => "not a 64-bit ELF file",
+            ElfError::UnsupportedEncoding => "not little-endian",
+            ElfError::UnsupportedVersion => "unsupported ELF version",
+            ElfError::Truncated => "truncated ELF file",
+            ElfError::UnsupportedType => "not a static executable",
+            ElfError::UnsupportedMachine => "not a RISC-V binary",
+            ElfError::WritableExecutable => "segment is writable and executable",
+            ElfError::UnsupportedRelocation => "unsupported relocation type",
+            ElfError::RelocationOutOfRange => "relocation outside the image",
+            ElfError::SegmentOutOfBounds => "segment outside the file",
+            ElfError::OverlappingSegments => "overlapping segments",
+            ElfError::OverlapsStack => "image overlaps the stack",
+            ElfError::BadTls => "bad thread-local storage segment",
+        };
+        f.write_str(msg)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -29,15 +110,35 @@ pub struct Segment {
     pub flags: u32,
 }
 
+/// A word to rebase once a PIE's load address is known: the word at
+/// link-time address `offset` becomes the load bias plus `addend`
+#[derive(Debug, Clone, Copy)]
+pub struct Relocation {
+    pub offset: u64,
+    pub addend: u64,
+}
+
+/// The function an address falls in, for crash reports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    /// Bytes from the start of the function
+    pub offset: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct ElfFile {
     pub entry: u64,
     pub segments: Vec<Segment>,
-    pub data: Vec<u8>,
+    /// Empty for fixed-address executables
+    pub relocations: Vec<Relocation>,
+    /// Template for each thread's TLS block: `file_size` bytes of
+    /// initial values at `vaddr`, inside a PT_LOAD, then zeroes
+    pub tls: Option<Segment>,
 }
 
 #[repr(C)]
-#[derive(ConstDefault, Debug, Clone)]
+#[derive(Debug, Clone)]
 struct Elf64Header {
     ident: [u8; 16],
     r#type: u16,
@@ -59,35 +160,44 @@ impl TryFrom<&[u8; 64]> for Elf64Header {
     type Error = ElfError;
 
     fn try_from(value: &[u8; 64]) -> Result<Self, Self::Error> {
-        let mut hdr = Elf64Header::DEFAULT;
-
-        hdr.ident.copy_from_slice(&value[0..16]);
-        if hdr.ident[0..4] != ELF_MAGIC {
+        let mut ident = [0u8; 16];
+        ident.copy_from_slice(&value[0..16]);
+        if ident[0..4] != ELF_MAGIC {
             return Err(ElfError::BadMagic);
         }
-        if hdr.ident[4] != ELF_CLASS_64 {
+        if ident[4] != ELF_CLASS_64 {
             return Err(ElfError::UnsupportedClass);
         }
-        if hdr.ident[5] != ELF_DATA_LSB {
+        if ident[5] != ELF_DATA_LSB {
             return Err(ElfError::UnsupportedEncoding);
         }
-        if hdr.ident[6] != ELF_VERSION {
+        if ident[6] != ELF_VERSION {
             return Err(ElfError::UnsupportedVersion);
         }
 
-        hdr.r#type = u16::from_le_bytes(value[16..18].try_into().unwrap());
-        hdr.machine = u16::from_le_bytes(value[18..20].try_into().unwrap());
-        hdr.version = u32::from_le_bytes(value[20..24].try_into().unwrap());
-        hdr.entry = u64::from_le_bytes(value[24..32].try_into().unwrap());
-        hdr.phoff = u64::from_le_bytes(value[32..40].try_into().unwrap());
-        hdr.shoff = u64::from_le_bytes(value[40..48].try_into().unwrap());
-        hdr.flags = u32::from_le_bytes(value[48..52].try_into().unwrap());
-        hdr.ehsize = u16::from_le_bytes(value[52..54].try_into().unwrap());
-        hdr.phentsize = u16::from_le_bytes(value[54..56].try_into().unwrap());
-        hdr.phnum = u16::from_le_bytes(value[56..58].try_into().unwrap());
-        hdr.shentsize = u16::from_le_bytes(value[58..60].try_into().unwrap());
-        hdr.shnum = u16::from_le_bytes(value[60..62].try_into().unwrap());
-        hdr.shstrndx = u16::from_le_bytes(value[62..64].try_into().unwrap());
+        let hdr = Self {
+            ident,
+            r#type: u16::from_le_bytes(value[16..18].try_into().unwrap()),
+            machine: u16::from_le_bytes(value[18..20].try_into().unwrap()),
+            version: u32::from_le_bytes(value[20..24].try_into().unwrap()),
+            entry: u64::from_le_bytes(value[24..32].try_into().unwrap()),
+            phoff: u64::from_le_bytes(value[32..40].try_into().unwrap()),
+            shoff: u64::from_le_bytes(value[40..48].try_into().unwrap()),
+            flags: u32::from_le_bytes(value[48..52].try_into().unwrap()),
+            ehsize: u16::from_le_bytes(value[52..54].try_into().unwrap()),
+            phentsize: u16::from_le_bytes(value[54..56].try_into().unwrap()),
+            phnum: u16::from_le_bytes(value[56..58].try_into().unwrap()),
+            shentsize: u16::from_le_bytes(value[58..60].try_into().unwrap()),
+            shnum: u16::from_le_bytes(value[60..62].try_into().unwrap()),
+            shstrndx: u16::from_le_bytes(value[62..64].try_into().unwrap()),
+        };
+
+        if hdr.machine != EM_RISCV {
+            return Err(ElfError::UnsupportedMachine);
+        }
+        if hdr.r#type != ET_EXEC && hdr.r#type != ET_DYN {
+            return Err(ElfError::UnsupportedType);
+        }
 
         Ok(hdr)
     }
@@ -121,49 +231,320 @@ impl From<&[u8; 56]> for Elf64ProgramHeader {
 }
 
 impl ElfFile {
+    /// Parse an image already in memory
     pub fn parse(data: &[u8]) -> Result<Self, ElfError> {
-        if data.len() < size_of::<Elf64Header>() {
-            return Err(ElfError::Truncated);
-        }
-        let mut hdr_buf = [0u8; size_of::<Elf64Header>()];
-        hdr_buf.copy_from_slice(&data[..size_of::<Elf64Header>()]);
-        let header = Elf64Header::try_from(&hdr_buf)?;
+        Self::read(data.len(), |offset, buf| {
+            let bytes = offset
+                .checked_add(buf.len())
+                .and_then(|end| data.get(offset..end))
+                .ok_or(ElfError::Truncated)?;
+            buf.copy_from_slice(bytes);
+            Ok(())
+        })
+    }
+
+    /// Parse a `len`-byte file through `read_at`, which fills its buffer
+    /// from a file offset. Only the headers and the relocation table are
+    /// read; segment contents stay in the file for the loader to stream.
+    pub fn read<E: From<ElfError>>(
+        len: usize,
+        mut read_at: impl FnMut(usize, &mut [u8]) -> Result<(), E>,
+    ) -> Result<Self, E> {
+        let header = read_header(len, &mut read_at)?;
 
         if header.phentsize as usize != size_of::<Elf64ProgramHeader>() {
-            return Err(ElfError::UnsupportedVersion);
+            return Err(ElfError::UnsupportedVersion.into());
         }
 
         let mut segments = Vec::new();
-        let phoff = header.phoff as usize;
+        let mut dynamic = None;
+        let mut tls = None;
         let phentsize = header.phentsize as usize;
         let phcount = header.phnum as usize;
+        let table = read_range(
+            len,
+            &mut read_at,
+            header.phoff,
+            (phcount * phentsize) as u64,
+        )?;
 
-        if phoff + phcount * phentsize > data.len() {
-            return Err(ElfError::Truncated);
-        }
-
-        for idx in 0..phcount {
-            let start = phoff + idx * phentsize;
-            let end = start + phentsize;
+        for entry in table.chunks_exact(phentsize) {
             let mut buf = [0u8; size_of::<Elf64ProgramHeader>()];
-            buf.copy_from_slice(&data[start..end]);
+            buf.copy_from_slice(entry);
             let ph = Elf64ProgramHeader::from(&buf);
-            if ph.r#type == PT_LOAD {
-                segments.push(Segment {
+            match ph.r#type {
+                PT_LOAD => segments.push(Segment {
                     vaddr: ph.vaddr,
                     mem_size: ph.memsz,
                     file_size: ph.filesz,
                     file_offset: ph.offset,
                     align: ph.align,
                     flags: ph.flags,
-                });
+                }),
+                PT_DYNAMIC => dynamic = Some((ph.offset, ph.filesz)),
+                PT_TLS if tls.is_some() => return Err(ElfError::BadTls.into()),
+                PT_TLS => {
+                    tls = Some(Segment {
+                        vaddr: ph.vaddr,
+                        mem_size: ph.memsz,
+                        file_size: ph.filesz,
+                        file_offset: ph.offset,
+                        align: ph.align.max(1),
+                        flags: ph.flags,
+                    })
+                }
+                // There is no dynamic linker to hand the image to
+                PT_INTERP => return Err(ElfError::UnsupportedType.into()),
+                _ => {}
             }
         }
 
+        check_segments(&segments, len)?;
+        check_write_xor_execute(&segments)?;
+        if let Some(tls) = &tls {
+            check_tls(tls, &segments)?;
+        }
+
+        let relocations = match dynamic {
+            Some((offset, size)) if header.r#type == ET_DYN => {
+                let dynamic = read_range(len, &mut read_at, offset, size)?;
+                parse_relocations(len, &mut read_at, &segments, &dynamic)?
+            }
+            _ => Vec::new(),
+        };
+
         Ok(Self {
             entry: header.entry,
             segments,
-            data: data.to_vec(),
+            relocations,
+            tls,
         })
     }
 }
+
+fn read_header<E: From<ElfError>>(
+    len: usize,
+    read_at: &mut impl FnMut(usize, &mut [u8]) -> Result<(), E>,
+) -> Result<Elf64Header, E> {
+    let mut hdr_buf = [0u8; size_of::<Elf64Header>()];
+    if len < hdr_buf.len() {
+        return Err(ElfError::Truncated.into());
+    }
+    read_at(0, &mut hdr_buf)?;
+    Ok(Elf64Header::try_from(&hdr_buf)?)
+}
+
+/// The function symbol at or nearest below link-time address `addr` in a
+/// `len`-byte file read through `read_at`. None if the file was stripped
+/// of its symbol table or no function starts at or below `addr`.
+pub fn symbolize<E: From<ElfError>>(
+    len: usize,
+    mut read_at: impl FnMut(usize, &mut [u8]) -> Result<(), E>,
+    addr: u64,
+) -> Result<Option<Symbol>, E> {
+    let header = read_header(len, &mut read_at)?;
+    if header.shoff == 0 || header.shentsize as usize != SECTION_HEADER_SIZE {
+        return Ok(None);
+    }
+    let sections = read_range(
+        len,
+        &mut read_at,
+        header.shoff,
+        (header.shnum as usize * SECTION_HEADER_SIZE) as u64,
+    )?;
+    let mut sections = sections.chunks_exact(SECTION_HEADER_SIZE);
+    let Some(symtab) = sections
+        .clone()
+        .find(|section| u32_at(section, 4) == SHT_SYMTAB)
+    else {
+        return Ok(None);
+    };
+    // sh_link names the string table holding the symbol names
+    let strtab = sections
+        .nth(u32_at(symtab, 40) as usize)
+        .ok_or(ElfError::Truncated)?;
+    let (strtab_offset, strtab_size) = (u64_at(strtab, 24), u64_at(strtab, 32));
+
+    // The table can be large; read it a chunk at a time
+    let (table_offset, table_size) = (u64_at(symtab, 24), u64_at(symtab, 32));
+    let count = table_size as usize / SYMBOL_SIZE;
+    let mut best: Option<(u32, u64)> = None;
+    for first in (0..count).step_by(SYMBOL_CHUNK) {
+        let chunk_len = (count - first).min(SYMBOL_CHUNK);
+        let chunk = read_range(
+            len,
+            &mut read_at,
+            table_offset.saturating_add((first * SYMBOL_SIZE) as u64),
+            (chunk_len * SYMBOL_SIZE) as u64,
+        )?;
+        for sym in chunk.chunks_exact(SYMBOL_SIZE) {
+            let name = u32_at(sym, 0);
+            let value = u64_at(sym, 8);
+            let is_function = sym[4] & 0xf == STT_FUNC;
+            if is_function && value <= addr && best.is_none_or(|(_, best)| value > best) {
+                best = Some((name, value));
+            }
+        }
+    }
+    let Some((name, value)) = best else {
+        return Ok(None);
+    };
+
+    if name as u64 >= strtab_size {
+        return Err(ElfError::Truncated.into());
+    }
+    let name_len = (strtab_size - name as u64).min(SYMBOL_NAME_MAX as u64);
+    let bytes = read_range(
+        len,
+        &mut read_at,
+        strtab_offset.saturating_add(name as u64),
+        name_len,
+    )?;
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    Ok(Some(Symbol {
+        name: String::from_utf8_lossy(&bytes[..end]).into_owned(),
+        offset: addr - value,
+    }))
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// Read `size` bytes at `offset`, which must lie inside the `len`-byte file
+fn read_range<E: From<ElfError>>(
+    len: usize,
+    read_at: &mut impl FnMut(usize, &mut [u8]) -> Result<(), E>,
+    offset: u64,
+    size: u64,
+) -> Result<Vec<u8>, E> {
+    let end = offset.checked_add(size);
+    if end.is_none_or(|end| end > len as u64) {
+        return Err(ElfError::Truncated.into());
+    }
+    let mut buf = vec![0u8; size as usize];
+    read_at(offset as usize, &mut buf)?;
+    Ok(buf)
+}
+
+/// The RELA table the dynamic section points at. A static PIE only has
+/// R_RISCV_RELATIVE entries; anything else needs symbols resolved.
+fn parse_relocations<E: From<ElfError>>(
+    len: usize,
+    read_at: &mut impl FnMut(usize, &mut [u8]) -> Result<(), E>,
+    segments: &[Segment],
+    dynamic: &[u8],
+) -> Result<Vec<Relocation>, E> {
+    let mut table = None;
+    let mut table_size = 0;
+    let mut entry_size = RELA_SIZE;
+    for entry in dynamic.chunks_exact(16) {
+        let tag = u64::from_le_bytes(entry[0..8].try_into().unwrap());
+        let value = u64::from_le_bytes(entry[8..16].try_into().unwrap());
+        match tag {
+            DT_NULL => break,
+            DT_RELA => table = Some(value),
+            DT_RELASZ => table_size = value,
+            DT_RELAENT => entry_size = value as usize,
+            DT_REL | DT_JMPREL => return Err(ElfError::UnsupportedRelocation.into()),
+            _ => {}
+        }
+    }
+    let Some(vaddr) = table else {
+        return Ok(Vec::new());
+    };
+    if entry_size != RELA_SIZE {
+        return Err(ElfError::UnsupportedRelocation.into());
+    }
+    // The dynamic section gives link-time addresses, not file offsets
+    let start = segments
+        .iter()
+        .find(|seg| seg.vaddr <= vaddr && vaddr < seg.vaddr + seg.file_size)
+        .map(|seg| seg.file_offset + (vaddr - seg.vaddr))
+        .ok_or(ElfError::Truncated)?;
+    let table = read_range(len, read_at, start, table_size)?;
+
+    let mut relocations = Vec::new();
+    for entry in table.chunks_exact(RELA_SIZE) {
+        let offset = u64::from_le_bytes(entry[0..8].try_into().unwrap());
+        let info = u64::from_le_bytes(entry[8..16].try_into().unwrap());
+        let addend = u64::from_le_bytes(entry[16..24].try_into().unwrap());
+        match info as u32 {
+            R_RISCV_NONE => {}
+            R_RISCV_RELATIVE => relocations.push(Relocation { offset, addend }),
+            _ => return Err(ElfError::UnsupportedRelocation.into()),
+        }
+    }
+    Ok(relocations)
+}
+
+/// Every segment's file bytes must lie inside the file, and no two
+/// segments may share an address; the loader copies them blindly.
+fn check_segments(segments: &[Segment], file_len: usize) -> Result<(), ElfError> {
+    for seg in segments {
+        let file_end = seg.file_offset.checked_add(seg.file_size);
+        if file_end.is_none_or(|end| end > file_len as u64)
+            || seg.file_size > seg.mem_size
+            || seg.vaddr.checked_add(seg.mem_size).is_none()
+        {
+            return Err(ElfError::SegmentOutOfBounds);
+        }
+    }
+    for (index, seg) in segments.iter().enumerate() {
+        let overlaps = segments[index + 1..].iter().any(|other| {
+            seg.vaddr < other.vaddr + other.mem_size && other.vaddr < seg.vaddr + seg.mem_size
+        });
+        if overlaps {
+            return Err(ElfError::OverlappingSegments);
+        }
+    }
+    Ok(())
+}
+
+/// The TLS template is copied out of the loaded image, so it must lie
+/// inside one segment; each block is placed at the template's alignment.
+fn check_tls(tls: &Segment, segments: &[Segment]) -> Result<(), ElfError> {
+    let template_end = tls.vaddr.checked_add(tls.file_size);
+    let in_image = segments.iter().any(|seg| {
+        seg.vaddr <= tls.vaddr && template_end.is_some_and(|end| end <= seg.vaddr + seg.mem_size)
+    });
+    if !in_image || tls.file_size > tls.mem_size || !tls.align.is_power_of_two() {
+        return Err(ElfError::BadTls);
+    }
+    Ok(())
+}
+
+/// Refuse images that break W^X. Without an MMU (and with PMP owned by
+/// the M-mode firmware) the hardware cannot enforce it yet, but keeping
+/// writable and executable segments on separate pages means page
+/// permissions can later be applied to any binary that loads today.
+fn check_write_xor_execute(segments: &[Segment]) -> Result<(), ElfError> {
+    let pages = |seg: &Segment| {
+        let last = seg.vaddr.saturating_add(seg.mem_size.max(1) - 1);
+        seg.vaddr / PAGE_SIZE..=last / PAGE_SIZE
+    };
+    for seg in segments.iter().filter(|seg| seg.flags & PF_W != 0) {
+        if seg.flags & PF_X != 0 {
+            return Err(ElfError::WritableExecutable);
+        }
+        let (first, last) = pages(seg).into_inner();
+        let shares_code_page = segments.iter().any(|other| {
+            other.flags & PF_X != 0 && *pages(other).start() <= last && first <= *pages(other).end()
+        });
+        if shares_code_page {
+            return Err(ElfError::WritableExecutable);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "elf_tests.rs"]
+mod tests;
@@ -0,0 +1,468 @@
+//! ELF parser tests, built only by the host crate in `tinyfs_host/`.
+
+use super::*;
+
+const PF_R: u32 = 0x4;
+const PT_NULL: u32 = 0;
+const HEADER_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+fn header(r#type: u16, machine: u16, entry: u64, phnum: u16) -> Vec<u8> {
+    let mut image = Vec::with_capacity(HEADER_SIZE);
+    image.extend_from_slice(&ELF_MAGIC);
+    image.extend_from_slice(&[ELF_CLASS_64, ELF_DATA_LSB, ELF_VERSION]);
+    image.resize(16, 0);
+    image.extend_from_slice(&r#type.to_le_bytes());
+    image.extend_from_slice(&machine.to_le_bytes());
+    image.extend_from_slice(&1u32.to_le_bytes());
+    image.extend_from_slice(&entry.to_le_bytes());
+    image.extend_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+    image.extend_from_slice(&0u64.to_le_bytes());
+    image.extend_from_slice(&0u32.to_le_bytes());
+    image.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+    image.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+    image.extend_from_slice(&phnum.to_le_bytes());
+    image.extend_from_slice(&[0; 6]);
+    image
+}
+
+fn phdr(kind: u32, flags: u32, offset: u64, vaddr: u64, file_size: u64, mem_size: u64) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(PHDR_SIZE);
+    entry.extend_from_slice(&kind.to_le_bytes());
+    entry.extend_from_slice(&flags.to_le_bytes());
+    for field in [offset, vaddr, vaddr, file_size, mem_size, PAGE_SIZE] {
+        entry.extend_from_slice(&field.to_le_bytes());
+    }
+    entry
+}
+
+/// Overwrite the little-endian u64 at `offset`
+fn set_u64(image: &mut [u8], offset: usize, value: u64) {
+    image[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Byte offset of program header `index`'s field at `field`
+fn phdr_field(index: usize, field: usize) -> usize {
+    HEADER_SIZE + index * PHDR_SIZE + field
+}
+
+/// Fixed-address executable: code at the window base, data on the next
+/// page with some bss after it
+fn executable() -> Vec<u8> {
+    let mut image = header(ET_EXEC, EM_RISCV, 0x8040_0000, 2);
+    image.extend(phdr(
+        PT_LOAD,
+        PF_R | PF_X,
+        0x1000,
+        0x8040_0000,
+        0x100,
+        0x100,
+    ));
+    image.extend(phdr(PT_LOAD, PF_R | PF_W, 0x1100, 0x8040_1000, 0x20, 0x80));
+    image.resize(0x1120, 0);
+    image
+}
+
+/// Static PIE linked at 0 whose data segment holds one pointer to rebase
+fn pie(reloc_type: u64) -> Vec<u8> {
+    let mut image = header(ET_DYN, EM_RISCV, 0x10, 3);
+    image.extend(phdr(PT_LOAD, PF_R | PF_X, 0, 0, 0x300, 0x300));
+    image.extend(phdr(PT_LOAD, PF_R | PF_W, 0x300, 0x1000, 0x10, 0x10));
+    image.extend(phdr(PT_DYNAMIC, PF_R, 0x200, 0x200, 0x40, 0x40));
+    image.resize(0x310, 0);
+    let dynamic = [
+        DT_RELA,
+        0x280,
+        DT_RELASZ,
+        RELA_SIZE as u64,
+        DT_RELAENT,
+        RELA_SIZE as u64,
+    ];
+    for (index, value) in dynamic.into_iter().enumerate() {
+        set_u64(&mut image, 0x200 + index * 8, value);
+    }
+    for (index, value) in [0x1008, reloc_type, 0x40].into_iter().enumerate() {
+        set_u64(&mut image, 0x280 + index * 8, value);
+    }
+    image
+}
+
+/// `executable()` with a PT_TLS whose template is the start of the data
+/// segment
+fn with_tls(file_size: u64, mem_size: u64, align: u64) -> Vec<u8> {
+    let mut image = executable();
+    image[56..58].copy_from_slice(&3u16.to_le_bytes());
+    let tls = phdr(PT_TLS, PF_R, 0x1100, 0x8040_1000, file_size, mem_size);
+    let start = HEADER_SIZE + 2 * PHDR_SIZE;
+    image[start..start + PHDR_SIZE].copy_from_slice(&tls);
+    set_u64(&mut image, phdr_field(2, 48), align);
+    image
+}
+
+/// `executable()` with a symbol table: `main` and `helper` are functions,
+/// `DATA` an object past both
+fn with_symbols() -> Vec<u8> {
+    let mut image = executable();
+    let strtab_offset = image.len();
+    image.extend_from_slice(b"\0main\0helper\0DATA\0");
+    let symtab_offset = image.len();
+    let symbols = [
+        (0u32, 0, 0u64, 0u64),
+        (1, STT_FUNC, 0x8040_0000, 0x40),
+        (6, STT_FUNC, 0x8040_0040, 0x40),
+        (13, 1, 0x8040_0090, 8),
+    ];
+    for (name, kind, value, size) in symbols {
+        image.extend_from_slice(&name.to_le_bytes());
+        image.extend_from_slice(&[kind, 0, 1, 0]);
+        image.extend_from_slice(&value.to_le_bytes());
+        image.extend_from_slice(&size.to_le_bytes());
+    }
+    let shoff = image.len();
+    let sections = [
+        (0, 0, 0, 0),
+        (SHT_SYMTAB, symtab_offset, symbols.len() * SYMBOL_SIZE, 2),
+        (3, strtab_offset, symtab_offset - strtab_offset, 0),
+    ];
+    for (kind, offset, size, link) in sections {
+        let mut section = [0u8; SECTION_HEADER_SIZE];
+        section[4..8].copy_from_slice(&kind.to_le_bytes());
+        section[24..32].copy_from_slice(&(offset as u64).to_le_bytes());
+        section[32..40].copy_from_slice(&(size as u64).to_le_bytes());
+        section[40..44].copy_from_slice(&(link as u32).to_le_bytes());
+        image.extend_from_slice(&section);
+    }
+    set_u64(&mut image, 40, shoff as u64);
+    image[58..60].copy_from_slice(&(SECTION_HEADER_SIZE as u16).to_le_bytes());
+    image[60..62].copy_from_slice(&(sections.len() as u16).to_le_bytes());
+    image
+}
+
+fn symbolize_image(image: &[u8], addr: u64) -> Result<Option<Symbol>, ElfError> {
+    symbolize(
+        image.len(),
+        |offset, buf: &mut [u8]| {
+            buf.copy_from_slice(&image[offset..offset + buf.len()]);
+            Ok(())
+        },
+        addr,
+    )
+}
+
+/// Deterministic xorshift so failures reproduce
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+/// What the loader relies on whenever `parse` succeeds
+fn assert_loadable(elf: &ElfFile, file_len: usize) {
+    for seg in &elf.segments {
+        assert!(seg.file_offset + seg.file_size <= file_len as u64);
+        assert!(seg.file_size <= seg.mem_size);
+    }
+    for (index, seg) in elf.segments.iter().enumerate() {
+        for other in &elf.segments[index + 1..] {
+            assert!(
+                seg.vaddr + seg.mem_size <= other.vaddr
+                    || other.vaddr + other.mem_size <= seg.vaddr
+            );
+        }
+    }
+}
+
+#[test]
+fn parses_fixed_address_executable() {
+    let elf = ElfFile::parse(&executable()).unwrap();
+    assert_eq!(elf.entry, 0x8040_0000);
+    assert_eq!(elf.segments.len(), 2);
+    assert_eq!(elf.segments[1].mem_size, 0x80);
+    assert!(elf.relocations.is_empty());
+}
+
+#[test]
+fn rejects_other_machines_and_types() {
+    let mut image = executable();
+    image[18..20].copy_from_slice(&62u16.to_le_bytes()); // EM_X86_64
+    assert_eq!(
+        ElfFile::parse(&image).unwrap_err(),
+        ElfError::UnsupportedMachine
+    );
+
+    let mut image = executable();
+    image[16..18].copy_from_slice(&1u16.to_le_bytes()); // ET_REL
+    assert_eq!(
+        ElfFile::parse(&image).unwrap_err(),
+        ElfError::UnsupportedType
+    );
+
+    let mut image = executable();
+    image[4] = 1; // ELFCLASS32
+    assert_eq!(
+        ElfFile::parse(&image).unwrap_err(),
+        ElfError::UnsupportedClass
+    );
+
+    let mut image = executable();
+    image[..4].copy_from_slice(b"\x7fELG");
+    assert_eq!(ElfFile::parse(&image).unwrap_err(), ElfError::BadMagic);
+}
+
+#[test]
+fn rejects_program_headers_past_end_of_file() {
+    let mut image = executable();
+    image[56..58].copy_from_slice(&u16::MAX.to_le_bytes());
+    assert_eq!(ElfFile::parse(&image).unwrap_err(), ElfError::Truncated);
+
+    let mut image = executable();
+    set_u64(&mut image, 32, u64::MAX - 8);
+    assert_eq!(ElfFile::parse(&image).unwrap_err(), ElfError::Truncated);
+}
+
+#[test]
+fn rejects_segments_outside_the_file() {
+    // File bytes run past the end
+    let mut image = executable();
+    set_u64(&mut image, phdr_field(1, 32), 0x40);
+    assert_eq!(
+        ElfFile::parse(&image).unwrap_err(),
+        ElfError::SegmentOutOfBounds
+    );
+
+    // Offset plus size wraps
+    let mut image = executable();
+    set_u64(&mut image, phdr_field(1, 8), u64::MAX);
+    assert_eq!(
+        ElfFile::parse(&image).unwrap_err(),
+        ElfError::SegmentOutOfBounds
+    );
+
+    // More file bytes than memory bytes
+    let mut image = executable();
+    set_u64(&mut image, phdr_field(0, 40), 0x80);
+    assert_eq!(
+        ElfFile::parse(&image).unwrap_err(),
+        ElfError::SegmentOutOfBounds
+    );
+
+    // Addresses wrap
+    let mut image = executable();
+    set_u64(&mut image, phdr_field(1, 16), u64::MAX - 0x10);
+    assert_eq!(
+        ElfFile::parse(&image).unwrap_err(),
+        ElfError::SegmentOutOfBounds
+    );
+}
+
+#[test]
+fn rejects_overlapping_segments() {
+    let mut image = executable();
+    // Data starts inside the code segment
+    set_u64(&mut image, phdr_field(1, 16), 0x8040_00f0);
+    assert_eq!(
+        ElfFile::parse(&image).unwrap_err(),
+        ElfError::OverlappingSegments
+    );
+}
+
+#[test]
+fn rejects_writable_and_executable_memory() {
+    // One segment both writable and executable
+    let mut image = executable();
+    let flags = phdr_field(1, 4);
+    image[flags..flags + 4].copy_from_slice(&(PF_R | PF_W | PF_X).to_le_bytes());
+    assert_eq!(
+        ElfFile::parse(&image).unwrap_err(),
+        ElfError::WritableExecutable
+    );
+
+    // Data right after the code, on the same page
+    let mut image = executable();
+    set_u64(&mut image, phdr_field(1, 16), 0x8040_0100);
+    assert_eq!(
+        ElfFile::parse(&image).unwrap_err(),
+        ElfError::WritableExecutable
+    );
+}
+
+#[test]
+fn ignores_other_program_header_types() {
+    let mut image = executable();
+    image[56..58].copy_from_slice(&3u16.to_le_bytes());
+    let extra = phdr(PT_NULL, 0, u64::MAX, u64::MAX, u64::MAX, 0);
+    image.splice(
+        HEADER_SIZE + 2 * PHDR_SIZE..HEADER_SIZE + 2 * PHDR_SIZE,
+        extra,
+    );
+    assert_eq!(ElfFile::parse(&image).unwrap().segments.len(), 2);
+}
+
+#[test]
+fn read_leaves_segment_contents_in_the_file() {
+    let image = executable();
+    let mut bytes_read = 0;
+    let elf = ElfFile::read(image.len(), |offset, buf: &mut [u8]| {
+        assert!(
+            offset + buf.len() <= 0x1000,
+            "read segment bytes at {offset:#x}"
+        );
+        buf.copy_from_slice(&image[offset..offset + buf.len()]);
+        bytes_read += buf.len();
+        Ok::<(), ElfError>(())
+    })
+    .unwrap();
+    assert_eq!(elf.segments.len(), 2);
+    assert_eq!(bytes_read, HEADER_SIZE + 2 * PHDR_SIZE);
+}
+
+#[test]
+fn symbolizes_addresses_to_the_nearest_function() {
+    let image = with_symbols();
+    let symbol = |addr| symbolize_image(&image, addr).unwrap();
+    assert_eq!(
+        symbol(0x8040_0000),
+        Some(Symbol {
+            name: "main".into(),
+            offset: 0
+        })
+    );
+    assert_eq!(symbol(0x8040_0052).unwrap().name, "helper");
+    assert_eq!(symbol(0x8040_0052).unwrap().offset, 0x12);
+    // Objects are not code; the nearest function below is still helper
+    assert_eq!(symbol(0x8040_0094).unwrap().name, "helper");
+    assert_eq!(symbol(0x8000_0000), None);
+}
+
+#[test]
+fn symbolize_without_a_symbol_table() {
+    assert_eq!(symbolize_image(&executable(), 0x8040_0010), Ok(None));
+
+    let mut image = with_symbols();
+    // sh_link points past the section table
+    let len = image.len();
+    image[len - 2 * SECTION_HEADER_SIZE + 40] = 9;
+    assert_eq!(
+        symbolize_image(&image, 0x8040_0010),
+        Err(ElfError::Truncated)
+    );
+}
+
+#[test]
+fn parses_the_tls_template() {
+    let elf = ElfFile::parse(&with_tls(0x10, 0x30, 16)).unwrap();
+    let tls = elf.tls.unwrap();
+    assert_eq!(
+        (tls.vaddr, tls.file_size, tls.mem_size),
+        (0x8040_1000, 0x10, 0x30)
+    );
+    assert_eq!(tls.align, 16);
+    // Only the PT_LOADs are loaded
+    assert_eq!(elf.segments.len(), 2);
+    assert!(ElfFile::parse(&executable()).unwrap().tls.is_none());
+}
+
+#[test]
+fn rejects_bad_tls_segments() {
+    // Alignment that is not a power of two
+    let image = with_tls(0x10, 0x30, 24);
+    assert_eq!(ElfFile::parse(&image).unwrap_err(), ElfError::BadTls);
+    // More initial values than the block holds
+    let image = with_tls(0x40, 0x30, 8);
+    assert_eq!(ElfFile::parse(&image).unwrap_err(), ElfError::BadTls);
+    // Template runs past the data segment
+    let image = with_tls(0x90, 0x90, 8);
+    assert_eq!(ElfFile::parse(&image).unwrap_err(), ElfError::BadTls);
+}
+
+#[test]
+fn collects_pie_relocations() {
+    let elf = ElfFile::parse(&pie(R_RISCV_RELATIVE as u64)).unwrap();
+    assert_eq!(elf.relocations.len(), 1);
+    assert_eq!(elf.relocations[0].offset, 0x1008);
+    assert_eq!(elf.relocations[0].addend, 0x40);
+
+    // R_RISCV_64 needs a symbol
+    assert_eq!(
+        ElfFile::parse(&pie(2)).unwrap_err(),
+        ElfError::UnsupportedRelocation
+    );
+
+    let mut image = pie(R_RISCV_RELATIVE as u64);
+    set_u64(&mut image, 0x208, 0xf000); // DT_RELA outside every segment
+    assert_eq!(ElfFile::parse(&image).unwrap_err(), ElfError::Truncated);
+
+    let mut image = pie(R_RISCV_RELATIVE as u64);
+    set_u64(&mut image, 0x218, u64::MAX); // DT_RELASZ
+    assert_eq!(ElfFile::parse(&image).unwrap_err(), ElfError::Truncated);
+}
+
+#[test]
+fn rejects_pie_needing_dynamic_linker() {
+    let mut image = pie(R_RISCV_RELATIVE as u64);
+    image[phdr_field(2, 0)..phdr_field(2, 4)].copy_from_slice(&PT_INTERP.to_le_bytes());
+    assert_eq!(
+        ElfFile::parse(&image).unwrap_err(),
+        ElfError::UnsupportedType
+    );
+}
+
+#[test]
+fn every_truncation_is_an_error() {
+    for image in [executable(), pie(R_RISCV_RELATIVE as u64)] {
+        for len in 0..image.len() {
+            assert!(
+                ElfFile::parse(&image[..len]).is_err(),
+                "prefix of {} bytes",
+                len
+            );
+        }
+    }
+}
+
+/// Corpus of randomly corrupted headers: parsing must never panic, and
+/// anything it accepts must be safe for the loader to copy
+#[test]
+fn corrupted_headers_never_panic() {
+    let mut state = 0x2545_f491_4f6c_dd1d;
+    let corpus = [executable(), pie(R_RISCV_RELATIVE as u64)];
+    for round in 0..4000 {
+        let mut image = corpus[round % corpus.len()].clone();
+        let header_bytes = HEADER_SIZE + 3 * PHDR_SIZE;
+        for _ in 0..1 + next_random(&mut state) % 4 {
+            let offset = (next_random(&mut state) as usize) % header_bytes.min(image.len());
+            image[offset] = next_random(&mut state) as u8;
+        }
+        if let Ok(elf) = ElfFile::parse(&image) {
+            assert_loadable(&elf, image.len());
+        }
+    }
+}
+
+/// Edge values in every program header field
+#[test]
+fn extreme_program_header_fields_never_panic() {
+    let values = [
+        0,
+        1,
+        0x7fff_ffff,
+        u32::MAX as u64,
+        u64::MAX / 2,
+        u64::MAX - 1,
+        u64::MAX,
+    ];
+    for base in [executable(), pie(R_RISCV_RELATIVE as u64)] {
+        for field in (8..PHDR_SIZE).step_by(8) {
+            for index in 0..2 {
+                for value in values {
+                    let mut image = base.clone();
+                    set_u64(&mut image, phdr_field(index, field), value);
+                    if let Ok(elf) = ElfFile::parse(&image) {
+                        assert_loadable(&elf, image.len());
+                    }
+                }
+            }
+        }
+    }
+}
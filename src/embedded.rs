@@ -3,24 +3,144 @@ use crate::println;
 pub const CAT_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/cat.bin"));
 pub const WC_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/wc.bin"));
 pub const SH_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/sh.bin"));
+pub const INIT_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/init.bin"));
+pub const PS_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/ps.bin"));
+pub const DATE_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/date.bin"));
+pub const LS_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/ls.bin"));
+pub const ECHO_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/echo.bin"));
+pub const GREP_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/grep.bin"));
+pub const HEAD_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/head.bin"));
+pub const TAIL_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/tail.bin"));
+pub const CP_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/cp.bin"));
+pub const MV_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/mv.bin"));
+pub const MKDIR_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/mkdir.bin"));
+pub const RM_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/rm.bin"));
+pub const RMDIR_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/rmdir.bin"));
+pub const HEXDUMP_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/hexdump.bin"));
+pub const SORT_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/sort.bin"));
+pub const UNIQ_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/uniq.bin"));
+pub const KILL_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/kill.bin"));
+pub const ED_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/ed.bin"));
+pub const FS_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/fs.bin"));
+pub const BENCH_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/bench.bin"));
 
 pub fn install_embedded_bins() {
     println!("Installing embedded binaries...");
 
     // Install cat
-    if let Err(err) = crate::fs::write_file("/bin/cat", CAT_BIN) {
+    if let Err(err) = crate::fs::atomic_write("/bin/cat", CAT_BIN) {
         println!("Failed to install /bin/cat: {}", err);
     }
 
     // Install wc
-    if let Err(err) = crate::fs::write_file("/bin/wc", WC_BIN) {
+    if let Err(err) = crate::fs::atomic_write("/bin/wc", WC_BIN) {
         println!("Failed to install /bin/wc: {}", err);
     }
 
     // Install sh
-    if let Err(err) = crate::fs::write_file("/bin/sh", SH_BIN) {
+    if let Err(err) = crate::fs::atomic_write("/bin/sh", SH_BIN) {
         println!("Failed to install /bin/sh: {}", err);
     }
 
-    println!("Installed embedded binaries: cat, wc, sh");
+    // Install init
+    if let Err(err) = crate::fs::atomic_write("/bin/init", INIT_BIN) {
+        println!("Failed to install /bin/init: {}", err);
+    }
+
+    // Install ps
+    if let Err(err) = crate::fs::atomic_write("/bin/ps", PS_BIN) {
+        println!("Failed to install /bin/ps: {}", err);
+    }
+
+    // Install date
+    if let Err(err) = crate::fs::atomic_write("/bin/date", DATE_BIN) {
+        println!("Failed to install /bin/date: {}", err);
+    }
+
+    // Install ls
+    if let Err(err) = crate::fs::atomic_write("/bin/ls", LS_BIN) {
+        println!("Failed to install /bin/ls: {}", err);
+    }
+
+    // Install echo
+    if let Err(err) = crate::fs::atomic_write("/bin/echo", ECHO_BIN) {
+        println!("Failed to install /bin/echo: {}", err);
+    }
+
+    // Install grep
+    if let Err(err) = crate::fs::atomic_write("/bin/grep", GREP_BIN) {
+        println!("Failed to install /bin/grep: {}", err);
+    }
+
+    // Install head
+    if let Err(err) = crate::fs::atomic_write("/bin/head", HEAD_BIN) {
+        println!("Failed to install /bin/head: {}", err);
+    }
+
+    // Install tail
+    if let Err(err) = crate::fs::atomic_write("/bin/tail", TAIL_BIN) {
+        println!("Failed to install /bin/tail: {}", err);
+    }
+
+    // Install cp
+    if let Err(err) = crate::fs::atomic_write("/bin/cp", CP_BIN) {
+        println!("Failed to install /bin/cp: {}", err);
+    }
+
+    // Install mv
+    if let Err(err) = crate::fs::atomic_write("/bin/mv", MV_BIN) {
+        println!("Failed to install /bin/mv: {}", err);
+    }
+
+    // Install mkdir
+    if let Err(err) = crate::fs::atomic_write("/bin/mkdir", MKDIR_BIN) {
+        println!("Failed to install /bin/mkdir: {}", err);
+    }
+
+    // Install rm
+    if let Err(err) = crate::fs::atomic_write("/bin/rm", RM_BIN) {
+        println!("Failed to install /bin/rm: {}", err);
+    }
+
+    // Install rmdir
+    if let Err(err) = crate::fs::atomic_write("/bin/rmdir", RMDIR_BIN) {
+        println!("Failed to install /bin/rmdir: {}", err);
+    }
+
+    // Install hexdump
+    if let Err(err) = crate::fs::atomic_write("/bin/hexdump", HEXDUMP_BIN) {
+        println!("Failed to install /bin/hexdump: {}", err);
+    }
+
+    // Install sort
+    if let Err(err) = crate::fs::atomic_write("/bin/sort", SORT_BIN) {
+        println!("Failed to install /bin/sort: {}", err);
+    }
+
+    // Install uniq
+    if let Err(err) = crate::fs::atomic_write("/bin/uniq", UNIQ_BIN) {
+        println!("Failed to install /bin/uniq: {}", err);
+    }
+
+    // Install kill
+    if let Err(err) = crate::fs::atomic_write("/bin/kill", KILL_BIN) {
+        println!("Failed to install /bin/kill: {}", err);
+    }
+
+    // Install ed
+    if let Err(err) = crate::fs::atomic_write("/bin/ed", ED_BIN) {
+        println!("Failed to install /bin/ed: {}", err);
+    }
+
+    // Install fs
+    if let Err(err) = crate::fs::atomic_write("/bin/fs", FS_BIN) {
+        println!("Failed to install /bin/fs: {}", err);
+    }
+
+    // Install bench
+    if let Err(err) = crate::fs::atomic_write("/bin/bench", BENCH_BIN) {
+        println!("Failed to install /bin/bench: {}", err);
+    }
+
+    println!("Installed embedded binaries: cat, wc, sh, init, ps, date, ls, echo, grep, head, tail, cp, mv, mkdir, rm, rmdir, hexdump, sort, uniq, kill, ed, fs, bench");
 }
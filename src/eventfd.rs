@@ -0,0 +1,155 @@
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::fd::FdError;
+use crate::proc::Pid;
+use crate::scheduler::Scheduler;
+
+/// Reads return 1 and decrement instead of draining the counter
+pub const EFD_SEMAPHORE: usize = 0x1;
+/// Close on spawn; the same bit as open's close-on-spawn flag
+pub const EFD_CLOEXEC: usize = 0x40;
+
+/// Largest counter value; a write that would pass it blocks
+const EVENT_MAX: u64 = u64::MAX - 1;
+
+/// Global event table
+pub static EVENT_TABLE: Mutex<EventTable> = Mutex::new(EventTable::new());
+
+/// Event file descriptor: reads and writes move 8-byte native-endian
+/// counter values
+#[derive(Clone)]
+pub struct EventFd {
+    pub event_id: usize,
+}
+
+impl EventFd {
+    pub fn new(event_id: usize) -> Self {
+        Self { event_id }
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, FdError> {
+        let Some(out) = buf.get_mut(..8) else {
+            return Err(FdError::InvalidArgument);
+        };
+        let value = EVENT_TABLE.lock().take(self.event_id)?;
+        out.copy_from_slice(&value.to_ne_bytes());
+        Ok(8)
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, FdError> {
+        let bytes: [u8; 8] = buf
+            .get(..8)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(FdError::InvalidArgument)?;
+        EVENT_TABLE.lock().add(self.event_id, u64::from_ne_bytes(bytes))?;
+        Ok(8)
+    }
+}
+
+/// Event table; ids stay stable while any descriptor refers to them
+pub struct EventTable {
+    events: Vec<Option<Event>>,
+}
+
+impl EventTable {
+    pub const fn new() -> Self {
+        Self { events: Vec::new() }
+    }
+
+    /// Create an event with counter `initval` and return its ID
+    pub fn create_event(&mut self, initval: u64, flags: usize) -> usize {
+        let event = Event {
+            counter: initval,
+            semaphore: flags & EFD_SEMAPHORE != 0,
+            refcount: 1,
+            waiters: Vec::new(),
+        };
+        if let Some(i) = self.events.iter().position(Option::is_none) {
+            self.events[i] = Some(event);
+            return i;
+        }
+        self.events.push(Some(event));
+        self.events.len() - 1
+    }
+
+    fn event_mut(&mut self, event_id: usize) -> Result<&mut Event, FdError> {
+        self.events
+            .get_mut(event_id)
+            .and_then(Option::as_mut)
+            .ok_or(FdError::BadFd)
+    }
+
+    /// Increment refcount when cloning/duplicating an event descriptor
+    pub fn incref(&mut self, event_id: usize) -> Result<(), FdError> {
+        self.event_mut(event_id)?.refcount += 1;
+        Ok(())
+    }
+
+    /// Take the counter (or 1 of it in semaphore mode); WouldBlock at 0
+    pub fn take(&mut self, event_id: usize) -> Result<u64, FdError> {
+        let event = self.event_mut(event_id)?;
+        if event.counter == 0 {
+            return Err(FdError::WouldBlock);
+        }
+        let value = if event.semaphore { 1 } else { event.counter };
+        event.counter -= value;
+        event.wake();
+        Ok(value)
+    }
+
+    /// Add to the counter; WouldBlock if it would pass the maximum
+    pub fn add(&mut self, event_id: usize, value: u64) -> Result<(), FdError> {
+        if value > EVENT_MAX {
+            return Err(FdError::InvalidArgument);
+        }
+        let event = self.event_mut(event_id)?;
+        if value > EVENT_MAX - event.counter {
+            return Err(FdError::WouldBlock);
+        }
+        event.counter += value;
+        if value > 0 {
+            event.wake();
+        }
+        Ok(())
+    }
+
+    /// Register a process that will block on this event
+    pub fn mark_waiting(&mut self, event_id: usize, pid: Pid) -> Result<(), FdError> {
+        let event = self.event_mut(event_id)?;
+        if !event.waiters.contains(&pid) {
+            event.waiters.push(pid);
+        }
+        Ok(())
+    }
+
+    /// Drop one descriptor reference; the last one frees the event
+    pub fn close(&mut self, event_id: usize) -> Result<(), FdError> {
+        let event = self.event_mut(event_id)?;
+        event.refcount = event.refcount.saturating_sub(1);
+        if event.refcount == 0 {
+            self.events[event_id] = None;
+            while matches!(self.events.last(), Some(None)) {
+                self.events.pop();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A counter that writers add to and readers drain. Readers and writers
+/// wait in one list: either side making progress can unblock the other.
+struct Event {
+    counter: u64,
+    semaphore: bool,
+    refcount: usize,
+    waiters: Vec<Pid>,
+}
+
+impl Event {
+    fn wake(&mut self) {
+        for pid in core::mem::take(&mut self.waiters) {
+            Scheduler::unblock(pid);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use alloc::{format, string::String, vec::Vec};
+
+use riscv::register::{
+    scause, sepc,
+    sstatus::{self, SPP},
+    stval,
+};
+use riscv_rt::TrapFrame;
+
+use crate::klog;
+use crate::proc::{INVALID_PID, PROCESS_TABLE, Pid};
+use crate::scheduler::Scheduler;
+use crate::signal::{SIGBUS, SIGILL, SIGSEGV, SIGTRAP};
+
+/// First bytes of a core file
+pub const CORE_MAGIC: &[u8; 4] = b"CORE";
+
+/// Name and killing signal for a synchronous exception cause
+fn describe(cause: usize) -> (&'static str, usize) {
+    match cause {
+        0 => ("instruction address misaligned", SIGBUS),
+        1 => ("instruction access fault", SIGSEGV),
+        2 => ("illegal instruction", SIGILL),
+        3 => ("breakpoint", SIGTRAP),
+        4 => ("load address misaligned", SIGBUS),
+        5 => ("load access fault", SIGSEGV),
+        6 => ("store address misaligned", SIGBUS),
+        7 => ("store access fault", SIGSEGV),
+        12 => ("instruction page fault", SIGSEGV),
+        13 => ("load page fault", SIGSEGV),
+        15 => ("store page fault", SIGSEGV),
+        _ => ("unknown exception", SIGSEGV),
+    }
+}
+
+/// Build the core file for the faulting current process:
+/// `CORE`, then little-endian pid u32, scause, stval and sepc u64s, the
+/// 32 saved register words of its `UserContext`, a range count u32 and
+/// each live window range as `[offset u32][len u32][bytes]`.
+fn core_image(pid: Pid, cause: usize, fault_addr: usize, pc: usize) -> Option<Vec<u8>> {
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.get_mut(pid)?;
+    let context = unsafe { *process.kernel_stack.context() };
+    let words = unsafe {
+        core::slice::from_raw_parts(
+            &context as *const crate::process::UserContext as *const usize,
+            core::mem::size_of::<crate::process::UserContext>() / core::mem::size_of::<usize>(),
+        )
+    };
+    let ranges = crate::process::live_ranges(process.image_end, context.sp);
+
+    let mut core = Vec::new();
+    core.extend_from_slice(CORE_MAGIC);
+    core.extend_from_slice(&(pid as u32).to_le_bytes());
+    for value in [cause, fault_addr, pc] {
+        core.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+    for &word in words {
+        core.extend_from_slice(&(word as u64).to_le_bytes());
+    }
+    let ranges: Vec<_> = ranges.into_iter().filter(|range| !range.is_empty()).collect();
+    core.extend_from_slice(&(ranges.len() as u32).to_le_bytes());
+    // The faulting process is resident, so its memory is the live window
+    let mut window = alloc::vec![0u8; crate::process::USER_WINDOW_SIZE];
+    crate::process::snapshot_user_window(&mut window);
+    for range in ranges {
+        core.extend_from_slice(&(range.start as u32).to_le_bytes());
+        core.extend_from_slice(&(range.len() as u32).to_le_bytes());
+        core.extend_from_slice(&window[range]);
+    }
+    Some(core)
+}
+
+/// Write `/core.<pid>`, owned by the process's user. Returns the path.
+fn dump_core(
+    pid: Pid,
+    cause: usize,
+    fault_addr: usize,
+    pc: usize,
+) -> Result<String, crate::fs::FsError> {
+    let image = core_image(pid, cause, fault_addr, pc).ok_or(crate::fs::FsError::NotFound)?;
+    let path = format!("/core.{}", pid);
+    crate::fs::write_file(&path, &image)?;
+    let uid = PROCESS_TABLE.lock().get(pid).map_or(crate::proc::ROOT_UID, |p| p.uid);
+    crate::fs::set_owner(&path, uid)?;
+    Ok(path)
+}
+
+/// Every exception without a handler of its own. A fault in user code
+/// dumps the process's core, kills it with the matching signal's status
+/// and runs the next process; a fault in the kernel is a bug.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ExceptionHandler(trap_frame: &mut TrapFrame) {
+    let cause = scause::read().code();
+    let fault_addr = stval::read();
+    let pc = sepc::read();
+    let (name, sig) = describe(cause);
+
+    if sstatus::read().spp() != SPP::User {
+        panic!("kernel {} at {:#x} (stval {:#x})", name, pc, fault_addr);
+    }
+
+    // Dumping core needs the filesystem a parked syscall may hold; the
+    // instruction faults again once the process is let back in
+    if Scheduler::hold_while_parked() {
+        return;
+    }
+
+    let (pid, path) = {
+        let table = PROCESS_TABLE.lock();
+        let pid = table.get_current_pid();
+        (pid, table.get(pid).map(|p| p.path.clone()))
+    };
+    let Some(path) = path else {
+        // A program the kernel shell ran without a process; go back to
+        // the shell
+        klog!(
+            Warn,
+            "[user program crashed: {} at {:#x} (stval {:#x})]",
+            name,
+            pc,
+            fault_addr
+        );
+        unsafe { crate::process::prepare_for_kernel_return(trap_frame, 128 + sig as isize) };
+        return;
+    };
+
+    let dumped = match dump_core(pid, cause, fault_addr, pc) {
+        Ok(path) => format!("core dumped to {}", path),
+        Err(err) => format!("no core: {}", err),
+    };
+    let location = match crate::process::symbolize(&path, pc) {
+        Some(symbol) => format!("{:#x} ({}+{:#x})", pc, symbol.name, symbol.offset),
+        None => format!("{:#x}", pc),
+    };
+    klog!(
+        Warn,
+        "[process {} ({}) crashed: {} at {} (stval {:#x}); {}]",
+        pid,
+        path,
+        name,
+        location,
+        fault_addr,
+        dumped
+    );
+
+    let parent_pid = {
+        let mut table = PROCESS_TABLE.lock();
+        let parent_pid = table.get(pid).map_or(INVALID_PID, |p| p.parent_pid);
+        // Shell convention: killed by signal N reports status 128 + N
+        table.exit_process(pid, 128 + sig as isize);
+        parent_pid
+    };
+    if parent_pid != INVALID_PID {
+        Scheduler::unblock(parent_pid);
+    }
+    if !Scheduler::maybe_switch() {
+        // Nothing left to run; returning would re-run the faulting
+        // instruction
+        unsafe { crate::process::prepare_for_kernel_return(trap_frame, 128 + sig as isize) };
+    }
+}
@@ -1,42 +1,65 @@
-use alloc::{string::String, vec::Vec};
+use alloc::{string::String, sync::Arc, vec::Vec};
 use core::fmt;
 use spin::Mutex;
 
+use crate::console::Port;
+use crate::eventfd::{EventFd, EVENT_TABLE};
 use crate::fs;
 use crate::proc::Pid;
+use crate::pty::{PtyFd, PTY_TABLE};
+use crate::random::RandomFd;
 use crate::scheduler::Scheduler;
+use crate::socket::{NetError, SocketFd, SOCKET_TABLE};
 
-/// Maximum number of open file descriptors
-pub const MAX_FDS: usize = 16;
+/// Default soft limit on open file descriptors per table
+pub const DEFAULT_FD_LIMIT: usize = 64;
+
+/// Ceiling for the soft limit (the RLIMIT_NOFILE hard limit)
+pub const FD_HARD_LIMIT: usize = 1024;
 
 /// Standard file descriptor numbers
 pub const STDIN_FD: usize = 0;
 pub const STDOUT_FD: usize = 1;
 pub const STDERR_FD: usize = 2;
 
+/// lseek whence values
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
 /// Global file descriptor table for kernel-side helpers (kernel shell)
 pub static FD_TABLE: Mutex<FdTable> = Mutex::new(FdTable::new());
 
-/// File descriptor table
+/// File descriptor table, grown on demand up to its soft limit
 #[derive(Clone)]
 pub struct FdTable {
-    fds: [Option<FileDescriptor>; MAX_FDS],
+    fds: Vec<Option<FdEntry>>,
+    limit: usize,
+}
+
+/// An open descriptor plus its per-fd flags
+#[derive(Clone)]
+struct FdEntry {
+    fd: FileDescriptor,
+    /// Not inherited by spawned children (FD_CLOEXEC)
+    cloexec: bool,
 }
 
 impl FdTable {
     /// Create a new empty file descriptor table
     pub const fn new() -> Self {
         Self {
-            fds: [const { None }; MAX_FDS],
+            fds: Vec::new(),
+            limit: DEFAULT_FD_LIMIT,
         }
     }
 
     /// Initialize the fd table with stdin/stdout/stderr
     pub fn with_standard() -> Self {
         let mut table = Self::new();
-        table.fds[STDIN_FD] = Some(FileDescriptor::Uart(UartFd::new(UartMode::Read)));
-        table.fds[STDOUT_FD] = Some(FileDescriptor::Uart(UartFd::new(UartMode::Write)));
-        table.fds[STDERR_FD] = Some(FileDescriptor::Uart(UartFd::new(UartMode::Write)));
+        for mode in [UartMode::Read, UartMode::Write, UartMode::Write] {
+            let _ = table.alloc(FileDescriptor::Uart(UartFd::new(mode)));
+        }
         table
     }
 
@@ -45,95 +68,218 @@ impl FdTable {
         *self = Self::with_standard();
     }
 
-    /// Allocate a new file descriptor
+    /// Current soft limit: descriptors are numbered below it
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Change the soft limit. Descriptors already open above a lowered
+    /// limit stay valid; only new allocations are refused.
+    pub fn set_limit(&mut self, limit: usize) -> Result<(), FdError> {
+        if limit == 0 || limit > FD_HARD_LIMIT {
+            return Err(FdError::InvalidLimit);
+        }
+        self.limit = limit;
+        Ok(())
+    }
+
+    /// Allocate the lowest free file descriptor
     pub fn alloc(&mut self, fd: FileDescriptor) -> Result<usize, FdError> {
-        for (i, slot) in self.fds.iter_mut().enumerate() {
-            if slot.is_none() {
-                *slot = Some(fd);
-                return Ok(i);
-            }
+        self.alloc_with(fd, false)
+    }
+
+    /// Allocate the lowest free file descriptor with its close-on-spawn flag
+    pub fn alloc_with(&mut self, fd: FileDescriptor, cloexec: bool) -> Result<usize, FdError> {
+        let entry = FdEntry { fd, cloexec };
+        let free = self.fds.iter().position(Option::is_none);
+        let fd_num = free.unwrap_or(self.fds.len());
+        if fd_num >= self.limit {
+            return Err(FdError::TooManyOpen);
+        }
+        if fd_num == self.fds.len() {
+            self.fds.push(Some(entry));
+        } else {
+            self.fds[fd_num] = Some(entry);
         }
-        Err(FdError::TooManyOpen)
+        Ok(fd_num)
     }
 
     /// Get a file descriptor by number
     pub fn get(&self, fd_num: usize) -> Result<&FileDescriptor, FdError> {
-        if fd_num >= MAX_FDS {
-            return Err(FdError::BadFd);
+        self.fds
+            .get(fd_num)
+            .and_then(Option::as_ref)
+            .map(|entry| &entry.fd)
+            .ok_or(FdError::BadFd)
+    }
+
+    /// The controlling terminal: the console stdin reads from, or the UART
+    /// when stdin is not a console
+    pub fn console_port(&self) -> Port {
+        match self.get(STDIN_FD) {
+            Ok(FileDescriptor::Uart(uart)) => uart.port(),
+            _ => Port::Uart,
         }
-        self.fds[fd_num].as_ref().ok_or(FdError::BadFd)
     }
 
     /// Get a mutable file descriptor by number
     pub fn get_mut(&mut self, fd_num: usize) -> Result<&mut FileDescriptor, FdError> {
-        if fd_num >= MAX_FDS {
-            return Err(FdError::BadFd);
+        self.fds
+            .get_mut(fd_num)
+            .and_then(Option::as_mut)
+            .map(|entry| &mut entry.fd)
+            .ok_or(FdError::BadFd)
+    }
+
+    /// Whether a descriptor is closed when spawning a child
+    pub fn cloexec(&self, fd_num: usize) -> Result<bool, FdError> {
+        self.fds
+            .get(fd_num)
+            .and_then(Option::as_ref)
+            .map(|entry| entry.cloexec)
+            .ok_or(FdError::BadFd)
+    }
+
+    /// Set or clear a descriptor's close-on-spawn flag
+    pub fn set_cloexec(&mut self, fd_num: usize, cloexec: bool) -> Result<(), FdError> {
+        let entry = self
+            .fds
+            .get_mut(fd_num)
+            .and_then(Option::as_mut)
+            .ok_or(FdError::BadFd)?;
+        entry.cloexec = cloexec;
+        Ok(())
+    }
+
+    /// Close every descriptor marked close-on-spawn. A spawned child gets
+    /// a copy of its parent's table, has its file actions applied, then
+    /// drops these.
+    pub fn close_cloexec(&mut self) {
+        for fd_num in 0..self.fds.len() {
+            if self.fds[fd_num].as_ref().is_some_and(|entry| entry.cloexec) {
+                let _ = self.close(fd_num);
+            }
         }
-        self.fds[fd_num].as_mut().ok_or(FdError::BadFd)
     }
 
     /// Close a file descriptor
     pub fn close(&mut self, fd_num: usize) -> Result<(), FdError> {
-        if fd_num >= MAX_FDS {
+        if fd_num >= self.fds.len() {
             return Err(FdError::BadFd);
         }
-        let fd = self.fds[fd_num].take();
-        if fd.is_none() {
-            return Err(FdError::BadFd);
-        }
-        if let Some(FileDescriptor::Pipe(pipe_fd)) = fd {
-            PIPE_TABLE
-                .lock()
-                .close_pipe_end(pipe_fd.pipe_id, pipe_fd.is_read_end)?;
-        }
-        Ok(())
+        let entry = self.fds[fd_num].take().ok_or(FdError::BadFd)?;
+        self.shrink();
+        entry.fd.release()
     }
 
     /// Duplicate a file descriptor to a specific fd number
     pub fn dup2(&mut self, old_fd: usize, new_fd: usize) -> Result<(), FdError> {
-        if old_fd >= MAX_FDS || new_fd >= MAX_FDS {
+        if new_fd >= self.limit {
             return Err(FdError::BadFd);
         }
-        let fd = self.fds[old_fd].as_ref().ok_or(FdError::BadFd)?;
-        let cloned = fd.clone();
+        let cloned = self.get(old_fd)?.clone();
+        // The duplicate starts with FD_CLOEXEC clear, as in POSIX
+        self.install(new_fd, cloned, false)
+    }
 
-        // Close new_fd if it's open
-        if let Some(existing) = self.fds[new_fd].take() {
-            if let FileDescriptor::Pipe(pipe_fd) = existing {
-                PIPE_TABLE
-                    .lock()
-                    .close_pipe_end(pipe_fd.pipe_id, pipe_fd.is_read_end)?;
-            }
+    /// Put `fd` at number `fd_num`, closing whatever was open there
+    pub fn install(&mut self, fd_num: usize, fd: FileDescriptor, cloexec: bool) -> Result<(), FdError> {
+        if fd_num >= self.limit {
+            let _ = fd.release();
+            return Err(FdError::BadFd);
+        }
+        if fd_num >= self.fds.len() {
+            self.fds.resize_with(fd_num + 1, || None);
+        }
+        match self.fds[fd_num].replace(FdEntry { fd, cloexec }) {
+            Some(existing) => existing.fd.release(),
+            None => Ok(()),
         }
-        self.fds[new_fd] = Some(cloned);
-        Ok(())
     }
 
     /// Close all open file descriptors, ignoring individual errors
     pub fn close_all(&mut self) {
-        for fd_num in 0..MAX_FDS {
+        for fd_num in (0..self.fds.len()).rev() {
             let _ = self.close(fd_num);
         }
     }
+
+    /// Drop trailing free slots so a burst of opens does not pin memory
+    fn shrink(&mut self) {
+        while let Some(None) = self.fds.last() {
+            self.fds.pop();
+        }
+    }
 }
 
 /// File descriptor types
 pub enum FileDescriptor {
     /// UART (stdin/stdout/stderr)
     Uart(UartFd),
-    /// Regular file
-    File(FileFd),
+    /// Regular file; dups and inherited copies share one open file, and
+    /// with it the offset
+    File(Arc<Mutex<FileFd>>),
     /// Pipe end
     Pipe(PipeFd),
+    /// Open directory
+    Dir(DirFd),
+    /// Pseudo-terminal master or slave
+    Pty(PtyFd),
+    /// Loopback network socket
+    Socket(SocketFd),
+    /// Event counter (eventfd)
+    Event(EventFd),
+    /// `/dev/random` or `/dev/urandom`
+    Random(RandomFd),
 }
 
 impl FileDescriptor {
+    /// Wrap a freshly opened file as a new open-file object
+    pub fn file(file_fd: FileFd) -> Self {
+        FileDescriptor::File(Arc::new(Mutex::new(file_fd)))
+    }
+
+    /// Drop this reference to the underlying object. Pipes lose one end
+    /// reference; files commit once their last descriptor goes away.
+    fn release(self) -> Result<(), FdError> {
+        match self {
+            FileDescriptor::Pipe(pipe_fd) => PIPE_TABLE
+                .lock()
+                .close_pipe_end(pipe_fd.pipe_id, pipe_fd.is_read_end),
+            FileDescriptor::File(file) => match Arc::try_unwrap(file) {
+                Ok(file) => file.into_inner().commit(),
+                Err(_) => Ok(()),
+            },
+            FileDescriptor::Pty(pty_fd) => PTY_TABLE.lock().close_end(pty_fd.pty_id, pty_fd.end),
+            FileDescriptor::Socket(socket_fd) => SOCKET_TABLE.lock().close(socket_fd.socket_id),
+            FileDescriptor::Event(event_fd) => EVENT_TABLE.lock().close(event_fd.event_id),
+            FileDescriptor::Uart(_) | FileDescriptor::Dir(_) | FileDescriptor::Random(_) => {
+                Ok(())
+            }
+        }
+    }
+
+    /// Flush the device under an open file or directory. Only files and
+    /// directories live on a device.
+    pub fn sync(&self) -> Result<(), FdError> {
+        match self {
+            FileDescriptor::File(file) => file.lock().sync(),
+            FileDescriptor::Dir(dir) => fs::sync(dir.path()).map_err(FdError::Fs),
+            _ => Err(FdError::InvalidArgument),
+        }
+    }
+
     /// Read from this file descriptor
     pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, FdError> {
         match self {
             FileDescriptor::Uart(uart) => uart.read(buf),
-            FileDescriptor::File(file) => file.read(buf),
+            FileDescriptor::File(file) => file.lock().read(buf),
             FileDescriptor::Pipe(pipe) => pipe.read(buf),
+            FileDescriptor::Pty(pty) => pty.read(buf),
+            FileDescriptor::Socket(socket) => socket.read(buf),
+            FileDescriptor::Event(event) => event.read(buf),
+            FileDescriptor::Random(random) => random.read(buf),
+            FileDescriptor::Dir(_) => Err(FdError::Fs(fs::FsError::IsDirectory)),
         }
     }
 
@@ -141,8 +287,21 @@ impl FileDescriptor {
     pub fn write(&mut self, buf: &[u8]) -> Result<usize, FdError> {
         match self {
             FileDescriptor::Uart(uart) => uart.write(buf),
-            FileDescriptor::File(file) => file.write(buf),
+            FileDescriptor::File(file) => file.lock().write(buf),
             FileDescriptor::Pipe(pipe) => pipe.write(buf),
+            FileDescriptor::Pty(pty) => pty.write(buf),
+            FileDescriptor::Socket(socket) => socket.write(buf),
+            FileDescriptor::Event(event) => event.write(buf),
+            FileDescriptor::Random(random) => random.write(buf),
+            FileDescriptor::Dir(_) => Err(FdError::Fs(fs::FsError::IsDirectory)),
+        }
+    }
+
+    /// Move the offset of a regular file; nothing else can seek
+    pub fn seek(&mut self, offset: isize, whence: usize) -> Result<usize, FdError> {
+        match self {
+            FileDescriptor::File(file) => file.lock().seek(offset, whence),
+            _ => Err(FdError::IllegalSeek),
         }
     }
 }
@@ -151,19 +310,37 @@ impl Clone for FileDescriptor {
     fn clone(&self) -> Self {
         match self {
             FileDescriptor::Uart(u) => FileDescriptor::Uart(u.clone()),
-            FileDescriptor::File(f) => FileDescriptor::File(f.clone()),
+            FileDescriptor::File(f) => FileDescriptor::File(Arc::clone(f)),
+            FileDescriptor::Dir(d) => FileDescriptor::Dir(d.clone()),
             FileDescriptor::Pipe(p) => {
                 let _ = PIPE_TABLE.lock().incref(p.pipe_id, p.is_read_end);
                 FileDescriptor::Pipe(p.clone())
             }
+            FileDescriptor::Pty(p) => {
+                let _ = PTY_TABLE.lock().incref(p.pty_id, p.end);
+                FileDescriptor::Pty(p.clone())
+            }
+            FileDescriptor::Socket(s) => {
+                let _ = SOCKET_TABLE.lock().incref(s.socket_id);
+                FileDescriptor::Socket(s.clone())
+            }
+            FileDescriptor::Event(e) => {
+                let _ = EVENT_TABLE.lock().incref(e.event_id);
+                FileDescriptor::Event(e.clone())
+            }
+            FileDescriptor::Random(r) => FileDescriptor::Random(r.clone()),
         }
     }
 }
 
-/// UART file descriptor (for stdin/stdout/stderr)
+/// Console file descriptor (for stdin/stdout/stderr): the UART, the
+/// virtio console when opened as `/dev/hvc0`, the second UART as
+/// `/dev/ttyS1`, or another virtual terminal on the UART when opened as
+/// `/dev/tty2` and up
 #[derive(Clone)]
 pub struct UartFd {
     mode: UartMode,
+    port: Port,
 }
 
 #[derive(Clone, Copy)]
@@ -174,19 +351,20 @@ pub enum UartMode {
 
 impl UartFd {
     pub fn new(mode: UartMode) -> Self {
-        Self { mode }
+        Self::on(Port::Uart, mode)
+    }
+
+    pub fn on(port: Port, mode: UartMode) -> Self {
+        Self { mode, port }
+    }
+
+    pub fn port(&self) -> Port {
+        self.port
     }
 
     pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, FdError> {
         match self.mode {
-            UartMode::Read => {
-                if buf.is_empty() {
-                    return Ok(0);
-                }
-                let byte = crate::uart::read_byte_blocking();
-                buf[0] = byte;
-                Ok(1)
-            }
+            UartMode::Read => crate::console::read(self.port, buf),
             UartMode::Write => Err(FdError::BadFd),
         }
     }
@@ -194,7 +372,7 @@ impl UartFd {
     pub fn write(&mut self, buf: &[u8]) -> Result<usize, FdError> {
         match self.mode {
             UartMode::Write => {
-                crate::uart::write_bytes(buf);
+                crate::console::write(self.port, buf);
                 Ok(buf.len())
             }
             UartMode::Read => Err(FdError::BadFd),
@@ -202,12 +380,19 @@ impl UartFd {
     }
 }
 
-/// Regular file descriptor
-#[derive(Clone)]
+/// Regular file descriptor (the open-file object shared between dups)
 pub struct FileFd {
     path: String,
+    /// Resolved entry used for reads; `None` only while an atomic
+    /// descriptor is staging a file that does not exist yet
+    handle: Option<fs::FileHandle>,
     pos: usize,
     mode: FileMode,
+    /// Pending contents of an atomic descriptor, committed on close
+    staged: Option<Vec<u8>>,
+    dirty: bool,
+    /// Uid that owns the file if this descriptor creates it
+    owner: crate::proc::Uid,
 }
 
 #[derive(Clone, Copy)]
@@ -216,6 +401,9 @@ pub struct FileMode {
     pub write: bool,
     pub append: bool,
     pub create: bool,
+    pub truncate: bool,
+    /// Stage writes in memory and replace the file in one step on close
+    pub atomic: bool,
 }
 
 impl FileMode {
@@ -225,6 +413,8 @@ impl FileMode {
             write: false,
             append: false,
             create: false,
+            truncate: false,
+            atomic: false,
         }
     }
 
@@ -234,6 +424,8 @@ impl FileMode {
             write: true,
             append: false,
             create: true,
+            truncate: true,
+            atomic: false,
         }
     }
 
@@ -243,6 +435,8 @@ impl FileMode {
             write: true,
             append: false,
             create: true,
+            truncate: false,
+            atomic: false,
         }
     }
 
@@ -252,31 +446,100 @@ impl FileMode {
             write: true,
             append: true,
             create: true,
+            truncate: false,
+            atomic: false,
         }
     }
 }
 
 impl FileFd {
     pub fn open(path: String, mode: FileMode) -> Result<Self, FdError> {
+        Self::open_as(path, mode, crate::proc::ROOT_UID)
+    }
+
+    /// Open `path`, giving a file it creates (now, or on commit for an
+    /// atomic descriptor) to `owner`
+    pub fn open_as(path: String, mode: FileMode, owner: crate::proc::Uid) -> Result<Self, FdError> {
         // Check if file exists
-        let exists = fs::read_file(&path).is_ok();
+        let size = match fs::file_size(&path) {
+            Ok(size) => Some(size),
+            Err(fs::FsError::NotFound) => None,
+            Err(err) => return Err(FdError::Fs(err)),
+        };
 
-        if !exists && !mode.create {
+        if size.is_none() && !mode.create {
             return Err(FdError::NotFound);
         }
 
-        if !exists && mode.create {
+        // Atomic descriptors leave the file untouched until they commit
+        let staged = if mode.atomic && mode.write {
+            let mut contents = Vec::new();
+            if size.is_some() && !mode.truncate {
+                contents = fs::read_file(&path).map_err(FdError::Fs)?;
+            }
+            Some(contents)
+        } else {
+            None
+        };
+
+        if size.is_none() && mode.create && staged.is_none() {
             fs::create_file(&path).map_err(|e| FdError::Fs(e))?;
+            if owner != crate::proc::ROOT_UID {
+                fs::set_owner(&path, owner).map_err(FdError::Fs)?;
+            }
+        }
+
+        if size.is_some() && mode.truncate && staged.is_none() {
+            fs::write_file(&path, &[]).map_err(FdError::Fs)?;
         }
 
-        let pos = if mode.append {
-            // Get file size for append mode
-            fs::read_file(&path).map(|data| data.len()).unwrap_or(0)
+        let pos = if mode.append && !mode.truncate {
+            size.unwrap_or(0)
         } else {
             0
         };
 
-        Ok(Self { path, pos, mode })
+        let handle = if staged.is_none() {
+            Some(fs::open(&path).map_err(FdError::Fs)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            path,
+            handle,
+            pos,
+            mode,
+            // A freshly created or truncated atomic file must still be written
+            dirty: staged.is_some() && (size.is_none() || mode.truncate),
+            staged,
+            owner,
+        })
+    }
+
+    /// Make what has been written through this file durable. Staged
+    /// contents of an atomic descriptor are left for `commit`, so the
+    /// file is still replaced in one step.
+    pub fn sync(&self) -> Result<(), FdError> {
+        fs::sync(&self.path).map_err(FdError::Fs)
+    }
+
+    /// Swap staged contents into place. Runs when the last descriptor
+    /// sharing this open file closes, and only if it wrote (or created or
+    /// truncated the file).
+    pub fn commit(&mut self) -> Result<(), FdError> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(staged) = &self.staged {
+            let creating = fs::file_size(&self.path) == Err(fs::FsError::NotFound);
+            fs::atomic_write(&self.path, staged).map_err(FdError::Fs)?;
+            if creating && self.owner != crate::proc::ROOT_UID {
+                fs::set_owner(&self.path, self.owner).map_err(FdError::Fs)?;
+            }
+        }
+        self.dirty = false;
+        Ok(())
     }
 
     pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, FdError> {
@@ -284,15 +547,16 @@ impl FileFd {
             return Err(FdError::BadFd);
         }
 
-        let contents = fs::read_file(&self.path).map_err(FdError::Fs)?;
-
-        if self.pos >= contents.len() {
-            return Ok(0); // EOF
-        }
-
-        let available = contents.len() - self.pos;
-        let to_read = buf.len().min(available);
-        buf[..to_read].copy_from_slice(&contents[self.pos..self.pos + to_read]);
+        let to_read = match (&self.staged, &mut self.handle) {
+            (Some(staged), _) => {
+                let available = staged.len().saturating_sub(self.pos);
+                let to_read = buf.len().min(available);
+                buf[..to_read].copy_from_slice(&staged[self.pos..self.pos + to_read]);
+                to_read
+            }
+            (None, Some(handle)) => fs::read_at(handle, self.pos, buf).map_err(FdError::Fs)?,
+            (None, None) => 0,
+        };
         self.pos += to_read;
         Ok(to_read)
     }
@@ -302,23 +566,127 @@ impl FileFd {
             return Err(FdError::BadFd);
         }
 
-        if self.mode.append {
-            // Append mode: read existing content, append, write back
-            let mut contents = fs::read_file(&self.path).unwrap_or_else(|_| Vec::new());
-            contents.extend_from_slice(buf);
-            fs::write_file(&self.path, &contents).map_err(FdError::Fs)?;
-            self.pos = contents.len();
+        if let Some(staged) = &mut self.staged {
+            if self.mode.append {
+                self.pos = staged.len();
+            }
+            let end = self.pos + buf.len();
+            if staged.len() < end {
+                staged.resize(end, 0);
+            }
+            staged[self.pos..end].copy_from_slice(buf);
+            self.pos = end;
+            self.dirty = true;
+            return Ok(buf.len());
+        }
+
+        let written = if self.mode.append {
+            // Append mode: every write lands at the current end of file
+            fs::append(&self.path, buf)
         } else {
-            // Write mode: for now, just overwrite the whole file
-            // TODO: Support proper seeking and partial writes
-            fs::write_file(&self.path, buf).map_err(FdError::Fs)?;
-            self.pos = buf.len();
+            fs::write_at(&self.path, self.pos, buf)
+        }
+        .map_err(FdError::Fs)?;
+        self.pos += written;
+
+        Ok(written)
+    }
+
+    /// Write no further than byte offset `max_size` (RLIMIT_FSIZE); a
+    /// write that starts there fails with FileTooLarge
+    pub fn write_capped(&mut self, buf: &[u8], max_size: usize) -> Result<usize, FdError> {
+        let start = if self.mode.append { self.end()? } else { self.pos };
+        let room = max_size.saturating_sub(start);
+        if room == 0 && !buf.is_empty() {
+            return Err(FdError::Fs(fs::FsError::FileTooLarge));
+        }
+        self.write(&buf[..buf.len().min(room)])
+    }
+
+    /// Set the offset to `offset` from the start (SEEK_SET), the current
+    /// offset (SEEK_CUR) or the end of file (SEEK_END), and return it.
+    /// Seeking past the end is allowed; before the start is not.
+    pub fn seek(&mut self, offset: isize, whence: usize) -> Result<usize, FdError> {
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => self.pos,
+            SEEK_END => self.end()?,
+            _ => return Err(FdError::InvalidArgument),
+        };
+        self.pos = base
+            .checked_add_signed(offset)
+            .ok_or(FdError::InvalidArgument)?;
+        Ok(self.pos)
+    }
+
+    /// Current end of file, staged contents included
+    fn end(&self) -> Result<usize, FdError> {
+        match &self.staged {
+            Some(staged) => Ok(staged.len()),
+            None => fs::file_size(&self.path).map_err(FdError::Fs),
         }
+    }
+}
+
+/// Directory file descriptor. TinyFS has no inode numbers, so the
+/// descriptor pins the canonical path resolved at open time; openat
+/// resolves relative names against it rather than the caller's cwd.
+#[derive(Clone)]
+pub struct DirFd {
+    path: String,
+    cookie: usize,
+}
 
-        Ok(buf.len())
+impl DirFd {
+    /// Open an existing directory
+    pub fn open(path: String) -> Result<Self, FdError> {
+        fs::ensure_directory(&path).map_err(FdError::Fs)?;
+        Ok(Self { path, cookie: 0 })
+    }
+
+    /// Canonical path of the directory
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Fill `buf` with the next directory records, continuing where the
+    /// previous call stopped. Returns 0 once every entry has been read.
+    pub fn read_entries(&mut self, buf: &mut [u8]) -> Result<usize, FdError> {
+        read_dirents(&self.path, &mut self.cookie, buf)
     }
 }
 
+/// Encode entries of `path` from `cookie` onward as getdents records
+/// (`[kind, name_len, name...]`, kind 1 = file, 2 = directory) and advance
+/// `cookie` past the last record written. Fails with InvalidArgument when
+/// `buf` cannot hold even one record.
+pub fn read_dirents(path: &str, cookie: &mut usize, buf: &mut [u8]) -> Result<usize, FdError> {
+    let mut written = 0;
+    'batches: loop {
+        let batch = fs::read_dir(path, *cookie).map_err(FdError::Fs)?;
+        for entry in &batch.entries {
+            let name = entry.name.as_bytes();
+            let record_len = 2 + name.len();
+            if written + record_len > buf.len() {
+                if written == 0 {
+                    return Err(FdError::InvalidArgument);
+                }
+                break 'batches;
+            }
+            buf[written] = if entry.is_dir { 2 } else { 1 };
+            buf[written + 1] = name.len() as u8;
+            buf[written + 2..written + record_len].copy_from_slice(name);
+            written += record_len;
+            *cookie = entry.cookie;
+        }
+        match batch.next_cookie {
+            Some(next) => *cookie = next,
+            None => break,
+        }
+    }
+    Ok(written)
+}
+
 /// Pipe file descriptor
 #[derive(Clone)]
 pub struct PipeFd {
@@ -349,45 +717,56 @@ impl PipeFd {
     }
 }
 
-/// Maximum number of pipes
-const MAX_PIPES: usize = 8;
+/// Default pipe buffer size (4KB)
+pub const PIPE_BUF_SIZE: usize = 4096;
 
-/// Pipe buffer size (4KB)
-const PIPE_BUF_SIZE: usize = 4096;
+/// Largest buffer F_SETPIPE_SZ will allocate (1MB)
+pub const PIPE_MAX_SIZE: usize = 1024 * 1024;
 
 /// Global pipe table
 pub static PIPE_TABLE: Mutex<PipeTable> = Mutex::new(PipeTable::new());
 
-/// Pipe table
+/// Pipe table; ids index `pipes` and stay stable while a pipe is open
 pub struct PipeTable {
-    pipes: [Option<Pipe>; MAX_PIPES],
+    pipes: Vec<Option<Pipe>>,
 }
 
 impl PipeTable {
     pub const fn new() -> Self {
-        Self {
-            pipes: [const { None }; MAX_PIPES],
-        }
+        Self { pipes: Vec::new() }
     }
 
-    /// Create a new pipe and return its ID
-    pub fn create_pipe() -> Result<usize, FdError> {
+    /// Create a new pipe whose buffer is charged to `owner`; return its ID
+    pub fn create_pipe(owner: Pid) -> Result<usize, FdError> {
         let mut table = PIPE_TABLE.lock();
-        for (i, slot) in table.pipes.iter_mut().enumerate() {
-            if slot.is_none() {
-                *slot = Some(Pipe::new());
-                return Ok(i);
-            }
+        if let Some(i) = table.pipes.iter().position(Option::is_none) {
+            table.pipes[i] = Some(Pipe::new(owner));
+            return Ok(i);
         }
-        Err(FdError::TooManyOpen)
+        table.pipes.push(Some(Pipe::new(owner)));
+        Ok(table.pipes.len() - 1)
+    }
+
+    /// Buffer bytes of the pipes charged to `pid`
+    pub fn charged_to(&self, pid: Pid) -> usize {
+        self.pipes
+            .iter()
+            .flatten()
+            .filter(|pipe| pipe.owner == pid)
+            .map(|pipe| pipe.capacity)
+            .sum()
+    }
+
+    fn pipe_mut(&mut self, pipe_id: usize) -> Result<&mut Pipe, FdError> {
+        self.pipes
+            .get_mut(pipe_id)
+            .and_then(Option::as_mut)
+            .ok_or(FdError::BadFd)
     }
 
     /// Increment refcount when cloning/duplicating a pipe end
     pub fn incref(&mut self, pipe_id: usize, is_read_end: bool) -> Result<(), FdError> {
-        if pipe_id >= MAX_PIPES {
-            return Err(FdError::BadFd);
-        }
-        let pipe = self.pipes[pipe_id].as_mut().ok_or(FdError::BadFd)?;
+        let pipe = self.pipe_mut(pipe_id)?;
         if is_read_end {
             pipe.read_refcount = pipe.read_refcount.saturating_add(1);
             pipe.read_end_open = true;
@@ -400,10 +779,7 @@ impl PipeTable {
 
     /// Read from a pipe
     pub fn read(&mut self, pipe_id: usize, buf: &mut [u8]) -> Result<usize, FdError> {
-        if pipe_id >= MAX_PIPES {
-            return Err(FdError::BadFd);
-        }
-        let pipe = self.pipes[pipe_id].as_mut().ok_or(FdError::BadFd)?;
+        let pipe = self.pipe_mut(pipe_id)?;
         let bytes = pipe.read(buf)?;
         if bytes > 0 {
             pipe.wake_writers();
@@ -413,10 +789,7 @@ impl PipeTable {
 
     /// Write to a pipe
     pub fn write(&mut self, pipe_id: usize, buf: &[u8]) -> Result<usize, FdError> {
-        if pipe_id >= MAX_PIPES {
-            return Err(FdError::BadFd);
-        }
-        let pipe = self.pipes[pipe_id].as_mut().ok_or(FdError::BadFd)?;
+        let pipe = self.pipe_mut(pipe_id)?;
         let written = pipe.write(buf)?;
         if written > 0 {
             pipe.wake_readers();
@@ -424,29 +797,42 @@ impl PipeTable {
         Ok(written)
     }
 
+    /// Buffer size of a pipe in bytes
+    pub fn capacity(&mut self, pipe_id: usize) -> Result<usize, FdError> {
+        Ok(self.pipe_mut(pipe_id)?.capacity)
+    }
+
+    /// Process the pipe's buffer is charged to
+    pub fn owner(&mut self, pipe_id: usize) -> Result<Pid, FdError> {
+        Ok(self.pipe_mut(pipe_id)?.owner)
+    }
+
+    /// Resize a pipe's buffer, charging it to `owner` from now on, and
+    /// return the size actually applied
+    pub fn set_capacity(&mut self, pipe_id: usize, size: usize, owner: Pid) -> Result<usize, FdError> {
+        let pipe = self.pipe_mut(pipe_id)?;
+        let capacity = pipe.resize(size)?;
+        pipe.owner = owner;
+        // A larger buffer may have room for blocked writers now
+        pipe.wake_writers();
+        Ok(capacity)
+    }
+
     /// Register a reader that will block on this pipe
     pub fn mark_reader_waiting(&mut self, pipe_id: usize, pid: Pid) -> Result<(), FdError> {
-        if pipe_id >= MAX_PIPES {
-            return Err(FdError::BadFd);
-        }
-        let pipe = self.pipes[pipe_id].as_mut().ok_or(FdError::BadFd)?;
-        pipe.mark_reader_waiting(pid);
+        self.pipe_mut(pipe_id)?.mark_reader_waiting(pid);
         Ok(())
     }
 
     /// Register a writer that will block on this pipe
     pub fn mark_writer_waiting(&mut self, pipe_id: usize, pid: Pid) -> Result<(), FdError> {
-        if pipe_id >= MAX_PIPES {
-            return Err(FdError::BadFd);
-        }
-        let pipe = self.pipes[pipe_id].as_mut().ok_or(FdError::BadFd)?;
-        pipe.mark_writer_waiting(pid);
+        self.pipe_mut(pipe_id)?.mark_writer_waiting(pid);
         Ok(())
     }
 
     /// Close a pipe end
     pub fn close_pipe_end(&mut self, pipe_id: usize, is_read_end: bool) -> Result<(), FdError> {
-        if pipe_id >= MAX_PIPES {
+        if pipe_id >= self.pipes.len() {
             return Err(FdError::BadFd);
         }
         if let Some(pipe) = &mut self.pipes[pipe_id] {
@@ -471,6 +857,9 @@ impl PipeTable {
             // Clean up pipe if both ends are closed
             if !pipe.read_end_open && !pipe.write_end_open {
                 self.pipes[pipe_id] = None;
+                while matches!(self.pipes.last(), Some(None)) {
+                    self.pipes.pop();
+                }
             }
         }
         Ok(())
@@ -479,52 +868,69 @@ impl PipeTable {
 
 /// Pipe structure with ring buffer
 pub struct Pipe {
+    /// Ring storage; left empty until the first write needs it
     buffer: Vec<u8>,
+    capacity: usize,
     read_pos: usize,
-    write_pos: usize,
+    len: usize,
     read_end_open: bool,
     write_end_open: bool,
     read_refcount: usize,
     write_refcount: usize,
     waiting_readers: Vec<Pid>,
     waiting_writers: Vec<Pid>,
+    /// Process whose memory limit the buffer counts against
+    owner: Pid,
 }
 
 impl Pipe {
-    pub fn new() -> Self {
+    pub fn new(owner: Pid) -> Self {
         Self {
-            buffer: alloc::vec![0u8; PIPE_BUF_SIZE],
+            buffer: Vec::new(),
+            capacity: PIPE_BUF_SIZE,
             read_pos: 0,
-            write_pos: 0,
+            len: 0,
             read_end_open: true,
             write_end_open: true,
             read_refcount: 1,
             write_refcount: 1,
             waiting_readers: Vec::new(),
             waiting_writers: Vec::new(),
-        }
-    }
-
-    /// Get number of bytes available to read
-    fn available(&self) -> usize {
-        if self.write_pos >= self.read_pos {
-            self.write_pos - self.read_pos
-        } else {
-            PIPE_BUF_SIZE - self.read_pos + self.write_pos
+            owner,
         }
     }
 
     /// Get number of bytes available to write
     fn space_available(&self) -> usize {
-        PIPE_BUF_SIZE - self.available() - 1 // -1 to distinguish full from empty
+        self.capacity - self.len
+    }
+
+    /// Move buffered bytes into a buffer of `size` bytes, rounded up to
+    /// whole 4KB pages. Fails with Busy if the data would not fit.
+    fn resize(&mut self, size: usize) -> Result<usize, FdError> {
+        if size == 0 || size > PIPE_MAX_SIZE {
+            return Err(FdError::InvalidArgument);
+        }
+        let capacity = size.div_ceil(PIPE_BUF_SIZE) * PIPE_BUF_SIZE;
+        if capacity < self.len {
+            return Err(FdError::Busy);
+        }
+        if !self.buffer.is_empty() {
+            let mut buffer = alloc::vec![0u8; capacity];
+            for (i, byte) in buffer.iter_mut().take(self.len).enumerate() {
+                *byte = self.buffer[(self.read_pos + i) % self.capacity];
+            }
+            self.buffer = buffer;
+            self.read_pos = 0;
+        }
+        self.capacity = capacity;
+        Ok(capacity)
     }
 
     /// Read from pipe
     /// Returns WouldBlock if pipe is empty and write end is still open
     pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, FdError> {
-        let available = self.available();
-
-        if available == 0 {
+        if self.len == 0 {
             if self.write_end_open {
                 // Pipe is empty but write end is open - would block
                 return Err(FdError::WouldBlock);
@@ -534,16 +940,14 @@ impl Pipe {
             }
         }
 
-        let to_read = buf.len().min(available);
-        let mut bytes_read = 0;
-
-        while bytes_read < to_read {
-            buf[bytes_read] = self.buffer[self.read_pos];
-            self.read_pos = (self.read_pos + 1) % PIPE_BUF_SIZE;
-            bytes_read += 1;
+        let to_read = buf.len().min(self.len);
+        for byte in buf.iter_mut().take(to_read) {
+            *byte = self.buffer[self.read_pos];
+            self.read_pos = (self.read_pos + 1) % self.capacity;
         }
+        self.len -= to_read;
 
-        Ok(bytes_read)
+        Ok(to_read)
     }
 
     /// Write to pipe
@@ -561,16 +965,19 @@ impl Pipe {
             return Err(FdError::WouldBlock);
         }
 
-        let to_write = buf.len().min(space);
-        let mut bytes_written = 0;
+        if self.buffer.is_empty() {
+            self.buffer = alloc::vec![0u8; self.capacity];
+        }
 
-        while bytes_written < to_write {
-            self.buffer[self.write_pos] = buf[bytes_written];
-            self.write_pos = (self.write_pos + 1) % PIPE_BUF_SIZE;
-            bytes_written += 1;
+        let to_write = buf.len().min(space);
+        let mut write_pos = (self.read_pos + self.len) % self.capacity;
+        for &byte in &buf[..to_write] {
+            self.buffer[write_pos] = byte;
+            write_pos = (write_pos + 1) % self.capacity;
         }
+        self.len += to_write;
 
-        Ok(bytes_written)
+        Ok(to_write)
     }
 
     fn mark_reader_waiting(&mut self, pid: Pid) {
@@ -609,7 +1016,14 @@ pub enum FdError {
     NotImplemented,
     WouldBlock,
     BrokenPipe,
+    InvalidLimit,
+    InvalidArgument,
+    Busy,
+    OutOfMemory,
+    /// lseek on a descriptor with no offset, like a pipe or the console
+    IllegalSeek,
     Fs(fs::FsError),
+    Net(NetError),
 }
 
 impl fmt::Display for FdError {
@@ -621,7 +1035,13 @@ impl fmt::Display for FdError {
             FdError::NotImplemented => write!(f, "Not implemented"),
             FdError::WouldBlock => write!(f, "Operation would block"),
             FdError::BrokenPipe => write!(f, "Broken pipe"),
+            FdError::InvalidLimit => write!(f, "Invalid descriptor limit"),
+            FdError::InvalidArgument => write!(f, "Invalid argument"),
+            FdError::Busy => write!(f, "Resource busy"),
+            FdError::OutOfMemory => write!(f, "Out of memory"),
+            FdError::IllegalSeek => write!(f, "Illegal seek"),
             FdError::Fs(err) => write!(f, "Filesystem error: {:?}", err),
+            FdError::Net(err) => write!(f, "Network error: {}", err),
         }
     }
 }
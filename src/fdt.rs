@@ -0,0 +1,69 @@
+/// Flattened device tree header and structure-block tokens
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+fn be32(blob: &[u8], offset: usize) -> Option<u32> {
+    let bytes = blob.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// The NUL-terminated string at `offset`, without the NUL
+fn cstr(blob: &[u8], offset: usize) -> Option<&[u8]> {
+    let rest = blob.get(offset..)?;
+    let len = rest.iter().position(|&byte| byte == 0)?;
+    Some(&rest[..len])
+}
+
+/// The kernel command line (`/chosen/bootargs`, what QEMU's `-append`
+/// sets) in the device tree the firmware passed at `dtb`, if any
+pub fn bootargs(dtb: usize) -> Option<&'static str> {
+    if dtb == 0 || !dtb.is_multiple_of(4) {
+        return None;
+    }
+    let header = unsafe { core::slice::from_raw_parts(dtb as *const u8, 8) };
+    if be32(header, 0)? != FDT_MAGIC {
+        return None;
+    }
+    let size = be32(header, 4)? as usize;
+    let blob: &'static [u8] = unsafe { core::slice::from_raw_parts(dtb as *const u8, size) };
+    let strings = be32(blob, 12)? as usize;
+    let mut offset = be32(blob, 8)? as usize;
+    let mut depth = 0;
+    let mut chosen = false;
+    loop {
+        let token = be32(blob, offset)?;
+        offset += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = cstr(blob, offset)?;
+                offset = (offset + name.len() + 1).next_multiple_of(4);
+                depth += 1;
+                // The root node is depth 1 and /chosen one below it
+                if depth == 2 {
+                    chosen = name == b"chosen";
+                }
+            }
+            FDT_END_NODE => {
+                if chosen && depth == 2 {
+                    return None;
+                }
+                depth -= 1;
+            }
+            FDT_PROP => {
+                let len = be32(blob, offset)? as usize;
+                let name = cstr(blob, strings + be32(blob, offset + 4)? as usize)?;
+                let value = blob.get(offset + 8..offset + 8 + len)?;
+                offset = (offset + 8 + len).next_multiple_of(4);
+                if chosen && depth == 2 && name == b"bootargs" {
+                    let value = value.strip_suffix(&[0]).unwrap_or(value);
+                    return core::str::from_utf8(value).ok();
+                }
+            }
+            FDT_NOP => {}
+            _ => return None,
+        }
+    }
+}
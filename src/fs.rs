@@ -2,18 +2,42 @@ use alloc::{string::String, vec, vec::Vec};
 use core::{fmt, str};
 use spin::Mutex;
 
+use crate::ninep;
 use crate::virtio::block::{self, VirtIoBlock, VirtioError};
 
+/// Block size of devices that do not report one, and the smallest
+/// supported; TinyFs uses whatever block size its device has
 pub const BLOCK_SIZE: usize = 512;
 const MAGIC: u32 = 0x5446_5331;
-const VERSION: u32 = 2;
+const VERSION: u32 = 3;
 const DIR_BLOCK_INDEX: u32 = 1;
 const DATA_START_BLOCK: u32 = 2;
 const NAME_LEN: usize = 32;
-const DIR_ENTRY_SIZE: usize = NAME_LEN + 4 + 4 + 1 + 3;
-const MAX_FILES: usize = BLOCK_SIZE / DIR_ENTRY_SIZE;
+const DIR_ENTRY_SIZE: usize = NAME_LEN + 4 + 4 + 1 + 3 + 4;
+const ENTRY_FLAG_EXTENTS: u8 = 1;
+const EXTENT_SIZE: usize = 8;
+/// Number of directory slots returned by one `read_dir` call
+pub const READ_DIR_BATCH: usize = 8;
+
+/// A filesystem attached to the tree at `point`
+struct Mount {
+    point: String,
+    device: String,
+    fs: Volume,
+}
+
+/// What a mount point is backed by
+enum Volume {
+    Tiny(TinyFs<VirtIoBlock>),
+    /// A host directory shared over virtio-9p
+    Host(ninep::Client),
+}
+
+/// Device name recorded for the virtio-9p host share
+pub const HOST_DEVICE: &str = "9p";
 
-static FS_INSTANCE: Mutex<Option<TinyFs<VirtIoBlock>>> = Mutex::new(None);
+/// Mounted filesystems; the root, on `vda`, is first
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum FsError {
@@ -30,6 +54,24 @@ pub enum FsError {
     DirectoryNotEmpty,
     IsDirectory,
     IsFile,
+    InvalidName,
+    Inconsistent,
+    InvalidArchive,
+    FileTooLarge,
+    QuotaExceeded,
+    PermissionDenied,
+    /// The mount point or device is already in use
+    Busy,
+    /// An operation whose paths lie on different mounts
+    CrossDevice,
+    /// The device holds no TinyFs
+    NoFilesystem,
+    /// The operation needs a TinyFs but the path is on a host share
+    Unsupported,
+    /// The filesystem is on read-only media
+    ReadOnly,
+    /// The device failed a read or write
+    IoError,
 }
 
 impl fmt::Display for FsError {
@@ -54,6 +96,7 @@ impl fmt::Display for FsError {
                 VirtioError::QueueUnavailable => "virtio queue unavailable",
                 VirtioError::DeviceRejectedFeatures => "virtio feature negotiation failed",
                 VirtioError::DeviceFailure => "virtio block device failed",
+                VirtioError::IoError => "virtio block I/O error",
             },
             FsError::InvalidPath => "invalid path",
             FsError::NotADirectory => "not a directory",
@@ -61,17 +104,212 @@ impl fmt::Display for FsError {
             FsError::DirectoryNotEmpty => "directory not empty",
             FsError::IsDirectory => "expected file but found directory",
             FsError::IsFile => "expected directory but found file",
+            FsError::InvalidName => "invalid file name",
+            FsError::Inconsistent => "filesystem inconsistent; run fs check --repair",
+            FsError::InvalidArchive => "invalid tar archive",
+            FsError::FileTooLarge => "file exceeds the maximum file size",
+            FsError::QuotaExceeded => "disk quota exceeded",
+            FsError::PermissionDenied => "permission denied",
+            FsError::Busy => "mount point or device busy",
+            FsError::CrossDevice => "paths are on different filesystems",
+            FsError::NoFilesystem => "no TinyFs on device; format it with fs mkfs",
+            FsError::Unsupported => "operation not supported on this filesystem",
+            FsError::ReadOnly => "read-only filesystem",
+            FsError::IoError => "I/O error",
         };
         f.write_str(message)
     }
 }
 
+/// One entry returned by [`read_dir`]
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u32,
+    /// Last modification, in seconds since the Unix epoch
+    pub mtime: u32,
+    /// Cookie that resumes iteration just after this entry
+    pub cookie: usize,
+}
+
+/// What `stat` reports about one path
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub is_dir: bool,
+    /// Length in bytes; 0 for directories
+    pub size: u64,
+    /// Last modification, in seconds since the Unix epoch
+    pub mtime: u32,
+    pub owner: u16,
+}
+
+/// A fixed-size slice of a directory
+#[derive(Clone, Debug)]
+pub struct DirBatch {
+    pub entries: Vec<DirEntry>,
+    /// Cookie for the next batch, or `None` once the directory is exhausted
+    pub next_cookie: Option<usize>,
+}
+
+/// Capacity figures for the mounted filesystem
+#[derive(Clone, Copy, Debug)]
+pub struct StatFs {
+    pub block_size: usize,
+    pub total_blocks: u32,
+    /// Blocks handed out by the allocator, including metadata and leaked extents
+    pub used_blocks: u32,
+    /// Blocks still available; the backup superblock is neither used nor free
+    pub free_blocks: u32,
+    pub root_entries: u32,
+    /// Longest name the filesystem accepts
+    pub max_name_len: usize,
+}
+
+/// Space consumed by one directory (or file) and everything beneath it
+#[derive(Clone, Debug)]
+pub struct DiskUsage {
+    pub path: String,
+    pub blocks: u32,
+    pub bytes: u64,
+}
+
+/// A single inconsistency found by [`check`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FsckIssue {
+    /// `next_free_block` lies outside the data area of the device
+    BadAllocator { next_free_block: u32, total_blocks: u32 },
+    /// The superblock's cached root entry count is stale
+    FileCountMismatch { recorded: u32, actual: u32 },
+    /// A directory slot carries an unknown entry type byte
+    InvalidEntryType { path: String, raw: u8 },
+    /// A name that is not UTF-8 or cannot be addressed by a path
+    InvalidName { path: String },
+    /// Two entries in the same directory share a name
+    DuplicateName { path: String },
+    /// An entry references blocks outside the allocated region
+    ExtentOutOfRange { path: String },
+    /// An entry references blocks already owned by another entry
+    OverlappingExtent { path: String },
+    /// Allocated blocks that no entry references
+    OrphanedBlocks { count: u32 },
+    /// The backup superblock at the end of the device differs from block 0
+    StaleBackupSuperblock,
+}
+
+impl fmt::Display for FsckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsckIssue::BadAllocator {
+                next_free_block,
+                total_blocks,
+            } => write!(
+                f,
+                "superblock next_free_block {} outside device of {} blocks",
+                next_free_block, total_blocks
+            ),
+            FsckIssue::FileCountMismatch { recorded, actual } => write!(
+                f,
+                "superblock records {} root entries, found {}",
+                recorded, actual
+            ),
+            FsckIssue::InvalidEntryType { path, raw } => {
+                write!(f, "{}: invalid entry type {}", path, raw)
+            }
+            FsckIssue::InvalidName { path } => write!(f, "{}: invalid name", path),
+            FsckIssue::DuplicateName { path } => write!(f, "{}: duplicate name", path),
+            FsckIssue::ExtentOutOfRange { path } => {
+                write!(f, "{}: blocks outside allocated region", path)
+            }
+            FsckIssue::OverlappingExtent { path } => {
+                write!(f, "{}: blocks shared with another entry", path)
+            }
+            FsckIssue::OrphanedBlocks { count } => {
+                write!(f, "{} allocated blocks are unreferenced", count)
+            }
+            FsckIssue::StaleBackupSuperblock => f.write_str("backup superblock out of date"),
+        }
+    }
+}
+
+/// Result of a consistency check
+#[derive(Clone, Debug, Default)]
+pub struct FsckReport {
+    pub issues: Vec<FsckIssue>,
+    pub files: usize,
+    pub directories: usize,
+    pub used_blocks: u32,
+    pub repaired: bool,
+}
+
+/// Limits checked whenever file data is allocated. Zero means unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FsLimits {
+    /// Largest file in bytes
+    pub max_file_size: u32,
+    /// Data-area blocks (as counted by the allocator) that file data may
+    /// push usage to; directory tables are exempt so `rm` keeps working
+    pub quota_blocks: u32,
+}
+
+/// An open file: the entry and its extents are resolved once by [`open`]
+/// and reused by [`read_at`] until the filesystem's metadata changes.
+#[derive(Clone, Debug)]
+pub struct FileHandle {
+    /// Mount point of the filesystem holding the file
+    mount: String,
+    /// Path within that filesystem
+    path: String,
+    generation: u64,
+    length: u32,
+    extents: Vec<Extent>,
+}
+
+impl FileHandle {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// File length in bytes when the handle was resolved
+    pub fn size(&self) -> usize {
+        self.length as usize
+    }
+}
+
+/// Outcome of [`compact`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompactReport {
+    /// Extent-mapped files rewritten as a single run
+    pub files_defragmented: usize,
+    /// Extent-mapped files left as they were for lack of contiguous space
+    pub files_skipped: usize,
+    pub blocks_moved: u32,
+    /// Blocks handed back to the allocator
+    pub blocks_reclaimed: u32,
+}
+
+/// Outcome of [`untar`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UntarReport {
+    pub files: usize,
+    pub directories: usize,
+    /// Links, special files, names TinyFs cannot store, and entries whose
+    /// names escape the destination
+    pub skipped: usize,
+    pub bytes: usize,
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 struct Superblock {
     magic: u32,
     version: u32,
     next_free_block: u32,
     file_count: u32,
+    /// Longest name accepted for new entries, at most `NAME_LEN`
+    max_name_len: u32,
+    limits: FsLimits,
+    /// Block size the filesystem was laid out with
+    block_size: u32,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -97,15 +335,67 @@ impl EntryType {
 #[derive(Clone, Debug)]
 struct FileEntry {
     name: String,
+    /// First data block, or the extent table block when `mapped` is set
     start_block: u32,
     length: u32,
     kind: EntryType,
+    /// File data is described by an extent table instead of one contiguous run
+    mapped: bool,
+    /// Owning uid; entries written before owners were recorded read as root
+    owner: u16,
+    /// Seconds since the Unix epoch when the contents last changed
+    mtime: u32,
+}
+
+/// A contiguous run of data blocks belonging to a file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Extent {
+    start: u32,
+    blocks: u32,
 }
 
 pub trait BlockDevice {
     fn total_blocks(&self) -> u32;
-    fn read_block(&self, index: u32, buf: &mut [u8]);
-    fn write_block(&self, index: u32, buf: &[u8]);
+    fn read_block(&self, index: u32, buf: &mut [u8]) -> Result<(), FsError>;
+    fn write_block(&self, index: u32, buf: &[u8]) -> Result<(), FsError>;
+
+    /// Bytes in one block: a power of two, at least `BLOCK_SIZE`
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    /// Whether the medium refuses writes
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Read `buf.len() / block_size()` consecutive blocks starting at `index`
+    fn read_blocks(&self, index: u32, buf: &mut [u8]) -> Result<(), FsError> {
+        for (i, block) in buf.chunks_exact_mut(self.block_size()).enumerate() {
+            self.read_block(index + i as u32, block)?;
+        }
+        Ok(())
+    }
+
+    /// Write `buf.len() / block_size()` consecutive blocks starting at `index`
+    fn write_blocks(&self, index: u32, buf: &[u8]) -> Result<(), FsError> {
+        for (i, block) in buf.chunks_exact(self.block_size()).enumerate() {
+            self.write_block(index + i as u32, block)?;
+        }
+        Ok(())
+    }
+
+    /// Make completed writes durable; devices without a write cache have
+    /// nothing to do
+    fn flush(&self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
+
+/// The driver has already retried and logged the failure; callers only
+/// need to know the data did not move
+fn io_error(_: VirtioError) -> FsError {
+    FsError::IoError
 }
 
 impl BlockDevice for VirtIoBlock {
@@ -113,87 +403,227 @@ impl BlockDevice for VirtIoBlock {
         VirtIoBlock::total_blocks(self)
     }
 
-    fn read_block(&self, index: u32, buf: &mut [u8]) {
-        VirtIoBlock::read_block(self, index, buf);
+    fn read_block(&self, index: u32, buf: &mut [u8]) -> Result<(), FsError> {
+        VirtIoBlock::read_block(self, index, buf).map_err(io_error)
+    }
+
+    fn write_block(&self, index: u32, buf: &[u8]) -> Result<(), FsError> {
+        VirtIoBlock::write_block(self, index, buf).map_err(io_error)
+    }
+
+    fn block_size(&self) -> usize {
+        VirtIoBlock::block_size(self)
     }
 
-    fn write_block(&self, index: u32, buf: &[u8]) {
-        VirtIoBlock::write_block(self, index, buf);
+    fn read_only(&self) -> bool {
+        VirtIoBlock::is_read_only(self)
+    }
+
+    fn read_blocks(&self, index: u32, buf: &mut [u8]) -> Result<(), FsError> {
+        VirtIoBlock::read_blocks(self, index, buf).map_err(io_error)
+    }
+
+    fn write_blocks(&self, index: u32, buf: &[u8]) -> Result<(), FsError> {
+        VirtIoBlock::write_blocks(self, index, buf).map_err(io_error)
+    }
+
+    fn flush(&self) -> Result<(), FsError> {
+        VirtIoBlock::flush(self).map(|_| ()).map_err(io_error)
     }
 }
 
 struct TinyFs<D: BlockDevice> {
     device: D,
+    /// The device's block size, which is also the filesystem's
+    block_size: usize,
+    /// The device refuses writes, so every change is refused up front
+    read_only: bool,
     superblock: Superblock,
     root_entries: Vec<FileEntry>,
+    /// Bumped on every superblock flush, i.e. whenever metadata may have
+    /// moved; open `FileHandle`s compare against it
+    generation: u64,
 }
 
 impl<D: BlockDevice> TinyFs<D> {
-    pub fn mount(device: D) -> Self {
-        let mut fs = Self {
+    pub fn mount(device: D) -> Result<Self, FsError> {
+        let mut fs = Self::unloaded(device);
+        // Read-only media without a TinyFs stays an empty tree
+        if !fs.load()? && !fs.read_only {
+            fs.format_disk()?;
+        }
+        Ok(fs)
+    }
+
+    /// Mount `device` only if it already holds a TinyFs
+    pub fn try_mount(device: D) -> Result<Self, FsError> {
+        let mut fs = Self::unloaded(device);
+        if !fs.load()? {
+            return Err(FsError::NoFilesystem);
+        }
+        Ok(fs)
+    }
+
+    /// Format `device` and mount the empty filesystem
+    pub fn create(device: D) -> Result<Self, FsError> {
+        let mut fs = Self::unloaded(device);
+        fs.format_disk()?;
+        Ok(fs)
+    }
+
+    fn unloaded(device: D) -> Self {
+        Self {
             superblock: Superblock::default(),
+            block_size: device.block_size(),
+            read_only: device.read_only(),
             device,
             root_entries: Vec::new(),
-        };
-        fs.load_or_format();
-        fs
+            generation: 0,
+        }
     }
 
-    fn load_or_format(&mut self) {
-        let mut buf = [0u8; BLOCK_SIZE];
-        self.device.read_block(0, &mut buf);
+    /// Load the superblock, or its backup, and the root directory. Returns
+    /// false if neither copy is valid.
+    fn load(&mut self) -> Result<bool, FsError> {
+        let mut buf = vec![0u8; self.block_size];
+        self.device.read_block(0, &mut buf)?;
         let superblock = Self::parse_superblock(&buf);
-        if superblock.magic != MAGIC || superblock.version != VERSION {
-            self.format_disk();
-        } else {
+        if self.usable(&superblock) {
             self.superblock = superblock;
-            self.load_root_directory();
+            self.load_root_directory()?;
+            return Ok(true);
+        }
+        if superblock.magic == MAGIC && superblock.version == VERSION {
+            crate::klog!(
+                Fs,
+                Warn,
+                "fs: filesystem has {}-byte blocks but the device has {}-byte blocks",
+                superblock.block_size,
+                self.block_size
+            );
+            return Ok(false);
+        }
+
+        let backup_index = self.backup_superblock_index();
+        self.device.read_block(backup_index, &mut buf)?;
+        let backup = Self::parse_superblock(&buf);
+        if self.usable(&backup)
+            && (DATA_START_BLOCK..=backup_index).contains(&backup.next_free_block)
+        {
+            crate::klog!(
+                Fs,
+                Warn,
+                "fs: primary superblock damaged, restored from backup"
+            );
+            self.superblock = backup;
+            if !self.read_only {
+                self.flush_superblock()?;
+            }
+            self.load_root_directory()?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Refuse changes to a filesystem on read-only media
+    fn check_writable(&self) -> Result<(), FsError> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
         }
+        Ok(())
+    }
+
+    /// Whether `superblock` describes a TinyFs laid out for this device
+    fn usable(&self, superblock: &Superblock) -> bool {
+        superblock.magic == MAGIC
+            && superblock.version == VERSION
+            && superblock.block_size as usize == self.block_size
+    }
+
+    /// Most entries the single root directory block holds
+    fn max_root_entries(&self) -> usize {
+        self.block_size / DIR_ENTRY_SIZE
+    }
+
+    /// Most runs one extent table block holds
+    fn max_extents(&self) -> usize {
+        self.block_size / EXTENT_SIZE
+    }
+
+    /// The last block of the device holds a copy of the superblock.
+    fn backup_superblock_index(&self) -> u32 {
+        self.device.total_blocks() - 1
+    }
+
+    fn format_disk(&mut self) -> Result<(), FsError> {
+        self.format_disk_with(NAME_LEN)
     }
 
-    fn format_disk(&mut self) {
-        let blank = [0u8; BLOCK_SIZE];
+    fn format_disk_with(&mut self, max_name_len: usize) -> Result<(), FsError> {
+        let blank = vec![0u8; self.block_size];
         for block in 0..DATA_START_BLOCK {
-            self.device.write_block(block, &blank);
+            self.device.write_block(block, &blank)?;
         }
         self.superblock = Superblock {
             magic: MAGIC,
             version: VERSION,
             next_free_block: DATA_START_BLOCK,
             file_count: 0,
+            max_name_len: max_name_len as u32,
+            limits: FsLimits::default(),
+            block_size: self.block_size as u32,
         };
         self.root_entries.clear();
-        self.flush_root_directory();
-        self.flush_superblock();
+        self.flush_root_directory()?;
+        self.flush_superblock()
     }
 
-    fn load_root_directory(&mut self) {
+    fn load_root_directory(&mut self) -> Result<(), FsError> {
         self.root_entries.clear();
-        let mut buf = [0u8; BLOCK_SIZE];
-        self.device.read_block(DIR_BLOCK_INDEX, &mut buf);
-        for chunk in buf.chunks(DIR_ENTRY_SIZE).take(MAX_FILES) {
+        let mut buf = vec![0u8; self.block_size];
+        self.device.read_block(DIR_BLOCK_INDEX, &mut buf)?;
+        for chunk in buf.chunks(DIR_ENTRY_SIZE).take(self.max_root_entries()) {
             if let Some(entry) = deserialize_entry(chunk) {
                 self.root_entries.push(entry);
             }
         }
+        Ok(())
+    }
+
+    fn flush_superblock(&mut self) -> Result<(), FsError> {
+        self.generation += 1;
+        let buf = self.serialize_superblock();
+        self.device.write_block(0, &buf)?;
+        // Images written before the backup existed may have data there
+        let backup_index = self.backup_superblock_index();
+        if self.superblock.next_free_block <= backup_index {
+            self.device.write_block(backup_index, &buf)?;
+        }
+        Ok(())
     }
 
-    fn flush_superblock(&mut self) {
-        let mut buf = [0u8; BLOCK_SIZE];
+    fn serialize_superblock(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; self.block_size];
         buf[..4].copy_from_slice(&self.superblock.magic.to_le_bytes());
         buf[4..8].copy_from_slice(&self.superblock.version.to_le_bytes());
         buf[8..12].copy_from_slice(&self.superblock.next_free_block.to_le_bytes());
         buf[12..16].copy_from_slice(&self.superblock.file_count.to_le_bytes());
-        self.device.write_block(0, &buf);
+        buf[16..20].copy_from_slice(&self.superblock.max_name_len.to_le_bytes());
+        buf[20..24].copy_from_slice(&self.superblock.limits.max_file_size.to_le_bytes());
+        buf[24..28].copy_from_slice(&self.superblock.limits.quota_blocks.to_le_bytes());
+        buf[28..32].copy_from_slice(&self.superblock.block_size.to_le_bytes());
+        buf
     }
 
-    fn flush_root_directory(&mut self) {
-        let mut buf = [0u8; BLOCK_SIZE];
-        for (slot, entry) in self.root_entries.iter().enumerate().take(MAX_FILES) {
+    fn flush_root_directory(&mut self) -> Result<(), FsError> {
+        let mut buf = vec![0u8; self.block_size];
+        let max_entries = self.max_root_entries();
+        for (slot, entry) in self.root_entries.iter().enumerate().take(max_entries) {
             let offset = slot * DIR_ENTRY_SIZE;
             write_entry(&mut buf[offset..offset + DIR_ENTRY_SIZE], entry);
         }
-        self.device.write_block(DIR_BLOCK_INDEX, &buf);
+        self.device.write_block(DIR_BLOCK_INDEX, &buf)
     }
 
     fn parse_superblock(buf: &[u8]) -> Superblock {
@@ -202,49 +632,149 @@ impl<D: BlockDevice> TinyFs<D> {
             version: u32::from_le_bytes(buf[4..8].try_into().unwrap()),
             next_free_block: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
             file_count: u32::from_le_bytes(buf[12..16].try_into().unwrap()),
+            max_name_len: match u32::from_le_bytes(buf[16..20].try_into().unwrap()) {
+                // Images formatted before the limit was recorded
+                0 => NAME_LEN as u32,
+                len => len.min(NAME_LEN as u32),
+            },
+            limits: FsLimits {
+                max_file_size: u32::from_le_bytes(buf[20..24].try_into().unwrap()),
+                quota_blocks: u32::from_le_bytes(buf[24..28].try_into().unwrap()),
+            },
+            block_size: match u32::from_le_bytes(buf[28..32].try_into().unwrap()) {
+                // Images formatted before the block size was recorded
+                0 => BLOCK_SIZE as u32,
+                size => size,
+            },
+        }
+    }
+
+    /// Reject names that could not round-trip through a directory entry.
+    fn validate_name(&self, name: &str) -> Result<(), FsError> {
+        check_name_chars(name)?;
+        if name.len() > self.superblock.max_name_len as usize {
+            return Err(FsError::NameTooLong);
         }
+        Ok(())
     }
 
     fn allocate_blocks(&mut self, blocks: u32) -> Result<u32, FsError> {
         let start = self.superblock.next_free_block;
-        if start + blocks > self.device.total_blocks() {
+        if start + blocks > self.backup_superblock_index() {
             return Err(FsError::NoSpace);
         }
         self.superblock.next_free_block += blocks;
         Ok(start)
     }
 
+    /// Reject file data that would break the configured `FsLimits`.
+    fn check_limits(&self, file_len: usize, new_blocks: u32) -> Result<(), FsError> {
+        let limits = self.superblock.limits;
+        if limits.max_file_size != 0 && file_len > limits.max_file_size as usize {
+            return Err(FsError::FileTooLarge);
+        }
+        let used = self.superblock.next_free_block.saturating_sub(DATA_START_BLOCK);
+        if limits.quota_blocks != 0 && used.saturating_add(new_blocks) > limits.quota_blocks {
+            return Err(FsError::QuotaExceeded);
+        }
+        Ok(())
+    }
+
     fn allocate_and_write(&mut self, contents: &[u8]) -> Result<(u32, u32), FsError> {
+        let blocks_needed = contents.len().div_ceil(self.block_size) as u32;
+        self.check_limits(contents.len(), blocks_needed)?;
+        self.write_run(contents)
+    }
+
+    /// Write `contents` to freshly allocated blocks without checking limits.
+    fn write_run(&mut self, contents: &[u8]) -> Result<(u32, u32), FsError> {
         if contents.is_empty() {
             return Ok((0, 0));
         }
-        let blocks_needed = contents.len().div_ceil(BLOCK_SIZE) as u32;
+        let blocks_needed = contents.len().div_ceil(self.block_size) as u32;
         let start_block = self.allocate_blocks(blocks_needed)?;
-        let mut buf = [0u8; BLOCK_SIZE];
-        for (i, chunk) in contents.chunks(BLOCK_SIZE).enumerate() {
-            buf.fill(0);
-            buf[..chunk.len()].copy_from_slice(chunk);
-            self.device.write_block(start_block + i as u32, &buf);
+        // Whole blocks go straight from `contents`; only the tail is padded
+        let whole = contents.len() / self.block_size * self.block_size;
+        if whole > 0 {
+            self.device.write_blocks(start_block, &contents[..whole])?;
+        }
+        if whole < contents.len() {
+            let mut buf = vec![0u8; self.block_size];
+            buf[..contents.len() - whole].copy_from_slice(&contents[whole..]);
+            let tail_block = start_block + (whole / self.block_size) as u32;
+            self.device.write_block(tail_block, &buf)?;
         }
         Ok((start_block, contents.len() as u32))
     }
 
-    fn read_data(&self, start_block: u32, length: u32) -> Vec<u8> {
+    fn read_data(&self, start_block: u32, length: u32) -> Result<Vec<u8>, FsError> {
+        let extent = Extent {
+            start: start_block,
+            blocks: (length as usize).div_ceil(self.block_size) as u32,
+        };
+        self.read_extents(&[extent], length)
+    }
+
+    fn read_extents(&self, extents: &[Extent], length: u32) -> Result<Vec<u8>, FsError> {
         if length == 0 {
-            return Vec::new();
+            return Ok(Vec::new());
+        }
+        // Read each run with one multi-block request, then drop the slack
+        // past `length` in the last block
+        let length = length as usize;
+        let mut data = Vec::with_capacity(length.next_multiple_of(self.block_size));
+        for extent in extents {
+            let remaining = length.saturating_sub(data.len());
+            if remaining == 0 {
+                break;
+            }
+            let blocks = (extent.blocks as usize).min(remaining.div_ceil(self.block_size));
+            let offset = data.len();
+            data.resize(offset + blocks * self.block_size, 0);
+            self.device.read_blocks(extent.start, &mut data[offset..])?;
+        }
+        data.truncate(length);
+        Ok(data)
+    }
+
+    fn read_entry_data(&self, entry: &FileEntry) -> Result<Vec<u8>, FsError> {
+        let extents = self.file_extents(entry)?;
+        self.read_extents(&extents, entry.length)
+    }
+
+    /// Every data run owned by `entry`, in file order.
+    fn file_extents(&self, entry: &FileEntry) -> Result<Vec<Extent>, FsError> {
+        if !entry.mapped {
+            if entry.start_block == 0 {
+                return Ok(Vec::new());
+            }
+            return Ok(vec![Extent {
+                start: entry.start_block,
+                blocks: (entry.length as usize).div_ceil(self.block_size) as u32,
+            }]);
         }
-        let mut remaining = length as usize;
-        let mut data = Vec::with_capacity(remaining);
-        let mut block_index = start_block;
-        let mut buf = vec![0u8; BLOCK_SIZE];
-        while remaining > 0 {
-            self.device.read_block(block_index, &mut buf);
-            let take = remaining.min(BLOCK_SIZE);
-            data.extend_from_slice(&buf[..take]);
-            remaining -= take;
-            block_index += 1;
+        let mut buf = vec![0u8; self.block_size];
+        self.device.read_block(entry.start_block, &mut buf)?;
+        let mut extents = Vec::new();
+        for chunk in buf.chunks(EXTENT_SIZE) {
+            let start = u32::from_le_bytes(chunk[0..4].try_into().unwrap());
+            let blocks = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+            if blocks == 0 {
+                break;
+            }
+            extents.push(Extent { start, blocks });
+        }
+        Ok(extents)
+    }
+
+    fn write_extent_table(&mut self, table_block: u32, extents: &[Extent]) -> Result<(), FsError> {
+        let mut buf = vec![0u8; self.block_size];
+        for (slot, extent) in extents.iter().enumerate().take(self.max_extents()) {
+            let offset = slot * EXTENT_SIZE;
+            buf[offset..offset + 4].copy_from_slice(&extent.start.to_le_bytes());
+            buf[offset + 4..offset + 8].copy_from_slice(&extent.blocks.to_le_bytes());
         }
-        data
+        self.device.write_block(table_block, &buf)
     }
 
     fn read_directory_entries(&self, entry: &FileEntry) -> Result<Vec<FileEntry>, FsError> {
@@ -254,7 +784,7 @@ impl<D: BlockDevice> TinyFs<D> {
         if entry.length == 0 {
             return Ok(Vec::new());
         }
-        let raw = self.read_data(entry.start_block, entry.length);
+        let raw = self.read_data(entry.start_block, entry.length)?;
         let mut entries = Vec::new();
         for chunk in raw.chunks(DIR_ENTRY_SIZE) {
             if chunk.len() < DIR_ENTRY_SIZE {
@@ -276,7 +806,7 @@ impl<D: BlockDevice> TinyFs<D> {
             let offset = i * DIR_ENTRY_SIZE;
             write_entry(&mut data[offset..offset + DIR_ENTRY_SIZE], entry);
         }
-        self.allocate_and_write(&data)
+        self.write_run(&data)
     }
 
     fn split_path<'a>(&self, path: &'a str) -> Result<Vec<&'a str>, FsError> {
@@ -336,9 +866,10 @@ impl<D: BlockDevice> TinyFs<D> {
         }
         self.root_entries = core::mem::take(&mut chain[0].entries);
         self.superblock.file_count = self.root_entries.len() as u32;
-        self.flush_root_directory();
-        self.flush_superblock();
-        Ok(())
+        // Reserve every block written above before the root block makes
+        // them reachable; the single root block write is the commit point.
+        self.flush_superblock()?;
+        self.flush_root_directory()
     }
 
     fn list_directory(&mut self, path: &str) -> Result<Vec<String>, FsError> {
@@ -359,6 +890,85 @@ impl<D: BlockDevice> TinyFs<D> {
         Ok(names)
     }
 
+    fn read_dir_batch(&mut self, path: &str, cookie: usize) -> Result<DirBatch, FsError> {
+        let components = self.split_path(path)?;
+        let (slots, total) = match components.split_last() {
+            None => {
+                let total = self.root_entries.len();
+                let end = cookie.saturating_add(READ_DIR_BATCH).min(total);
+                let slots = self
+                    .root_entries
+                    .iter()
+                    .cloned()
+                    .enumerate()
+                    .take(end)
+                    .skip(cookie)
+                    .collect();
+                (slots, total)
+            }
+            Some((leaf, dirs)) => {
+                let chain = self.load_directory_chain(dirs)?;
+                let parent = &chain.last().expect("chain non-empty").entries;
+                let Some(dir) = parent.iter().find(|entry| entry.name == *leaf) else {
+                    return Err(FsError::NotFound);
+                };
+                if dir.kind != EntryType::Directory {
+                    return Err(FsError::NotADirectory);
+                }
+                let total = dir.length as usize / DIR_ENTRY_SIZE;
+                (self.read_directory_slots(dir, cookie, READ_DIR_BATCH)?, total)
+            }
+        };
+
+        let entries = slots
+            .into_iter()
+            .map(|(slot, entry)| DirEntry {
+                is_dir: entry.kind == EntryType::Directory,
+                size: entry.length,
+                mtime: entry.mtime,
+                name: entry.name,
+                cookie: slot + 1,
+            })
+            .collect();
+        let next = cookie.saturating_add(READ_DIR_BATCH);
+        Ok(DirBatch {
+            entries,
+            next_cookie: (next < total).then_some(next),
+        })
+    }
+
+    /// Decode up to `count` entries starting at slot `first`, reading only the
+    /// blocks that hold them.
+    fn read_directory_slots(
+        &self,
+        dir: &FileEntry,
+        first: usize,
+        count: usize,
+    ) -> Result<Vec<(usize, FileEntry)>, FsError> {
+        let total = dir.length as usize / DIR_ENTRY_SIZE;
+        if first >= total {
+            return Ok(Vec::new());
+        }
+        let last = first.saturating_add(count).min(total);
+        let start_byte = first * DIR_ENTRY_SIZE;
+        let end_byte = last * DIR_ENTRY_SIZE;
+        let mut raw = Vec::with_capacity(end_byte - start_byte);
+        let mut buf = vec![0u8; self.block_size];
+        let block_size = self.block_size;
+        for block in start_byte / block_size..end_byte.div_ceil(block_size) {
+            self.device.read_block(dir.start_block + block as u32, &mut buf)?;
+            let block_start = block * block_size;
+            let lo = start_byte.max(block_start) - block_start;
+            let hi = end_byte.min(block_start + block_size) - block_start;
+            raw.extend_from_slice(&buf[lo..hi]);
+        }
+        Ok(raw
+            .chunks(DIR_ENTRY_SIZE)
+            .enumerate()
+            .filter_map(|(i, chunk)| deserialize_entry(chunk).map(|entry| (first + i, entry)))
+            .collect())
+    }
+
     fn read_file_contents(&mut self, path: &str) -> Result<Vec<u8>, FsError> {
         let components = self.split_path(path)?;
         if components.is_empty() {
@@ -373,19 +983,18 @@ impl<D: BlockDevice> TinyFs<D> {
         if entry.kind != EntryType::File {
             return Err(FsError::NotADirectory);
         }
-        Ok(self.read_data(entry.start_block, entry.length))
+        self.read_entry_data(entry)
     }
 
     fn write_file_contents(&mut self, path: &str, contents: &[u8]) -> Result<(), FsError> {
+        self.check_writable()?;
         let components = self.split_path(path)?;
         if components.is_empty() {
             return Err(FsError::InvalidPath);
         }
         let (dirs, leaf) = components.split_at(components.len() - 1);
         let file_name = leaf[0];
-        if file_name.is_empty() || file_name.len() > NAME_LEN {
-            return Err(FsError::NameTooLong);
-        }
+        self.validate_name(file_name)?;
         let mut chain = self.load_directory_chain(dirs)?;
         let parent_is_root = chain.len() == 1;
         let parent_entries = chain.last_mut().expect("chain non-empty");
@@ -395,123 +1004,442 @@ impl<D: BlockDevice> TinyFs<D> {
             .iter()
             .position(|entry| entry.name == file_name);
 
-        if existing_index.is_none() && parent_is_root && parent_entries.entries.len() >= MAX_FILES {
+        let root_full = parent_entries.entries.len() >= self.max_root_entries();
+        if existing_index.is_none() && parent_is_root && root_full {
             return Err(FsError::DirectoryFull);
         }
+        if let Some(idx) = existing_index
+            && parent_entries.entries[idx].kind != EntryType::File
+        {
+            return Err(FsError::NotADirectory);
+        }
 
+        // Stage the new contents in unreferenced blocks as a temporary
+        // entry; the old data stays reachable until the swap below.
         let (start_block, length) = self.allocate_and_write(contents)?;
+        let staged = FileEntry {
+            name: String::from(file_name),
+            start_block,
+            length,
+            kind: EntryType::File,
+            mapped: false,
+            owner: existing_index.map_or(0, |idx| parent_entries.entries[idx].owner),
+            mtime: now(),
+        };
 
         match existing_index {
-            Some(idx) => {
-                if parent_entries.entries[idx].kind != EntryType::File {
-                    return Err(FsError::NotADirectory);
-                }
-                parent_entries.entries[idx].start_block = start_block;
-                parent_entries.entries[idx].length = length;
-            }
-            None => {
-                parent_entries.entries.push(FileEntry {
-                    name: String::from(file_name),
-                    start_block,
-                    length,
-                    kind: EntryType::File,
-                });
-            }
+            Some(idx) => parent_entries.entries[idx] = staged,
+            None => parent_entries.entries.push(staged),
         }
 
         self.persist_directory_chain(&mut chain)
     }
 
-    fn create_directory(&mut self, path: &str) -> Result<(), FsError> {
+    fn write_file_at(&mut self, path: &str, offset: usize, data: &[u8]) -> Result<usize, FsError> {
+        self.write_file_range(path, Some(offset), data)
+    }
+
+    fn append_file(&mut self, path: &str, data: &[u8]) -> Result<usize, FsError> {
+        self.write_file_range(path, None, data)
+    }
+
+    /// Write `data` at `offset`, or at the end of the file when `offset` is
+    /// `None`, growing the file's extent only as far as needed.
+    fn write_file_range(
+        &mut self,
+        path: &str,
+        offset: Option<usize>,
+        data: &[u8],
+    ) -> Result<usize, FsError> {
+        self.check_writable()?;
         let components = self.split_path(path)?;
         if components.is_empty() {
             return Err(FsError::InvalidPath);
         }
         let (dirs, leaf) = components.split_at(components.len() - 1);
-        let dir_name = leaf[0];
-        if dir_name.is_empty() || dir_name.len() > NAME_LEN {
-            return Err(FsError::NameTooLong);
-        }
         let mut chain = self.load_directory_chain(dirs)?;
-        let parent_is_root = chain.len() == 1;
         let parent_entries = chain.last_mut().expect("chain non-empty");
 
-        if let Some(entry) = parent_entries
+        let Some(idx) = parent_entries
             .entries
             .iter()
-            .find(|entry| entry.name == dir_name)
-        {
-            return Err(FsError::AlreadyExists);
+            .position(|entry| entry.name == leaf[0])
+        else {
+            return Err(FsError::NotFound);
+        };
+        let entry = parent_entries.entries[idx].clone();
+        if entry.kind != EntryType::File {
+            return Err(FsError::IsDirectory);
+        }
+        if data.is_empty() {
+            return Ok(0);
         }
 
-        if parent_is_root && parent_entries.entries.len() >= MAX_FILES {
-            return Err(FsError::DirectoryFull);
+        let length = entry.length as usize;
+        let offset = offset.unwrap_or(length);
+        let end = offset.checked_add(data.len()).ok_or(FsError::NoSpace)?;
+        if end > u32::MAX as usize {
+            return Err(FsError::NoSpace);
         }
 
-        parent_entries.entries.push(FileEntry {
-            name: String::from(dir_name),
-            start_block: 0,
-            length: 0,
-            kind: EntryType::Directory,
-        });
+        let owned = self.file_extents(&entry)?;
+        let mut extents = owned.clone();
+        let (start_block, mapped) = self.ensure_capacity(&entry, &mut extents, end)?;
+        if mapped && extents != owned {
+            self.write_extent_table(start_block, &extents)?;
+        }
+        self.write_data_at(&extents, length, offset, data)?;
 
-        self.persist_directory_chain(&mut chain)
+        // In-place writes within the same second leave the entry alone
+        let mtime = now();
+        let moved = start_block != entry.start_block || mapped != entry.mapped;
+        if !moved && end <= length && mtime == entry.mtime {
+            return Ok(data.len());
+        }
+        parent_entries.entries[idx].start_block = start_block;
+        parent_entries.entries[idx].length = end.max(length) as u32;
+        parent_entries.entries[idx].mapped = mapped;
+        parent_entries.entries[idx].mtime = mtime;
+        self.persist_directory_chain(&mut chain)?;
+        Ok(data.len())
     }
 
-    fn create_file(&mut self, path: &str) -> Result<(), FsError> {
-        let components = self.split_path(path)?;
-        if components.is_empty() {
-            return Err(FsError::InvalidPath);
-        }
-        let (dirs, leaf) = components.split_at(components.len() - 1);
-        let file_name = leaf[0];
-        if file_name.is_empty() || file_name.len() > NAME_LEN {
-            return Err(FsError::NameTooLong);
+    /// Grow `extents` until they cover `len` bytes. Returns the entry's new
+    /// `start_block` and whether it now needs an extent table.
+    fn ensure_capacity(
+        &mut self,
+        entry: &FileEntry,
+        extents: &mut Vec<Extent>,
+        len: usize,
+    ) -> Result<(u32, bool), FsError> {
+        let owned_blocks: u32 = extents.iter().map(|extent| extent.blocks).sum();
+        let needed_blocks = len.div_ceil(self.block_size) as u32;
+        // Overwrites inside a file that predates a smaller limit still work
+        let checked_len = if len > entry.length as usize { len } else { 0 };
+        let extra = needed_blocks.saturating_sub(owned_blocks);
+        self.check_limits(checked_len, extra)?;
+        if extra == 0 {
+            return Ok((entry.start_block, entry.mapped));
         }
 
-        let mut chain = self.load_directory_chain(dirs)?;
-        let parent_is_root = chain.len() == 1;
-        let parent_entries = chain.last_mut().expect("chain non-empty");
-
-        if parent_entries
-            .entries
-            .iter()
-            .any(|entry| entry.name == file_name)
+        // The most recent allocation can grow by bumping the allocator.
+        if let Some(last) = extents.last_mut()
+            && last.start + last.blocks == self.superblock.next_free_block
         {
-            return Err(FsError::AlreadyExists);
+            self.allocate_blocks(extra)?;
+            last.blocks += extra;
+            return Ok(table_or_start(entry, extents));
         }
 
-        if parent_is_root && parent_entries.entries.len() >= MAX_FILES {
-            return Err(FsError::DirectoryFull);
+        if extents.is_empty() {
+            let start = self.allocate_blocks(extra)?;
+            extents.push(Extent {
+                start,
+                blocks: extra,
+            });
+            return Ok((start, false));
         }
 
-        parent_entries.entries.push(FileEntry {
-            name: String::from(file_name),
-            start_block: 0,
-            length: 0,
-            kind: EntryType::File,
-        });
+        if extents.len() >= self.max_extents() {
+            return self.coalesce_extents(extents, needed_blocks);
+        }
 
-        self.persist_directory_chain(&mut chain)
+        // Grab the table block before the data so the new run stays at the
+        // tail of the allocator and can keep growing in place.
+        let table_block = if entry.mapped {
+            entry.start_block
+        } else {
+            self.allocate_blocks(1)?
+        };
+        let start = self.allocate_blocks(extra)?;
+        extents.push(Extent {
+            start,
+            blocks: extra,
+        });
+        Ok((table_block, true))
     }
 
-    fn remove_file(&mut self, path: &str) -> Result<(), FsError> {
-        let components = self.split_path(path)?;
-        if components.is_empty() {
-            return Err(FsError::InvalidPath);
+    /// Copy a file whose extent table is full into one contiguous run of
+    /// `needed_blocks` blocks.
+    fn coalesce_extents(
+        &mut self,
+        extents: &mut Vec<Extent>,
+        needed_blocks: u32,
+    ) -> Result<(u32, bool), FsError> {
+        let start = self.allocate_blocks(needed_blocks)?;
+        let mut buf = vec![0u8; self.block_size];
+        for (i, block_index) in extent_blocks(extents).enumerate() {
+            self.device.read_block(block_index, &mut buf)?;
+            self.device.write_block(start + i as u32, &buf)?;
         }
-        let (dirs, leaf) = components.split_at(components.len() - 1);
-        let file_name = leaf[0];
-
-        let mut chain = self.load_directory_chain(dirs)?;
-        let parent_entries = chain.last_mut().expect("chain non-empty");
+        extents.clear();
+        extents.push(Extent {
+            start,
+            blocks: needed_blocks,
+        });
+        Ok((start, false))
+    }
 
-        let Some(idx) = parent_entries
-            .entries
+    /// Patch `data` into the blocks described by `extents`. Bytes at or
+    /// past `valid_len` are treated as unwritten and zeroed rather than
+    /// read back.
+    fn write_data_at(
+        &mut self,
+        extents: &[Extent],
+        valid_len: usize,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), FsError> {
+        let blocks: Vec<u32> = extent_blocks(extents).collect();
+        let end = offset + data.len();
+        let first_block = offset.min(valid_len) / self.block_size;
+        let last_block = end.div_ceil(self.block_size);
+        let mut buf = vec![0u8; self.block_size];
+        for (block, &device_block) in blocks
             .iter()
-            .position(|entry| entry.name == file_name)
-        else {
-            return Err(FsError::NotFound);
+            .enumerate()
+            .take(last_block)
+            .skip(first_block)
+        {
+            let block_start = block * self.block_size;
+            let block_end = block_start + self.block_size;
+            let covered = offset <= block_start && end >= block_end;
+            buf.fill(0);
+            if !covered && block_start < valid_len {
+                self.device.read_block(device_block, &mut buf)?;
+                if valid_len < block_end {
+                    buf[valid_len - block_start..].fill(0);
+                }
+            }
+            let copy_start = offset.max(block_start);
+            let copy_end = end.min(block_end);
+            if copy_start < copy_end {
+                buf[copy_start - block_start..copy_end - block_start]
+                    .copy_from_slice(&data[copy_start - offset..copy_end - offset]);
+            }
+            self.device.write_block(device_block, &buf)?;
+        }
+        Ok(())
+    }
+
+    /// Owner of the file or directory at `path`; the root directory
+    /// belongs to root
+    fn entry_owner(&mut self, path: &str) -> Result<u16, FsError> {
+        let components = self.split_path(path)?;
+        let Some((leaf, dirs)) = components.split_last() else {
+            return Ok(0);
+        };
+        let chain = self.load_directory_chain(dirs)?;
+        let entries = &chain.last().expect("chain non-empty").entries;
+        entries
+            .iter()
+            .find(|entry| entry.name == *leaf)
+            .map(|entry| entry.owner)
+            .ok_or(FsError::NotFound)
+    }
+
+    fn metadata(&mut self, path: &str) -> Result<Metadata, FsError> {
+        let components = self.split_path(path)?;
+        let Some((leaf, dirs)) = components.split_last() else {
+            return Ok(Metadata {
+                is_dir: true,
+                size: 0,
+                mtime: 0,
+                owner: 0,
+            });
+        };
+        let chain = self.load_directory_chain(dirs)?;
+        let entries = &chain.last().expect("chain non-empty").entries;
+        let entry = entries
+            .iter()
+            .find(|entry| entry.name == *leaf)
+            .ok_or(FsError::NotFound)?;
+        let is_dir = entry.kind == EntryType::Directory;
+        Ok(Metadata {
+            is_dir,
+            size: if is_dir { 0 } else { entry.length as u64 },
+            mtime: entry.mtime,
+            owner: entry.owner,
+        })
+    }
+
+    fn set_entry_owner(&mut self, path: &str, owner: u16) -> Result<(), FsError> {
+        self.check_writable()?;
+        let components = self.split_path(path)?;
+        let Some((leaf, dirs)) = components.split_last() else {
+            return Err(FsError::InvalidPath);
+        };
+        let mut chain = self.load_directory_chain(dirs)?;
+        let entries = &mut chain.last_mut().expect("chain non-empty").entries;
+        let Some(entry) = entries.iter_mut().find(|entry| entry.name == *leaf) else {
+            return Err(FsError::NotFound);
+        };
+        entry.owner = owner;
+        self.persist_directory_chain(&mut chain)
+    }
+
+    fn file_length(&mut self, path: &str) -> Result<usize, FsError> {
+        Ok(self.lookup_file(path)?.length as usize)
+    }
+
+    fn lookup_file(&mut self, path: &str) -> Result<FileEntry, FsError> {
+        let components = self.split_path(path)?;
+        if components.is_empty() {
+            return Err(FsError::InvalidPath);
+        }
+        let (dirs, leaf) = components.split_at(components.len() - 1);
+        let chain = self.load_directory_chain(dirs)?;
+        let entries = &chain.last().expect("chain non-empty").entries;
+        let Some(entry) = entries.iter().find(|entry| entry.name == leaf[0]) else {
+            return Err(FsError::NotFound);
+        };
+        if entry.kind != EntryType::File {
+            return Err(FsError::IsDirectory);
+        }
+        Ok(entry.clone())
+    }
+
+    fn open_file(&mut self, path: &str) -> Result<FileHandle, FsError> {
+        let entry = self.lookup_file(path)?;
+        Ok(FileHandle {
+            mount: String::from("/"),
+            path: String::from(path),
+            generation: self.generation,
+            length: entry.length,
+            extents: self.file_extents(&entry)?,
+        })
+    }
+
+    /// Read from `offset` into `buf` touching only the blocks that overlap
+    /// the range. The handle is re-resolved if metadata changed since it was
+    /// opened.
+    fn read_handle(
+        &mut self,
+        handle: &mut FileHandle,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, FsError> {
+        if handle.generation != self.generation {
+            *handle = self.open_file(&handle.path)?;
+        }
+        let length = handle.length as usize;
+        if offset >= length || buf.is_empty() {
+            return Ok(0);
+        }
+        let end = length.min(offset + buf.len());
+        let first_block = offset / self.block_size;
+        let last_block = end.div_ceil(self.block_size);
+        let mut block_buf = vec![0u8; self.block_size];
+        for (block, device_block) in extent_blocks(&handle.extents)
+            .enumerate()
+            .take(last_block)
+            .skip(first_block)
+        {
+            self.device.read_block(device_block, &mut block_buf)?;
+            let block_start = block * self.block_size;
+            let lo = offset.max(block_start);
+            let hi = end.min(block_start + self.block_size);
+            buf[lo - offset..hi - offset]
+                .copy_from_slice(&block_buf[lo - block_start..hi - block_start]);
+        }
+        Ok(end - offset)
+    }
+
+    fn create_directory(&mut self, path: &str) -> Result<(), FsError> {
+        self.check_writable()?;
+        let components = self.split_path(path)?;
+        if components.is_empty() {
+            return Err(FsError::InvalidPath);
+        }
+        let (dirs, leaf) = components.split_at(components.len() - 1);
+        let dir_name = leaf[0];
+        self.validate_name(dir_name)?;
+        let mut chain = self.load_directory_chain(dirs)?;
+        let parent_is_root = chain.len() == 1;
+        let parent_entries = chain.last_mut().expect("chain non-empty");
+
+        if parent_entries
+            .entries
+            .iter()
+            .any(|entry| entry.name == dir_name)
+        {
+            return Err(FsError::AlreadyExists);
+        }
+
+        if parent_is_root && parent_entries.entries.len() >= self.max_root_entries() {
+            return Err(FsError::DirectoryFull);
+        }
+
+        parent_entries.entries.push(FileEntry {
+            name: String::from(dir_name),
+            start_block: 0,
+            length: 0,
+            kind: EntryType::Directory,
+            mapped: false,
+            owner: 0,
+            mtime: now(),
+        });
+
+        self.persist_directory_chain(&mut chain)
+    }
+
+    fn create_file(&mut self, path: &str) -> Result<(), FsError> {
+        self.check_writable()?;
+        let components = self.split_path(path)?;
+        if components.is_empty() {
+            return Err(FsError::InvalidPath);
+        }
+        let (dirs, leaf) = components.split_at(components.len() - 1);
+        let file_name = leaf[0];
+        self.validate_name(file_name)?;
+
+        let mut chain = self.load_directory_chain(dirs)?;
+        let parent_is_root = chain.len() == 1;
+        let parent_entries = chain.last_mut().expect("chain non-empty");
+
+        if parent_entries
+            .entries
+            .iter()
+            .any(|entry| entry.name == file_name)
+        {
+            return Err(FsError::AlreadyExists);
+        }
+
+        if parent_is_root && parent_entries.entries.len() >= self.max_root_entries() {
+            return Err(FsError::DirectoryFull);
+        }
+
+        parent_entries.entries.push(FileEntry {
+            name: String::from(file_name),
+            start_block: 0,
+            length: 0,
+            kind: EntryType::File,
+            mapped: false,
+            owner: 0,
+            mtime: now(),
+        });
+
+        self.persist_directory_chain(&mut chain)
+    }
+
+    fn remove_file(&mut self, path: &str) -> Result<(), FsError> {
+        self.check_writable()?;
+        let components = self.split_path(path)?;
+        if components.is_empty() {
+            return Err(FsError::InvalidPath);
+        }
+        let (dirs, leaf) = components.split_at(components.len() - 1);
+        let file_name = leaf[0];
+
+        let mut chain = self.load_directory_chain(dirs)?;
+        let parent_entries = chain.last_mut().expect("chain non-empty");
+
+        let Some(idx) = parent_entries
+            .entries
+            .iter()
+            .position(|entry| entry.name == file_name)
+        else {
+            return Err(FsError::NotFound);
         };
 
         if parent_entries.entries[idx].kind != EntryType::File {
@@ -523,6 +1451,7 @@ impl<D: BlockDevice> TinyFs<D> {
     }
 
     fn remove_directory(&mut self, path: &str) -> Result<(), FsError> {
+        self.check_writable()?;
         let components = self.split_path(path)?;
         if components.is_empty() {
             return Err(FsError::InvalidPath);
@@ -554,6 +1483,90 @@ impl<D: BlockDevice> TinyFs<D> {
         parent_entries.entries.remove(idx);
         self.persist_directory_chain(&mut chain)
     }
+
+    /// Move the entry at `from` to `to`, replacing a file already there.
+    /// Within one directory this is a single commit; across directories
+    /// the entry joins its new parent before leaving the old one, so an
+    /// interrupted move leaves it in both rather than neither.
+    fn rename(&mut self, from: &str, to: &str) -> Result<(), FsError> {
+        self.check_writable()?;
+        let from_parts = self.split_path(from)?;
+        let to_parts = self.split_path(to)?;
+        if from_parts.is_empty() || to_parts.is_empty() {
+            return Err(FsError::InvalidPath);
+        }
+        let (from_dirs, from_leaf) = from_parts.split_at(from_parts.len() - 1);
+        let (to_dirs, to_leaf) = to_parts.split_at(to_parts.len() - 1);
+        let from_name = from_leaf[0];
+        self.validate_name(to_leaf[0])?;
+
+        let mut chain = self.load_directory_chain(from_dirs)?;
+        let Some(mut entry) = chain
+            .last()
+            .expect("chain non-empty")
+            .entries
+            .iter()
+            .find(|entry| entry.name == from_name)
+            .cloned()
+        else {
+            return Err(FsError::NotFound);
+        };
+        if from_parts == to_parts {
+            return Ok(());
+        }
+        // A directory cannot move inside itself
+        if to_parts.starts_with(&from_parts) {
+            return Err(FsError::InvalidPath);
+        }
+        entry.name = String::from(to_leaf[0]);
+
+        if from_dirs == to_dirs {
+            let entries = &mut chain.last_mut().expect("chain non-empty").entries;
+            drop_replaced(entries, &entry)?;
+            let idx = entries
+                .iter()
+                .position(|existing| existing.name == from_name)
+                .expect("source entry still present");
+            entries[idx] = entry;
+            return self.persist_directory_chain(&mut chain);
+        }
+
+        let mut to_chain = self.load_directory_chain(to_dirs)?;
+        let parent_is_root = to_chain.len() == 1;
+        let entries = &mut to_chain.last_mut().expect("chain non-empty").entries;
+        drop_replaced(entries, &entry)?;
+        if parent_is_root && entries.len() >= self.max_root_entries() {
+            return Err(FsError::DirectoryFull);
+        }
+        entries.push(entry);
+        self.persist_directory_chain(&mut to_chain)?;
+
+        // The commit above may have rewritten directories on the source
+        // path, so look them up again
+        let mut chain = self.load_directory_chain(from_dirs)?;
+        chain
+            .last_mut()
+            .expect("chain non-empty")
+            .entries
+            .retain(|existing| existing.name != from_name);
+        self.persist_directory_chain(&mut chain)
+    }
+}
+
+/// Remove the entry that `entry` is about to replace, if any. Only a file
+/// can replace a file.
+fn drop_replaced(entries: &mut Vec<FileEntry>, entry: &FileEntry) -> Result<(), FsError> {
+    let Some(idx) = entries
+        .iter()
+        .position(|existing| existing.name == entry.name)
+    else {
+        return Ok(());
+    };
+    if entries[idx].kind != EntryType::File || entry.kind != EntryType::File {
+        return Err(FsError::AlreadyExists);
+    }
+    entries.remove(idx);
+    Ok(())
 }
 
 struct LoadedDir {
@@ -561,99 +1574,750 @@ struct LoadedDir {
     entry_index_in_parent: Option<usize>,
 }
 
+/// A run of live blocks found by `compact` and the pointer that names it
+struct Relocation {
+    from: u32,
+    /// Current location: `from` until the run is moved
+    to: u32,
+    blocks: u32,
+    owner: RunOwner,
+}
+
+/// Where a run's start block is recorded
+#[derive(Clone, Copy)]
+enum RunOwner {
+    /// Slot in the root directory block
+    Root(usize),
+    /// Entry slot inside the directory stored in another run
+    Directory { run: usize, slot: usize },
+    /// Extent slot inside the extent table stored in another run
+    ExtentTable { run: usize, slot: usize },
+}
+
+/// A directory as seen by the checker, with invalid entries already dropped
+struct CheckedDir {
+    entries: Vec<FileEntry>,
+    subdirs: Vec<(usize, CheckedDir)>,
+    dirty: bool,
+}
+
+/// Resolve `input` against `cwd`, collapsing repeated slashes, `.` and `..`.
+/// The result is always absolute; the root is `/`. `..` at the root stays
+/// at the root.
+pub fn canonicalize(cwd: &str, input: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    if !input.starts_with('/') {
+        segments.extend(cwd.split('/').filter(|segment| !segment.is_empty()));
+    }
+
+    for part in input.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(part),
+        }
+    }
+
+    if segments.is_empty() {
+        String::from("/")
+    } else {
+        alloc::format!("/{}", segments.join("/"))
+    }
+}
+
+/// A mounted filesystem as reported by [`mounts`]
+#[derive(Clone, Debug)]
+pub struct MountInfo {
+    pub point: String,
+    pub device: String,
+    pub read_only: bool,
+}
+
+/// Mount the root filesystem from `vda`, formatting the disk if it holds
+/// no TinyFs
 pub fn init() -> Result<(), FsError> {
-    let mut guard = FS_INSTANCE.lock();
-    if guard.is_none() {
+    let mut mounts = MOUNTS.lock();
+    if mounts.is_empty() {
         let device = block::init().map_err(FsError::DeviceInitFailed)?;
-        *guard = Some(TinyFs::mount(device));
+        mounts.push(Mount {
+            point: String::from("/"),
+            device: block::name(0),
+            fs: Volume::Tiny(TinyFs::mount(device)?),
+        });
+    }
+    Ok(())
+}
+
+/// Attach the TinyFs on block device `device` (`vdb` or `/dev/vdb`) at the
+/// existing directory `point`. Unlike the root, a disk without a TinyFs
+/// is refused rather than formatted; see [`format_device`]. Device `9p`
+/// is the host directory shared over virtio-9p.
+pub fn mount(device: &str, point: &str) -> Result<(), FsError> {
+    let device = device.strip_prefix("/dev/").unwrap_or(device);
+    if !point.starts_with('/') {
+        return Err(FsError::InvalidPath);
+    }
+    let mut mounts = MOUNTS.lock();
+    if mounts
+        .iter()
+        .any(|mount| mount.point == point || mount.device == device)
+    {
+        return Err(FsError::Busy);
+    }
+    let (index, relative) = resolve(&mounts, point)?;
+    mounts[index].fs.ensure_directory_exists(relative)?;
+    let fs = if device == HOST_DEVICE {
+        Volume::Host(ninep::Client::attach()?)
+    } else {
+        let block = block::open(device).map_err(FsError::DeviceInitFailed)?;
+        Volume::Tiny(TinyFs::try_mount(block)?)
+    };
+    mounts.push(Mount {
+        point: String::from(point),
+        device: String::from(device),
+        fs,
+    });
+    Ok(())
+}
+
+/// Detach the filesystem mounted at `point`. The root and mount points
+/// with other mounts below them stay.
+pub fn unmount(point: &str) -> Result<(), FsError> {
+    let mut mounts = MOUNTS.lock();
+    let index = mounts
+        .iter()
+        .position(|mount| mount.point == point)
+        .ok_or(FsError::NotFound)?;
+    let nested = mounts
+        .iter()
+        .any(|mount| mount.point != point && mount_relative(point, &mount.point).is_some());
+    if index == 0 || nested {
+        return Err(FsError::Busy);
+    }
+    mounts.remove(index);
+    Ok(())
+}
+
+/// Every mounted filesystem, the root first
+pub fn mounts() -> Vec<MountInfo> {
+    MOUNTS
+        .lock()
+        .iter()
+        .map(|mount| MountInfo {
+            point: mount.point.clone(),
+            device: mount.device.clone(),
+            read_only: mount.fs.read_only(),
+        })
+        .collect()
+}
+
+/// Write an empty TinyFs to the unmounted block device `device`
+pub fn format_device(device: &str) -> Result<(), FsError> {
+    let device = device.strip_prefix("/dev/").unwrap_or(device);
+    if MOUNTS.lock().iter().any(|mount| mount.device == device) {
+        return Err(FsError::Busy);
     }
+    let block = block::open(device).map_err(FsError::DeviceInitFailed)?;
+    if block.is_read_only() {
+        return Err(FsError::ReadOnly);
+    }
+    TinyFs::create(block)?;
     Ok(())
 }
 
+/// The part of `path` below mount point `point`, or None if `path` is not
+/// under it
+fn mount_relative<'a>(point: &str, path: &'a str) -> Option<&'a str> {
+    if point == "/" {
+        return Some(path);
+    }
+    let rest = path.strip_prefix(point)?;
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+/// Index of the mount holding `path`, the one with the longest matching
+/// point, and the path within it
+fn resolve<'a>(mounts: &[Mount], path: &'a str) -> Result<(usize, &'a str), FsError> {
+    mounts
+        .iter()
+        .enumerate()
+        .filter_map(|(index, mount)| Some((index, mount_relative(&mount.point, path)?)))
+        .max_by_key(|&(index, _)| mounts[index].point.len())
+        .ok_or(FsError::NotInitialized)
+}
+
+/// Run `f` on the filesystem holding `path` with the path within it
+fn with_fs_at<T>(
+    path: &str,
+    f: impl FnOnce(&mut Volume, &str) -> Result<T, FsError>,
+) -> Result<T, FsError> {
+    let mut mounts = MOUNTS.lock();
+    let (index, relative) = resolve(&mounts, path)?;
+    crate::klog!(Fs, Debug, "fs: {} on {}", path, mounts[index].device);
+    f(&mut mounts[index].fs, relative)
+}
+
+/// Run `f` on the root filesystem
 fn with_fs<T>(
     f: impl FnOnce(&mut TinyFs<VirtIoBlock>) -> Result<T, FsError>,
 ) -> Result<T, FsError> {
-    let mut guard = FS_INSTANCE.lock();
-    match guard.as_mut() {
-        Some(fs) => f(fs),
-        None => Err(FsError::NotInitialized),
-    }
+    with_fs_at("/", |fs, _| f(fs.tiny()?))
 }
 
 pub fn list_files(path: Option<&str>) -> Result<Vec<String>, FsError> {
-    with_fs(|fs| fs.list_directory(path.unwrap_or("")))
+    with_fs_at(path.unwrap_or(""), |fs, path| fs.list_directory(path))
+}
+
+/// Return the batch of entries in `path` starting at `cookie` (0 for the
+/// first batch). Only the directory blocks covering the batch are read.
+pub fn read_dir(path: &str, cookie: usize) -> Result<DirBatch, FsError> {
+    with_fs_at(path, |fs, path| fs.read_dir_batch(path, cookie))
 }
 
 pub fn read_file(path: &str) -> Result<Vec<u8>, FsError> {
-    with_fs(|fs| fs.read_file_contents(path))
+    with_fs_at(path, |fs, path| fs.read_file_contents(path))
 }
 
 pub fn write_file(path: &str, data: &[u8]) -> Result<(), FsError> {
-    with_fs(|fs| fs.write_file_contents(path, data))
+    with_fs_at(path, |fs, path| fs.write_file_contents(path, data))
+}
+
+/// Replace `path` with `data` so an interrupted update leaves either the old
+/// or the new contents: the data is staged in fresh blocks and swapped into
+/// the directory by a single update. `write_file` takes the same path; this
+/// name is for callers that depend on the guarantee.
+pub fn atomic_write(path: &str, data: &[u8]) -> Result<(), FsError> {
+    with_fs_at(path, |fs, path| fs.write_file_contents(path, data))
+}
+
+/// Write `data` at byte `offset`, touching only the blocks that change when
+/// the file's existing extent is large enough. Returns the bytes written.
+pub fn write_at(path: &str, offset: usize, data: &[u8]) -> Result<usize, FsError> {
+    with_fs_at(path, |fs, path| fs.write_file_at(path, offset, data))
+}
+
+/// Append `data` to the end of `path`. Files that sit at the end of the
+/// allocated region grow in place instead of being copied.
+pub fn append(path: &str, data: &[u8]) -> Result<usize, FsError> {
+    with_fs_at(path, |fs, path| fs.append_file(path, data))
+}
+
+pub fn file_size(path: &str) -> Result<usize, FsError> {
+    with_fs_at(path, |fs, path| fs.file_length(path))
+}
+
+pub fn open(path: &str) -> Result<FileHandle, FsError> {
+    let mut mounts = MOUNTS.lock();
+    let (index, relative) = resolve(&mounts, path)?;
+    let mount = &mut mounts[index];
+    let mut handle = mount.fs.open_file(relative)?;
+    handle.mount = mount.point.clone();
+    Ok(handle)
+}
+
+/// Read up to `buf.len()` bytes at `offset`; returns 0 at end of file.
+pub fn read_at(handle: &mut FileHandle, offset: usize, buf: &mut [u8]) -> Result<usize, FsError> {
+    let mut mounts = MOUNTS.lock();
+    let mount = mounts
+        .iter_mut()
+        .find(|mount| mount.point == handle.mount)
+        .ok_or(FsError::NotFound)?;
+    mount.fs.read_handle(handle, offset, buf)
 }
 
 pub fn mkdir(path: &str) -> Result<(), FsError> {
-    with_fs(|fs| fs.create_directory(path))
+    with_fs_at(path, |fs, path| fs.create_directory(path))
 }
 
 pub fn ensure_directory(path: &str) -> Result<(), FsError> {
-    with_fs(|fs| fs.ensure_directory_exists(path))
+    with_fs_at(path, |fs, path| fs.ensure_directory_exists(path))
 }
 
 pub fn create_file(path: &str) -> Result<(), FsError> {
-    with_fs(|fs| fs.create_file(path))
+    with_fs_at(path, |fs, path| fs.create_file(path))
+}
+
+/// Uid that owns `path`. New entries belong to root until `set_owner`;
+/// overwriting a file keeps its owner.
+pub fn owner(path: &str) -> Result<u16, FsError> {
+    with_fs_at(path, |fs, path| fs.entry_owner(path))
+}
+
+/// Kind, size, mtime and owner of the file or directory at `path`
+pub fn metadata(path: &str) -> Result<Metadata, FsError> {
+    with_fs_at(path, |fs, path| fs.metadata(path))
+}
+
+pub fn set_owner(path: &str, uid: u16) -> Result<(), FsError> {
+    with_fs_at(path, |fs, path| fs.set_entry_owner(path, uid))
 }
 
 pub fn remove_file(path: &str) -> Result<(), FsError> {
-    with_fs(|fs| fs.remove_file(path))
+    with_fs_at(path, |fs, path| fs.remove_file(path))
 }
 
 pub fn remove_directory(path: &str) -> Result<(), FsError> {
-    with_fs(|fs| fs.remove_directory(path))
+    with_fs_at(path, |fs, path| fs.remove_directory(path))
 }
 
-pub fn format() -> Result<(), FsError> {
-    with_fs(|fs| {
-        fs.format_disk();
-        Ok(())
-    })
+/// Move `from` to `to`. Both must be on the same filesystem; moving
+/// between mounts fails with CrossDevice and has to copy instead.
+pub fn rename(from: &str, to: &str) -> Result<(), FsError> {
+    let mut mounts = MOUNTS.lock();
+    let (index, from) = resolve(&mounts, from)?;
+    let (to_index, to) = resolve(&mounts, to)?;
+    if index != to_index {
+        return Err(FsError::CrossDevice);
+    }
+    mounts[index].fs.rename(from, to)
 }
 
-fn write_entry(buf: &mut [u8], entry: &FileEntry) {
-    buf.fill(0);
-    let name_bytes = entry.name.as_bytes();
-    let copy_len = NAME_LEN.min(name_bytes.len());
-    buf[..copy_len].copy_from_slice(&name_bytes[..copy_len]);
-    buf[NAME_LEN..NAME_LEN + 4].copy_from_slice(&entry.start_block.to_le_bytes());
-    buf[NAME_LEN + 4..NAME_LEN + 8].copy_from_slice(&entry.length.to_le_bytes());
-    buf[NAME_LEN + 8] = entry.kind.to_raw();
+/// Scan the superblock and directory tree for inconsistencies. With
+/// `repair`, invalid entries are dropped, the root entry count is fixed, and
+/// unreferenced blocks at the tail of the allocator are reclaimed.
+pub fn check(repair: bool) -> Result<FsckReport, FsError> {
+    with_fs(|fs| fs.check(repair))
 }
 
-fn deserialize_entry(chunk: &[u8]) -> Option<FileEntry> {
-    if chunk.len() < DIR_ENTRY_SIZE {
-        return None;
+/// Capacity of the filesystem holding `path`
+pub fn statfs(path: &str) -> Result<StatFs, FsError> {
+    with_fs_at(path, |fs, _| fs.statfs())
+}
+
+/// Usage for `path` and, if it is a directory, every directory below it.
+/// Children are listed before their parents; the last entry is `path`.
+pub fn disk_usage(path: &str) -> Result<Vec<DiskUsage>, FsError> {
+    let mut mounts = MOUNTS.lock();
+    let (index, relative) = resolve(&mounts, path)?;
+    let mount = &mut mounts[index];
+    let mut usage = mount.fs.disk_usage(relative)?;
+    if mount.point != "/" {
+        for entry in &mut usage {
+            entry.path = if entry.path == "/" {
+                mount.point.clone()
+            } else {
+                alloc::format!("{}{}", mount.point, entry.path)
+            };
+        }
     }
-    if chunk[0] == 0 {
-        return None;
+    Ok(usage)
+}
+
+/// Rewrite extent-mapped files as single runs, then slide every live run
+/// down over the gaps left by leaked extents. `progress(done, total)` is
+/// called after each run is placed. Each run is copied before the pointer
+/// to it is updated, but a run that overlaps its new location is
+/// overwritten in place, so avoid interrupting compaction.
+pub fn compact(mut progress: impl FnMut(usize, usize)) -> Result<CompactReport, FsError> {
+    with_fs(|fs| fs.compact(&mut progress))
+}
+
+/// Extract the ustar archive stored at `archive` into the directory `dest`,
+/// creating it and any intermediate directories. Existing files are
+/// overwritten. Both paths must be on the same filesystem.
+pub fn untar(archive: &str, dest: &str) -> Result<UntarReport, FsError> {
+    let mut mounts = MOUNTS.lock();
+    let (index, archive) = resolve(&mounts, archive)?;
+    let (dest_index, dest) = resolve(&mounts, dest)?;
+    if index != dest_index {
+        return Err(FsError::CrossDevice);
     }
-    let name_bytes = &chunk[..NAME_LEN];
-    let end = name_bytes.iter().position(|&b| b == 0).unwrap_or(NAME_LEN);
-    let name_slice = &name_bytes[..end];
-    let name = match str::from_utf8(name_slice) {
+    mounts[index].fs.untar(archive, dest)
+}
+
+/// Flush the device holding `path` so everything written to it so far
+/// survives a power loss
+pub fn sync(path: &str) -> Result<(), FsError> {
+    with_fs_at(path, |fs, _| fs.flush())
+}
+
+/// Flush every mounted device; run before the machine powers off. A
+/// device that fails to flush does not stop the others.
+pub fn sync_all() {
+    for mount in MOUNTS.lock().iter_mut() {
+        if let Err(err) = mount.fs.flush() {
+            crate::klog!(Fs, Error, "fs: flushing {} failed: {}", mount.point, err);
+        }
+    }
+}
+
+pub fn limits() -> Result<FsLimits, FsError> {
+    with_fs(|fs| Ok(fs.superblock.limits))
+}
+
+/// Persist new limits in the superblock. Existing files are left alone;
+/// only later allocations are checked.
+pub fn set_limits(limits: FsLimits) -> Result<(), FsError> {
+    with_fs(|fs| {
+        fs.check_writable()?;
+        fs.superblock.limits = limits;
+        fs.flush_superblock()
+    })
+}
+
+pub fn format() -> Result<(), FsError> {
+    format_with_name_limit(NAME_LEN)
+}
+
+/// Format, recording `max_name_len` (1..=32 bytes) as the name limit.
+pub fn format_with_name_limit(max_name_len: usize) -> Result<(), FsError> {
+    if max_name_len == 0 || max_name_len > NAME_LEN {
+        return Err(FsError::NameTooLong);
+    }
+    with_fs(|fs| {
+        fs.check_writable()?;
+        fs.format_disk_with(max_name_len)
+    })
+}
+
+/// Each operation goes to the TinyFs or host share behind the mount;
+/// repair, compaction and archive extraction only exist on TinyFs.
+impl Volume {
+    fn tiny(&mut self) -> Result<&mut TinyFs<VirtIoBlock>, FsError> {
+        match self {
+            Volume::Tiny(fs) => Ok(fs),
+            Volume::Host(_) => Err(FsError::Unsupported),
+        }
+    }
+
+    fn list_directory(&mut self, path: &str) -> Result<Vec<String>, FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.list_directory(path),
+            Volume::Host(host) => {
+                let (records, _) = host.readdir(path, 0, usize::MAX)?;
+                Ok(records
+                    .into_iter()
+                    .map(|record| {
+                        let mut name = record.name;
+                        if record.is_dir {
+                            name.push('/');
+                        }
+                        name
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    fn read_dir_batch(&mut self, path: &str, cookie: usize) -> Result<DirBatch, FsError> {
+        let host = match self {
+            Volume::Tiny(fs) => return fs.read_dir_batch(path, cookie),
+            Volume::Host(host) => host,
+        };
+        let (records, more) = host.readdir(path, cookie as u64, READ_DIR_BATCH)?;
+        let next_cookie = if more {
+            records.last().map(|record| record.offset as usize)
+        } else {
+            None
+        };
+        let mut entries = Vec::with_capacity(records.len());
+        for record in records {
+            let child = alloc::format!("{}/{}", path, record.name);
+            let (size, mtime) = match host.getattr(&child) {
+                Ok(attr) => {
+                    let size = if record.is_dir { 0 } else { attr.size };
+                    (
+                        size.min(u32::MAX as u64) as u32,
+                        attr.mtime.min(u32::MAX as u64) as u32,
+                    )
+                }
+                Err(_) => (0, 0),
+            };
+            entries.push(DirEntry {
+                name: record.name,
+                is_dir: record.is_dir,
+                size,
+                mtime,
+                cookie: record.offset as usize,
+            });
+        }
+        Ok(DirBatch {
+            entries,
+            next_cookie,
+        })
+    }
+
+    fn read_file_contents(&mut self, path: &str) -> Result<Vec<u8>, FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.read_file_contents(path),
+            Volume::Host(host) => host.read_all(path),
+        }
+    }
+
+    fn write_file_contents(&mut self, path: &str, contents: &[u8]) -> Result<(), FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.write_file_contents(path, contents),
+            Volume::Host(host) => host.create(path, contents, false),
+        }
+    }
+
+    fn write_file_at(&mut self, path: &str, offset: usize, data: &[u8]) -> Result<usize, FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.write_file_at(path, offset, data),
+            Volume::Host(host) => host.write(path, offset as u64, data, false),
+        }
+    }
+
+    fn append_file(&mut self, path: &str, data: &[u8]) -> Result<usize, FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.append_file(path, data),
+            Volume::Host(host) => {
+                let length = host.getattr(path)?.size;
+                host.write(path, length, data, false)
+            }
+        }
+    }
+
+    fn file_length(&mut self, path: &str) -> Result<usize, FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.file_length(path),
+            Volume::Host(host) => {
+                let attr = host.getattr(path)?;
+                if attr.is_dir() {
+                    return Err(FsError::IsDirectory);
+                }
+                Ok(attr.size as usize)
+            }
+        }
+    }
+
+    fn open_file(&mut self, path: &str) -> Result<FileHandle, FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.open_file(path),
+            Volume::Host(host) => {
+                let attr = host.getattr(path)?;
+                if attr.is_dir() {
+                    return Err(FsError::IsDirectory);
+                }
+                // Host files are read by path, so the handle maps no blocks
+                Ok(FileHandle {
+                    mount: String::new(),
+                    path: String::from(path),
+                    generation: 0,
+                    length: attr.size.min(u32::MAX as u64) as u32,
+                    extents: Vec::new(),
+                })
+            }
+        }
+    }
+
+    fn read_handle(
+        &mut self,
+        handle: &mut FileHandle,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.read_handle(handle, offset, buf),
+            Volume::Host(host) => host.read(&handle.path, offset as u64, buf),
+        }
+    }
+
+    fn create_directory(&mut self, path: &str) -> Result<(), FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.create_directory(path),
+            Volume::Host(host) => host.mkdir(path),
+        }
+    }
+
+    fn ensure_directory_exists(&mut self, path: &str) -> Result<(), FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.ensure_directory_exists(path),
+            Volume::Host(host) => {
+                if !host.getattr(path)?.is_dir() {
+                    return Err(FsError::NotADirectory);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn create_file(&mut self, path: &str) -> Result<(), FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.create_file(path),
+            Volume::Host(host) => host.create(path, &[], true),
+        }
+    }
+
+    fn entry_owner(&mut self, path: &str) -> Result<u16, FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.entry_owner(path),
+            Volume::Host(host) => Ok(host.getattr(path)?.uid as u16),
+        }
+    }
+
+    fn metadata(&mut self, path: &str) -> Result<Metadata, FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.metadata(path),
+            Volume::Host(host) => {
+                let attr = host.getattr(path)?;
+                Ok(Metadata {
+                    is_dir: attr.is_dir(),
+                    size: if attr.is_dir() { 0 } else { attr.size },
+                    mtime: attr.mtime.min(u32::MAX as u64) as u32,
+                    owner: attr.uid as u16,
+                })
+            }
+        }
+    }
+
+    fn set_entry_owner(&mut self, path: &str, owner: u16) -> Result<(), FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.set_entry_owner(path, owner),
+            Volume::Host(host) => host.set_uid(path, owner as u32),
+        }
+    }
+
+    fn remove_file(&mut self, path: &str) -> Result<(), FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.remove_file(path),
+            Volume::Host(host) => host.remove(path, false),
+        }
+    }
+
+    fn remove_directory(&mut self, path: &str) -> Result<(), FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.remove_directory(path),
+            Volume::Host(host) => host.remove(path, true),
+        }
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> Result<(), FsError> {
+        match self {
+            Volume::Tiny(fs) => fs.rename(from, to),
+            Volume::Host(host) => host.rename(from, to),
+        }
+    }
+
+    fn statfs(&mut self) -> Result<StatFs, FsError> {
+        let host = match self {
+            Volume::Tiny(fs) => return Ok(fs.statfs()),
+            Volume::Host(host) => host,
+        };
+        let stat = host.statfs()?;
+        let (root, _) = host.readdir("", 0, usize::MAX)?;
+        let total_blocks = stat.blocks.min(u32::MAX as u64) as u32;
+        let free_blocks = stat.free_blocks.min(total_blocks as u64) as u32;
+        Ok(StatFs {
+            block_size: stat.block_size as usize,
+            total_blocks,
+            used_blocks: total_blocks - free_blocks,
+            free_blocks,
+            root_entries: root.len() as u32,
+            max_name_len: stat.name_len as usize,
+        })
+    }
+
+    fn disk_usage(&mut self, path: &str) -> Result<Vec<DiskUsage>, FsError> {
+        self.tiny()?.disk_usage(path)
+    }
+
+    /// Host writes are applied by the host as they arrive, so only block
+    /// devices have a cache to drain
+    fn flush(&mut self) -> Result<(), FsError> {
+        match self {
+            Volume::Tiny(fs) if !fs.read_only => BlockDevice::flush(&fs.device),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the TinyFs is on read-only media; the host decides per
+    /// file for a host share
+    fn read_only(&self) -> bool {
+        matches!(self, Volume::Tiny(fs) if fs.read_only)
+    }
+
+    fn untar(&mut self, archive: &str, dest: &str) -> Result<UntarReport, FsError> {
+        self.tiny()?.untar(archive, dest)
+    }
+}
+
+/// Start block and mapping flag for an entry whose extents just changed.
+fn table_or_start(entry: &FileEntry, extents: &[Extent]) -> (u32, bool) {
+    if entry.mapped {
+        (entry.start_block, true)
+    } else {
+        (extents[0].start, false)
+    }
+}
+
+/// Blocks charged to `entry`: its data plus any extent table.
+fn entry_block_count(entry: &FileEntry, block_size: usize) -> u32 {
+    (entry.length as usize).div_ceil(block_size) as u32 + entry.mapped as u32
+}
+
+fn extent_blocks(extents: &[Extent]) -> impl Iterator<Item = u32> + '_ {
+    extents
+        .iter()
+        .flat_map(|extent| extent.start..extent.start.saturating_add(extent.blocks))
+}
+
+/// Reserved character policy shared by name validation and `check`: no
+/// empty names, `.`/`..`, path separators, NULs, or control characters.
+fn check_name_chars(name: &str) -> Result<(), FsError> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(FsError::InvalidName);
+    }
+    if name.chars().any(|c| c == '/' || c.is_control()) {
+        return Err(FsError::InvalidName);
+    }
+    Ok(())
+}
+
+fn write_entry(buf: &mut [u8], entry: &FileEntry) {
+    buf.fill(0);
+    let name_bytes = entry.name.as_bytes();
+    debug_assert!(name_bytes.len() <= NAME_LEN, "name not validated");
+    let copy_len = NAME_LEN.min(name_bytes.len());
+    buf[..copy_len].copy_from_slice(&name_bytes[..copy_len]);
+    buf[NAME_LEN..NAME_LEN + 4].copy_from_slice(&entry.start_block.to_le_bytes());
+    buf[NAME_LEN + 4..NAME_LEN + 8].copy_from_slice(&entry.length.to_le_bytes());
+    buf[NAME_LEN + 8] = entry.kind.to_raw();
+    if entry.mapped {
+        buf[NAME_LEN + 9] = ENTRY_FLAG_EXTENTS;
+    }
+    buf[NAME_LEN + 10..NAME_LEN + 12].copy_from_slice(&entry.owner.to_le_bytes());
+    buf[NAME_LEN + 12..NAME_LEN + 16].copy_from_slice(&entry.mtime.to_le_bytes());
+}
+
+fn deserialize_entry(chunk: &[u8]) -> Option<FileEntry> {
+    if chunk.len() < DIR_ENTRY_SIZE {
+        return None;
+    }
+    if chunk[0] == 0 {
+        return None;
+    }
+    let name_bytes = &chunk[..NAME_LEN];
+    let end = name_bytes.iter().position(|&b| b == 0).unwrap_or(NAME_LEN);
+    let name_slice = &name_bytes[..end];
+    let name = match str::from_utf8(name_slice) {
         Ok(s) => s,
         Err(_) => return None,
     };
     let start_block = u32::from_le_bytes(chunk[NAME_LEN..NAME_LEN + 4].try_into().unwrap());
     let length = u32::from_le_bytes(chunk[NAME_LEN + 4..NAME_LEN + 8].try_into().unwrap());
     let kind = EntryType::from_raw(chunk[NAME_LEN + 8])?;
+    let mapped = chunk[NAME_LEN + 9] & ENTRY_FLAG_EXTENTS != 0;
+    let owner = u16::from_le_bytes(chunk[NAME_LEN + 10..NAME_LEN + 12].try_into().unwrap());
+    let mtime = u32::from_le_bytes(chunk[NAME_LEN + 12..NAME_LEN + 16].try_into().unwrap());
     Some(FileEntry {
         name: String::from(name),
         start_block,
         length,
         kind,
+        mapped,
+        owner,
+        mtime,
     })
 }
 
+/// The wall-clock time for a new mtime
+fn now() -> u32 {
+    crate::rtc::unix_time().min(u32::MAX as u64) as u32
+}
+
 impl<D: BlockDevice> TinyFs<D> {
     fn ensure_directory_exists(&mut self, path: &str) -> Result<(), FsError> {
         let components = self.split_path(path)?;
@@ -661,3 +2325,583 @@ impl<D: BlockDevice> TinyFs<D> {
         Ok(())
     }
 }
+
+impl<D: BlockDevice> TinyFs<D> {
+    fn compact(&mut self, progress: &mut dyn FnMut(usize, usize)) -> Result<CompactReport, FsError> {
+        self.check_writable()?;
+        let healthy = self
+            .check(false)?
+            .issues
+            .iter()
+            .all(|issue| {
+                matches!(
+                    issue,
+                    FsckIssue::OrphanedBlocks { .. } | FsckIssue::StaleBackupSuperblock
+                )
+            });
+        if !healthy {
+            return Err(FsError::Inconsistent);
+        }
+        let mut report = CompactReport::default();
+        let allocated_before = self.superblock.next_free_block;
+
+        let root: Vec<(usize, FileEntry)> = self.root_entries.iter().cloned().enumerate().collect();
+        let mut runs = Vec::new();
+        let mut fragmented = Vec::new();
+        self.collect_runs("", &root, None, &mut runs, &mut fragmented)?;
+        for path in fragmented {
+            let data = self.read_file_contents(&path)?;
+            match self.write_file_contents(&path, &data) {
+                Ok(()) => report.files_defragmented += 1,
+                Err(FsError::NoSpace | FsError::QuotaExceeded) => report.files_skipped += 1,
+                Err(err) => return Err(err),
+            }
+        }
+
+        // Rewrites above moved data and directories, so start over
+        let root: Vec<(usize, FileEntry)> = self.root_entries.iter().cloned().enumerate().collect();
+        runs.clear();
+        self.collect_runs("", &root, None, &mut runs, &mut Vec::new())?;
+        let mut order: Vec<usize> = (0..runs.len()).collect();
+        order.sort_unstable_by_key(|&index| runs[index].from);
+
+        let mut cursor = DATA_START_BLOCK;
+        let mut buf = vec![0u8; self.block_size];
+        for (done, &index) in order.iter().enumerate() {
+            let Relocation { from, blocks, .. } = runs[index];
+            if from != cursor {
+                for block in 0..blocks {
+                    self.device.read_block(from + block, &mut buf)?;
+                    self.device.write_block(cursor + block, &buf)?;
+                }
+                runs[index].to = cursor;
+                self.update_run_owner(&runs, index)?;
+                report.blocks_moved += blocks;
+            }
+            cursor += blocks;
+            progress(done + 1, order.len());
+        }
+
+        self.superblock.next_free_block = cursor;
+        self.flush_superblock()?;
+        report.blocks_reclaimed = allocated_before.saturating_sub(cursor);
+        Ok(report)
+    }
+
+    /// Record every live run below `entries`, each tagged with the slot that
+    /// points at it. Extent-mapped files are also listed in `fragmented`.
+    fn collect_runs(
+        &self,
+        path: &str,
+        entries: &[(usize, FileEntry)],
+        parent: Option<usize>,
+        runs: &mut Vec<Relocation>,
+        fragmented: &mut Vec<String>,
+    ) -> Result<(), FsError> {
+        for (slot, entry) in entries {
+            let owner = match parent {
+                None => RunOwner::Root(*slot),
+                Some(run) => RunOwner::Directory { run, slot: *slot },
+            };
+            let entry_path = alloc::format!("{}/{}", path, entry.name);
+            if entry.mapped {
+                let table = runs.len();
+                runs.push(Relocation {
+                    from: entry.start_block,
+                    to: entry.start_block,
+                    blocks: 1,
+                    owner,
+                });
+                for (slot, extent) in self.file_extents(entry)?.into_iter().enumerate() {
+                    runs.push(Relocation {
+                        from: extent.start,
+                        to: extent.start,
+                        blocks: extent.blocks,
+                        owner: RunOwner::ExtentTable { run: table, slot },
+                    });
+                }
+                fragmented.push(entry_path);
+                continue;
+            }
+            if entry.start_block == 0 {
+                continue;
+            }
+            let run = runs.len();
+            runs.push(Relocation {
+                from: entry.start_block,
+                to: entry.start_block,
+                blocks: (entry.length as usize).div_ceil(self.block_size) as u32,
+                owner,
+            });
+            if entry.kind == EntryType::Directory {
+                let raw = self.read_data(entry.start_block, entry.length)?;
+                let children: Vec<(usize, FileEntry)> = raw
+                    .chunks(DIR_ENTRY_SIZE)
+                    .enumerate()
+                    .filter_map(|(slot, chunk)| Some((slot, deserialize_entry(chunk)?)))
+                    .collect();
+                self.collect_runs(&entry_path, &children, Some(run), runs, fragmented)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Point the owner of `runs[index]` at its new location.
+    fn update_run_owner(&mut self, runs: &[Relocation], index: usize) -> Result<(), FsError> {
+        let start = runs[index].to;
+        match runs[index].owner {
+            RunOwner::Root(slot) => {
+                self.root_entries[slot].start_block = start;
+                self.flush_root_directory()
+            }
+            RunOwner::Directory { run, slot } => {
+                self.patch_u32(runs[run].to, slot * DIR_ENTRY_SIZE + NAME_LEN, start)
+            }
+            RunOwner::ExtentTable { run, slot } => {
+                self.patch_u32(runs[run].to, slot * EXTENT_SIZE, start)
+            }
+        }
+    }
+
+    /// Overwrite a little-endian u32 at `offset` bytes into the run starting
+    /// at `start_block`; directory entries may straddle a block boundary.
+    fn patch_u32(&mut self, start_block: u32, offset: usize, value: u32) -> Result<(), FsError> {
+        let bytes = value.to_le_bytes();
+        let mut buf = vec![0u8; self.block_size];
+        let mut written = 0;
+        while written < bytes.len() {
+            let pos = offset + written;
+            let block = start_block + (pos / self.block_size) as u32;
+            self.device.read_block(block, &mut buf)?;
+            let take = (self.block_size - pos % self.block_size).min(bytes.len() - written);
+            buf[pos % self.block_size..pos % self.block_size + take]
+                .copy_from_slice(&bytes[written..written + take]);
+            self.device.write_block(block, &buf)?;
+            written += take;
+        }
+        Ok(())
+    }
+
+    fn statfs(&self) -> StatFs {
+        let total_blocks = self.device.total_blocks();
+        let used_blocks = self.superblock.next_free_block.min(total_blocks);
+        StatFs {
+            block_size: self.block_size,
+            total_blocks,
+            used_blocks,
+            free_blocks: self.backup_superblock_index().saturating_sub(used_blocks),
+            root_entries: self.root_entries.len() as u32,
+            max_name_len: self.superblock.max_name_len as usize,
+        }
+    }
+
+    fn disk_usage(&mut self, path: &str) -> Result<Vec<DiskUsage>, FsError> {
+        let components = self.split_path(path)?;
+        let mut usage = Vec::new();
+        let Some((leaf, dirs)) = components.split_last() else {
+            let root = self.root_entries.clone();
+            self.directory_usage(String::from("/"), 0, &root, &mut usage)?;
+            return Ok(usage);
+        };
+
+        let chain = self.load_directory_chain(dirs)?;
+        let entries = &chain.last().expect("chain non-empty").entries;
+        let Some(entry) = entries.iter().find(|entry| entry.name == *leaf) else {
+            return Err(FsError::NotFound);
+        };
+        let display = alloc::format!("/{}", components.join("/"));
+        match entry.kind {
+            EntryType::File => usage.push(DiskUsage {
+                path: display,
+                blocks: entry_block_count(entry, self.block_size),
+                bytes: entry.length as u64,
+            }),
+            EntryType::Directory => {
+                let children = self.read_directory_entries(entry)?;
+                self.directory_usage(display, entry_block_count(entry, self.block_size), &children, &mut usage)?;
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Record usage for a directory whose own entry table takes `own_blocks`
+    /// and return its totals.
+    fn directory_usage(
+        &self,
+        path: String,
+        own_blocks: u32,
+        entries: &[FileEntry],
+        usage: &mut Vec<DiskUsage>,
+    ) -> Result<(u32, u64), FsError> {
+        let mut blocks = own_blocks;
+        let mut bytes = 0;
+        for entry in entries {
+            match entry.kind {
+                EntryType::File => {
+                    blocks += entry_block_count(entry, self.block_size);
+                    bytes += entry.length as u64;
+                }
+                EntryType::Directory => {
+                    let child_path = if path == "/" {
+                        alloc::format!("/{}", entry.name)
+                    } else {
+                        alloc::format!("{}/{}", path, entry.name)
+                    };
+                    let children = self.read_directory_entries(entry)?;
+                    let (child_blocks, child_bytes) = self.directory_usage(
+                        child_path,
+                        entry_block_count(entry, self.block_size),
+                        &children,
+                        usage,
+                    )?;
+                    blocks += child_blocks;
+                    bytes += child_bytes;
+                }
+            }
+        }
+        usage.push(DiskUsage {
+            path,
+            blocks,
+            bytes,
+        });
+        Ok((blocks, bytes))
+    }
+
+    fn check(&mut self, repair: bool) -> Result<FsckReport, FsError> {
+        if repair {
+            self.check_writable()?;
+        }
+        let mut report = FsckReport::default();
+        let total_blocks = self.device.total_blocks();
+        let next_free_block = self.superblock.next_free_block;
+        let limit = if next_free_block < DATA_START_BLOCK || next_free_block > total_blocks {
+            report.issues.push(FsckIssue::BadAllocator {
+                next_free_block,
+                total_blocks,
+            });
+            total_blocks
+        } else {
+            next_free_block
+        };
+
+        let mut used = vec![false; limit as usize];
+        let mut raw_root = vec![0u8; self.block_size];
+        self.device.read_block(DIR_BLOCK_INDEX, &mut raw_root)?;
+        let raw_root = &raw_root[..self.max_root_entries() * DIR_ENTRY_SIZE];
+        let mut root = self.check_directory("", raw_root, limit, &mut used, &mut report)?;
+
+        if self.superblock.file_count as usize != root.entries.len() {
+            report.issues.push(FsckIssue::FileCountMismatch {
+                recorded: self.superblock.file_count,
+                actual: root.entries.len() as u32,
+            });
+        }
+
+        let orphaned = (DATA_START_BLOCK..limit)
+            .filter(|&block| !used[block as usize])
+            .count() as u32;
+        if orphaned > 0 {
+            report.issues.push(FsckIssue::OrphanedBlocks { count: orphaned });
+        }
+        report.used_blocks = limit.saturating_sub(DATA_START_BLOCK) - orphaned;
+
+        let backup_index = self.backup_superblock_index();
+        if next_free_block <= backup_index {
+            let mut backup = vec![0u8; self.block_size];
+            self.device.read_block(backup_index, &mut backup)?;
+            if backup != self.serialize_superblock() {
+                report.issues.push(FsckIssue::StaleBackupSuperblock);
+            }
+        }
+
+        if repair && !report.issues.is_empty() {
+            // Unreferenced blocks at the end of the allocated region can be
+            // handed back by pulling the bump allocator down.
+            self.superblock.next_free_block = (DATA_START_BLOCK..limit)
+                .rev()
+                .find(|&block| used[block as usize])
+                .map_or(DATA_START_BLOCK, |block| block + 1);
+            if root.dirty {
+                self.write_checked_directory(&mut root)?;
+                self.root_entries = root.entries;
+                self.flush_root_directory()?;
+            }
+            self.superblock.file_count = self.root_entries.len() as u32;
+            self.flush_superblock()?;
+            report.repaired = true;
+        }
+
+        Ok(report)
+    }
+
+    fn check_directory(
+        &self,
+        path: &str,
+        raw: &[u8],
+        limit: u32,
+        used: &mut [bool],
+        report: &mut FsckReport,
+    ) -> Result<CheckedDir, FsError> {
+        let mut dir = CheckedDir {
+            entries: Vec::new(),
+            subdirs: Vec::new(),
+            dirty: false,
+        };
+
+        for (slot, chunk) in raw.chunks(DIR_ENTRY_SIZE).enumerate() {
+            if chunk.len() < DIR_ENTRY_SIZE || chunk[0] == 0 {
+                continue;
+            }
+            let name_bytes = &chunk[..NAME_LEN];
+            let end = name_bytes.iter().position(|&b| b == 0).unwrap_or(NAME_LEN);
+            let name = match str::from_utf8(&name_bytes[..end]) {
+                Ok(name) if check_name_chars(name).is_ok() => name,
+                _ => {
+                    report.issues.push(FsckIssue::InvalidName {
+                        path: alloc::format!("{}/<slot {}>", path, slot),
+                    });
+                    dir.dirty = true;
+                    continue;
+                }
+            };
+            let entry_path = alloc::format!("{}/{}", path, name);
+            let Some(entry) = deserialize_entry(chunk) else {
+                report.issues.push(FsckIssue::InvalidEntryType {
+                    path: entry_path,
+                    raw: chunk[NAME_LEN + 8],
+                });
+                dir.dirty = true;
+                continue;
+            };
+            if dir.entries.iter().any(|existing| existing.name == entry.name) {
+                report.issues.push(FsckIssue::DuplicateName { path: entry_path });
+                dir.dirty = true;
+                continue;
+            }
+            let Some(blocks) = self.entry_blocks(&entry, limit)? else {
+                report.issues.push(FsckIssue::ExtentOutOfRange { path: entry_path });
+                dir.dirty = true;
+                continue;
+            };
+            if blocks.iter().any(|&block| used[block as usize]) {
+                report.issues.push(FsckIssue::OverlappingExtent { path: entry_path });
+                dir.dirty = true;
+                continue;
+            }
+            for &block in &blocks {
+                used[block as usize] = true;
+            }
+
+            match entry.kind {
+                EntryType::File => report.files += 1,
+                EntryType::Directory => {
+                    report.directories += 1;
+                    let raw = self.read_data(entry.start_block, entry.length)?;
+                    let child = self.check_directory(&entry_path, &raw, limit, used, report)?;
+                    dir.dirty |= child.dirty;
+                    dir.subdirs.push((dir.entries.len(), child));
+                }
+            }
+            dir.entries.push(entry);
+        }
+
+        Ok(dir)
+    }
+
+    /// Every block owned by `entry`, or `None` if any of them fall outside
+    /// `DATA_START_BLOCK..limit`.
+    fn entry_blocks(&self, entry: &FileEntry, limit: u32) -> Result<Option<Vec<u32>>, FsError> {
+        let in_range = |block: u32| (DATA_START_BLOCK..limit).contains(&block);
+        let needed = (entry.length as usize).div_ceil(self.block_size) as u32;
+        let mut blocks = Vec::new();
+        if entry.mapped {
+            if entry.kind != EntryType::File || !in_range(entry.start_block) {
+                return Ok(None);
+            }
+            blocks.push(entry.start_block);
+        } else if entry.start_block == 0 {
+            return Ok((needed == 0).then_some(blocks));
+        }
+
+        let extents = self.file_extents(entry)?;
+        let owned = extents
+            .iter()
+            .fold(0u32, |owned, extent| owned.saturating_add(extent.blocks));
+        if owned < needed {
+            return Ok(None);
+        }
+        for block in extent_blocks(&extents) {
+            if !in_range(block) {
+                return Ok(None);
+            }
+            blocks.push(block);
+        }
+        Ok(Some(blocks))
+    }
+
+    fn write_checked_directory(&mut self, dir: &mut CheckedDir) -> Result<(), FsError> {
+        let CheckedDir {
+            entries, subdirs, ..
+        } = dir;
+        for (idx, child) in subdirs.iter_mut() {
+            if !child.dirty {
+                continue;
+            }
+            self.write_checked_directory(child)?;
+            let (start, length) = self.write_directory_entries(&child.entries)?;
+            entries[*idx].start_block = start;
+            entries[*idx].length = length;
+        }
+        Ok(())
+    }
+}
+
+const TAR_HEADER_SIZE: usize = 512;
+
+/// The fields of a ustar header that extraction needs
+struct TarHeader<'a> {
+    prefix: &'a str,
+    name: &'a str,
+    size: usize,
+    kind: u8,
+}
+
+/// Parse one header block. `Ok(None)` marks the zero block that ends the
+/// archive.
+fn parse_tar_header(block: &[u8]) -> Result<Option<TarHeader<'_>>, FsError> {
+    if block.iter().all(|&byte| byte == 0) {
+        return Ok(None);
+    }
+    let recorded = parse_octal(&block[148..156])?;
+    let computed: usize = block
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| if (148..156).contains(&i) { b' ' } else { byte } as usize)
+        .sum();
+    if recorded != computed {
+        return Err(FsError::InvalidArchive);
+    }
+    let field = |range: core::ops::Range<usize>| {
+        let raw = &block[range];
+        let end = raw.iter().position(|&byte| byte == 0).unwrap_or(raw.len());
+        str::from_utf8(&raw[..end]).map_err(|_| FsError::InvalidEncoding)
+    };
+    // Old GNU headers reuse the prefix area, so require the POSIX magic
+    let prefix = if &block[257..263] == b"ustar\0" {
+        field(345..500)?
+    } else {
+        ""
+    };
+    Ok(Some(TarHeader {
+        prefix,
+        name: field(0..100)?,
+        size: parse_octal(&block[124..136])?,
+        kind: block[156],
+    }))
+}
+
+/// Numeric header fields are NUL- or space-terminated octal.
+fn parse_octal(field: &[u8]) -> Result<usize, FsError> {
+    let mut value: usize = 0;
+    for &byte in field.iter().skip_while(|&&byte| byte == b' ') {
+        match byte {
+            b'0'..=b'7' => {
+                value = value
+                    .checked_mul(8)
+                    .and_then(|value| value.checked_add((byte - b'0') as usize))
+                    .ok_or(FsError::InvalidArchive)?;
+            }
+            0 | b' ' => break,
+            _ => return Err(FsError::InvalidArchive),
+        }
+    }
+    Ok(value)
+}
+
+impl<D: BlockDevice> TinyFs<D> {
+    fn untar(&mut self, archive: &str, dest: &str) -> Result<UntarReport, FsError> {
+        self.check_writable()?;
+        let data = self.read_file_contents(archive)?;
+        let dest = canonicalize("/", dest);
+        self.create_directory_all(&dest)?;
+
+        let mut report = UntarReport::default();
+        let mut long_name: Option<String> = None;
+        let mut offset = 0;
+        while offset + TAR_HEADER_SIZE <= data.len() {
+            let Some(header) = parse_tar_header(&data[offset..offset + TAR_HEADER_SIZE])? else {
+                return Ok(report);
+            };
+            let body_start = offset + TAR_HEADER_SIZE;
+            let body_end = body_start
+                .checked_add(header.size)
+                .filter(|&end| end <= data.len())
+                .ok_or(FsError::InvalidArchive)?;
+            offset = body_start + header.size.div_ceil(TAR_HEADER_SIZE) * TAR_HEADER_SIZE;
+
+            let body = &data[body_start..body_end];
+            if header.kind == b'L' {
+                // GNU long name: the body names the entry that follows
+                let end = body.iter().position(|&byte| byte == 0).unwrap_or(body.len());
+                let name = str::from_utf8(&body[..end]).map_err(|_| FsError::InvalidEncoding)?;
+                long_name = Some(String::from(name));
+                continue;
+            }
+            let name = match long_name.take() {
+                Some(name) => name,
+                None => alloc::format!("{}/{}", header.prefix, header.name),
+            };
+            if name.split('/').any(|segment| segment == "..") {
+                report.skipped += 1;
+                continue;
+            }
+            let path = canonicalize(&dest, name.trim_start_matches('/'));
+            if path == dest {
+                continue;
+            }
+            let result = match header.kind {
+                b'0' | 0 => path
+                    .rsplit_once('/')
+                    .map_or(Ok(()), |(parent, _)| self.create_directory_all(parent))
+                    .and_then(|()| self.write_file_contents(&path, body))
+                    .map(|()| {
+                        report.files += 1;
+                        report.bytes += header.size;
+                    }),
+                b'5' => self
+                    .create_directory_all(&path)
+                    .map(|()| report.directories += 1),
+                _ => {
+                    report.skipped += 1;
+                    Ok(())
+                }
+            };
+            match result {
+                // Names TinyFs cannot store are skipped rather than aborting
+                // half way through the archive
+                Err(FsError::NameTooLong | FsError::InvalidName) => report.skipped += 1,
+                other => other?,
+            }
+        }
+        // Archives may end without the trailing zero blocks
+        Ok(report)
+    }
+
+    /// Create `path` and any missing parents; existing directories are fine.
+    fn create_directory_all(&mut self, path: &str) -> Result<(), FsError> {
+        let mut prefix = String::new();
+        for component in path.split('/').filter(|segment| !segment.is_empty()) {
+            prefix.push('/');
+            prefix.push_str(component);
+            match self.create_directory(&prefix) {
+                Ok(()) | Err(FsError::AlreadyExists) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        self.ensure_directory_exists(path)
+    }
+}
+
+#[cfg(test)]
+#[path = "fs_tests.rs"]
+mod tests;
@@ -0,0 +1,853 @@
+//! TinyFs tests, built only by the host crate in `tinyfs_host/`.
+
+use super::*;
+use alloc::collections::BTreeMap;
+use core::cell::{Cell, RefCell};
+
+const TEST_BLOCKS: u32 = 1024;
+
+/// RAM-backed block device. `write_budget` simulates power loss: once it
+/// reaches zero, further block writes are silently dropped. `requests`
+/// counts calls, so multi-block transfers show up as one. Writes to a
+/// `read_only` device panic, as they would fail on real media. Requests
+/// touching `bad_block` fail like a bad sector.
+struct MemBlockDevice {
+    data: RefCell<Vec<u8>>,
+    block_size: usize,
+    read_only: bool,
+    bad_block: Cell<Option<u32>>,
+    write_budget: Cell<Option<usize>>,
+    requests: Cell<usize>,
+}
+
+impl MemBlockDevice {
+    fn new(blocks: u32) -> Self {
+        Self::with_block_size(blocks, BLOCK_SIZE)
+    }
+
+    fn with_block_size(blocks: u32, block_size: usize) -> Self {
+        Self {
+            block_size,
+            ..Self::from_image(vec![0; blocks as usize * block_size])
+        }
+    }
+
+    fn from_image(image: Vec<u8>) -> Self {
+        Self {
+            data: RefCell::new(image),
+            block_size: BLOCK_SIZE,
+            read_only: false,
+            bad_block: Cell::new(None),
+            write_budget: Cell::new(None),
+            requests: Cell::new(0),
+        }
+    }
+
+    fn image(&self) -> Vec<u8> {
+        self.data.borrow().clone()
+    }
+
+    /// Fail if the `blocks` starting at `index` include the bad block
+    fn check_request(&self, index: u32, blocks: usize) -> Result<(), FsError> {
+        self.requests.set(self.requests.get() + 1);
+        match self.bad_block.get() {
+            Some(bad) if (index..index + blocks as u32).contains(&bad) => Err(FsError::IoError),
+            _ => Ok(()),
+        }
+    }
+
+    fn store(&self, index: u32, buf: &[u8]) {
+        assert!(!self.read_only, "write to read-only device");
+        match self.write_budget.get() {
+            Some(0) => return,
+            Some(left) => self.write_budget.set(Some(left - 1)),
+            None => {}
+        }
+        let size = self.block_size;
+        let offset = index as usize * size;
+        self.data.borrow_mut()[offset..offset + size].copy_from_slice(&buf[..size]);
+    }
+}
+
+impl BlockDevice for MemBlockDevice {
+    fn total_blocks(&self) -> u32 {
+        (self.data.borrow().len() / self.block_size) as u32
+    }
+
+    fn read_block(&self, index: u32, buf: &mut [u8]) -> Result<(), FsError> {
+        self.read_blocks(index, &mut buf[..self.block_size])
+    }
+
+    fn write_block(&self, index: u32, buf: &[u8]) -> Result<(), FsError> {
+        self.write_blocks(index, &buf[..self.block_size])
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn read_blocks(&self, index: u32, buf: &mut [u8]) -> Result<(), FsError> {
+        self.check_request(index, buf.len() / self.block_size)?;
+        let offset = index as usize * self.block_size;
+        buf.copy_from_slice(&self.data.borrow()[offset..offset + buf.len()]);
+        Ok(())
+    }
+
+    fn write_blocks(&self, index: u32, buf: &[u8]) -> Result<(), FsError> {
+        self.check_request(index, buf.len() / self.block_size)?;
+        for (i, block) in buf.chunks_exact(self.block_size).enumerate() {
+            self.store(index + i as u32, block);
+        }
+        Ok(())
+    }
+}
+
+fn fresh() -> TinyFs<MemBlockDevice> {
+    TinyFs::mount(MemBlockDevice::new(TEST_BLOCKS)).unwrap()
+}
+
+fn remount(fs: &TinyFs<MemBlockDevice>) -> TinyFs<MemBlockDevice> {
+    TinyFs::mount(MemBlockDevice::from_image(fs.device.image())).unwrap()
+}
+
+/// Leaked extents are normal for the bump allocator, and an interrupted
+/// flush can leave the backup superblock behind; anything else is not.
+fn assert_consistent(fs: &mut TinyFs<MemBlockDevice>) {
+    let report = fs.check(false).unwrap();
+    for issue in &report.issues {
+        assert!(
+            matches!(
+                issue,
+                FsckIssue::OrphanedBlocks { .. } | FsckIssue::StaleBackupSuperblock
+            ),
+            "unexpected fsck issue: {}",
+            issue
+        );
+    }
+}
+
+/// Deterministic xorshift so failures reproduce without a rand dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+#[test]
+fn blank_device_formats_on_mount() {
+    let mut fs = fresh();
+    assert!(fs.list_directory("").unwrap().is_empty());
+    assert_eq!(fs.statfs().used_blocks, DATA_START_BLOCK);
+    assert_consistent(&mut fs);
+}
+
+#[test]
+fn write_read_roundtrip_survives_remount() {
+    let mut fs = fresh();
+    fs.write_file_contents("/hello", b"hello world").unwrap();
+    fs.write_file_contents("/empty", b"").unwrap();
+    let mut fs = remount(&fs);
+    assert_eq!(fs.read_file_contents("/hello").unwrap(), b"hello world");
+    assert_eq!(fs.read_file_contents("/empty").unwrap(), b"");
+    assert_consistent(&mut fs);
+}
+
+#[test]
+fn create_delete_overwrite_cycles() {
+    let mut fs = fresh();
+    for round in 0..20usize {
+        let data = vec![round as u8; round * 97];
+        fs.write_file_contents("/cycle", &data).unwrap();
+        assert_eq!(fs.read_file_contents("/cycle").unwrap(), data);
+        if round % 3 == 0 {
+            fs.remove_file("/cycle").unwrap();
+            assert_eq!(fs.read_file_contents("/cycle"), Err(FsError::NotFound));
+        }
+    }
+    assert_eq!(fs.remove_file("/missing"), Err(FsError::NotFound));
+    assert_consistent(&mut remount(&fs));
+}
+
+#[test]
+fn nested_directories() {
+    let mut fs = fresh();
+    fs.create_directory("/a").unwrap();
+    fs.create_directory("/a/b").unwrap();
+    fs.create_directory("/a/b/c").unwrap();
+    fs.write_file_contents("/a/b/c/leaf", b"deep").unwrap();
+    for i in 0..40 {
+        fs.create_file(&alloc::format!("/a/b/f{}", i)).unwrap();
+    }
+    let mut fs = remount(&fs);
+    assert_eq!(fs.read_file_contents("/a/b/c/leaf").unwrap(), b"deep");
+    assert_eq!(fs.list_directory("/a/b").unwrap().len(), 41);
+    assert_eq!(fs.create_directory("/a/b"), Err(FsError::AlreadyExists));
+    assert_eq!(fs.remove_directory("/a/b/c"), Err(FsError::DirectoryNotEmpty));
+    assert_eq!(fs.read_file_contents("/a/b"), Err(FsError::NotADirectory));
+    assert_eq!(fs.create_file("/a/b/c/leaf/x"), Err(FsError::NotADirectory));
+    fs.remove_file("/a/b/c/leaf").unwrap();
+    fs.remove_directory("/a/b/c").unwrap();
+    assert!(!fs.list_directory("/a/b").unwrap().contains(&String::from("c/")));
+    assert_consistent(&mut fs);
+}
+
+#[test]
+fn owners_persist_and_survive_overwrites() {
+    let mut fs = fresh();
+    fs.create_directory("/home").unwrap();
+    fs.create_file("/home/notes").unwrap();
+    assert_eq!(fs.entry_owner("/home/notes").unwrap(), 0);
+    fs.set_entry_owner("/home", 1000).unwrap();
+    fs.set_entry_owner("/home/notes", 1000).unwrap();
+    fs.write_file_contents("/home/notes", b"replaced").unwrap();
+    fs.write_file_at("/home/notes", 8, b" and grown").unwrap();
+    let mut fs = remount(&fs);
+    assert_eq!(fs.entry_owner("/home").unwrap(), 1000);
+    assert_eq!(fs.entry_owner("/home/notes").unwrap(), 1000);
+    assert_eq!(fs.entry_owner("/").unwrap(), 0);
+    assert_eq!(fs.entry_owner("/home/missing"), Err(FsError::NotFound));
+    assert_eq!(fs.set_entry_owner("/", 1000), Err(FsError::InvalidPath));
+    assert_consistent(&mut fs);
+}
+
+#[test]
+fn metadata_reports_kind_size_and_owner() {
+    let mut fs = fresh();
+    fs.create_directory("/docs").unwrap();
+    fs.write_file_contents("/docs/readme", b"twelve bytes")
+        .unwrap();
+    fs.set_entry_owner("/docs/readme", 1000).unwrap();
+    let file = fs.metadata("/docs/readme").unwrap();
+    assert!(!file.is_dir);
+    assert_eq!(file.size, 12);
+    assert_eq!(file.owner, 1000);
+    let dir = fs.metadata("/docs").unwrap();
+    assert!(dir.is_dir);
+    assert_eq!(dir.size, 0);
+    assert!(fs.metadata("/").unwrap().is_dir);
+    assert_eq!(fs.metadata("/docs/missing"), Err(FsError::NotFound));
+    assert_eq!(fs.metadata("/docs/readme/x"), Err(FsError::NotADirectory));
+}
+
+#[test]
+fn rename_moves_entries_within_and_across_directories() {
+    let mut fs = fresh();
+    fs.create_directory("/a").unwrap();
+    fs.create_directory("/b").unwrap();
+    fs.write_file_contents("/a/one", b"first").unwrap();
+    fs.write_file_contents("/b/two", b"second").unwrap();
+
+    fs.rename("/a/one", "/a/uno").unwrap();
+    assert_eq!(fs.read_file_contents("/a/uno").unwrap(), b"first");
+    assert_eq!(fs.read_file_contents("/a/one"), Err(FsError::NotFound));
+
+    // Across directories, replacing the file already there
+    fs.rename("/a/uno", "/b/two").unwrap();
+    assert_eq!(fs.read_file_contents("/b/two").unwrap(), b"first");
+    assert_eq!(fs.list_directory("/a").unwrap(), Vec::<String>::new());
+
+    // Directories move with their contents but never into themselves
+    fs.rename("/b", "/a/b").unwrap();
+    assert_eq!(fs.read_file_contents("/a/b/two").unwrap(), b"first");
+    assert_eq!(fs.rename("/a", "/a/b/a"), Err(FsError::InvalidPath));
+    fs.write_file_contents("/file", b"x").unwrap();
+    assert_eq!(fs.rename("/file", "/a"), Err(FsError::AlreadyExists));
+    assert_eq!(fs.rename("/missing", "/x"), Err(FsError::NotFound));
+
+    let mut fs = remount(&fs);
+    assert_eq!(fs.read_file_contents("/a/b/two").unwrap(), b"first");
+    assert_consistent(&mut fs);
+}
+
+#[test]
+fn root_directory_is_capped() {
+    let mut fs = fresh();
+    for i in 0..fs.max_root_entries() {
+        fs.create_file(&alloc::format!("/f{}", i)).unwrap();
+    }
+    assert_eq!(fs.create_file("/one-more"), Err(FsError::DirectoryFull));
+    fs.write_file_contents("/f0", b"overwrite still fits").unwrap();
+}
+
+#[test]
+fn rejects_invalid_names() {
+    let mut fs = fresh();
+    assert_eq!(fs.create_file("/bad\x01name"), Err(FsError::InvalidName));
+    assert_eq!(fs.create_directory("/.."), Err(FsError::InvalidName));
+    let long = alloc::format!("/{}", "n".repeat(NAME_LEN + 1));
+    assert_eq!(fs.write_file_contents(&long, b"x"), Err(FsError::NameTooLong));
+}
+
+#[test]
+fn partial_writes_match_model() {
+    let mut fs = fresh();
+    let mut rng = Rng(0x5eed);
+    let mut model = Vec::new();
+    fs.create_file("/f").unwrap();
+    fs.create_file("/g").unwrap();
+    for _ in 0..300 {
+        let len = 1 + rng.below(700);
+        let data = rng.bytes(len);
+        if rng.below(2) == 0 {
+            fs.append_file("/f", &data).unwrap();
+            model.extend_from_slice(&data);
+        } else {
+            let offset = rng.below(model.len() + 64);
+            fs.write_file_at("/f", offset, &data).unwrap();
+            if model.len() < offset + data.len() {
+                model.resize(offset + data.len(), 0);
+            }
+            model[offset..offset + data.len()].copy_from_slice(&data);
+        }
+        // Interleave another growing file so /f fragments into extents
+        fs.append_file("/g", &data[..1]).unwrap();
+    }
+    assert_eq!(fs.read_file_contents("/f").unwrap(), model);
+    assert_eq!(fs.file_length("/f").unwrap(), model.len());
+    let mut fs = remount(&fs);
+    assert_eq!(fs.read_file_contents("/f").unwrap(), model);
+    assert_consistent(&mut fs);
+}
+
+#[test]
+fn random_operations_match_model() {
+    for seed in 1..=8u64 {
+        let mut fs = fresh();
+        let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        let mut model: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+        fs.create_directory("/d").unwrap();
+        for _ in 0..120 {
+            let path = alloc::format!(
+                "{}/n{}",
+                if rng.below(2) == 0 { "" } else { "/d" },
+                rng.below(6)
+            );
+            match rng.below(4) {
+                0 => {
+                    let len = rng.below(1500);
+                    let data = rng.bytes(len);
+                    fs.write_file_contents(&path, &data).unwrap();
+                    model.insert(path, data);
+                }
+                1 => {
+                    let len = rng.below(300);
+                    let data = rng.bytes(len);
+                    match model.get_mut(&path) {
+                        Some(contents) => {
+                            fs.append_file(&path, &data).unwrap();
+                            contents.extend_from_slice(&data);
+                        }
+                        None => assert_eq!(fs.append_file(&path, &data), Err(FsError::NotFound)),
+                    }
+                }
+                2 => {
+                    let expected = if model.remove(&path).is_some() {
+                        Ok(())
+                    } else {
+                        Err(FsError::NotFound)
+                    };
+                    assert_eq!(fs.remove_file(&path), expected);
+                }
+                _ => {
+                    let expected = model.get(&path).cloned().ok_or(FsError::NotFound);
+                    assert_eq!(fs.read_file_contents(&path), expected);
+                }
+            }
+        }
+        let mut fs = remount(&fs);
+        for (path, contents) in &model {
+            assert_eq!(&fs.read_file_contents(path).unwrap(), contents, "seed {}", seed);
+        }
+        assert_consistent(&mut fs);
+    }
+}
+
+#[test]
+fn interrupted_replace_keeps_old_or_new_contents() {
+    let old = vec![b'o'; 1200];
+    let new = vec![b'n'; 2000];
+    let mut base = fresh();
+    base.create_directory("/etc").unwrap();
+    base.write_file_contents("/etc/conf", &old).unwrap();
+    let image = base.device.image();
+
+    let mut saw_new = false;
+    for budget in 0.. {
+        let mut fs = TinyFs::mount(MemBlockDevice::from_image(image.clone())).unwrap();
+        fs.device.write_budget.set(Some(budget));
+        fs.write_file_contents("/etc/conf", &new).unwrap();
+        let complete = fs.device.write_budget.get() != Some(0);
+
+        let mut after = remount(&fs);
+        let contents = after.read_file_contents("/etc/conf").unwrap();
+        assert!(contents == old || contents == new, "torn file at budget {}", budget);
+        saw_new |= contents == new;
+        assert_consistent(&mut after);
+        if complete {
+            break;
+        }
+    }
+    assert!(saw_new);
+}
+
+#[test]
+fn check_repairs_corrupted_entries() {
+    let mut fs = fresh();
+    fs.write_file_contents("/good", b"ok").unwrap();
+    fs.write_file_contents("/bad", b"bad").unwrap();
+    fs.root_entries[1].start_block = TEST_BLOCKS + 10;
+    fs.flush_root_directory().unwrap();
+    let report = fs.check(true).unwrap();
+    assert!(report.repaired);
+    assert!(
+        report
+            .issues
+            .iter()
+            .any(|issue| matches!(issue, FsckIssue::ExtentOutOfRange { .. }))
+    );
+    let mut fs = remount(&fs);
+    assert_eq!(fs.read_file_contents("/good").unwrap(), b"ok");
+    assert_eq!(fs.read_file_contents("/bad"), Err(FsError::NotFound));
+    assert_consistent(&mut fs);
+}
+
+#[test]
+fn canonicalize_paths() {
+    assert_eq!(canonicalize("/", ""), "/");
+    assert_eq!(canonicalize("/a/b", "../c//./d"), "/a/c/d");
+    assert_eq!(canonicalize("/a", "/x/.."), "/");
+    assert_eq!(canonicalize("/", "../.."), "/");
+}
+
+#[test]
+fn compact_defragments_and_reclaims_leaks() {
+    let mut fs = fresh();
+    fs.create_directory("/d").unwrap();
+    fs.create_directory("/d/e").unwrap();
+    let mut expected: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+    for name in ["/frag", "/d/frag", "/d/e/frag"] {
+        fs.create_file(name).unwrap();
+        expected.insert(String::from(name), Vec::new());
+    }
+    for round in 0..30u8 {
+        for name in ["/frag", "/d/frag", "/d/e/frag"] {
+            let chunk = vec![round; 300 + round as usize];
+            fs.append_file(name, &chunk).unwrap();
+            expected.get_mut(name).unwrap().extend_from_slice(&chunk);
+        }
+        // Leak the previous copy of a plain file on every round
+        let plain = vec![round; 1000];
+        fs.write_file_contents("/d/plain", &plain).unwrap();
+        expected.insert(String::from("/d/plain"), plain);
+    }
+    assert!(fs.root_entries.iter().any(|entry| entry.mapped));
+
+    let mut calls = 0;
+    let report = fs
+        .compact(&mut |done, total| {
+            calls += 1;
+            assert!(done <= total);
+        })
+        .unwrap();
+    assert_eq!(report.files_defragmented, 3);
+    assert!(report.blocks_reclaimed > 0);
+    assert!(calls > 0);
+
+    let mut fs = remount(&fs);
+    for (path, contents) in &expected {
+        assert_eq!(&fs.read_file_contents(path).unwrap(), contents, "{}", path);
+    }
+    let check = fs.check(false).unwrap();
+    assert!(check.issues.is_empty(), "{:?}", check.issues);
+    assert!(!fs.root_entries.iter().any(|entry| entry.mapped));
+}
+
+#[test]
+fn compact_refuses_inconsistent_filesystem() {
+    let mut fs = fresh();
+    fs.write_file_contents("/f", b"data").unwrap();
+    fs.superblock.file_count += 1;
+    assert_eq!(fs.compact(&mut |_, _| {}).map(|_| ()), Err(FsError::Inconsistent));
+}
+
+#[test]
+fn compact_moves_extent_tables_when_space_is_short() {
+    let mut fs = TinyFs::mount(MemBlockDevice::new(160)).unwrap();
+    fs.create_file("/a").unwrap();
+    fs.create_file("/b").unwrap();
+    let mut a = Vec::new();
+    for round in 0..10u8 {
+        let chunk = vec![round; 2 * BLOCK_SIZE];
+        fs.append_file("/a", &chunk).unwrap();
+        a.extend_from_slice(&chunk);
+        fs.append_file("/b", &[round]).unwrap();
+    }
+    // Leak space ahead of the file, then leave no room for a 20 block copy
+    fs.write_file_contents("/leak", &vec![1; 20 * BLOCK_SIZE]).unwrap();
+    fs.remove_file("/leak").unwrap();
+    let free = fs.statfs().free_blocks as usize;
+    let filler = vec![2; (free - 10) * BLOCK_SIZE];
+    fs.write_file_contents("/fill", &filler).unwrap();
+
+    let report = fs.compact(&mut |_, _| {}).unwrap();
+    assert!(report.files_skipped >= 1);
+    assert!(report.blocks_moved > 0);
+    let mut fs = remount(&fs);
+    assert_eq!(fs.read_file_contents("/a").unwrap(), a);
+    assert_consistent(&mut fs);
+}
+
+/// Build a ustar archive from `(name, typeflag, body)` entries.
+fn ustar(entries: &[(&str, u8, &[u8])]) -> Vec<u8> {
+    let mut archive = Vec::new();
+    for &(name, kind, body) in entries {
+        let mut header = [0u8; TAR_HEADER_SIZE];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(alloc::format!("{:011o}", body.len()).as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[148..156].fill(b' ');
+        let sum: usize = header.iter().map(|&byte| byte as usize).sum();
+        header[148..155].copy_from_slice(alloc::format!("{:06o}\0", sum).as_bytes());
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(body);
+        archive.resize(archive.len().next_multiple_of(TAR_HEADER_SIZE), 0);
+    }
+    archive.resize(archive.len() + 2 * TAR_HEADER_SIZE, 0);
+    archive
+}
+
+#[test]
+fn untar_recreates_tree() {
+    let mut fs = fresh();
+    let big = vec![b'z'; 1500];
+    let archive = ustar(&[
+        ("./bin/", b'5', b""),
+        ("./bin/hello", b'0', b"hello"),
+        ("./etc/motd", b'0', &big),
+        ("./etc/link", b'2', b""),
+        ("../escape", b'0', b"nope"),
+    ]);
+    fs.write_file_contents("/image.tar", &archive).unwrap();
+    let report = fs.untar("/image.tar", "/root").unwrap();
+    assert_eq!(report.files, 2);
+    assert_eq!(report.directories, 1);
+    assert_eq!(report.skipped, 2);
+    assert_eq!(fs.read_file_contents("/root/bin/hello").unwrap(), b"hello");
+    assert_eq!(fs.read_file_contents("/root/etc/motd").unwrap(), big);
+    assert_eq!(fs.read_file_contents("/escape"), Err(FsError::NotFound));
+    assert_consistent(&mut fs);
+}
+
+#[test]
+fn untar_rejects_corrupt_headers() {
+    let mut fs = fresh();
+    let mut archive = ustar(&[("file", b'0', b"data")]);
+    archive[0] = b'g';
+    fs.write_file_contents("/bad.tar", &archive).unwrap();
+    assert_eq!(fs.untar("/bad.tar", "/").map(|_| ()), Err(FsError::InvalidArchive));
+}
+
+#[test]
+fn mount_recovers_from_backup_superblock() {
+    let mut fs = fresh();
+    fs.create_directory("/d").unwrap();
+    fs.write_file_contents("/d/f", b"survives").unwrap();
+    fs.device.write_block(0, &[0xff; BLOCK_SIZE]).unwrap();
+
+    let mut fs = remount(&fs);
+    assert_eq!(fs.read_file_contents("/d/f").unwrap(), b"survives");
+    // The primary was rewritten from the backup during mount
+    let mut fs = remount(&fs);
+    assert_eq!(fs.read_file_contents("/d/f").unwrap(), b"survives");
+    assert_consistent(&mut fs);
+}
+
+#[test]
+fn allocator_never_hands_out_backup_superblock() {
+    let mut fs = TinyFs::mount(MemBlockDevice::new(64)).unwrap();
+    let free = fs.statfs().free_blocks as usize;
+    fs.write_file_contents("/fill", &vec![7; free * BLOCK_SIZE]).unwrap();
+    fs.create_file("/more").unwrap();
+    assert_eq!(fs.append_file("/more", b"x"), Err(FsError::NoSpace));
+    fs.device.write_block(0, &[0; BLOCK_SIZE]).unwrap();
+    let mut fs = remount(&fs);
+    assert_eq!(fs.read_file_contents("/fill").unwrap().len(), free * BLOCK_SIZE);
+    assert_consistent(&mut fs);
+}
+
+#[test]
+fn check_reports_stale_backup_superblock() {
+    let mut fs = fresh();
+    let backup = fs.backup_superblock_index();
+    fs.device.write_block(backup, &[0; BLOCK_SIZE]).unwrap();
+    let report = fs.check(true).unwrap();
+    assert_eq!(report.issues, vec![FsckIssue::StaleBackupSuperblock]);
+    assert!(fs.check(false).unwrap().issues.is_empty());
+}
+
+#[test]
+fn limits_cap_file_size_and_usage() {
+    let mut fs = fresh();
+    fs.write_file_contents("/old", &vec![1; 4000]).unwrap();
+    fs.superblock.limits = FsLimits {
+        max_file_size: 2000,
+        quota_blocks: 40,
+    };
+    fs.flush_superblock().unwrap();
+    let mut fs = remount(&fs);
+
+    assert_eq!(fs.write_file_contents("/big", &vec![0; 2001]), Err(FsError::FileTooLarge));
+    fs.create_file("/log").unwrap();
+    let mut written = 0;
+    let err = loop {
+        match fs.append_file("/log", b"y\n") {
+            Ok(n) => written += n,
+            Err(err) => break err,
+        }
+    };
+    assert_eq!(err, FsError::FileTooLarge);
+    assert_eq!(written, 2000);
+    // Rewriting inside a file larger than the new limit is still allowed
+    fs.write_file_at("/old", 0, b"patched").unwrap();
+
+    let mut n = 0;
+    let err = loop {
+        match fs.write_file_contents(&alloc::format!("/f{}", n % 4), &vec![2; 1500]) {
+            Ok(()) => n += 1,
+            Err(err) => break err,
+        }
+    };
+    assert_eq!(err, FsError::QuotaExceeded);
+    assert!(n > 0);
+    assert!(fs.statfs().used_blocks - DATA_START_BLOCK <= 40);
+    // Metadata stays writable so space can be released
+    fs.remove_file("/f0").unwrap();
+    fs.create_directory("/still-works").unwrap();
+    assert_consistent(&mut fs);
+}
+
+#[test]
+fn read_handle_reads_ranges_and_follows_updates() {
+    let mut fs = fresh();
+    let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+    fs.write_file_contents("/f", &data).unwrap();
+    let mut handle = fs.open_file("/f").unwrap();
+
+    let mut out = Vec::new();
+    let mut chunk = [0u8; 700];
+    loop {
+        let n = fs.read_handle(&mut handle, out.len(), &mut chunk).unwrap();
+        if n == 0 {
+            break;
+        }
+        out.extend_from_slice(&chunk[..n]);
+    }
+    assert_eq!(out, data);
+
+    let mut small = [0u8; 10];
+    assert_eq!(fs.read_handle(&mut handle, 510, &mut small).unwrap(), 10);
+    assert_eq!(&small, &data[510..520]);
+
+    // Growth moves metadata; the handle picks up the new length
+    fs.append_file("/f", b"tail").unwrap();
+    fs.write_file_contents("/other", &[0; 600]).unwrap();
+    fs.append_file("/f", &[9; 600]).unwrap();
+    let mut tail = [0u8; 8];
+    assert_eq!(fs.read_handle(&mut handle, 5000, &mut tail).unwrap(), 8);
+    assert_eq!(&tail, b"tail\x09\x09\x09\x09");
+
+    fs.remove_file("/f").unwrap();
+    assert_eq!(fs.read_handle(&mut handle, 0, &mut tail), Err(FsError::NotFound));
+    fs.create_directory("/d").unwrap();
+    assert_eq!(fs.open_file("/d").err(), Some(FsError::IsDirectory));
+}
+
+#[test]
+fn file_data_moves_in_multi_block_requests() {
+    let mut fs = fresh();
+    let data: Vec<u8> = (0..40 * BLOCK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+
+    let before = fs.device.requests.get();
+    fs.write_file_contents("/big", &data).unwrap();
+    let write_requests = fs.device.requests.get() - before;
+    // One request for the whole blocks and one for the padded tail, plus
+    // the metadata; nowhere near one per block
+    assert!(write_requests < 10, "{write_requests} requests to write 41 blocks");
+
+    let before = fs.device.requests.get();
+    assert_eq!(fs.read_file_contents("/big").unwrap(), data);
+    let read_requests = fs.device.requests.get() - before;
+    assert!(read_requests < 10, "{read_requests} requests to read 41 blocks");
+
+    assert_eq!(remount(&fs).read_file_contents("/big").unwrap(), data);
+}
+
+#[test]
+fn secondary_mounts_need_an_existing_filesystem() {
+    assert!(matches!(
+        TinyFs::try_mount(MemBlockDevice::new(TEST_BLOCKS)),
+        Err(FsError::NoFilesystem)
+    ));
+
+    let mut fs = TinyFs::create(MemBlockDevice::new(TEST_BLOCKS)).unwrap();
+    fs.write_file_contents("/data", b"kept").unwrap();
+    let image = fs.device.image();
+    let mut fs = TinyFs::try_mount(MemBlockDevice::from_image(image)).unwrap();
+    assert_eq!(fs.read_file_contents("/data").unwrap(), b"kept");
+}
+
+#[test]
+fn filesystems_use_the_device_block_size() {
+    let mut fs = TinyFs::mount(MemBlockDevice::with_block_size(256, 4096)).unwrap();
+    assert_eq!(fs.statfs().block_size, 4096);
+    assert_eq!(fs.max_root_entries(), 4096 / DIR_ENTRY_SIZE);
+
+    let data: Vec<u8> = (0..3 * 4096 + 7).map(|i| (i % 251) as u8).collect();
+    fs.create_directory("/dir").unwrap();
+    fs.write_file_contents("/dir/file", &data).unwrap();
+    fs.write_file_at("/dir/file", 4090, b"across").unwrap();
+    let mut expected = data;
+    expected[4090..4096].copy_from_slice(b"across");
+
+    let image = fs.device.image();
+    let device = MemBlockDevice {
+        block_size: 4096,
+        ..MemBlockDevice::from_image(image.clone())
+    };
+    let mut fs = TinyFs::try_mount(device).unwrap();
+    assert_eq!(fs.read_file_contents("/dir/file").unwrap(), expected);
+    assert_consistent(&mut fs);
+
+    // Block numbers mean something else with 512-byte blocks
+    assert!(matches!(
+        TinyFs::try_mount(MemBlockDevice::from_image(image)),
+        Err(FsError::NoFilesystem)
+    ));
+}
+
+#[test]
+fn writes_stamp_the_modification_time() {
+    let mtime = |fs: &mut TinyFs<MemBlockDevice>, name: &str| {
+        let batch = fs.read_dir_batch("/dir", 0).unwrap();
+        batch.entries.iter().find(|e| e.name == name).unwrap().mtime
+    };
+    crate::rtc::set_unix_time(1_000);
+    let mut fs = fresh();
+    fs.create_directory("/dir").unwrap();
+    fs.create_file("/dir/a").unwrap();
+    fs.write_file_contents("/dir/b", b"first").unwrap();
+    assert_eq!(mtime(&mut fs, "a"), 1_000);
+
+    crate::rtc::set_unix_time(2_000);
+    fs.write_file_at("/dir/b", 0, b"F").unwrap();
+    fs.append_file("/dir/a", b"more").unwrap();
+    assert_eq!(mtime(&mut fs, "b"), 2_000);
+    crate::rtc::set_unix_time(3_000);
+    fs.write_file_contents("/dir/b", b"second").unwrap();
+
+    let mut fs = remount(&fs);
+    assert_eq!(mtime(&mut fs, "a"), 2_000);
+    assert_eq!(mtime(&mut fs, "b"), 3_000);
+    assert_eq!(fs.read_file_contents("/dir/b").unwrap(), b"second");
+}
+
+#[test]
+fn device_errors_reach_the_caller() {
+    let mut fs = fresh();
+    fs.write_file_contents("/bad", &[7u8; 1000]).unwrap();
+    fs.write_file_contents("/good", b"fine").unwrap();
+    let bad = fs.lookup_file("/bad").unwrap().start_block;
+    fs.device.bad_block.set(Some(bad + 1));
+
+    assert_eq!(fs.read_file_contents("/bad"), Err(FsError::IoError));
+    let mut handle = fs.open_file("/bad").unwrap();
+    let mut buf = [0u8; 16];
+    assert_eq!(fs.read_handle(&mut handle, 0, &mut buf), Ok(16));
+    assert_eq!(
+        fs.read_handle(&mut handle, BLOCK_SIZE, &mut buf),
+        Err(FsError::IoError)
+    );
+    assert_eq!(
+        fs.write_file_range("/bad", Some(BLOCK_SIZE + 8), b"x"),
+        Err(FsError::IoError)
+    );
+
+    // The rest of the filesystem is unaffected
+    assert_eq!(fs.read_file_contents("/good").unwrap(), b"fine");
+    fs.write_file_contents("/new", b"data").unwrap();
+    fs.device.bad_block.set(None);
+    assert_eq!(fs.read_file_contents("/bad").unwrap(), vec![7u8; 1000]);
+    assert_consistent(&mut fs);
+
+    // A bad superblock fails the mount instead of reformatting the disk
+    let device = MemBlockDevice::from_image(fs.device.image());
+    device.bad_block.set(Some(0));
+    assert!(matches!(TinyFs::mount(device), Err(FsError::IoError)));
+}
+
+#[test]
+fn read_only_media_refuses_changes() {
+    let mut fs = fresh();
+    fs.write_file_contents("/kept", b"data").unwrap();
+    fs.create_directory("/dir").unwrap();
+    let image = fs.device.image();
+
+    let device = MemBlockDevice {
+        read_only: true,
+        ..MemBlockDevice::from_image(image.clone())
+    };
+    let mut fs = TinyFs::mount(device).unwrap();
+    assert_eq!(fs.read_file_contents("/kept").unwrap(), b"data");
+    assert_eq!(
+        fs.write_file_contents("/kept", b"new"),
+        Err(FsError::ReadOnly)
+    );
+    assert_eq!(fs.append_file("/kept", b"more"), Err(FsError::ReadOnly));
+    assert_eq!(fs.create_file("/new"), Err(FsError::ReadOnly));
+    assert_eq!(fs.create_directory("/dir/sub"), Err(FsError::ReadOnly));
+    assert_eq!(fs.remove_file("/kept"), Err(FsError::ReadOnly));
+    assert_eq!(fs.remove_directory("/dir"), Err(FsError::ReadOnly));
+    assert_eq!(fs.set_entry_owner("/kept", 1000), Err(FsError::ReadOnly));
+    assert!(fs.check(false).unwrap().issues.is_empty());
+    assert!(matches!(fs.check(true), Err(FsError::ReadOnly)));
+    assert!(matches!(fs.compact(&mut |_, _| {}), Err(FsError::ReadOnly)));
+    assert_eq!(fs.device.image(), image);
+
+    // Blank read-only media is left alone instead of formatted
+    let blank = MemBlockDevice {
+        read_only: true,
+        ..MemBlockDevice::new(64)
+    };
+    let mut fs = TinyFs::mount(blank).unwrap();
+    assert_eq!(fs.list_directory("").unwrap(), Vec::<String>::new());
+    assert_eq!(fs.create_file("/new"), Err(FsError::ReadOnly));
+}
+
+#[test]
+fn paths_resolve_relative_to_mount_points() {
+    assert_eq!(mount_relative("/", "/mnt/a"), Some("/mnt/a"));
+    assert_eq!(mount_relative("/mnt", "/mnt/a"), Some("/a"));
+    assert_eq!(mount_relative("/mnt", "/mnt"), Some(""));
+    assert_eq!(mount_relative("/mnt", "/mntx/a"), None);
+    assert_eq!(mount_relative("/mnt", "/"), None);
+}
@@ -3,9 +3,17 @@ use linked_list_allocator::LockedHeap;
 #[global_allocator]
 static mut KERNEL_HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
 
-// Increased heap size to 2MB for multitasking support
-// Each process needs 128KB memory snapshot, plus overhead for process structs, pipes, etc.
-static mut KERNEL_HEAP: [u8; 0x200000] = [0; 0x200000];
+// Heap for multitasking support. Each process keeps a window-sized memory
+// snapshot and a kernel stack; room for twice that at the default process
+// cap (proc::DEFAULT_MAX_PROCESSES) also covers the two window copies a
+// spawn makes and the filesystem, pipes and fd tables. That is 8.5MB with
+// the default 256KB user window. The heap lives in the 16MB RAM region of
+// memory.x, so a window much past 384KB no longer links.
+pub const HEAP_SIZE: usize = 2
+    * crate::proc::DEFAULT_MAX_PROCESSES
+    * (crate::process::USER_WINDOW_SIZE + crate::process::KERNEL_STACK_SIZE);
+
+static mut KERNEL_HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
 
 /// Initialize the heap allocator.
 #[allow(static_mut_refs)]
@@ -14,3 +22,10 @@ pub unsafe fn init_kernel_heap() {
     let heap_size = unsafe { KERNEL_HEAP.len() };
     unsafe { KERNEL_HEAP_ALLOCATOR.lock().init(heap_start, heap_size) };
 }
+
+/// Bytes not currently allocated. Fragmentation can still make a request
+/// this size fail.
+#[allow(static_mut_refs)]
+pub fn free_bytes() -> usize {
+    unsafe { KERNEL_HEAP_ALLOCATOR.lock().free() }
+}
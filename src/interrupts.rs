@@ -3,7 +3,11 @@ use core::{
     sync::atomic::{AtomicBool, Ordering},
 };
 
-use riscv::register::{sie, sstatus};
+use riscv::register::{
+    sie,
+    sstatus::{self, SPP},
+    time,
+};
 
 const PLIC_BASE: usize = 0x0c00_0000;
 const PLIC_PRIORITY_BASE: usize = PLIC_BASE;
@@ -13,18 +17,47 @@ const PLIC_SCLAIM: usize = PLIC_BASE + 0x201004; // Supervisor claim/complete ha
 
 const UART_IRQ: u32 = 10;
 
+/// Timer period in timebase cycles: 10ms at QEMU virt's 10 MHz
+const TIMER_INTERVAL: u64 = 100_000;
+/// Timer interrupts per second
+pub const TICKS_PER_SECOND: u64 = 100;
+
 static EVENT_READY: AtomicBool = AtomicBool::new(false);
 
 pub fn init() {
     unsafe {
-        write32(PLIC_PRIORITY_BASE + (UART_IRQ as usize) * 4, 1);
-        let enabled = read32(PLIC_SENABLE);
-        write32(PLIC_SENABLE, enabled | (1 << UART_IRQ));
+        // The UARTs and every virtio device that has a driver
+        let virtio = crate::virtio::probe();
+        let irqs = virtio
+            .iter()
+            .filter(|device| {
+                matches!(
+                    device.device_id,
+                    crate::virtio::DEVICE_BLOCK
+                        | crate::virtio::DEVICE_NET
+                        | crate::virtio::DEVICE_CONSOLE
+                )
+            })
+            .map(|device| device.irq);
+        let uarts = core::iter::once(UART_IRQ).chain(crate::uart::secondary_irq());
+        for irq in uarts.chain(irqs) {
+            write32(PLIC_PRIORITY_BASE + (irq as usize) * 4, 1);
+            // PCI INTx lines are sources 32-35, in the second enable word
+            let enable = PLIC_SENABLE + (irq as usize / 32) * 4;
+            write32(enable, read32(enable) | 1 << (irq % 32));
+        }
         write32(PLIC_STHRESHOLD, 0);
 
         sie::set_sext();
+        sie::set_stimer();
         sstatus::set_sie();
     }
+    arm_timer();
+}
+
+/// Request the next timer interrupt; this also clears the pending one
+fn arm_timer() {
+    let _ = sbi::timer::set_timer(time::read64() + TIMER_INTERVAL);
 }
 
 pub fn wait_for_event() {
@@ -52,13 +85,46 @@ fn supervisor_external() {
     let claim = unsafe { read32(PLIC_SCLAIM) };
     if claim == UART_IRQ {
         crate::uart::handle_interrupt();
+    } else {
+        if Some(claim) == crate::uart::secondary_irq() {
+            crate::console::handle_uart1_interrupt();
+        }
+        let mut net = false;
+        crate::virtio::for_each_on_irq(claim, |device| match device.device_id {
+            crate::virtio::DEVICE_BLOCK => crate::virtio::block::handle_interrupt(device.transport),
+            crate::virtio::DEVICE_NET => {
+                crate::virtio::net::handle_interrupt(device.transport);
+                net = true;
+            }
+            crate::virtio::DEVICE_CONSOLE => {
+                crate::console::handle_virtio_interrupt(device.transport)
+            }
+            // Polled devices can still share a PCI line with these
+            _ => crate::virtio::ack_interrupt(device.transport),
+        });
+        if net {
+            crate::net::poll();
+        }
     }
     unsafe {
         write32(PLIC_SCLAIM, claim);
     }
+    crate::random::add_interrupt_timing();
     signal_event();
 }
 
+/// Timer tick; only ticks that interrupt user code are charged as CPU time
+#[riscv_rt::core_interrupt(riscv::interrupt::Interrupt::SupervisorTimer)]
+fn supervisor_timer() {
+    arm_timer();
+    crate::uart::poll_sbi();
+    crate::scheduler::Scheduler::account_tick();
+    if sstatus::read().spp() == SPP::User {
+        crate::rlimit::charge_cpu_tick();
+    }
+    crate::net::poll();
+}
+
 unsafe fn read32(addr: usize) -> u32 {
     unsafe { ptr::read_volatile(addr as *const u32) }
 }
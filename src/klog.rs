@@ -0,0 +1,186 @@
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+use crate::rtc::TIMEBASE_HZ;
+
+/// Bytes of messages kept for dmesg; older lines are overwritten
+pub const BUFFER_SIZE: usize = 16 * 1024;
+
+/// How important a message is, most important first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub fn from_index(index: usize) -> Option<Level> {
+        match index {
+            0 => Some(Level::Error),
+            1 => Some(Level::Warn),
+            2 => Some(Level::Info),
+            3 => Some(Level::Debug),
+            _ => None,
+        }
+    }
+}
+
+/// Part of the kernel a message comes from; each has its own level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    Kernel,
+    Syscall,
+    Sched,
+    Fs,
+    Virtio,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 5] = [
+        Subsystem::Kernel,
+        Subsystem::Syscall,
+        Subsystem::Sched,
+        Subsystem::Fs,
+        Subsystem::Virtio,
+    ];
+}
+
+/// Messages less important than this are compiled out; lower it to drop
+/// the cost of the checks on hot paths like syscall entry
+pub const MAX_LEVEL: Level = Level::Debug;
+
+/// Least important level recorded per subsystem, indexed by
+/// `Subsystem as usize`; `log set` changes them at runtime
+static LEVELS: [AtomicUsize; Subsystem::ALL.len()] =
+    [const { AtomicUsize::new(Level::Info as usize) }; Subsystem::ALL.len()];
+
+/// Messages this important or more are also printed on the log console
+static CONSOLE_LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+
+/// The last `BUFFER_SIZE` bytes ever logged
+struct Ring {
+    bytes: [u8; BUFFER_SIZE],
+    /// Bytes logged since boot; the next one goes at `written % BUFFER_SIZE`
+    written: usize,
+    /// Position of the last `clear`; nothing before it is read back
+    cleared: usize,
+}
+
+static RING: Mutex<Ring> = Mutex::new(Ring {
+    bytes: [0; BUFFER_SIZE],
+    written: 0,
+    cleared: 0,
+});
+
+impl Ring {
+    /// Position of the oldest byte still held
+    fn start(&self) -> usize {
+        self.written.saturating_sub(BUFFER_SIZE).max(self.cleared)
+    }
+
+    fn byte_at(&self, position: usize) -> u8 {
+        self.bytes[position % BUFFER_SIZE]
+    }
+
+    /// Copy bytes from position `*cursor` into `out` and advance it. A
+    /// cursor that has been overwritten skips to the next whole line.
+    fn read(&self, cursor: &mut usize, out: &mut [u8]) -> usize {
+        let start = self.start();
+        if *cursor < start && start == self.cleared {
+            *cursor = start;
+        } else if *cursor < start {
+            *cursor = (start..self.written)
+                .find(|&position| self.byte_at(position) == b'\n')
+                .map_or(self.written, |newline| newline + 1);
+        }
+        let count = out.len().min(self.written.saturating_sub(*cursor));
+        for (i, byte) in out[..count].iter_mut().enumerate() {
+            *byte = self.byte_at(*cursor + i);
+        }
+        *cursor += count;
+        count
+    }
+}
+
+impl Write for Ring {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.bytes[self.written % BUFFER_SIZE] = byte;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+pub fn console_level() -> Level {
+    Level::from_index(CONSOLE_LEVEL.load(Ordering::Relaxed)).unwrap_or(Level::Info)
+}
+
+/// Print messages at `level` and above on the console; the rest only
+/// reach dmesg
+pub fn set_console_level(level: Level) {
+    CONSOLE_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+pub fn level(subsystem: Subsystem) -> Level {
+    Level::from_index(LEVELS[subsystem as usize].load(Ordering::Relaxed)).unwrap_or(Level::Info)
+}
+
+/// Record messages from `subsystem` at `level` and above
+pub fn set_level(subsystem: Subsystem, level: Level) {
+    LEVELS[subsystem as usize].store(level as usize, Ordering::Relaxed);
+}
+
+/// Whether `klog!` keeps a message; checked before formatting it
+pub fn enabled(subsystem: Subsystem, level: Level) -> bool {
+    level <= MAX_LEVEL && level <= self::level(subsystem)
+}
+
+/// Record a message with its boot timestamp; use `klog!` instead
+pub fn log(level: Level, args: fmt::Arguments) {
+    let micros = riscv::register::time::read64() / (TIMEBASE_HZ / 1_000_000);
+    // A message from an interrupt that lands while the ring is being
+    // written or read is only printed
+    if let Some(mut ring) = RING.try_lock() {
+        let _ = writeln!(
+            ring,
+            "[{:>5}.{:06}] {}",
+            micros / 1_000_000,
+            micros % 1_000_000,
+            args
+        );
+    }
+    if level <= console_level() {
+        crate::utils::print_args(args);
+        crate::utils::print("\n");
+    }
+}
+
+/// Copy logged text from position `*cursor` (0 for the oldest) into
+/// `out`; returns the bytes copied, 0 once caught up
+pub fn read(cursor: &mut usize, out: &mut [u8]) -> usize {
+    RING.lock().read(cursor, out)
+}
+
+/// Forget everything logged so far
+pub fn clear() {
+    let mut ring = RING.lock();
+    ring.cleared = ring.written;
+}
+
+/// `klog!(Sched, Debug, "format", args...)`: log at a `klog::Level` for a
+/// `klog::Subsystem`; without a subsystem the message is `Kernel`'s
+#[macro_export]
+macro_rules! klog {
+    ($subsystem:ident, $level:ident, $($arg:tt)+) => {
+        if $crate::klog::enabled($crate::klog::Subsystem::$subsystem, $crate::klog::Level::$level) {
+            $crate::klog::log($crate::klog::Level::$level, format_args!($($arg)+))
+        }
+    };
+    ($level:ident, $($arg:tt)+) => {
+        $crate::klog!(Kernel, $level, $($arg)+)
+    };
+}
@@ -16,19 +16,36 @@ use riscv_rt::entry;
 mod panic_handler;
 mod utils;
 
+mod bench;
+mod console;
 mod elf;
 mod embedded;
+mod eventfd;
+mod fault;
 mod fd;
+mod fdt;
 mod fs;
 mod heap;
 mod interrupts;
+mod klog;
+mod net;
+mod ninep;
+mod oom;
+mod pci;
 mod proc;
 mod process;
+mod pty;
+mod random;
+mod rlimit;
+mod rtc;
 mod scheduler;
+mod signal;
+mod socket;
 mod syscall;
 mod uart;
 mod user;
 mod virtio;
+mod vt;
 
 core::arch::global_asm!(include_str!("kernel_entry.S"));
 
@@ -95,44 +112,48 @@ fn process_command(command: &str, cwd: &mut String) {
             let mut parts = command.split_ascii_whitespace();
             parts.next(); // Skip "ls"
             let target_path = if let Some(arg) = parts.next() {
-                normalize_path(cwd.as_str(), arg)
+                crate::fs::canonicalize(cwd.as_str(), arg)
             } else {
                 cwd.clone()
             };
-            let path_opt = if target_path.is_empty() {
-                None
-            } else {
-                Some(target_path.as_str())
-            };
-
             if let Err(err) = crate::fs::init() {
                 println!("fs error: {}", err);
                 return;
             }
 
-            match crate::fs::list_files(path_opt) {
-                Ok(entries) => {
-                    if entries.is_empty() {
-                        println!("(empty)");
+            let mut cookie = 0;
+            let mut listed = 0;
+            loop {
+                let batch = match crate::fs::read_dir(&target_path, cookie) {
+                    Ok(batch) => batch,
+                    Err(err) => {
+                        println!("fs error: {}", err);
+                        return;
+                    }
+                };
+                for entry in &batch.entries {
+                    if entry.is_dir {
+                        println!("{}/", entry.name);
                     } else {
-                        for name in entries {
-                            println!("{}", name);
-                        }
+                        println!("{}", entry.name);
                     }
                 }
-                Err(err) => println!("fs error: {}", err),
+                listed += batch.entries.len();
+                match batch.next_cookie {
+                    Some(next) => cookie = next,
+                    None => break,
+                }
+            }
+            if listed == 0 {
+                println!("(empty)");
             }
         }
         command if command.starts_with("cd") => {
             let mut parts = command.split_ascii_whitespace();
             parts.next(); // Skip "cd"
             let path_arg = parts.next().unwrap_or("/");
-            let target = normalize_path(cwd.as_str(), path_arg);
-            let fs_path = if target.is_empty() {
-                ""
-            } else {
-                target.as_str()
-            };
+            let target = crate::fs::canonicalize(cwd.as_str(), path_arg);
+            let fs_path = target.as_str();
 
             if let Err(err) = crate::fs::init() {
                 println!("fs error: {}", err);
@@ -209,12 +230,8 @@ fn handle_fs_command(command: &str, cwd: &mut String) {
     match subcommand {
         "mkdir" => {
             if let Some(path) = parts.next() {
-                let target = normalize_path(cwd.as_str(), path);
-                let fs_path = if target.is_empty() {
-                    ""
-                } else {
-                    target.as_str()
-                };
+                let target = crate::fs::canonicalize(cwd.as_str(), path);
+                let fs_path = target.as_str();
                 match crate::fs::mkdir(fs_path) {
                     Ok(()) => println!("created directory {}", path),
                     Err(err) => println!("fs error: {}", err),
@@ -225,12 +242,8 @@ fn handle_fs_command(command: &str, cwd: &mut String) {
         }
         "rm" => {
             if let Some(path) = parts.next() {
-                let target = normalize_path(cwd.as_str(), path);
-                let fs_path = if target.is_empty() {
-                    ""
-                } else {
-                    target.as_str()
-                };
+                let target = crate::fs::canonicalize(cwd.as_str(), path);
+                let fs_path = target.as_str();
                 match crate::fs::remove_file(fs_path) {
                     Ok(()) => println!("created directory {}", path),
                     Err(err) => println!("fs error: {}", err),
@@ -241,12 +254,8 @@ fn handle_fs_command(command: &str, cwd: &mut String) {
         }
         "cat" => {
             if let Some(path) = parts.next() {
-                let target = normalize_path(cwd.as_str(), path);
-                let fs_path = if target.is_empty() {
-                    ""
-                } else {
-                    target.as_str()
-                };
+                let target = crate::fs::canonicalize(cwd.as_str(), path);
+                let fs_path = target.as_str();
                 match crate::fs::read_file(fs_path) {
                     Ok(contents) => match String::from_utf8(contents) {
                         Ok(text) => println!("{}", text),
@@ -274,24 +283,55 @@ fn handle_fs_command(command: &str, cwd: &mut String) {
                 println!("usage: fs write <path> <text>");
                 return;
             };
-            let target = normalize_path(cwd.as_str(), path);
-            let fs_path = if target.is_empty() {
-                ""
-            } else {
-                target.as_str()
-            };
+            let target = crate::fs::canonicalize(cwd.as_str(), path);
+            let fs_path = target.as_str();
             match crate::fs::write_file(fs_path, data.as_bytes()) {
                 Ok(()) => println!("wrote {} bytes", data.len()),
                 Err(err) => println!("fs error: {}", err),
             }
         }
-        "format" => match crate::fs::format() {
-            Ok(()) => {
-                *cwd = String::new();
-                println!("filesystem formatted");
+        "limits" => {
+            let (Some(size), Some(quota)) = (parts.next(), parts.next()) else {
+                match crate::fs::limits() {
+                    Ok(limits) => println!(
+                        "max file size: {} bytes, quota: {} blocks (0 = unlimited)",
+                        limits.max_file_size, limits.quota_blocks
+                    ),
+                    Err(err) => println!("fs error: {}", err),
+                }
+                return;
+            };
+            let (Ok(max_file_size), Ok(quota_blocks)) = (size.parse(), quota.parse()) else {
+                println!("usage: fs limits [<max-file-bytes> <quota-blocks>]");
+                return;
+            };
+            let limits = crate::fs::FsLimits {
+                max_file_size,
+                quota_blocks,
+            };
+            if let Err(err) = crate::fs::set_limits(limits) {
+                println!("fs error: {}", err);
             }
-            Err(err) => println!("fs error: {}", err),
-        },
+        }
+        "format" => {
+            let result = match parts.next() {
+                Some(arg) => match arg.parse::<usize>() {
+                    Ok(max_name_len) => crate::fs::format_with_name_limit(max_name_len),
+                    Err(_) => {
+                        println!("usage: fs format [max-name-len]");
+                        return;
+                    }
+                },
+                None => crate::fs::format(),
+            };
+            match result {
+                Ok(()) => {
+                    *cwd = String::from("/");
+                    println!("filesystem formatted");
+                }
+                Err(err) => println!("fs error: {}", err),
+            }
+        }
         _ => {
             print_fs_usage();
         }
@@ -304,7 +344,8 @@ fn print_fs_usage() {
     println!("  fs write <path> <text>");
     println!("  fs rm <path>");
     println!("  fs mkdir <path>");
-    println!("  fs format");
+    println!("  fs limits [<max-file-bytes> <quota-blocks>]");
+    println!("  fs format [max-name-len]");
 }
 
 fn handle_run_command(command: &str, cwd: &str) {
@@ -330,7 +371,7 @@ fn handle_run_command(command: &str, cwd: &str) {
         return;
     }
 
-    let target = normalize_path(cwd, path_arg);
+    let target = crate::fs::canonicalize(cwd, path_arg);
     let path = target.as_str();
 
     match crate::process::load(path) {
@@ -342,7 +383,7 @@ fn handle_run_command(command: &str, cwd: &str) {
             // This ensures that paths like "test.txt" work correctly
             let normalized_args: Vec<String> = extra_args
                 .iter()
-                .map(|&arg| normalize_path(cwd, arg))
+                .map(|&arg| crate::fs::canonicalize(cwd, arg))
                 .collect();
 
             let mut args: Vec<&str> = Vec::new();
@@ -354,8 +395,9 @@ fn handle_run_command(command: &str, cwd: &str) {
             unsafe { crate::process::enter_user(&program, &args) };
         }
         Err(LoadError::Fs(err)) => println!("fs error: {}", err),
-        Err(LoadError::Elf(err)) => println!("elf error: {:?}", err),
+        Err(LoadError::Elf(err)) => println!("elf error: {}", err),
         Err(LoadError::OutOfMemory) => println!("loader error: out of memory"),
+        Err(LoadError::BadInterpreter) => println!("loader error: bad #! line"),
     }
 }
 
@@ -372,18 +414,22 @@ fn handle_output_redirect(command: &str, cwd: &str) {
     // Check for append mode (>>)
     let (file_path, mode) = if file_part.starts_with('>') {
         let file = file_part[1..].trim();
-        (normalize_path(cwd, file), crate::fd::FileMode {
+        (crate::fs::canonicalize(cwd, file), crate::fd::FileMode {
             read: false,
             write: true,
             create: true,
             append: true,
+            truncate: false,
+            atomic: false,
         })
     } else {
-        (normalize_path(cwd, file_part), crate::fd::FileMode {
+        (crate::fs::canonicalize(cwd, file_part), crate::fd::FileMode {
             read: false,
             write: true,
             create: true,
             append: false,
+            truncate: true,
+            atomic: false,
         })
     };
 
@@ -396,7 +442,7 @@ fn handle_output_redirect(command: &str, cwd: &str) {
         }
     };
 
-    let fd = match crate::fd::FD_TABLE.lock().alloc(crate::fd::FileDescriptor::File(file_fd)) {
+    let fd = match crate::fd::FD_TABLE.lock().alloc(crate::fd::FileDescriptor::file(file_fd)) {
         Ok(fd) => fd,
         Err(err) => {
             println!("Failed to allocate fd: {:?}", err);
@@ -433,7 +479,7 @@ fn handle_input_redirect(command: &str, cwd: &str) {
 
     let cmd_part = command[..redir_pos].trim();
     let file_part = command[redir_pos + 1..].trim();
-    let file_path = normalize_path(cwd, file_part);
+    let file_path = crate::fs::canonicalize(cwd, file_part);
 
     // Open input file
     let mode = crate::fd::FileMode {
@@ -441,6 +487,8 @@ fn handle_input_redirect(command: &str, cwd: &str) {
         write: false,
         create: false,
         append: false,
+        truncate: false,
+        atomic: false,
     };
 
     let file_fd = match crate::fd::FileFd::open(file_path.clone(), mode) {
@@ -451,7 +499,7 @@ fn handle_input_redirect(command: &str, cwd: &str) {
         }
     };
 
-    let fd = match crate::fd::FD_TABLE.lock().alloc(crate::fd::FileDescriptor::File(file_fd)) {
+    let fd = match crate::fd::FD_TABLE.lock().alloc(crate::fd::FileDescriptor::file(file_fd)) {
         Ok(fd) => fd,
         Err(err) => {
             println!("Failed to allocate fd: {:?}", err);
@@ -498,45 +546,13 @@ fn execute_simple_command(command: &str, cwd: &str) {
 }
 
 fn print_prompt(cwd: &str) {
-    if cwd.is_empty() {
+    if cwd.is_empty() || cwd == "/" {
         print!("/> ");
     } else {
         print!("{}/> ", cwd);
     }
 }
 
-fn normalize_path(cwd: &str, input: &str) -> String {
-    if input.is_empty() {
-        return String::from(cwd);
-    }
-
-    let mut segments: Vec<String> = if input.starts_with('/') {
-        Vec::new()
-    } else {
-        cwd.split('/')
-            .filter(|segment| !segment.is_empty())
-            .map(String::from)
-            .collect()
-    };
-
-    for part in input.split('/') {
-        if part.is_empty() || part == "." {
-            continue;
-        }
-        if part == ".." {
-            segments.pop();
-            continue;
-        }
-        segments.push(String::from(part));
-    }
-
-    if segments.is_empty() {
-        String::new()
-    } else {
-        alloc::format!("/{}", segments.join("/"))
-    }
-}
-
 fn install_embedded_bins() {
     use crate::fs::{self, FsError};
 
@@ -570,6 +586,186 @@ fn install_embedded_bins() {
         Err(err) => println!("fs error: {}", err),
     }
 
+    match fs::read_file("/bin/init") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/init", crate::embedded::INIT_BIN) {
+            Ok(_) => println!("installed /bin/init"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/ps") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/ps", crate::embedded::PS_BIN) {
+            Ok(_) => println!("installed /bin/ps"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/date") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/date", crate::embedded::DATE_BIN) {
+            Ok(_) => println!("installed /bin/date"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/ls") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/ls", crate::embedded::LS_BIN) {
+            Ok(_) => println!("installed /bin/ls"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/echo") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/echo", crate::embedded::ECHO_BIN) {
+            Ok(_) => println!("installed /bin/echo"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/grep") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/grep", crate::embedded::GREP_BIN) {
+            Ok(_) => println!("installed /bin/grep"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/head") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/head", crate::embedded::HEAD_BIN) {
+            Ok(_) => println!("installed /bin/head"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/tail") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/tail", crate::embedded::TAIL_BIN) {
+            Ok(_) => println!("installed /bin/tail"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/cp") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/cp", crate::embedded::CP_BIN) {
+            Ok(_) => println!("installed /bin/cp"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/mv") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/mv", crate::embedded::MV_BIN) {
+            Ok(_) => println!("installed /bin/mv"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/mkdir") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/mkdir", crate::embedded::MKDIR_BIN) {
+            Ok(_) => println!("installed /bin/mkdir"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/rm") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/rm", crate::embedded::RM_BIN) {
+            Ok(_) => println!("installed /bin/rm"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/rmdir") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/rmdir", crate::embedded::RMDIR_BIN) {
+            Ok(_) => println!("installed /bin/rmdir"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/hexdump") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/hexdump", crate::embedded::HEXDUMP_BIN) {
+            Ok(_) => println!("installed /bin/hexdump"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/sort") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/sort", crate::embedded::SORT_BIN) {
+            Ok(_) => println!("installed /bin/sort"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/uniq") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/uniq", crate::embedded::UNIQ_BIN) {
+            Ok(_) => println!("installed /bin/uniq"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/kill") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/kill", crate::embedded::KILL_BIN) {
+            Ok(_) => println!("installed /bin/kill"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/ed") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/ed", crate::embedded::ED_BIN) {
+            Ok(_) => println!("installed /bin/ed"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/fs") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/fs", crate::embedded::FS_BIN) {
+            Ok(_) => println!("installed /bin/fs"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
+    match fs::read_file("/bin/bench") {
+        Ok(_) => {}
+        Err(FsError::NotFound) => match fs::write_file("/bin/bench", crate::embedded::BENCH_BIN) {
+            Ok(_) => println!("installed /bin/bench"),
+            Err(err) => println!("fs error: {}", err),
+        },
+        Err(err) => println!("fs error: {}", err),
+    }
+
     match fs::read_file("/bin/wc") {
         Ok(_) => {}
         Err(FsError::NotFound) => match fs::write_file("/bin/wc", crate::embedded::WC_BIN) {
@@ -581,13 +777,20 @@ fn install_embedded_bins() {
 }
 
 fn launch_user_shell() -> ! {
-    let sh_path = "/bin/sh";
+    // /bin/init starts the shell and reaps orphans; without it the shell
+    // itself becomes pid 1
+    let sh_path = if crate::fs::read_file("/bin/init").is_ok() {
+        "/bin/init"
+    } else {
+        "/bin/sh"
+    };
     let args = [sh_path];
+    let env = crate::process::DEFAULT_ENV;
 
     let program = match crate::process::load(sh_path) {
         Ok(p) => p,
         Err(_) => {
-            println!("failed to load /bin/sh");
+            println!("failed to load {}", sh_path);
             return idle_loop();
         }
     };
@@ -597,21 +800,23 @@ fn launch_user_shell() -> ! {
         println!("failed to load shell image");
         return idle_loop();
     }
-    let (sp, _argc, _argv_ptr) = match crate::process::build_user_stack(&args) {
-        Ok(v) => v,
-        Err(_) => {
-            println!("failed to build shell stack");
-            return idle_loop();
-        }
-    };
+    let (sp, _argc, _argv_ptr, _envp_ptr) =
+        match crate::process::build_user_stack(&args, env, program.entry) {
+            Ok(v) => v,
+            Err(_) => {
+                println!("failed to build shell stack");
+                return idle_loop();
+            }
+        };
 
-    let (sp, shell_argc, shell_argv_ptr) = match crate::process::build_user_stack(&args) {
-        Ok(v) => v,
-        Err(_) => {
-            println!("failed to build shell stack");
-            return idle_loop();
-        }
-    };
+    let (sp, shell_argc, shell_argv_ptr, shell_envp_ptr) =
+        match crate::process::build_user_stack(&args, env, program.entry) {
+            Ok(v) => v,
+            Err(_) => {
+                println!("failed to build shell stack");
+                return idle_loop();
+            }
+        };
 
     // Capture shell's initial memory state
     let mut shell_memory = alloc::vec![0u8; crate::process::USER_WINDOW_SIZE];
@@ -636,6 +841,14 @@ fn launch_user_shell() -> ! {
                 return idle_loop();
             }
         };
+        if let Some(shell) = table.get_mut(pid) {
+            shell.image_end = crate::process::image_end(&program);
+            shell.heap_start = shell.image_end;
+            shell.read_only = crate::process::read_only_ranges(&program);
+            shell.env = env.iter().map(|s| s.to_string()).collect();
+            shell.envp_ptr = shell_envp_ptr;
+            shell.tp = crate::process::thread_pointer(&program);
+        }
         // Don't set as current yet - scheduler will handle it
     }
 
@@ -643,48 +856,149 @@ fn launch_user_shell() -> ! {
 
     // Restore shell's memory and enter it
     // After this, all scheduling happens via trap handlers
-    let (entry, sp) = {
+    let context = {
         let mut table = crate::proc::PROCESS_TABLE.lock();
         let shell_pid = table.get_all_processes().first().expect("no shell process").pid;
         table.set_current(shell_pid);
         table.restore_process_memory(shell_pid);
+        table.restore_process_registers(shell_pid);
 
-        let process = table.get(shell_pid).expect("shell process vanished");
-        (process.pc, process.sp)
+        let process = table.get_mut(shell_pid).expect("shell process vanished");
+        process.kernel_stack.context()
     };
 
     // Enter user mode for shell
     // Scheduling happens via syscall trap handlers calling Scheduler::maybe_switch
     // This never returns in normal operation
-    unsafe { crate::process::enter_user_at(entry, sp, 0, 0) };
+    unsafe { crate::process::enter_user_at(context) };
 
     println!("All processes exited");
     idle_loop()
 }
 
 #[entry]
-fn main(a0: usize) -> ! {
+fn main(a0: usize, a1: usize) -> ! {
     if a0 != 0 {
         idle_loop();
     }
 
+    // a1 is the device tree the firmware passed
+    let bootargs = fdt::bootargs(a1).unwrap_or("");
+    if bootargs.split_whitespace().any(|arg| arg == "console=sbi") {
+        uart::use_sbi();
+    }
+
     unsafe {
         heap::init_kernel_heap();
     }
 
     uart::init();
+    // Before interrupts::init, which enables its interrupt line
+    let uart1 = uart::init_secondary();
     interrupts::init();
 
     println!("Hello world from hart {}!\n", a0);
+    for device in crate::virtio::probe() {
+        klog!(
+            Virtio,
+            Info,
+            "virtio: {} device at {} (irq {})",
+            device.kind(),
+            device.transport,
+            device.irq
+        );
+    }
+
+    if uart::is_sbi() {
+        klog!(Info, "uart: console through SBI");
+    }
+    if uart1 {
+        klog!(Info, "uart: second 16550 on /dev/ttyS1");
+    }
+    if console::init() {
+        klog!(Info, "console: virtio console on /dev/hvc0");
+    }
+    if random::init() {
+        klog!(Info, "random: seeded from virtio entropy device");
+    } else {
+        klog!(
+            Info,
+            "random: no virtio entropy device, seeded from timer jitter"
+        );
+    }
+    if rtc::init() {
+        klog!(Info, "rtc: {}", rtc::DateTime::from_unix(rtc::unix_time()));
+    } else {
+        klog!(Info, "rtc: no goldfish RTC, wall clock counts from boot");
+    }
+
+    match crate::net::init() {
+        Ok(mac) => klog!(
+            Info,
+            "net: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x} up as 10.0.2.15",
+            mac[0],
+            mac[1],
+            mac[2],
+            mac[3],
+            mac[4],
+            mac[5]
+        ),
+        Err(crate::virtio::block::VirtioError::DeviceNotFound) => {}
+        Err(err) => klog!(Virtio, Warn, "net: virtio-net init failed: {:?}", err),
+    }
 
     match crate::fs::init() {
-        Ok(()) => install_embedded_bins(),
-        Err(err) => println!("failed to initialize filesystem: {}", err),
+        Ok(()) => {
+            install_embedded_bins();
+            mount_data_disk();
+            mount_host_share();
+        }
+        Err(err) => klog!(Fs, Warn, "failed to initialize filesystem: {}", err),
     }
 
     launch_user_shell()
 }
 
+/// Mount a second block device, if there is one, at /mnt
+fn mount_data_disk() {
+    if crate::virtio::find(crate::virtio::DEVICE_BLOCK, 1).is_none() {
+        return;
+    }
+    let device = crate::virtio::block::name(1);
+    match crate::fs::mkdir("/mnt") {
+        Ok(()) | Err(crate::fs::FsError::AlreadyExists) => {}
+        Err(err) => {
+            klog!(Fs, Warn, "fs: cannot create /mnt: {}", err);
+            return;
+        }
+    }
+    match crate::fs::mount(&device, "/mnt") {
+        Ok(()) => klog!(Fs, Info, "fs: mounted /dev/{} on /mnt", device),
+        Err(err) => klog!(Fs, Warn, "fs: cannot mount /dev/{}: {}", device, err),
+    }
+}
+
+/// Mount the directory QEMU shares over virtio-9p, if any, at /host
+fn mount_host_share() {
+    if crate::virtio::find(crate::virtio::DEVICE_9P, 0).is_none() {
+        return;
+    }
+    match crate::fs::mkdir("/host") {
+        Ok(()) | Err(crate::fs::FsError::AlreadyExists) => {}
+        Err(err) => {
+            klog!(Fs, Warn, "fs: cannot create /host: {}", err);
+            return;
+        }
+    }
+    match crate::fs::mount(crate::fs::HOST_DEVICE, "/host") {
+        Ok(()) => match crate::virtio::p9::init() {
+            Ok(tag) => klog!(Fs, Info, "fs: mounted host share '{}' on /host", tag),
+            Err(_) => klog!(Fs, Info, "fs: mounted host share on /host"),
+        },
+        Err(err) => klog!(Fs, Warn, "fs: cannot mount host share: {}", err),
+    }
+}
+
 fn idle_loop() -> ! {
     loop {
         unsafe {
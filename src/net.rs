@@ -0,0 +1,724 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use spin::Mutex;
+
+use crate::socket::{SOCKET_BUF_SIZE, SOCKET_TABLE};
+use crate::virtio::{self, block::VirtioError};
+
+/// Static address on QEMU's user-mode network, 10.0.2.15
+pub const LOCAL_ADDR: u32 = 0x0a00_020f;
+/// QEMU's user-mode router, 10.0.2.2; everything off-subnet goes there
+const GATEWAY_ADDR: u32 = 0x0a00_0202;
+const NETMASK: u32 = 0xffff_ff00;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const BROADCAST_MAC: [u8; 6] = [0xff; 6];
+const ETH_HEADER: usize = 14;
+/// Shorter frames are padded; the device does not do it for us
+const ETH_MIN_FRAME: usize = 60;
+
+const ARP_REQUEST: u16 = 1;
+const ARP_REPLY: u16 = 2;
+const ARP_PACKET: usize = 28;
+
+const IPV4_HEADER: usize = 20;
+const IPV4_TTL: u8 = 64;
+const PROTO_ICMP: u8 = 1;
+const PROTO_TCP: u8 = 6;
+const PROTO_UDP: u8 = 17;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+const UDP_HEADER: usize = 8;
+/// Largest datagram that fits one frame; there is no fragmentation
+pub const MAX_UDP_PAYLOAD: usize = virtio::net::MAX_FRAME - ETH_HEADER - IPV4_HEADER - UDP_HEADER;
+const TCP_HEADER: usize = 20;
+
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+
+/// Largest TCP payload sent: the default MSS, since no options are
+/// exchanged
+const TCP_MSS: usize = 536;
+/// First retransmission timeout in timebase cycles (500ms); doubles on
+/// each retry
+const TCP_RTO: u64 = 5_000_000;
+/// Retransmissions before a connection is given up
+const TCP_MAX_RETRIES: u8 = 8;
+
+/// IP packets held while their next hop's MAC is being resolved
+const MAX_PENDING: usize = 8;
+
+struct Interface {
+    mac: [u8; 6],
+    /// IPv4 address to MAC address
+    arp_cache: Vec<(u32, [u8; 6])>,
+    /// IP packets waiting for an ARP reply, with their next hop
+    pending: Vec<(u32, Vec<u8>)>,
+    next_ip_id: u16,
+}
+
+static INTERFACE: Mutex<Option<Interface>> = Mutex::new(None);
+
+/// Bring up the first virtio-net device with the static address
+pub fn init() -> Result<[u8; 6], VirtioError> {
+    let mac = virtio::net::init()?;
+    *INTERFACE.lock() = Some(Interface {
+        mac,
+        arp_cache: Vec::new(),
+        pending: Vec::new(),
+        next_ip_id: 1,
+    });
+    Ok(mac)
+}
+
+/// Whether there is a network device to reach other hosts through
+pub fn is_up() -> bool {
+    INTERFACE.lock().is_some()
+}
+
+/// Handle every received frame, then run TCP retransmission timers.
+/// Called from the NIC interrupt and every timer tick; a frame that
+/// arrives while the socket table is busy is dropped.
+pub fn poll() {
+    if INTERFACE.try_lock().is_none_or(|iface| iface.is_none()) {
+        return;
+    }
+    while let Some(frame) = virtio::net::receive() {
+        handle_frame(&frame);
+    }
+    if let Some(mut sockets) = SOCKET_TABLE.try_lock() {
+        sockets.tcp_tick();
+    }
+}
+
+impl Interface {
+    fn send_frame(&self, dst: [u8; 6], ethertype: u16, payload: &[u8]) {
+        let mut frame = Vec::with_capacity(ETH_MIN_FRAME.max(ETH_HEADER + payload.len()));
+        frame.extend_from_slice(&dst);
+        frame.extend_from_slice(&self.mac);
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        if frame.len() < ETH_MIN_FRAME {
+            frame.resize(ETH_MIN_FRAME, 0);
+        }
+        virtio::net::transmit(&frame);
+    }
+
+    fn send_arp(&self, op: u16, target_mac: [u8; 6], target_addr: u32) {
+        let mut packet = Vec::with_capacity(ARP_PACKET);
+        packet.extend_from_slice(&1u16.to_be_bytes());
+        packet.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        packet.extend_from_slice(&[6, 4]);
+        packet.extend_from_slice(&op.to_be_bytes());
+        packet.extend_from_slice(&self.mac);
+        packet.extend_from_slice(&LOCAL_ADDR.to_be_bytes());
+        packet.extend_from_slice(&target_mac);
+        packet.extend_from_slice(&target_addr.to_be_bytes());
+        let dst = if op == ARP_REQUEST {
+            BROADCAST_MAC
+        } else {
+            target_mac
+        };
+        self.send_frame(dst, ETHERTYPE_ARP, &packet);
+    }
+
+    fn lookup(&self, addr: u32) -> Option<[u8; 6]> {
+        self.arp_cache
+            .iter()
+            .find(|(cached, _)| *cached == addr)
+            .map(|&(_, mac)| mac)
+    }
+
+    /// Record `addr`'s MAC and send whatever was waiting for it
+    fn learn(&mut self, addr: u32, mac: [u8; 6]) {
+        match self.arp_cache.iter_mut().find(|(cached, _)| *cached == addr) {
+            Some(entry) => entry.1 = mac,
+            None => self.arp_cache.push((addr, mac)),
+        }
+        let (ready, waiting) = core::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|(next_hop, _)| *next_hop == addr);
+        self.pending = waiting;
+        for (_, packet) in ready {
+            self.send_frame(mac, ETHERTYPE_IPV4, &packet);
+        }
+    }
+}
+
+/// Send `payload` to `dst` as one IPv4 packet of protocol `proto`. A
+/// packet whose next hop is not in the ARP cache waits for the reply.
+fn send_ip(proto: u8, dst: u32, payload: &[u8]) {
+    let mut guard = INTERFACE.lock();
+    let Some(iface) = guard.as_mut() else {
+        return;
+    };
+    let id = iface.next_ip_id;
+    iface.next_ip_id = iface.next_ip_id.wrapping_add(1);
+
+    let mut packet = Vec::with_capacity(IPV4_HEADER + payload.len());
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&((IPV4_HEADER + payload.len()) as u16).to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    // Don't fragment; we never reassemble either
+    packet.extend_from_slice(&0x4000u16.to_be_bytes());
+    packet.extend_from_slice(&[IPV4_TTL, proto, 0, 0]);
+    packet.extend_from_slice(&LOCAL_ADDR.to_be_bytes());
+    packet.extend_from_slice(&dst.to_be_bytes());
+    let sum = checksum(&packet);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+
+    let next_hop = if dst & NETMASK == LOCAL_ADDR & NETMASK {
+        dst
+    } else {
+        GATEWAY_ADDR
+    };
+    match iface.lookup(next_hop) {
+        Some(mac) => iface.send_frame(mac, ETHERTYPE_IPV4, &packet),
+        None => {
+            if iface.pending.len() < MAX_PENDING {
+                iface.pending.push((next_hop, packet));
+            }
+            iface.send_arp(ARP_REQUEST, [0; 6], next_hop);
+        }
+    }
+}
+
+fn handle_frame(frame: &[u8]) {
+    if frame.len() < ETH_HEADER {
+        return;
+    }
+    let payload = &frame[ETH_HEADER..];
+    match be16(frame, 12) {
+        ETHERTYPE_ARP => handle_arp(payload),
+        ETHERTYPE_IPV4 => handle_ipv4(payload),
+        _ => {}
+    }
+}
+
+fn handle_arp(packet: &[u8]) {
+    if packet.len() < ARP_PACKET || be16(packet, 2) != ETHERTYPE_IPV4 || packet[4..6] != [6, 4] {
+        return;
+    }
+    let op = be16(packet, 6);
+    let mut sender_mac = [0u8; 6];
+    sender_mac.copy_from_slice(&packet[8..14]);
+    let sender_addr = be32(packet, 14);
+    if be32(packet, 24) != LOCAL_ADDR {
+        return;
+    }
+    let mut guard = INTERFACE.lock();
+    let Some(iface) = guard.as_mut() else {
+        return;
+    };
+    iface.learn(sender_addr, sender_mac);
+    if op == ARP_REQUEST {
+        iface.send_arp(ARP_REPLY, sender_mac, sender_addr);
+    }
+}
+
+fn handle_ipv4(packet: &[u8]) {
+    if packet.len() < IPV4_HEADER || packet[0] >> 4 != 4 {
+        return;
+    }
+    let header_len = (packet[0] & 0x0f) as usize * 4;
+    let total_len = be16(packet, 2) as usize;
+    if header_len < IPV4_HEADER || total_len < header_len || total_len > packet.len() {
+        return;
+    }
+    // Fragments (MF set or a non-zero offset) are dropped
+    if be16(packet, 6) & 0x3fff != 0 || checksum(&packet[..header_len]) != 0 {
+        return;
+    }
+    let src = be32(packet, 12);
+    if be32(packet, 16) != LOCAL_ADDR {
+        return;
+    }
+    let payload = &packet[header_len..total_len];
+    match packet[9] {
+        PROTO_ICMP => handle_icmp(src, payload),
+        PROTO_UDP => handle_udp(src, payload),
+        PROTO_TCP => handle_tcp(src, payload),
+        _ => {}
+    }
+}
+
+/// Answer echo requests; everything else is ignored
+fn handle_icmp(src: u32, message: &[u8]) {
+    if message.len() < 8 || message[0] != ICMP_ECHO_REQUEST || checksum(message) != 0 {
+        return;
+    }
+    let mut reply = message.to_vec();
+    reply[0] = ICMP_ECHO_REPLY;
+    reply[2..4].fill(0);
+    let sum = checksum(&reply);
+    reply[2..4].copy_from_slice(&sum.to_be_bytes());
+    send_ip(PROTO_ICMP, src, &reply);
+}
+
+fn handle_udp(src: u32, datagram: &[u8]) {
+    if datagram.len() < UDP_HEADER {
+        return;
+    }
+    let len = be16(datagram, 4) as usize;
+    if len < UDP_HEADER || len > datagram.len() {
+        return;
+    }
+    let datagram = &datagram[..len];
+    // A zero checksum means the sender did not compute one
+    if be16(datagram, 6) != 0 && transport_checksum(src, LOCAL_ADDR, PROTO_UDP, datagram) != 0 {
+        return;
+    }
+    let from = (src, be16(datagram, 0));
+    if let Some(mut sockets) = SOCKET_TABLE.try_lock() {
+        sockets.deliver_datagram(be16(datagram, 2), from, &datagram[UDP_HEADER..]);
+    }
+}
+
+/// Send one UDP datagram from `src_port`
+pub fn send_udp(src_port: u16, dst: (u32, u16), data: &[u8]) {
+    let len = UDP_HEADER + data.len();
+    let mut datagram = Vec::with_capacity(len);
+    datagram.extend_from_slice(&src_port.to_be_bytes());
+    datagram.extend_from_slice(&dst.1.to_be_bytes());
+    datagram.extend_from_slice(&(len as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(data);
+    let sum = match transport_checksum(LOCAL_ADDR, dst.0, PROTO_UDP, &datagram) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+    send_ip(PROTO_UDP, dst.0, &datagram);
+}
+
+/// A parsed TCP segment
+pub struct Segment<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub payload: &'a [u8],
+}
+
+impl Segment<'_> {
+    /// Sequence space the segment occupies; SYN and FIN count as one each
+    fn len(&self) -> u32 {
+        self.payload.len() as u32
+            + (self.flags & TCP_SYN != 0) as u32
+            + (self.flags & TCP_FIN != 0) as u32
+    }
+}
+
+fn handle_tcp(src: u32, bytes: &[u8]) {
+    if bytes.len() < TCP_HEADER || transport_checksum(src, LOCAL_ADDR, PROTO_TCP, bytes) != 0 {
+        return;
+    }
+    let data_offset = (bytes[12] >> 4) as usize * 4;
+    if data_offset < TCP_HEADER || data_offset > bytes.len() {
+        return;
+    }
+    let segment = Segment {
+        src_port: be16(bytes, 0),
+        dst_port: be16(bytes, 2),
+        seq: be32(bytes, 4),
+        ack: be32(bytes, 8),
+        flags: bytes[13],
+        window: be16(bytes, 14),
+        payload: &bytes[data_offset..],
+    };
+    let Some(mut sockets) = SOCKET_TABLE.try_lock() else {
+        return;
+    };
+    if sockets.tcp_input(src, &segment) || segment.flags & TCP_RST != 0 {
+        return;
+    }
+    drop(sockets);
+    // Nobody has this connection: reset it
+    let remote = (src, segment.src_port);
+    if segment.flags & TCP_ACK != 0 {
+        send_tcp(segment.dst_port, remote, segment.ack, 0, TCP_RST, 0, &[]);
+    } else {
+        let ack = segment.seq.wrapping_add(segment.len());
+        send_tcp(segment.dst_port, remote, 0, ack, TCP_RST | TCP_ACK, 0, &[]);
+    }
+}
+
+fn send_tcp(
+    src_port: u16,
+    dst: (u32, u16),
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    payload: &[u8],
+) {
+    let mut segment = Vec::with_capacity(TCP_HEADER + payload.len());
+    segment.extend_from_slice(&src_port.to_be_bytes());
+    segment.extend_from_slice(&dst.1.to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.extend_from_slice(&[(TCP_HEADER as u8 / 4) << 4, flags]);
+    segment.extend_from_slice(&window.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    segment.extend_from_slice(payload);
+    let sum = transport_checksum(LOCAL_ADDR, dst.0, PROTO_TCP, &segment);
+    segment[16..18].copy_from_slice(&sum.to_be_bytes());
+    send_ip(PROTO_TCP, dst.0, &segment);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcpState {
+    SynSent,
+    SynReceived,
+    Established,
+    /// The peer has sent FIN; buffered data can still be read
+    CloseWait,
+    /// Our FIN is queued or sent; waiting for it to be acknowledged and
+    /// for the peer's FIN
+    Closing,
+    /// Finished, reset, refused or timed out
+    Closed,
+}
+
+/// One TCP connection. In-order segments only: anything out of order is
+/// dropped and re-acknowledged, relying on the peer to retransmit.
+pub struct TcpConn {
+    pub local_port: u16,
+    pub remote: (u32, u16),
+    pub state: TcpState,
+    /// Oldest unacknowledged sequence number
+    snd_una: u32,
+    /// Next sequence number to send
+    snd_nxt: u32,
+    /// Peer's advertised window
+    snd_wnd: u16,
+    /// Next sequence number expected from the peer
+    rcv_nxt: u32,
+    /// Bytes from `snd_una` on: sent but unacknowledged, then unsent
+    unacked: VecDeque<u8>,
+    /// In-order bytes waiting for `recv`
+    pub received: VecDeque<u8>,
+    fin_sent: bool,
+    peer_fin: bool,
+    /// Timebase reading when the oldest outstanding segment was sent
+    sent_at: u64,
+    retries: u8,
+}
+
+impl TcpConn {
+    fn new(local_port: u16, remote: (u32, u16), state: TcpState) -> Self {
+        let iss = now() as u32;
+        Self {
+            local_port,
+            remote,
+            state,
+            snd_una: iss,
+            snd_nxt: iss.wrapping_add(1),
+            snd_wnd: 0,
+            rcv_nxt: 0,
+            unacked: VecDeque::new(),
+            received: VecDeque::new(),
+            fin_sent: false,
+            peer_fin: false,
+            sent_at: now(),
+            retries: 0,
+        }
+    }
+
+    /// Active open: send SYN to `remote`
+    pub fn connect(local_port: u16, remote: (u32, u16)) -> Self {
+        let conn = Self::new(local_port, remote, TcpState::SynSent);
+        conn.emit(conn.snd_una, TCP_SYN, &[]);
+        conn
+    }
+
+    /// Passive open: answer `syn` with SYN-ACK
+    pub fn accept(local_port: u16, remote: (u32, u16), syn: &Segment) -> Self {
+        let mut conn = Self::new(local_port, remote, TcpState::SynReceived);
+        conn.rcv_nxt = syn.seq.wrapping_add(1);
+        conn.snd_wnd = syn.window;
+        conn.emit(conn.snd_una, TCP_SYN | TCP_ACK, &[]);
+        conn
+    }
+
+    /// Room left in the receive buffer, advertised as our window
+    fn window(&self) -> u16 {
+        (SOCKET_BUF_SIZE - self.received.len()).min(u16::MAX as usize) as u16
+    }
+
+    fn emit(&self, seq: u32, flags: u8, payload: &[u8]) {
+        let ack = if flags & TCP_SYN != 0 && flags & TCP_ACK == 0 {
+            0
+        } else {
+            self.rcv_nxt
+        };
+        send_tcp(self.local_port, self.remote, seq, ack, flags, self.window(), payload);
+    }
+
+    /// Process a segment for this connection. Returns true if the
+    /// socket's waiters should be woken.
+    pub fn input(&mut self, seg: &Segment) -> bool {
+        if seg.flags & TCP_RST != 0 {
+            // In SYN-SENT a reset only counts if it answers our SYN
+            if self.state == TcpState::SynSent
+                && (seg.flags & TCP_ACK == 0 || seg.ack != self.snd_nxt)
+            {
+                return false;
+            }
+            self.state = TcpState::Closed;
+            return true;
+        }
+
+        let mut wake = false;
+        match self.state {
+            TcpState::SynSent => {
+                if seg.flags & (TCP_SYN | TCP_ACK) != TCP_SYN | TCP_ACK || seg.ack != self.snd_nxt {
+                    return false;
+                }
+                self.rcv_nxt = seg.seq.wrapping_add(1);
+                self.snd_una = seg.ack;
+                self.snd_wnd = seg.window;
+                self.state = TcpState::Established;
+                self.retries = 0;
+                self.emit(self.snd_nxt, TCP_ACK, &[]);
+                return true;
+            }
+            TcpState::SynReceived => {
+                if seg.flags & TCP_SYN != 0 {
+                    // Our SYN-ACK was lost
+                    self.emit(self.snd_una, TCP_SYN | TCP_ACK, &[]);
+                    return false;
+                }
+                if seg.flags & TCP_ACK == 0 || seg.ack != self.snd_nxt {
+                    return false;
+                }
+                self.snd_una = seg.ack;
+                self.state = TcpState::Established;
+                self.retries = 0;
+                wake = true;
+            }
+            TcpState::Closed => return false,
+            _ => {}
+        }
+
+        if seg.flags & TCP_ACK != 0 {
+            if seq_lt(self.snd_una, seg.ack) && !seq_lt(self.snd_nxt, seg.ack) {
+                let acked = seg.ack.wrapping_sub(self.snd_una) as usize;
+                self.unacked.drain(..acked.min(self.unacked.len()));
+                self.snd_una = seg.ack;
+                self.retries = 0;
+                self.sent_at = now();
+                wake = true;
+            }
+            self.snd_wnd = seg.window;
+        }
+
+        if seg.seq == self.rcv_nxt {
+            let taken = match self.state {
+                TcpState::Established => {
+                    let room = SOCKET_BUF_SIZE - self.received.len();
+                    let taken = seg.payload.len().min(room);
+                    self.received.extend(&seg.payload[..taken]);
+                    taken
+                }
+                // Nobody reads a closing connection; take the data and
+                // acknowledge it
+                TcpState::Closing => seg.payload.len(),
+                _ => 0,
+            };
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(taken as u32);
+            wake |= taken > 0;
+            if seg.flags & TCP_FIN != 0 && taken == seg.payload.len() && !self.peer_fin {
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                self.peer_fin = true;
+                if self.state == TcpState::Established {
+                    self.state = TcpState::CloseWait;
+                }
+                wake = true;
+            }
+        }
+        // Data, FIN and a repeated SYN (our ACK was lost) all get an ACK,
+        // which repeats rcv_nxt for anything out of order
+        if !seg.payload.is_empty() || seg.flags & (TCP_FIN | TCP_SYN) != 0 {
+            self.emit(self.snd_nxt, TCP_ACK, &[]);
+        }
+
+        self.output();
+        if self.state == TcpState::Closing
+            && self.peer_fin
+            && self.fin_sent
+            && self.snd_una == self.snd_nxt
+        {
+            self.state = TcpState::Closed;
+        }
+        wake
+    }
+
+    /// Send as much queued data as the peer's window allows, then our FIN
+    /// once everything before it is out
+    fn output(&mut self) {
+        if !matches!(
+            self.state,
+            TcpState::Established | TcpState::CloseWait | TcpState::Closing
+        ) {
+            return;
+        }
+        loop {
+            let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let unsent = self.unacked.len().saturating_sub(in_flight);
+            let window = (self.snd_wnd as usize).saturating_sub(in_flight);
+            let len = unsent.min(window).min(TCP_MSS);
+            if len == 0 {
+                break;
+            }
+            let chunk: Vec<u8> = self.unacked.range(in_flight..in_flight + len).copied().collect();
+            if in_flight == 0 {
+                self.sent_at = now();
+            }
+            self.emit(self.snd_nxt, TCP_ACK | TCP_PSH, &chunk);
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+        }
+        let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        if self.state == TcpState::Closing && !self.fin_sent && in_flight == self.unacked.len() {
+            if in_flight == 0 {
+                self.sent_at = now();
+            }
+            self.emit(self.snd_nxt, TCP_FIN | TCP_ACK, &[]);
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.fin_sent = true;
+        }
+    }
+
+    /// Queue `data` for sending; returns how much fit in the send buffer
+    pub fn send(&mut self, data: &[u8]) -> usize {
+        let room = SOCKET_BUF_SIZE.saturating_sub(self.unacked.len());
+        let len = data.len().min(room);
+        self.unacked.extend(&data[..len]);
+        self.output();
+        len
+    }
+
+    /// Move received bytes into `buf`, reopening the window if it had
+    /// closed below one segment
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let was_small = (self.window() as usize) < TCP_MSS;
+        let len = buf.len().min(self.received.len());
+        for (dst, src) in buf.iter_mut().zip(self.received.drain(..len)) {
+            *dst = src;
+        }
+        if was_small && self.window() as usize >= TCP_MSS && self.state == TcpState::Established {
+            self.emit(self.snd_nxt, TCP_ACK, &[]);
+        }
+        len
+    }
+
+    /// Start an orderly close; the connection stays `Closing` until the
+    /// FIN exchange finishes
+    pub fn close(&mut self) {
+        match self.state {
+            TcpState::Established | TcpState::CloseWait => {
+                self.state = TcpState::Closing;
+                self.output();
+            }
+            TcpState::SynSent | TcpState::SynReceived => {
+                self.emit(self.snd_nxt, TCP_RST, &[]);
+                self.state = TcpState::Closed;
+            }
+            TcpState::Closing | TcpState::Closed => {}
+        }
+    }
+
+    /// Retransmit if the oldest outstanding segment has timed out. Returns
+    /// true if the connection was given up.
+    pub fn tick(&mut self) -> bool {
+        if self.state == TcpState::Closed || self.snd_una == self.snd_nxt {
+            return false;
+        }
+        let timeout = TCP_RTO << self.retries.min(4);
+        let now = now();
+        if now.wrapping_sub(self.sent_at) < timeout {
+            return false;
+        }
+        if self.retries >= TCP_MAX_RETRIES {
+            self.state = TcpState::Closed;
+            return true;
+        }
+        self.retries += 1;
+        self.sent_at = now;
+        match self.state {
+            TcpState::SynSent => self.emit(self.snd_una, TCP_SYN, &[]),
+            TcpState::SynReceived => self.emit(self.snd_una, TCP_SYN | TCP_ACK, &[]),
+            _ => {
+                // Go back to the oldest unacknowledged byte
+                self.snd_nxt = self.snd_una;
+                self.fin_sent = false;
+                self.output();
+            }
+        }
+        false
+    }
+}
+
+fn now() -> u64 {
+    riscv::register::time::read64()
+}
+
+/// `a` comes before `b` in sequence space
+fn seq_lt(a: u32, b: u32) -> bool {
+    (b.wrapping_sub(a) as i32) > 0
+}
+
+fn be16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn be32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// One's-complement sum of `data` as big-endian 16-bit words
+fn sum_words(mut sum: u32, data: &[u8]) -> u32 {
+    let (pairs, rest) = data.as_chunks::<2>();
+    for pair in pairs {
+        sum += u16::from_be_bytes(*pair) as u32;
+    }
+    if let [last] = rest {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Internet checksum; 0 when run over data that includes a valid one
+fn checksum(data: &[u8]) -> u16 {
+    fold(sum_words(0, data))
+}
+
+/// Checksum of a UDP or TCP message including the IPv4 pseudo-header
+fn transport_checksum(src: u32, dst: u32, proto: u8, message: &[u8]) -> u16 {
+    let mut pseudo = [0u8; 12];
+    pseudo[0..4].copy_from_slice(&src.to_be_bytes());
+    pseudo[4..8].copy_from_slice(&dst.to_be_bytes());
+    pseudo[9] = proto;
+    pseudo[10..12].copy_from_slice(&(message.len() as u16).to_be_bytes());
+    fold(sum_words(sum_words(0, &pseudo), message))
+}
@@ -0,0 +1,627 @@
+use alloc::{string::String, vec, vec::Vec};
+
+use crate::fs::FsError;
+use crate::virtio::p9::{self, MAX_MESSAGE};
+
+const VERSION: &str = "9P2000.L";
+/// Tag on every request but Tversion; one request is in flight at a time
+const TAG: u16 = 1;
+const NOTAG: u16 = 0xffff;
+const NOFID: u32 = 0xffff_ffff;
+/// Most names one Twalk may carry
+const MAX_WALK: usize = 16;
+/// Size, type and tag at the start of every message
+const HEADER_SIZE: usize = 7;
+
+const RLERROR: u8 = 7;
+const TSTATFS: u8 = 8;
+const TLOPEN: u8 = 12;
+const TLCREATE: u8 = 14;
+const TGETATTR: u8 = 24;
+const TSETATTR: u8 = 26;
+const TREADDIR: u8 = 40;
+const TMKDIR: u8 = 72;
+const TRENAMEAT: u8 = 74;
+const TUNLINKAT: u8 = 76;
+const TVERSION: u8 = 100;
+const TATTACH: u8 = 104;
+const TWALK: u8 = 110;
+const TREAD: u8 = 116;
+const TWRITE: u8 = 118;
+const TCLUNK: u8 = 120;
+
+// Linux open flags, as 9P2000.L carries them
+const O_RDONLY: u32 = 0;
+const O_WRONLY: u32 = 1;
+const O_CREAT: u32 = 0o100;
+const O_EXCL: u32 = 0o200;
+const O_TRUNC: u32 = 0o1000;
+
+const S_IFMT: u32 = 0o170_000;
+const S_IFDIR: u32 = 0o040_000;
+const FILE_MODE: u32 = 0o644;
+const DIR_MODE: u32 = 0o755;
+
+const GETATTR_BASIC: u64 = 0x7ff;
+const SETATTR_UID: u32 = 0x2;
+const AT_REMOVEDIR: u32 = 0x200;
+
+/// What Tgetattr reports about a file
+#[derive(Clone, Copy, Debug)]
+pub struct Attr {
+    pub mode: u32,
+    pub uid: u32,
+    pub size: u64,
+    /// Seconds since the Unix epoch
+    pub mtime: u64,
+}
+
+impl Attr {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+}
+
+/// One Rreaddir record
+#[derive(Clone, Debug)]
+pub struct DirRecord {
+    pub name: String,
+    /// Offset that resumes reading just after this record
+    pub offset: u64,
+    pub is_dir: bool,
+}
+
+/// Rstatfs figures for the exported tree
+#[derive(Clone, Copy, Debug)]
+pub struct HostStat {
+    pub block_size: u32,
+    pub blocks: u64,
+    pub free_blocks: u64,
+    pub name_len: u32,
+}
+
+/// A 9P2000.L session with the host. Each operation walks a fresh fid
+/// from the attach point to its path and clunks it afterwards, so no
+/// state is kept per open file.
+pub struct Client {
+    msize: usize,
+    root: u32,
+    next_fid: u32,
+    free_fids: Vec<u32>,
+    response: Vec<u8>,
+}
+
+/// A request being built; the size is filled in by `finish`
+struct Message {
+    buf: Vec<u8>,
+}
+
+impl Message {
+    fn new(kind: u8, tag: u16) -> Self {
+        let mut buf = vec![0, 0, 0, 0, kind];
+        buf.extend_from_slice(&tag.to_le_bytes());
+        Self { buf }
+    }
+
+    fn u16(mut self, value: u16) -> Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u32(mut self, value: u32) -> Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u64(mut self, value: u64) -> Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn str(self, value: &str) -> Self {
+        let mut message = self.u16(value.len() as u16);
+        message.buf.extend_from_slice(value.as_bytes());
+        message
+    }
+
+    fn bytes(mut self, value: &[u8]) -> Self {
+        self.buf.extend_from_slice(value);
+        self
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let size = self.buf.len() as u32;
+        self.buf[..4].copy_from_slice(&size.to_le_bytes());
+        self.buf
+    }
+}
+
+/// Cursor over a reply body; running off the end means the host sent
+/// something malformed
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], FsError> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + len)
+            .ok_or(FsError::Inconsistent)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, FsError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, FsError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, FsError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, FsError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<String, FsError> {
+        let len = self.u16()? as usize;
+        let bytes = self.bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| FsError::InvalidEncoding)
+    }
+
+    /// Skip a qid: type, version and path
+    fn qid(&mut self) -> Result<u8, FsError> {
+        let kind = self.u8()?;
+        self.bytes(12)?;
+        Ok(kind)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+}
+
+/// Map a Linux errno from Rlerror
+fn errno_error(errno: u32) -> FsError {
+    match errno {
+        1 | 13 => FsError::PermissionDenied,
+        2 => FsError::NotFound,
+        5 => FsError::IoError,
+        16 => FsError::Busy,
+        17 => FsError::AlreadyExists,
+        18 => FsError::CrossDevice,
+        20 => FsError::NotADirectory,
+        21 => FsError::IsDirectory,
+        22 => FsError::InvalidName,
+        27 => FsError::FileTooLarge,
+        28 => FsError::NoSpace,
+        30 => FsError::ReadOnly,
+        36 => FsError::NameTooLong,
+        39 => FsError::DirectoryNotEmpty,
+        122 => FsError::QuotaExceeded,
+        _ => FsError::IoError,
+    }
+}
+
+/// The non-empty components of `path`
+fn components(path: &str) -> Vec<&str> {
+    path.split('/').filter(|part| !part.is_empty()).collect()
+}
+
+/// `path` split into its parent's components and its last name
+fn split_leaf(path: &str) -> Result<(Vec<&str>, &str), FsError> {
+    let mut parts = components(path);
+    let leaf = parts.pop().ok_or(FsError::InvalidPath)?;
+    Ok((parts, leaf))
+}
+
+impl Client {
+    /// Negotiate the protocol with the virtio-9p device and attach to
+    /// its exported tree
+    pub fn attach() -> Result<Client, FsError> {
+        p9::init().map_err(FsError::DeviceInitFailed)?;
+        let mut client = Client {
+            msize: MAX_MESSAGE,
+            root: 0,
+            next_fid: 1,
+            free_fids: Vec::new(),
+            response: Vec::with_capacity(MAX_MESSAGE),
+        };
+        let request = Message::new(TVERSION, NOTAG)
+            .u32(MAX_MESSAGE as u32)
+            .str(VERSION)
+            .finish();
+        let body = client.rpc(request, TVERSION)?;
+        let mut reply = Reader::new(&body);
+        let msize = reply.u32()? as usize;
+        if reply.str()? != VERSION {
+            return Err(FsError::NoFilesystem);
+        }
+        client.msize = msize.min(MAX_MESSAGE);
+
+        let request = Message::new(TATTACH, TAG)
+            .u32(client.root)
+            .u32(NOFID)
+            .str("root")
+            .str("")
+            .u32(0)
+            .finish();
+        client.rpc(request, TATTACH)?;
+        Ok(client)
+    }
+
+    /// Send `request` and return the body of the reply, which must be
+    /// of the kind answering `kind`
+    fn rpc(&mut self, request: Vec<u8>, kind: u8) -> Result<Vec<u8>, FsError> {
+        p9::transact(&request, &mut self.response).map_err(FsError::DeviceInitFailed)?;
+        let mut header = Reader::new(&self.response);
+        let size = (header.u32()? as usize).min(self.response.len());
+        let reply_kind = header.u8()?;
+        header.u16()?;
+        let body = self
+            .response
+            .get(HEADER_SIZE..size)
+            .ok_or(FsError::Inconsistent)?;
+        if reply_kind == RLERROR {
+            return Err(errno_error(Reader::new(body).u32()?));
+        }
+        if reply_kind != kind + 1 {
+            return Err(FsError::Inconsistent);
+        }
+        Ok(body.to_vec())
+    }
+
+    fn alloc_fid(&mut self) -> u32 {
+        self.free_fids.pop().unwrap_or_else(|| {
+            self.next_fid += 1;
+            self.next_fid - 1
+        })
+    }
+
+    fn clunk(&mut self, fid: u32) {
+        let request = Message::new(TCLUNK, TAG).u32(fid).finish();
+        // The host drops the fid even when clunk fails
+        let _ = self.rpc(request, TCLUNK);
+        self.free_fids.push(fid);
+    }
+
+    /// Walk a new fid from the root to `names`
+    fn walk(&mut self, names: &[&str]) -> Result<u32, FsError> {
+        let fid = self.alloc_fid();
+        let mut from = self.root;
+        let mut chunks: Vec<&[&str]> = names.chunks(MAX_WALK).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+        for chunk in chunks {
+            let mut request = Message::new(TWALK, TAG)
+                .u32(from)
+                .u32(fid)
+                .u16(chunk.len() as u16);
+            for name in chunk {
+                request = request.str(name);
+            }
+            let walked = self
+                .rpc(request.finish(), TWALK)
+                .and_then(|body| Ok(Reader::new(&body).u16()? as usize));
+            match walked {
+                Ok(count) if count == chunk.len() => from = fid,
+                // A partial walk leaves `fid` unused
+                Ok(_) | Err(FsError::NotFound) => {
+                    if from == fid {
+                        self.clunk(fid);
+                    } else {
+                        self.free_fids.push(fid);
+                    }
+                    return Err(FsError::NotFound);
+                }
+                Err(err) => {
+                    if from == fid {
+                        self.clunk(fid);
+                    } else {
+                        self.free_fids.push(fid);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(fid)
+    }
+
+    /// Walk to `names`, run `f` on the fid and clunk it
+    fn with_fid<T>(
+        &mut self,
+        names: &[&str],
+        f: impl FnOnce(&mut Self, u32) -> Result<T, FsError>,
+    ) -> Result<T, FsError> {
+        let fid = self.walk(names)?;
+        let result = f(self, fid);
+        self.clunk(fid);
+        result
+    }
+
+    fn getattr_fid(&mut self, fid: u32) -> Result<Attr, FsError> {
+        let request = Message::new(TGETATTR, TAG)
+            .u32(fid)
+            .u64(GETATTR_BASIC)
+            .finish();
+        let body = self.rpc(request, TGETATTR)?;
+        let mut reply = Reader::new(&body);
+        reply.u64()?;
+        reply.qid()?;
+        let mode = reply.u32()?;
+        let uid = reply.u32()?;
+        reply.u32()?;
+        reply.u64()?;
+        reply.u64()?;
+        let size = reply.u64()?;
+        // blksize, blocks, atime
+        for _ in 0..4 {
+            reply.u64()?;
+        }
+        let mtime = reply.u64()?;
+        Ok(Attr {
+            mode,
+            uid,
+            size,
+            mtime,
+        })
+    }
+
+    fn lopen(&mut self, fid: u32, flags: u32) -> Result<(), FsError> {
+        let request = Message::new(TLOPEN, TAG).u32(fid).u32(flags).finish();
+        self.rpc(request, TLOPEN).map(|_| ())
+    }
+
+    fn read_fid(&mut self, fid: u32, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let count = buf.len().min(self.msize - HEADER_SIZE - 4);
+        let request = Message::new(TREAD, TAG)
+            .u32(fid)
+            .u64(offset)
+            .u32(count as u32)
+            .finish();
+        let body = self.rpc(request, TREAD)?;
+        let mut reply = Reader::new(&body);
+        let len = (reply.u32()? as usize).min(count);
+        buf[..len].copy_from_slice(reply.bytes(len)?);
+        Ok(len)
+    }
+
+    fn write_fid(&mut self, fid: u32, offset: u64, data: &[u8]) -> Result<usize, FsError> {
+        let mut written = 0;
+        while written < data.len() {
+            let chunk = &data[written..];
+            let chunk = &chunk[..chunk.len().min(self.msize - HEADER_SIZE - 16)];
+            let request = Message::new(TWRITE, TAG)
+                .u32(fid)
+                .u64(offset + written as u64)
+                .u32(chunk.len() as u32)
+                .bytes(chunk)
+                .finish();
+            let body = self.rpc(request, TWRITE)?;
+            let count = Reader::new(&body).u32()? as usize;
+            if count == 0 {
+                return Err(FsError::NoSpace);
+            }
+            written += count.min(chunk.len());
+        }
+        Ok(written)
+    }
+
+    pub fn getattr(&mut self, path: &str) -> Result<Attr, FsError> {
+        self.with_fid(&components(path), |client, fid| client.getattr_fid(fid))
+    }
+
+    /// Read up to `buf.len()` bytes of the file at `path` from `offset`
+    pub fn read(&mut self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        self.with_fid(&components(path), |client, fid| {
+            if client.getattr_fid(fid)?.is_dir() {
+                return Err(FsError::IsDirectory);
+            }
+            client.lopen(fid, O_RDONLY)?;
+            let mut total = 0;
+            while total < buf.len() {
+                let count = client.read_fid(fid, offset + total as u64, &mut buf[total..])?;
+                if count == 0 {
+                    break;
+                }
+                total += count;
+            }
+            Ok(total)
+        })
+    }
+
+    pub fn read_all(&mut self, path: &str) -> Result<Vec<u8>, FsError> {
+        let size = self.getattr(path)?.size as usize;
+        let mut data = vec![0; size];
+        let len = self.read(path, 0, &mut data)?;
+        data.truncate(len);
+        Ok(data)
+    }
+
+    /// Write `data` at `offset` in the existing file at `path`, emptying
+    /// it first with `truncate`
+    pub fn write(
+        &mut self,
+        path: &str,
+        offset: u64,
+        data: &[u8],
+        truncate: bool,
+    ) -> Result<usize, FsError> {
+        self.with_fid(&components(path), |client, fid| {
+            if client.getattr_fid(fid)?.is_dir() {
+                return Err(FsError::IsDirectory);
+            }
+            client.lopen(fid, O_WRONLY | if truncate { O_TRUNC } else { 0 })?;
+            client.write_fid(fid, offset, data)
+        })
+    }
+
+    /// Create the file `path` and write `data` to it. Without `exclusive`
+    /// an existing file is emptied instead.
+    pub fn create(&mut self, path: &str, data: &[u8], exclusive: bool) -> Result<(), FsError> {
+        let (parent, leaf) = split_leaf(path)?;
+        let flags = O_WRONLY | O_CREAT | if exclusive { O_EXCL } else { O_TRUNC };
+        // Tlcreate turns the directory fid into the new file's
+        self.with_fid(&parent, |client, fid| {
+            let request = Message::new(TLCREATE, TAG)
+                .u32(fid)
+                .str(leaf)
+                .u32(flags)
+                .u32(FILE_MODE)
+                .u32(0)
+                .finish();
+            client.rpc(request, TLCREATE)?;
+            client.write_fid(fid, 0, data).map(|_| ())
+        })
+    }
+
+    pub fn mkdir(&mut self, path: &str) -> Result<(), FsError> {
+        let (parent, leaf) = split_leaf(path)?;
+        self.with_fid(&parent, |client, fid| {
+            let request = Message::new(TMKDIR, TAG)
+                .u32(fid)
+                .str(leaf)
+                .u32(DIR_MODE)
+                .u32(0)
+                .finish();
+            client.rpc(request, TMKDIR).map(|_| ())
+        })
+    }
+
+    /// Remove the file, or with `dir` the empty directory, at `path`
+    pub fn remove(&mut self, path: &str, dir: bool) -> Result<(), FsError> {
+        let attr = self.getattr(path)?;
+        match (dir, attr.is_dir()) {
+            (true, false) => return Err(FsError::NotADirectory),
+            (false, true) => return Err(FsError::IsDirectory),
+            _ => {}
+        }
+        let (parent, leaf) = split_leaf(path)?;
+        self.with_fid(&parent, |client, fid| {
+            let request = Message::new(TUNLINKAT, TAG)
+                .u32(fid)
+                .str(leaf)
+                .u32(if dir { AT_REMOVEDIR } else { 0 })
+                .finish();
+            client.rpc(request, TUNLINKAT).map(|_| ())
+        })
+    }
+
+    /// Move `from` to `to`, both relative to the export
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<(), FsError> {
+        let (from_parent, from_leaf) = split_leaf(from)?;
+        let (to_parent, to_leaf) = split_leaf(to)?;
+        self.with_fid(&from_parent, |client, old_dir| {
+            client.with_fid(&to_parent, |client, new_dir| {
+                let request = Message::new(TRENAMEAT, TAG)
+                    .u32(old_dir)
+                    .str(from_leaf)
+                    .u32(new_dir)
+                    .str(to_leaf)
+                    .finish();
+                client.rpc(request, TRENAMEAT).map(|_| ())
+            })
+        })
+    }
+
+    /// Up to `max` records of the directory at `path` from `offset`,
+    /// skipping `.` and `..`, and whether more may follow
+    pub fn readdir(
+        &mut self,
+        path: &str,
+        offset: u64,
+        max: usize,
+    ) -> Result<(Vec<DirRecord>, bool), FsError> {
+        self.with_fid(&components(path), |client, fid| {
+            if !client.getattr_fid(fid)?.is_dir() {
+                return Err(FsError::NotADirectory);
+            }
+            client.lopen(fid, O_RDONLY)?;
+            let mut records = Vec::new();
+            let mut offset = offset;
+            loop {
+                let count = (client.msize - HEADER_SIZE - 4) as u32;
+                let request = Message::new(TREADDIR, TAG)
+                    .u32(fid)
+                    .u64(offset)
+                    .u32(count)
+                    .finish();
+                let body = client.rpc(request, TREADDIR)?;
+                let mut reply = Reader::new(&body);
+                let len = reply.u32()? as usize;
+                let mut data = Reader::new(reply.bytes(len)?);
+                if data.is_empty() {
+                    return Ok((records, false));
+                }
+                while !data.is_empty() {
+                    let kind = data.qid()?;
+                    let next = data.u64()?;
+                    data.u8()?;
+                    let name = data.str()?;
+                    offset = next;
+                    if name == "." || name == ".." {
+                        continue;
+                    }
+                    records.push(DirRecord {
+                        name,
+                        offset: next,
+                        is_dir: kind & 0x80 != 0,
+                    });
+                    if records.len() == max {
+                        return Ok((records, true));
+                    }
+                }
+            }
+        })
+    }
+
+    pub fn set_uid(&mut self, path: &str, uid: u32) -> Result<(), FsError> {
+        self.with_fid(&components(path), |client, fid| {
+            let request = Message::new(TSETATTR, TAG)
+                .u32(fid)
+                .u32(SETATTR_UID)
+                .u32(0)
+                .u32(uid)
+                .u32(0)
+                .u64(0)
+                .u64(0)
+                .u64(0)
+                .u64(0)
+                .u64(0)
+                .finish();
+            client.rpc(request, TSETATTR).map(|_| ())
+        })
+    }
+
+    pub fn statfs(&mut self) -> Result<HostStat, FsError> {
+        let request = Message::new(TSTATFS, TAG).u32(self.root).finish();
+        let body = self.rpc(request, TSTATFS)?;
+        let mut reply = Reader::new(&body);
+        reply.u32()?;
+        let block_size = reply.u32()?;
+        let blocks = reply.u64()?;
+        let free_blocks = reply.u64()?;
+        reply.u64()?;
+        reply.u64()?;
+        reply.u64()?;
+        reply.u64()?;
+        let name_len = reply.u32()?;
+        Ok(HostStat {
+            block_size,
+            blocks,
+            free_blocks,
+            name_len,
+        })
+    }
+}
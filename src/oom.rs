@@ -0,0 +1,83 @@
+use alloc::vec::Vec;
+
+use crate::fd::PIPE_TABLE;
+use crate::klog;
+use crate::proc::{INIT_PID, PROCESS_TABLE, Pid};
+use crate::scheduler::Scheduler;
+
+/// Heap kept free for the kernel's own small, infallible allocations
+const HEAP_RESERVE: usize = 64 * 1024;
+
+/// Status of a process killed to free memory (128 + SIGKILL)
+const OOM_EXIT_CODE: isize = 128 + 9;
+
+/// Kernel memory charged to `pid`: its user window snapshot plus the
+/// buffers of the pipes it created
+pub fn charge_of(pid: Pid) -> usize {
+    let snapshot = PROCESS_TABLE
+        .lock()
+        .get(pid)
+        .map_or(0, |p| p.memory.capacity());
+    snapshot + PIPE_TABLE.lock().charged_to(pid)
+}
+
+/// True if `extra` more bytes keep `pid` within its memory limit
+pub fn within_limit(pid: Pid, extra: usize) -> bool {
+    let Some(limit) = PROCESS_TABLE.lock().get(pid).map(|p| p.rlimits.mem.soft) else {
+        return true;
+    };
+    charge_of(pid).saturating_add(extra) <= limit
+}
+
+/// Make room for `bytes` of heap, killing the process with the largest
+/// charge until it fits. `caller` (who gets ENOMEM instead) and init are
+/// never chosen. Returns false if nothing is left to kill.
+pub fn reserve(bytes: usize, caller: Pid) -> bool {
+    while crate::heap::free_bytes() < bytes.saturating_add(HEAP_RESERVE) {
+        if !kill_worst(caller) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Terminate the live process with the largest charge, other than
+/// `spare`, init and a process parked in the middle of a syscall
+fn kill_worst(spare: Pid) -> bool {
+    // Take the locks one at a time: pipe wakeups lock the process table
+    let candidates: Vec<(Pid, usize)> = PROCESS_TABLE
+        .lock()
+        .get_all_processes()
+        .iter()
+        .filter(|p| p.pid != spare && p.pid != INIT_PID && !p.has_exited())
+        .filter(|p| !Scheduler::is_parked(p.pid))
+        .map(|p| (p.pid, p.memory.capacity()))
+        .collect();
+    let victim = {
+        let pipes = PIPE_TABLE.lock();
+        candidates
+            .into_iter()
+            .map(|(pid, snapshot)| (pid, snapshot + pipes.charged_to(pid)))
+            .max_by_key(|&(_, charge)| charge)
+    };
+    let Some((pid, charge)) = victim else {
+        return false;
+    };
+
+    let parent_pid = {
+        let mut table = PROCESS_TABLE.lock();
+        let parent_pid = table.get(pid).map(|p| p.parent_pid);
+        table.exit_process(pid, OOM_EXIT_CODE);
+        parent_pid
+    };
+    klog!(
+        Warn,
+        "[out of memory: killed process {} ({} bytes charged)]",
+        pid,
+        charge
+    );
+    if let Some(parent_pid) = parent_pid {
+        Scheduler::unblock(parent_pid);
+    }
+    true
+}
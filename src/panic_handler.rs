@@ -9,5 +9,5 @@ fn panic(info: &PanicInfo) -> ! {
     let _ = sbi::system_reset::system_reset(ResetType::Shutdown, ResetReason::SystemFailure);
 
     println!("System reset failed");
-    loop {}
+    sbi::legacy::shutdown()
 }
@@ -0,0 +1,270 @@
+use core::{fmt, ptr};
+
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::klog;
+
+/// PCIe ECAM window of QEMU virt's host bridge; each function gets 4 KiB
+/// of config space
+const ECAM_BASE: usize = 0x3000_0000;
+/// Only the root bus is scanned; QEMU virt puts devices straight on it
+const BUS: u8 = 0;
+const DEVICES_PER_BUS: u8 = 32;
+const FUNCTIONS_PER_DEVICE: u8 = 8;
+
+/// Window the host bridge forwards to 32-bit memory BARs. Nothing has
+/// assigned BARs before the kernel runs, so `scan` places them here.
+const MMIO_WINDOW_BASE: usize = 0x4000_0000;
+const MMIO_WINDOW_END: usize = 0x8000_0000;
+/// Where the host bridge maps PCI I/O space into memory, and the part of
+/// that space `scan` hands to I/O BARs (the first 4 KiB is left to
+/// legacy ISA addresses)
+const IO_WINDOW_BASE: usize = 0x0300_0000;
+const IO_SPACE_START: usize = 0x1000;
+const IO_SPACE_END: usize = 0x1_0000;
+
+/// PLIC source of INTA on device 0; the lines rotate by device number
+const FIRST_INTX_IRQ: u32 = 32;
+const INTX_LINES: u32 = 4;
+
+const VENDOR_ID: usize = 0x00;
+const DEVICE_ID: usize = 0x02;
+const COMMAND: usize = 0x04;
+const STATUS: usize = 0x06;
+const HEADER_TYPE: usize = 0x0e;
+const BAR0: usize = 0x10;
+const BAR_COUNT: usize = 6;
+const SUBSYSTEM_ID: usize = 0x2e;
+const CAPABILITIES_POINTER: usize = 0x34;
+const INTERRUPT_PIN: usize = 0x3d;
+
+const COMMAND_IO: u16 = 1 << 0;
+const COMMAND_MEMORY: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+const HEADER_MULTI_FUNCTION: u8 = 0x80;
+/// Bridges have a different header layout; they are skipped
+const HEADER_ENDPOINT: u8 = 0x00;
+
+const BAR_IO: u32 = 1;
+const BAR_TYPE_MASK: u32 = 0b110;
+const BAR_TYPE_64: u32 = 0b100;
+const BAR_ADDRESS_MASK: u32 = !0xf;
+const BAR_IO_ADDRESS_MASK: u32 = !0x3;
+
+/// Upper bound on a capability list, in case a device's list loops
+const MAX_CAPABILITIES: usize = 48;
+
+/// Functions found by `scan`
+static FUNCTIONS: Mutex<Option<Vec<Function>>> = Mutex::new(None);
+
+/// One PCI function, by its bus/device/function address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Function {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+impl Function {
+    fn config(&self) -> usize {
+        ECAM_BASE
+            + ((self.bus as usize) << 20)
+            + ((self.device as usize) << 15)
+            + ((self.function as usize) << 12)
+    }
+
+    pub fn read8(&self, offset: usize) -> u8 {
+        unsafe { ptr::read_volatile((self.config() + offset) as *const u8) }
+    }
+
+    pub fn read16(&self, offset: usize) -> u16 {
+        unsafe { ptr::read_volatile((self.config() + offset) as *const u16) }
+    }
+
+    pub fn read32(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile((self.config() + offset) as *const u32) }
+    }
+
+    fn write16(&self, offset: usize, value: u16) {
+        unsafe { ptr::write_volatile((self.config() + offset) as *mut u16, value) };
+    }
+
+    fn write32(&self, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile((self.config() + offset) as *mut u32, value) };
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.read16(VENDOR_ID)
+    }
+
+    pub fn device_id(&self) -> u16 {
+        self.read16(DEVICE_ID)
+    }
+
+    pub fn subsystem_id(&self) -> u16 {
+        self.read16(SUBSYSTEM_ID)
+    }
+
+    /// PLIC source of the function's INTx line, None if it has none
+    pub fn irq(&self) -> Option<u32> {
+        let pin = self.read8(INTERRUPT_PIN) as u32;
+        if pin == 0 {
+            return None;
+        }
+        Some(FIRST_INTX_IRQ + (self.device as u32 + pin - 1) % INTX_LINES)
+    }
+
+    /// Address memory BAR `index` was given, None for an unassigned or
+    /// I/O BAR
+    pub fn bar(&self, index: usize) -> Option<usize> {
+        if index >= BAR_COUNT {
+            return None;
+        }
+        let offset = BAR0 + index * 4;
+        let low = self.read32(offset);
+        if low & BAR_IO != 0 {
+            return None;
+        }
+        let mut address = (low & BAR_ADDRESS_MASK) as usize;
+        if low & BAR_TYPE_MASK == BAR_TYPE_64 && index + 1 < BAR_COUNT {
+            address |= (self.read32(offset + 4) as usize) << 32;
+        }
+        (address != 0).then_some(address)
+    }
+
+    /// Memory address I/O BAR `index` was mapped at, None for an
+    /// unassigned or memory BAR
+    pub fn io_bar(&self, index: usize) -> Option<usize> {
+        if index >= BAR_COUNT {
+            return None;
+        }
+        let low = self.read32(BAR0 + index * 4);
+        if low & BAR_IO == 0 {
+            return None;
+        }
+        let port = (low & BAR_IO_ADDRESS_MASK) as usize;
+        (port != 0).then_some(IO_WINDOW_BASE + port)
+    }
+
+    /// `(id, offset)` of each entry in the capability list
+    pub fn capabilities(&self) -> impl Iterator<Item = (u8, usize)> + '_ {
+        let first = if self.read16(STATUS) & STATUS_CAPABILITIES != 0 {
+            self.read8(CAPABILITIES_POINTER) & !3
+        } else {
+            0
+        };
+        let next = |offset: &u8| Some(self.read8(*offset as usize + 1) & !3);
+        core::iter::successors(Some(first), next)
+            .take_while(|&offset| offset != 0)
+            .take(MAX_CAPABILITIES)
+            .map(|offset| (self.read8(offset as usize), offset as usize))
+    }
+
+    /// Size each BAR and place memory BARs at `*next` in the MMIO window
+    /// and I/O BARs at `*next_io` in I/O space, then turn on decoding and
+    /// bus mastering so the device can reach its rings
+    fn assign_bars(&self, next: &mut usize, next_io: &mut usize) {
+        let command = self.read16(COMMAND);
+        let enable = COMMAND_IO | COMMAND_MEMORY | COMMAND_BUS_MASTER;
+        self.write16(COMMAND, command & !enable);
+
+        let mut index = 0;
+        while index < BAR_COUNT {
+            let bar = index;
+            let offset = BAR0 + bar * 4;
+            let original = self.read32(offset);
+            let wide = original & BAR_IO == 0 && original & BAR_TYPE_MASK == BAR_TYPE_64;
+            index += if wide { 2 } else { 1 };
+            if original & BAR_IO != 0 {
+                self.assign_io_bar(bar, next_io);
+                continue;
+            }
+
+            self.write32(offset, u32::MAX);
+            let mask = self.read32(offset) & BAR_ADDRESS_MASK;
+            if mask == 0 {
+                self.write32(offset, original);
+                continue;
+            }
+            let size = (!mask as usize) + 1;
+            let address = next.next_multiple_of(size);
+            if address + size > MMIO_WINDOW_END {
+                klog!(Virtio, Warn, "pci: {} BAR {} does not fit", self, bar);
+                self.write32(offset, original);
+                continue;
+            }
+            self.write32(offset, address as u32);
+            if wide {
+                self.write32(offset + 4, 0);
+            }
+            *next = address + size;
+        }
+
+        self.write16(COMMAND, command | enable);
+    }
+
+    fn assign_io_bar(&self, bar: usize, next_io: &mut usize) {
+        let offset = BAR0 + bar * 4;
+        let original = self.read32(offset);
+        self.write32(offset, u32::MAX);
+        // Only the low 16 bits decode; the rest may read back as zero
+        let mask = self.read32(offset) & BAR_IO_ADDRESS_MASK & 0xffff;
+        if mask == 0 {
+            self.write32(offset, original);
+            return;
+        }
+        let size = (!mask & 0xffff) as usize + 1;
+        let port = next_io.next_multiple_of(size);
+        if port + size > IO_SPACE_END {
+            klog!(Virtio, Warn, "pci: {} I/O BAR {} does not fit", self, bar);
+            self.write32(offset, original);
+            return;
+        }
+        self.write32(offset, port as u32);
+        *next_io = port + size;
+    }
+}
+
+/// Enumerate the root bus once, assigning BARs to each endpoint found
+pub fn scan() -> Vec<Function> {
+    let mut functions = FUNCTIONS.lock();
+    if let Some(found) = functions.as_ref() {
+        return found.clone();
+    }
+    let mut found = Vec::new();
+    let mut next_bar = MMIO_WINDOW_BASE;
+    let mut next_io = IO_SPACE_START;
+    for device in 0..DEVICES_PER_BUS {
+        for function in 0..FUNCTIONS_PER_DEVICE {
+            let candidate = Function {
+                bus: BUS,
+                device,
+                function,
+            };
+            if candidate.vendor_id() == 0xffff {
+                if function == 0 {
+                    break;
+                }
+                continue;
+            }
+            let header = candidate.read8(HEADER_TYPE);
+            if header & !HEADER_MULTI_FUNCTION == HEADER_ENDPOINT {
+                candidate.assign_bars(&mut next_bar, &mut next_io);
+                found.push(candidate);
+            }
+            if function == 0 && header & HEADER_MULTI_FUNCTION == 0 {
+                break;
+            }
+        }
+    }
+    *functions = Some(found.clone());
+    found
+}
@@ -1,8 +1,23 @@
-use alloc::{string::String, vec::Vec};
+use alloc::{boxed::Box, collections::VecDeque, string::String, vec::Vec};
+use core::ops::Range;
 use spin::Mutex;
 
-/// Maximum number of concurrent processes
-pub const MAX_PROCESSES: usize = 8;
+/// Default cap on processes in the table, zombies included. Each live
+/// process holds a window-sized memory snapshot on the kernel heap.
+pub const DEFAULT_MAX_PROCESSES: usize = 16;
+
+/// Largest cap `set_max_processes` accepts
+pub const PROCESS_HARD_LIMIT: usize = 64;
+
+/// PIDs wrap back to 1 after this, skipping ones still in use
+const PID_MAX: Pid = 32768;
+
+/// Number of scheduler priority levels; level 0 runs first
+pub const PRIORITY_LEVELS: usize = 3;
+
+/// Every this many scheduling picks, all processes return to level 0 so
+/// demoted batch jobs cannot starve
+const PRIORITY_BOOST_INTERVAL: usize = 64;
 
 /// Process ID type
 pub type Pid = usize;
@@ -10,6 +25,16 @@ pub type Pid = usize;
 /// Invalid/null PID
 pub const INVALID_PID: Pid = usize::MAX;
 
+/// The first process; it adopts orphans and reaps them
+pub const INIT_PID: Pid = 1;
+
+/// User and group IDs; stored as 16 bits in filesystem entries
+pub type Uid = u16;
+
+/// The superuser, exempt from permission checks. Processes the kernel
+/// starts run as root.
+pub const ROOT_UID: Uid = 0;
+
 /// Process states
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ProcessState {
@@ -19,12 +44,25 @@ pub enum ProcessState {
     Ready,
     /// Process is blocked waiting for I/O
     Blocked,
+    /// Process was suspended by a signal and waits for SIGCONT
+    Stopped,
     /// Process has exited
     Exited,
 }
 
+impl ProcessState {
+    /// One-letter state shown by `ps`
+    pub fn code(self) -> u8 {
+        match self {
+            ProcessState::Running | ProcessState::Ready => b'R',
+            ProcessState::Blocked => b'S',
+            ProcessState::Stopped => b'T',
+            ProcessState::Exited => b'Z',
+        }
+    }
+}
+
 /// Process control block
-#[derive(Clone)]
 pub struct Process {
     /// Process ID
     pub pid: Pid,
@@ -40,24 +78,63 @@ pub struct Process {
     pub stack_top: u64,
     /// Saved program counter
     pub pc: usize,
-    /// Saved stack pointer
+    /// User stack pointer as of its last trap
     pub sp: usize,
-    /// Saved registers (x1-x31, excluding x0 which is always 0)
-    pub regs: [usize; 31],
+    /// Kernel stack its traps run on; the user registers they save sit
+    /// at the top (see `UserContext`)
+    pub kernel_stack: Box<crate::process::KernelStack>,
     /// Program path (for debugging)
     pub path: String,
     /// Command-line arguments
     pub args: Vec<String>,
+    /// Environment it was started with, as `NAME=value` strings;
+    /// inherited by children spawned without one of their own
+    pub env: Vec<String>,
+    /// Canonical working directory that relative syscall paths resolve against
+    pub cwd: String,
     /// File descriptors for this process
     pub fd_table: crate::fd::FdTable,
-    /// Memory snapshot of the user window (stored when process is not running)
+    /// Memory snapshot of the user window; stale while the process is
+    /// resident in the window (see `ProcessTable::resident`)
     pub memory: Vec<u8>,
+    /// Window offset where the loaded image ends, heap included (the
+    /// program break); with `sp` this bounds what a switch has to copy
+    pub image_end: usize,
+    /// Window offset where the heap starts: the image end at load time,
+    /// which brk never moves below
+    pub heap_start: usize,
+    /// Image segments syscalls may not write into
+    pub read_only: Vec<Range<usize>>,
     /// Initial argc value (for newly spawned processes)
     pub argc: usize,
-    /// Initial argv pointer (for newly spawned processes)
+    /// Whether it has entered user mode yet
     pub started: bool,
     /// Initial argv pointer (for newly spawned processes)
     pub argv_ptr: usize,
+    /// Initial envp pointer (for newly spawned processes)
+    pub envp_ptr: usize,
+    /// Initial thread pointer: the TLS block, or 0
+    pub tp: usize,
+    /// Bitmask of signals raised but not yet delivered
+    pub pending_signals: u32,
+    /// Bitmask of signals set to SIG_IGN; spawn starts with none ignored
+    pub ignored_signals: u32,
+    /// Process group, used for job control and console signals
+    pub pgid: Pid,
+    /// Signal that stopped the process, until a waitpid reports it
+    pub unreported_stop: Option<usize>,
+    /// Scheduler level, 0 (highest) to PRIORITY_LEVELS - 1
+    pub priority: usize,
+    /// Resource limits other than RLIMIT_NOFILE (see `fd_table`)
+    pub rlimits: crate::rlimit::Rlimits,
+    /// Timer ticks spent running user code, for RLIMIT_CPU
+    pub cpu_ticks: u64,
+    /// Context switch and state time counters
+    pub sched: crate::scheduler::SchedStats,
+    /// User ID, checked by filesystem writes, kill and setuid
+    pub uid: Uid,
+    /// Group ID; recorded but not yet checked
+    pub gid: Uid,
 }
 
 impl Process {
@@ -69,6 +146,7 @@ impl Process {
         stack_top: u64,
         path: String,
         args: Vec<String>,
+        cwd: String,
         fd_table: crate::fd::FdTable,
         memory: Vec<u8>,
         argc: usize,
@@ -83,14 +161,31 @@ impl Process {
             stack_top,
             pc: entry as usize,
             sp: stack_top as usize,
-            regs: [0; 31],
+            kernel_stack: crate::process::KernelStack::alloc(),
             path,
             args,
+            env: Vec::new(),
+            cwd,
             fd_table,
             memory,
+            image_end: crate::process::USER_WINDOW_SIZE,
+            heap_start: crate::process::USER_WINDOW_SIZE,
+            read_only: Vec::new(),
             argc,
             argv_ptr,
+            envp_ptr: 0,
+            tp: 0,
             started: false,
+            pending_signals: 0,
+            ignored_signals: 0,
+            pgid: pid,
+            unreported_stop: None,
+            priority: 0,
+            rlimits: crate::rlimit::Rlimits::new(),
+            cpu_ticks: 0,
+            sched: crate::scheduler::SchedStats::new(),
+            uid: ROOT_UID,
+            gid: ROOT_UID,
         }
     }
 
@@ -100,6 +195,16 @@ impl Process {
         self.exit_code = code;
     }
 
+    /// Take the lowest-numbered pending signal
+    pub fn take_signal(&mut self) -> Option<usize> {
+        if self.pending_signals == 0 {
+            return None;
+        }
+        let sig = self.pending_signals.trailing_zeros() as usize;
+        self.pending_signals &= !(1 << sig);
+        Some(sig)
+    }
+
     /// Check if process is running
     pub fn is_running(&self) -> bool {
         self.state == ProcessState::Running
@@ -109,6 +214,11 @@ impl Process {
     pub fn has_exited(&self) -> bool {
         self.state == ProcessState::Exited
     }
+
+    /// Root bypasses permission checks
+    pub fn is_root(&self) -> bool {
+        self.uid == ROOT_UID
+    }
 }
 
 /// Global process table
@@ -116,34 +226,90 @@ pub static PROCESS_TABLE: Mutex<ProcessTable> = Mutex::new(ProcessTable::new());
 
 /// Process table managing all processes
 pub struct ProcessTable {
-    /// Array of processes
-    processes: [Option<Process>; MAX_PROCESSES],
+    /// Process slots; freed slots are reused before the table grows
+    processes: Vec<Option<Process>>,
+    /// Most processes the table will hold
+    max_processes: usize,
     /// Currently running process ID
     current_pid: Pid,
     /// Next PID to allocate
     next_pid: Pid,
+    /// Process group that owns each console (by `Port::index`) and
+    /// receives its Ctrl-C/Ctrl-Z
+    foreground: [Pid; crate::console::PORTS],
+    /// Process whose memory is in the user window. It is only copied out
+    /// when another process is switched in, so switching back to the
+    /// process that last ran copies nothing.
+    resident: Pid,
+    /// Ready processes per priority level, in run order. Entries can be
+    /// stale (the process blocked or exited since); picks skip those.
+    ready_queues: [VecDeque<Pid>; PRIORITY_LEVELS],
+    /// Picks since every process was last boosted to level 0
+    picks_since_boost: usize,
+    /// Scheduling counters summed over every process since boot
+    sched: crate::scheduler::SchedStats,
 }
 
 impl ProcessTable {
     /// Create a new empty process table
     pub const fn new() -> Self {
         Self {
-            processes: [const { None }; MAX_PROCESSES],
+            processes: Vec::new(),
+            max_processes: DEFAULT_MAX_PROCESSES,
             current_pid: INVALID_PID,
             next_pid: 1, // PID 0 is reserved for kernel
+            foreground: [INVALID_PID; crate::console::PORTS],
+            resident: INVALID_PID,
+            ready_queues: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            picks_since_boost: 0,
+            sched: crate::scheduler::SchedStats::new(),
         }
     }
 
-    /// Allocate a new PID
+    /// Allocate a new PID. PIDs are handed out in increasing order and
+    /// wrap at PID_MAX; a PID is not reused while a process (even an
+    /// unreaped zombie) has it or a process group is still named after it.
     fn alloc_pid(&mut self) -> Pid {
-        let pid = self.next_pid;
-        self.next_pid += 1;
-        pid
+        loop {
+            let pid = self.next_pid;
+            self.next_pid = if pid >= PID_MAX { 1 } else { pid + 1 };
+            let in_use = self
+                .processes
+                .iter()
+                .flatten()
+                .any(|p| p.pid == pid || p.pgid == pid);
+            if !in_use && !self.foreground.contains(&pid) {
+                return pid;
+            }
+        }
     }
 
-    /// Find a free process slot
+    /// Find a free process slot, growing the table up to its cap
     fn find_free_slot(&mut self) -> Option<usize> {
-        self.processes.iter().position(|p| p.is_none())
+        if let Some(slot) = self.processes.iter().position(|p| p.is_none()) {
+            return Some(slot);
+        }
+        if self.processes.len() >= self.max_processes {
+            return None;
+        }
+        self.processes.push(None);
+        Some(self.processes.len() - 1)
+    }
+
+    /// Most processes the table will hold
+    pub fn max_processes(&self) -> usize {
+        self.max_processes
+    }
+
+    /// Change the process cap; it cannot drop below the processes that
+    /// already exist
+    pub fn set_max_processes(&mut self, max: usize) -> Result<(), SpawnError> {
+        let live = self.processes.iter().flatten().count();
+        if max == 0 || max > PROCESS_HARD_LIMIT || max < live {
+            return Err(SpawnError::InvalidLimit);
+        }
+        self.max_processes = max;
+        Ok(())
     }
 
     /// Create a new process
@@ -158,23 +324,48 @@ impl ProcessTable {
         argc: usize,
         argv_ptr: usize,
     ) -> Result<Pid, SpawnError> {
+        let parent_pid = self.current_pid;
+        if let Some(parent) = self.current() {
+            let children = self.get_children(parent_pid).len();
+            if children >= parent.rlimits.nproc.soft {
+                return Err(SpawnError::ChildLimit);
+            }
+        }
         let slot = self.find_free_slot().ok_or(SpawnError::TooManyProcesses)?;
         let pid = self.alloc_pid();
-        let parent_pid = self.current_pid;
+        // Children start in their parent's working directory
+        let cwd = self
+            .current()
+            .map(|parent| parent.cwd.clone())
+            .unwrap_or_else(|| String::from("/"));
 
-        let process = Process::new(
+        let mut process = Process::new(
             pid,
             parent_pid,
             entry,
             stack_top,
             path,
             args,
+            cwd,
             fd_table,
             memory,
             argc,
             argv_ptr,
         );
+        // Children join their parent's process group and inherit its
+        // resource limits and credentials
+        if let Some(parent) = self.current() {
+            process.pgid = parent.pgid;
+            process.rlimits = parent.rlimits;
+            process.uid = parent.uid;
+            process.gid = parent.gid;
+        }
+        let uart = crate::console::Port::Uart.index();
+        if self.foreground[uart] == INVALID_PID {
+            self.foreground[uart] = process.pgid;
+        }
         self.processes[slot] = Some(process);
+        self.ready_queues[0].push_back(pid);
 
         Ok(pid)
     }
@@ -225,19 +416,258 @@ impl ProcessTable {
 
     /// Set the current running process
     pub fn set_current(&mut self, pid: Pid) {
+        if pid != self.current_pid
+            && let Some(process) = self.get_mut(pid)
+        {
+            process.sched.switches += 1;
+            self.sched.switches += 1;
+        }
         self.current_pid = pid;
     }
 
+    /// Charge the `cycles` a switch to `pid` took to it and the system
+    pub fn charge_switch(&mut self, pid: Pid, cycles: u64) {
+        if let Some(process) = self.get_mut(pid) {
+            process.sched.switch_cycles += cycles;
+        }
+        self.sched.switch_cycles += cycles;
+    }
+
+    /// System-wide scheduling counters; the per-state ticks are summed
+    /// over processes, except `running_ticks`, which counts ticks when
+    /// any process was running
+    pub fn sched_stats(&self) -> crate::scheduler::SchedStats {
+        self.sched
+    }
+
+    /// Sample one timer tick: charge it to the state each process is in
+    pub fn account_tick(&mut self) {
+        let mut any_running = false;
+        for process in self.processes.iter_mut().flatten() {
+            let stats = &mut process.sched;
+            match process.state {
+                ProcessState::Running => {
+                    stats.running_ticks += 1;
+                    any_running = true;
+                }
+                ProcessState::Ready => {
+                    stats.ready_ticks += 1;
+                    self.sched.ready_ticks += 1;
+                }
+                ProcessState::Blocked => {
+                    stats.blocked_ticks += 1;
+                    self.sched.blocked_ticks += 1;
+                }
+                ProcessState::Stopped => {}
+                ProcessState::Exited => continue,
+            }
+            stats.ticks += 1;
+        }
+        if any_running {
+            self.sched.running_ticks += 1;
+        }
+        self.sched.ticks += 1;
+    }
+
     /// Get the current process PID
     pub fn get_current_pid(&self) -> Pid {
         self.current_pid
     }
 
-    /// Mark a process as exited
+    /// Mark a process as exited; its children are handed to init
     pub fn exit_process(&mut self, pid: Pid, code: isize) {
         if let Some(process) = self.get_mut(pid) {
             process.fd_table.close_all();
             process.exit(code);
+            // A zombie never runs again; only its exit code is kept
+            process.memory = Vec::new();
+            let (pgid, parent_pid) = (process.pgid, process.parent_pid);
+            // An emptied foreground group hands the console back
+            if self.group_members(pgid).is_empty() {
+                let parent_pgid = self
+                    .get(parent_pid)
+                    .map(|parent| parent.pgid)
+                    .unwrap_or(INVALID_PID);
+                for foreground in &mut self.foreground {
+                    if *foreground == pgid {
+                        *foreground = parent_pgid;
+                    }
+                }
+            }
+            self.reparent_children(pid);
+        }
+    }
+
+    /// Give `pid`'s children to init, waking it if any are already
+    /// zombies. With no init left they become parentless and are never
+    /// waited for.
+    fn reparent_children(&mut self, pid: Pid) {
+        let init_alive = pid != INIT_PID && self.get(INIT_PID).is_some_and(|p| !p.has_exited());
+        let new_parent = if init_alive { INIT_PID } else { INVALID_PID };
+        let mut adopted_zombie = false;
+        for child in self.processes.iter_mut().flatten() {
+            if child.parent_pid == pid {
+                child.parent_pid = new_parent;
+                adopted_zombie |= child.has_exited();
+            }
+        }
+        if adopted_zombie {
+            self.wake(INIT_PID);
+        }
+        // Parentless zombies are freed at once
+        if !init_alive {
+            let orphans: Vec<usize> = (0..self.processes.len())
+                .filter(|&slot| {
+                    self.processes[slot]
+                        .as_ref()
+                        .is_some_and(|p| p.parent_pid == INVALID_PID && p.has_exited() && p.pid != pid)
+                })
+                .collect();
+            for slot in orphans {
+                self.remove_slot(slot);
+            }
+        }
+    }
+
+    /// Process group that currently owns console `port`
+    pub fn foreground(&self, port: crate::console::Port) -> Pid {
+        self.foreground[port.index()]
+    }
+
+    /// Give console `port` to process group `pgid`
+    pub fn set_foreground(&mut self, port: crate::console::Port, pgid: Pid) {
+        self.foreground[port.index()] = pgid;
+    }
+
+    /// Move `pid` into group `pgid`; false if there is no such process
+    pub fn set_pgid(&mut self, pid: Pid, pgid: Pid) -> bool {
+        match self.get_mut(pid) {
+            Some(process) if !process.has_exited() => {
+                process.pgid = pgid;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Live processes in group `pgid`
+    pub fn group_members(&self, pgid: Pid) -> Vec<Pid> {
+        self.processes
+            .iter()
+            .flatten()
+            .filter(|p| p.pgid == pgid && !p.has_exited())
+            .map(|p| p.pid)
+            .collect()
+    }
+
+    /// Suspend `pid` after signal `sig`; its parent can see the stop
+    /// through waitpid
+    pub fn stop_process(&mut self, pid: Pid, sig: usize) {
+        if let Some(process) = self.get_mut(pid) {
+            if !process.has_exited() {
+                process.state = ProcessState::Stopped;
+                process.unreported_stop = Some(sig);
+            }
+        }
+    }
+
+    /// Resume a stopped process
+    pub fn continue_process(&mut self, pid: Pid) {
+        if let Some(process) = self.get_mut(pid) {
+            if process.state == ProcessState::Stopped {
+                process.unreported_stop = None;
+                self.make_ready(pid);
+            }
+        }
+    }
+
+    /// Wake a blocked process. Sleeping marks it as interactive, so it
+    /// also moves up a priority level.
+    pub fn wake(&mut self, pid: Pid) {
+        if let Some(process) = self.get_mut(pid) {
+            if process.state == ProcessState::Blocked {
+                process.priority = process.priority.saturating_sub(1);
+                self.make_ready(pid);
+            }
+        }
+    }
+
+    /// Move a process that gave up the CPU while still runnable down a
+    /// priority level
+    pub fn demote(&mut self, pid: Pid) {
+        if let Some(process) = self.get_mut(pid) {
+            process.priority = (process.priority + 1).min(PRIORITY_LEVELS - 1);
+        }
+    }
+
+    /// Mark a live process Ready and queue it at the back of its level
+    pub fn make_ready(&mut self, pid: Pid) {
+        let Some(process) = self.get_mut(pid) else {
+            return;
+        };
+        if process.has_exited() {
+            return;
+        }
+        process.state = ProcessState::Ready;
+        let level = process.priority;
+        for queue in &mut self.ready_queues {
+            queue.retain(|&queued| queued != pid);
+        }
+        self.ready_queues[level].push_back(pid);
+    }
+
+    /// True if a process other than `pid` is waiting to run
+    pub fn has_ready_besides(&self, pid: Pid) -> bool {
+        self.ready_queues.iter().flatten().any(|&queued| {
+            queued != pid && self.get(queued).is_some_and(|p| p.state == ProcessState::Ready)
+        })
+    }
+
+    /// Dequeue the next process to run: the front of the highest
+    /// non-empty level. Stale entries are dropped on the way.
+    pub fn pick_next(&mut self) -> Option<Pid> {
+        self.picks_since_boost += 1;
+        if self.picks_since_boost >= PRIORITY_BOOST_INTERVAL {
+            self.boost_all();
+        }
+        for level in 0..PRIORITY_LEVELS {
+            while let Some(pid) = self.ready_queues[level].pop_front() {
+                let ready = self
+                    .get(pid)
+                    .is_some_and(|p| p.state == ProcessState::Ready && p.priority == level);
+                if ready {
+                    return Some(pid);
+                }
+            }
+        }
+        None
+    }
+
+    /// Return every process to level 0, keeping the queued run order
+    fn boost_all(&mut self) {
+        self.picks_since_boost = 0;
+        for process in self.processes.iter_mut().flatten() {
+            process.priority = 0;
+        }
+        for level in 1..PRIORITY_LEVELS {
+            let demoted = core::mem::take(&mut self.ready_queues[level]);
+            self.ready_queues[0].extend(demoted);
+        }
+    }
+
+    /// Mark `sig` pending on `pid`; false if there is no such live process
+    /// Ignored signals are dropped here but still count as sent; SIGKILL
+    /// and SIGSTOP cannot be ignored.
+    pub fn raise(&mut self, pid: Pid, sig: usize) -> bool {
+        match self.get_mut(pid) {
+            Some(process) if !process.has_exited() => {
+                let catchable = sig != crate::signal::SIGKILL && sig != crate::signal::SIGSTOP;
+                if !catchable || process.ignored_signals & (1 << sig) == 0 {
+                    process.pending_signals |= 1 << sig;
+                }
+                true
+            }
+            _ => false,
         }
     }
 
@@ -255,7 +685,7 @@ impl ProcessTable {
                     .iter()
                     .position(|p| p.as_ref().map(|pr| pr.pid) == Some(child_pid))
                 {
-                    self.processes[slot] = None;
+                    self.remove_slot(slot);
                 }
                 return Some((child_pid, exit_code));
             }
@@ -264,6 +694,47 @@ impl ProcessTable {
         None
     }
 
+    /// Reap an exited child of `parent_pid` (any child, or only `pid`), or
+    /// with `report_stopped` report a child that stopped since the last call
+    pub fn wait_for(
+        &mut self,
+        parent_pid: Pid,
+        pid: Option<Pid>,
+        report_stopped: bool,
+    ) -> Option<(Pid, WaitStatus)> {
+        let slot = self.processes.iter().position(|p| {
+            p.as_ref().is_some_and(|child| {
+                child.parent_pid == parent_pid
+                    && pid.is_none_or(|pid| child.pid == pid)
+                    && (child.has_exited() || (report_stopped && child.unreported_stop.is_some()))
+            })
+        })?;
+        let child = self.processes[slot].as_mut()?;
+        let child_pid = child.pid;
+        if child.has_exited() {
+            let code = child.exit_code;
+            self.remove_slot(slot);
+            return Some((child_pid, WaitStatus::Exited(code)));
+        }
+        let sig = child.unreported_stop.take()?;
+        Some((child_pid, WaitStatus::Stopped(sig)))
+    }
+
+    /// Free a reaped process's slot
+    fn remove_slot(&mut self, slot: usize) {
+        if let Some(process) = self.processes[slot].take() {
+            if self.resident == process.pid {
+                self.resident = INVALID_PID;
+            }
+            for queue in &mut self.ready_queues {
+                queue.retain(|&queued| queued != process.pid);
+            }
+        }
+        while matches!(self.processes.last(), Some(None)) {
+            self.processes.pop();
+        }
+    }
+
     /// Check if a process has any children
     pub fn has_children(&self, parent_pid: Pid) -> bool {
         self.processes
@@ -284,8 +755,13 @@ impl ProcessTable {
 
     /// Clean up all processes
     pub fn clear(&mut self) {
-        self.processes = [const { None }; MAX_PROCESSES];
+        self.processes.clear();
         self.current_pid = INVALID_PID;
+        self.foreground = [INVALID_PID; crate::console::PORTS];
+        self.resident = INVALID_PID;
+        for queue in &mut self.ready_queues {
+            queue.clear();
+        }
     }
 
     /// Get all processes (for scheduling)
@@ -293,90 +769,138 @@ impl ProcessTable {
         self.processes.iter().filter_map(|p| p.as_ref()).collect()
     }
 
-    /// Save the current process's memory from the user window
-    pub fn save_current_memory(&mut self) {
-        if self.current_pid == INVALID_PID {
-            return;
+    /// Fill `buf` with records for processes whose pid is at least
+    /// `*cookie`, in pid order, and advance the cookie past the last one.
+    /// Each record is `[pid u32][ppid u32][pgid u32][state][path_len]
+    /// [args_len][path][args]` with little-endian numbers, ppid 0 for none
+    /// and the args joined by spaces; both strings are cut to 255 bytes.
+    /// Returns 0 once every process has been listed.
+    pub fn read_procinfo(&self, cookie: &mut usize, buf: &mut [u8]) -> Option<usize> {
+        let mut listed: Vec<&Process> = self
+            .get_all_processes()
+            .into_iter()
+            .filter(|p| p.pid >= *cookie)
+            .collect();
+        listed.sort_by_key(|p| p.pid);
+
+        let mut written = 0;
+        for process in listed {
+            let args = process.args.join(" ");
+            let path = &process.path.as_bytes()[..process.path.len().min(255)];
+            let args = &args.as_bytes()[..args.len().min(255)];
+            let record_len = 15 + path.len() + args.len();
+            if written + record_len > buf.len() {
+                if written == 0 {
+                    return None;
+                }
+                break;
+            }
+            let ppid = if process.parent_pid == INVALID_PID {
+                0
+            } else {
+                process.parent_pid
+            };
+            let record = &mut buf[written..written + record_len];
+            record[0..4].copy_from_slice(&(process.pid as u32).to_le_bytes());
+            record[4..8].copy_from_slice(&(ppid as u32).to_le_bytes());
+            record[8..12].copy_from_slice(&(process.pgid as u32).to_le_bytes());
+            record[12] = process.state.code();
+            record[13] = path.len() as u8;
+            record[14] = args.len() as u8;
+            record[15..15 + path.len()].copy_from_slice(path);
+            record[15 + path.len()..].copy_from_slice(args);
+            written += record_len;
+            *cookie = process.pid + 1;
         }
-        if let Some(process) = self.get_mut(self.current_pid) {
-            process.memory.clear();
-            process.memory.resize(crate::process::USER_WINDOW_SIZE, 0);
-            crate::process::snapshot_user_window(&mut process.memory);
+        Some(written)
+    }
+
+    /// Copy the resident process's live ranges out of the user window.
+    /// Its saved `sp` must be current. Exited processes are dropped.
+    fn evict_resident(&mut self) {
+        let pid = core::mem::replace(&mut self.resident, INVALID_PID);
+        let Some(process) = self.get_mut(pid) else {
+            return;
+        };
+        if process.has_exited() {
+            return;
         }
+        process.memory.resize(crate::process::USER_WINDOW_SIZE, 0);
+        let ranges = crate::process::live_ranges(process.image_end, process.sp);
+        crate::process::snapshot_user_ranges(&mut process.memory, &ranges);
+        let copied = ranges.iter().map(|range| range.len()).sum::<usize>() as u64;
+        process.sched.window_bytes += copied;
+        self.sched.window_bytes += copied;
     }
 
-    /// Restore a process's memory into the user window
-    pub fn restore_process_memory(&self, pid: Pid) {
-        if let Some(process) = self.get(pid) {
+    /// Make the user window hold `pid`'s memory, evicting the resident
+    /// process first. Nothing is copied if `pid` is already resident.
+    pub fn restore_process_memory(&mut self, pid: Pid) {
+        if self.resident == pid {
+            return;
+        }
+        self.evict_resident();
+        if let Some(process) = self.get_mut(pid) {
+            let mut copied = 0;
             if !process.memory.is_empty() {
-                crate::process::restore_user_window(&process.memory);
+                let ranges = crate::process::live_ranges(process.image_end, process.sp);
+                crate::process::restore_user_ranges(&process.memory, &ranges);
+                copied = ranges.iter().map(|range| range.len()).sum::<usize>() as u64;
+                process.sched.window_bytes += copied;
             }
+            self.sched.window_bytes += copied;
+            self.resident = pid;
         }
     }
 
-    /// Save the current process's state
-    /// Saves PC and SP which are needed to resume execution
-    pub fn save_current_registers(&mut self, _trap_frame: &riscv_rt::TrapFrame) {
+    /// Save the current process's state. Its registers are already on
+    /// its kernel stack; only the pc and user sp need recording.
+    pub fn save_current_registers(&mut self) {
         if self.current_pid == INVALID_PID {
             return;
         }
         if let Some(process) = self.get_mut(self.current_pid) {
             // Save PC - this is where the process will resume
             process.pc = unsafe { riscv::register::sepc::read() };
-
-            // Save SP - it's stored in sscratch CSR
-            process.sp = unsafe {
-                let sp: usize;
-                core::arch::asm!(
-                    "csrr {0}, sscratch",
-                    out(reg) sp
-                );
-                sp
-            };
+            process.sp = unsafe { (*process.kernel_stack.context()).sp };
         }
     }
 
-    /// Restore a process's state to resume execution
-    /// This sets up the trap frame to return to the process
-    pub fn restore_process_registers(&mut self, pid: Pid, trap_frame: &mut riscv_rt::TrapFrame) {
+    /// Make the next return to user mode resume `pid`
+    pub fn restore_process_registers(&mut self, pid: Pid) {
         if let Some(process) = self.get_mut(pid) {
             // Restore PC - this is where we'll return to
             unsafe { riscv::register::sepc::write(process.pc) };
 
-            // Restore SP - it's stored in sscratch CSR
-            // The trap handler swaps SP with sscratch on entry/exit
-            unsafe {
-                core::arch::asm!(
-                    "csrw sscratch, {0}",
-                    in(reg) process.sp
-                );
-            }
-
-            // For newly spawned processes, initialize trap frame registers
-            // Set argc and argv for the process's _start function
+            let context = process.kernel_stack.context();
+            // Newly spawned processes start in _start(argc, argv, envp)
+            // with every other register zeroed
             if !process.started {
-                trap_frame.ra = 0;
-                trap_frame.a0 = process.argc;
-                trap_frame.a1 = process.argv_ptr;
-                trap_frame.a2 = 0;
-                trap_frame.a3 = 0;
-                trap_frame.a4 = 0;
-                trap_frame.a5 = 0;
-                trap_frame.a6 = 0;
-                trap_frame.a7 = 0;
-                trap_frame.t0 = 0;
-                trap_frame.t1 = 0;
-                trap_frame.t2 = 0;
-                trap_frame.t3 = 0;
-                trap_frame.t4 = 0;
-                trap_frame.t5 = 0;
-                trap_frame.t6 = 0;
+                unsafe {
+                    *context = crate::process::UserContext::initial(
+                        process.sp,
+                        process.argc,
+                        process.argv_ptr,
+                        process.envp_ptr,
+                        process.tp,
+                    );
+                }
                 process.started = true;
             }
+            crate::process::set_trap_context(context);
         }
     }
 }
 
+/// Child state change reported by `wait_for`
+#[derive(Debug, Clone, Copy)]
+pub enum WaitStatus {
+    /// Child exited with this code
+    Exited(isize),
+    /// Child was stopped by this signal
+    Stopped(usize),
+}
+
 /// Errors that can occur during process spawning
 #[derive(Debug, Clone, Copy)]
 pub enum SpawnError {
@@ -386,6 +910,12 @@ pub enum SpawnError {
     ProgramNotFound,
     /// Failed to load program
     LoadFailed,
+    /// The program is not a RISC-V executable
+    NotExecutable,
     /// Out of memory
     OutOfMemory,
+    /// Process cap out of range
+    InvalidLimit,
+    /// The parent already has RLIMIT_NPROC children
+    ChildLimit,
 }
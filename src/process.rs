@@ -1,25 +1,208 @@
-use alloc::{format, vec, vec::Vec};
+use alloc::{alloc::alloc_zeroed, boxed::Box, string::String, vec::Vec};
+use core::alloc::Layout;
+use core::ops::Range;
 use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use riscv::register::sstatus::{self, SPP};
 use riscv_rt::TrapFrame;
 
-use crate::{elf::ElfFile, fs, uart};
+use crate::{
+    elf::{ElfError, ElfFile, Relocation, Symbol},
+    fs, klog,
+};
 
-const USER_IMAGE_BASE: u64 = 0x8040_0000;
-const USER_IMAGE_LIMIT: u64 = USER_IMAGE_BASE + 0x0002_0000; // 128 KiB window
+// USER_WINDOW_KIB: set with CRABV6_USER_WINDOW_KIB at build time
+include!(concat!(env!("OUT_DIR"), "/user_window.rs"));
+
+// Just past the end of the kernel's RAM region in memory.x, so nothing
+// the kernel links (its heap included) can land in the window
+const USER_IMAGE_BASE: u64 = 0x8120_0000;
+const USER_IMAGE_LIMIT: u64 = USER_IMAGE_BASE + USER_WINDOW_SIZE as u64;
 const USER_STACK_SIZE: usize = 8 * 1024;
-pub const USER_WINDOW_SIZE: usize = (USER_IMAGE_LIMIT - USER_IMAGE_BASE) as usize;
+pub const USER_WINDOW_SIZE: usize = USER_WINDOW_KIB * 1024;
+/// Window offset the heap may grow to; the rest is left for the stack
+pub const HEAP_LIMIT: usize = USER_WINDOW_SIZE - USER_STACK_SIZE;
+
+/// Environment the kernel gives the first user process
+pub const DEFAULT_ENV: &[&str] = &["PATH=/bin", "HOME=/"];
+
+/// Bytes of a script read for its `#!` line, as Linux's BINPRM_BUF_SIZE
+const SHEBANG_MAX: usize = 256;
+
+/// Most arguments a process can be started with
+pub const MAX_ARGS: usize = 16;
 
+/// Most environment variables a process can be started with
+pub const MAX_ENV_VARS: usize = 32;
+
+// Auxiliary vector entry types, as on Linux
+const AT_NULL: usize = 0;
+const AT_PAGESZ: usize = 6;
+const AT_ENTRY: usize = 9;
+const AT_RANDOM: usize = 25;
+/// Words in the auxiliary vector: four (type, value) pairs
+const AUXV_WORDS: usize = 8;
+const PAGE_SIZE: usize = 4096;
+
+/// Size of each process's kernel stack, which its traps run on
+pub const KERNEL_STACK_SIZE: usize = 16 * 1024;
+
+/// Boot stack to come back to once user mode is left for good
 #[unsafe(no_mangle)]
 static mut KERNEL_STACK_POINTER: usize = 0;
 #[unsafe(no_mangle)]
 static mut KERNEL_RETURN_ADDRESS: usize = 0;
 static mut USER_SNAPSHOT: [u8; USER_WINDOW_SIZE] = [0; USER_WINDOW_SIZE];
 
+/// Saved user registers of the process the next return to user mode
+/// resumes. `_start_trap` points this at the context it just saved and
+/// reloads it after the handler, so switching processes only has to
+/// repoint it.
+#[unsafe(no_mangle)]
+static mut CURRENT_TRAP_FRAME: *mut UserContext = ptr::null_mut();
+
+/// Kernel registers of the syscall parked in `park_in_kernel`; only one
+/// process is ever parked at a time (see `Scheduler::park`)
+#[unsafe(no_mangle)]
+static mut PARKED_CONTEXT: KernelContext = KernelContext {
+    ra: 0,
+    sp: 0,
+    s: [0; 12],
+};
+/// Nonzero to make the next trap return jump back into `PARKED_CONTEXT`
+/// instead of user mode
+#[unsafe(no_mangle)]
+static mut RESUME_PARKED: usize = 0;
+
+/// Kernel stack for programs the kernel shell runs without a process
+static mut LOADER_STACK: KernelStack = KernelStack([0; KERNEL_STACK_SIZE]);
+
+/// User registers saved by `_start_trap`, at the top of the kernel stack
+/// of the process that trapped. The first 16 words are laid out like
+/// riscv-rt's `TrapFrame`, which is the view trap handlers get.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct UserContext {
+    pub ra: usize,
+    pub t0: usize,
+    pub t1: usize,
+    pub t2: usize,
+    pub t3: usize,
+    pub t4: usize,
+    pub t5: usize,
+    pub t6: usize,
+    pub a0: usize,
+    pub a1: usize,
+    pub a2: usize,
+    pub a3: usize,
+    pub a4: usize,
+    pub a5: usize,
+    pub a6: usize,
+    pub a7: usize,
+    /// s0-s11
+    pub s: [usize; 12],
+    pub gp: usize,
+    pub tp: usize,
+    pub sp: usize,
+    _pad: usize,
+}
+
+impl UserContext {
+    /// Registers for a process about to enter `_start(argc, argv, envp)`
+    /// with thread pointer `tp`
+    pub fn initial(sp: usize, argc: usize, argv_ptr: usize, envp_ptr: usize, tp: usize) -> Self {
+        Self {
+            sp,
+            tp,
+            a0: argc,
+            a1: argv_ptr,
+            a2: envp_ptr,
+            ..Self::default()
+        }
+    }
+}
+
+/// Callee-saved registers of a syscall that gave up the CPU partway
+#[repr(C)]
+struct KernelContext {
+    ra: usize,
+    sp: usize,
+    /// s0-s11
+    s: [usize; 12],
+}
+
+/// A kernel stack; the process's `UserContext` lives at its top
+#[repr(C, align(16))]
+pub struct KernelStack([u8; KERNEL_STACK_SIZE]);
+
+impl KernelStack {
+    /// Allocate a zeroed stack straight on the heap; building it on the
+    /// caller's stack first would overflow a kernel stack
+    pub fn alloc() -> Box<Self> {
+        let layout = Layout::new::<Self>();
+        unsafe {
+            let raw = alloc_zeroed(layout) as *mut Self;
+            if raw.is_null() {
+                alloc::alloc::handle_alloc_error(layout);
+            }
+            Box::from_raw(raw)
+        }
+    }
+
+    /// The saved user registers
+    pub fn context(&mut self) -> *mut UserContext {
+        let top = self.0.as_mut_ptr_range().end;
+        unsafe { (top as *mut UserContext).sub(1) }
+    }
+
+    /// Whether the code calling this runs on this stack
+    pub fn is_running_on(&self) -> bool {
+        let sp: usize;
+        unsafe { core::arch::asm!("mv {}, sp", out(reg) sp) };
+        self.0.as_ptr_range().contains(&(sp as *const u8))
+    }
+}
+
+/// Make the next return to user mode resume `context`
+pub fn set_trap_context(context: *mut UserContext) {
+    unsafe { CURRENT_TRAP_FRAME = context };
+}
+
+/// Leave the syscall in progress through the trap return path, which
+/// resumes whatever process `set_trap_context` last picked, and come back
+/// here once `resume_parked` has been called and the next trap returns.
+/// Everything the syscall keeps on its kernel stack survives the wait.
+pub fn park_in_kernel() {
+    unsafe { park_kernel_context(ptr::addr_of_mut!(PARKED_CONTEXT)) };
+}
+
+/// Make the next trap return continue the parked syscall
+pub fn resume_parked() {
+    unsafe { RESUME_PARKED = 1 };
+}
+
+/// Whether `len` bytes at `addr` overlap the user window, whose contents
+/// change whenever another process is switched in
+pub fn in_user_window(addr: usize, len: usize) -> bool {
+    addr < USER_IMAGE_LIMIT as usize && addr + len > USER_IMAGE_BASE as usize
+}
+
+/// Whether all `len` bytes at `addr` lie inside the user window
+pub fn inside_user_window(addr: usize, len: usize) -> bool {
+    addr >= USER_IMAGE_BASE as usize
+        && addr
+            .checked_add(len)
+            .is_some_and(|end| end <= USER_IMAGE_LIMIT as usize)
+}
+
+/// Bytes moved by the ranged window copies, to measure switch cost
+pub static WINDOW_BYTES_COPIED: AtomicUsize = AtomicUsize::new(0);
+
 unsafe extern "C" {
-    fn enter_user_trampoline(entry: usize, stack_top: usize, argc: usize, argv: usize) -> isize;
+    fn enter_user_trampoline(context: *mut UserContext) -> isize;
     fn kernel_resume_from_user();
+    fn park_kernel_context(context: *mut KernelContext);
 }
 
 #[derive(Debug)]
@@ -27,25 +210,75 @@ pub enum LoadError {
     Fs(crate::fs::FsError),
     Elf(crate::elf::ElfError),
     OutOfMemory,
+    /// A `#!` line that is too long, not UTF-8 or names no interpreter
+    BadInterpreter,
+}
+
+impl From<ElfError> for LoadError {
+    fn from(err: ElfError) -> Self {
+        LoadError::Elf(err)
+    }
 }
 
+/// A parsed executable. Segment contents stay in the file until
+/// `load_into_user_window` streams them to their destinations.
 #[derive(Debug)]
 pub struct LoadedProgram {
     pub entry: u64,
     pub stack_top: u64,
     pub segments: Vec<SegmentImage>,
+    /// Words to patch after loading, already moved to load addresses
+    pub relocations: Vec<Relocation>,
+    pub tls: Option<TlsImage>,
+    pub file: fs::FileHandle,
 }
 
 #[derive(Debug)]
 pub struct SegmentImage {
     pub dest: *mut u8,
-    pub data: Vec<u8>,
+    /// Bytes in memory; those past `file_size` are zeroed (bss)
+    pub mem_size: usize,
+    pub file_offset: usize,
+    pub file_size: usize,
     pub flags: u32,
 }
 
+/// The process's thread-local storage block, placed after the image. The
+/// thread pointer holds `dest`: on RISC-V the block starts right at tp.
+#[derive(Debug)]
+pub struct TlsImage {
+    pub dest: *mut u8,
+    /// Initial values (.tdata) in the loaded image
+    pub template: *const u8,
+    pub template_size: usize,
+    /// Bytes in the block; those past the template are zeroed (.tbss)
+    pub mem_size: usize,
+}
+
+/// Fill `buf` from `offset` in `file`; a short file is a truncated image
+fn read_exact_at(
+    file: &mut fs::FileHandle,
+    offset: usize,
+    buf: &mut [u8],
+) -> Result<(), LoadError> {
+    let mut done = 0;
+    while done < buf.len() {
+        let read = fs::read_at(file, offset + done, &mut buf[done..]).map_err(LoadError::Fs)?;
+        if read == 0 {
+            return Err(LoadError::Elf(ElfError::Truncated));
+        }
+        done += read;
+    }
+    Ok(())
+}
+
+/// Parse the headers of `path` and check where its segments go. Only the
+/// headers and relocation table are read here.
 pub fn load(path: &str) -> Result<LoadedProgram, LoadError> {
-    let bytes = fs::read_file(path).map_err(LoadError::Fs)?;
-    let elf = ElfFile::parse(&bytes).map_err(LoadError::Elf)?;
+    let mut file = fs::open(path).map_err(LoadError::Fs)?;
+    let elf = ElfFile::read(file.size(), |offset, buf| {
+        read_exact_at(&mut file, offset, buf)
+    })?;
 
     let base_vaddr = elf
         .segments
@@ -56,27 +289,73 @@ pub fn load(path: &str) -> Result<LoadedProgram, LoadError> {
 
     let mut segments = Vec::new();
     for seg in &elf.segments {
-        let mut data = vec![0u8; seg.mem_size as usize];
-        if seg.file_size > 0 {
-            let start = seg.file_offset as usize;
-            let end = start + seg.file_size as usize;
-            data[..seg.file_size as usize].copy_from_slice(&elf.data[start..end]);
-        }
-
-        let offset = seg.vaddr.saturating_sub(base_vaddr);
-        let dest_addr = USER_IMAGE_BASE + offset;
-        let dest_end = dest_addr + data.len() as u64;
+        let offset = seg.vaddr - base_vaddr;
+        let dest_addr = USER_IMAGE_BASE.saturating_add(offset);
+        let dest_end = dest_addr.saturating_add(seg.mem_size);
         if dest_end > USER_IMAGE_LIMIT {
             return Err(LoadError::OutOfMemory);
         }
+        if dest_end > USER_IMAGE_LIMIT - USER_STACK_SIZE as u64 {
+            return Err(LoadError::Elf(ElfError::OverlapsStack));
+        }
 
+        // ElfFile::read checked the file range
         segments.push(SegmentImage {
             dest: dest_addr as *mut u8,
-            data,
+            mem_size: seg.mem_size as usize,
+            file_offset: seg.file_offset as usize,
+            file_size: seg.file_size as usize,
             flags: seg.flags,
         });
     }
 
+    // Each segment moved by the same amount; a PIE's absolute addresses
+    // move with it. Fixed-address binaries are linked at USER_IMAGE_BASE
+    // and have no relocations.
+    let bias = USER_IMAGE_BASE.wrapping_sub(base_vaddr);
+    let mut relocations = Vec::with_capacity(elf.relocations.len());
+    for reloc in &elf.relocations {
+        let target = reloc.offset.wrapping_add(bias);
+        let in_image = segments.iter().any(|seg| {
+            target
+                .checked_sub(seg.dest as u64)
+                .and_then(|start| start.checked_add(8))
+                .is_some_and(|end| end <= seg.mem_size as u64)
+        });
+        if !in_image {
+            return Err(LoadError::Elf(ElfError::RelocationOutOfRange));
+        }
+        relocations.push(Relocation {
+            offset: target,
+            addend: reloc.addend.wrapping_add(bias),
+        });
+    }
+
+    // The block goes after the last segment, so it is part of what a
+    // context switch keeps
+    let tls = match elf.tls {
+        Some(tls) => {
+            let image_end = segments
+                .iter()
+                .map(|seg| seg.dest as u64 + seg.mem_size as u64)
+                .max()
+                .unwrap_or(USER_IMAGE_BASE);
+            let dest = image_end.next_multiple_of(tls.align);
+            let end = dest.saturating_add(tls.mem_size);
+            if end > USER_IMAGE_LIMIT - USER_STACK_SIZE as u64 {
+                return Err(LoadError::Elf(ElfError::OverlapsStack));
+            }
+            // ElfFile::read checked the template lies in a segment
+            Some(TlsImage {
+                dest: dest as *mut u8,
+                template: (tls.vaddr - base_vaddr + USER_IMAGE_BASE) as *const u8,
+                template_size: tls.file_size as usize,
+                mem_size: tls.mem_size as usize,
+            })
+        }
+        None => None,
+    };
+
     let entry = USER_IMAGE_BASE + elf.entry.saturating_sub(base_vaddr);
     let stack_top = USER_IMAGE_LIMIT;
 
@@ -84,50 +363,188 @@ pub fn load(path: &str) -> Result<LoadedProgram, LoadError> {
         entry,
         stack_top,
         segments,
+        relocations,
+        tls,
+        file,
     })
 }
 
+/// The interpreter a script's `#!` line names, with the one optional
+/// argument after it; None if `path` does not start with `#!`.
+pub fn interpreter(path: &str) -> Result<Option<(String, Option<String>)>, LoadError> {
+    let mut file = fs::open(path).map_err(LoadError::Fs)?;
+    let mut buf = [0u8; SHEBANG_MAX];
+    let len = fs::read_at(&mut file, 0, &mut buf).map_err(LoadError::Fs)?;
+    let Some(rest) = buf[..len].strip_prefix(b"#!") else {
+        return Ok(None);
+    };
+    let line = match rest.iter().position(|&byte| byte == b'\n') {
+        Some(end) => &rest[..end],
+        None if len < SHEBANG_MAX => rest,
+        None => return Err(LoadError::BadInterpreter),
+    };
+    let line = core::str::from_utf8(line).map_err(|_| LoadError::BadInterpreter)?;
+    // Everything after the interpreter is one argument, as on Linux
+    let line = line.trim();
+    let (name, arg) = match line.split_once([' ', '\t']) {
+        Some((name, arg)) => (name, Some(String::from(arg.trim()))),
+        None => (line, None),
+    };
+    if name.is_empty() {
+        return Err(LoadError::BadInterpreter);
+    }
+    Ok(Some((String::from(name), arg)))
+}
+
+/// The function `pc` falls in, from the symbol table of the binary at
+/// `path`. None if the binary was stripped or can no longer be read; one
+/// replaced since it was loaded gives a wrong answer.
+pub fn symbolize(path: &str, pc: usize) -> Option<Symbol> {
+    let mut file = fs::open(path).ok()?;
+    let elf = ElfFile::read(file.size(), |offset, buf| {
+        read_exact_at(&mut file, offset, buf)
+    })
+    .ok()?;
+    // Back to the link-time address the symbols use
+    let base_vaddr = elf.segments.iter().map(|seg| seg.vaddr).min()?;
+    let addr = (pc as u64)
+        .checked_sub(USER_IMAGE_BASE)?
+        .wrapping_add(base_vaddr);
+    crate::elf::symbolize(
+        file.size(),
+        |offset, buf| read_exact_at(&mut file, offset, buf),
+        addr,
+    )
+    .ok()?
+}
+
 pub fn dump(program: &LoadedProgram) {
-    uart::write_str("Loaded program:\n");
-    uart::write_str(&format!(" entry: 0x{:x}\n", program.entry));
+    klog!(Debug, "Loaded program:");
+    klog!(Debug, " entry: 0x{:x}", program.entry);
     for seg in &program.segments {
-        uart::write_str(&format!(
-            "  segment @0x{:x}, {} bytes (flags 0x{:x})\n",
+        klog!(
+            Debug,
+            "  segment @0x{:x}, {} bytes (flags 0x{:x})",
             seg.dest as usize,
-            seg.data.len(),
+            seg.mem_size,
             seg.flags
-        ));
+        );
     }
 }
 
-/// Load program segments directly into the user window.
+/// Window offset just past the last loaded segment (data and bss) or
+/// the TLS block after them.
+pub fn image_end(program: &LoadedProgram) -> usize {
+    let tls = program
+        .tls
+        .as_ref()
+        .map(|tls| tls.dest as usize + tls.mem_size - USER_IMAGE_BASE as usize);
+    program
+        .segments
+        .iter()
+        .map(|seg| seg.dest as usize + seg.mem_size - USER_IMAGE_BASE as usize)
+        .chain(tls)
+        .max()
+        .unwrap_or(0)
+}
+
+/// User address of window offset `offset`
+pub fn user_address(offset: usize) -> usize {
+    USER_IMAGE_BASE as usize + offset
+}
+
+/// Thread pointer a process starts with: its TLS block, or 0 without one
+pub fn thread_pointer(program: &LoadedProgram) -> usize {
+    program.tls.as_ref().map_or(0, |tls| tls.dest as usize)
+}
+
+/// Addresses of the segments loaded without write permission. Until an
+/// MMU or PMP can map them read-only only the kernel honours this, by
+/// refusing to write there on the program's behalf.
+pub fn read_only_ranges(program: &LoadedProgram) -> Vec<Range<usize>> {
+    program
+        .segments
+        .iter()
+        .filter(|seg| seg.flags & crate::elf::PF_W == 0)
+        .map(|seg| seg.dest as usize..seg.dest as usize + seg.mem_size)
+        .collect()
+}
+
+/// Window offsets that can hold live data for a process whose image ends
+/// at `image_end` and whose stack pointer is `sp`. Below `sp` the stack is
+/// dead (there is no red zone) and the heap ends at `image_end`, so the
+/// gap between the two ranges never needs copying.
+pub fn live_ranges(image_end: usize, sp: usize) -> [Range<usize>; 2] {
+    let stack = sp.saturating_sub(USER_IMAGE_BASE as usize).min(USER_WINDOW_SIZE);
+    let image = image_end.min(USER_WINDOW_SIZE);
+    if stack <= image {
+        // Stack grew into the image; keep the whole window
+        return [0..USER_WINDOW_SIZE, 0..0];
+    }
+    [0..image, stack..USER_WINDOW_SIZE]
+}
+
+/// Stream each segment from the file into the user window, zero its bss
+/// and apply the relocations. On error the window holds a partial image.
 pub fn load_into_user_window(program: &LoadedProgram) -> Result<(), LoadError> {
+    let mut file = program.file.clone();
     for seg in &program.segments {
-        let dest = seg.dest as usize;
-        let offset = dest.saturating_sub(USER_IMAGE_BASE as usize);
-        let end = offset + seg.data.len();
-        if end > USER_WINDOW_SIZE {
+        let offset = (seg.dest as usize).saturating_sub(USER_IMAGE_BASE as usize);
+        if offset + seg.mem_size > USER_WINDOW_SIZE {
             return Err(LoadError::OutOfMemory);
         }
+        let dest = unsafe { core::slice::from_raw_parts_mut(seg.dest, seg.mem_size) };
+        let (data, bss) = dest.split_at_mut(seg.file_size);
+        read_exact_at(&mut file, seg.file_offset, data)?;
+        bss.fill(0);
+    }
+    for reloc in &program.relocations {
+        unsafe { ptr::write_unaligned(reloc.offset as *mut u64, reloc.addend) };
+    }
+    // Copied after relocation so pointers in .tdata are rebased too
+    if let Some(tls) = &program.tls {
         unsafe {
-            ptr::copy_nonoverlapping(
-                seg.data.as_ptr(),
-                (USER_IMAGE_BASE as usize + offset) as *mut u8,
-                seg.data.len(),
+            ptr::copy_nonoverlapping(tls.template, tls.dest, tls.template_size);
+            ptr::write_bytes(
+                tls.dest.add(tls.template_size),
+                0,
+                tls.mem_size - tls.template_size,
             );
         }
     }
     Ok(())
 }
 
-/// Build the user stack in place inside the user window.
-pub fn build_user_stack(args: &[&str]) -> Result<(usize, usize, usize), LoadError> {
+/// Build the user stack in place inside the user window. From `sp` up it
+/// holds argc, the argv pointers, a null, the envp pointers, a null and
+/// the auxiliary vector (AT_PAGESZ, AT_ENTRY for `entry`, AT_RANDOM and
+/// AT_NULL), as on Linux. Returns `(sp, argc, argv_ptr, envp_ptr)`.
+pub fn build_user_stack(
+    args: &[&str],
+    env: &[&str],
+    entry: u64,
+) -> Result<(usize, usize, usize, usize), LoadError> {
     let mut sp = USER_IMAGE_LIMIT as usize;
     let argc = args.len();
-    debug_assert!(argc <= 16, "too many arguments (max 16)");
-    let mut arg_ptrs: [usize; 16] = [0; 16];
+    debug_assert!(argc <= MAX_ARGS, "too many arguments (max 16)");
+    debug_assert!(env.len() <= MAX_ENV_VARS, "too many environment variables");
+    let mut arg_ptrs: [usize; MAX_ARGS] = [0; MAX_ARGS];
+    let mut env_ptrs: [usize; MAX_ENV_VARS] = [0; MAX_ENV_VARS];
+
+    klog!(Debug, "[build_user_stack] argc={}", argc);
 
-    uart::write_str(&format!("[build_user_stack] argc={}\n", argc));
+    for (index, &var) in env.iter().enumerate().rev() {
+        let bytes = var.as_bytes();
+        sp = sp.saturating_sub(bytes.len() + 1);
+        if sp < USER_IMAGE_BASE as usize {
+            return Err(LoadError::OutOfMemory);
+        }
+        unsafe {
+            copy_to_user(sp as *mut u8, bytes.as_ptr(), bytes.len());
+            write_byte_to_user((sp + bytes.len()) as *mut u8, 0);
+        }
+        env_ptrs[index] = sp;
+    }
 
     for (index, &arg) in args.iter().enumerate().rev() {
         let bytes = arg.as_bytes();
@@ -140,50 +557,92 @@ pub fn build_user_stack(args: &[&str]) -> Result<(usize, usize, usize), LoadErro
             write_byte_to_user((sp + bytes.len()) as *mut u8, 0);
         }
         arg_ptrs[index] = sp;
-        uart::write_str(&format!("[build_user_stack] arg[{}]='{}' at 0x{:x}\n", index, arg, sp));
+        klog!(Debug, "[build_user_stack] arg[{}]='{}' at 0x{:x}", index, arg, sp);
     }
 
+    // AT_RANDOM points at 16 bytes for stack protectors and hash seeds
+    let mut random = [0u8; 16];
+    crate::random::fill(&mut random);
+    sp = sp.saturating_sub(random.len());
+    if sp < USER_IMAGE_BASE as usize {
+        return Err(LoadError::OutOfMemory);
+    }
+    unsafe { copy_to_user(sp as *mut u8, random.as_ptr(), random.len()) };
+    let random_ptr = sp;
+
     sp &= !(core::mem::size_of::<usize>() * 2 - 1);
 
-    let pointer_pushes = argc + 2;
+    let pointer_pushes = argc + env.len() + 3 + AUXV_WORDS;
     if pointer_pushes & 1 != 0 {
         sp = sp.saturating_sub(core::mem::size_of::<usize>());
         unsafe { write_usize_to_user(sp as *mut usize, 0) };
     }
 
+    let auxv = [
+        AT_PAGESZ,
+        PAGE_SIZE,
+        AT_ENTRY,
+        entry as usize,
+        AT_RANDOM,
+        random_ptr,
+        AT_NULL,
+        0,
+    ];
+    for &word in auxv.iter().rev() {
+        sp = sp.saturating_sub(core::mem::size_of::<usize>());
+        unsafe { write_usize_to_user(sp as *mut usize, word) };
+    }
+
+    sp = sp.saturating_sub(core::mem::size_of::<usize>());
+    unsafe { write_usize_to_user(sp as *mut usize, 0) };
+    for &ptr in env_ptrs[..env.len()].iter().rev() {
+        sp = sp.saturating_sub(core::mem::size_of::<usize>());
+        unsafe { write_usize_to_user(sp as *mut usize, ptr) };
+    }
+    let envp_ptr = sp;
+
     sp = sp.saturating_sub(core::mem::size_of::<usize>());
     unsafe { write_usize_to_user(sp as *mut usize, 0) };
 
-    uart::write_str(&format!("[build_user_stack] writing argv array at sp=0x{:x}\n", sp));
+    klog!(Debug, "[build_user_stack] writing argv array at sp=0x{:x}", sp);
     for (i, &ptr) in arg_ptrs[..argc].iter().rev().enumerate() {
         sp = sp.saturating_sub(core::mem::size_of::<usize>());
         unsafe { write_usize_to_user(sp as *mut usize, ptr) };
-        uart::write_str(&format!("[build_user_stack] argv[{}]=0x{:x} written at sp=0x{:x}\n", argc - 1 - i, ptr, sp));
+        klog!(Debug, "[build_user_stack] argv[{}]=0x{:x} written at sp=0x{:x}", argc - 1 - i, ptr, sp);
     }
     let argv_ptr = sp;
 
     sp = sp.saturating_sub(core::mem::size_of::<usize>());
     unsafe { write_usize_to_user(sp as *mut usize, argc) };
 
-    uart::write_str(&format!("[build_user_stack] returning sp=0x{:x}, argc={}, argv_ptr=0x{:x}\n", sp, argc, argv_ptr));
+    klog!(Debug, "[build_user_stack] returning sp=0x{:x}, argc={}, argv_ptr=0x{:x}", sp, argc, argv_ptr);
 
-    Ok((sp, argc, argv_ptr))
+    Ok((sp, argc, argv_ptr, envp_ptr))
 }
 
 /// Compatibility helper: load a program and enter user mode immediately.
 pub unsafe fn enter_user(program: &LoadedProgram, args: &[&str]) -> isize {
     load_into_user_window(program).expect("load_into_user_window failed");
-    let (sp, argc, argv_ptr) = build_user_stack(args).expect("build_user_stack failed");
-    unsafe { enter_user_at(program.entry as usize, sp, argc, argv_ptr) }
+    let (sp, argc, argv_ptr, envp_ptr) =
+        build_user_stack(args, DEFAULT_ENV, program.entry).expect("build_user_stack failed");
+    unsafe {
+        let context = (*ptr::addr_of_mut!(LOADER_STACK)).context();
+        *context = UserContext::initial(sp, argc, argv_ptr, envp_ptr, thread_pointer(program));
+        riscv::register::sepc::write(program.entry as usize);
+        enter_user_at(context)
+    }
 }
 
-/// Enter user mode using a pre-built memory image already loaded into the user window.
-pub unsafe fn enter_user_at(entry: usize, sp: usize, argc: usize, argv_ptr: usize) -> isize {
+/// Enter user mode with the registers in `context`, which must sit at
+/// the top of a kernel stack, at the pc already in sepc. The image must
+/// already be loaded into the user window.
+pub unsafe fn enter_user_at(context: *mut UserContext) -> isize {
     unsafe {
         sstatus::set_spp(SPP::User);
         sstatus::set_spie();
+        set_trap_context(context);
+        enter_user_trampoline(context)
     }
-    unsafe { enter_user_trampoline(entry, sp, argc, argv_ptr) }
 }
 
 /// Copy the live user window into the provided buffer.
@@ -208,6 +667,36 @@ pub fn restore_user_window(buf: &[u8]) {
     }
 }
 
+/// Copy `ranges` of the live user window into the same offsets of `buf`.
+pub fn snapshot_user_ranges(buf: &mut [u8], ranges: &[Range<usize>]) {
+    for range in ranges {
+        let len = range.len();
+        unsafe {
+            ptr::copy_nonoverlapping(
+                (USER_IMAGE_BASE as usize + range.start) as *const u8,
+                buf[range.clone()].as_mut_ptr(),
+                len,
+            );
+        }
+        WINDOW_BYTES_COPIED.fetch_add(len, Ordering::Relaxed);
+    }
+}
+
+/// Copy `ranges` of `buf` back into the same offsets of the user window.
+pub fn restore_user_ranges(buf: &[u8], ranges: &[Range<usize>]) {
+    for range in ranges {
+        let len = range.len();
+        unsafe {
+            ptr::copy_nonoverlapping(
+                buf[range.clone()].as_ptr(),
+                (USER_IMAGE_BASE as usize + range.start) as *mut u8,
+                len,
+            );
+        }
+        WINDOW_BYTES_COPIED.fetch_add(len, Ordering::Relaxed);
+    }
+}
+
 /// Snapshot the user window into a static buffer.
 pub fn snapshot_user_window_static() {
     unsafe {
@@ -0,0 +1,317 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use spin::Mutex;
+
+use crate::fd::FdError;
+use crate::proc::Pid;
+use crate::scheduler::Scheduler;
+
+/// Bytes buffered in each direction before writers see WouldBlock
+const PTY_BUF_SIZE: usize = 4096;
+
+const CTRL_D: u8 = 0x04;
+const BACKSPACE: u8 = 0x08;
+const CTRL_U: u8 = 0x15;
+const DELETE: u8 = 0x7f;
+
+/// Global pty table
+pub static PTY_TABLE: Mutex<PtyTable> = Mutex::new(PtyTable::new());
+
+/// Which side of a pty a descriptor refers to
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PtyEnd {
+    /// Terminal emulator side
+    Master,
+    /// Program side, behaves like a serial console
+    Slave,
+}
+
+impl PtyEnd {
+    fn other(self) -> Self {
+        match self {
+            PtyEnd::Master => PtyEnd::Slave,
+            PtyEnd::Slave => PtyEnd::Master,
+        }
+    }
+}
+
+/// Pty file descriptor
+#[derive(Clone)]
+pub struct PtyFd {
+    pub pty_id: usize,
+    pub end: PtyEnd,
+}
+
+impl PtyFd {
+    pub fn new(pty_id: usize, end: PtyEnd) -> Self {
+        Self { pty_id, end }
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, FdError> {
+        PTY_TABLE.lock().read(self.pty_id, self.end, buf)
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, FdError> {
+        PTY_TABLE.lock().write(self.pty_id, self.end, buf)
+    }
+}
+
+/// Pty table; ids stay stable while either end is open
+pub struct PtyTable {
+    ptys: Vec<Option<Pty>>,
+}
+
+impl PtyTable {
+    pub const fn new() -> Self {
+        Self { ptys: Vec::new() }
+    }
+
+    /// Create a new pty and return its ID
+    pub fn create_pty() -> Result<usize, FdError> {
+        let mut table = PTY_TABLE.lock();
+        if let Some(i) = table.ptys.iter().position(Option::is_none) {
+            table.ptys[i] = Some(Pty::new());
+            return Ok(i);
+        }
+        table.ptys.push(Some(Pty::new()));
+        Ok(table.ptys.len() - 1)
+    }
+
+    fn pty_mut(&mut self, pty_id: usize) -> Result<&mut Pty, FdError> {
+        self.ptys
+            .get_mut(pty_id)
+            .and_then(Option::as_mut)
+            .ok_or(FdError::BadFd)
+    }
+
+    /// Increment refcount when cloning/duplicating a pty end
+    pub fn incref(&mut self, pty_id: usize, end: PtyEnd) -> Result<(), FdError> {
+        let pty = self.pty_mut(pty_id)?;
+        match end {
+            PtyEnd::Master => pty.master_refcount += 1,
+            PtyEnd::Slave => pty.slave_refcount += 1,
+        }
+        Ok(())
+    }
+
+    pub fn read(&mut self, pty_id: usize, end: PtyEnd, buf: &mut [u8]) -> Result<usize, FdError> {
+        let pty = self.pty_mut(pty_id)?;
+        let bytes = match end {
+            PtyEnd::Master => pty.read_master(buf)?,
+            PtyEnd::Slave => pty.read_slave(buf)?,
+        };
+        if bytes > 0 {
+            pty.wake(end.other());
+        }
+        Ok(bytes)
+    }
+
+    pub fn write(&mut self, pty_id: usize, end: PtyEnd, buf: &[u8]) -> Result<usize, FdError> {
+        let pty = self.pty_mut(pty_id)?;
+        let written = match end {
+            PtyEnd::Master => pty.write_master(buf)?,
+            PtyEnd::Slave => pty.write_slave(buf)?,
+        };
+        if written > 0 {
+            // Echo lands in the master's queue, so wake both sides
+            pty.wake(PtyEnd::Master);
+            pty.wake(PtyEnd::Slave);
+        }
+        Ok(written)
+    }
+
+    /// Register a process that will block on one end of this pty
+    pub fn mark_waiting(&mut self, pty_id: usize, end: PtyEnd, pid: Pid) -> Result<(), FdError> {
+        let pty = self.pty_mut(pty_id)?;
+        let waiters = match end {
+            PtyEnd::Master => &mut pty.master_waiters,
+            PtyEnd::Slave => &mut pty.slave_waiters,
+        };
+        if !waiters.contains(&pid) {
+            waiters.push(pid);
+        }
+        Ok(())
+    }
+
+    /// Close a pty end; the pty is freed once both sides are gone
+    pub fn close_end(&mut self, pty_id: usize, end: PtyEnd) -> Result<(), FdError> {
+        let pty = self.pty_mut(pty_id)?;
+        match end {
+            PtyEnd::Master => pty.master_refcount = pty.master_refcount.saturating_sub(1),
+            PtyEnd::Slave => pty.slave_refcount = pty.slave_refcount.saturating_sub(1),
+        }
+        // The other side sees EOF or a broken pipe now
+        pty.wake(end.other());
+        if pty.master_refcount == 0 && pty.slave_refcount == 0 {
+            self.ptys[pty_id] = None;
+            while matches!(self.ptys.last(), Some(None)) {
+                self.ptys.pop();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A master/slave pair joined by a line discipline. Bytes written to the
+/// master are what a user typed: they are echoed back to the master, edited
+/// (erase, kill, ^D) and handed to the slave a line at a time. Bytes the
+/// slave writes come out of the master with `\n` expanded to `\r\n`.
+struct Pty {
+    /// Finished lines waiting for the slave to read them
+    input: VecDeque<u8>,
+    /// Slave output and echo waiting for the master to read them
+    output: VecDeque<u8>,
+    /// Line being edited; not visible to the slave until newline or ^D
+    line: Vec<u8>,
+    /// ^D on an empty line: the next slave read returns 0
+    eof: bool,
+    master_refcount: usize,
+    slave_refcount: usize,
+    master_waiters: Vec<Pid>,
+    slave_waiters: Vec<Pid>,
+}
+
+impl Pty {
+    fn new() -> Self {
+        Self {
+            input: VecDeque::new(),
+            output: VecDeque::new(),
+            line: Vec::new(),
+            eof: false,
+            master_refcount: 1,
+            slave_refcount: 1,
+            master_waiters: Vec::new(),
+            slave_waiters: Vec::new(),
+        }
+    }
+
+    /// Feed typed bytes through the line discipline
+    fn write_master(&mut self, buf: &[u8]) -> Result<usize, FdError> {
+        if self.slave_refcount == 0 {
+            return Err(FdError::BrokenPipe);
+        }
+        let mut accepted = 0;
+        for &byte in buf {
+            if self.input.len() + self.line.len() >= PTY_BUF_SIZE {
+                break;
+            }
+            match byte {
+                b'\r' | b'\n' => {
+                    self.line.push(b'\n');
+                    self.input.extend(self.line.drain(..));
+                    self.echo(b"\r\n");
+                }
+                BACKSPACE | DELETE => {
+                    if self.line.pop().is_some() {
+                        self.echo(b"\x08 \x08");
+                    }
+                }
+                CTRL_U => {
+                    while self.line.pop().is_some() {
+                        self.echo(b"\x08 \x08");
+                    }
+                }
+                CTRL_D => {
+                    if self.line.is_empty() {
+                        self.eof = true;
+                    } else {
+                        self.input.extend(self.line.drain(..));
+                    }
+                }
+                _ => {
+                    self.line.push(byte);
+                    self.echo(&[byte]);
+                }
+            }
+            accepted += 1;
+        }
+        if accepted == 0 && !buf.is_empty() {
+            return Err(FdError::WouldBlock);
+        }
+        Ok(accepted)
+    }
+
+    /// Echo is best effort: a master that never reads loses it rather
+    /// than stalling input
+    fn echo(&mut self, bytes: &[u8]) {
+        if self.output.len() + bytes.len() <= PTY_BUF_SIZE {
+            self.output.extend(bytes.iter().copied());
+        }
+    }
+
+    /// Hand the slave at most one line
+    fn read_slave(&mut self, buf: &mut [u8]) -> Result<usize, FdError> {
+        if self.input.is_empty() {
+            if self.eof {
+                self.eof = false;
+                return Ok(0);
+            }
+            if self.master_refcount == 0 {
+                return Ok(0);
+            }
+            return Err(FdError::WouldBlock);
+        }
+        let mut read = 0;
+        while read < buf.len() {
+            let Some(byte) = self.input.pop_front() else {
+                break;
+            };
+            buf[read] = byte;
+            read += 1;
+            if byte == b'\n' {
+                break;
+            }
+        }
+        Ok(read)
+    }
+
+    /// Queue program output for the master, expanding `\n` to `\r\n`
+    fn write_slave(&mut self, buf: &[u8]) -> Result<usize, FdError> {
+        if self.master_refcount == 0 {
+            return Err(FdError::BrokenPipe);
+        }
+        let mut accepted = 0;
+        for &byte in buf {
+            let needed = if byte == b'\n' { 2 } else { 1 };
+            if self.output.len() + needed > PTY_BUF_SIZE {
+                break;
+            }
+            if byte == b'\n' {
+                self.output.push_back(b'\r');
+            }
+            self.output.push_back(byte);
+            accepted += 1;
+        }
+        if accepted == 0 && !buf.is_empty() {
+            return Err(FdError::WouldBlock);
+        }
+        Ok(accepted)
+    }
+
+    fn read_master(&mut self, buf: &mut [u8]) -> Result<usize, FdError> {
+        if self.output.is_empty() {
+            if self.slave_refcount == 0 {
+                return Ok(0);
+            }
+            return Err(FdError::WouldBlock);
+        }
+        let mut read = 0;
+        while read < buf.len() {
+            let Some(byte) = self.output.pop_front() else {
+                break;
+            };
+            buf[read] = byte;
+            read += 1;
+        }
+        Ok(read)
+    }
+
+    fn wake(&mut self, end: PtyEnd) {
+        let waiters = match end {
+            PtyEnd::Master => core::mem::take(&mut self.master_waiters),
+            PtyEnd::Slave => core::mem::take(&mut self.slave_waiters),
+        };
+        for pid in waiters {
+            Scheduler::unblock(pid);
+        }
+    }
+}
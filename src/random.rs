@@ -0,0 +1,186 @@
+use spin::Mutex;
+
+use crate::fd::FdError;
+
+/// getrandom flags. The pool is seeded before the first process runs, so
+/// neither changes anything; they are accepted for compatibility.
+pub const GRND_NONBLOCK: usize = 0x1;
+pub const GRND_RANDOM: usize = 0x2;
+
+/// Output bytes between reseeds from the entropy sources
+const RESEED_INTERVAL: usize = 64 * 1024;
+/// Timer samples taken when there is no virtio entropy device
+const JITTER_SAMPLES: usize = 256;
+
+static POOL: Mutex<Pool> = Mutex::new(Pool::new());
+
+/// A ChaCha20 key that input is folded into and output is drawn from.
+/// Each `fill` replaces the key with fresh keystream, so earlier output
+/// cannot be recovered from a later state.
+struct Pool {
+    key: [u32; 8],
+    /// Bytes handed out since the last reseed
+    since_reseed: usize,
+    seeded: bool,
+}
+
+impl Pool {
+    const fn new() -> Self {
+        Self {
+            key: [0; 8],
+            since_reseed: 0,
+            seeded: false,
+        }
+    }
+
+    /// Fold `input` into the key
+    fn mix(&mut self, input: &[u8]) {
+        for chunk in input.chunks(32) {
+            for (i, byte) in chunk.iter().enumerate() {
+                self.key[i / 4] ^= (*byte as u32) << ((i % 4) * 8);
+            }
+            let block = chacha20_block(&self.key, 0, 0);
+            self.key.copy_from_slice(&block[..8]);
+        }
+    }
+
+    fn reseed(&mut self) {
+        let mut seed = [0u8; 32];
+        let mut have = 0;
+        while have < seed.len() {
+            let count = crate::virtio::rng::read(&mut seed[have..]);
+            if count == 0 {
+                break;
+            }
+            have += count;
+        }
+        if have < seed.len() {
+            self.mix_jitter();
+        }
+        self.mix(&seed[..have]);
+        self.since_reseed = 0;
+        self.seeded = true;
+    }
+
+    /// Gather entropy from how long a few memory accesses take, measured
+    /// on the timebase; only the low bits of each sample vary
+    fn mix_jitter(&mut self) {
+        let mut samples = [0u8; JITTER_SAMPLES];
+        let mut scratch = [0u64; 16];
+        let mut last = riscv::register::time::read64();
+        for (i, sample) in samples.iter_mut().enumerate() {
+            for (j, word) in scratch.iter_mut().enumerate() {
+                *word = word.wrapping_mul(6364136223846793005).wrapping_add((i ^ j) as u64 ^ last);
+                unsafe { core::ptr::write_volatile(word, *word) };
+            }
+            let now = riscv::register::time::read64();
+            *sample = (now.wrapping_sub(last) ^ now) as u8;
+            last = now;
+        }
+        self.mix(&samples);
+    }
+
+    fn fill(&mut self, out: &mut [u8]) {
+        if !self.seeded || self.since_reseed >= RESEED_INTERVAL {
+            self.reseed();
+        }
+        // Block 0 becomes the next key; output starts at block 1
+        for (counter, chunk) in (1u64..).zip(out.chunks_mut(64)) {
+            let block = chacha20_block(&self.key, counter, 0);
+            let stream = block.iter().flat_map(|word| word.to_le_bytes());
+            for (byte, value) in chunk.iter_mut().zip(stream) {
+                *byte = value;
+            }
+        }
+        let next = chacha20_block(&self.key, 0, 0);
+        self.key.copy_from_slice(&next[..8]);
+        self.since_reseed = self.since_reseed.saturating_add(out.len());
+    }
+}
+
+/// Bring up the virtio entropy device, if there is one, and seed the pool.
+/// Returns whether the device is there.
+pub fn init() -> bool {
+    let present = crate::virtio::rng::init().is_ok();
+    POOL.lock().reseed();
+    present
+}
+
+/// Fill `out` with random bytes
+pub fn fill(out: &mut [u8]) {
+    POOL.lock().fill(out);
+}
+
+/// Fold bytes written to /dev/random into the pool; like on Linux they
+/// are not trusted as the only source
+pub fn mix(input: &[u8]) {
+    POOL.lock().mix(input);
+}
+
+/// Fold the arrival time of an interrupt into the pool. Skipped when the
+/// pool is busy, since this runs from interrupt handlers.
+pub fn add_interrupt_timing() {
+    if let Some(mut pool) = POOL.try_lock() {
+        let now = riscv::register::time::read64();
+        pool.mix(&now.to_le_bytes());
+    }
+}
+
+/// `/dev/random` and `/dev/urandom`: reads never block
+#[derive(Clone)]
+pub struct RandomFd;
+
+impl RandomFd {
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, FdError> {
+        fill(buf);
+        Ok(buf.len())
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, FdError> {
+        mix(buf);
+        Ok(buf.len())
+    }
+}
+
+/// Whether `path` is one of the random devices
+pub fn is_device(path: &str) -> bool {
+    matches!(path, "/dev/random" | "/dev/urandom")
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// One ChaCha20 block (RFC 8439) with a 64-bit counter and nonce
+fn chacha20_block(key: &[u32; 8], counter: u64, nonce: u64) -> [u32; 16] {
+    let mut initial = [0u32; 16];
+    initial[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+    initial[4..12].copy_from_slice(key);
+    initial[12] = counter as u32;
+    initial[13] = (counter >> 32) as u32;
+    initial[14] = nonce as u32;
+    initial[15] = (nonce >> 32) as u32;
+
+    let mut state = initial;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    for (word, start) in state.iter_mut().zip(initial) {
+        *word = word.wrapping_add(start);
+    }
+    state
+}
@@ -0,0 +1,111 @@
+use crate::interrupts::TICKS_PER_SECOND;
+use crate::proc::PROCESS_TABLE;
+use crate::signal::{SIGKILL, SIGXCPU};
+
+/// CPU time in seconds (Linux resource numbering throughout)
+pub const RLIMIT_CPU: usize = 0;
+/// Largest file offset a write may reach
+pub const RLIMIT_FSIZE: usize = 1;
+/// Most children (zombies included) a process may have at once
+pub const RLIMIT_NPROC: usize = 6;
+/// Open descriptor limit; kept in the fd table rather than in `Rlimits`
+pub const RLIMIT_NOFILE: usize = 7;
+/// Kernel memory charged to the process (see `oom::charge_of`)
+pub const RLIMIT_AS: usize = 9;
+
+/// No limit
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+/// Default cap on the kernel memory charged to a process; room for its
+/// snapshot and a few large pipes
+pub const DEFAULT_MEM_LIMIT: usize = 1024 * 1024;
+
+/// Soft limit (enforced) and hard limit (ceiling for the soft limit)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rlimit {
+    pub soft: usize,
+    pub hard: usize,
+}
+
+impl Rlimit {
+    const fn new(soft: usize, hard: usize) -> Self {
+        Self { soft, hard }
+    }
+}
+
+/// Per-process limits, inherited by spawned children
+#[derive(Debug, Clone, Copy)]
+pub struct Rlimits {
+    pub cpu: Rlimit,
+    pub fsize: Rlimit,
+    pub nproc: Rlimit,
+    pub mem: Rlimit,
+}
+
+impl Rlimits {
+    pub const fn new() -> Self {
+        Self {
+            cpu: Rlimit::new(RLIM_INFINITY, RLIM_INFINITY),
+            fsize: Rlimit::new(RLIM_INFINITY, RLIM_INFINITY),
+            nproc: Rlimit::new(RLIM_INFINITY, RLIM_INFINITY),
+            mem: Rlimit::new(DEFAULT_MEM_LIMIT, crate::heap::HEAP_SIZE),
+        }
+    }
+
+    fn slot_mut(&mut self, resource: usize) -> Option<&mut Rlimit> {
+        match resource {
+            RLIMIT_CPU => Some(&mut self.cpu),
+            RLIMIT_FSIZE => Some(&mut self.fsize),
+            RLIMIT_NPROC => Some(&mut self.nproc),
+            RLIMIT_AS => Some(&mut self.mem),
+            _ => None,
+        }
+    }
+
+    /// Limits for `resource`, or None if it is not tracked here
+    pub fn get(&self, resource: usize) -> Option<Rlimit> {
+        let mut copy = *self;
+        copy.slot_mut(resource).copied()
+    }
+
+    /// Replace the limits for `resource`. The hard limit can only be
+    /// lowered and the soft limit cannot exceed it; false otherwise or if
+    /// the resource is not tracked here.
+    pub fn set(&mut self, resource: usize, limit: Rlimit) -> bool {
+        let Some(slot) = self.slot_mut(resource) else {
+            return false;
+        };
+        if limit.hard > slot.hard || limit.soft > limit.hard {
+            return false;
+        }
+        *slot = limit;
+        true
+    }
+}
+
+/// Charge a timer tick to the current process. Each second past its
+/// RLIMIT_CPU soft limit it gets SIGXCPU; at the hard limit, SIGKILL.
+pub fn charge_cpu_tick() {
+    let (pid, sig) = {
+        let mut table = PROCESS_TABLE.lock();
+        let pid = table.get_current_pid();
+        let Some(process) = table.get_mut(pid) else {
+            return;
+        };
+        process.cpu_ticks += 1;
+        if process.cpu_ticks % TICKS_PER_SECOND != 0 {
+            return;
+        }
+        let seconds = (process.cpu_ticks / TICKS_PER_SECOND) as usize;
+        let sig = if seconds >= process.rlimits.cpu.hard {
+            SIGKILL
+        } else if seconds >= process.rlimits.cpu.soft {
+            SIGXCPU
+        } else {
+            return;
+        };
+        (pid, sig)
+    };
+    crate::signal::send(pid, sig);
+    crate::signal::deliver_to_interrupted();
+}
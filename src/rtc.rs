@@ -0,0 +1,117 @@
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Goldfish RTC on the QEMU virt machine
+const RTC_BASE: usize = 0x0010_1000;
+/// Reading TIME_LOW latches the high half into TIME_HIGH
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+
+/// Ticks of the `time` CSR per second on QEMU virt
+pub const TIMEBASE_HZ: u64 = 10_000_000;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// clock_gettime clock ids
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+
+static PRESENT: AtomicBool = AtomicBool::new(false);
+
+/// `struct timespec` as clock_gettime writes it
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timespec {
+    pub sec: u64,
+    pub nsec: u64,
+}
+
+impl Timespec {
+    fn from_nanos(nanos: u64) -> Self {
+        Self {
+            sec: nanos / NANOS_PER_SEC,
+            nsec: nanos % NANOS_PER_SEC,
+        }
+    }
+}
+
+/// Check for the RTC; returns false if it reads as stopped at the epoch,
+/// in which case wall-clock time counts from boot instead
+pub fn init() -> bool {
+    let present = read_rtc() != 0;
+    PRESENT.store(present, Ordering::Relaxed);
+    present
+}
+
+fn read_rtc() -> u64 {
+    unsafe {
+        let low = ptr::read_volatile((RTC_BASE + TIME_LOW) as *const u32);
+        let high = ptr::read_volatile((RTC_BASE + TIME_HIGH) as *const u32);
+        ((high as u64) << 32) | low as u64
+    }
+}
+
+/// Nanoseconds since boot
+fn monotonic_nanos() -> u64 {
+    riscv::register::time::read64() * (NANOS_PER_SEC / TIMEBASE_HZ)
+}
+
+/// The time on `clock`, or None for an unknown clock id
+pub fn clock_gettime(clock: usize) -> Option<Timespec> {
+    match clock {
+        CLOCK_REALTIME if PRESENT.load(Ordering::Relaxed) => Some(Timespec::from_nanos(read_rtc())),
+        CLOCK_REALTIME | CLOCK_MONOTONIC => Some(Timespec::from_nanos(monotonic_nanos())),
+        _ => None,
+    }
+}
+
+/// Seconds since the Unix epoch, as recorded in file mtimes
+pub fn unix_time() -> u64 {
+    clock_gettime(CLOCK_REALTIME).map_or(0, |now| now.sec)
+}
+
+/// A UTC calendar date and time
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    /// Split `secs` since the epoch into a proleptic Gregorian date
+    pub fn from_unix(secs: u64) -> Self {
+        let days = (secs / 86_400) as i64;
+        let rem = secs % 86_400;
+        // Days from 0000-03-01, so leap days fall at the end of a year
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        Self {
+            year,
+            month,
+            day,
+            hour: (rem / 3600) as u32,
+            minute: (rem / 60 % 60) as u32,
+            second: (rem % 60) as u32,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
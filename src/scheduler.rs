@@ -1,49 +1,77 @@
-use crate::proc::{INVALID_PID, PROCESS_TABLE, Pid, ProcessState};
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use riscv::register::sstatus::{self, SPP};
+use spin::Mutex;
+
+use crate::klog;
+use crate::proc::{INVALID_PID, PROCESS_TABLE, Pid, ProcessState};
+
+/// Process sleeping inside a syscall in `Scheduler::park`, or INVALID_PID
+static PARKED_PID: AtomicUsize = AtomicUsize::new(INVALID_PID);
+
+/// Processes `hold_while_parked` turned back, woken when the parked
+/// process runs again
+static HELD: Mutex<Vec<Pid>> = Mutex::new(Vec::new());
+
+/// Callers inside `Scheduler::without_parking`
+static NO_PARK: AtomicUsize = AtomicUsize::new(0);
+
+/// Scheduling counters for a process, or summed over all of them (see
+/// `ProcessTable::sched_stats`). Ticks are timer samples of the state
+/// the process was in, so they are only as precise as TICKS_PER_SECOND.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedStats {
+    /// Times the process was switched in
+    pub switches: u64,
+    pub running_ticks: u64,
+    pub ready_ticks: u64,
+    pub blocked_ticks: u64,
+    /// Samples taken while the process existed
+    pub ticks: u64,
+    /// User window bytes copied to switch the process in and out
+    pub window_bytes: u64,
+    /// `cycle` CSR counts spent in `maybe_switch` switching the process in
+    pub switch_cycles: u64,
+}
 
-/// Simple round-robin scheduler
+impl SchedStats {
+    pub const fn new() -> Self {
+        Self {
+            switches: 0,
+            running_ticks: 0,
+            ready_ticks: 0,
+            blocked_ticks: 0,
+            ticks: 0,
+            window_bytes: 0,
+            switch_cycles: 0,
+        }
+    }
+}
+
+/// Multi-level feedback queue scheduler. Each process sits on one of
+/// PRIORITY_LEVELS ready queues; the highest non-empty level runs first,
+/// round-robin within it. Giving up the CPU while still runnable moves a
+/// process down a level, and waking from a block moves it up one, so
+/// interactive processes like the shell run ahead of batch jobs.
 pub struct Scheduler;
 
 impl Scheduler {
-    /// Select the next process to run
+    /// Select the next process to run and take it off its ready queue
     /// Returns None if no processes are ready
     pub fn schedule() -> Option<Pid> {
-        let mut table = PROCESS_TABLE.lock();
-        let current_pid = table.get_current_pid();
-
-        // Find next ready process after current
-        let processes: Vec<_> = table
-            .get_all_processes()
-            .iter()
-            .filter(|p| p.state == ProcessState::Ready || p.state == ProcessState::Running)
-            .map(|p| p.pid)
-            .collect();
-
-        if processes.is_empty() {
-            return None;
-        }
-
-        // Round-robin: find next process after current
-        if current_pid != INVALID_PID {
-            if let Some(current_idx) = processes.iter().position(|&pid| pid == current_pid) {
-                let next_idx = (current_idx + 1) % processes.len();
-                return Some(processes[next_idx]);
-            }
-        }
-
-        // No current process or not found, return first ready process
-        Some(processes[0])
+        PROCESS_TABLE.lock().pick_next()
     }
 
     /// Yield CPU to another process
     pub fn yield_cpu() {
-        // Mark current as Ready
+        // Requeue current behind the other processes at its level
         let current_pid = PROCESS_TABLE.lock().get_current_pid();
         if current_pid != INVALID_PID {
-            if let Some(process) = PROCESS_TABLE.lock().get_mut(current_pid) {
-                if process.state == ProcessState::Running {
-                    process.state = ProcessState::Ready;
-                }
+            let mut table = PROCESS_TABLE.lock();
+            if table.get(current_pid).is_some_and(|p| p.state == ProcessState::Running) {
+                table.make_ready(current_pid);
             }
         }
 
@@ -66,19 +94,122 @@ impl Scheduler {
         }
     }
 
-    /// Unblock a specific process
-    pub fn unblock(pid: Pid) {
-        if let Some(process) = PROCESS_TABLE.lock().get_mut(pid) {
-            if process.state == ProcessState::Blocked {
-                process.state = ProcessState::Ready;
+    /// Sleep inside the current syscall until `unblock`: another process
+    /// runs meanwhile, and this returns once the current one is picked
+    /// again. Returns false at once, for the caller to wait some other
+    /// way, if that is not possible: before any process runs, outside a
+    /// trap from user mode or off the process's own kernel stack, within
+    /// `without_parking`, while the process table is held, while another
+    /// process is parked, or when nothing else is ready.
+    ///
+    /// The parked syscall may hold filesystem and file locks, so
+    /// `hold_while_parked` keeps other processes out of the kernel until
+    /// it runs again. Its user window is swapped out meanwhile.
+    pub fn park() -> bool {
+        if sstatus::read().spp() != SPP::User || NO_PARK.load(Ordering::Acquire) != 0 {
+            return false;
+        }
+        let pid = {
+            let Some(mut table) = PROCESS_TABLE.try_lock() else {
+                return false;
+            };
+            let pid = table.get_current_pid();
+            let parkable = table.get(pid).is_some_and(|process| {
+                process.state == ProcessState::Running && process.kernel_stack.is_running_on()
+            });
+            if !parkable || !table.has_ready_besides(pid) {
+                return false;
+            }
+            if PARKED_PID
+                .compare_exchange(INVALID_PID, pid, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+            {
+                return false;
+            }
+            if let Some(process) = table.get_mut(pid) {
+                process.state = ProcessState::Blocked;
             }
+            pid
+        };
+
+        klog!(Sched, Debug, "sched: pid {} parked in the kernel", pid);
+        let slept = Self::maybe_switch();
+        if slept {
+            crate::process::park_in_kernel();
+        } else if let Some(process) = PROCESS_TABLE.lock().get_mut(pid) {
+            process.state = ProcessState::Running;
         }
+
+        PARKED_PID.store(INVALID_PID, Ordering::Release);
+        let held = core::mem::take(&mut *HELD.lock());
+        for pid in held {
+            Self::unblock(pid);
+        }
+        slept
+    }
+
+    /// Run `f` with `park` disabled, for code that fills the user window
+    /// with something other than the current process's memory: switching
+    /// away would save that as the process's own.
+    pub fn without_parking<T>(f: impl FnOnce() -> T) -> T {
+        NO_PARK.fetch_add(1, Ordering::AcqRel);
+        let value = f();
+        NO_PARK.fetch_sub(1, Ordering::AcqRel);
+        value
+    }
+
+    /// Whether `pid` is sleeping inside a syscall in `park`
+    pub fn is_parked(pid: Pid) -> bool {
+        pid != INVALID_PID && PARKED_PID.load(Ordering::Acquire) == pid
+    }
+
+    /// Whether any process is sleeping inside a syscall in `park`
+    pub fn any_parked() -> bool {
+        PARKED_PID.load(Ordering::Acquire) != INVALID_PID
+    }
+
+    /// Turn the current process back from the kernel while another one
+    /// is parked in it: block it until the parked process runs again and
+    /// switch away. The caller returns to user mode without doing
+    /// anything, so the trapping instruction runs again once it is woken.
+    /// Returns false when no process is parked.
+    pub fn hold_while_parked() -> bool {
+        if !Self::any_parked() {
+            return false;
+        }
+        let current_pid = PROCESS_TABLE.lock().get_current_pid();
+        if Self::is_parked(current_pid) {
+            return false;
+        }
+        {
+            let mut held = HELD.lock();
+            if !held.contains(&current_pid) {
+                held.push(current_pid);
+            }
+        }
+        Self::block_current();
+        Self::maybe_switch();
+        true
+    }
+
+    /// Charge a timer tick to every process's current state. A tick that
+    /// interrupts the kernel while it holds the process table is dropped.
+    pub fn account_tick() {
+        if let Some(mut table) = PROCESS_TABLE.try_lock() {
+            table.account_tick();
+        }
+    }
+
+    /// Unblock a specific process
+    pub fn unblock(pid: Pid) {
+        klog!(Sched, Debug, "sched: wake pid {}", pid);
+        PROCESS_TABLE.lock().wake(pid);
     }
 
     /// Perform a full context switch if needed
     /// This should be called after syscalls that might block or when yielding
     /// Returns true if a context switch occurred
-    pub fn maybe_switch(trap_frame: &mut riscv_rt::TrapFrame) -> bool {
+    pub fn maybe_switch() -> bool {
         let current_pid = PROCESS_TABLE.lock().get_current_pid();
 
         // Determine if we should switch
@@ -87,12 +218,12 @@ impl Scheduler {
         } else {
             let mut table = PROCESS_TABLE.lock();
             let state = table.get(current_pid).map(|p| p.state);
-            let has_other_ready = table.get_all_processes().iter().any(|p| {
-                p.pid != current_pid
-                    && (p.state == ProcessState::Ready || p.state == ProcessState::Running)
-            });
+            let has_other_ready = table.has_ready_besides(current_pid);
             match state {
-                Some(ProcessState::Blocked) | Some(ProcessState::Exited) | None => (true, false),
+                Some(ProcessState::Blocked)
+                | Some(ProcessState::Stopped)
+                | Some(ProcessState::Exited)
+                | None => (true, false),
                 Some(ProcessState::Running) | Some(ProcessState::Ready) => {
                     if has_other_ready {
                         (true, true)
@@ -106,33 +237,51 @@ impl Scheduler {
         if !should_switch {
             return false;
         }
+        let start = riscv::register::cycle::read64();
 
         // Save current process state if there is one
         if current_pid != INVALID_PID {
             let mut table = PROCESS_TABLE.lock();
-            table.save_current_registers(trap_frame);
-            table.save_current_memory();
+            // Memory stays in the window until another process needs it
+            table.save_current_registers();
             if make_current_ready {
-                if let Some(proc) = table.get_mut(current_pid) {
-                    if proc.state == ProcessState::Running {
-                        proc.state = ProcessState::Ready;
-                    }
-                }
+                table.demote(current_pid);
+                table.make_ready(current_pid);
             }
         }
 
-        // Schedule next process
+        // Schedule next process; a demoted current process can still
+        // outrank everything else
         if let Some(next_pid) = Self::schedule() {
+            if next_pid == current_pid {
+                if let Some(process) = PROCESS_TABLE.lock().get_mut(next_pid) {
+                    process.state = ProcessState::Running;
+                }
+                return false;
+            }
+
+            klog!(
+                Sched,
+                Debug,
+                "sched: switch pid {} -> {}",
+                current_pid,
+                next_pid
+            );
+
             // Restore next process state
             let mut table = PROCESS_TABLE.lock();
             table.set_current(next_pid);
             table.restore_process_memory(next_pid);
-            table.restore_process_registers(next_pid, trap_frame);
+            table.restore_process_registers(next_pid);
+            table.charge_switch(next_pid, riscv::register::cycle::read64() - start);
 
             // Mark as running
             if let Some(process) = table.get_mut(next_pid) {
                 process.state = ProcessState::Running;
             }
+            if Self::is_parked(next_pid) {
+                crate::process::resume_parked();
+            }
 
             true
         } else {
@@ -0,0 +1,150 @@
+use riscv::register::sstatus::{self, SPP};
+
+use crate::klog;
+use crate::proc::{INIT_PID, INVALID_PID, PROCESS_TABLE, Pid};
+use crate::scheduler::Scheduler;
+
+/// Interrupt from the keyboard (Ctrl-C)
+pub const SIGINT: usize = 2;
+/// Illegal instruction
+pub const SIGILL: usize = 4;
+/// Breakpoint
+pub const SIGTRAP: usize = 5;
+/// Misaligned access
+pub const SIGBUS: usize = 7;
+/// Unconditional termination; cannot be ignored
+pub const SIGKILL: usize = 9;
+/// Invalid memory access
+pub const SIGSEGV: usize = 11;
+/// Resume a stopped process
+pub const SIGCONT: usize = 18;
+/// Unconditional stop; cannot be ignored
+pub const SIGSTOP: usize = 19;
+/// Stop request from the keyboard (Ctrl-Z)
+pub const SIGTSTP: usize = 20;
+/// CPU time limit (RLIMIT_CPU) exceeded
+pub const SIGXCPU: usize = 24;
+/// Highest signal number that fits the pending mask
+pub const MAX_SIGNAL: usize = 31;
+
+/// `signal()` dispositions (Linux values); there are no user handlers
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+/// Console byte that interrupts the foreground process group
+pub const CTRL_C: u8 = 0x03;
+/// Console byte that stops the foreground process group
+pub const CTRL_Z: u8 = 0x1a;
+
+/// Whether `sig` stops the process rather than terminating it
+pub fn is_stop(sig: usize) -> bool {
+    sig == SIGSTOP || sig == SIGTSTP
+}
+
+/// Send `sig` to `pid`. SIGCONT resumes a stopped process on the spot
+/// (it never runs to notice a pending signal), and a stop signal stops a
+/// process that is not running on the spot, so the scheduler skips it
+/// from now on. Anything else is marked pending and the process is woken
+/// so it is delivered. A signal the process ignores is dropped. Returns
+/// false if there is no such process.
+pub fn send(pid: Pid, sig: usize) -> bool {
+    let mut stopped_parent = None;
+    let sent = {
+        let mut table = PROCESS_TABLE.lock();
+        if sig == SIGCONT {
+            table.continue_process(pid);
+            table.get(pid).is_some_and(|p| !p.has_exited())
+        } else if !table.raise(pid, sig) {
+            false
+        } else {
+            // A parked process stops itself once its syscall returns
+            if is_stop(sig)
+                && pid != table.get_current_pid()
+                && pid != INIT_PID
+                && !Scheduler::is_parked(pid)
+            {
+                let bit = 1 << sig;
+                if let Some(process) = table.get_mut(pid) {
+                    if process.pending_signals & bit != 0 {
+                        process.pending_signals &= !bit;
+                        stopped_parent = Some(process.parent_pid);
+                    }
+                }
+                if stopped_parent.is_some() {
+                    table.stop_process(pid, sig);
+                }
+            }
+            true
+        }
+    };
+    if let Some(parent_pid) = stopped_parent {
+        Scheduler::unblock(parent_pid);
+    } else if sent && sig != SIGCONT {
+        Scheduler::unblock(pid);
+    }
+    sent
+}
+
+/// Send `sig` to every process in group `pgid`; false if it is empty
+pub fn send_group(pgid: Pid, sig: usize) -> bool {
+    let members = PROCESS_TABLE.lock().group_members(pgid);
+    for &pid in &members {
+        send(pid, sig);
+    }
+    !members.is_empty()
+}
+
+/// Called from a console interrupt for Ctrl-C/Ctrl-Z. Returns false when
+/// nothing is in `port`'s foreground, so the byte should be queued as
+/// ordinary input.
+pub fn signal_foreground(port: crate::console::Port, sig: usize) -> bool {
+    let foreground = PROCESS_TABLE.lock().foreground(port);
+    if foreground == INVALID_PID || !send_group(foreground, sig) {
+        return false;
+    }
+    deliver_to_interrupted();
+    true
+}
+
+/// From an interrupt handler: if the interrupt landed in user code, act
+/// on the current process's pending signals now, since a busy loop may
+/// never make another syscall. Exiting closes files, which waits for a
+/// syscall parked in the kernel, so while one is the signals stay
+/// pending for the next syscall.
+pub fn deliver_to_interrupted() {
+    if sstatus::read().spp() == SPP::User && !Scheduler::any_parked() {
+        deliver_pending();
+    }
+}
+
+/// Act on signals pending for the current process before it returns to
+/// user mode. SIGSTOP and SIGTSTP stop the process; every other signal terminates
+/// it. Init ignores them, as on Linux. Returns true if another process
+/// was switched in.
+pub fn deliver_pending() -> bool {
+    let (pid, parent_pid, sig) = {
+        let mut table = PROCESS_TABLE.lock();
+        let pid = table.get_current_pid();
+        let Some(process) = table.get_mut(pid) else {
+            return false;
+        };
+        let Some(sig) = process.take_signal() else {
+            return false;
+        };
+        if pid == INIT_PID {
+            return false;
+        }
+        let parent_pid = process.parent_pid;
+        if is_stop(sig) {
+            table.stop_process(pid, sig);
+        } else {
+            // Shell convention: killed by signal N reports status 128 + N
+            table.exit_process(pid, 128 + sig as isize);
+        }
+        (pid, parent_pid, sig)
+    };
+    let action = if is_stop(sig) { "stopped" } else { "terminated" };
+    klog!(Warn, "[process {} {} by signal {}]", pid, action, sig);
+    Scheduler::unblock(parent_pid);
+    Scheduler::maybe_switch()
+}
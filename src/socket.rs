@@ -0,0 +1,638 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use core::fmt;
+use spin::Mutex;
+
+use crate::fd::FdError;
+use crate::net::{self, Segment, TcpConn, TcpState};
+use crate::proc::Pid;
+use crate::scheduler::Scheduler;
+
+/// Bytes a stream socket buffers before senders see WouldBlock; also the
+/// largest datagram
+pub const SOCKET_BUF_SIZE: usize = 4096;
+
+/// Datagrams queued on a socket before new ones are dropped
+const MAX_DATAGRAMS: usize = 16;
+
+/// First port handed out to sockets that send or connect unbound
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// 127.0.0.1; loopback and our own address never touch the network
+pub const LOOPBACK_ADDR: u32 = 0x7f00_0001;
+
+/// Global socket table
+pub static SOCKET_TABLE: Mutex<SocketTable> = Mutex::new(SocketTable::new());
+
+/// Socket type requested from `socket()` (Linux numbering)
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SocketKind {
+    /// TCP-style byte stream
+    Stream = 1,
+    /// UDP-style datagrams
+    Datagram = 2,
+}
+
+impl SocketKind {
+    pub fn from_raw(raw: usize) -> Option<Self> {
+        match raw {
+            1 => Some(SocketKind::Stream),
+            2 => Some(SocketKind::Datagram),
+            _ => None,
+        }
+    }
+}
+
+/// Socket errors, reported through `FdError::Net`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetError {
+    /// Port already bound by another socket of the same kind
+    AddressInUse,
+    /// Address is not local and there is no network device, or a local
+    /// bind names another host's address
+    Unreachable,
+    /// Nobody is listening on the destination port
+    ConnectionRefused,
+    /// Stream socket is not connected, or datagram has no destination
+    NotConnected,
+    /// Socket is already connected or listening
+    AlreadyConnected,
+    /// Operation does not apply to this socket kind or state
+    NotSupported,
+    /// Descriptor is not a socket
+    NotSocket,
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetError::AddressInUse => write!(f, "Address in use"),
+            NetError::Unreachable => write!(f, "Network unreachable"),
+            NetError::ConnectionRefused => write!(f, "Connection refused"),
+            NetError::NotConnected => write!(f, "Socket not connected"),
+            NetError::AlreadyConnected => write!(f, "Socket already connected"),
+            NetError::NotSupported => write!(f, "Operation not supported"),
+            NetError::NotSocket => write!(f, "Not a socket"),
+        }
+    }
+}
+
+/// Socket file descriptor
+#[derive(Clone)]
+pub struct SocketFd {
+    pub socket_id: usize,
+}
+
+impl SocketFd {
+    pub fn new(socket_id: usize) -> Self {
+        Self { socket_id }
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, FdError> {
+        SOCKET_TABLE
+            .lock()
+            .recv(self.socket_id, buf)
+            .map(|(len, _)| len)
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, FdError> {
+        SOCKET_TABLE.lock().send(self.socket_id, buf, None)
+    }
+}
+
+/// Socket table. Between local sockets "sending" is queueing bytes on
+/// the socket bound to the destination port; traffic to other hosts goes
+/// through `net` as UDP datagrams or a `TcpConn`.
+pub struct SocketTable {
+    sockets: Vec<Option<Socket>>,
+    next_ephemeral: u16,
+    /// TCP connections whose sockets are closed but whose FIN exchange
+    /// has not finished
+    orphans: Vec<TcpConn>,
+}
+
+impl SocketTable {
+    pub const fn new() -> Self {
+        Self {
+            sockets: Vec::new(),
+            next_ephemeral: EPHEMERAL_PORT_START,
+            orphans: Vec::new(),
+        }
+    }
+
+    /// Create an unbound socket and return its ID
+    pub fn create_socket(&mut self, kind: SocketKind) -> usize {
+        self.insert(Socket::new(kind))
+    }
+
+    fn insert(&mut self, socket: Socket) -> usize {
+        if let Some(i) = self.sockets.iter().position(Option::is_none) {
+            self.sockets[i] = Some(socket);
+            return i;
+        }
+        self.sockets.push(Some(socket));
+        self.sockets.len() - 1
+    }
+
+    fn socket_mut(&mut self, socket_id: usize) -> Result<&mut Socket, FdError> {
+        self.sockets
+            .get_mut(socket_id)
+            .and_then(Option::as_mut)
+            .ok_or(FdError::BadFd)
+    }
+
+    /// Socket of `kind` bound to `port`, excluding accepted connections
+    /// (which share their listener's port)
+    fn find_bound(&self, kind: SocketKind, port: u16) -> Option<usize> {
+        self.sockets.iter().position(|s| {
+            s.as_ref().is_some_and(|s| {
+                s.kind == kind && s.port == Some(port) && !s.accepted
+            })
+        })
+    }
+
+    fn ephemeral_port(&mut self, kind: SocketKind) -> u16 {
+        loop {
+            let port = self.next_ephemeral;
+            self.next_ephemeral = self
+                .next_ephemeral
+                .checked_add(1)
+                .unwrap_or(EPHEMERAL_PORT_START);
+            if self.find_bound(kind, port).is_none() {
+                return port;
+            }
+        }
+    }
+
+    /// Give an unbound socket an ephemeral port
+    fn autobind(&mut self, socket_id: usize) -> Result<u16, FdError> {
+        let socket = self.socket_mut(socket_id)?;
+        if let Some(port) = socket.port {
+            return Ok(port);
+        }
+        let kind = socket.kind;
+        let port = self.ephemeral_port(kind);
+        self.socket_mut(socket_id)?.port = Some(port);
+        Ok(port)
+    }
+
+    /// Increment refcount when cloning/duplicating a socket descriptor
+    pub fn incref(&mut self, socket_id: usize) -> Result<(), FdError> {
+        self.socket_mut(socket_id)?.refcount += 1;
+        Ok(())
+    }
+
+    /// Bind to `port` on `addr` (loopback, ours or any); port 0 picks one
+    pub fn bind(&mut self, socket_id: usize, addr: u32, port: u16) -> Result<(), FdError> {
+        if !is_local(addr) {
+            return Err(FdError::Net(NetError::Unreachable));
+        }
+        let socket = self.socket_mut(socket_id)?;
+        if socket.port.is_some() {
+            return Err(FdError::InvalidArgument);
+        }
+        let kind = socket.kind;
+        let port = if port == 0 {
+            self.ephemeral_port(kind)
+        } else if self.find_bound(kind, port).is_some() {
+            return Err(FdError::Net(NetError::AddressInUse));
+        } else {
+            port
+        };
+        self.socket_mut(socket_id)?.port = Some(port);
+        Ok(())
+    }
+
+    /// Start accepting connections on a bound stream socket
+    pub fn listen(&mut self, socket_id: usize, backlog: usize) -> Result<(), FdError> {
+        self.autobind(socket_id)?;
+        let socket = self.socket_mut(socket_id)?;
+        if socket.kind != SocketKind::Stream {
+            return Err(FdError::Net(NetError::NotSupported));
+        }
+        match socket.state {
+            SocketState::Idle | SocketState::Listening { .. } => {}
+            _ => return Err(FdError::Net(NetError::AlreadyConnected)),
+        }
+        let pending = match core::mem::replace(&mut socket.state, SocketState::Idle) {
+            SocketState::Listening { pending, .. } => pending,
+            _ => VecDeque::new(),
+        };
+        socket.state = SocketState::Listening {
+            pending,
+            backlog: backlog.max(1),
+        };
+        Ok(())
+    }
+
+    /// Take the next pending connection and return its socket ID
+    pub fn accept(&mut self, socket_id: usize) -> Result<usize, FdError> {
+        let socket = self.socket_mut(socket_id)?;
+        let SocketState::Listening { pending, .. } = &mut socket.state else {
+            return Err(FdError::InvalidArgument);
+        };
+        let conn = pending.pop_front().ok_or(FdError::WouldBlock)?;
+        self.socket_mut(conn)?.refcount = 1;
+        Ok(conn)
+    }
+
+    /// Connect a stream socket to a listener, or set a datagram socket's
+    /// default destination. Loopback connections complete immediately;
+    /// remote ones return WouldBlock until the handshake finishes, so the
+    /// caller retries.
+    pub fn connect(&mut self, socket_id: usize, addr: u32, port: u16) -> Result<(), FdError> {
+        check_addr(addr)?;
+        let local_port = self.autobind(socket_id)?;
+        let socket = self.socket_mut(socket_id)?;
+        let dest = (if is_local(addr) { LOOPBACK_ADDR } else { addr }, port);
+        if socket.kind == SocketKind::Datagram {
+            socket.dest = Some(dest);
+            return Ok(());
+        }
+        if let SocketState::Remote { conn } = &socket.state {
+            return match conn.state {
+                TcpState::SynSent | TcpState::SynReceived => Err(FdError::WouldBlock),
+                TcpState::Established if conn.remote == dest => Ok(()),
+                TcpState::Closed if conn.remote == dest => {
+                    socket.state = SocketState::Idle;
+                    Err(FdError::Net(NetError::ConnectionRefused))
+                }
+                _ => Err(FdError::Net(NetError::AlreadyConnected)),
+            };
+        }
+        if !matches!(socket.state, SocketState::Idle) {
+            return Err(FdError::Net(NetError::AlreadyConnected));
+        }
+        if !is_local(addr) {
+            socket.dest = Some(dest);
+            socket.state = SocketState::Remote {
+                conn: TcpConn::connect(local_port, dest),
+            };
+            return Err(FdError::WouldBlock);
+        }
+
+        let listener_id = self
+            .find_bound(SocketKind::Stream, port)
+            .filter(|&id| id != socket_id)
+            .ok_or(FdError::Net(NetError::ConnectionRefused))?;
+        match &self.socket_mut(listener_id)?.state {
+            SocketState::Listening { pending, backlog } if pending.len() < *backlog => {}
+            _ => return Err(FdError::Net(NetError::ConnectionRefused)),
+        }
+
+        // The server's end waits in the backlog, owned by the listener
+        let mut server = Socket::new(SocketKind::Stream);
+        server.refcount = 0;
+        server.port = Some(port);
+        server.accepted = true;
+        server.dest = Some((LOOPBACK_ADDR, local_port));
+        server.state = SocketState::Connected { peer: socket_id };
+        let server_id = self.insert(server);
+
+        let socket = self.socket_mut(socket_id)?;
+        socket.dest = Some(dest);
+        socket.state = SocketState::Connected { peer: server_id };
+
+        let listener = self.socket_mut(listener_id)?;
+        if let SocketState::Listening { pending, .. } = &mut listener.state {
+            pending.push_back(server_id);
+        }
+        listener.wake();
+        Ok(())
+    }
+
+    /// Send on a connected stream, or send one datagram to `dest` (or the
+    /// connected destination). Datagrams nobody has room for are dropped.
+    pub fn send(
+        &mut self,
+        socket_id: usize,
+        buf: &[u8],
+        dest: Option<(u32, u16)>,
+    ) -> Result<usize, FdError> {
+        if let Some((addr, _)) = dest {
+            check_addr(addr)?;
+        }
+        let socket = self.socket_mut(socket_id)?;
+        match socket.kind {
+            SocketKind::Stream => {
+                if let SocketState::Remote { conn } = &mut socket.state {
+                    return match conn.state {
+                        TcpState::SynSent | TcpState::SynReceived => Err(FdError::WouldBlock),
+                        TcpState::Established | TcpState::CloseWait => {
+                            match conn.send(buf) {
+                                0 if !buf.is_empty() => Err(FdError::WouldBlock),
+                                sent => Ok(sent),
+                            }
+                        }
+                        TcpState::Closing | TcpState::Closed => Err(FdError::BrokenPipe),
+                    };
+                }
+                let SocketState::Connected { peer } = socket.state else {
+                    return Err(match socket.state {
+                        SocketState::Disconnected => FdError::BrokenPipe,
+                        _ => FdError::Net(NetError::NotConnected),
+                    });
+                };
+                let peer = self.socket_mut(peer)?;
+                let room = SOCKET_BUF_SIZE - peer.stream.len();
+                if room == 0 && !buf.is_empty() {
+                    return Err(FdError::WouldBlock);
+                }
+                let written = buf.len().min(room);
+                peer.stream.extend(buf[..written].iter().copied());
+                peer.wake();
+                Ok(written)
+            }
+            SocketKind::Datagram => {
+                let (addr, port) = dest
+                    .or(socket.dest)
+                    .ok_or(FdError::Net(NetError::NotConnected))?;
+                let from = self.autobind(socket_id)?;
+                if !is_local(addr) {
+                    if buf.len() > net::MAX_UDP_PAYLOAD {
+                        return Err(FdError::InvalidArgument);
+                    }
+                    net::send_udp(from, (addr, port), buf);
+                    return Ok(buf.len());
+                }
+                if buf.len() > SOCKET_BUF_SIZE {
+                    return Err(FdError::InvalidArgument);
+                }
+                let target = self
+                    .find_bound(SocketKind::Datagram, port)
+                    .ok_or(FdError::Net(NetError::ConnectionRefused))?;
+                let target = self.socket_mut(target)?;
+                if target.datagrams.len() < MAX_DATAGRAMS {
+                    target.datagrams.push_back((LOOPBACK_ADDR, from, buf.to_vec()));
+                    target.wake();
+                }
+                Ok(buf.len())
+            }
+        }
+    }
+
+    /// Receive stream bytes or one datagram (truncated to `buf`). Returns
+    /// the length and the sender's address and port; 0 bytes means the
+    /// peer closed.
+    pub fn recv(
+        &mut self,
+        socket_id: usize,
+        buf: &mut [u8],
+    ) -> Result<(usize, (u32, u16)), FdError> {
+        let socket = self.socket_mut(socket_id)?;
+        match socket.kind {
+            SocketKind::Stream => {
+                let from = socket.dest.unwrap_or((LOOPBACK_ADDR, 0));
+                if let SocketState::Remote { conn } = &mut socket.state {
+                    if conn.received.is_empty() {
+                        return match conn.state {
+                            TcpState::CloseWait | TcpState::Closing | TcpState::Closed => {
+                                Ok((0, from))
+                            }
+                            _ if buf.is_empty() => Ok((0, from)),
+                            _ => Err(FdError::WouldBlock),
+                        };
+                    }
+                    return Ok((conn.read(buf), from));
+                }
+                let peer = match socket.state {
+                    SocketState::Connected { peer } => Some(peer),
+                    SocketState::Disconnected => None,
+                    _ => return Err(FdError::Net(NetError::NotConnected)),
+                };
+                if socket.stream.is_empty() {
+                    return match peer {
+                        Some(_) if !buf.is_empty() => Err(FdError::WouldBlock),
+                        _ => Ok((0, from)),
+                    };
+                }
+                let mut read = 0;
+                while read < buf.len() {
+                    let Some(byte) = socket.stream.pop_front() else {
+                        break;
+                    };
+                    buf[read] = byte;
+                    read += 1;
+                }
+                // Senders block on their own socket, so wake the peer
+                if let Some(peer) = peer {
+                    self.socket_mut(peer)?.wake();
+                }
+                Ok((read, from))
+            }
+            SocketKind::Datagram => {
+                let (addr, port, data) =
+                    socket.datagrams.pop_front().ok_or(FdError::WouldBlock)?;
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                Ok((len, (addr, port)))
+            }
+        }
+    }
+
+    /// Queue a datagram that arrived from the network on `port`; dropped
+    /// if nobody is bound there or the queue is full
+    pub fn deliver_datagram(&mut self, port: u16, from: (u32, u16), data: &[u8]) {
+        let Some(target) = self.find_bound(SocketKind::Datagram, port) else {
+            return;
+        };
+        let Ok(target) = self.socket_mut(target) else {
+            return;
+        };
+        if target.datagrams.len() < MAX_DATAGRAMS {
+            target.datagrams.push_back((from.0, from.1, data.to_vec()));
+            target.wake();
+        }
+    }
+
+    /// Hand a TCP segment from `src` to its connection, starting one if it
+    /// is a SYN for a listening port. False if nothing wants it.
+    pub fn tcp_input(&mut self, src: u32, seg: &Segment) -> bool {
+        let remote = (src, seg.src_port);
+        let ours = |conn: &TcpConn| conn.local_port == seg.dst_port && conn.remote == remote;
+        for socket in self.sockets.iter_mut().flatten() {
+            if socket.tcp_mut().is_some_and(|conn| ours(conn)) {
+                let wake = socket.tcp_mut().is_some_and(|conn| conn.input(seg));
+                if wake {
+                    socket.wake();
+                }
+                return true;
+            }
+        }
+        if let Some(conn) = self.orphans.iter_mut().find(|conn| ours(conn)) {
+            conn.input(seg);
+            return true;
+        }
+
+        if seg.flags & (net::TCP_SYN | net::TCP_ACK) != net::TCP_SYN {
+            return false;
+        }
+        let Some(listener_id) = self.find_bound(SocketKind::Stream, seg.dst_port) else {
+            return false;
+        };
+        match self.sockets[listener_id].as_ref().map(|s| &s.state) {
+            Some(SocketState::Listening { pending, backlog }) if pending.len() < *backlog => {}
+            _ => return false,
+        }
+        // Like a loopback connection, the server end waits in the backlog;
+        // it reports WouldBlock until the handshake completes
+        let mut server = Socket::new(SocketKind::Stream);
+        server.refcount = 0;
+        server.port = Some(seg.dst_port);
+        server.accepted = true;
+        server.dest = Some(remote);
+        server.state = SocketState::Remote {
+            conn: TcpConn::accept(seg.dst_port, remote, seg),
+        };
+        let server_id = self.insert(server);
+        if let Ok(listener) = self.socket_mut(listener_id) {
+            if let SocketState::Listening { pending, .. } = &mut listener.state {
+                pending.push_back(server_id);
+            }
+            listener.wake();
+        }
+        true
+    }
+
+    /// Run TCP retransmission timers and drop finished orphans
+    pub fn tcp_tick(&mut self) {
+        for socket in self.sockets.iter_mut().flatten() {
+            if socket.tcp_mut().is_some_and(|conn| conn.tick()) {
+                socket.wake();
+            }
+        }
+        for conn in &mut self.orphans {
+            conn.tick();
+        }
+        self.orphans.retain(|conn| conn.state != TcpState::Closed);
+    }
+
+    /// Register a process that will block on this socket
+    pub fn mark_waiting(&mut self, socket_id: usize, pid: Pid) -> Result<(), FdError> {
+        let socket = self.socket_mut(socket_id)?;
+        if !socket.waiters.contains(&pid) {
+            socket.waiters.push(pid);
+        }
+        Ok(())
+    }
+
+    /// Drop one descriptor reference; the last one closes the socket
+    pub fn close(&mut self, socket_id: usize) -> Result<(), FdError> {
+        let socket = self.socket_mut(socket_id)?;
+        socket.refcount = socket.refcount.saturating_sub(1);
+        if socket.refcount == 0 {
+            self.destroy(socket_id)?;
+        }
+        Ok(())
+    }
+
+    fn destroy(&mut self, socket_id: usize) -> Result<(), FdError> {
+        let Some(mut socket) = self.sockets.get_mut(socket_id).and_then(Option::take) else {
+            return Err(FdError::BadFd);
+        };
+        socket.wake();
+        match socket.state {
+            // The peer reads what is buffered, then EOF
+            SocketState::Connected { peer } => {
+                if let Ok(peer) = self.socket_mut(peer) {
+                    peer.state = SocketState::Disconnected;
+                    peer.wake();
+                }
+            }
+            // Connections nobody accepted are refused after the fact
+            SocketState::Listening { pending, .. } => {
+                for conn in pending {
+                    let _ = self.destroy(conn);
+                }
+            }
+            // Finish the FIN exchange without the socket
+            SocketState::Remote { mut conn } => {
+                conn.close();
+                if conn.state == TcpState::Closing {
+                    self.orphans.push(conn);
+                }
+            }
+            SocketState::Idle | SocketState::Disconnected => {}
+        }
+        while matches!(self.sockets.last(), Some(None)) {
+            self.sockets.pop();
+        }
+        Ok(())
+    }
+}
+
+/// Loopback, the any-address and our own address stay on this host
+fn is_local(addr: u32) -> bool {
+    addr == 0 || addr >> 24 == 127 || addr == net::LOCAL_ADDR
+}
+
+/// Other hosts are only reachable through a network device
+fn check_addr(addr: u32) -> Result<(), FdError> {
+    if is_local(addr) || net::is_up() {
+        Ok(())
+    } else {
+        Err(FdError::Net(NetError::Unreachable))
+    }
+}
+
+enum SocketState {
+    /// Fresh, or a datagram socket (which never leaves this state)
+    Idle,
+    /// Stream listener with connections waiting for accept
+    Listening {
+        pending: VecDeque<usize>,
+        backlog: usize,
+    },
+    /// Stream joined to the socket `peer`
+    Connected { peer: usize },
+    /// Stream whose peer has closed
+    Disconnected,
+    /// Stream to another host
+    Remote { conn: TcpConn },
+}
+
+struct Socket {
+    kind: SocketKind,
+    state: SocketState,
+    /// Local port, once bound
+    port: Option<u16>,
+    /// Server end of a connection; shares the listener's port
+    accepted: bool,
+    /// Remote address and port: the stream peer, or a datagram socket's
+    /// default
+    dest: Option<(u32, u16)>,
+    /// Stream bytes waiting to be received from a local peer
+    stream: VecDeque<u8>,
+    /// Datagrams waiting to be received, with the sender's address and port
+    datagrams: VecDeque<(u32, u16, Vec<u8>)>,
+    refcount: usize,
+    waiters: Vec<Pid>,
+}
+
+impl Socket {
+    fn new(kind: SocketKind) -> Self {
+        Self {
+            kind,
+            state: SocketState::Idle,
+            port: None,
+            accepted: false,
+            dest: None,
+            stream: VecDeque::new(),
+            datagrams: VecDeque::new(),
+            refcount: 1,
+            waiters: Vec::new(),
+        }
+    }
+
+    fn wake(&mut self) {
+        for pid in core::mem::take(&mut self.waiters) {
+            Scheduler::unblock(pid);
+        }
+    }
+
+    fn tcp_mut(&mut self) -> Option<&mut TcpConn> {
+        match &mut self.state {
+            SocketState::Remote { conn } => Some(conn),
+            _ => None,
+        }
+    }
+}
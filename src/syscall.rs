@@ -1,12 +1,15 @@
-use alloc::{string::String, vec::Vec};
-use core::{fmt::Write, ptr, slice, str};
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::{ptr, slice, str};
+use core::sync::atomic::Ordering;
 
 use riscv::register::sepc;
 use riscv_rt::TrapFrame;
+use spin::Mutex;
 
 use crate::fs::{self, FsError};
-use crate::uart;
-use crate::proc::PROCESS_TABLE;
+use crate::console;
+use crate::klog;
+use crate::proc::{PROCESS_TABLE, Pid, ROOT_UID, Uid};
 
 pub const SYS_WRITE: usize = 1;
 pub const SYS_EXIT: usize = 2;
@@ -23,8 +26,89 @@ pub const SYS_DUP2: usize = 12;
 pub const SYS_PIPE: usize = 13;
 pub const SYS_SPAWN: usize = 14;
 pub const SYS_WAIT: usize = 15;
+pub const SYS_GETDENTS: usize = 16;
+pub const SYS_GETRLIMIT: usize = 17;
+pub const SYS_SETRLIMIT: usize = 18;
+pub const SYS_FCNTL: usize = 19;
+pub const SYS_OPENAT: usize = 20;
+pub const SYS_FGETDENTS: usize = 21;
+pub const SYS_OPENPTY: usize = 22;
+pub const SYS_SETPGID: usize = 23;
+pub const SYS_TCSETPGRP: usize = 24;
+pub const SYS_KILL: usize = 25;
+pub const SYS_WAITPID: usize = 26;
+pub const SYS_SOCKET: usize = 27;
+pub const SYS_BIND: usize = 28;
+pub const SYS_LISTEN: usize = 29;
+pub const SYS_ACCEPT: usize = 30;
+pub const SYS_CONNECT: usize = 31;
+pub const SYS_SEND: usize = 32;
+pub const SYS_RECV: usize = 33;
+pub const SYS_EVENTFD: usize = 34;
+pub const SYS_SIGNAL: usize = 35;
+pub const SYS_PROCINFO: usize = 36;
+pub const SYS_SCHEDSTAT: usize = 37;
+pub const SYS_GETUID: usize = 38;
+pub const SYS_SETUID: usize = 39;
+pub const SYS_GETGID: usize = 40;
+pub const SYS_SETGID: usize = 41;
+pub const SYS_CHOWN: usize = 42;
+pub const SYS_CONSOLE_LOG: usize = 43;
+pub const SYS_GETRANDOM: usize = 44;
+pub const SYS_FSYNC: usize = 45;
+pub const SYS_CLOCK_GETTIME: usize = 46;
+pub const SYS_KLOG_READ: usize = 47;
+pub const SYS_KLOG_LEVEL: usize = 48;
+pub const SYS_KLOG_SUBSYSTEM: usize = 49;
+pub const SYS_STAT: usize = 50;
+pub const SYS_LSEEK: usize = 51;
+pub const SYS_RENAME: usize = 52;
+pub const SYS_BRK: usize = 53;
+pub const SYS_CHDIR: usize = 54;
+pub const SYS_GETCWD: usize = 55;
+pub const SYS_FSCHECK: usize = 56;
+pub const SYS_STATFS: usize = 57;
+pub const SYS_DISKUSAGE: usize = 58;
+pub const SYS_COMPACT: usize = 59;
+pub const SYS_UNTAR: usize = 60;
+pub const SYS_MOUNT: usize = 61;
+pub const SYS_UMOUNT: usize = 62;
+pub const SYS_MOUNTS: usize = 63;
+pub const SYS_MKFS: usize = 64;
+pub const SYS_MAXPROCS: usize = 65;
+pub const SYS_BENCH: usize = 66;
+pub const SYS_KLOG_CLEAR: usize = 67;
+pub const SYS_BENCH_SWITCH: usize = 68;
+
+/// spawn pgid argument: the child leads a new process group
+const SPAWN_NEW_PGRP: usize = usize::MAX;
+
+// spawn file action ops
+const SPAWN_ACTION_DUP2: usize = 1;
+const SPAWN_ACTION_CLOSE: usize = 2;
+const SPAWN_ACTION_OPEN: usize = 3;
+/// Most file actions one spawn accepts
+const SPAWN_MAX_ACTIONS: usize = 16;
+
+// waitpid options
+const WNOHANG: usize = 1;
+const WUNTRACED: usize = 2;
+
+/// openat dirfd meaning "relative to the working directory" (Linux value)
+const AT_FDCWD: usize = -100isize as usize;
+
+
+// fcntl commands (Linux numbering)
+const F_GETFD: usize = 1;
+const F_SETFD: usize = 2;
+const FD_CLOEXEC: usize = 1;
+const F_SETPIPE_SZ: usize = 1031;
+const F_GETPIPE_SZ: usize = 1032;
 
 const ENOSYS: isize = -38;
+const ENOEXEC: isize = -8;
+const EPERM: isize = -1;
+const EACCES: isize = -13;
 const EBADF: isize = -9;
 const EINVAL: isize = -22;
 const EFAULT: isize = -14;
@@ -37,6 +121,22 @@ const ENOTEMPTY: isize = -39;
 const EIO: isize = -5;
 const ENXIO: isize = -6;
 const ENAMETOOLONG: isize = -36;
+const EFBIG: isize = -27;
+const EDQUOT: isize = -122;
+const EBUSY: isize = -16;
+const EXDEV: isize = -18;
+const EROFS: isize = -30;
+const ESRCH: isize = -3;
+const EAGAIN: isize = -11;
+const ENOMEM: isize = -12;
+const EADDRINUSE: isize = -98;
+const ENETUNREACH: isize = -101;
+const EISCONN: isize = -106;
+const ENOTCONN: isize = -107;
+const ECONNREFUSED: isize = -111;
+const EOPNOTSUPP: isize = -95;
+const ENOTSOCK: isize = -88;
+const ESPIPE: isize = -29;
 
 pub fn dispatch(trap_frame: &TrapFrame) -> usize {
     let syscall_no = trap_frame.a0;
@@ -56,6 +156,59 @@ pub fn dispatch(trap_frame: &TrapFrame) -> usize {
         SYS_PIPE => sys_pipe(trap_frame),
         SYS_SPAWN => sys_spawn(trap_frame),
         SYS_WAIT => sys_wait(trap_frame),
+        SYS_GETDENTS => sys_getdents(trap_frame),
+        SYS_GETRLIMIT => sys_getrlimit(trap_frame),
+        SYS_SETRLIMIT => sys_setrlimit(trap_frame),
+        SYS_FCNTL => sys_fcntl(trap_frame),
+        SYS_OPENAT => sys_openat(trap_frame),
+        SYS_FGETDENTS => sys_fgetdents(trap_frame),
+        SYS_OPENPTY => sys_openpty(trap_frame),
+        SYS_SETPGID => sys_setpgid(trap_frame),
+        SYS_TCSETPGRP => sys_tcsetpgrp(trap_frame),
+        SYS_KILL => sys_kill(trap_frame),
+        SYS_WAITPID => sys_waitpid(trap_frame),
+        SYS_SOCKET => sys_socket(trap_frame),
+        SYS_BIND => sys_bind(trap_frame),
+        SYS_LISTEN => sys_listen(trap_frame),
+        SYS_ACCEPT => sys_accept(trap_frame),
+        SYS_CONNECT => sys_connect(trap_frame),
+        SYS_SEND => sys_send(trap_frame),
+        SYS_RECV => sys_recv(trap_frame),
+        SYS_EVENTFD => sys_eventfd(trap_frame),
+        SYS_SIGNAL => sys_signal(trap_frame),
+        SYS_PROCINFO => sys_procinfo(trap_frame),
+        SYS_SCHEDSTAT => sys_schedstat(trap_frame),
+        SYS_GETUID => sys_getuid(trap_frame),
+        SYS_SETUID => sys_setuid(trap_frame),
+        SYS_GETGID => sys_getgid(trap_frame),
+        SYS_SETGID => sys_setgid(trap_frame),
+        SYS_CHOWN => sys_chown(trap_frame),
+        SYS_CONSOLE_LOG => sys_console_log(trap_frame),
+        SYS_GETRANDOM => sys_getrandom(trap_frame),
+        SYS_FSYNC => sys_fsync(trap_frame),
+        SYS_CLOCK_GETTIME => sys_clock_gettime(trap_frame),
+        SYS_KLOG_READ => sys_klog_read(trap_frame),
+        SYS_KLOG_LEVEL => sys_klog_level(trap_frame),
+        SYS_KLOG_SUBSYSTEM => sys_klog_subsystem(trap_frame),
+        SYS_STAT => sys_stat(trap_frame),
+        SYS_LSEEK => sys_lseek(trap_frame),
+        SYS_RENAME => sys_rename(trap_frame),
+        SYS_BRK => sys_brk(trap_frame),
+        SYS_CHDIR => sys_chdir(trap_frame),
+        SYS_GETCWD => sys_getcwd(trap_frame),
+        SYS_FSCHECK => sys_fscheck(trap_frame),
+        SYS_STATFS => sys_statfs(trap_frame),
+        SYS_DISKUSAGE => sys_diskusage(trap_frame),
+        SYS_COMPACT => sys_compact(trap_frame),
+        SYS_UNTAR => sys_untar(trap_frame),
+        SYS_MOUNT => sys_mount(trap_frame),
+        SYS_UMOUNT => sys_umount(trap_frame),
+        SYS_MOUNTS => sys_mounts(trap_frame),
+        SYS_MKFS => sys_mkfs(trap_frame),
+        SYS_MAXPROCS => sys_maxprocs(trap_frame),
+        SYS_BENCH => sys_bench(trap_frame),
+        SYS_KLOG_CLEAR => sys_klog_clear(trap_frame),
+        SYS_BENCH_SWITCH => sys_bench_switch(trap_frame),
         _ => Err(SysError::NoSys),
     };
 
@@ -64,18 +217,27 @@ pub fn dispatch(trap_frame: &TrapFrame) -> usize {
         Err(SysError::NoSys) => ENOSYS,
         Err(SysError::BadFd) => EBADF,
         Err(SysError::InvalidUtf8) => EINVAL,
+        Err(SysError::InvalidArgument) => EINVAL,
         Err(SysError::Fault) => EFAULT,
         Err(SysError::Fs(err)) => fs_errno(err),
         Err(SysError::Fd(err)) => fd_errno(err),
         Err(SysError::Proc(err)) => proc_errno(err),
         Err(SysError::Child) => -10, // ECHILD
         Err(SysError::NoProcess) => EBADF,
+        Err(SysError::NoSuchProcess) => ESRCH,
+        Err(SysError::NotPermitted) => EPERM,
+        Err(SysError::OutOfMemory) => ENOMEM,
     };
 
     code as usize
 }
 
 unsafe fn handle_ecall(trap_frame: &mut TrapFrame) {
+    // sepc still points at the ecall, so a held-back caller re-issues it
+    if crate::scheduler::Scheduler::hold_while_parked() {
+        return;
+    }
+
     let sepc_value = unsafe { sepc::read().wrapping_add(4) };
     unsafe { sepc::write(sepc_value) };
 
@@ -83,17 +245,44 @@ unsafe fn handle_ecall(trap_frame: &mut TrapFrame) {
     let current_pid = crate::proc::PROCESS_TABLE.lock().get_current_pid();
 
     let retval = dispatch(trap_frame);
-    trap_frame.a0 = retval;
+    if retval as isize == EAGAIN && current_is_blocked() {
+        // The caller sleeps inside the syscall: point sepc back at the
+        // ecall and keep the syscall number in a0, so once woken it
+        // re-issues the call and only ever sees the final result
+        unsafe { sepc::write(sepc_value.wrapping_sub(4)) };
+    } else {
+        trap_frame.a0 = retval;
+    }
+
+    if crate::signal::deliver_pending() {
+        return;
+    }
 
     // After syscall, check if we should context switch
-    uart::write_str(&alloc::format!("[syscall] pid={} sys={} ret={} calling maybe_switch\n", current_pid, syscall_num, retval as isize));
-    crate::scheduler::Scheduler::maybe_switch(trap_frame);
+    klog!(Syscall, Debug, "[syscall] pid={} sys={} ret={} calling maybe_switch", current_pid, syscall_num, retval as isize);
+    let copied_before = crate::process::WINDOW_BYTES_COPIED.load(Ordering::Relaxed);
+    crate::scheduler::Scheduler::maybe_switch();
     let after_pid = crate::proc::PROCESS_TABLE.lock().get_current_pid();
     if after_pid != current_pid {
-        uart::write_str(&alloc::format!("[syscall] SWITCHED to pid={}\n", after_pid));
+        let copied = crate::process::WINDOW_BYTES_COPIED.load(Ordering::Relaxed) - copied_before;
+        klog!(
+            Sched,
+            Debug,
+            "[syscall] SWITCHED to pid={} (copied {} bytes)",
+            after_pid,
+            copied
+        );
     }
 }
 
+/// True if the syscall just handled put the caller to sleep
+fn current_is_blocked() -> bool {
+    PROCESS_TABLE
+        .lock()
+        .current()
+        .is_some_and(|p| p.state == crate::proc::ProcessState::Blocked)
+}
+
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn SupervisorEnvCall(trap_frame: &mut TrapFrame) {
     unsafe {
@@ -126,9 +315,26 @@ fn sys_write(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     // Capture the writer's PID to use consistently
     let writer_pid = PROCESS_TABLE.lock().get_current_pid();
 
+    if let Some(file) = regular_file(writer_pid, fd) {
+        let fsize = PROCESS_TABLE
+            .lock()
+            .get(writer_pid)
+            .map_or(crate::rlimit::RLIM_INFINITY, |p| p.rlimits.fsize.soft);
+        let mut file = file.lock();
+        let result = if fsize != crate::rlimit::RLIM_INFINITY {
+            file.write_capped(bytes, fsize)
+        } else {
+            file.write(bytes)
+        };
+        return result.map_err(SysError::Fd);
+    }
+
     loop {
         // Use writer_pid to get the correct process's fd table
         let mut pipe_waiting_on: Option<usize> = None;
+        let mut pty_waiting_on: Option<(usize, crate::pty::PtyEnd)> = None;
+        let mut socket_waiting_on: Option<usize> = None;
+        let mut event_waiting_on: Option<usize> = None;
         let result = {
             let mut table = PROCESS_TABLE.lock();
             if let Some(proc) = table.get_mut(writer_pid) {
@@ -140,6 +346,18 @@ fn sys_write(trap_frame: &TrapFrame) -> Result<usize, SysError> {
                                 pipe_waiting_on = Some(pipe_fd.pipe_id);
                                 Ok(pipe_fd.write(bytes))
                             }
+                            crate::fd::FileDescriptor::Pty(pty_fd) => {
+                                pty_waiting_on = Some((pty_fd.pty_id, pty_fd.end));
+                                Ok(pty_fd.write(bytes))
+                            }
+                            crate::fd::FileDescriptor::Socket(socket_fd) => {
+                                socket_waiting_on = Some(socket_fd.socket_id);
+                                Ok(socket_fd.write(bytes))
+                            }
+                            crate::fd::FileDescriptor::Event(event_fd) => {
+                                event_waiting_on = Some(event_fd.event_id);
+                                Ok(event_fd.write(bytes))
+                            }
                             _ => Ok(fd_entry.write(bytes)),
                         }
                     })
@@ -157,6 +375,21 @@ fn sys_write(trap_frame: &TrapFrame) -> Result<usize, SysError> {
                         .lock()
                         .mark_writer_waiting(pipe_id, writer_pid);
                 }
+                if let Some((pty_id, end)) = pty_waiting_on {
+                    let _ = crate::pty::PTY_TABLE
+                        .lock()
+                        .mark_waiting(pty_id, end, writer_pid);
+                }
+                if let Some(socket_id) = socket_waiting_on {
+                    let _ = crate::socket::SOCKET_TABLE
+                        .lock()
+                        .mark_waiting(socket_id, writer_pid);
+                }
+                if let Some(event_id) = event_waiting_on {
+                    let _ = crate::eventfd::EVENT_TABLE
+                        .lock()
+                        .mark_waiting(event_id, writer_pid);
+                }
                 crate::scheduler::Scheduler::block_current();
                 return Err(SysError::Fd(crate::fd::FdError::WouldBlock));
             }
@@ -181,10 +414,13 @@ fn sys_exit(trap_frame: &TrapFrame) -> Result<usize, SysError> {
             }
         }
     }
-    let mut buf = String::new();
-    let _ = writeln!(&mut buf, "\n[process {} exited with code {}]",
-        PROCESS_TABLE.lock().get_current_pid(), code);
-    uart::write_str(&buf);
+    klog!(
+        Syscall,
+        Debug,
+        "[process {} exited with code {}]",
+        PROCESS_TABLE.lock().get_current_pid(),
+        code
+    );
 
     // Process is now Exited, scheduler will switch to another process
     Ok(code as usize)
@@ -204,7 +440,20 @@ fn sys_file_write(trap_frame: &TrapFrame) -> Result<usize, SysError> {
         unsafe { slice::from_raw_parts(data_ptr, data_len) }
     };
 
+    let fsize = PROCESS_TABLE
+        .lock()
+        .current()
+        .map_or(crate::rlimit::RLIM_INFINITY, |p| p.rlimits.fsize.soft);
+    if data_len > fsize {
+        return Err(SysError::Fs(FsError::FileTooLarge));
+    }
+
+    let uid = current_uid();
+    let creating = check_write(uid, &path, true)?;
     fs::write_file(&path, data).map_err(SysError::Fs)?;
+    if creating {
+        claim(uid, &path)?;
+    }
     Ok(data_len)
 }
 
@@ -213,8 +462,8 @@ fn sys_file_read(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     let buf_ptr = trap_frame.a3 as *mut u8;
     let buf_len = trap_frame.a4;
 
-    if buf_len > 0 && buf_ptr.is_null() {
-        return Err(SysError::Fault);
+    if buf_len > 0 {
+        check_user_write(buf_ptr, buf_len)?;
     }
 
     let contents = fs::read_file(&path).map_err(SysError::Fs)?;
@@ -227,31 +476,460 @@ fn sys_file_read(trap_frame: &TrapFrame) -> Result<usize, SysError> {
 
 fn sys_file_create(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     let path = read_path(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    let uid = current_uid();
+    check_owner(uid, parent_dir(&path))?;
     fs::create_file(&path).map_err(SysError::Fs)?;
+    claim(uid, &path)?;
     Ok(0)
 }
 
 fn sys_file_delete(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     let path = read_path(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    check_owner(current_uid(), &path)?;
     fs::remove_file(&path).map_err(SysError::Fs)?;
     Ok(0)
 }
 
 fn sys_dir_create(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     let path = read_path(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    let uid = current_uid();
+    check_owner(uid, parent_dir(&path))?;
     fs::mkdir(&path).map_err(SysError::Fs)?;
+    claim(uid, &path)?;
     Ok(0)
 }
 
 fn sys_dir_delete(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     let path = read_path(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    check_owner(current_uid(), &path)?;
     fs::remove_directory(&path).map_err(SysError::Fs)?;
     Ok(0)
 }
 
+/// rename(from, from_len, to, to_len): move an entry within its filesystem.
+/// The caller must own it, and own whatever it replaces or the directory
+/// it moves into.
+fn sys_rename(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let from = read_path(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    let to = read_path(trap_frame.a3 as *const u8, trap_frame.a4)?;
+    let uid = current_uid();
+    check_owner(uid, &from)?;
+    check_write(uid, &to, true)?;
+    fs::rename(&from, &to).map_err(SysError::Fs)?;
+    Ok(0)
+}
+
+/// chdir(path, len): make `path` the working directory that relative paths
+/// resolve against; children inherit it
+fn sys_chdir(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let path = read_path(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    fs::ensure_directory(&path).map_err(SysError::Fs)?;
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current_mut().ok_or(SysError::NoProcess)?;
+    process.cwd = path;
+    Ok(0)
+}
+
+/// getcwd(buf, len): copy the working directory, without a trailing NUL,
+/// into the user buffer and return its length
+fn sys_getcwd(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let buf_ptr = trap_frame.a1 as *mut u8;
+    let buf_len = trap_frame.a2;
+    let cwd = current_cwd();
+    if cwd.len() > buf_len {
+        return Err(SysError::Fs(FsError::NameTooLong));
+    }
+    check_user_write(buf_ptr, cwd.len())?;
+    unsafe { ptr::copy_nonoverlapping(cwd.as_ptr(), buf_ptr, cwd.len()) };
+    Ok(cwd.len())
+}
+
+/// Fill a user buffer with `[kind, name_len, name...]` records for the
+/// directory at a1/a2, resuming from and updating the cookie at a3.
+fn sys_getdents(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let path = read_path(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    let cookie_ptr = trap_frame.a3 as *mut usize;
+    let buf_ptr = trap_frame.a4 as *mut u8;
+    let buf_len = trap_frame.a5;
+
+    check_user_write(cookie_ptr, 1)?;
+    if buf_len > 0 {
+        check_user_write(buf_ptr, buf_len)?;
+    }
+    let buf = if buf_len == 0 {
+        &mut [][..]
+    } else {
+        unsafe { slice::from_raw_parts_mut(buf_ptr, buf_len) }
+    };
+
+    let mut cookie = unsafe { ptr::read(cookie_ptr) };
+    let written = crate::fd::read_dirents(&path, &mut cookie, buf).map_err(SysError::Fd)?;
+    unsafe { ptr::write(cookie_ptr, cookie) };
+    Ok(written)
+}
+
+/// What stat copies out. `kind` is 1 for a file and 2 for a directory,
+/// as in getdents records.
+#[repr(C)]
+struct Stat {
+    size: u64,
+    /// Seconds since the Unix epoch
+    mtime: u64,
+    kind: u32,
+    owner: u32,
+}
+
+/// stat(path, len, *mut Stat): describe the file or directory at `path`
+fn sys_stat(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let path = read_path(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    let out = trap_frame.a3 as *mut Stat;
+    check_user_write(out, 1)?;
+    let metadata = fs::metadata(&path).map_err(SysError::Fs)?;
+    let stat = Stat {
+        size: metadata.size,
+        mtime: metadata.mtime as u64,
+        kind: if metadata.is_dir { 2 } else { 1 },
+        owner: metadata.owner as u32,
+    };
+    unsafe { ptr::write(out, stat) };
+    Ok(0)
+}
+
+/// Totals fscheck copies out next to the issue text
+#[repr(C)]
+struct FsckSummary {
+    files: u64,
+    directories: u64,
+    used_blocks: u64,
+    issues: u64,
+    /// 1 if the check repaired the filesystem
+    repaired: u64,
+}
+
+/// fscheck(repair, *mut FsckSummary, buf, len): check the root filesystem,
+/// and with a non-zero `repair` fix it (root only). Writes one line per
+/// issue into buf, as many whole lines as fit; returns the bytes written.
+fn sys_fscheck(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let repair = trap_frame.a1 != 0;
+    let out = trap_frame.a2 as *mut FsckSummary;
+    let buf_ptr = trap_frame.a3 as *mut u8;
+    let buf_len = trap_frame.a4;
+    check_user_write(out, 1)?;
+    if buf_len > 0 {
+        check_user_write(buf_ptr, buf_len)?;
+    }
+    if repair && current_uid() != ROOT_UID {
+        return Err(SysError::NotPermitted);
+    }
+    let buf = if buf_len == 0 {
+        &mut [][..]
+    } else {
+        unsafe { slice::from_raw_parts_mut(buf_ptr, buf_len) }
+    };
+
+    let report = fs::check(repair).map_err(SysError::Fs)?;
+    let mut written = 0;
+    for issue in &report.issues {
+        let line = alloc::format!("{}\n", issue);
+        if written + line.len() > buf.len() {
+            break;
+        }
+        buf[written..written + line.len()].copy_from_slice(line.as_bytes());
+        written += line.len();
+    }
+    let summary = FsckSummary {
+        files: report.files as u64,
+        directories: report.directories as u64,
+        used_blocks: report.used_blocks as u64,
+        issues: report.issues.len() as u64,
+        repaired: report.repaired as u64,
+    };
+    unsafe { ptr::write(out, summary) };
+    Ok(written)
+}
+
+/// What statfs copies out
+#[repr(C)]
+struct StatFsInfo {
+    block_size: u64,
+    total_blocks: u64,
+    used_blocks: u64,
+    free_blocks: u64,
+    root_entries: u64,
+    max_name_len: u64,
+}
+
+/// statfs(path, len, *mut StatFsInfo): block counts for the filesystem
+/// holding `path`
+fn sys_statfs(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let path = read_path(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    let out = trap_frame.a3 as *mut StatFsInfo;
+    check_user_write(out, 1)?;
+    let stat = fs::statfs(&path).map_err(SysError::Fs)?;
+    let info = StatFsInfo {
+        block_size: stat.block_size as u64,
+        total_blocks: stat.total_blocks as u64,
+        used_blocks: stat.used_blocks as u64,
+        free_blocks: stat.free_blocks as u64,
+        root_entries: stat.root_entries as u64,
+        max_name_len: stat.max_name_len as u64,
+    };
+    unsafe { ptr::write(out, info) };
+    Ok(0)
+}
+
+/// diskusage(path, len, cookie, buf, buflen): one "blocks\tbytes\tpath"
+/// line for `path` and each directory below it, children first. Start
+/// with a zero cookie and call until it returns 0.
+fn sys_diskusage(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let path = read_path(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    let cookie_ptr = trap_frame.a3 as *mut usize;
+    let buf_ptr = trap_frame.a4 as *mut u8;
+    let buf_len = trap_frame.a5;
+
+    check_user_write(cookie_ptr, 1)?;
+    if buf_len > 0 {
+        check_user_write(buf_ptr, buf_len)?;
+    }
+    let buf = if buf_len == 0 {
+        &mut [][..]
+    } else {
+        unsafe { slice::from_raw_parts_mut(buf_ptr, buf_len) }
+    };
+
+    let mut cookie = unsafe { ptr::read(cookie_ptr) };
+    let usage = fs::disk_usage(&path).map_err(SysError::Fs)?;
+    let mut written = 0;
+    for entry in usage.iter().skip(cookie) {
+        let line = alloc::format!("{}\t{}\t{}\n", entry.blocks, entry.bytes, entry.path);
+        if written + line.len() > buf.len() {
+            if written == 0 {
+                // Not even one line fits
+                return Err(SysError::InvalidArgument);
+            }
+            break;
+        }
+        buf[written..written + line.len()].copy_from_slice(line.as_bytes());
+        written += line.len();
+        cookie += 1;
+    }
+    unsafe { ptr::write(cookie_ptr, cookie) };
+    Ok(written)
+}
+
+/// What compact copies out
+#[repr(C)]
+struct CompactSummary {
+    files_defragmented: u64,
+    files_skipped: u64,
+    blocks_moved: u64,
+    blocks_reclaimed: u64,
+}
+
+/// compact(*mut CompactSummary): defragment the root filesystem and
+/// hand its free tail back to the allocator; root only
+fn sys_compact(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let out = trap_frame.a1 as *mut CompactSummary;
+    check_user_write(out, 1)?;
+    if current_uid() != ROOT_UID {
+        return Err(SysError::NotPermitted);
+    }
+    let report = fs::compact(|_, _| {}).map_err(SysError::Fs)?;
+    let summary = CompactSummary {
+        files_defragmented: report.files_defragmented as u64,
+        files_skipped: report.files_skipped as u64,
+        blocks_moved: report.blocks_moved as u64,
+        blocks_reclaimed: report.blocks_reclaimed as u64,
+    };
+    unsafe { ptr::write(out, summary) };
+    Ok(0)
+}
+
+/// What untar copies out
+#[repr(C)]
+struct UntarSummary {
+    files: u64,
+    directories: u64,
+    skipped: u64,
+    bytes: u64,
+}
+
+/// untar(archive, len, dest, len, *mut UntarSummary): extract the ustar
+/// archive at `archive` into `dest`; root only, since the entries are not
+/// given to anyone
+fn sys_untar(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let archive = read_path(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    let dest = read_path(trap_frame.a3 as *const u8, trap_frame.a4)?;
+    let out = trap_frame.a5 as *mut UntarSummary;
+    check_user_write(out, 1)?;
+    if current_uid() != ROOT_UID {
+        return Err(SysError::NotPermitted);
+    }
+    let report = fs::untar(&archive, &dest).map_err(SysError::Fs)?;
+    let summary = UntarSummary {
+        files: report.files as u64,
+        directories: report.directories as u64,
+        skipped: report.skipped as u64,
+        bytes: report.bytes as u64,
+    };
+    unsafe { ptr::write(out, summary) };
+    Ok(0)
+}
+
+/// mount(device, len, path, len): mount the TinyFs on `device` (`vdb` or
+/// `/dev/vdb`) at the directory `path`; root only
+fn sys_mount(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let device = read_device(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    let point = read_path(trap_frame.a3 as *const u8, trap_frame.a4)?;
+    if current_uid() != ROOT_UID {
+        return Err(SysError::NotPermitted);
+    }
+    fs::mount(&device, &point).map_err(SysError::Fs)?;
+    Ok(0)
+}
+
+/// umount(path, len): detach the filesystem mounted at `path`; root only
+fn sys_umount(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let point = read_path(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    if current_uid() != ROOT_UID {
+        return Err(SysError::NotPermitted);
+    }
+    fs::unmount(&point).map_err(SysError::Fs)?;
+    Ok(0)
+}
+
+/// mounts(buf, len): one "device on point" line per mount, with " (ro)"
+/// for read-only ones. Returns the bytes written; the table is short, so
+/// a buffer that cannot hold all of it is an error.
+fn sys_mounts(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let buf_ptr = trap_frame.a1 as *mut u8;
+    let buf_len = trap_frame.a2;
+    if buf_len > 0 {
+        check_user_write(buf_ptr, buf_len)?;
+    }
+    let mut text = String::new();
+    for mount in fs::mounts() {
+        let flags = if mount.read_only { " (ro)" } else { "" };
+        let prefix = if mount.device == fs::HOST_DEVICE { "" } else { "/dev/" };
+        text.push_str(&alloc::format!("{}{} on {}{}\n", prefix, mount.device, mount.point, flags));
+    }
+    if text.len() > buf_len {
+        return Err(SysError::InvalidArgument);
+    }
+    if !text.is_empty() {
+        let buf = unsafe { slice::from_raw_parts_mut(buf_ptr, text.len()) };
+        buf.copy_from_slice(text.as_bytes());
+    }
+    Ok(text.len())
+}
+
+/// mkfs(device, len): write an empty TinyFs to an unmounted `device`;
+/// root only
+fn sys_mkfs(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let device = read_device(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    if current_uid() != ROOT_UID {
+        return Err(SysError::NotPermitted);
+    }
+    fs::format_device(&device).map_err(SysError::Fs)?;
+    Ok(0)
+}
+
+/// bench(device, len, buf, buflen): time reads and writes on `device`
+/// and the filesystem on it, and write the results into buf as text;
+/// root only. Returns the bytes written.
+fn sys_bench(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let device = read_device(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    let buf_ptr = trap_frame.a3 as *mut u8;
+    let buf_len = trap_frame.a4;
+    if buf_len > 0 {
+        check_user_write(buf_ptr, buf_len)?;
+    }
+    if current_uid() != ROOT_UID {
+        return Err(SysError::NotPermitted);
+    }
+    let mut text = String::new();
+    crate::bench::disk(&device, &mut text);
+    let len = text.len().min(buf_len);
+    if len > 0 {
+        let buf = unsafe { slice::from_raw_parts_mut(buf_ptr, len) };
+        buf.copy_from_slice(&text.as_bytes()[..len]);
+    }
+    Ok(len)
+}
+
+/// bench_switch(buf, len): time copying the caller out of the user window
+/// and back, whole and by live ranges, and write the report to `buf`.
+/// Returns the bytes written.
+fn sys_bench_switch(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let buf_ptr = trap_frame.a1 as *mut u8;
+    let buf_len = trap_frame.a2;
+    if buf_len > 0 {
+        check_user_write(buf_ptr, buf_len)?;
+    }
+    let mut text = String::new();
+    crate::bench::switch(&mut text);
+    let len = text.len().min(buf_len);
+    if len > 0 {
+        let buf = unsafe { slice::from_raw_parts_mut(buf_ptr, len) };
+        buf.copy_from_slice(&text.as_bytes()[..len]);
+    }
+    Ok(len)
+}
+
+/// lseek(fd, offset, whence): move a file's offset and return the new one
+fn sys_lseek(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let fd = trap_frame.a1;
+    let offset = trap_frame.a2 as isize;
+    let whence = trap_frame.a3;
+    with_current_fd_table_mut(|table| table.get_mut(fd)?.seek(offset, whence))
+}
+
+/// fgetdents(fd, buf, len): getdents over an open directory descriptor,
+/// which keeps its own position
+fn sys_fgetdents(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let fd = trap_frame.a1;
+    let buf_ptr = trap_frame.a2 as *mut u8;
+    let buf_len = trap_frame.a3;
+
+    if buf_len > 0 {
+        check_user_write(buf_ptr, buf_len)?;
+    }
+    let buf = if buf_len == 0 {
+        &mut [][..]
+    } else {
+        unsafe { slice::from_raw_parts_mut(buf_ptr, buf_len) }
+    };
+
+    with_current_fd_table_mut(|table| match table.get_mut(fd)? {
+        crate::fd::FileDescriptor::Dir(dir) => dir.read_entries(buf),
+        _ => Err(crate::fd::FdError::Fs(FsError::NotADirectory)),
+    })
+}
+
+/// Copy a path out of user memory and canonicalize it against the calling
+/// process's working directory, so every syscall sees the same absolute form.
 fn read_path(ptr: *const u8, len: usize) -> Result<String, SysError> {
+    read_path_at(current_cwd(), ptr, len)
+}
+
+/// Like `read_path`, but relative paths resolve against `base`
+fn read_path_at(base: String, ptr: *const u8, len: usize) -> Result<String, SysError> {
+    if len == 0 {
+        return Ok(base);
+    }
+    if ptr.is_null() {
+        return Err(SysError::Fault);
+    }
+    let bytes = unsafe { slice::from_raw_parts(ptr, len) };
+    let s = str::from_utf8(bytes).map_err(|_| SysError::InvalidUtf8)?;
+    Ok(fs::canonicalize(&base, s))
+}
+
+/// A block device name from user memory; unlike a path it is not
+/// resolved against the working directory
+fn read_device(ptr: *const u8, len: usize) -> Result<String, SysError> {
     if len == 0 {
-        return Ok(String::new());
+        return Err(SysError::InvalidArgument);
     }
     if ptr.is_null() {
         return Err(SysError::Fault);
@@ -261,18 +939,78 @@ fn read_path(ptr: *const u8, len: usize) -> Result<String, SysError> {
     Ok(String::from(s))
 }
 
+fn current_cwd() -> String {
+    PROCESS_TABLE
+        .lock()
+        .current()
+        .map(|process| process.cwd.clone())
+        .unwrap_or_else(|| String::from("/"))
+}
+
+/// Uid of the calling process; the kernel itself acts as root
+fn current_uid() -> Uid {
+    PROCESS_TABLE.lock().current().map_or(ROOT_UID, |p| p.uid)
+}
+
+/// Directory holding canonical `path`
+fn parent_dir(path: &str) -> &str {
+    match path.rsplit_once('/') {
+        Some(("", _)) | None => "/",
+        Some((parent, _)) => parent,
+    }
+}
+
+/// Root may change anything; other users only entries they own
+fn check_owner(uid: Uid, path: &str) -> Result<(), SysError> {
+    if uid == ROOT_UID || fs::owner(path).map_err(SysError::Fs)? == uid {
+        Ok(())
+    } else {
+        Err(SysError::Fs(FsError::PermissionDenied))
+    }
+}
+
+/// Check that `uid` may write `path`: it must own the file, or, if the
+/// file does not exist and `create` is set, the directory it would be
+/// created in. Returns true if the file does not exist yet.
+fn check_write(uid: Uid, path: &str, create: bool) -> Result<bool, SysError> {
+    match fs::owner(path) {
+        Ok(_) => check_owner(uid, path).map(|()| false),
+        Err(FsError::NotFound) if create => check_owner(uid, parent_dir(path)).map(|()| true),
+        Err(err) => Err(SysError::Fs(err)),
+    }
+}
+
+/// Give a newly created entry to its creator
+fn claim(uid: Uid, path: &str) -> Result<(), SysError> {
+    if uid != ROOT_UID {
+        fs::set_owner(path, uid).map_err(SysError::Fs)?;
+    }
+    Ok(())
+}
+
 fn fs_errno(err: FsError) -> isize {
     match err {
-        FsError::NotInitialized => EIO,
+        FsError::NotInitialized | FsError::Inconsistent | FsError::IoError => EIO,
         FsError::NameTooLong => ENAMETOOLONG,
         FsError::DirectoryFull | FsError::NoSpace => ENOSPC,
         FsError::NotFound => ENOENT,
-        FsError::InvalidEncoding | FsError::InvalidPath => EINVAL,
+        FsError::InvalidEncoding
+        | FsError::InvalidPath
+        | FsError::InvalidName
+        | FsError::InvalidArchive
+        | FsError::NoFilesystem => EINVAL,
         FsError::DeviceInitFailed(_) => ENXIO,
         FsError::NotADirectory | FsError::IsFile => ENOTDIR,
         FsError::AlreadyExists => EEXIST,
         FsError::DirectoryNotEmpty => ENOTEMPTY,
         FsError::IsDirectory => EISDIR,
+        FsError::FileTooLarge => EFBIG,
+        FsError::QuotaExceeded => EDQUOT,
+        FsError::PermissionDenied => EACCES,
+        FsError::Busy => EBUSY,
+        FsError::CrossDevice => EXDEV,
+        FsError::Unsupported => EOPNOTSUPP,
+        FsError::ReadOnly => EROFS,
     }
 }
 
@@ -282,9 +1020,27 @@ fn fd_errno(err: crate::fd::FdError) -> isize {
         crate::fd::FdError::TooManyOpen => -24, // EMFILE
         crate::fd::FdError::NotFound => ENOENT,
         crate::fd::FdError::NotImplemented => ENOSYS,
-        crate::fd::FdError::WouldBlock => -11, // EAGAIN
+        crate::fd::FdError::WouldBlock => EAGAIN,
         crate::fd::FdError::BrokenPipe => -32, // EPIPE
+        crate::fd::FdError::InvalidLimit => EINVAL,
+        crate::fd::FdError::InvalidArgument => EINVAL,
+        crate::fd::FdError::Busy => EBUSY,
+        crate::fd::FdError::OutOfMemory => ENOMEM,
+        crate::fd::FdError::IllegalSeek => ESPIPE,
         crate::fd::FdError::Fs(fs_err) => fs_errno(fs_err),
+        crate::fd::FdError::Net(net_err) => net_errno(net_err),
+    }
+}
+
+fn net_errno(err: crate::socket::NetError) -> isize {
+    match err {
+        crate::socket::NetError::AddressInUse => EADDRINUSE,
+        crate::socket::NetError::Unreachable => ENETUNREACH,
+        crate::socket::NetError::ConnectionRefused => ECONNREFUSED,
+        crate::socket::NetError::NotConnected => ENOTCONN,
+        crate::socket::NetError::AlreadyConnected => EISCONN,
+        crate::socket::NetError::NotSupported => EOPNOTSUPP,
+        crate::socket::NetError::NotSocket => ENOTSOCK,
     }
 }
 
@@ -293,12 +1049,27 @@ enum SysError {
     NoSys,
     BadFd,
     InvalidUtf8,
+    InvalidArgument,
     Fault,
     Fs(FsError),
     Fd(crate::fd::FdError),
     Proc(crate::proc::SpawnError),
     Child, // ECHILD - No child processes
     NoProcess,
+    NoSuchProcess, // ESRCH - kill/setpgid target does not exist
+    NotPermitted,  // EPERM - needs root, or the target belongs to another user
+    OutOfMemory,   // ENOMEM - brk would run into the stack
+}
+
+/// The open file behind `pid`'s descriptor `fd`, if it is a regular file.
+/// Reads and writes go through it without the process table held, since
+/// they may park on the disk (see `Scheduler::park`).
+fn regular_file(pid: Pid, fd: usize) -> Option<Arc<Mutex<crate::fd::FileFd>>> {
+    let table = PROCESS_TABLE.lock();
+    match table.get(pid)?.fd_table.get(fd) {
+        Ok(crate::fd::FileDescriptor::File(file)) => Some(Arc::clone(file)),
+        _ => None,
+    }
 }
 
 fn with_current_fd_table_mut<F, R>(f: F) -> Result<R, SysError>
@@ -318,20 +1089,83 @@ where
 
 fn sys_open(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     let path = read_path(trap_frame.a1 as *const u8, trap_frame.a2)?;
-    let flags = trap_frame.a3;
+    open_path(path, trap_frame.a3)
+}
+
+/// openat(dirfd, path, len, flags): relative paths resolve against the
+/// directory open at `dirfd`, or the working directory for AT_FDCWD
+fn sys_openat(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let dirfd = trap_frame.a1;
+    let base = if dirfd == AT_FDCWD {
+        current_cwd()
+    } else {
+        with_current_fd_table_mut(|table| match table.get(dirfd)? {
+            crate::fd::FileDescriptor::Dir(dir) => Ok(String::from(dir.path())),
+            _ => Err(crate::fd::FdError::Fs(FsError::NotADirectory)),
+        })?
+    };
+    let path = read_path_at(base, trap_frame.a2 as *const u8, trap_frame.a3)?;
+    open_path(path, trap_frame.a4)
+}
 
-    // Parse flags: bit 0 = read, bit 1 = write, bit 2 = create, bit 3 = append
+fn open_path(path: String, flags: usize) -> Result<usize, SysError> {
+    let (descriptor, cloexec) = open_descriptor(path, flags)?;
+    let fd_num = with_current_fd_table_mut(|table| table.alloc_with(descriptor, cloexec))?;
+    Ok(fd_num)
+}
+
+/// Open `path` for the calling process without installing it anywhere;
+/// also returns whether O_CLOEXEC was given
+fn open_descriptor(path: String, flags: usize) -> Result<(crate::fd::FileDescriptor, bool), SysError> {
+    // Parse flags: bit 0 = read, bit 1 = write, bit 2 = create, bit 3 = append,
+    // bit 4 = truncate, bit 5 = atomic replace on close, bit 6 = close on spawn,
+    // bit 7 = must be a directory
     let mode = crate::fd::FileMode {
         read: flags & 0x1 != 0,
         write: flags & 0x2 != 0,
         create: flags & 0x4 != 0,
         append: flags & 0x8 != 0,
+        truncate: flags & 0x10 != 0,
+        atomic: flags & 0x20 != 0,
     };
 
-    let file_fd = crate::fd::FileFd::open(path, mode).map_err(SysError::Fd)?;
-    let fd_num =
-        with_current_fd_table_mut(|table| table.alloc(crate::fd::FileDescriptor::File(file_fd)))?;
-    Ok(fd_num)
+    let cloexec = flags & 0x40 != 0;
+    let directory = flags & 0x80 != 0;
+
+    if let Some(port) = console::device_node(&path) {
+        // Console descriptors go one way; open stdin and stdout separately
+        let mode = if mode.write {
+            crate::fd::UartMode::Write
+        } else {
+            crate::fd::UartMode::Read
+        };
+        let descriptor = crate::fd::FileDescriptor::Uart(crate::fd::UartFd::on(port, mode));
+        return Ok((descriptor, cloexec));
+    }
+    if crate::random::is_device(&path) {
+        return Ok((crate::fd::FileDescriptor::Random(crate::random::RandomFd), cloexec));
+    }
+
+    // Directories open read-only as a DirFd
+    let descriptor = match fs::ensure_directory(&path) {
+        Ok(()) if mode.write || mode.create || mode.append || mode.truncate => {
+            return Err(SysError::Fs(FsError::IsDirectory));
+        }
+        Ok(()) => crate::fd::FileDescriptor::Dir(
+            crate::fd::DirFd::open(path).map_err(SysError::Fd)?,
+        ),
+        Err(_) if directory => return Err(SysError::Fs(FsError::NotADirectory)),
+        Err(_) => {
+            let uid = current_uid();
+            if mode.write || mode.create || mode.append || mode.truncate {
+                check_write(uid, &path, mode.create)?;
+            }
+            crate::fd::FileDescriptor::file(
+                crate::fd::FileFd::open_as(path, mode, uid).map_err(SysError::Fd)?,
+            )
+        }
+    };
+    Ok((descriptor, cloexec))
 }
 
 fn sys_close(trap_frame: &TrapFrame) -> Result<usize, SysError> {
@@ -345,8 +1179,8 @@ fn sys_read(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     let buf_ptr = trap_frame.a2 as *mut u8;
     let buf_len = trap_frame.a3;
 
-    if buf_len > 0 && buf_ptr.is_null() {
-        return Err(SysError::Fault);
+    if buf_len > 0 {
+        check_user_write(buf_ptr, buf_len)?;
     }
 
     let buf = if buf_len == 0 {
@@ -358,9 +1192,17 @@ fn sys_read(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     // Capture the reader's PID to use consistently
     let reader_pid = PROCESS_TABLE.lock().get_current_pid();
 
+    if let Some(file) = regular_file(reader_pid, fd) {
+        return file.lock().read(buf).map_err(SysError::Fd);
+    }
+
     // Try to read, block if would block
     loop {
         let mut pipe_waiting_on: Option<usize> = None;
+        let mut pty_waiting_on: Option<(usize, crate::pty::PtyEnd)> = None;
+        let mut socket_waiting_on: Option<usize> = None;
+        let mut event_waiting_on: Option<usize> = None;
+        let mut console_waiting = None;
         let result = {
             let mut table = PROCESS_TABLE.lock();
             if let Some(proc) = table.get_mut(reader_pid) {
@@ -372,6 +1214,22 @@ fn sys_read(trap_frame: &TrapFrame) -> Result<usize, SysError> {
                                 pipe_waiting_on = Some(pipe_fd.pipe_id);
                                 Ok(pipe_fd.read(buf))
                             }
+                            crate::fd::FileDescriptor::Pty(pty_fd) => {
+                                pty_waiting_on = Some((pty_fd.pty_id, pty_fd.end));
+                                Ok(pty_fd.read(buf))
+                            }
+                            crate::fd::FileDescriptor::Socket(socket_fd) => {
+                                socket_waiting_on = Some(socket_fd.socket_id);
+                                Ok(socket_fd.read(buf))
+                            }
+                            crate::fd::FileDescriptor::Event(event_fd) => {
+                                event_waiting_on = Some(event_fd.event_id);
+                                Ok(event_fd.read(buf))
+                            }
+                            crate::fd::FileDescriptor::Uart(uart_fd) => {
+                                console_waiting = Some(uart_fd.port());
+                                Ok(uart_fd.read(buf))
+                            }
                             _ => Ok(fd_entry.read(buf)),
                         }
                     })
@@ -389,6 +1247,24 @@ fn sys_read(trap_frame: &TrapFrame) -> Result<usize, SysError> {
                         .lock()
                         .mark_reader_waiting(pipe_id, reader_pid);
                 }
+                if let Some((pty_id, end)) = pty_waiting_on {
+                    let _ = crate::pty::PTY_TABLE
+                        .lock()
+                        .mark_waiting(pty_id, end, reader_pid);
+                }
+                if let Some(socket_id) = socket_waiting_on {
+                    let _ = crate::socket::SOCKET_TABLE
+                        .lock()
+                        .mark_waiting(socket_id, reader_pid);
+                }
+                if let Some(event_id) = event_waiting_on {
+                    let _ = crate::eventfd::EVENT_TABLE
+                        .lock()
+                        .mark_waiting(event_id, reader_pid);
+                }
+                if let Some(port) = console_waiting {
+                    console::mark_waiting(port, reader_pid);
+                }
                 crate::scheduler::Scheduler::block_current();
                 return Err(SysError::Fd(crate::fd::FdError::WouldBlock));
             }
@@ -397,6 +1273,94 @@ fn sys_read(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     }
 }
 
+/// getrlimit(resource, *mut [soft, hard])
+fn sys_getrlimit(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let resource = trap_frame.a1;
+    let out = trap_frame.a2 as *mut [usize; 2];
+    check_user_write(out, 1)?;
+    let limits = if resource == crate::rlimit::RLIMIT_NOFILE {
+        let soft = with_current_fd_table_mut(|table| Ok(table.limit()))?;
+        [soft, crate::fd::FD_HARD_LIMIT]
+    } else {
+        let table = PROCESS_TABLE.lock();
+        let process = table.current().ok_or(SysError::NoProcess)?;
+        let limit = process.rlimits.get(resource).ok_or(SysError::InvalidArgument)?;
+        [limit.soft, limit.hard]
+    };
+    unsafe { ptr::write(out, limits) };
+    Ok(0)
+}
+
+/// setrlimit(resource, *const [soft, hard]). Hard limits can only be
+/// lowered; RLIMIT_NOFILE's is fixed, so it must be passed back
+/// unchanged. A soft limit below current usage only fails later requests.
+fn sys_setrlimit(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let resource = trap_frame.a1;
+    let input = trap_frame.a2 as *const [usize; 2];
+    if input.is_null() {
+        return Err(SysError::Fault);
+    }
+    let [soft, hard] = unsafe { ptr::read(input) };
+    if resource == crate::rlimit::RLIMIT_NOFILE {
+        if hard != crate::fd::FD_HARD_LIMIT {
+            return Err(SysError::InvalidArgument);
+        }
+        with_current_fd_table_mut(|table| table.set_limit(soft))?;
+        return Ok(0);
+    }
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current_mut().ok_or(SysError::NoProcess)?;
+    if !process.rlimits.set(resource, crate::rlimit::Rlimit { soft, hard }) {
+        return Err(SysError::InvalidArgument);
+    }
+    Ok(0)
+}
+
+/// fcntl(fd, cmd, arg); supports the descriptor flags (FD_CLOEXEC) and
+/// the pipe size commands
+fn sys_fcntl(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let fd = trap_frame.a1;
+    let cmd = trap_frame.a2;
+    let arg = trap_frame.a3;
+    match cmd {
+        F_GETFD => {
+            let cloexec = with_current_fd_table_mut(|table| table.cloexec(fd))?;
+            return Ok(if cloexec { FD_CLOEXEC } else { 0 });
+        }
+        F_SETFD => {
+            with_current_fd_table_mut(|table| table.set_cloexec(fd, arg & FD_CLOEXEC != 0))?;
+            return Ok(0);
+        }
+        F_GETPIPE_SZ | F_SETPIPE_SZ => {}
+        _ => return Err(SysError::InvalidArgument),
+    }
+    let pipe_id = with_current_fd_table_mut(|table| match table.get(fd)? {
+        crate::fd::FileDescriptor::Pipe(pipe_fd) => Ok(pipe_fd.pipe_id),
+        _ => Err(crate::fd::FdError::BadFd),
+    })?;
+    let (old_size, owner) = {
+        let mut pipes = crate::fd::PIPE_TABLE.lock();
+        let size = pipes.capacity(pipe_id).map_err(SysError::Fd)?;
+        (size, pipes.owner(pipe_id).map_err(SysError::Fd)?)
+    };
+    if cmd == F_GETPIPE_SZ {
+        return Ok(old_size);
+    }
+    // The buffer is charged to whoever resized it last; the resize
+    // briefly holds both the old and the new buffer
+    let pid = PROCESS_TABLE.lock().get_current_pid();
+    let extra = if owner == pid { arg.saturating_sub(old_size) } else { arg };
+    if !crate::oom::within_limit(pid, extra)
+        || !crate::oom::reserve(arg.min(crate::fd::PIPE_MAX_SIZE), pid)
+    {
+        return Err(SysError::Fd(crate::fd::FdError::OutOfMemory));
+    }
+    crate::fd::PIPE_TABLE
+        .lock()
+        .set_capacity(pipe_id, arg, pid)
+        .map_err(SysError::Fd)
+}
+
 fn sys_dup2(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     let old_fd = trap_frame.a1;
     let new_fd = trap_frame.a2;
@@ -406,22 +1370,203 @@ fn sys_dup2(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     Ok(new_fd)
 }
 
-fn sys_pipe(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+/// openpty(fds): create a pty and store [master_fd, slave_fd] at `fds`
+fn sys_openpty(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     let fds_ptr = trap_frame.a1 as *mut usize;
 
-    if fds_ptr.is_null() {
-        return Err(SysError::Fault);
-    }
+    check_user_write(fds_ptr, 2)?;
 
-    // Create a new pipe
-    let pipe_id = crate::fd::PipeTable::create_pipe().map_err(SysError::Fd)?;
-
-    // Create file descriptors for both ends
-    let read_fd = crate::fd::PipeFd::new(pipe_id, true);
-    let write_fd = crate::fd::PipeFd::new(pipe_id, false);
+    let pty_id = crate::pty::PtyTable::create_pty().map_err(SysError::Fd)?;
+    let master = crate::pty::PtyFd::new(pty_id, crate::pty::PtyEnd::Master);
+    let slave = crate::pty::PtyFd::new(pty_id, crate::pty::PtyEnd::Slave);
 
-    // Allocate file descriptors
-    let mut fd_nums = [0usize; 2];
+    let fd_nums = with_current_fd_table_mut(|table| {
+        let master_fd_num = table.alloc(crate::fd::FileDescriptor::Pty(master))?;
+        let slave_fd_num = table
+            .alloc(crate::fd::FileDescriptor::Pty(slave))
+            .map_err(|e| {
+                let _ = table.close(master_fd_num);
+                e
+            })?;
+        Ok([master_fd_num, slave_fd_num])
+    })?;
+
+    unsafe { ptr::write(fds_ptr as *mut [usize; 2], fd_nums) };
+    Ok(0)
+}
+
+/// eventfd(initval, flags): flags are EFD_SEMAPHORE and EFD_CLOEXEC;
+/// returns the new fd
+fn sys_eventfd(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let initval = trap_frame.a1 as u64;
+    let flags = trap_frame.a2;
+    if flags & !(crate::eventfd::EFD_SEMAPHORE | crate::eventfd::EFD_CLOEXEC) != 0 {
+        return Err(SysError::InvalidArgument);
+    }
+    let event_id = crate::eventfd::EVENT_TABLE.lock().create_event(initval, flags);
+    let event = crate::fd::FileDescriptor::Event(crate::eventfd::EventFd::new(event_id));
+    with_current_fd_table_mut(|table| table.alloc_with(event, flags & crate::eventfd::EFD_CLOEXEC != 0))
+        .inspect_err(|_| {
+            let _ = crate::eventfd::EVENT_TABLE.lock().close(event_id);
+        })
+}
+
+/// socket(kind): 1 = stream, 2 = datagram; returns the new fd
+fn sys_socket(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let kind = crate::socket::SocketKind::from_raw(trap_frame.a1).ok_or(SysError::InvalidArgument)?;
+    let socket_id = crate::socket::SOCKET_TABLE.lock().create_socket(kind);
+    let socket = crate::socket::SocketFd::new(socket_id);
+    // A descriptor dropped by a failed alloc is never released
+    with_current_fd_table_mut(|table| table.alloc(crate::fd::FileDescriptor::Socket(socket)))
+        .inspect_err(|_| {
+            let _ = crate::socket::SOCKET_TABLE.lock().close(socket_id);
+        })
+}
+
+/// Socket id behind `fd`
+fn socket_of(fd: usize) -> Result<usize, SysError> {
+    with_current_fd_table_mut(|table| match table.get(fd)? {
+        crate::fd::FileDescriptor::Socket(socket) => Ok(socket.socket_id),
+        _ => Err(crate::fd::FdError::Net(crate::socket::NetError::NotSocket)),
+    })
+}
+
+/// Park the caller until `socket_id` changes state; handle_ecall restarts
+/// the syscall once it is woken
+fn block_on_socket(socket_id: usize) -> SysError {
+    let pid = PROCESS_TABLE.lock().get_current_pid();
+    let _ = crate::socket::SOCKET_TABLE.lock().mark_waiting(socket_id, pid);
+    crate::scheduler::Scheduler::block_current();
+    SysError::Fd(crate::fd::FdError::WouldBlock)
+}
+
+/// Ports travel in a register; reject ones that do not fit
+fn port_arg(raw: usize) -> Result<u16, SysError> {
+    u16::try_from(raw).map_err(|_| SysError::InvalidArgument)
+}
+
+/// bind(fd, addr, port)
+fn sys_bind(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let socket_id = socket_of(trap_frame.a1)?;
+    let port = port_arg(trap_frame.a3)?;
+    crate::socket::SOCKET_TABLE
+        .lock()
+        .bind(socket_id, trap_frame.a2 as u32, port)
+        .map_err(SysError::Fd)?;
+    Ok(0)
+}
+
+/// listen(fd, backlog)
+fn sys_listen(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let socket_id = socket_of(trap_frame.a1)?;
+    crate::socket::SOCKET_TABLE
+        .lock()
+        .listen(socket_id, trap_frame.a2)
+        .map_err(SysError::Fd)?;
+    Ok(0)
+}
+
+/// accept(fd): blocks until a connection arrives; returns its fd
+fn sys_accept(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let socket_id = socket_of(trap_frame.a1)?;
+    let conn_id = match crate::socket::SOCKET_TABLE.lock().accept(socket_id) {
+        Ok(conn_id) => conn_id,
+        Err(crate::fd::FdError::WouldBlock) => return Err(block_on_socket(socket_id)),
+        Err(e) => return Err(SysError::Fd(e)),
+    };
+    let conn = crate::socket::SocketFd::new(conn_id);
+    with_current_fd_table_mut(|table| table.alloc(crate::fd::FileDescriptor::Socket(conn)))
+        .inspect_err(|_| {
+            let _ = crate::socket::SOCKET_TABLE.lock().close(conn_id);
+        })
+}
+
+/// connect(fd, addr, port): blocks until a remote handshake finishes
+fn sys_connect(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let socket_id = socket_of(trap_frame.a1)?;
+    let port = port_arg(trap_frame.a3)?;
+    let result = crate::socket::SOCKET_TABLE
+        .lock()
+        .connect(socket_id, trap_frame.a2 as u32, port);
+    match result {
+        Ok(()) => Ok(0),
+        Err(crate::fd::FdError::WouldBlock) => Err(block_on_socket(socket_id)),
+        Err(e) => Err(SysError::Fd(e)),
+    }
+}
+
+/// send(fd, buf, len, addr, port): port 0 sends to the connected peer
+fn sys_send(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let socket_id = socket_of(trap_frame.a1)?;
+    let ptr = trap_frame.a2 as *const u8;
+    let len = trap_frame.a3;
+    let port = port_arg(trap_frame.a5)?;
+    if len > 0 && ptr.is_null() {
+        return Err(SysError::Fault);
+    }
+    let bytes = if len == 0 {
+        &[][..]
+    } else {
+        unsafe { slice::from_raw_parts(ptr, len) }
+    };
+    let dest = (port != 0).then_some((trap_frame.a4 as u32, port));
+    match crate::socket::SOCKET_TABLE.lock().send(socket_id, bytes, dest) {
+        Ok(sent) => Ok(sent),
+        Err(crate::fd::FdError::WouldBlock) => Err(block_on_socket(socket_id)),
+        Err(e) => Err(SysError::Fd(e)),
+    }
+}
+
+/// recv(fd, buf, len, from): blocks for data; if `from` is non-null the
+/// sender's [addr, port] is stored there
+fn sys_recv(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let socket_id = socket_of(trap_frame.a1)?;
+    let ptr = trap_frame.a2 as *mut u8;
+    let len = trap_frame.a3;
+    let from = trap_frame.a4 as *mut [usize; 2];
+    if len > 0 {
+        check_user_write(ptr, len)?;
+    }
+    if !from.is_null() {
+        check_user_write(from, 1)?;
+    }
+    let buf = if len == 0 {
+        &mut [][..]
+    } else {
+        unsafe { slice::from_raw_parts_mut(ptr, len) }
+    };
+    let result = crate::socket::SOCKET_TABLE.lock().recv(socket_id, buf);
+    match result {
+        Ok((received, (addr, port))) => {
+            if !from.is_null() {
+                unsafe { ptr::write(from, [addr as usize, port as usize]) };
+            }
+            Ok(received)
+        }
+        Err(crate::fd::FdError::WouldBlock) => Err(block_on_socket(socket_id)),
+        Err(e) => Err(SysError::Fd(e)),
+    }
+}
+
+fn sys_pipe(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let fds_ptr = trap_frame.a1 as *mut usize;
+
+    check_user_write(fds_ptr, 2)?;
+
+    // Create a new pipe, charged to the caller
+    let pid = PROCESS_TABLE.lock().get_current_pid();
+    let size = crate::fd::PIPE_BUF_SIZE;
+    if !crate::oom::within_limit(pid, size) || !crate::oom::reserve(size, pid) {
+        return Err(SysError::Fd(crate::fd::FdError::OutOfMemory));
+    }
+    let pipe_id = crate::fd::PipeTable::create_pipe(pid).map_err(SysError::Fd)?;
+
+    // Create file descriptors for both ends
+    let read_fd = crate::fd::PipeFd::new(pipe_id, true);
+    let write_fd = crate::fd::PipeFd::new(pipe_id, false);
+
+    // Allocate file descriptors
+    let mut fd_nums = [0usize; 2];
     with_current_fd_table_mut(|table| {
         let read_fd_num = table.alloc(crate::fd::FileDescriptor::Pipe(read_fd))?;
         let write_fd_num = table
@@ -444,101 +1589,350 @@ fn sys_pipe(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     Ok(0)
 }
 
+/// brk(addr): move the caller's program break to `addr` and return it;
+/// addr 0 only reports the current break. The heap starts where the image
+/// ends and grows toward the stack, and memory it gains reads as zero.
+fn sys_brk(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let addr = trap_frame.a1;
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current_mut().ok_or(SysError::NoProcess)?;
+    if addr == 0 {
+        return Ok(crate::process::user_address(process.image_end));
+    }
+    let end = addr
+        .checked_sub(crate::process::user_address(0))
+        .filter(|end| (process.heap_start..=crate::process::HEAP_LIMIT).contains(end))
+        .ok_or(SysError::OutOfMemory)?;
+    if end > process.image_end {
+        // The caller is resident, so its heap is the window itself
+        let start = crate::process::user_address(process.image_end) as *mut u8;
+        unsafe { ptr::write_bytes(start, 0, end - process.image_end) };
+    }
+    process.image_end = end;
+    Ok(addr)
+}
+
+/// A zeroed window-sized buffer; the heap running out fails the spawn
+/// instead of panicking
+fn window_buffer() -> Result<Vec<u8>, SysError> {
+    let mut buf = Vec::new();
+    buf.try_reserve_exact(crate::process::USER_WINDOW_SIZE)
+        .map_err(|_| SysError::Proc(crate::proc::SpawnError::OutOfMemory))?;
+    buf.resize(crate::process::USER_WINDOW_SIZE, 0);
+    Ok(buf)
+}
+
+/// One posix_spawn-style file action, as laid out by the user library
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SpawnFileAction {
+    op: usize,
+    /// Descriptor number in the child the action sets up or closes
+    fd: usize,
+    /// Source descriptor for dup2, open flags for open
+    arg: usize,
+    path: *const u8,
+    path_len: usize,
+}
+
+/// Optional spawn arguments that do not fit in registers
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SpawnExtras {
+    actions: *const SpawnFileAction,
+    action_count: usize,
+    /// Environment strings and their lengths; a null `envp` inherits
+    /// the parent's environment
+    envp: *const *const u8,
+    env_lens: *const usize,
+    envc: usize,
+}
+
+/// Apply `count` file actions at `actions_ptr` to a child's copy of its
+/// parent's descriptor table, in order. Runs before close-on-spawn
+/// descriptors are dropped, so they can be dup2ed into place.
+fn apply_file_actions(
+    fd_table: &mut crate::fd::FdTable,
+    actions_ptr: *const SpawnFileAction,
+    count: usize,
+) -> Result<(), SysError> {
+    if count > SPAWN_MAX_ACTIONS {
+        return Err(SysError::InvalidArgument);
+    }
+    if count > 0 && actions_ptr.is_null() {
+        return Err(SysError::Fault);
+    }
+    let actions = if count == 0 {
+        &[][..]
+    } else {
+        unsafe { slice::from_raw_parts(actions_ptr, count) }
+    };
+    for action in actions {
+        match action.op {
+            SPAWN_ACTION_DUP2 => fd_table.dup2(action.arg, action.fd).map_err(SysError::Fd)?,
+            SPAWN_ACTION_CLOSE => fd_table.close(action.fd).map_err(SysError::Fd)?,
+            SPAWN_ACTION_OPEN => {
+                let path = read_path(action.path, action.path_len)?;
+                let (descriptor, cloexec) = open_descriptor(path, action.arg)?;
+                fd_table
+                    .install(action.fd, descriptor, cloexec)
+                    .map_err(SysError::Fd)?;
+            }
+            _ => return Err(SysError::InvalidArgument),
+        }
+    }
+    Ok(())
+}
+
+/// Check that the kernel may store `count` values of `T` at user address
+/// `ptr`: inside the user window, so never into kernel memory, and clear
+/// of the caller's read-only segments, which stay unprotected from the
+/// program itself until there is an MMU
+fn check_user_write<T>(ptr: *mut T, count: usize) -> Result<(), SysError> {
+    let start = ptr as usize;
+    let end = count
+        .checked_mul(core::mem::size_of::<T>())
+        .and_then(|len| start.checked_add(len))
+        .ok_or(SysError::Fault)?;
+    if !crate::process::inside_user_window(start, end - start) {
+        return Err(SysError::Fault);
+    }
+    let table = PROCESS_TABLE.lock();
+    let Some(process) = table.current() else {
+        return Ok(());
+    };
+    if let Some(segment) = process
+        .read_only
+        .iter()
+        .find(|segment| segment.start < end && start < segment.end)
+    {
+        klog!(
+            Syscall,
+            Warn,
+            "pid {}: write to read-only segment at 0x{:x}",
+            process.pid,
+            start.max(segment.start)
+        );
+        return Err(SysError::Fault);
+    }
+    Ok(())
+}
+
+/// Copy `count` strings out of user memory. Each length comes from
+/// `lens`, or if that is null, from a terminating NUL. A null entry ends
+/// the list early.
+fn read_user_strings(
+    ptrs: *const *const u8,
+    lens: *const usize,
+    count: usize,
+) -> Result<Vec<String>, SysError> {
+    let mut strings = Vec::new();
+    if count == 0 || ptrs.is_null() {
+        return Ok(strings);
+    }
+    for i in 0..count {
+        unsafe {
+            let str_ptr = *ptrs.add(i);
+            if str_ptr.is_null() {
+                break;
+            }
+            // Read the length from the lengths array
+            let len = if !lens.is_null() {
+                *lens.add(i)
+            } else {
+                // Fallback: find string length by searching for null terminator
+                let mut l = 0;
+                while *str_ptr.add(l) != 0 {
+                    l += 1;
+                    if l > 4096 {
+                        // Prevent infinite loop
+                        return Err(SysError::Fault);
+                    }
+                }
+                l
+            };
+            let bytes = slice::from_raw_parts(str_ptr, len);
+            let string = str::from_utf8(bytes).map_err(|_| SysError::InvalidUtf8)?;
+            strings.push(String::from(string));
+        }
+    }
+    Ok(strings)
+}
+
+fn default_env() -> Vec<String> {
+    crate::process::DEFAULT_ENV.iter().map(|&var| String::from(var)).collect()
+}
+
+/// What spawn reports when `path` cannot be loaded
+fn spawn_load_error(path: &str, err: crate::process::LoadError) -> SysError {
+    match err {
+        crate::process::LoadError::Elf(err) => {
+            klog!(Syscall, Warn, "spawn: {}: {}", path, err);
+            SysError::Proc(crate::proc::SpawnError::NotExecutable)
+        }
+        crate::process::LoadError::BadInterpreter => {
+            klog!(Syscall, Warn, "spawn: {}: bad #! line", path);
+            SysError::Proc(crate::proc::SpawnError::NotExecutable)
+        }
+        _ => SysError::Proc(crate::proc::SpawnError::ProgramNotFound),
+    }
+}
+
 fn sys_spawn(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     let path = read_path(trap_frame.a1 as *const u8, trap_frame.a2)?;
     let argv_ptr = trap_frame.a3 as *const *const u8;
     let argc = trap_frame.a4;
     let arg_lens_ptr = trap_frame.a5 as *const usize;
+    // 0 = parent's group, SPAWN_NEW_PGRP = own group, else join that group
+    let pgid = trap_frame.a6;
+    // File actions and environment for the child, or null
+    let extras_ptr = trap_frame.a7 as *const SpawnExtras;
+    let extras = if extras_ptr.is_null() {
+        SpawnExtras {
+            actions: ptr::null(),
+            action_count: 0,
+            envp: ptr::null(),
+            env_lens: ptr::null(),
+            envc: 0,
+        }
+    } else {
+        unsafe { ptr::read(extras_ptr) }
+    };
 
-    uart::write_str(&alloc::format!("[spawn] path={}, argc={}\n", path, argc));
+    klog!(Syscall, Debug, "[spawn] path={}, argc={}", path, argc);
 
     // Parse arguments from user space
-    let mut args = alloc::vec![];
-    if argc > 0 && !argv_ptr.is_null() {
-        for i in 0..argc {
-            unsafe {
-                let arg_ptr = *argv_ptr.add(i);
-                if arg_ptr.is_null() {
-                    break;
-                }
-                // Read the length from the lengths array
-                let len = if !arg_lens_ptr.is_null() {
-                    *arg_lens_ptr.add(i)
-                } else {
-                    // Fallback: find string length by searching for null terminator
-                    let mut l = 0;
-                    while *arg_ptr.add(l) != 0 {
-                        l += 1;
-                        if l > 4096 {
-                            // Prevent infinite loop
-                            return Err(SysError::Fault);
-                        }
-                    }
-                    l
-                };
-                let bytes = slice::from_raw_parts(arg_ptr, len);
-                let arg = str::from_utf8(bytes).map_err(|_| SysError::InvalidUtf8)?;
-                args.push(String::from(arg));
-            }
+    let args = read_user_strings(argv_ptr, arg_lens_ptr, argc)?;
+    let env = if extras.envp.is_null() {
+        PROCESS_TABLE
+            .lock()
+            .current()
+            .map_or_else(default_env, |p| p.env.clone())
+    } else {
+        read_user_strings(extras.envp, extras.env_lens, extras.envc)?
+    };
+    if env.len() > crate::process::MAX_ENV_VARS {
+        return Err(SysError::InvalidArgument);
+    }
+
+    // A script runs under the interpreter its #! line names, which gets
+    // the optional #! argument and the script's path before the rest
+    let (path, args) = match crate::process::interpreter(&path) {
+        Ok(None) => (path, args),
+        Ok(Some((interpreter, arg))) => {
+            let mut script_args = vec![interpreter.clone()];
+            script_args.extend(arg);
+            script_args.push(path);
+            script_args.extend(args.into_iter().skip(1));
+            (interpreter, script_args)
         }
+        Err(err) => return Err(spawn_load_error(&path, err)),
+    };
+    if args.len() > crate::process::MAX_ARGS {
+        return Err(SysError::InvalidArgument);
     }
 
-    let program = crate::process::load(&path).map_err(|_| {
-        SysError::Proc(crate::proc::SpawnError::ProgramNotFound)
-    })?;
+    let program = crate::process::load(&path).map_err(|err| spawn_load_error(&path, err))?;
 
     let arg_refs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    let env_refs: Vec<&str> = env.iter().map(|s| s.as_str()).collect();
 
-    // Inherit fds from parent
-    let fd_table = {
+    // The child's snapshot counts against the limit it inherits; the
+    // heap must also fit the parent's temporary copy
+    let (parent_pid, mem_limit) = {
         let table = PROCESS_TABLE.lock();
-        let parent_pid = table.get_current_pid();
-        if parent_pid == crate::proc::INVALID_PID {
-            crate::fd::FdTable::with_standard()
-        } else {
-            table
-                .get(parent_pid)
-                .map(|p| p.fd_table.clone())
-                .unwrap_or_else(crate::fd::FdTable::with_standard)
-        }
+        let limit = table
+            .current()
+            .map_or(crate::rlimit::DEFAULT_MEM_LIMIT, |p| p.rlimits.mem.soft);
+        (table.get_current_pid(), limit)
     };
+    let window = crate::process::USER_WINDOW_SIZE;
+    if window > mem_limit || !crate::oom::reserve(2 * window, parent_pid) {
+        return Err(SysError::Proc(crate::proc::SpawnError::OutOfMemory));
+    }
 
     // Save current user window state
-    let mut saved_window = alloc::vec![0u8; crate::process::USER_WINDOW_SIZE];
+    let mut saved_window = window_buffer()?;
     crate::process::snapshot_user_window(&mut saved_window);
 
-    // Load child program into user window to build its initial state
-    crate::process::load_into_user_window(&program)
-        .map_err(|_| SysError::Proc(crate::proc::SpawnError::LoadFailed))?;
-    let (sp, built_argc, built_argv_ptr) =
-        crate::process::build_user_stack(&arg_refs)
-        .map_err(|_| SysError::Proc(crate::proc::SpawnError::LoadFailed))?;
+    // Load child program into user window to build its initial state.
+    // Segments are read from the file as they are placed, so a failure
+    // can leave a partial image; the parent gets its window back. The
+    // window is not the parent's meanwhile, so its reads must not park.
+    let built = crate::scheduler::Scheduler::without_parking(|| {
+        crate::process::load_into_user_window(&program)
+            .and_then(|()| crate::process::build_user_stack(&arg_refs, &env_refs, program.entry))
+    });
+    let (sp, built_argc, built_argv_ptr, built_envp_ptr) = match built {
+        Ok(built) => built,
+        Err(_) => {
+            crate::process::restore_user_window(&saved_window);
+            return Err(SysError::Proc(crate::proc::SpawnError::LoadFailed));
+        }
+    };
 
     // Capture child's initial memory state
-    let mut child_memory = alloc::vec![0u8; crate::process::USER_WINDOW_SIZE];
+    let mut child_memory = window_buffer()?;
     crate::process::snapshot_user_window(&mut child_memory);
 
     // Restore parent's user window
     crate::process::restore_user_window(&saved_window);
 
+    // Copy the parent's fds, apply the file actions, then drop the ones
+    // marked close-on-spawn. Closing can wake pipe peers, which takes the
+    // process table lock, so only the copy happens under it.
+    let mut fd_table = {
+        let table = PROCESS_TABLE.lock();
+        table
+            .current()
+            .map(|p| p.fd_table.clone())
+            .unwrap_or_else(crate::fd::FdTable::with_standard)
+    };
+    if let Err(err) = apply_file_actions(&mut fd_table, extras.actions, extras.action_count) {
+        fd_table.close_all();
+        return Err(err);
+    }
+    fd_table.close_cloexec();
+
     // Create process entry with child's memory snapshot and initial argc/argv
     let child_pid = {
         let mut table = PROCESS_TABLE.lock();
         let parent_pid = table.get_current_pid();
-        uart::write_str(&alloc::format!("[spawn] parent_pid={}, creating child...\n", parent_pid));
-        table
+        klog!(Syscall, Debug, "[spawn] parent_pid={}, creating child...", parent_pid);
+        let pid = table
             .spawn(program.entry, sp as u64, path.clone(), args.clone(), fd_table, child_memory, built_argc, built_argv_ptr)
-            .map_err(SysError::Proc)?
+            .map_err(SysError::Proc)?;
+        if let Some(child) = table.get_mut(pid) {
+            child.image_end = crate::process::image_end(&program);
+            child.heap_start = child.image_end;
+            child.read_only = crate::process::read_only_ranges(&program);
+            child.env = env;
+            child.envp_ptr = built_envp_ptr;
+            child.tp = crate::process::thread_pointer(&program);
+        }
+        match pgid {
+            0 => {}
+            SPAWN_NEW_PGRP => {
+                table.set_pgid(pid, pid);
+            }
+            pgid => {
+                table.set_pgid(pid, pgid);
+            }
+        }
+        pid
     };
 
-    uart::write_str(&alloc::format!("[spawn] created child_pid={}\n", child_pid));
-    uart::write_str("[spawn] returning to parent\n");
+    klog!(Syscall, Debug, "[spawn] created child_pid={}", child_pid);
+    klog!(Syscall, Debug, "[spawn] returning to parent");
     // Child is now Ready - it will run when scheduled
     Ok(child_pid)
 }
 
 fn sys_wait(trap_frame: &TrapFrame) -> Result<usize, SysError> {
     let status_ptr = trap_frame.a1 as *mut isize;
+    if !status_ptr.is_null() {
+        check_user_write(status_ptr, 1)?;
+    }
 
     let mut table = PROCESS_TABLE.lock();
     let current_pid = table.get_current_pid();
@@ -553,7 +1947,7 @@ fn sys_wait(trap_frame: &TrapFrame) -> Result<usize, SysError> {
 
     // Try to reap an exited child
     if let Some((child_pid, exit_code)) = table.wait(current_pid) {
-        uart::write_str(&alloc::format!("[wait] reaped child_pid={}\n", child_pid));
+        klog!(Syscall, Debug, "[wait] reaped child_pid={}", child_pid);
         if !status_ptr.is_null() {
             unsafe {
                 ptr::write(status_ptr, exit_code);
@@ -562,21 +1956,403 @@ fn sys_wait(trap_frame: &TrapFrame) -> Result<usize, SysError> {
         return Ok(child_pid);
     }
 
-    // No exited children yet - mark as blocked and return EAGAIN
-    // The process will be rescheduled when a child exits
-    // User-space should retry the syscall
+    // No exited children yet - block; handle_ecall restarts the syscall
+    // when a child exits and wakes us
     drop(table);
     crate::scheduler::Scheduler::block_current();
+    Err(SysError::Fd(crate::fd::FdError::WouldBlock))
+}
+
+/// waitpid(pid, status, options): pid usize::MAX (-1) waits for any child.
+/// WNOHANG returns 0 instead of blocking; WUNTRACED also reports stopped
+/// children. Status is `(code & 0xff) << 8` for an exit and
+/// `(sig << 8) | 0x7f` for a stop, as on Linux.
+fn sys_waitpid(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let target = trap_frame.a1 as isize;
+    let status_ptr = trap_frame.a2 as *mut isize;
+    let options = trap_frame.a3;
+    if !status_ptr.is_null() {
+        check_user_write(status_ptr, 1)?;
+    }
+
+    let mut table = PROCESS_TABLE.lock();
+    let current_pid = table.get_current_pid();
+    if current_pid == crate::proc::INVALID_PID {
+        return Err(SysError::Child);
+    }
+    let pid = if target == -1 { None } else { Some(target as usize) };
+    let is_child = |p: usize| {
+        table
+            .get(p)
+            .is_some_and(|child| child.parent_pid == current_pid)
+    };
+    let has_target = match pid {
+        Some(pid) => is_child(pid),
+        None => table.has_children(current_pid),
+    };
+    if !has_target {
+        return Err(SysError::Child);
+    }
 
-    // Return EAGAIN to indicate "would block"
+    if let Some((child_pid, status)) = table.wait_for(current_pid, pid, options & WUNTRACED != 0) {
+        let encoded = match status {
+            crate::proc::WaitStatus::Exited(code) => (code & 0xff) << 8,
+            crate::proc::WaitStatus::Stopped(sig) => ((sig as isize) << 8) | 0x7f,
+        };
+        if !status_ptr.is_null() {
+            unsafe { ptr::write(status_ptr, encoded) };
+        }
+        return Ok(child_pid);
+    }
+    if options & WNOHANG != 0 {
+        return Ok(0);
+    }
+
+    // Same as wait: block until a child changes state, then restart
+    drop(table);
+    crate::scheduler::Scheduler::block_current();
     Err(SysError::Fd(crate::fd::FdError::WouldBlock))
 }
 
+/// setpgid(pid, pgid): pid 0 means the caller, pgid 0 means `pid` itself.
+/// Only the caller and its children can be moved.
+fn sys_setpgid(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let mut table = PROCESS_TABLE.lock();
+    let current_pid = table.get_current_pid();
+    let pid = if trap_frame.a1 == 0 { current_pid } else { trap_frame.a1 };
+    let pgid = if trap_frame.a2 == 0 { pid } else { trap_frame.a2 };
+    let allowed = pid == current_pid
+        || table
+            .get(pid)
+            .is_some_and(|p| p.parent_pid == current_pid);
+    if !allowed || !table.set_pgid(pid, pgid) {
+        return Err(SysError::NoSuchProcess);
+    }
+    Ok(0)
+}
+
+/// tcsetpgrp(pgid): hand the caller's console (Ctrl-C/Ctrl-Z) to group
+/// `pgid`; 0 means the caller's own group
+fn sys_tcsetpgrp(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let mut table = PROCESS_TABLE.lock();
+    let current = table.current().ok_or(SysError::NoProcess)?;
+    let port = current.fd_table.console_port();
+    let pgid = match trap_frame.a1 {
+        0 => current.pgid,
+        pgid => pgid,
+    };
+    if table.group_members(pgid).is_empty() {
+        return Err(SysError::NoSuchProcess);
+    }
+    table.set_foreground(port, pgid);
+    Ok(0)
+}
+
+/// signal(sig, disposition): SIG_IGN or SIG_DFL; returns the previous
+/// disposition. SIGCONT always resumes a stopped process, ignored or not.
+fn sys_signal(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let sig = trap_frame.a1;
+    let disposition = trap_frame.a2;
+    if sig == 0
+        || sig > crate::signal::MAX_SIGNAL
+        || sig == crate::signal::SIGKILL
+        || sig == crate::signal::SIGSTOP
+    {
+        return Err(SysError::InvalidArgument);
+    }
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current_mut().ok_or(SysError::NoProcess)?;
+    let bit = 1u32 << sig;
+    let previous = if process.ignored_signals & bit != 0 {
+        crate::signal::SIG_IGN
+    } else {
+        crate::signal::SIG_DFL
+    };
+    match disposition {
+        crate::signal::SIG_IGN => {
+            process.ignored_signals |= bit;
+            process.pending_signals &= !bit;
+        }
+        crate::signal::SIG_DFL => process.ignored_signals &= !bit,
+        _ => return Err(SysError::InvalidArgument),
+    }
+    Ok(previous)
+}
+
+/// procinfo(cookie, buf, len): process records in the format of
+/// `ProcessTable::read_procinfo`; start with a zero cookie and call until
+/// it returns 0
+fn sys_procinfo(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let cookie_ptr = trap_frame.a1 as *mut usize;
+    let buf_ptr = trap_frame.a2 as *mut u8;
+    let buf_len = trap_frame.a3;
+
+    check_user_write(cookie_ptr, 1)?;
+    if buf_len > 0 {
+        check_user_write(buf_ptr, buf_len)?;
+    }
+    let buf = if buf_len == 0 {
+        &mut [][..]
+    } else {
+        unsafe { slice::from_raw_parts_mut(buf_ptr, buf_len) }
+    };
+
+    let mut cookie = unsafe { ptr::read(cookie_ptr) };
+    let written = PROCESS_TABLE
+        .lock()
+        .read_procinfo(&mut cookie, buf)
+        .ok_or(SysError::InvalidArgument)?;
+    unsafe { ptr::write(cookie_ptr, cookie) };
+    Ok(written)
+}
+
+/// schedstat(pid, *mut SchedStats): scheduling counters for `pid`, or
+/// the system-wide totals for pid 0
+fn sys_schedstat(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let pid = trap_frame.a1;
+    let out = trap_frame.a2 as *mut crate::scheduler::SchedStats;
+    check_user_write(out, 1)?;
+    let table = PROCESS_TABLE.lock();
+    let stats = if pid == 0 {
+        table.sched_stats()
+    } else {
+        table.get(pid).ok_or(SysError::NoSuchProcess)?.sched
+    };
+    unsafe { ptr::write(out, stats) };
+    Ok(0)
+}
+
+/// maxprocs(max): set the process cap (root only), or with 0 just ask.
+/// Returns the cap in effect.
+fn sys_maxprocs(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let max = trap_frame.a1;
+    if max != 0 && current_uid() != ROOT_UID {
+        return Err(SysError::NotPermitted);
+    }
+    let mut table = PROCESS_TABLE.lock();
+    if max != 0 {
+        table
+            .set_max_processes(max)
+            .map_err(|_| SysError::InvalidArgument)?;
+    }
+    Ok(table.max_processes())
+}
+
+fn sys_getuid(_trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    Ok(current_uid() as usize)
+}
+
+fn sys_getgid(_trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let table = PROCESS_TABLE.lock();
+    Ok(table.current().map_or(ROOT_UID, |p| p.gid) as usize)
+}
+
+/// setuid(uid): root may become any user; others may only pass their
+/// own uid. There is no saved uid, so root cannot come back.
+fn sys_setuid(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let uid = Uid::try_from(trap_frame.a1).map_err(|_| SysError::InvalidArgument)?;
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current_mut().ok_or(SysError::NoProcess)?;
+    if !process.is_root() && process.uid != uid {
+        return Err(SysError::NotPermitted);
+    }
+    process.uid = uid;
+    Ok(0)
+}
+
+/// setgid(gid): root may pick any group; others may only pass their own
+fn sys_setgid(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let gid = Uid::try_from(trap_frame.a1).map_err(|_| SysError::InvalidArgument)?;
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current_mut().ok_or(SysError::NoProcess)?;
+    if !process.is_root() && process.gid != gid {
+        return Err(SysError::NotPermitted);
+    }
+    process.gid = gid;
+    Ok(0)
+}
+
+/// chown(path, len, uid): give a file or directory to `uid`; root only
+fn sys_chown(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let path = read_path(trap_frame.a1 as *const u8, trap_frame.a2)?;
+    let uid = Uid::try_from(trap_frame.a3).map_err(|_| SysError::InvalidArgument)?;
+    if current_uid() != ROOT_UID {
+        return Err(SysError::NotPermitted);
+    }
+    fs::set_owner(&path, uid).map_err(SysError::Fs)?;
+    Ok(0)
+}
+
+/// console_log(port): send kernel messages to console `port` (0 the
+/// UART, 1 the virtio console, 2 to 4 its other virtual terminals, 5 the
+/// second UART); root only. `usize::MAX` just asks.
+/// Returns the port messages went to before.
+fn sys_console_log(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let previous = console::log_port().index();
+    if trap_frame.a1 == usize::MAX {
+        return Ok(previous);
+    }
+    let port = console::Port::from_index(trap_frame.a1).ok_or(SysError::InvalidArgument)?;
+    if current_uid() != ROOT_UID {
+        return Err(SysError::NotPermitted);
+    }
+    if !console::set_log_port(port) {
+        return Err(SysError::Fs(FsError::NotFound));
+    }
+    Ok(previous)
+}
+
+/// getrandom(buf, len, flags): fill `buf` from the kernel entropy pool.
+/// Never blocks, since the pool is seeded at boot; GRND_NONBLOCK and
+/// GRND_RANDOM are accepted and change nothing.
+fn sys_getrandom(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let (buf_ptr, len, flags) = (trap_frame.a1 as *mut u8, trap_frame.a2, trap_frame.a3);
+    if flags & !(crate::random::GRND_NONBLOCK | crate::random::GRND_RANDOM) != 0 {
+        return Err(SysError::InvalidArgument);
+    }
+    if len == 0 {
+        return Ok(0);
+    }
+    check_user_write(buf_ptr, len)?;
+    let buf = unsafe { slice::from_raw_parts_mut(buf_ptr, len) };
+    crate::random::fill(buf);
+    Ok(len)
+}
+
+/// fsync(fd): flush the device holding an open file or directory, so
+/// its writes survive a crash. Other descriptors give EINVAL.
+fn sys_fsync(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let fd = trap_frame.a1;
+    with_current_fd_table_mut(|table| table.get(fd)?.sync())?;
+    Ok(0)
+}
+
+/// clock_gettime(clock, *mut Timespec): wall-clock time from the RTC
+/// for CLOCK_REALTIME, time since boot for CLOCK_MONOTONIC
+fn sys_clock_gettime(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let clock = trap_frame.a1;
+    let out = trap_frame.a2 as *mut crate::rtc::Timespec;
+    check_user_write(out, 1)?;
+    let now = crate::rtc::clock_gettime(clock).ok_or(SysError::InvalidArgument)?;
+    unsafe { ptr::write(out, now) };
+    Ok(0)
+}
+
+/// klog_read(*mut cursor, buf, len): copy kernel log text from `*cursor`
+/// (0 for the oldest still kept) and advance it. Returns bytes copied,
+/// 0 once caught up.
+fn sys_klog_read(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let cursor_ptr = trap_frame.a1 as *mut usize;
+    let buf_ptr = trap_frame.a2 as *mut u8;
+    let buf_len = trap_frame.a3;
+    check_user_write(cursor_ptr, 1)?;
+    if buf_len > 0 {
+        check_user_write(buf_ptr, buf_len)?;
+    }
+    let buf = if buf_len == 0 {
+        &mut [][..]
+    } else {
+        unsafe { slice::from_raw_parts_mut(buf_ptr, buf_len) }
+    };
+    let mut cursor = unsafe { ptr::read(cursor_ptr) };
+    let copied = klog::read(&mut cursor, buf);
+    unsafe { ptr::write(cursor_ptr, cursor) };
+    Ok(copied)
+}
+
+/// klog_level(level): print kernel messages up to `level` (0 errors
+/// only ... 3 debug) on the console; root only. `usize::MAX` just asks.
+/// Returns the previous level.
+fn sys_klog_level(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let previous = klog::console_level() as usize;
+    if trap_frame.a1 == usize::MAX {
+        return Ok(previous);
+    }
+    let level = klog::Level::from_index(trap_frame.a1).ok_or(SysError::InvalidArgument)?;
+    if current_uid() != ROOT_UID {
+        return Err(SysError::NotPermitted);
+    }
+    klog::set_console_level(level);
+    Ok(previous)
+}
+
+/// klog_subsystem(subsystem, level): record messages from `subsystem`
+/// (a `klog::Subsystem` number) up to `level`; root only. `usize::MAX`
+/// just asks. Returns the previous level.
+fn sys_klog_subsystem(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let subsystem = *klog::Subsystem::ALL
+        .get(trap_frame.a1)
+        .ok_or(SysError::InvalidArgument)?;
+    let previous = klog::level(subsystem) as usize;
+    if trap_frame.a2 == usize::MAX {
+        return Ok(previous);
+    }
+    let level = klog::Level::from_index(trap_frame.a2).ok_or(SysError::InvalidArgument)?;
+    if current_uid() != ROOT_UID {
+        return Err(SysError::NotPermitted);
+    }
+    klog::set_level(subsystem, level);
+    Ok(previous)
+}
+
+/// klog_clear(): forget the kernel log logged so far, so readers start
+/// after it; root only
+fn sys_klog_clear(_trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    if current_uid() != ROOT_UID {
+        return Err(SysError::NotPermitted);
+    }
+    klog::clear();
+    Ok(0)
+}
+
+/// kill(pid, sig): a negative pid signals the whole group `-pid`.
+/// Signal 0 only checks that the target exists.
+fn sys_kill(trap_frame: &TrapFrame) -> Result<usize, SysError> {
+    let target = trap_frame.a1 as isize;
+    let sig = trap_frame.a2;
+    if sig > crate::signal::MAX_SIGNAL || target == 0 {
+        return Err(SysError::InvalidArgument);
+    }
+    // Non-root callers may only signal their own user's processes
+    let (targets, permitted) = {
+        let table = PROCESS_TABLE.lock();
+        let sender = table.current().map_or(ROOT_UID, |p| p.uid);
+        let targets = if target < 0 {
+            table.group_members(target.unsigned_abs())
+        } else {
+            let pid = target as usize;
+            let live = table.get(pid).is_some_and(|p| !p.has_exited());
+            if live { vec![pid] } else { Vec::new() }
+        };
+        let permitted: Vec<Pid> = targets
+            .iter()
+            .copied()
+            .filter(|&pid| sender == ROOT_UID || table.get(pid).is_some_and(|p| p.uid == sender))
+            .collect();
+        (targets, permitted)
+    };
+    if targets.is_empty() {
+        return Err(SysError::NoSuchProcess);
+    }
+    if permitted.is_empty() {
+        return Err(SysError::NotPermitted);
+    }
+    if sig != 0 {
+        for pid in permitted {
+            crate::signal::send(pid, sig);
+        }
+    }
+    Ok(0)
+}
+
 fn proc_errno(err: crate::proc::SpawnError) -> isize {
     match err {
         crate::proc::SpawnError::TooManyProcesses => -24, // EMFILE
         crate::proc::SpawnError::ProgramNotFound => ENOENT,
         crate::proc::SpawnError::LoadFailed => EIO,
-        crate::proc::SpawnError::OutOfMemory => -12, // ENOMEM
+        crate::proc::SpawnError::NotExecutable => ENOEXEC,
+        crate::proc::SpawnError::OutOfMemory => ENOMEM,
+        crate::proc::SpawnError::InvalidLimit => EINVAL,
+        crate::proc::SpawnError::ChildLimit => EAGAIN,
     }
 }
@@ -1,11 +1,13 @@
-use alloc::collections::VecDeque;
+use alloc::{collections::VecDeque, vec::Vec};
 use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use spin::Mutex;
 
-const UART0_BASE: usize = 0x1000_0000;
 const REG_RBR: usize = 0; // Receiver Buffer Register (read)
 const REG_THR: usize = 0; // Transmitter Holding Register (write)
+const REG_DLL: usize = 0; // Divisor Latch low byte (DLAB set)
 const REG_IER: usize = 1; // Interrupt Enable Register
+const REG_DLM: usize = 1; // Divisor Latch high byte (DLAB set)
 const REG_FCR: usize = 2; // FIFO Control Register
 const REG_LCR: usize = 3; // Line Control Register
 const REG_MCR: usize = 4; // Modem Control Register
@@ -14,32 +16,156 @@ const REG_LSR: usize = 5; // Line Status Register
 const LSR_DATA_READY: u8 = 1 << 0;
 const LSR_THR_EMPTY: u8 = 1 << 5;
 
+const LCR_WORD_8: u8 = 0b11;
+const LCR_TWO_STOP_BITS: u8 = 1 << 2;
+const LCR_PARITY_ENABLE: u8 = 1 << 3;
+const LCR_EVEN_PARITY: u8 = 1 << 4;
+const LCR_DLAB: u8 = 1 << 7;
+
 const IER_RECEIVE_AVAILABLE: u8 = 1 << 0;
 
+/// Line settings both UARTs start with
+pub const DEFAULT_BAUD: u32 = 115_200;
+
+/// QEMU virt's UART; its device tree gives the 16550 a 3.6864 MHz clock
+const UART0: Uart = Uart::new(0x1000_0000, 3_686_400);
+
+/// `-device pci-serial`: a 16550 behind an I/O BAR, clocked for a
+/// 115200 baud base
+const PCI_SERIAL_VENDOR: u16 = 0x1b36;
+const PCI_SERIAL_DEVICE: u16 = 0x0002;
+const PCI_SERIAL_CLOCK_HZ: u32 = 1_843_200;
+
+/// Console bytes go through SBI ecalls instead of UART0's registers, for
+/// boards whose 16550 is elsewhere (`console=sbi` on the command line)
+static SBI_CONSOLE: AtomicBool = AtomicBool::new(false);
+
+/// Register base and interrupt line of the second UART; 0 until
+/// `init_secondary` finds one
+static UART1_BASE: AtomicUsize = AtomicUsize::new(0);
+static UART1_IRQ: AtomicU32 = AtomicU32::new(0);
+
 static RX_QUEUE: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
 
-fn read_reg(offset: usize) -> u8 {
-    unsafe { ptr::read_volatile((UART0_BASE + offset) as *const u8) }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UartError {
+    /// The clock cannot be divided down to this rate
+    UnsupportedBaud(u32),
+    /// Only 1 and 2 stop bits exist
+    UnsupportedStopBits(u8),
+    /// The console goes through SBI, whose line settings the firmware owns
+    FirmwareConsole,
 }
 
-fn write_reg(offset: usize, value: u8) {
-    unsafe { ptr::write_volatile((UART0_BASE + offset) as *mut u8, value) }
+/// A 16550-compatible UART: where its registers are and the input clock
+/// its baud rate divisor divides
+#[derive(Debug, Clone, Copy)]
+pub struct Uart {
+    base: usize,
+    clock_hz: u32,
+}
+
+impl Uart {
+    const fn new(base: usize, clock_hz: u32) -> Self {
+        Self { base, clock_hz }
+    }
+
+    fn read_reg(&self, offset: usize) -> u8 {
+        unsafe { ptr::read_volatile((self.base + offset) as *const u8) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u8) {
+        unsafe { ptr::write_volatile((self.base + offset) as *mut u8, value) }
+    }
+
+    /// Set the line to `baud` with 8 data bits, `parity` and `stop_bits`,
+    /// then enable the FIFOs and receive interrupts
+    pub fn configure(&self, baud: u32, parity: Parity, stop_bits: u8) -> Result<(), UartError> {
+        let mut line = LCR_WORD_8;
+        match stop_bits {
+            1 => {}
+            2 => line |= LCR_TWO_STOP_BITS,
+            _ => return Err(UartError::UnsupportedStopBits(stop_bits)),
+        }
+        match parity {
+            Parity::None => {}
+            Parity::Odd => line |= LCR_PARITY_ENABLE,
+            Parity::Even => line |= LCR_PARITY_ENABLE | LCR_EVEN_PARITY,
+        }
+        // The UART samples each bit 16 times; round to the nearest divisor
+        let divisor = match baud.checked_mul(16) {
+            Some(rate) if baud > 0 => (self.clock_hz + rate / 2) / rate,
+            _ => 0,
+        };
+        if !(1..=0xffff).contains(&divisor) {
+            return Err(UartError::UnsupportedBaud(baud));
+        }
+        self.write_reg(REG_IER, 0x00);
+        self.write_reg(REG_LCR, LCR_DLAB);
+        self.write_reg(REG_DLL, divisor as u8);
+        self.write_reg(REG_DLM, (divisor >> 8) as u8);
+        self.write_reg(REG_LCR, line);
+        self.write_reg(REG_FCR, 0x07); // Enable FIFO, clear RX/TX queues.
+        self.write_reg(REG_MCR, 0x0B); // Assert DTR, RTS, OUT2 (enables interrupts).
+        self.write_reg(REG_IER, IER_RECEIVE_AVAILABLE);
+        Ok(())
+    }
+
+    fn write_byte(&self, byte: u8) {
+        while self.read_reg(REG_LSR) & LSR_THR_EMPTY == 0 {}
+        self.write_reg(REG_THR, byte);
+    }
+
+    fn write_bytes(&self, bytes: &[u8]) {
+        for &byte in bytes {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+    }
+
+    fn data_ready(&self) -> bool {
+        self.read_reg(REG_LSR) & LSR_DATA_READY != 0
+    }
 }
 
 pub fn init() {
-    // Configure 16550-compatible UART for 8N1, enable FIFO, and RX interrupts.
-    write_reg(REG_LCR, 0x80); // Set DLAB to access divisor registers.
-    write_reg(REG_THR, 0x00); // Divisor LSB (ignored by QEMU default clock).
-    write_reg(REG_IER, 0x00); // Divisor MSB.
-    write_reg(REG_LCR, 0x03); // 8 bits, no parity, one stop bit.
-    write_reg(REG_FCR, 0x07); // Enable FIFO, clear RX/TX queues.
-    write_reg(REG_MCR, 0x0B); // Assert DTR, RTS, OUT2 (enables interrupts).
-    write_reg(REG_IER, IER_RECEIVE_AVAILABLE);
+    // 8N1 at the default rate cannot be refused
+    let _ = configure(DEFAULT_BAUD, Parity::None, 1);
+}
+
+/// Route the console through SBI's putchar and getchar from now on;
+/// input is then polled on the timer tick since there is no interrupt
+pub fn use_sbi() {
+    SBI_CONSOLE.store(true, Ordering::Relaxed);
+}
+
+pub fn is_sbi() -> bool {
+    SBI_CONSOLE.load(Ordering::Relaxed)
+}
+
+/// Reprogram the line settings of the first UART
+pub fn configure(baud: u32, parity: Parity, stop_bits: u8) -> Result<(), UartError> {
+    if is_sbi() {
+        return Err(UartError::FirmwareConsole);
+    }
+    UART0.configure(baud, parity, stop_bits)
 }
 
 pub fn write_byte(byte: u8) {
-    while read_reg(REG_LSR) & LSR_THR_EMPTY == 0 {}
-    write_reg(REG_THR, byte);
+    if is_sbi() {
+        sbi::legacy::console_putchar(byte);
+    } else {
+        UART0.write_byte(byte);
+    }
 }
 
 pub fn write_bytes(bytes: &[u8]) {
@@ -65,9 +191,13 @@ pub fn read_byte_blocking() -> u8 {
         if let Some(b) = read_byte_nonblocking() {
             return b;
         }
-        // Fallback to polling the UART data-ready bit.
-        if read_reg(REG_LSR) & LSR_DATA_READY != 0 {
-            return read_reg(REG_RBR);
+        // Fallback to polling the firmware or the UART data-ready bit.
+        if is_sbi() {
+            if let Some(b) = sbi::legacy::console_getchar() {
+                return b;
+            }
+        } else if UART0.data_ready() {
+            return UART0.read_reg(REG_RBR);
         }
         core::hint::spin_loop();
     }
@@ -77,12 +207,86 @@ pub fn has_pending_byte() -> bool {
     !RX_QUEUE.lock().is_empty()
 }
 
+/// Drain the receive FIFO and hand the bytes to the virtual terminals
 pub fn handle_interrupt() {
     let mut queue = RX_QUEUE.lock();
-    while read_reg(REG_LSR) & LSR_DATA_READY != 0 {
-        let byte = read_reg(REG_RBR);
-        queue.push_back(byte);
+    while UART0.data_ready() {
+        queue.push_back(UART0.read_reg(REG_RBR));
     }
     drop(queue);
+    crate::vt::receive();
     crate::interrupts::signal_event();
 }
+
+/// Timer tick: with the SBI console, fetch what was typed since the last
+/// one and hand it to the virtual terminals
+pub fn poll_sbi() {
+    if !is_sbi() {
+        return;
+    }
+    let mut queue = RX_QUEUE.lock();
+    let before = queue.len();
+    while let Some(byte) = sbi::legacy::console_getchar() {
+        queue.push_back(byte);
+    }
+    let received = queue.len() > before;
+    drop(queue);
+    if received {
+        crate::vt::receive();
+        crate::interrupts::signal_event();
+    }
+}
+
+/// Find a second 16550 on the PCI bus and bring it up at 115200 8N1.
+/// Returns whether there is one.
+pub fn init_secondary() -> bool {
+    let Some(function) = crate::pci::scan().into_iter().find(|function| {
+        function.vendor_id() == PCI_SERIAL_VENDOR && function.device_id() == PCI_SERIAL_DEVICE
+    }) else {
+        return false;
+    };
+    let (Some(base), Some(irq)) = (function.io_bar(0), function.irq()) else {
+        return false;
+    };
+    let uart = Uart::new(base, PCI_SERIAL_CLOCK_HZ);
+    if uart.configure(DEFAULT_BAUD, Parity::None, 1).is_err() {
+        return false;
+    }
+    UART1_IRQ.store(irq, Ordering::Relaxed);
+    UART1_BASE.store(base, Ordering::Release);
+    true
+}
+
+/// The second UART, if `init_secondary` found one
+pub fn secondary() -> Option<Uart> {
+    match UART1_BASE.load(Ordering::Acquire) {
+        0 => None,
+        base => Some(Uart::new(base, PCI_SERIAL_CLOCK_HZ)),
+    }
+}
+
+/// Interrupt line of the second UART, which it may share with PCI devices
+pub fn secondary_irq() -> Option<u32> {
+    secondary().map(|_| UART1_IRQ.load(Ordering::Relaxed))
+}
+
+/// Write to the second UART, turning `\n` into `\r\n`; false if there
+/// is none
+pub fn write_secondary(bytes: &[u8]) -> bool {
+    let Some(uart) = secondary() else {
+        return false;
+    };
+    uart.write_bytes(bytes);
+    true
+}
+
+/// Drain the second UART's receive FIFO
+pub fn receive_secondary() -> Vec<u8> {
+    let mut received = Vec::new();
+    if let Some(uart) = secondary() {
+        while uart.data_ready() {
+            received.push(uart.read_reg(REG_RBR));
+        }
+    }
+    received
+}
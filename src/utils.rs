@@ -1,5 +1,5 @@
 pub fn print(t: &str) {
-    crate::uart::write_str(t);
+    crate::console::write_log(t);
 }
 
 struct Writer {}
@@ -17,12 +17,15 @@ impl core::fmt::Write for Writer {
     }
 }
 
+/// Power off once every mounted disk has been flushed. Firmware without
+/// the SBI system reset extension gets the legacy shutdown call.
 pub fn shutdown() -> ! {
+    crate::fs::sync_all();
     let _ = sbi::system_reset::system_reset(
         sbi::system_reset::ResetType::Shutdown,
         sbi::system_reset::ResetReason::NoReason,
     );
-    unreachable!("System reset failed");
+    sbi::legacy::shutdown()
 }
 
 #[macro_export]
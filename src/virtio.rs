@@ -1,81 +1,563 @@
 use core::sync::atomic::{Ordering, fence};
-use core::{hint::spin_loop, mem::size_of, ptr};
+use alloc::boxed::Box;
+use core::{fmt, mem::size_of, ptr};
 
+use alloc::vec::Vec;
+use const_default::ConstDefault;
 use spin::Mutex;
 
-pub mod block {
-    use const_default::ConstDefault;
+use crate::{klog, pci};
+use block::VirtioError;
 
-    use super::*;
+/// First virtio-mmio slot on QEMU virt; the rest follow at `MMIO_STRIDE`
+const MMIO_BASE: usize = 0x1000_1000;
+const MMIO_STRIDE: usize = 0x1000;
+const MMIO_SLOTS: usize = 8;
+/// PLIC source of slot 0; slot `n` uses `MMIO_FIRST_IRQ + n`
+const MMIO_FIRST_IRQ: u32 = 1;
+const MMIO_MAGIC: u32 = 0x7472_6976;
 
-    const VIRTIO_MMIO_BASE: usize = 0x1000_1000;
-    const QUEUE_SIZE: usize = 8;
-    const SECTOR_SIZE: usize = 512;
+/// Red Hat's PCI vendor ID, which every virtio-pci function carries
+const PCI_VENDOR_VIRTIO: u16 = 0x1af4;
+/// Modern virtio-pci functions use `PCI_DEVICE_MODERN + device ID`;
+/// transitional ones sit below it and keep the device ID in the
+/// subsystem ID
+const PCI_DEVICE_MODERN: u16 = 0x1040;
+const PCI_DEVICE_TRANSITIONAL: u16 = 0x1000;
 
-    const MAGIC_VALUE: usize = 0x000;
-    const VERSION: usize = 0x004;
-    const DEVICE_ID: usize = 0x008;
-    const DEVICE_FEATURES: usize = 0x010;
-    const DEVICE_FEATURES_SEL: usize = 0x014;
-    const DRIVER_FEATURES: usize = 0x020;
-    const DRIVER_FEATURES_SEL: usize = 0x024;
-    const QUEUE_SEL: usize = 0x030;
-    const QUEUE_NUM_MAX: usize = 0x034;
-    const QUEUE_NUM: usize = 0x038;
-    const QUEUE_READY: usize = 0x044;
-    const QUEUE_NOTIFY: usize = 0x050;
-    const INTERRUPT_STATUS: usize = 0x060;
-    const INTERRUPT_ACK: usize = 0x064;
-    const STATUS: usize = 0x070;
-    const QUEUE_DESC_LOW: usize = 0x080;
-    const QUEUE_DESC_HIGH: usize = 0x084;
-    const QUEUE_AVAIL_LOW: usize = 0x090;
-    const QUEUE_AVAIL_HIGH: usize = 0x094;
-    const QUEUE_USED_LOW: usize = 0x0a0;
-    const QUEUE_USED_HIGH: usize = 0x0a4;
-    const CONFIG_GENERATION: usize = 0x0fc;
-    const CONFIG_OFFSET: usize = 0x100;
-
-    const STATUS_ACKNOWLEDGE: u32 = 1;
-    const STATUS_DRIVER: u32 = 2;
-    const STATUS_FEATURES_OK: u32 = 8;
-    const STATUS_DRIVER_OK: u32 = 4;
-
-    const VIRTIO_F_VERSION_1_BIT: u32 = 0;
-
-    static DEVICE: Mutex<Option<VirtIoBlock>> = Mutex::new(None);
+/// Device IDs from the virtio spec
+pub const DEVICE_NET: u32 = 1;
+pub const DEVICE_BLOCK: u32 = 2;
+pub const DEVICE_CONSOLE: u32 = 3;
+pub const DEVICE_ENTROPY: u32 = 4;
+pub const DEVICE_9P: u32 = 9;
 
-    #[repr(C)]
-    #[derive(ConstDefault, Clone, Copy)]
-    struct VirtqDesc {
-        addr: u64,
-        len: u32,
-        flags: u16,
-        next: u16,
+/// How the driver reaches a device's registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// A virtio-mmio register block
+    Mmio(usize),
+    /// The structures a virtio-pci function's capabilities point at
+    Pci(PciRegions),
+}
+
+/// Addresses of a virtio-pci function's configuration structures, inside
+/// its memory BARs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciRegions {
+    function: pci::Function,
+    common: usize,
+    notify: usize,
+    notify_multiplier: u32,
+    isr: usize,
+    /// Device-specific config; 0 for devices without any
+    device: usize,
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Mmio(base) => write!(f, "{:#x}", base),
+            Transport::Pci(regions) => write!(f, "pci {}", regions.function),
+        }
     }
+}
 
-    #[repr(C, align(2))]
-    #[derive(ConstDefault, Debug, Clone, Copy)]
-    struct VirtqAvail {
-        flags: u16,
-        idx: u16,
-        ring: [u16; QUEUE_SIZE],
+/// A device found on either transport
+#[derive(Debug, Clone, Copy)]
+pub struct VirtioDevice {
+    pub transport: Transport,
+    pub irq: u32,
+    pub device_id: u32,
+    pub version: u32,
+}
+
+impl VirtioDevice {
+    pub fn kind(&self) -> &'static str {
+        match self.device_id {
+            DEVICE_NET => "net",
+            DEVICE_BLOCK => "block",
+            DEVICE_CONSOLE => "console",
+            DEVICE_ENTROPY => "entropy",
+            DEVICE_9P => "9p",
+            _ => "unknown",
+        }
     }
+}
 
-    #[repr(C)]
-    #[derive(ConstDefault, Debug, Clone, Copy)]
-    struct VirtqUsedElem {
-        id: u32,
-        len: u32,
+/// Devices found by `probe`, MMIO slots first, then PCI functions
+static REGISTRY: Mutex<Option<Vec<VirtioDevice>>> = Mutex::new(None);
+
+/// Scan every virtio-mmio slot and the PCI bus once and remember what is
+/// there. Empty MMIO slots have the magic value but device ID 0.
+pub fn probe() -> Vec<VirtioDevice> {
+    let mut registry = REGISTRY.lock();
+    if let Some(devices) = registry.as_ref() {
+        return devices.clone();
     }
+    let mut devices = Vec::new();
+    for slot in 0..MMIO_SLOTS {
+        let base = MMIO_BASE + slot * MMIO_STRIDE;
+        let read = |offset: usize| unsafe { ptr::read_volatile((base + offset) as *const u32) };
+        if read(0x000) != MMIO_MAGIC {
+            continue;
+        }
+        let device_id = read(0x008);
+        if device_id == 0 {
+            continue;
+        }
+        devices.push(VirtioDevice {
+            transport: Transport::Mmio(base),
+            irq: MMIO_FIRST_IRQ + slot as u32,
+            device_id,
+            version: read(0x004),
+        });
+    }
+    for function in pci::scan() {
+        if let Some(device) = probe_pci(function) {
+            devices.push(device);
+        }
+    }
+    *registry = Some(devices.clone());
+    devices
+}
 
-    #[repr(C, align(4096))]
-    #[derive(ConstDefault, Debug, Clone, Copy)]
-    struct VirtqUsed {
-        flags: u16,
-        idx: u16,
-        ring: [VirtqUsedElem; QUEUE_SIZE],
+/// The virtio device behind PCI `function`, if it is one we can drive.
+/// Functions without the modern capabilities are legacy-only and skipped.
+fn probe_pci(function: pci::Function) -> Option<VirtioDevice> {
+    if function.vendor_id() != PCI_VENDOR_VIRTIO {
+        return None;
+    }
+    let device_id = match function.device_id() {
+        id if id >= PCI_DEVICE_MODERN => (id - PCI_DEVICE_MODERN) as u32,
+        id if id >= PCI_DEVICE_TRANSITIONAL => function.subsystem_id() as u32,
+        _ => return None,
+    };
+    let regions = pci_regions(function)?;
+    Some(VirtioDevice {
+        transport: Transport::Pci(regions),
+        irq: function.irq()?,
+        device_id,
+        version: 2,
+    })
+}
+
+/// Vendor-specific capability that locates one virtio structure
+const PCI_CAP_VENDOR: u8 = 0x09;
+const PCI_CAP_COMMON_CFG: u8 = 1;
+const PCI_CAP_NOTIFY_CFG: u8 = 2;
+const PCI_CAP_ISR_CFG: u8 = 3;
+const PCI_CAP_DEVICE_CFG: u8 = 4;
+
+/// Follow the function's virtio capabilities to its structures. The spec
+/// lets a device offer several of each type; the first is preferred.
+fn pci_regions(function: pci::Function) -> Option<PciRegions> {
+    let (mut common, mut notify, mut isr, mut device) = (None, None, None, None);
+    let mut notify_multiplier = 0;
+    for (id, cap) in function.capabilities() {
+        if id != PCI_CAP_VENDOR {
+            continue;
+        }
+        let Some(bar) = function.bar(function.read8(cap + 4) as usize) else {
+            continue;
+        };
+        let address = bar + function.read32(cap + 8) as usize;
+        let slot = match function.read8(cap + 3) {
+            PCI_CAP_COMMON_CFG => &mut common,
+            PCI_CAP_NOTIFY_CFG => {
+                if notify.is_none() {
+                    notify_multiplier = function.read32(cap + 16);
+                }
+                &mut notify
+            }
+            PCI_CAP_ISR_CFG => &mut isr,
+            PCI_CAP_DEVICE_CFG => &mut device,
+            _ => continue,
+        };
+        slot.get_or_insert(address);
+    }
+    Some(PciRegions {
+        function,
+        common: common?,
+        notify: notify?,
+        notify_multiplier,
+        isr: isr?,
+        device: device.unwrap_or(0),
+    })
+}
+
+/// The `index`th device (from 0) with `device_id`
+pub fn find(device_id: u32, index: usize) -> Option<VirtioDevice> {
+    probe()
+        .into_iter()
+        .filter(|device| device.device_id == device_id)
+        .nth(index)
+}
+
+/// Call `handle` for each device raising PLIC source `irq`; PCI functions
+/// share four INTx lines, so there may be several. Called from the
+/// interrupt handler, so it does not allocate; devices are only known
+/// after `probe`.
+pub fn for_each_on_irq(irq: u32, mut handle: impl FnMut(&VirtioDevice)) {
+    let registry = REGISTRY.lock();
+    for device in registry.iter().flatten().filter(|device| device.irq == irq) {
+        handle(device);
+    }
+}
+
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const DEVICE_FEATURES: usize = 0x010;
+const DEVICE_FEATURES_SEL: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+const QUEUE_DESC_LOW: usize = 0x080;
+const QUEUE_DESC_HIGH: usize = 0x084;
+const QUEUE_AVAIL_LOW: usize = 0x090;
+const QUEUE_AVAIL_HIGH: usize = 0x094;
+const QUEUE_USED_LOW: usize = 0x0a0;
+const QUEUE_USED_HIGH: usize = 0x0a4;
+const CONFIG_GENERATION: usize = 0x0fc;
+const CONFIG_OFFSET: usize = 0x100;
+
+/// `virtio_pci_common_cfg` offsets
+const PCI_DEVICE_FEATURE_SELECT: usize = 0x00;
+const PCI_DEVICE_FEATURE: usize = 0x04;
+const PCI_DRIVER_FEATURE_SELECT: usize = 0x08;
+const PCI_DRIVER_FEATURE: usize = 0x0c;
+const PCI_DEVICE_STATUS: usize = 0x14;
+const PCI_CONFIG_GENERATION: usize = 0x15;
+const PCI_QUEUE_SELECT: usize = 0x16;
+const PCI_QUEUE_SIZE: usize = 0x18;
+const PCI_QUEUE_ENABLE: usize = 0x1c;
+const PCI_QUEUE_NOTIFY_OFF: usize = 0x1e;
+const PCI_QUEUE_DESC: usize = 0x20;
+const PCI_QUEUE_DRIVER: usize = 0x28;
+const PCI_QUEUE_DEVICE: usize = 0x30;
+
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_FEATURES_OK: u32 = 8;
+const STATUS_DRIVER_OK: u32 = 4;
+/// Set by the device after an error it cannot recover from by itself
+const STATUS_DEVICE_NEEDS_RESET: u32 = 64;
+const VIRTIO_F_VERSION_1_BIT: u32 = 0;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+/// Descriptors per virtqueue: room for five block requests in flight at
+/// three descriptors each
+const QUEUE_SIZE: usize = 16;
+
+#[repr(C)]
+#[derive(ConstDefault, Clone, Copy)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C, align(2))]
+#[derive(ConstDefault, Debug, Clone, Copy)]
+struct VirtqAvail {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+}
+
+#[repr(C)]
+#[derive(ConstDefault, Debug, Clone, Copy)]
+struct VirtqUsedElem {
+    id: u32,
+    len: u32,
+}
+
+#[repr(C, align(4096))]
+#[derive(ConstDefault, Debug, Clone, Copy)]
+struct VirtqUsed {
+    flags: u16,
+    idx: u16,
+    ring: [VirtqUsedElem; QUEUE_SIZE],
+}
+
+/// Ring memory shared with the device for one virtqueue
+#[repr(C)]
+#[derive(ConstDefault, Clone, Copy)]
+struct QueueMemory {
+    desc: [VirtqDesc; QUEUE_SIZE],
+    avail: VirtqAvail,
+    used: VirtqUsed,
+}
+
+/// Check the device on `transport`, reset it and negotiate VERSION_1
+/// plus whichever of `features_lo` it offers. Returns the low feature
+/// bits both sides accepted.
+unsafe fn start_init(
+    transport: Transport,
+    device_id: u32,
+    features_lo: u32,
+) -> Result<u32, VirtioError> {
+    // probe already matched PCI functions by ID
+    if let Transport::Mmio(base) = transport {
+        if read32(base, MAGIC_VALUE) != MMIO_MAGIC {
+            return Err(VirtioError::DeviceNotFound);
+        }
+        let version = read32(base, VERSION);
+        if version != 2 {
+            return Err(VirtioError::LegacyOnly(version));
+        }
+        if read32(base, DEVICE_ID) != device_id {
+            return Err(VirtioError::UnsupportedDevice);
+        }
+    }
+
+    transport.set_status(0);
+    transport.set_status(STATUS_ACKNOWLEDGE);
+    transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+    let device_features_lo = transport.device_features(0);
+    let driver_features_lo = device_features_lo & features_lo;
+    transport.set_driver_features(0, driver_features_lo);
+
+    let device_features_hi = transport.device_features(1);
+    let mut driver_features_hi = 0u32;
+    if (device_features_hi & (1 << VIRTIO_F_VERSION_1_BIT)) != 0 {
+        driver_features_hi |= 1 << VIRTIO_F_VERSION_1_BIT;
+    }
+    transport.set_driver_features(1, driver_features_hi);
+
+    transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK);
+    if (transport.status() & STATUS_FEATURES_OK) == 0 {
+        return Err(VirtioError::DeviceRejectedFeatures);
+    }
+    Ok(driver_features_lo)
+}
+
+/// Hand queue `index` the zeroed ring memory at `memory`. Returns the
+/// queue size, at most `QUEUE_SIZE`.
+unsafe fn setup_queue(
+    transport: Transport,
+    index: u32,
+    memory: *mut QueueMemory,
+) -> Result<u16, VirtioError> {
+    let queue_max = transport.select_queue(index);
+    if queue_max == 0 {
+        return Err(VirtioError::QueueUnavailable);
     }
+    let queue_size = core::cmp::min(queue_max as usize, QUEUE_SIZE) as u16;
+
+    let (desc_addr, avail_addr, used_addr) = unsafe {
+        (
+            ptr::addr_of!((*memory).desc) as u64,
+            ptr::addr_of!((*memory).avail) as u64,
+            ptr::addr_of!((*memory).used) as u64,
+        )
+    };
+    match transport {
+        Transport::Mmio(base) => {
+            write32(base, QUEUE_NUM, queue_size as u32);
+            write64(base, QUEUE_DESC_LOW, QUEUE_DESC_HIGH, desc_addr);
+            write64(base, QUEUE_AVAIL_LOW, QUEUE_AVAIL_HIGH, avail_addr);
+            write64(base, QUEUE_USED_LOW, QUEUE_USED_HIGH, used_addr);
+            write32(base, QUEUE_READY, 1);
+        }
+        Transport::Pci(regions) => {
+            let common = regions.common;
+            write16(common, PCI_QUEUE_SIZE, queue_size);
+            write64(common, PCI_QUEUE_DESC, PCI_QUEUE_DESC + 4, desc_addr);
+            write64(common, PCI_QUEUE_DRIVER, PCI_QUEUE_DRIVER + 4, avail_addr);
+            write64(common, PCI_QUEUE_DEVICE, PCI_QUEUE_DEVICE + 4, used_addr);
+            write16(common, PCI_QUEUE_ENABLE, 1);
+        }
+    }
+    Ok(queue_size)
+}
+
+/// Tell the device the driver is ready
+fn finish_init(transport: Transport) {
+    transport.set_status(
+        STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK | STATUS_DRIVER_OK,
+    );
+}
+
+/// Clear the device's pending interrupt. Reading the PCI ISR register
+/// is what acknowledges it there.
+pub fn ack_interrupt(transport: Transport) {
+    match transport {
+        Transport::Mmio(base) => {
+            let interrupt_status = read32(base, INTERRUPT_STATUS);
+            if interrupt_status != 0 {
+                write32(base, INTERRUPT_ACK, interrupt_status);
+            }
+        }
+        Transport::Pci(regions) => {
+            read8(regions.isr, 0);
+        }
+    }
+}
+
+impl Transport {
+    fn status(&self) -> u32 {
+        match *self {
+            Transport::Mmio(base) => read32(base, STATUS),
+            Transport::Pci(regions) => read8(regions.common, PCI_DEVICE_STATUS) as u32,
+        }
+    }
+
+    fn set_status(&self, status: u32) {
+        match *self {
+            Transport::Mmio(base) => write32(base, STATUS, status),
+            Transport::Pci(regions) => write8(regions.common, PCI_DEVICE_STATUS, status as u8),
+        }
+    }
+
+    /// Feature bits `32 * select` and up that the device offers
+    fn device_features(&self, select: u32) -> u32 {
+        match *self {
+            Transport::Mmio(base) => {
+                write32(base, DEVICE_FEATURES_SEL, select);
+                read32(base, DEVICE_FEATURES)
+            }
+            Transport::Pci(regions) => {
+                write32(regions.common, PCI_DEVICE_FEATURE_SELECT, select);
+                read32(regions.common, PCI_DEVICE_FEATURE)
+            }
+        }
+    }
+
+    fn set_driver_features(&self, select: u32, features: u32) {
+        match *self {
+            Transport::Mmio(base) => {
+                write32(base, DRIVER_FEATURES_SEL, select);
+                write32(base, DRIVER_FEATURES, features);
+            }
+            Transport::Pci(regions) => {
+                write32(regions.common, PCI_DRIVER_FEATURE_SELECT, select);
+                write32(regions.common, PCI_DRIVER_FEATURE, features);
+            }
+        }
+    }
+
+    /// Point the queue registers at queue `index`; returns the most
+    /// descriptors it takes, 0 if it does not exist
+    fn select_queue(&self, index: u32) -> u32 {
+        match *self {
+            Transport::Mmio(base) => {
+                write32(base, QUEUE_SEL, index);
+                read32(base, QUEUE_NUM_MAX)
+            }
+            Transport::Pci(regions) => {
+                write16(regions.common, PCI_QUEUE_SELECT, index as u16);
+                read16(regions.common, PCI_QUEUE_SIZE) as u32
+            }
+        }
+    }
+
+    /// Tell the device queue `index` has new buffers
+    fn notify(&self, index: u32) {
+        match *self {
+            Transport::Mmio(base) => write32(base, QUEUE_NOTIFY, index),
+            Transport::Pci(regions) => {
+                write16(regions.common, PCI_QUEUE_SELECT, index as u16);
+                let offset = read16(regions.common, PCI_QUEUE_NOTIFY_OFF) as usize;
+                let address = regions.notify + offset * regions.notify_multiplier as usize;
+                write16(address, 0, index as u16);
+            }
+        }
+    }
+
+    fn config_generation(&self) -> u32 {
+        match *self {
+            Transport::Mmio(base) => read32(base, CONFIG_GENERATION),
+            Transport::Pci(regions) => read8(regions.common, PCI_CONFIG_GENERATION) as u32,
+        }
+    }
+
+    /// Address of the device-specific config space
+    fn config(&self) -> usize {
+        match *self {
+            Transport::Mmio(base) => base + CONFIG_OFFSET,
+            Transport::Pci(regions) => regions.device,
+        }
+    }
+}
+
+fn read8(base: usize, offset: usize) -> u8 {
+    unsafe { ptr::read_volatile((base + offset) as *const u8) }
+}
+
+fn write8(base: usize, offset: usize, value: u8) {
+    unsafe { ptr::write_volatile((base + offset) as *mut u8, value) };
+}
+
+fn read16(base: usize, offset: usize) -> u16 {
+    unsafe { ptr::read_volatile((base + offset) as *const u16) }
+}
+
+fn write16(base: usize, offset: usize, value: u16) {
+    unsafe { ptr::write_volatile((base + offset) as *mut u16, value) };
+}
+
+fn read32(base: usize, offset: usize) -> u32 {
+    unsafe { ptr::read_volatile((base + offset) as *const u32) }
+}
+
+fn write32(base: usize, offset: usize, value: u32) {
+    unsafe { ptr::write_volatile((base + offset) as *mut u32, value) };
+}
+
+fn write64(base: usize, low_offset: usize, high_offset: usize, value: u64) {
+    write32(base, low_offset, value as u32);
+    write32(base, high_offset, (value >> 32) as u32);
+}
+
+pub mod block {
+    use alloc::{
+        alloc::{Layout, alloc_zeroed, handle_alloc_error},
+        format,
+        string::String,
+        sync::Arc,
+    };
+    use riscv::register::sstatus::{self, SPP};
+
+    use super::*;
+    use crate::proc::{INVALID_PID, PROCESS_TABLE, Pid};
+    use crate::scheduler::Scheduler;
+
+    /// Block devices the driver handles, named `vda`, `vdb`, ...
+    pub const MAX_DEVICES: usize = 4;
+
+    const SECTOR_SIZE: usize = 512;
+    /// Most sectors moved by one request; we negotiate neither SIZE_MAX
+    /// nor SEG_MAX, so this only bounds how long one request holds the
+    /// queue
+    const MAX_REQUEST_SECTORS: usize = 128;
+    /// Device refuses writes
+    const VIRTIO_BLK_F_RO: u32 = 5;
+    /// Device reports its logical block size in `blk_size`
+    const VIRTIO_BLK_F_BLK_SIZE: u32 = 6;
+    /// Device has a write cache that VIRTIO_BLK_T_FLUSH drains
+    const VIRTIO_BLK_F_FLUSH: u32 = 9;
+    const VIRTIO_BLK_S_OK: u8 = 0;
+    const VIRTIO_BLK_S_IOERR: u8 = 1;
+    const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+    /// Tries per request before an I/O error is reported
+    const MAX_ATTEMPTS: usize = 3;
+
+    /// Initialized devices, indexed like their names
+    static DEVICES: Mutex<[Option<VirtIoBlock>; MAX_DEVICES]> =
+        Mutex::new([const { None }; MAX_DEVICES]);
 
     #[repr(C)]
     #[derive(ConstDefault, Debug, Clone, Copy)]
@@ -85,9 +567,11 @@ pub mod block {
         sector: u64,
     }
 
+    #[derive(Clone, Copy)]
     enum RequestType {
         In = 0,
         Out = 1,
+        Flush = 4,
     }
 
     #[repr(C)]
@@ -153,260 +637,1158 @@ pub mod block {
         QueueUnavailable,
         DeviceRejectedFeatures,
         DeviceFailure,
+        /// A request kept failing after retries
+        IoError,
     }
 
-    #[derive(Clone, Copy)]
+    #[derive(Clone)]
     pub struct VirtIoBlock {
-        regs_base: usize,
+        /// Index into `DEVICES`
+        slot: usize,
+        transport: Transport,
         capacity_sectors: u64,
         queue_size: u16,
+        /// Logical block size; requests still address 512-byte sectors
+        block_size: usize,
+        /// VIRTIO_BLK_F_FLUSH was negotiated
+        flush: bool,
+        /// VIRTIO_BLK_F_RO was negotiated
+        read_only: bool,
+        /// Ring memory and request bookkeeping, shared by every handle
+        /// on the device
+        queue: Arc<Mutex<VirtQueueState>>,
     }
 
     impl VirtIoBlock {
         pub fn total_blocks(&self) -> u32 {
-            self.capacity_sectors.min(u32::MAX as u64) as u32
+            (self.capacity_sectors / self.sectors_per_block()).min(u32::MAX as u64) as u32
         }
 
-        pub fn read_block(&self, index: u32, buf: &mut [u8]) {
-            self.transfer(index, buf.as_mut_ptr(), buf.len(), RequestType::In);
+        /// Bytes in one block; `index` arguments count in these
+        pub fn block_size(&self) -> usize {
+            self.block_size
+        }
+
+        /// Whether the device is read-only media; the filesystem refuses
+        /// changes before any write reaches it
+        pub fn is_read_only(&self) -> bool {
+            self.read_only
+        }
+
+        fn sectors_per_block(&self) -> u64 {
+            (self.block_size / SECTOR_SIZE) as u64
+        }
+
+        fn first_sector(&self, index: u32) -> u64 {
+            index as u64 * self.sectors_per_block()
+        }
+
+        pub fn read_block(&self, index: u32, buf: &mut [u8]) -> Result<(), VirtioError> {
+            let sector = self.first_sector(index);
+            self.transfer(sector, buf.as_mut_ptr(), self.block_size, RequestType::In)
+        }
+
+        pub fn write_block(&self, index: u32, buf: &[u8]) -> Result<(), VirtioError> {
+            let sector = self.first_sector(index);
+            let ptr = buf.as_ptr() as *mut u8;
+            self.transfer(sector, ptr, self.block_size, RequestType::Out)
+        }
+
+        /// Read `buf.len() / block_size()` blocks starting at `index`,
+        /// as few requests as possible
+        pub fn read_blocks(&self, index: u32, buf: &mut [u8]) -> Result<(), VirtioError> {
+            let chunk = MAX_REQUEST_SECTORS * SECTOR_SIZE;
+            let whole = buf.len() / self.block_size * self.block_size;
+            for (i, part) in buf[..whole].chunks_mut(chunk).enumerate() {
+                let sector = self.first_sector(index) + (i * MAX_REQUEST_SECTORS) as u64;
+                self.transfer(sector, part.as_mut_ptr(), part.len(), RequestType::In)?;
+            }
+            Ok(())
         }
 
-        pub fn write_block(&self, index: u32, buf: &[u8]) {
-            self.transfer(index, buf.as_ptr() as *mut u8, buf.len(), RequestType::Out);
+        /// Write `buf.len() / block_size()` blocks starting at `index`,
+        /// as few requests as possible
+        pub fn write_blocks(&self, index: u32, buf: &[u8]) -> Result<(), VirtioError> {
+            let chunk = MAX_REQUEST_SECTORS * SECTOR_SIZE;
+            let whole = buf.len() / self.block_size * self.block_size;
+            for (i, part) in buf[..whole].chunks(chunk).enumerate() {
+                let sector = self.first_sector(index) + (i * MAX_REQUEST_SECTORS) as u64;
+                let ptr = part.as_ptr() as *mut u8;
+                self.transfer(sector, ptr, part.len(), RequestType::Out)?;
+            }
+            Ok(())
+        }
+
+        /// Wait until every completed write is on stable storage. Returns
+        /// false without doing anything if the device did not offer
+        /// VIRTIO_BLK_F_FLUSH; such a device is write-through, so a write
+        /// is already durable once it completes.
+        pub fn flush(&self) -> Result<bool, VirtioError> {
+            if !self.flush || self.read_only {
+                return Ok(false);
+            }
+            self.perform(0, ptr::null_mut(), 0, RequestType::Flush)?;
+            Ok(true)
+        }
+
+        /// Move `len` bytes, a whole number of blocks, starting at
+        /// `sector` in one request
+        fn transfer(
+            &self,
+            sector: u64,
+            buffer: *mut u8,
+            len: usize,
+            request: RequestType,
+        ) -> Result<(), VirtioError> {
+            assert!(len >= self.block_size && len.is_multiple_of(self.block_size));
+            let sectors = (len / SECTOR_SIZE) as u64;
+            assert!(sector + sectors <= self.capacity_sectors);
+            assert!(
+                !(self.read_only && matches!(request, RequestType::Out)),
+                "write to read-only virtio block device"
+            );
+            self.perform(sector, buffer, len, request)
+        }
+
+        /// Run one request to completion, retrying it if the device
+        /// reports an error and resetting the device first if it asks for
+        /// that. A request the device does not support is not retried.
+        fn perform(
+            &self,
+            sector: u64,
+            buffer: *mut u8,
+            len: usize,
+            request: RequestType,
+        ) -> Result<(), VirtioError> {
+            let mut status = VIRTIO_BLK_S_OK;
+            for _ in 0..MAX_ATTEMPTS {
+                status = self.submit_and_wait(sector, buffer, len, request);
+                match status {
+                    VIRTIO_BLK_S_OK => return Ok(()),
+                    VIRTIO_BLK_S_UNSUPP => break,
+                    _ => self.recover(),
+                }
+            }
+            klog!(
+                Virtio,
+                Error,
+                "virtio: {} request at sector {} failed with status {}",
+                name(self.slot),
+                sector,
+                status
+            );
+            Err(VirtioError::IoError)
+        }
+
+        /// Submit one request and return its status byte. Other requests
+        /// may be in flight at the same time; only queue bookkeeping is
+        /// done under the lock. A device that stops to ask for a reset
+        /// will not complete the request, so it is given up as an I/O
+        /// error.
+        fn submit_and_wait(
+            &self,
+            sector: u64,
+            buffer: *mut u8,
+            len: usize,
+            request: RequestType,
+        ) -> u8 {
+            let head = self.wait_until(|queue| queue.submit(self, sector, buffer, len, request));
+            // Switching processes swaps the user window, so only a request
+            // that leaves it alone lets its process sleep through one
+            let waiter = if crate::process::in_user_window(buffer as usize, len) {
+                None
+            } else {
+                PROCESS_TABLE
+                    .try_lock()
+                    .map(|table| table.get_current_pid())
+                    .filter(|&pid| pid != INVALID_PID)
+            };
+            self.wait_for(head, waiter)
+        }
+
+        fn needs_reset(&self) -> bool {
+            self.transport.status() & STATUS_DEVICE_NEEDS_RESET != 0
+        }
+
+        /// Reset and restart a device that flagged DEVICE_NEEDS_RESET.
+        /// Waits for other requests to give up first, since a reset
+        /// starts the queue over empty.
+        fn recover(&self) {
+            if !self.needs_reset() {
+                return;
+            }
+            let mut queue = self.queue.lock();
+            if queue.inflight.iter().any(Option::is_some) {
+                return;
+            }
+            match unsafe { start_queue(self.transport) } {
+                Ok((_, _, fresh)) => {
+                    *queue = fresh;
+                    finish_init(self.transport);
+                }
+                Err(err) => klog!(
+                    Virtio,
+                    Error,
+                    "virtio: {} reset failed: {:?}",
+                    name(self.slot),
+                    err
+                ),
+            }
+        }
+
+        /// Retry `step` under the queue lock until it yields a value,
+        /// collecting completions in between. Sleeps until the completion
+        /// interrupt instead of spinning; inside a trap interrupts are
+        /// masked, but wfi still wakes on the pending IRQ. Timer ticks
+        /// bound a wakeup we miss.
+        fn wait_until<T>(&self, mut step: impl FnMut(&mut VirtQueueState) -> Option<T>) -> T {
+            loop {
+                {
+                    let mut queue = self.queue.lock();
+                    queue.collect_used(self);
+                    if let Some(value) = step(&mut queue) {
+                        return value;
+                    }
+                }
+                ack_interrupt(self.transport);
+                riscv::asm::wfi();
+            }
+        }
+
+        /// Wait for the request at `head` and return its status byte, or
+        /// an I/O error if the device stops to ask for a reset. `waiter`
+        /// is parked on the request (see `Scheduler::park`) until
+        /// `handle_interrupt` sees it come back, so other processes run
+        /// meanwhile; without one, or where the scheduler cannot park it
+        /// yet, this sleeps in wfi like `wait_until`.
+        fn wait_for(&self, head: u16, waiter: Option<Pid>) -> u8 {
+            loop {
+                {
+                    let mut queue = self.queue.lock();
+                    queue.collect_used(self);
+                    if let Some(status) = queue.take_completed(self, head) {
+                        return status;
+                    }
+                    if self.needs_reset() {
+                        queue.abandon(head);
+                        return VIRTIO_BLK_S_IOERR;
+                    }
+                    queue.waiters[head as usize] = waiter;
+                }
+                if waiter.is_some() && Scheduler::park() {
+                    continue;
+                }
+                ack_interrupt(self.transport);
+                riscv::asm::wfi();
+            }
+        }
+    }
+
+    /// Acknowledge the interrupt of the device on `transport` and wake
+    /// the processes parked on requests that have come back, or on every
+    /// request once the device asks for a reset. The completions
+    /// themselves are taken by the waiting `transfer`.
+    pub fn handle_interrupt(transport: Transport) {
+        let mut woken = Vec::new();
+        {
+            // init holds the lock while probing, before any request is made
+            let Some(devices) = DEVICES.try_lock() else {
+                return;
+            };
+            let Some(device) = devices.iter().flatten().find(|d| d.transport == transport) else {
+                return;
+            };
+            ack_interrupt(transport);
+            if let Some(mut queue) = device.queue.try_lock() {
+                queue.collect_used(device);
+                let reset = device.needs_reset();
+                for head in 0..QUEUE_SIZE {
+                    if reset || queue.completed[head] {
+                        woken.extend(queue.waiters[head].take());
+                    }
+                }
+            }
+        }
+        if woken.is_empty() {
+            return;
+        }
+        for pid in woken {
+            Scheduler::unblock(pid);
+        }
+        // Finish the woken syscall now instead of at the interrupted
+        // process's next syscall
+        if sstatus::read().spp() == SPP::User {
+            Scheduler::maybe_switch();
+        }
+    }
+
+    /// Descriptors in each request's chain: header, data, status. A
+    /// flush carries no data and uses one fewer.
+    const CHAIN_LEN: usize = 3;
+
+    /// Header and status byte of one request; heap-allocated so each
+    /// in-flight request has its own
+    struct Request {
+        header: VirtioBlkReqHeader,
+        status: u8,
+    }
+
+    /// Driver side of the queue. Requests are tracked by the id of their
+    /// head descriptor, which is also what the used ring reports.
+    struct VirtQueueState {
+        /// Rings shared with the device; boxed so they stay put when the
+        /// state moves
+        memory: Box<QueueMemory>,
+        next_avail: u16,
+        last_used: u16,
+        /// Descriptors not part of any chain
+        free: [bool; QUEUE_SIZE],
+        /// Requests submitted and not yet taken back, by head descriptor
+        inflight: [Option<Box<Request>>; QUEUE_SIZE],
+        /// Whether the request at each head has come back in the used ring
+        completed: [bool; QUEUE_SIZE],
+        /// Process parked on the request at each head
+        waiters: [Option<Pid>; QUEUE_SIZE],
+    }
+
+    impl VirtQueueState {
+        fn new() -> Self {
+            Self {
+                memory: alloc_queue_memory(),
+                next_avail: 0,
+                last_used: 0,
+                free: [true; QUEUE_SIZE],
+                inflight: [const { None }; QUEUE_SIZE],
+                completed: [false; QUEUE_SIZE],
+                waiters: [None; QUEUE_SIZE],
+            }
         }
 
-        fn transfer(&self, index: u32, buffer: *mut u8, len: usize, request: RequestType) {
-            assert!(len >= SECTOR_SIZE);
-            assert!((index as u64) < self.capacity_sectors);
+        /// Take `len` free descriptors, at most `CHAIN_LEN`, or None if
+        /// too few are left
+        fn alloc_chain(&mut self, queue_size: u16, len: usize) -> Option<[u16; CHAIN_LEN]> {
+            let mut chain = [0u16; CHAIN_LEN];
+            let mut found = 0;
+            for id in 0..queue_size as usize {
+                if found == len {
+                    break;
+                }
+                if self.free[id] {
+                    chain[found] = id as u16;
+                    found += 1;
+                }
+            }
+            if found < len {
+                return None;
+            }
+            for &id in &chain[..len] {
+                self.free[id as usize] = false;
+            }
+            Some(chain)
+        }
 
-            let mut queue = QUEUE_STATE.lock();
+        /// Queue a request and notify the device; returns its head
+        /// descriptor, or None if the ring is full
+        fn submit(
+            &mut self,
+            device: &VirtIoBlock,
+            sector: u64,
+            buffer: *mut u8,
+            len: usize,
+            request: RequestType,
+        ) -> Option<u16> {
+            let flush = matches!(request, RequestType::Flush);
+            let chain_len = if flush { CHAIN_LEN - 1 } else { CHAIN_LEN };
+            let chain = self.alloc_chain(device.queue_size, chain_len)?;
+            let (head, data, tail) = if flush {
+                (chain[0], chain[1], chain[1])
+            } else {
+                (chain[0], chain[1], chain[2])
+            };
+            let mut req = Box::new(Request {
+                header: VirtioBlkReqHeader {
+                    ty: request as u32,
+                    reserved: 0,
+                    sector,
+                },
+                status: 0xFF,
+            });
             unsafe {
-                let header_ptr = ptr::addr_of_mut!(REQUEST_HEADER);
-                (*header_ptr).ty = match request {
-                    RequestType::In => 0,
-                    RequestType::Out => 1,
-                };
-                (*header_ptr).reserved = 0;
-                (*header_ptr).sector = index as u64;
-                ptr::write(ptr::addr_of_mut!(REQUEST_STATUS), 0xFF);
-
-                let desc0 = ptr::addr_of_mut!(VIRTQ_DESC[0]);
-                (*desc0).addr = ptr::addr_of!(REQUEST_HEADER) as u64;
+                let memory: *mut QueueMemory = &mut *self.memory;
+                let desc0 = ptr::addr_of_mut!((*memory).desc[head as usize]);
+                (*desc0).addr = ptr::addr_of!(req.header) as u64;
                 (*desc0).len = size_of::<VirtioBlkReqHeader>() as u32;
                 (*desc0).flags = VIRTQ_DESC_F_NEXT;
-                (*desc0).next = 1;
-
-                let desc1 = ptr::addr_of_mut!(VIRTQ_DESC[1]);
-                (*desc1).addr = buffer as u64;
-                (*desc1).len = SECTOR_SIZE as u32;
-                (*desc1).flags = VIRTQ_DESC_F_NEXT
-                    | match request {
-                        RequestType::In => VIRTQ_DESC_F_WRITE,
-                        RequestType::Out => 0,
-                    };
-                (*desc1).next = 2;
-
-                let desc2 = ptr::addr_of_mut!(VIRTQ_DESC[2]);
-                (*desc2).addr = ptr::addr_of!(REQUEST_STATUS) as u64;
+                (*desc0).next = data;
+
+                if !flush {
+                    let desc1 = ptr::addr_of_mut!((*memory).desc[data as usize]);
+                    (*desc1).addr = buffer as u64;
+                    (*desc1).len = len as u32;
+                    (*desc1).flags = VIRTQ_DESC_F_NEXT
+                        | match request {
+                            RequestType::In => VIRTQ_DESC_F_WRITE,
+                            RequestType::Out | RequestType::Flush => 0,
+                        };
+                    (*desc1).next = tail;
+                }
+
+                let desc2 = ptr::addr_of_mut!((*memory).desc[tail as usize]);
+                (*desc2).addr = ptr::addr_of_mut!(req.status) as u64;
                 (*desc2).len = 1;
                 (*desc2).flags = VIRTQ_DESC_F_WRITE;
                 (*desc2).next = 0;
 
-                let avail_ptr = ptr::addr_of_mut!(VIRTQ_AVAIL);
-                let slot = (queue.next_avail as usize) % (self.queue_size as usize);
-                (*avail_ptr).ring[slot] = 0;
+                self.inflight[head as usize] = Some(req);
+                self.completed[head as usize] = false;
+
+                let avail_ptr = ptr::addr_of_mut!((*memory).avail);
+                let slot = (self.next_avail as usize) % (device.queue_size as usize);
+                (*avail_ptr).ring[slot] = head;
                 fence(Ordering::Release);
-                queue.next_avail = queue.next_avail.wrapping_add(1);
-                (*avail_ptr).idx = queue.next_avail;
+                self.next_avail = self.next_avail.wrapping_add(1);
+                (*avail_ptr).idx = self.next_avail;
 
                 fence(Ordering::SeqCst);
-                write32(self.regs_base, QUEUE_NOTIFY, 0);
+                device.transport.notify(0);
+            }
+            Some(head)
+        }
 
-                let expected = queue.last_used.wrapping_add(1);
-                loop {
-                    fence(Ordering::Acquire);
-                    if ptr::read_volatile(ptr::addr_of!(VIRTQ_USED.idx)) == expected {
-                        break;
-                    }
-                    spin_loop();
+        /// Mark every request the device has handed back as completed
+        fn collect_used(&mut self, device: &VirtIoBlock) {
+            fence(Ordering::Acquire);
+            let used = ptr::addr_of!(self.memory.used);
+            let used_idx = unsafe { ptr::read_volatile(ptr::addr_of!((*used).idx)) };
+            while self.last_used != used_idx {
+                let slot = (self.last_used as usize) % (device.queue_size as usize);
+                let id = unsafe { ptr::read_volatile(ptr::addr_of!((*used).ring[slot].id)) };
+                if let Some(done) = self.completed.get_mut(id as usize) {
+                    *done = true;
                 }
-                queue.last_used = expected;
+                self.last_used = self.last_used.wrapping_add(1);
+            }
+        }
 
-                let status = ptr::read_volatile(ptr::addr_of!(REQUEST_STATUS));
-                if status != 0 {
-                    panic!("virtio block request failed with status {}", status);
-                }
+        /// Forget the request at `head` without waiting for it. Its
+        /// descriptors stay allocated until the device is reset, since
+        /// the device may still own them.
+        fn abandon(&mut self, head: u16) {
+            self.inflight[head as usize] = None;
+            self.completed[head as usize] = false;
+            self.waiters[head as usize] = None;
+        }
 
-                let interrupt_status = read32(self.regs_base, INTERRUPT_STATUS);
-                if interrupt_status != 0 {
-                    write32(self.regs_base, INTERRUPT_ACK, interrupt_status);
+        /// If the request at `head` has completed, free its chain and
+        /// return its status byte
+        fn take_completed(&mut self, device: &VirtIoBlock, head: u16) -> Option<u8> {
+            if !self.completed[head as usize] {
+                return None;
+            }
+            self.completed[head as usize] = false;
+            self.waiters[head as usize] = None;
+            let req = self.inflight[head as usize].take()?;
+            let mut id = head as usize;
+            loop {
+                self.free[id] = true;
+                let desc = unsafe { ptr::read(ptr::addr_of!(self.memory.desc[id])) };
+                if desc.flags & VIRTQ_DESC_F_NEXT == 0 {
+                    break;
                 }
+                id = desc.next as usize;
             }
+            Some(unsafe { ptr::read_volatile(ptr::addr_of!(req.status)) })
         }
     }
 
-    const VIRTQ_DESC_F_NEXT: u16 = 1;
-    const VIRTQ_DESC_F_WRITE: u16 = 2;
-
-    #[derive(ConstDefault, Debug, Clone, Copy)]
-    struct VirtQueueState {
-        next_avail: u16,
-        last_used: u16,
+    /// Zeroed ring memory, allocated in place; a `QueueMemory` is two
+    /// pages, too big to build on the stack and move
+    fn alloc_queue_memory() -> Box<QueueMemory> {
+        let layout = Layout::new::<QueueMemory>();
+        unsafe {
+            let memory = alloc_zeroed(layout) as *mut QueueMemory;
+            if memory.is_null() {
+                handle_alloc_error(layout);
+            }
+            Box::from_raw(memory)
+        }
     }
 
-    static mut VIRTQ_DESC: [VirtqDesc; QUEUE_SIZE] = [VirtqDesc::DEFAULT; QUEUE_SIZE];
-    static mut VIRTQ_AVAIL: VirtqAvail = VirtqAvail::DEFAULT;
-    static mut VIRTQ_USED: VirtqUsed = VirtqUsed::DEFAULT;
-    static mut REQUEST_HEADER: VirtioBlkReqHeader = VirtioBlkReqHeader::DEFAULT;
-    static mut REQUEST_STATUS: u8 = 0;
-    static QUEUE_STATE: Mutex<VirtQueueState> = Mutex::new(VirtQueueState::DEFAULT);
+    /// Name of the `index`th block device: `vda`, `vdb`, ...
+    pub fn name(index: usize) -> String {
+        format!("vd{}", (b'a' + index as u8) as char)
+    }
 
+    /// The first block device, which holds the root filesystem
     pub fn init() -> Result<VirtIoBlock, VirtioError> {
+        init_nth(0)
+    }
+
+    /// The block device called `name`, initialized on first use
+    pub fn open(name: &str) -> Result<VirtIoBlock, VirtioError> {
+        let index = match name.strip_prefix("vd").map(str::as_bytes) {
+            Some(&[letter]) if letter.is_ascii_lowercase() => (letter - b'a') as usize,
+            _ => return Err(VirtioError::DeviceNotFound),
+        };
+        init_nth(index)
+    }
+
+    /// The `index`th virtio block device in slot order
+    pub fn init_nth(index: usize) -> Result<VirtIoBlock, VirtioError> {
+        if index >= MAX_DEVICES {
+            return Err(VirtioError::DeviceNotFound);
+        }
+        let mut guard = DEVICES.lock();
+        if let Some(device) = &guard[index] {
+            return Ok(device.clone());
+        }
+        let found = super::find(super::DEVICE_BLOCK, index).ok_or(VirtioError::DeviceNotFound)?;
+        let device = unsafe { initialize(index, found.transport)? };
+        guard[index] = Some(device.clone());
+        Ok(device)
+    }
+
+    /// Reset the device on `transport` and give it fresh ring memory. Returns
+    /// the features both sides accepted, the queue size and the queue.
+    unsafe fn start_queue(transport: Transport) -> Result<(u32, u16, VirtQueueState), VirtioError> {
+        let wanted =
+            (1 << VIRTIO_BLK_F_RO) | (1 << VIRTIO_BLK_F_BLK_SIZE) | (1 << VIRTIO_BLK_F_FLUSH);
+        let features = unsafe { start_init(transport, super::DEVICE_BLOCK, wanted)? };
+        let mut queue = VirtQueueState::new();
+        let queue_size = unsafe { setup_queue(transport, 0, &mut *queue.memory)? };
+        Ok((features, queue_size, queue))
+    }
+
+    unsafe fn initialize(slot: usize, transport: Transport) -> Result<VirtIoBlock, VirtioError> {
+        let (features, queue_size, queue) = unsafe { start_queue(transport)? };
+
+        let config_generation = transport.config_generation();
+        let config = read_config(transport);
+        let block_size = if features & (1 << VIRTIO_BLK_F_BLK_SIZE) != 0 {
+            config.block_size() as usize
+        } else {
+            SECTOR_SIZE
+        };
+        // A block must be whole sectors and fit in one request
+        if !block_size.is_power_of_two()
+            || !(SECTOR_SIZE..=MAX_REQUEST_SECTORS * SECTOR_SIZE).contains(&block_size)
+        {
+            return Err(VirtioError::UnsupportedDevice);
+        }
+        let capacity_sectors = config.sector_capacity();
+        let config_generation_after = transport.config_generation();
+        if config_generation != config_generation_after {
+            return Err(VirtioError::DeviceFailure);
+        }
+
+        finish_init(transport);
+        Ok(VirtIoBlock {
+            slot,
+            transport,
+            capacity_sectors,
+            queue_size,
+            block_size,
+            flush: features & (1 << VIRTIO_BLK_F_FLUSH) != 0,
+            read_only: features & (1 << VIRTIO_BLK_F_RO) != 0,
+            queue: Arc::new(Mutex::new(queue)),
+        })
+    }
+
+    fn read_config(transport: Transport) -> VirtioBlockConfig {
+        unsafe { ptr::read_volatile(transport.config() as *const VirtioBlockConfig) }
+    }
+}
+
+pub mod net {
+    use super::*;
+
+    const RX_QUEUE: u32 = 0;
+    const TX_QUEUE: u32 = 1;
+    /// `virtio_net_hdr` as laid out once VERSION_1 is negotiated
+    const HEADER_SIZE: usize = 12;
+    /// Largest Ethernet frame without the FCS
+    pub const MAX_FRAME: usize = 1514;
+    const BUFFER_SIZE: usize = HEADER_SIZE + MAX_FRAME;
+    /// Device reports its MAC address in config space
+    const VIRTIO_NET_F_MAC: u32 = 5;
+
+    static DEVICE: Mutex<Option<VirtIoNet>> = Mutex::new(None);
+
+    static mut RX_QUEUE_MEMORY: QueueMemory = QueueMemory::DEFAULT;
+    static mut TX_QUEUE_MEMORY: QueueMemory = QueueMemory::DEFAULT;
+    /// One buffer per descriptor; RX buffers stay posted to the device
+    /// and are handed back after each frame is copied out
+    static mut RX_BUFFERS: [[u8; BUFFER_SIZE]; QUEUE_SIZE] = [[0; BUFFER_SIZE]; QUEUE_SIZE];
+    static mut TX_BUFFERS: [[u8; BUFFER_SIZE]; QUEUE_SIZE] = [[0; BUFFER_SIZE]; QUEUE_SIZE];
+
+    struct VirtIoNet {
+        transport: Transport,
+        mac: [u8; 6],
+        rx_size: u16,
+        tx_size: u16,
+        rx_last_used: u16,
+        rx_next_avail: u16,
+        tx_next_avail: u16,
+        tx_last_used: u16,
+    }
+
+    impl VirtIoNet {
+        /// Put RX descriptor `id` back on the available ring
+        fn post_rx(&mut self, id: u16) {
+            unsafe {
+                let memory = ptr::addr_of_mut!(RX_QUEUE_MEMORY);
+                let desc = ptr::addr_of_mut!((*memory).desc[id as usize]);
+                (*desc).addr = ptr::addr_of_mut!(RX_BUFFERS[id as usize]) as u64;
+                (*desc).len = BUFFER_SIZE as u32;
+                (*desc).flags = VIRTQ_DESC_F_WRITE;
+                (*desc).next = 0;
+
+                let avail = ptr::addr_of_mut!((*memory).avail);
+                let slot = (self.rx_next_avail as usize) % (self.rx_size as usize);
+                (*avail).ring[slot] = id;
+                fence(Ordering::Release);
+                self.rx_next_avail = self.rx_next_avail.wrapping_add(1);
+                (*avail).idx = self.rx_next_avail;
+            }
+        }
+
+        fn receive(&mut self) -> Option<Vec<u8>> {
+            fence(Ordering::Acquire);
+            let used = unsafe { ptr::addr_of!(RX_QUEUE_MEMORY.used) };
+            let used_idx = unsafe { ptr::read_volatile(ptr::addr_of!((*used).idx)) };
+            if self.rx_last_used == used_idx {
+                return None;
+            }
+            let slot = (self.rx_last_used as usize) % (self.rx_size as usize);
+            let elem = unsafe { ptr::read_volatile(ptr::addr_of!((*used).ring[slot])) };
+            self.rx_last_used = self.rx_last_used.wrapping_add(1);
+
+            let len = (elem.len as usize).clamp(HEADER_SIZE, BUFFER_SIZE);
+            let buffer = unsafe { &*ptr::addr_of!(RX_BUFFERS[elem.id as usize]) };
+            let frame = buffer[HEADER_SIZE..len].to_vec();
+            self.post_rx(elem.id as u16);
+            fence(Ordering::SeqCst);
+            self.transport.notify(RX_QUEUE);
+            Some(frame)
+        }
+
+        /// Queue `frame` for sending; false if every TX buffer is still
+        /// owned by the device. Buffers are reused round-robin, which
+        /// relies on the device completing transmits in order.
+        fn transmit(&mut self, frame: &[u8]) -> bool {
+            fence(Ordering::Acquire);
+            let used_idx = unsafe { ptr::read_volatile(ptr::addr_of!(TX_QUEUE_MEMORY.used.idx)) };
+            self.tx_last_used = used_idx;
+            let in_flight = self.tx_next_avail.wrapping_sub(self.tx_last_used);
+            if in_flight >= self.tx_size || frame.len() > MAX_FRAME {
+                return false;
+            }
+
+            let id = (self.tx_next_avail as usize) % (self.tx_size as usize);
+            unsafe {
+                let buffer = &mut *ptr::addr_of_mut!(TX_BUFFERS[id]);
+                buffer[..HEADER_SIZE].fill(0);
+                buffer[HEADER_SIZE..HEADER_SIZE + frame.len()].copy_from_slice(frame);
+
+                let memory = ptr::addr_of_mut!(TX_QUEUE_MEMORY);
+                let desc = ptr::addr_of_mut!((*memory).desc[id]);
+                (*desc).addr = buffer.as_ptr() as u64;
+                (*desc).len = (HEADER_SIZE + frame.len()) as u32;
+                (*desc).flags = 0;
+                (*desc).next = 0;
+
+                let avail = ptr::addr_of_mut!((*memory).avail);
+                (*avail).ring[id] = id as u16;
+                fence(Ordering::Release);
+                self.tx_next_avail = self.tx_next_avail.wrapping_add(1);
+                (*avail).idx = self.tx_next_avail;
+            }
+            fence(Ordering::SeqCst);
+            self.transport.notify(TX_QUEUE);
+            true
+        }
+    }
+
+    /// Bring up the first virtio-net device and return its MAC address
+    pub fn init() -> Result<[u8; 6], VirtioError> {
         let mut guard = DEVICE.lock();
-        if let Some(device) = *guard {
-            return Ok(device);
+        if let Some(device) = guard.as_ref() {
+            return Ok(device.mac);
         }
-        let device = unsafe { initialize()? };
+        let found = super::find(super::DEVICE_NET, 0).ok_or(VirtioError::DeviceNotFound)?;
+        let device = unsafe { initialize(found.transport)? };
+        let mac = device.mac;
         *guard = Some(device);
+        Ok(mac)
+    }
+
+    unsafe fn initialize(transport: Transport) -> Result<VirtIoNet, VirtioError> {
+        let features = unsafe { start_init(transport, super::DEVICE_NET, 1 << VIRTIO_NET_F_MAC)? };
+        if features & (1 << VIRTIO_NET_F_MAC) == 0 {
+            return Err(VirtioError::DeviceRejectedFeatures);
+        }
+        let (rx_size, tx_size) = unsafe {
+            ptr::write(ptr::addr_of_mut!(RX_QUEUE_MEMORY), QueueMemory::DEFAULT);
+            ptr::write(ptr::addr_of_mut!(TX_QUEUE_MEMORY), QueueMemory::DEFAULT);
+            (
+                setup_queue(transport, RX_QUEUE, ptr::addr_of_mut!(RX_QUEUE_MEMORY))?,
+                setup_queue(transport, TX_QUEUE, ptr::addr_of_mut!(TX_QUEUE_MEMORY))?,
+            )
+        };
+
+        let mut mac = [0u8; 6];
+        for (i, byte) in mac.iter_mut().enumerate() {
+            *byte = unsafe { ptr::read_volatile((transport.config() + i) as *const u8) };
+        }
+
+        let mut device = VirtIoNet {
+            transport,
+            mac,
+            rx_size,
+            tx_size,
+            rx_last_used: 0,
+            rx_next_avail: 0,
+            tx_next_avail: 0,
+            tx_last_used: 0,
+        };
+        for id in 0..rx_size {
+            device.post_rx(id);
+        }
+        finish_init(transport);
+        transport.notify(RX_QUEUE);
         Ok(device)
     }
 
-    unsafe fn initialize() -> Result<VirtIoBlock, VirtioError> {
-        if read32(VIRTIO_MMIO_BASE, MAGIC_VALUE) != 0x7472_6976 {
-            return Err(VirtioError::DeviceNotFound);
+    /// Next received Ethernet frame, if any. Never blocks.
+    pub fn receive() -> Option<Vec<u8>> {
+        DEVICE.try_lock()?.as_mut()?.receive()
+    }
+
+    /// Send one Ethernet frame; false if it was dropped
+    pub fn transmit(frame: &[u8]) -> bool {
+        match DEVICE.lock().as_mut() {
+            Some(device) => device.transmit(frame),
+            None => false,
         }
-        let version = read32(VIRTIO_MMIO_BASE, VERSION);
-        if version != 2 {
-            return Err(VirtioError::LegacyOnly(version));
+    }
+
+    /// Acknowledge the device's interrupt; frames are picked up by
+    /// `crate::net::poll`
+    pub fn handle_interrupt(transport: Transport) {
+        // init holds the lock while probing
+        let Some(guard) = DEVICE.try_lock() else {
+            return;
+        };
+        if guard.as_ref().is_some_and(|device| device.transport == transport) {
+            ack_interrupt(transport);
         }
-        if read32(VIRTIO_MMIO_BASE, DEVICE_ID) != 2 {
-            return Err(VirtioError::UnsupportedDevice);
+    }
+}
+
+pub mod console {
+    use super::*;
+
+    const RX_QUEUE: u32 = 0;
+    const TX_QUEUE: u32 = 1;
+    /// Each RX buffer holds whatever arrived in one go, usually a
+    /// keystroke or a pasted line
+    const RX_BUFFER_SIZE: usize = 64;
+    const TX_BUFFER_SIZE: usize = 256;
+
+    static DEVICE: Mutex<Option<VirtIoConsole>> = Mutex::new(None);
+
+    static mut RX_QUEUE_MEMORY: QueueMemory = QueueMemory::DEFAULT;
+    static mut TX_QUEUE_MEMORY: QueueMemory = QueueMemory::DEFAULT;
+    static mut RX_BUFFERS: [[u8; RX_BUFFER_SIZE]; QUEUE_SIZE] = [[0; RX_BUFFER_SIZE]; QUEUE_SIZE];
+    static mut TX_BUFFERS: [[u8; TX_BUFFER_SIZE]; QUEUE_SIZE] = [[0; TX_BUFFER_SIZE]; QUEUE_SIZE];
+
+    struct VirtIoConsole {
+        transport: Transport,
+        rx_size: u16,
+        tx_size: u16,
+        rx_last_used: u16,
+        rx_next_avail: u16,
+        tx_next_avail: u16,
+    }
+
+    impl VirtIoConsole {
+        /// Put RX descriptor `id` back on the available ring
+        fn post_rx(&mut self, id: u16) {
+            unsafe {
+                let memory = ptr::addr_of_mut!(RX_QUEUE_MEMORY);
+                let desc = ptr::addr_of_mut!((*memory).desc[id as usize]);
+                (*desc).addr = ptr::addr_of_mut!(RX_BUFFERS[id as usize]) as u64;
+                (*desc).len = RX_BUFFER_SIZE as u32;
+                (*desc).flags = VIRTQ_DESC_F_WRITE;
+                (*desc).next = 0;
+
+                let avail = ptr::addr_of_mut!((*memory).avail);
+                let slot = (self.rx_next_avail as usize) % (self.rx_size as usize);
+                (*avail).ring[slot] = id;
+                fence(Ordering::Release);
+                self.rx_next_avail = self.rx_next_avail.wrapping_add(1);
+                (*avail).idx = self.rx_next_avail;
+            }
         }
 
-        write32(VIRTIO_MMIO_BASE, STATUS, 0);
-        write32(VIRTIO_MMIO_BASE, STATUS, STATUS_ACKNOWLEDGE);
-        write32(VIRTIO_MMIO_BASE, STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        /// Append every byte the device has delivered to `out`
+        fn receive(&mut self, out: &mut Vec<u8>) {
+            let mut reposted = false;
+            loop {
+                fence(Ordering::Acquire);
+                let used = unsafe { ptr::addr_of!(RX_QUEUE_MEMORY.used) };
+                let used_idx = unsafe { ptr::read_volatile(ptr::addr_of!((*used).idx)) };
+                if self.rx_last_used == used_idx {
+                    break;
+                }
+                let slot = (self.rx_last_used as usize) % (self.rx_size as usize);
+                let elem = unsafe { ptr::read_volatile(ptr::addr_of!((*used).ring[slot])) };
+                self.rx_last_used = self.rx_last_used.wrapping_add(1);
 
-        write32(VIRTIO_MMIO_BASE, DEVICE_FEATURES_SEL, 0);
-        let device_features_lo = read32(VIRTIO_MMIO_BASE, DEVICE_FEATURES);
-        let driver_features_lo = device_features_lo & SUPPORTED_FEATURES_LO;
-        write32(VIRTIO_MMIO_BASE, DRIVER_FEATURES_SEL, 0);
-        write32(VIRTIO_MMIO_BASE, DRIVER_FEATURES, driver_features_lo);
+                let len = (elem.len as usize).min(RX_BUFFER_SIZE);
+                let buffer = unsafe { &*ptr::addr_of!(RX_BUFFERS[elem.id as usize]) };
+                out.extend_from_slice(&buffer[..len]);
+                self.post_rx(elem.id as u16);
+                reposted = true;
+            }
+            if reposted {
+                fence(Ordering::SeqCst);
+                self.transport.notify(RX_QUEUE);
+            }
+        }
+
+        /// Queue up to `TX_BUFFER_SIZE` bytes of `bytes`, waiting for the
+        /// device to hand a buffer back if all are in flight. Returns how
+        /// many bytes were queued.
+        fn transmit(&mut self, bytes: &[u8]) -> usize {
+            loop {
+                fence(Ordering::Acquire);
+                let used_idx =
+                    unsafe { ptr::read_volatile(ptr::addr_of!(TX_QUEUE_MEMORY.used.idx)) };
+                if self.tx_next_avail.wrapping_sub(used_idx) < self.tx_size {
+                    break;
+                }
+                core::hint::spin_loop();
+            }
 
-        write32(VIRTIO_MMIO_BASE, DEVICE_FEATURES_SEL, 1);
-        let device_features_hi = read32(VIRTIO_MMIO_BASE, DEVICE_FEATURES);
-        let mut driver_features_hi = 0u32;
-        if (device_features_hi & (1 << VIRTIO_F_VERSION_1_BIT)) != 0 {
-            driver_features_hi |= 1 << VIRTIO_F_VERSION_1_BIT;
+            let len = bytes.len().min(TX_BUFFER_SIZE);
+            let id = (self.tx_next_avail as usize) % (self.tx_size as usize);
+            unsafe {
+                let buffer = &mut *ptr::addr_of_mut!(TX_BUFFERS[id]);
+                buffer[..len].copy_from_slice(&bytes[..len]);
+
+                let memory = ptr::addr_of_mut!(TX_QUEUE_MEMORY);
+                let desc = ptr::addr_of_mut!((*memory).desc[id]);
+                (*desc).addr = buffer.as_ptr() as u64;
+                (*desc).len = len as u32;
+                (*desc).flags = 0;
+                (*desc).next = 0;
+
+                let avail = ptr::addr_of_mut!((*memory).avail);
+                (*avail).ring[id] = id as u16;
+                fence(Ordering::Release);
+                self.tx_next_avail = self.tx_next_avail.wrapping_add(1);
+                (*avail).idx = self.tx_next_avail;
+            }
+            fence(Ordering::SeqCst);
+            self.transport.notify(TX_QUEUE);
+            len
         }
-        write32(VIRTIO_MMIO_BASE, DRIVER_FEATURES_SEL, 1);
-        write32(VIRTIO_MMIO_BASE, DRIVER_FEATURES, driver_features_hi);
+    }
 
-        write32(
-            VIRTIO_MMIO_BASE,
-            STATUS,
-            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK,
-        );
-        if (read32(VIRTIO_MMIO_BASE, STATUS) & STATUS_FEATURES_OK) == 0 {
-            return Err(VirtioError::DeviceRejectedFeatures);
+    /// Bring up the first virtio-console device (port 0 only)
+    pub fn init() -> Result<(), VirtioError> {
+        let mut guard = DEVICE.lock();
+        if guard.is_some() {
+            return Ok(());
         }
+        let found = super::find(super::DEVICE_CONSOLE, 0).ok_or(VirtioError::DeviceNotFound)?;
+        *guard = Some(unsafe { initialize(found.transport)? });
+        Ok(())
+    }
+
+    unsafe fn initialize(transport: Transport) -> Result<VirtIoConsole, VirtioError> {
+        unsafe { start_init(transport, super::DEVICE_CONSOLE, 0)? };
+        let (rx_size, tx_size) = unsafe {
+            ptr::write(ptr::addr_of_mut!(RX_QUEUE_MEMORY), QueueMemory::DEFAULT);
+            ptr::write(ptr::addr_of_mut!(TX_QUEUE_MEMORY), QueueMemory::DEFAULT);
+            (
+                setup_queue(transport, RX_QUEUE, ptr::addr_of_mut!(RX_QUEUE_MEMORY))?,
+                setup_queue(transport, TX_QUEUE, ptr::addr_of_mut!(TX_QUEUE_MEMORY))?,
+            )
+        };
 
-        write32(VIRTIO_MMIO_BASE, QUEUE_SEL, 0);
-        let queue_max = read32(VIRTIO_MMIO_BASE, QUEUE_NUM_MAX);
-        if queue_max == 0 {
-            return Err(VirtioError::QueueUnavailable);
-        }
-        let queue_size = core::cmp::min(queue_max as usize, QUEUE_SIZE) as u16;
-        write32(VIRTIO_MMIO_BASE, QUEUE_NUM, queue_size as u32);
-
-        zero_queue_memory();
-
-        let desc_addr = ptr::addr_of!(VIRTQ_DESC) as usize;
-        let avail_addr = ptr::addr_of!(VIRTQ_AVAIL) as usize;
-        let used_addr = ptr::addr_of!(VIRTQ_USED) as usize;
-
-        write64(
-            VIRTIO_MMIO_BASE,
-            QUEUE_DESC_LOW,
-            QUEUE_DESC_HIGH,
-            desc_addr as u64,
-        );
-        write64(
-            VIRTIO_MMIO_BASE,
-            QUEUE_AVAIL_LOW,
-            QUEUE_AVAIL_HIGH,
-            avail_addr as u64,
-        );
-        write64(
-            VIRTIO_MMIO_BASE,
-            QUEUE_USED_LOW,
-            QUEUE_USED_HIGH,
-            used_addr as u64,
-        );
-
-        write32(VIRTIO_MMIO_BASE, QUEUE_READY, 1);
-
-        let config_generation = read32(VIRTIO_MMIO_BASE, CONFIG_GENERATION);
-        let config = read_config();
-        let block_size = config.block_size();
-        if block_size as usize != SECTOR_SIZE {
-            panic!("unsupported block size: {}", block_size);
+        let mut device = VirtIoConsole {
+            transport,
+            rx_size,
+            tx_size,
+            rx_last_used: 0,
+            rx_next_avail: 0,
+            tx_next_avail: 0,
+        };
+        for id in 0..rx_size {
+            device.post_rx(id);
         }
-        let capacity_sectors = config.sector_capacity();
-        let config_generation_after = read32(VIRTIO_MMIO_BASE, CONFIG_GENERATION);
-        if config_generation != config_generation_after {
-            return Err(VirtioError::DeviceFailure);
+        finish_init(transport);
+        transport.notify(RX_QUEUE);
+        Ok(device)
+    }
+
+    pub fn is_present() -> bool {
+        DEVICE.lock().is_some()
+    }
+
+    /// Send `bytes`; false if there is no device or it is busy, e.g. a
+    /// panic while another write holds the lock
+    pub fn write(bytes: &[u8]) -> bool {
+        let Some(mut guard) = DEVICE.try_lock() else {
+            return false;
+        };
+        let Some(device) = guard.as_mut() else {
+            return false;
+        };
+        let mut sent = 0;
+        while sent < bytes.len() {
+            sent += device.transmit(&bytes[sent..]);
         }
+        true
+    }
 
-        write32(
-            VIRTIO_MMIO_BASE,
-            STATUS,
-            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK | STATUS_DRIVER_OK,
-        );
+    /// Acknowledge the device's interrupt and return the input it carried
+    pub fn handle_interrupt(transport: Transport) -> Vec<u8> {
+        let mut input = Vec::new();
+        let Some(mut guard) = DEVICE.try_lock() else {
+            return input;
+        };
+        if let Some(device) = guard.as_mut().filter(|device| device.transport == transport) {
+            ack_interrupt(transport);
+            device.receive(&mut input);
+        }
+        input
+    }
+}
 
-        Ok(VirtIoBlock {
-            regs_base: VIRTIO_MMIO_BASE,
-            capacity_sectors,
-            queue_size,
-        })
+pub mod rng {
+    use super::*;
+
+    const REQUEST_QUEUE: u32 = 0;
+    /// Most bytes asked for per request
+    const BUFFER_SIZE: usize = 64;
+    /// Give up on a request after this many timebase cycles (10 ms), in
+    /// case the host rate-limits the device
+    const TIMEOUT: u64 = 100_000;
+
+    static DEVICE: Mutex<Option<VirtIoRng>> = Mutex::new(None);
+
+    static mut QUEUE_MEMORY: QueueMemory = QueueMemory::DEFAULT;
+    static mut BUFFER: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+
+    struct VirtIoRng {
+        transport: Transport,
+        queue_size: u16,
+        next_avail: u16,
+        /// The request still with the device after a timeout; its bytes
+        /// are taken by the next read
+        outstanding: bool,
     }
 
-    const SUPPORTED_FEATURES_LO: u32 = 0;
+    impl VirtIoRng {
+        fn submit(&mut self) {
+            unsafe {
+                let memory = ptr::addr_of_mut!(QUEUE_MEMORY);
+                let desc = ptr::addr_of_mut!((*memory).desc[0]);
+                (*desc).addr = ptr::addr_of_mut!(BUFFER) as u64;
+                (*desc).len = BUFFER_SIZE as u32;
+                (*desc).flags = VIRTQ_DESC_F_WRITE;
+                (*desc).next = 0;
 
-    fn zero_queue_memory() {
-        unsafe {
-            let desc_base = ptr::addr_of_mut!(VIRTQ_DESC) as *mut VirtqDesc;
-            for i in 0..QUEUE_SIZE {
-                ptr::write(desc_base.add(i), VirtqDesc::DEFAULT);
-            }
-            ptr::write(ptr::addr_of_mut!(VIRTQ_AVAIL), VirtqAvail::DEFAULT);
-            ptr::write(ptr::addr_of_mut!(VIRTQ_USED), VirtqUsed::DEFAULT);
-            ptr::write(
-                ptr::addr_of_mut!(REQUEST_HEADER),
-                VirtioBlkReqHeader::DEFAULT,
-            );
-            ptr::write(ptr::addr_of_mut!(REQUEST_STATUS), 0);
+                let avail = ptr::addr_of_mut!((*memory).avail);
+                (*avail).ring[(self.next_avail % self.queue_size) as usize] = 0;
+                fence(Ordering::Release);
+                self.next_avail = self.next_avail.wrapping_add(1);
+                (*avail).idx = self.next_avail;
+            }
+            fence(Ordering::SeqCst);
+            self.transport.notify(REQUEST_QUEUE);
+            self.outstanding = true;
+        }
+
+        /// Length the device wrote for the outstanding request, once it
+        /// is done
+        fn completed(&self) -> Option<usize> {
+            fence(Ordering::Acquire);
+            let used = unsafe { ptr::addr_of!(QUEUE_MEMORY.used) };
+            let used_idx = unsafe { ptr::read_volatile(ptr::addr_of!((*used).idx)) };
+            if used_idx != self.next_avail {
+                return None;
+            }
+            let slot = (used_idx.wrapping_sub(1) % self.queue_size) as usize;
+            let elem = unsafe { ptr::read_volatile(ptr::addr_of!((*used).ring[slot])) };
+            Some((elem.len as usize).min(BUFFER_SIZE))
+        }
+
+        fn read(&mut self, out: &mut [u8]) -> usize {
+            if !self.outstanding {
+                self.submit();
+            }
+            let deadline = riscv::register::time::read64() + TIMEOUT;
+            let len = loop {
+                if let Some(len) = self.completed() {
+                    break len;
+                }
+                if riscv::register::time::read64() > deadline {
+                    return 0;
+                }
+                core::hint::spin_loop();
+            };
+            self.outstanding = false;
+            let count = len.min(out.len());
+            let buffer = unsafe { &mut *ptr::addr_of_mut!(BUFFER) };
+            out[..count].copy_from_slice(&buffer[..count]);
+            // Never hand out the same bytes twice
+            buffer.fill(0);
+            count
         }
-        let mut state = QUEUE_STATE.lock();
-        *state = VirtQueueState::DEFAULT;
     }
 
-    fn read_config() -> VirtioBlockConfig {
-        unsafe {
-            ptr::read_volatile((VIRTIO_MMIO_BASE + CONFIG_OFFSET) as *const VirtioBlockConfig)
+    /// Bring up the first virtio entropy device
+    pub fn init() -> Result<(), VirtioError> {
+        let mut guard = DEVICE.lock();
+        if guard.is_some() {
+            return Ok(());
+        }
+        let found = super::find(super::DEVICE_ENTROPY, 0).ok_or(VirtioError::DeviceNotFound)?;
+        let transport = found.transport;
+        let queue_size = unsafe {
+            start_init(transport, super::DEVICE_ENTROPY, 0)?;
+            ptr::write(ptr::addr_of_mut!(QUEUE_MEMORY), QueueMemory::DEFAULT);
+            setup_queue(transport, REQUEST_QUEUE, ptr::addr_of_mut!(QUEUE_MEMORY))?
+        };
+        finish_init(transport);
+        *guard = Some(VirtIoRng {
+            transport,
+            queue_size,
+            next_avail: 0,
+            outstanding: false,
+        });
+        Ok(())
+    }
+
+    /// Fill the front of `out` with bytes from the device; returns how
+    /// many, 0 if there is no device or it did not answer in time
+    pub fn read(out: &mut [u8]) -> usize {
+        match DEVICE.try_lock().as_deref_mut() {
+            Some(Some(device)) => device.read(out),
+            _ => 0,
         }
     }
+}
+
+pub mod p9 {
+    use super::*;
+    use alloc::string::String;
+
+    const REQUEST_QUEUE: u32 = 0;
+    /// Largest 9p message either way; negotiated as msize
+    pub const MAX_MESSAGE: usize = 8192;
+    /// Device reports the tag QEMU's `-virtfs mount_tag=` gave it
+    const VIRTIO_9P_MOUNT_TAG: u32 = 0;
+
+    static DEVICE: Mutex<Option<VirtIo9p>> = Mutex::new(None);
 
-    fn read32(base: usize, offset: usize) -> u32 {
-        unsafe { ptr::read_volatile((base + offset) as *const u32) }
+    static mut QUEUE_MEMORY: QueueMemory = QueueMemory::DEFAULT;
+    static mut REQUEST: [u8; MAX_MESSAGE] = [0; MAX_MESSAGE];
+    static mut RESPONSE: [u8; MAX_MESSAGE] = [0; MAX_MESSAGE];
+
+    struct VirtIo9p {
+        transport: Transport,
+        queue_size: u16,
+        next_avail: u16,
+        tag: String,
     }
 
-    fn write32(base: usize, offset: usize, value: u32) {
-        unsafe { ptr::write_volatile((base + offset) as *mut u32, value) };
+    impl VirtIo9p {
+        /// Send `request` and wait for the reply, which is copied into
+        /// `response`. One message is in flight at a time, so descriptors
+        /// 0 and 1 are reused for every exchange.
+        fn transact(&mut self, request: &[u8], response: &mut Vec<u8>) -> Result<(), VirtioError> {
+            if request.len() > MAX_MESSAGE {
+                return Err(VirtioError::DeviceFailure);
+            }
+            unsafe {
+                let out = &mut *ptr::addr_of_mut!(REQUEST);
+                out[..request.len()].copy_from_slice(request);
+
+                let memory = ptr::addr_of_mut!(QUEUE_MEMORY);
+                let desc = ptr::addr_of_mut!((*memory).desc[0]);
+                (*desc).addr = out.as_ptr() as u64;
+                (*desc).len = request.len() as u32;
+                (*desc).flags = VIRTQ_DESC_F_NEXT;
+                (*desc).next = 1;
+                let desc = ptr::addr_of_mut!((*memory).desc[1]);
+                (*desc).addr = ptr::addr_of_mut!(RESPONSE) as u64;
+                (*desc).len = MAX_MESSAGE as u32;
+                (*desc).flags = VIRTQ_DESC_F_WRITE;
+                (*desc).next = 0;
+
+                let avail = ptr::addr_of_mut!((*memory).avail);
+                (*avail).ring[(self.next_avail % self.queue_size) as usize] = 0;
+                fence(Ordering::Release);
+                self.next_avail = self.next_avail.wrapping_add(1);
+                (*avail).idx = self.next_avail;
+            }
+            fence(Ordering::SeqCst);
+            self.transport.notify(REQUEST_QUEUE);
+
+            let used = unsafe { ptr::addr_of!(QUEUE_MEMORY.used) };
+            while unsafe { ptr::read_volatile(ptr::addr_of!((*used).idx)) } != self.next_avail {
+                core::hint::spin_loop();
+            }
+            fence(Ordering::Acquire);
+            let slot = (self.next_avail.wrapping_sub(1) % self.queue_size) as usize;
+            let elem = unsafe { ptr::read_volatile(ptr::addr_of!((*used).ring[slot])) };
+            let len = (elem.len as usize).min(MAX_MESSAGE);
+            let reply = unsafe { &*ptr::addr_of!(RESPONSE) };
+            response.clear();
+            response.extend_from_slice(&reply[..len]);
+            Ok(())
+        }
     }
 
-    fn write64(base: usize, low_offset: usize, high_offset: usize, value: u64) {
-        write32(base, low_offset, value as u32);
-        write32(base, high_offset, (value >> 32) as u32);
+    /// Bring up the first virtio-9p device and return its mount tag
+    pub fn init() -> Result<String, VirtioError> {
+        let mut guard = DEVICE.lock();
+        if let Some(device) = guard.as_ref() {
+            return Ok(device.tag.clone());
+        }
+        let found = super::find(super::DEVICE_9P, 0).ok_or(VirtioError::DeviceNotFound)?;
+        let transport = found.transport;
+        let queue_size = unsafe {
+            start_init(transport, super::DEVICE_9P, 1 << VIRTIO_9P_MOUNT_TAG)?;
+            ptr::write(ptr::addr_of_mut!(QUEUE_MEMORY), QueueMemory::DEFAULT);
+            setup_queue(transport, REQUEST_QUEUE, ptr::addr_of_mut!(QUEUE_MEMORY))?
+        };
+        let config = |offset: usize| unsafe {
+            ptr::read_volatile((transport.config() + offset) as *const u8)
+        };
+        let tag_len = u16::from_le_bytes([config(0), config(1)]) as usize;
+        let tag: Vec<u8> = (0..tag_len).map(|i| config(2 + i)).collect();
+        finish_init(transport);
+        let tag = String::from_utf8_lossy(&tag).into_owned();
+        *guard = Some(VirtIo9p {
+            transport,
+            queue_size,
+            next_avail: 0,
+            tag: tag.clone(),
+        });
+        Ok(tag)
+    }
+
+    /// Exchange one 9p message with the host
+    pub fn transact(request: &[u8], response: &mut Vec<u8>) -> Result<(), VirtioError> {
+        match DEVICE.lock().as_mut() {
+            Some(device) => device.transact(request, response),
+            None => Err(VirtioError::DeviceNotFound),
+        }
     }
 }
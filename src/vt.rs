@@ -0,0 +1,260 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use spin::Mutex;
+
+use crate::console::Port;
+use crate::fd::FdError;
+use crate::proc::Pid;
+use crate::scheduler::Scheduler;
+
+/// Virtual terminals sharing the UART, switched with Alt-1 to Alt-4.
+/// Terminal 1 is `Port::Uart` (ttyS0); the others are `Port::Vt(n)`.
+pub const TERMINALS: usize = 4;
+
+/// Output kept per terminal to repaint the screen on a switch
+const SCROLLBACK: usize = 8 * 1024;
+
+/// Alt-<key> arrives as ESC followed by the key
+const ESC: u8 = 0x1b;
+/// Typed at the start of a line, ends the input: that read returns 0
+const CTRL_D: u8 = 0x04;
+const CLEAR_SCREEN: &[u8] = b"\x1b[2J\x1b[H";
+
+/// Bytes typed on a console and not yet read, the end-of-file marks
+/// among them, and the processes blocked reading them
+pub struct Input {
+    bytes: VecDeque<u8>,
+    /// Positions (counted in `received`) where Ctrl-D ended the input
+    eofs: VecDeque<usize>,
+    /// Bytes queued and read since boot
+    received: usize,
+    consumed: usize,
+    /// Nothing but newlines typed since the last Ctrl-D or boot
+    line_start: bool,
+    waiting: Vec<Pid>,
+}
+
+impl Input {
+    pub const fn new() -> Self {
+        Self {
+            bytes: VecDeque::new(),
+            eofs: VecDeque::new(),
+            received: 0,
+            consumed: 0,
+            line_start: true,
+            waiting: Vec::new(),
+        }
+    }
+
+    /// Queue a typed byte; Ctrl-D at the start of a line marks end of file
+    /// instead, and anywhere else is an ordinary byte
+    pub fn push(&mut self, byte: u8) {
+        if byte == CTRL_D && self.line_start {
+            self.eofs.push_back(self.received);
+            return;
+        }
+        self.line_start = matches!(byte, b'\r' | b'\n');
+        self.bytes.push_back(byte);
+        self.received += 1;
+    }
+
+    /// Read queued bytes up to the next end-of-file mark; Ok(0) consumes
+    /// the mark, WouldBlock means nothing has been typed
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, FdError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.eofs.front() == Some(&self.consumed) {
+            self.eofs.pop_front();
+            return Ok(0);
+        }
+        if self.bytes.is_empty() {
+            return Err(FdError::WouldBlock);
+        }
+        let until_eof = self
+            .eofs
+            .front()
+            .map_or(usize::MAX, |eof| eof - self.consumed);
+        let count = buf.len().min(self.bytes.len()).min(until_eof);
+        for (slot, byte) in buf.iter_mut().zip(self.bytes.drain(..count)) {
+            *slot = byte;
+        }
+        self.consumed += count;
+        Ok(count)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty() && self.eofs.is_empty()
+    }
+
+    /// Wake `pid` when there is something to read
+    pub fn mark_waiting(&mut self, pid: Pid) {
+        if !self.waiting.contains(&pid) {
+            self.waiting.push(pid);
+        }
+    }
+
+    /// Readers to wake, if there is something for them
+    pub fn take_waiting(&mut self) -> Vec<Pid> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        core::mem::take(&mut self.waiting)
+    }
+}
+
+/// One terminal: its input and its recent output
+struct Terminal {
+    input: Input,
+    scrollback: VecDeque<u8>,
+}
+
+impl Terminal {
+    const fn new() -> Self {
+        Self {
+            input: Input::new(),
+            scrollback: VecDeque::new(),
+        }
+    }
+}
+
+struct Mux {
+    terminals: [Terminal; TERMINALS],
+    /// Index of the terminal on screen
+    active: usize,
+    /// The last byte received was ESC; a digit after it switches terminals
+    escape: bool,
+}
+
+static MUX: Mutex<Mux> = Mutex::new(Mux {
+    terminals: [const { Terminal::new() }; TERMINALS],
+    active: 0,
+    escape: false,
+});
+
+impl Mux {
+    /// Put terminal `index` on screen, repainted from its scrollback
+    fn switch(&mut self, index: usize) {
+        if index == self.active {
+            return;
+        }
+        self.active = index;
+        crate::uart::write_bytes(CLEAR_SCREEN);
+        let (front, back) = self.terminals[index].scrollback.as_slices();
+        crate::uart::write_bytes(front);
+        crate::uart::write_bytes(back);
+    }
+}
+
+/// Index into the terminal table of a port multiplexed on the UART
+fn index(port: Port) -> Option<usize> {
+    match port {
+        Port::Uart => Some(0),
+        Port::Vt(number) if (2..=TERMINALS).contains(&number) => Some(number - 1),
+        _ => None,
+    }
+}
+
+fn port(index: usize) -> Port {
+    if index == 0 {
+        Port::Uart
+    } else {
+        Port::Vt(index + 1)
+    }
+}
+
+/// Device name of terminal `number` (2 and up) under /dev
+pub fn name(number: usize) -> &'static str {
+    match number {
+        2 => "tty2",
+        3 => "tty3",
+        4 => "tty4",
+        _ => "tty",
+    }
+}
+
+/// Record output for `port` and show it if that terminal is on screen
+pub fn write(port: Port, bytes: &[u8]) {
+    let Some(index) = index(port) else {
+        return;
+    };
+    // An interrupt handler printing while the code it interrupted holds
+    // the table cannot wait for it
+    let Some(mut mux) = MUX.try_lock() else {
+        crate::uart::write_bytes(bytes);
+        return;
+    };
+    let scrollback = &mut mux.terminals[index].scrollback;
+    scrollback.extend(bytes);
+    if scrollback.len() > SCROLLBACK {
+        // Keep whole lines so a repaint starts at the left margin
+        let excess = scrollback.len() - SCROLLBACK;
+        let cut = scrollback
+            .iter()
+            .skip(excess)
+            .position(|&byte| byte == b'\n')
+            .map_or(scrollback.len(), |newline| excess + newline + 1);
+        scrollback.drain(..cut);
+    }
+    if mux.active == index {
+        crate::uart::write_bytes(bytes);
+    }
+}
+
+/// Read bytes typed on `port`; WouldBlock if there are none, 0 at a
+/// Ctrl-D
+pub fn read(port: Port, buf: &mut [u8]) -> Result<usize, FdError> {
+    let index = index(port).ok_or(FdError::BadFd)?;
+    // Bytes the interrupt handler could not sort while the table was busy
+    receive();
+    MUX.lock().terminals[index].input.read(buf)
+}
+
+/// Wake `pid` when `port` next has input
+pub fn mark_waiting(port: Port, pid: Pid) {
+    if let Some(index) = index(port) {
+        MUX.lock().terminals[index].input.mark_waiting(pid);
+    }
+}
+
+/// Sort bytes received on the UART: Alt-<n> switches terminals, Ctrl-C and
+/// Ctrl-Z go to the foreground group of the terminal on screen, and the
+/// rest is that terminal's input. Wakes readers that now have input.
+pub fn receive() {
+    let Some(mut mux) = MUX.try_lock() else {
+        return;
+    };
+    let mut signals = Vec::new();
+    while let Some(byte) = crate::uart::read_byte_nonblocking() {
+        let active = mux.active;
+        if mux.escape {
+            mux.escape = false;
+            let number = byte.wrapping_sub(b'0') as usize;
+            if (1..=TERMINALS).contains(&number) {
+                mux.switch(number - 1);
+                continue;
+            }
+            mux.terminals[active].input.push(ESC);
+        }
+        match byte {
+            ESC => mux.escape = true,
+            crate::signal::CTRL_C => signals.push((active, byte, crate::signal::SIGINT)),
+            crate::signal::CTRL_Z => signals.push((active, byte, crate::signal::SIGTSTP)),
+            _ => mux.terminals[active].input.push(byte),
+        }
+    }
+    drop(mux);
+
+    // Without a foreground process group these are ordinary input
+    for (index, byte, sig) in signals {
+        if !crate::signal::signal_foreground(port(index), sig) {
+            MUX.lock().terminals[index].input.push(byte);
+        }
+    }
+    let mut waiting = Vec::new();
+    for terminal in MUX.lock().terminals.iter_mut() {
+        waiting.append(&mut terminal.input.take_waiting());
+    }
+    for pid in waiting {
+        Scheduler::unblock(pid);
+    }
+}
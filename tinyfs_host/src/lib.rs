@@ -0,0 +1,144 @@
+//! Host (std) build of TinyFs for `cargo test`.
+//!
+//! `src/fs.rs` is compiled unchanged against the shims below, which stand
+//! in for the kernel's console and log macros and VirtIO driver. The test suite in
+//! `src/fs_tests.rs` is only compiled under `cfg(test)` and drives `TinyFs`
+//! directly through an in-memory `BlockDevice`. `src/elf.rs` is built too,
+//! so the ELF parser's tests in `src/elf_tests.rs` run here as well, as
+//! does `/bin/sh`'s command list splitter from `user_bin/src/sh_list.rs`.
+
+extern crate alloc;
+
+#[macro_export]
+macro_rules! println {
+    ($($arg:tt)*) => { std::println!($($arg)*) };
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => { std::print!($($arg)*) };
+}
+
+/// Kernel log messages go to stderr; debug traces are dropped
+#[macro_export]
+macro_rules! klog {
+    ($subsystem:ident, Debug, $($arg:tt)+) => {{
+        let _ = format_args!($($arg)+);
+    }};
+    ($subsystem:ident, $level:ident, $($arg:tt)+) => {
+        std::eprintln!($($arg)+)
+    };
+}
+
+pub mod virtio {
+    pub mod block {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum VirtioError {
+            DeviceNotFound,
+            UnsupportedDevice,
+            LegacyOnly(u32),
+            QueueUnavailable,
+            DeviceRejectedFeatures,
+            DeviceFailure,
+            IoError,
+        }
+
+        /// There is no VirtIO device on the host; the global `fs::*` helpers
+        /// report `DeviceInitFailed` and tests mount `TinyFs` themselves.
+        pub struct VirtIoBlock;
+
+        impl VirtIoBlock {
+            pub fn total_blocks(&self) -> u32 {
+                0
+            }
+
+            pub fn read_block(&self, _index: u32, _buf: &mut [u8]) -> Result<(), VirtioError> {
+                Ok(())
+            }
+
+            pub fn write_block(&self, _index: u32, _buf: &[u8]) -> Result<(), VirtioError> {
+                Ok(())
+            }
+
+            pub fn read_blocks(&self, _index: u32, _buf: &mut [u8]) -> Result<(), VirtioError> {
+                Ok(())
+            }
+
+            pub fn write_blocks(&self, _index: u32, _buf: &[u8]) -> Result<(), VirtioError> {
+                Ok(())
+            }
+
+            pub fn block_size(&self) -> usize {
+                512
+            }
+
+            pub fn is_read_only(&self) -> bool {
+                false
+            }
+
+            pub fn flush(&self) -> Result<bool, VirtioError> {
+                Ok(false)
+            }
+        }
+
+        pub fn init() -> Result<VirtIoBlock, VirtioError> {
+            Err(VirtioError::DeviceNotFound)
+        }
+
+        pub fn open(_name: &str) -> Result<VirtIoBlock, VirtioError> {
+            Err(VirtioError::DeviceNotFound)
+        }
+
+        pub fn name(index: usize) -> alloc::string::String {
+            alloc::format!("vd{}", (b'a' + index as u8) as char)
+        }
+    }
+
+    /// No host share either; mounting `9p` fails with `DeviceInitFailed`.
+    pub mod p9 {
+        use super::block::VirtioError;
+
+        pub const MAX_MESSAGE: usize = 8192;
+
+        pub fn init() -> Result<alloc::string::String, VirtioError> {
+            Err(VirtioError::DeviceNotFound)
+        }
+
+        pub fn transact(
+            _request: &[u8],
+            _response: &mut alloc::vec::Vec<u8>,
+        ) -> Result<(), VirtioError> {
+            Err(VirtioError::DeviceNotFound)
+        }
+    }
+}
+
+/// Stands in for the goldfish RTC. Each test thread has its own clock,
+/// which stays at the epoch unless the test sets it.
+pub mod rtc {
+    use std::cell::Cell;
+
+    std::thread_local! {
+        static NOW: Cell<u64> = const { Cell::new(0) };
+    }
+
+    pub fn unix_time() -> u64 {
+        NOW.with(Cell::get)
+    }
+
+    pub fn set_unix_time(secs: u64) {
+        NOW.with(|now| now.set(secs));
+    }
+}
+
+#[path = "../../src/elf.rs"]
+pub mod elf;
+
+#[path = "../../src/fs.rs"]
+pub mod fs;
+
+#[path = "../../src/ninep.rs"]
+pub mod ninep;
+
+#[path = "../../user_bin/src/sh_list.rs"]
+pub mod sh_list;
@@ -1,7 +1,7 @@
 MEMORY
 {
-  ROM (rx)  : ORIGIN = 0x80400000, LENGTH = 0x00010000
-  RAM (rwx) : ORIGIN = 0x80410000, LENGTH = 0x00010000
+  ROM (rx)  : ORIGIN = 0x81200000, LENGTH = 0x00020000
+  RAM (rwx) : ORIGIN = 0x81220000, LENGTH = 0x00010000
 }
 
 REGION_ALIAS("REGION_TEXT", ROM);
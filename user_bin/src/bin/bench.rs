@@ -0,0 +1,31 @@
+#![no_std]
+#![no_main]
+
+use user_bin::{args, bench, bench_switch, eprintln, exit, write};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let mut words = args(argc, argv).skip(1);
+    // The kernel runs every pass before it hands back the report
+    let mut buf = [0u8; 2048];
+    let report = match (words.next(), words.next(), words.next()) {
+        (Some("disk"), device, None) => bench(device.unwrap_or("vda"), &mut buf),
+        (Some("switch"), None, _) => bench_switch(&mut buf),
+        _ => {
+            eprintln!("usage: bench disk [device]");
+            eprintln!("       bench switch");
+            exit(2);
+        }
+    };
+
+    match report {
+        Ok(len) => {
+            let _ = write(1, &buf[..len]);
+            exit(0)
+        }
+        Err(err) => {
+            eprintln!("bench: {}", err);
+            exit(1)
+        }
+    }
+}
@@ -1,63 +1,120 @@
 #![no_std]
 #![no_main]
 
-use user_bin::{close, exit, get_arg, open, read, write, O_READ};
+use core::fmt::Write;
+use user_bin::{
+    close, eprintln, exit, open, BufReader, BufWriter, Error, Opt, OptError, Options, O_READ,
+};
+
+/// Line numbers for `cat -n`, counted across all the files
+struct Numbering {
+    line: usize,
+    /// Whether the next byte starts a line
+    at_start: bool,
+}
+
+impl Numbering {
+    /// Copy `chunk` to `out`, putting a number in front of each line
+    fn write(&mut self, out: &mut BufWriter, mut chunk: &[u8]) -> Result<(), Error> {
+        while !chunk.is_empty() {
+            if self.at_start {
+                self.line += 1;
+                write!(out, "{:>6}\t", self.line).map_err(|_| Error::Io)?;
+            }
+            let end = chunk
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(chunk.len(), |i| i + 1);
+            out.write_all(&chunk[..end])?;
+            self.at_start = chunk[end - 1] == b'\n';
+            chunk = &chunk[end..];
+        }
+        Ok(())
+    }
+}
 
 #[unsafe(no_mangle)]
 pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
-    write(2, b"[cat] _start called\n");
+    eprintln!("[cat] _start called");
+    let mut out = BufWriter::new(1);
+
+    let mut numbering = None;
+    let mut opts = Options::new(argc, argv, "n");
+    for opt in &mut opts {
+        match opt {
+            Ok(Opt::Flag('n')) => {
+                numbering = Some(Numbering {
+                    line: 0,
+                    at_start: true,
+                })
+            }
+            Ok(_) => {}
+            Err(err) => usage(err),
+        }
+    }
 
-    // If no arguments, read from stdin
-    if argc == 1 {
-        write(2, b"[cat] argc=1, reading stdin\n");
-        cat_fd(0);
+    // If no files, read from stdin
+    let mut files = opts.operands().peekable();
+    if files.peek().is_none() {
+        eprintln!("[cat] no files, reading stdin");
+        cat_fd(0, &mut out, &mut numbering);
+        let _ = out.flush();
         exit(0);
     }
 
-    write(2, b"[cat] argc>1, opening files\n");
+    eprintln!("[cat] opening files");
 
     // Otherwise, cat each file argument
-    let mut i = 1;
-    write(2, b"[cat] entering while loop\n");
-    while i < argc {
-        write(2, b"[cat] calling get_arg\n");
-        let Some(filename) = get_arg(argc, argv, i) else {
-            write(2, b"[cat] get_arg returned None\n");
-            break;
-        };
-        write(2, b"[cat] get_arg returned\n");
-
-        write(2, b"[cat] opening file: ");
-        write(2, filename.as_bytes());
-        write(2, b"\n");
+    for filename in files {
+        eprintln!("[cat] opening file: {}", filename);
 
-        let fd = open(filename, O_READ);
-
-        if fd < 0 {
-            write(2, b"[cat] open failed\n");
-            write(2, b"cat: cannot open ");
-            write(2, filename.as_bytes());
-            write(2, b"\n");
-            exit(1);
-        }
+        let fd = match open(filename, O_READ) {
+            Ok(fd) => fd,
+            Err(err) => {
+                eprintln!("[cat] open failed");
+                let _ = out.flush();
+                eprintln!("cat: cannot open {}: {}", filename, err);
+                exit(1);
+            }
+        };
 
-        write(2, b"[cat] reading from file\n");
+        eprintln!("[cat] reading from file");
 
-        cat_fd(fd as usize);
-        close(fd as usize);
-        i += 1;
+        cat_fd(fd, &mut out, &mut numbering);
+        let _ = close(fd);
     }
 
+    let _ = out.flush();
     exit(0)
 }
 
-fn cat_fd(fd: usize) {
-    let mut buf = [0u8; 4096];
+fn usage(err: OptError) -> ! {
+    eprintln!("cat: {}", err);
+    eprintln!("usage: cat [-n] [file...]");
+    exit(2)
+}
+
+fn cat_fd(fd: usize, out: &mut BufWriter, numbering: &mut Option<Numbering>) {
+    let mut input = BufReader::new(fd);
     loop {
-        let len = read(fd, &mut buf);
-        if len <= 0 {
+        // Flush before blocking on more input so a cat reading the
+        // console echoes each line as it comes
+        if input.buffer().is_empty() && out.flush().is_err() {
             break;
         }
-        write(1, &buf[..len as usize]);
+        let len = match input.fill_buf() {
+            Ok([]) | Err(_) => break,
+            Ok(chunk) => {
+                let written = match numbering.as_mut() {
+                    Some(numbering) => numbering.write(out, chunk),
+                    None => out.write_all(chunk),
+                };
+                if written.is_err() {
+                    break;
+                }
+                chunk.len()
+            }
+        };
+        input.consume(len);
     }
 }
@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+
+use user_bin::{
+    args, basename, copy_file, eprintln, exit, join_path, same_file, stat, Stat, MAX_PATH,
+};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let operands = args(argc, argv).skip(1);
+    let count = operands.len();
+    let Some(target) = operands.clone().last().filter(|_| count >= 2) else {
+        eprintln!("usage: cp source dest");
+        eprintln!("       cp source... directory");
+        exit(2);
+    };
+
+    let mut info = Stat::default();
+    let into_dir = stat(target, &mut info).is_ok() && info.is_dir();
+    if count > 2 && !into_dir {
+        eprintln!("cp: {}: not a directory", target);
+        exit(1);
+    }
+
+    let mut status = 0;
+    // Every operand but the last is a source
+    for source in operands.take(count - 1) {
+        let mut path_buf = [0u8; MAX_PATH];
+        let dest = if into_dir {
+            match join_path(&mut path_buf, target, basename(source)) {
+                Some(dest) => dest,
+                None => {
+                    eprintln!("cp: {}/{}: name too long", target, basename(source));
+                    status = 1;
+                    continue;
+                }
+            }
+        } else {
+            target
+        };
+        if matches!(same_file(source, dest), Ok(true)) {
+            eprintln!("cp: {} and {} are the same file", source, dest);
+            status = 1;
+            continue;
+        }
+        if let Err(err) = copy_file(source, dest) {
+            eprintln!("cp: {}: {}", source, err);
+            status = 1;
+        }
+    }
+    exit(status)
+}
@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+
+use user_bin::{clock_gettime, eprintln, exit, println, Timespec, CLOCK_REALTIME};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(_argc: usize, _argv: *const *const u8) -> ! {
+    let mut now = Timespec::default();
+    if let Err(err) = clock_gettime(CLOCK_REALTIME, &mut now) {
+        eprintln!("date: cannot read the clock: {}", err);
+        exit(1);
+    }
+
+    let (year, month, day) = civil_from_days((now.sec / 86_400) as i64);
+    let secs = now.sec % 86_400;
+
+    println!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    );
+
+    exit(0);
+}
+
+/// Convert days since the epoch into a (year, month, day) Gregorian date
+fn civil_from_days(days: i64) -> (i64, u64, u64) {
+    // Count from 0000-03-01 so leap days fall at the end of a year
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u64;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u64;
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+use user_bin::{args, exit, BufWriter};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let mut words = args(argc, argv).skip(1).peekable();
+    // Like sh's echo, only a leading -n is an option; anything else,
+    // dashes included, is printed
+    let newline = words.next_if_eq(&"-n").is_none();
+
+    let mut out = BufWriter::new(1);
+    let mut first = true;
+    for word in words {
+        if !first {
+            let _ = out.write_all(b" ");
+        }
+        first = false;
+        if !write_escaped(&mut out, word) {
+            let _ = out.flush();
+            exit(0);
+        }
+    }
+    if newline {
+        let _ = out.write_all(b"\n");
+    }
+    let _ = out.flush();
+    exit(0)
+}
+
+/// Write `word` with `\n`, `\t`, `\\` and `\c` expanded. Returns false
+/// after `\c`, which ends the output there, final newline included.
+fn write_escaped(out: &mut BufWriter, word: &str) -> bool {
+    let mut bytes = word.bytes();
+    while let Some(byte) = bytes.next() {
+        let expanded = match byte {
+            b'\\' => match bytes.next() {
+                Some(b'n') => b'\n',
+                Some(b't') => b'\t',
+                Some(b'\\') => b'\\',
+                Some(b'c') => return false,
+                Some(other) => {
+                    let _ = out.write_all(b"\\");
+                    other
+                }
+                None => b'\\',
+            },
+            byte => byte,
+        };
+        let _ = out.write_all(&[expanded]);
+    }
+    true
+}
@@ -0,0 +1,325 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::ops::RangeInclusive;
+use core::str;
+use user_bin::{
+    args, close, exit, open, BufReader, BufWriter, Error, O_ATOMIC, O_CREATE, O_READ, O_TRUNC,
+    O_WRITE,
+};
+
+/// Longest line ed reads; the rest of a longer line is dropped
+const MAX_LINE: usize = 1024;
+
+/// What went wrong with a command; ed only prints `?`, and `h` explains
+type Failure = &'static str;
+
+struct Editor {
+    lines: Vec<Vec<u8>>,
+    /// Current line, 1-based; 0 only when the buffer is empty
+    current: usize,
+    /// Default file for `w`
+    path: Option<String>,
+    /// Changed since the last write
+    modified: bool,
+    /// A `q` was refused for unsaved changes; a second one quits anyway
+    warned: bool,
+    last_error: Failure,
+    input: BufReader,
+    out: BufWriter,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let mut ed = Editor {
+        lines: Vec::new(),
+        current: 0,
+        path: None,
+        modified: false,
+        warned: false,
+        last_error: "",
+        input: BufReader::new(0),
+        out: BufWriter::new(1),
+    };
+    if let Some(path) = args(argc, argv).nth(1) {
+        ed.path = Some(String::from(path));
+        match ed.load(path) {
+            Ok(bytes) => ed.say(format_args!("{}", bytes)),
+            // Editing a new file is fine; it is created on `w`
+            Err(Error::NotFound) => ed.fail("cannot open input file"),
+            Err(_) => ed.fail("cannot read input file"),
+        }
+    }
+
+    let mut command = [0u8; MAX_LINE];
+    loop {
+        let _ = ed.out.flush();
+        let len = match ed.input.read_line(&mut command) {
+            Ok(Some(len)) => len,
+            // End of input quits, as `q` would
+            Ok(None) | Err(_) => exit(0),
+        };
+        let Ok(command) = str::from_utf8(&command[..len]) else {
+            ed.fail("invalid command");
+            continue;
+        };
+        match ed.run(command) {
+            Ok(true) => {
+                let _ = ed.out.flush();
+                exit(0);
+            }
+            Ok(false) => {}
+            Err(failure) => ed.fail(failure),
+        }
+    }
+}
+
+impl Editor {
+    /// Run one command line. Ok(true) means quit.
+    fn run(&mut self, command: &str) -> Result<bool, Failure> {
+        let (range, rest) = self.parse_range(command)?;
+        let mut chars = rest.chars();
+        let name = chars.next();
+        let operand = chars.as_str().trim();
+        if name != Some('q') {
+            self.warned = false;
+        }
+        match name {
+            // A bare address moves there; a bare newline moves down one
+            None => {
+                let line = match range {
+                    Some(range) => *range.end(),
+                    None => self.current + 1,
+                };
+                self.check(line..=line)?;
+                self.current = line;
+                self.print(line..=line, false);
+            }
+            Some('a') => {
+                let after = range.map_or(self.current, |range| *range.end());
+                self.check_addr(after)?;
+                self.insert(after);
+            }
+            Some('i') => {
+                let before = range.map_or(self.current, |range| *range.end());
+                self.check_addr(before)?;
+                self.insert(before.saturating_sub(1));
+            }
+            Some('c') => {
+                let range = self.range_or_current(range)?;
+                let start = *range.start();
+                self.delete(range);
+                self.insert(start - 1);
+            }
+            Some('d') => {
+                let range = self.range_or_current(range)?;
+                self.delete(range);
+            }
+            Some(command @ ('p' | 'n')) => {
+                let range = self.range_or_current(range)?;
+                self.current = *range.end();
+                self.print(range, command == 'n');
+            }
+            Some('=') => {
+                let line = range.map_or(self.lines.len(), |range| *range.end());
+                self.say(format_args!("{}", line));
+            }
+            Some('w') => {
+                if !operand.is_empty() {
+                    self.path = Some(String::from(operand));
+                }
+                let path = self.path.clone().ok_or("no current filename")?;
+                let bytes = self.save(&path).map_err(|_| "cannot write file")?;
+                self.modified = false;
+                self.say(format_args!("{}", bytes));
+            }
+            Some('q') => {
+                if self.modified && !self.warned {
+                    self.warned = true;
+                    return Err("warning: buffer modified");
+                }
+                return Ok(true);
+            }
+            Some('Q') => return Ok(true),
+            Some('h') => {
+                let error = self.last_error;
+                self.say(format_args!("{}", error));
+            }
+            Some(_) => return Err("unknown command"),
+        }
+        Ok(false)
+    }
+
+    /// Split a command into its addresses and the rest. `,` alone means
+    /// the whole buffer; one address is a range of one line.
+    fn parse_range<'a>(
+        &self,
+        command: &'a str,
+    ) -> Result<(Option<RangeInclusive<usize>>, &'a str), Failure> {
+        let (first, rest) = self.parse_addr(command)?;
+        let Some(rest) = rest.strip_prefix(',') else {
+            return Ok((first.map(|line| line..=line), rest));
+        };
+        let (second, rest) = self.parse_addr(rest)?;
+        let range = match (first, second) {
+            (None, None) => 1..=self.lines.len(),
+            (Some(first), None) => first..=first,
+            (None, Some(second)) => 1..=second,
+            (Some(first), Some(second)) => first..=second,
+        };
+        Ok((Some(range), rest))
+    }
+
+    /// An address at the start of `text`: a number, `.` or `$`, then any
+    /// `+n` or `-n` offsets
+    fn parse_addr<'a>(&self, text: &'a str) -> Result<(Option<usize>, &'a str), Failure> {
+        let (mut line, mut rest) = match text.as_bytes().first() {
+            Some(b'.') => (self.current as isize, &text[1..]),
+            Some(b'$') => (self.lines.len() as isize, &text[1..]),
+            Some(b'0'..=b'9') => split_number(text),
+            Some(b'+' | b'-') => (self.current as isize, text),
+            _ => return Ok((None, text)),
+        };
+        while let Some(sign) = rest.chars().next().filter(|&c| c == '+' || c == '-') {
+            let (mut offset, after) = split_number(&rest[1..]);
+            // A sign on its own moves by one
+            if after.len() == rest.len() - 1 {
+                offset = 1;
+            }
+            line += if sign == '+' { offset } else { -offset };
+            rest = after;
+        }
+        usize::try_from(line)
+            .map(|line| (Some(line), rest))
+            .map_err(|_| "invalid address")
+    }
+
+    /// Fail unless every line of `range` exists
+    fn check(&self, range: RangeInclusive<usize>) -> Result<(), Failure> {
+        if *range.start() == 0 || range.start() > range.end() || *range.end() > self.lines.len() {
+            return Err("invalid address");
+        }
+        Ok(())
+    }
+
+    /// Fail unless `line` exists or is 0, the place before the first line
+    fn check_addr(&self, line: usize) -> Result<(), Failure> {
+        if line > self.lines.len() {
+            return Err("invalid address");
+        }
+        Ok(())
+    }
+
+    /// `range`, or the current line when the command gave none
+    fn range_or_current(
+        &self,
+        range: Option<RangeInclusive<usize>>,
+    ) -> Result<RangeInclusive<usize>, Failure> {
+        let range = range.unwrap_or(self.current..=self.current);
+        self.check(range.clone())?;
+        Ok(range)
+    }
+
+    /// Read lines from the terminal until `.` and add them after `after`
+    fn insert(&mut self, after: usize) {
+        let mut line = [0u8; MAX_LINE];
+        let mut at = after;
+        while let Ok(Some(len)) = self.input.read_line(&mut line) {
+            if &line[..len] == b"." {
+                break;
+            }
+            self.lines.insert(at, Vec::from(&line[..len]));
+            at += 1;
+            self.modified = true;
+        }
+        self.current = at;
+    }
+
+    fn delete(&mut self, range: RangeInclusive<usize>) {
+        let start = *range.start();
+        self.lines.drain(start - 1..*range.end());
+        self.current = start.min(self.lines.len());
+        self.modified = true;
+    }
+
+    /// Print the lines of `range`, after their numbers for `n`
+    fn print(&mut self, range: RangeInclusive<usize>, numbered: bool) {
+        for number in range {
+            if numbered {
+                let _ = write!(self.out, "{}\t", number);
+            }
+            let _ = self.out.write_all(&self.lines[number - 1]);
+            let _ = self.out.write_all(b"\n");
+        }
+    }
+
+    /// Replace the buffer with the file at `path`; returns its size
+    fn load(&mut self, path: &str) -> Result<usize, Error> {
+        let fd = open(path, O_READ)?;
+        let mut input = BufReader::new(fd);
+        let mut line = [0u8; MAX_LINE];
+        let mut bytes = 0;
+        let mut read = Ok(());
+        loop {
+            match input.read_line(&mut line) {
+                Ok(Some(len)) => {
+                    self.lines.push(Vec::from(&line[..len]));
+                    bytes += len + 1;
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    read = Err(err);
+                    break;
+                }
+            }
+        }
+        let _ = close(fd);
+        read?;
+        self.current = self.lines.len();
+        Ok(bytes)
+    }
+
+    /// Write the buffer to `path`, replacing the file in one step so a
+    /// failed write leaves the old contents; returns the bytes written
+    fn save(&self, path: &str) -> Result<usize, Error> {
+        let fd = open(path, O_WRITE | O_CREATE | O_TRUNC | O_ATOMIC)?;
+        let mut file = BufWriter::new(fd);
+        let written = self.lines.iter().try_fold(0, |bytes, line| {
+            file.write_all(line)?;
+            file.write_all(b"\n")?;
+            Ok(bytes + line.len() + 1)
+        });
+        let written = written.and_then(|bytes| file.flush().map(|()| bytes));
+        drop(file);
+        let closed = close(fd);
+        let bytes = written?;
+        closed?;
+        Ok(bytes)
+    }
+
+    fn say(&mut self, args: core::fmt::Arguments) {
+        let _ = self.out.write_fmt(args);
+        let _ = self.out.write_all(b"\n");
+    }
+
+    /// Report a failed command the way ed does, with a bare `?`
+    fn fail(&mut self, failure: Failure) {
+        self.last_error = failure;
+        let _ = self.out.write_all(b"?\n");
+    }
+}
+
+/// The decimal number at the start of `text` (0 if none) and the rest
+fn split_number(text: &str) -> (isize, &str) {
+    let end = text
+        .bytes()
+        .position(|byte| !byte.is_ascii_digit())
+        .unwrap_or(text.len());
+    let number = text[..end].parse().unwrap_or(0);
+    (number, &text[end..])
+}
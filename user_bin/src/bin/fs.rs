@@ -0,0 +1,243 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use user_bin::{
+    args, compact, diskusage, eprintln, exit, fscheck, mkfs, mount, mounts, println, statfs,
+    umount, untar, write, BufWriter, CompactSummary, FsckSummary, StatFs, UntarSummary,
+};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let mut words = args(argc, argv).skip(1);
+    let status = match words.next() {
+        Some("check") => check(words),
+        Some("df") => df(words),
+        Some("du") => du(words),
+        Some("compact") => compact_fs(words),
+        Some("untar") => untar_archive(words),
+        Some("mount") => mount_device(words),
+        Some("umount") => umount_path(words),
+        Some("mkfs") => mkfs_device(words),
+        _ => usage(),
+    };
+    exit(status)
+}
+
+fn usage() -> ! {
+    eprintln!("usage: fs check [--repair]");
+    eprintln!("       fs df [path]");
+    eprintln!("       fs du [path]");
+    eprintln!("       fs compact");
+    eprintln!("       fs untar <archive> [dest]");
+    eprintln!("       fs mount [<device> <path>]");
+    eprintln!("       fs umount <path>");
+    eprintln!("       fs mkfs <device>");
+    exit(2)
+}
+
+/// `fs check [--repair]`: report inconsistencies in the root filesystem,
+/// fixing them with --repair
+fn check(mut words: impl Iterator<Item = &'static str>) -> isize {
+    let repair = match (words.next(), words.next()) {
+        (None, _) => false,
+        (Some("--repair"), None) => true,
+        _ => usage(),
+    };
+
+    let mut summary = FsckSummary::default();
+    let mut buf = [0u8; 2048];
+    let len = match fscheck(repair, &mut summary, &mut buf) {
+        Ok(len) => len,
+        Err(err) => {
+            eprintln!("fs: check: {}", err);
+            return 1;
+        }
+    };
+
+    let mut out = BufWriter::new(1);
+    let mut listed = 0;
+    for line in buf[..len].split(|&b| b == b'\n').filter(|line| !line.is_empty()) {
+        let _ = out.write_all(b"  ");
+        let _ = out.write_all(line);
+        let _ = out.write_all(b"\n");
+        listed += 1;
+    }
+    if summary.issues > listed {
+        let _ = writeln!(out, "  ... and {} more", summary.issues - listed);
+    }
+    let _ = writeln!(
+        out,
+        "{} files, {} directories, {} blocks in use, {} issue(s)",
+        summary.files, summary.directories, summary.used_blocks, summary.issues
+    );
+    if summary.repaired != 0 {
+        let _ = writeln!(out, "filesystem repaired");
+    } else if summary.issues > 0 && !repair {
+        let _ = writeln!(out, "run `fs check --repair` to fix");
+    }
+    let _ = out.flush();
+    if summary.issues == 0 || summary.repaired != 0 {
+        0
+    } else {
+        1
+    }
+}
+
+/// The optional path argument of df and du; "" means the working directory
+fn path_arg(mut words: impl Iterator<Item = &'static str>) -> &'static str {
+    match (words.next(), words.next()) {
+        (path, None) => path.unwrap_or(""),
+        _ => usage(),
+    }
+}
+
+/// `fs df [path]`: block counts for the filesystem holding `path`
+fn df(words: impl Iterator<Item = &'static str>) -> isize {
+    let path = path_arg(words);
+    let mut stat = StatFs::default();
+    if let Err(err) = statfs(path, &mut stat) {
+        eprintln!("fs: df: {}", err);
+        return 1;
+    }
+
+    let mut out = BufWriter::new(1);
+    let _ = writeln!(out, "block size: {} bytes", stat.block_size);
+    let _ = writeln!(
+        out,
+        "{} blocks total, {} used, {} free",
+        stat.total_blocks, stat.used_blocks, stat.free_blocks
+    );
+    let _ = writeln!(
+        out,
+        "{} KiB free of {} KiB",
+        stat.free_blocks * stat.block_size / 1024,
+        stat.total_blocks * stat.block_size / 1024
+    );
+    let _ = writeln!(out, "max name length: {} bytes", stat.max_name_len);
+    let _ = out.flush();
+    0
+}
+
+/// `fs du [path]`: blocks and bytes used by `path` and each directory
+/// below it
+fn du(words: impl Iterator<Item = &'static str>) -> isize {
+    let path = path_arg(words);
+    let mut cookie = 0;
+    let mut buf = [0u8; 1024];
+    loop {
+        match diskusage(path, &mut cookie, &mut buf) {
+            Ok(0) => return 0,
+            Ok(len) => {
+                let _ = write(1, &buf[..len]);
+            }
+            Err(err) => {
+                eprintln!("fs: du: {}", err);
+                return 1;
+            }
+        }
+    }
+}
+
+/// `fs compact`: defragment the root filesystem
+fn compact_fs(mut words: impl Iterator<Item = &'static str>) -> isize {
+    if words.next().is_some() {
+        usage();
+    }
+    println!("compacting...");
+    let mut summary = CompactSummary::default();
+    if let Err(err) = compact(&mut summary) {
+        eprintln!("fs: compact: {}", err);
+        return 1;
+    }
+    println!(
+        "moved {} blocks, reclaimed {}, defragmented {} file(s){}",
+        summary.blocks_moved,
+        summary.blocks_reclaimed,
+        summary.files_defragmented,
+        if summary.files_skipped > 0 {
+            " (some skipped: disk too full)"
+        } else {
+            ""
+        }
+    );
+    0
+}
+
+/// `fs untar <archive> [dest]`: extract a ustar archive, into the working
+/// directory by default
+fn untar_archive(mut words: impl Iterator<Item = &'static str>) -> isize {
+    let Some(archive) = words.next() else {
+        usage();
+    };
+    let dest = path_arg(words);
+    let mut summary = UntarSummary::default();
+    if let Err(err) = untar(archive, dest, &mut summary) {
+        eprintln!("fs: untar: {}", err);
+        return 1;
+    }
+    println!(
+        "extracted {} file(s) ({} bytes), {} director(ies), skipped {}",
+        summary.files, summary.bytes, summary.directories, summary.skipped
+    );
+    0
+}
+
+/// `fs mount [<device> <path>]`: list the mounts, or mount `device`
+fn mount_device(mut words: impl Iterator<Item = &'static str>) -> isize {
+    let (device, path) = match (words.next(), words.next(), words.next()) {
+        (None, _, _) => {
+            let mut buf = [0u8; 512];
+            return match mounts(&mut buf) {
+                Ok(len) => {
+                    let _ = write(1, &buf[..len]);
+                    0
+                }
+                Err(err) => {
+                    eprintln!("fs: mount: {}", err);
+                    1
+                }
+            };
+        }
+        (Some(device), Some(path), None) => (device, path),
+        _ => usage(),
+    };
+    match mount(device, path) {
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("fs: mount: {}", err);
+            1
+        }
+    }
+}
+
+/// `fs umount <path>`
+fn umount_path(mut words: impl Iterator<Item = &'static str>) -> isize {
+    let (Some(path), None) = (words.next(), words.next()) else {
+        usage();
+    };
+    match umount(path) {
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("fs: umount: {}", err);
+            1
+        }
+    }
+}
+
+/// `fs mkfs <device>`: format an unmounted disk
+fn mkfs_device(mut words: impl Iterator<Item = &'static str>) -> isize {
+    let (Some(device), None) = (words.next(), words.next()) else {
+        usage();
+    };
+    match mkfs(device) {
+        Ok(_) => {
+            println!("formatted {}", device);
+            0
+        }
+        Err(err) => {
+            eprintln!("fs: mkfs: {}", err);
+            1
+        }
+    }
+}
@@ -0,0 +1,154 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use user_bin::{close, eprintln, exit, open, BufReader, BufWriter, Opt, OptError, Options, O_READ};
+
+/// Longest line grep looks at; the rest of a longer line is ignored
+const MAX_LINE: usize = 1024;
+
+struct Flags {
+    /// -v: select the lines that do not match
+    invert: bool,
+    /// -c: print how many lines were selected instead of the lines
+    count: bool,
+    /// -F: the pattern is a plain string
+    fixed: bool,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let mut flags = Flags {
+        invert: false,
+        count: false,
+        fixed: false,
+    };
+    let mut opts = Options::new(argc, argv, "vcF");
+    for opt in &mut opts {
+        match opt {
+            Ok(Opt::Flag('v')) => flags.invert = true,
+            Ok(Opt::Flag('c')) => flags.count = true,
+            Ok(Opt::Flag('F')) => flags.fixed = true,
+            Ok(_) => {}
+            Err(err) => usage(Some(err)),
+        }
+    }
+
+    let mut operands = opts.operands();
+    let Some(pattern) = operands.next() else {
+        usage(None);
+    };
+    let files = opts.operands().count() - 1;
+
+    let mut out = BufWriter::new(1);
+    let mut selected = false;
+    let mut status = 0;
+    if files == 0 {
+        selected = grep_fd(&mut out, 0, pattern.as_bytes(), &flags, None);
+    }
+    for path in operands {
+        let fd = match open(path, O_READ) {
+            Ok(fd) => fd,
+            Err(err) => {
+                let _ = out.flush();
+                eprintln!("grep: {}: {}", path, err);
+                status = 2;
+                continue;
+            }
+        };
+        // With several files each line says which one it came from
+        let name = (files > 1).then_some(path);
+        selected |= grep_fd(&mut out, fd, pattern.as_bytes(), &flags, name);
+        let _ = close(fd);
+    }
+    let _ = out.flush();
+    if status == 0 && !selected {
+        status = 1;
+    }
+    exit(status)
+}
+
+fn usage(err: Option<OptError>) -> ! {
+    if let Some(err) = err {
+        eprintln!("grep: {}", err);
+    }
+    eprintln!("usage: grep [-vcF] pattern [file...]");
+    exit(2)
+}
+
+/// Print the lines of `fd` that `flags` selects, or their count with -c.
+/// Returns whether any line was selected.
+fn grep_fd(
+    out: &mut BufWriter,
+    fd: usize,
+    pattern: &[u8],
+    flags: &Flags,
+    name: Option<&str>,
+) -> bool {
+    let mut input = BufReader::new(fd);
+    let mut line = [0u8; MAX_LINE];
+    let mut count = 0;
+    while let Ok(Some(len)) = input.read_line(&mut line) {
+        let text = &line[..len];
+        let found = if flags.fixed {
+            pattern.is_empty() || text.windows(pattern.len()).any(|window| window == pattern)
+        } else {
+            matches(pattern, text)
+        };
+        if found == flags.invert {
+            continue;
+        }
+        count += 1;
+        if flags.count {
+            continue;
+        }
+        if let Some(name) = name {
+            let _ = write!(out, "{}:", name);
+        }
+        let _ = out.write_all(text);
+        let _ = out.write_all(b"\n");
+    }
+    if flags.count {
+        let _ = match name {
+            Some(name) => writeln!(out, "{}:{}", name, count),
+            None => writeln!(out, "{}", count),
+        };
+    }
+    count > 0
+}
+
+/// Kernighan and Pike's matcher: `^` and `$` anchor the pattern, `.`
+/// matches any byte and `c*` any run of `c`; everything else is literal
+fn matches(pattern: &[u8], text: &[u8]) -> bool {
+    if let Some(rest) = pattern.strip_prefix(b"^") {
+        return match_here(rest, text);
+    }
+    (0..=text.len()).any(|start| match_here(pattern, &text[start..]))
+}
+
+/// Whether `pattern` matches at the start of `text`
+fn match_here(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => true,
+        [c, b'*', rest @ ..] => match_star(*c, rest, text),
+        [b'$'] => text.is_empty(),
+        [c, rest @ ..] => match text.split_first() {
+            Some((first, text)) if *c == b'.' || c == first => match_here(rest, text),
+            _ => false,
+        },
+    }
+}
+
+/// Whether any run of `c` at the start of `text` (the shortest first) is
+/// followed by a match for `pattern`
+fn match_star(c: u8, pattern: &[u8], mut text: &[u8]) -> bool {
+    loop {
+        if match_here(pattern, text) {
+            return true;
+        }
+        match text.split_first() {
+            Some((&first, rest)) if c == b'.' || first == c => text = rest,
+            _ => return false,
+        }
+    }
+}
@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use user_bin::{close, eprintln, exit, open, BufReader, BufWriter, Opt, OptError, Options, O_READ};
+
+/// Lines printed without -n
+const DEFAULT_LINES: usize = 10;
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let mut lines = DEFAULT_LINES;
+    let mut opts = Options::new(argc, argv, "n:");
+    for opt in &mut opts {
+        match opt {
+            Ok(Opt::Value('n', count)) => match count.parse() {
+                Ok(count) => lines = count,
+                Err(_) => {
+                    eprintln!("head: invalid line count: {}", count);
+                    exit(2);
+                }
+            },
+            Ok(_) => {}
+            Err(err) => usage(err),
+        }
+    }
+
+    let mut out = BufWriter::new(1);
+    let mut status = 0;
+    let files = opts.operands().count();
+    if files == 0 {
+        head_fd(&mut out, 0, lines);
+    }
+    for (i, path) in opts.operands().enumerate() {
+        let fd = match open(path, O_READ) {
+            Ok(fd) => fd,
+            Err(err) => {
+                let _ = out.flush();
+                eprintln!("head: cannot open {}: {}", path, err);
+                status = 1;
+                continue;
+            }
+        };
+        // With several files each gets a `==> name <==` heading
+        if files > 1 {
+            let gap = if i > 0 { "\n" } else { "" };
+            let _ = writeln!(out, "{}==> {} <==", gap, path);
+        }
+        head_fd(&mut out, fd, lines);
+        let _ = close(fd);
+    }
+    let _ = out.flush();
+    exit(status)
+}
+
+fn usage(err: OptError) -> ! {
+    eprintln!("head: {}", err);
+    eprintln!("usage: head [-n lines] [file...]");
+    exit(2)
+}
+
+/// Copy the first `lines` lines of `fd` to `out`
+fn head_fd(out: &mut BufWriter, fd: usize, mut lines: usize) {
+    let mut input = BufReader::new(fd);
+    while lines > 0 {
+        let len = match input.fill_buf() {
+            Ok([]) | Err(_) => break,
+            Ok(chunk) => {
+                // Stop just after the newline that ends the last line wanted
+                let mut end = chunk.len();
+                for (i, &byte) in chunk.iter().enumerate() {
+                    if byte == b'\n' {
+                        lines -= 1;
+                        if lines == 0 {
+                            end = i + 1;
+                            break;
+                        }
+                    }
+                }
+                if out.write_all(&chunk[..end]).is_err() {
+                    break;
+                }
+                end
+            }
+        };
+        input.consume(len);
+    }
+}
@@ -0,0 +1,158 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use user_bin::{
+    close, eprintln, exit, lseek, open, BufReader, BufWriter, Error, Opt, OptError, Options,
+    O_READ, SEEK_SET,
+};
+
+/// Bytes shown per line
+const LINE: usize = 16;
+
+/// -s and -n: which part of the input to show
+struct Range {
+    skip: usize,
+    length: Option<usize>,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let mut range = Range {
+        skip: 0,
+        length: None,
+    };
+    let mut opts = Options::new(argc, argv, "s:n:");
+    for opt in &mut opts {
+        match opt {
+            Ok(Opt::Value('s', value)) => range.skip = number(value),
+            Ok(Opt::Value('n', value)) => range.length = Some(number(value)),
+            Ok(_) => {}
+            Err(err) => usage(Some(err)),
+        }
+    }
+
+    let mut files = opts.operands();
+    let path = files.next();
+    if files.next().is_some() {
+        usage(None);
+    }
+
+    let fd = match path {
+        None => 0,
+        Some(path) => match open(path, O_READ) {
+            Ok(fd) => fd,
+            Err(err) => {
+                eprintln!("hexdump: {}: {}", path, err);
+                exit(1);
+            }
+        },
+    };
+    let mut out = BufWriter::new(1);
+    let dumped = dump(&mut out, fd, &range);
+    let _ = out.flush();
+    if path.is_some() {
+        let _ = close(fd);
+    }
+    if let Err(err) = dumped {
+        eprintln!("hexdump: {}", err);
+        exit(1);
+    }
+    exit(0)
+}
+
+fn usage(err: Option<OptError>) -> ! {
+    if let Some(err) = err {
+        eprintln!("hexdump: {}", err);
+    }
+    eprintln!("usage: hexdump [-s offset] [-n length] [file]");
+    exit(2)
+}
+
+/// A count given in decimal or, with a `0x` prefix, hex
+fn number(value: &str) -> usize {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.unwrap_or_else(|_| {
+        eprintln!("hexdump: invalid number: {}", value);
+        exit(2)
+    })
+}
+
+/// Print `fd` as offset, hex and ASCII columns. A run of lines that
+/// repeat the one before is shown as a single `*`.
+fn dump(out: &mut BufWriter, fd: usize, range: &Range) -> Result<(), Error> {
+    let mut input = BufReader::new(fd);
+    let mut offset = range.skip;
+    // Seek past the skipped bytes if we can; pipes have to be read
+    if range.skip > 0 && lseek(fd, range.skip as isize, SEEK_SET).is_err() {
+        let mut left = range.skip;
+        while left > 0 {
+            let len = match input.fill_buf()? {
+                [] => break,
+                chunk => chunk.len().min(left),
+            };
+            input.consume(len);
+            left -= len;
+        }
+    }
+
+    let mut left = range.length.unwrap_or(usize::MAX);
+    let mut line = [0u8; LINE];
+    let mut previous = [0u8; LINE];
+    let mut squeezing = false;
+    loop {
+        let mut len = 0;
+        while len < LINE.min(left) {
+            match input.read_byte()? {
+                Some(byte) => line[len] = byte,
+                None => break,
+            }
+            len += 1;
+        }
+        if len == 0 {
+            break;
+        }
+        left -= len;
+
+        let repeat = len == LINE && offset > range.skip && line == previous;
+        if repeat {
+            if !squeezing {
+                out.write_all(b"*\n")?;
+                squeezing = true;
+            }
+        } else {
+            squeezing = false;
+            write_line(out, offset, &line[..len])?;
+        }
+        previous = line;
+        offset += len;
+    }
+    writeln!(out, "{:08x}", offset).map_err(|_| Error::Io)
+}
+
+/// `00000010  7f 45 4c 46 02 01 01 00  00 00 00 00 00 00 00 00  |.ELF............|`
+fn write_line(out: &mut BufWriter, offset: usize, bytes: &[u8]) -> Result<(), Error> {
+    write!(out, "{:08x} ", offset).map_err(|_| Error::Io)?;
+    for i in 0..LINE {
+        if i == LINE / 2 {
+            out.write_all(b" ")?;
+        }
+        match bytes.get(i) {
+            Some(byte) => write!(out, " {:02x}", byte).map_err(|_| Error::Io)?,
+            None => out.write_all(b"   ")?,
+        }
+    }
+    out.write_all(b"  |")?;
+    for &byte in bytes {
+        let shown = if byte.is_ascii_graphic() || byte == b' ' {
+            byte
+        } else {
+            b'.'
+        };
+        out.write_all(&[shown])?;
+    }
+    out.write_all(b"|\n")
+}
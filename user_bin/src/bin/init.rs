@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+use user_bin::{
+    close, eprintln, open, println, spawn_pgrp, spawn_with, tcsetpgrp, wait, Error,
+    SpawnFileActions, O_READ, O_WRITE, SPAWN_NEW_PGRP,
+};
+
+const SHELL: &str = "/bin/sh";
+/// Other terminals that get a shell of their own: the virtio console, if
+/// QEMU has one, and the UART's virtual terminals (Alt-2 to Alt-4)
+const CONSOLES: [&str; 4] = ["/dev/hvc0", "/dev/tty2", "/dev/tty3", "/dev/tty4"];
+
+/// Start the shell in its own process group and give it the console
+fn start_shell() -> Result<usize, Error> {
+    let pid = spawn_pgrp(SHELL, &[SHELL], SPAWN_NEW_PGRP);
+    match pid {
+        Ok(pid) => {
+            let _ = tcsetpgrp(pid);
+        }
+        Err(err) => eprintln!("init: cannot start /bin/sh: {}", err),
+    }
+    pid
+}
+
+/// Start a shell reading and writing `console`, if it exists. It takes
+/// that console's foreground itself once it runs a job.
+fn start_console_shell(console: &str) -> Result<usize, Error> {
+    let probe = open(console, O_READ)?;
+    let _ = close(probe);
+
+    let mut actions = SpawnFileActions::new();
+    actions.add_open(0, console, O_READ);
+    actions.add_open(1, console, O_WRITE);
+    actions.add_open(2, console, O_WRITE);
+    let pid = spawn_with(SHELL, &[SHELL], SPAWN_NEW_PGRP, &actions);
+    if let Err(err) = pid {
+        eprintln!("init: cannot start /bin/sh on {}: {}", console, err);
+    }
+    pid
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(_argc: usize, _argv: *const *const u8) -> ! {
+    let mut shell = start_shell();
+    let mut console_shells = CONSOLES.map(start_console_shell);
+
+    // Reap everything: our shells and every orphan the kernel hands us.
+    // Restart a shell whenever it exits.
+    loop {
+        let mut status = 0;
+        let Ok(pid) = wait(Some(&mut status)) else {
+            // No children at all: the shell could not be started
+            shell = start_shell();
+            if shell.is_err() {
+                user_bin::exit(1);
+            }
+            continue;
+        };
+        if shell == Ok(pid) {
+            println!("init: shell exited, restarting");
+            shell = start_shell();
+        } else if let Some(slot) = console_shells.iter().position(|&shell| shell == Ok(pid)) {
+            console_shells[slot] = start_console_shell(CONSOLES[slot]);
+        }
+    }
+}
@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use user_bin::{
+    args, eprintln, exit, kill, BufWriter, SIGCONT, SIGINT, SIGKILL, SIGSTOP, SIGTERM, SIGTSTP,
+    SIGXCPU,
+};
+
+/// Signals kill knows by name; any other number up to 31 works too
+const SIGNALS: [(&str, usize); 7] = [
+    ("INT", SIGINT),
+    ("KILL", SIGKILL),
+    ("TERM", SIGTERM),
+    ("CONT", SIGCONT),
+    ("STOP", SIGSTOP),
+    ("TSTP", SIGTSTP),
+    ("XCPU", SIGXCPU),
+];
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let mut words = args(argc, argv).skip(1).peekable();
+    // The signal comes first as -NAME, -NUMBER or -s NAME. Option letters
+    // cannot be parsed getopt-style since -9 is not a flag.
+    let mut sig = SIGTERM;
+    match words.peek().copied() {
+        Some("-l") => list(),
+        Some("-s") => {
+            words.next();
+            sig = words.next().map_or_else(|| usage(), signal);
+        }
+        Some("--") => {
+            words.next();
+        }
+        Some(word) if word.len() > 1 && word.starts_with('-') => {
+            words.next();
+            sig = signal(&word[1..]);
+        }
+        _ => {}
+    }
+    words.next_if_eq(&"--");
+    if words.peek().is_none() {
+        usage();
+    }
+
+    let mut status = 0;
+    for word in words {
+        // A negative pid names a process group
+        let Ok(pid) = word.parse::<isize>() else {
+            eprintln!("kill: {}: not a process id", word);
+            status = 1;
+            continue;
+        };
+        if let Err(err) = kill(pid, sig) {
+            eprintln!("kill: {}: {}", pid, err);
+            status = 1;
+        }
+    }
+    exit(status)
+}
+
+fn usage() -> ! {
+    eprintln!("usage: kill [-s signal | -signal] pid...");
+    eprintln!("       kill -l");
+    exit(2)
+}
+
+/// The signal numbered or named by `spec`, with or without a SIG prefix
+fn signal(spec: &str) -> usize {
+    let name = spec.strip_prefix("SIG").unwrap_or(spec);
+    let number = match spec.parse::<usize>() {
+        Ok(number) => Some(number).filter(|&number| number <= 31),
+        Err(_) => SIGNALS
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+            .map(|&(_, number)| number),
+    };
+    number.unwrap_or_else(|| {
+        eprintln!("kill: {}: unknown signal", spec);
+        exit(2)
+    })
+}
+
+/// -l: print the signals kill knows by name
+fn list() -> ! {
+    let mut out = BufWriter::new(1);
+    for (name, number) in SIGNALS {
+        let _ = writeln!(out, "{:>2} {}", number, name);
+    }
+    let _ = out.flush();
+    exit(0)
+}
@@ -0,0 +1,138 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use core::str;
+use user_bin::{
+    eprintln, exit, getdents, join_path, stat, BufWriter, Error, Opt, OptError, Options, Stat,
+    MAX_PATH,
+};
+
+struct Flags {
+    /// -l: kind and size before each name
+    long: bool,
+    /// -a: include names starting with `.`, and `.` and `..` themselves
+    all: bool,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let mut flags = Flags {
+        long: false,
+        all: false,
+    };
+    let mut opts = Options::new(argc, argv, "la");
+    for opt in &mut opts {
+        match opt {
+            Ok(Opt::Flag('l')) => flags.long = true,
+            Ok(Opt::Flag('a')) => flags.all = true,
+            Ok(_) => {}
+            Err(err) => usage(err),
+        }
+    }
+
+    let mut out = BufWriter::new(1);
+    let mut status = 0;
+    let count = opts.operands().count();
+    if count == 0 && list(&mut out, ".", &flags, false).is_err() {
+        status = 1;
+    }
+    for (i, path) in opts.operands().enumerate() {
+        // Several directories each get a `path:` heading
+        if count > 1 && i > 0 {
+            let _ = writeln!(out);
+        }
+        if list(&mut out, path, &flags, count > 1).is_err() {
+            status = 1;
+        }
+    }
+    let _ = out.flush();
+    exit(status)
+}
+
+fn usage(err: OptError) -> ! {
+    eprintln!("ls: {}", err);
+    eprintln!("usage: ls [-la] [path...]");
+    exit(2)
+}
+
+/// List the directory `path`, or just `path` itself if it is a file
+fn list(out: &mut BufWriter, path: &str, flags: &Flags, heading: bool) -> Result<(), Error> {
+    let mut info = Stat::default();
+    if let Err(err) = stat(path, &mut info) {
+        let _ = out.flush();
+        eprintln!("ls: cannot access {}: {}", path, err);
+        return Err(err);
+    }
+    if !info.is_dir() {
+        show(out, flags, path, Some(&info));
+        return Ok(());
+    }
+
+    if heading {
+        let _ = writeln!(out, "{}:", path);
+    }
+    if flags.all {
+        for name in [".", ".."] {
+            show_entry(out, flags, path, name);
+        }
+    }
+
+    // getdents records are `[kind, name_len, name...]`
+    let mut cookie = 0;
+    let mut buf = [0u8; 512];
+    loop {
+        let len = match getdents(path, &mut cookie, &mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) => {
+                let _ = out.flush();
+                eprintln!("ls: cannot read {}: {}", path, err);
+                return Err(err);
+            }
+        };
+        let mut at = 0;
+        while at + 2 <= len {
+            let name_len = buf[at + 1] as usize;
+            let name = buf
+                .get(at + 2..at + 2 + name_len)
+                .and_then(|name| str::from_utf8(name).ok());
+            at += 2 + name_len;
+            match name {
+                Some(name) if flags.all || !name.starts_with('.') => {
+                    show_entry(out, flags, path, name)
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Show `name` from directory `dir`, looking it up first for -l
+fn show_entry(out: &mut BufWriter, flags: &Flags, dir: &str, name: &str) {
+    if !flags.long {
+        show(out, flags, name, None);
+        return;
+    }
+    let mut path_buf = [0u8; MAX_PATH];
+    let mut info = Stat::default();
+    let found =
+        join_path(&mut path_buf, dir, name).is_some_and(|path| stat(path, &mut info).is_ok());
+    show(out, flags, name, found.then_some(&info));
+}
+
+/// Print one line for `name`; `info` is None when -l could not stat it
+fn show(out: &mut BufWriter, flags: &Flags, name: &str, info: Option<&Stat>) {
+    if !flags.long {
+        let _ = writeln!(out, "{}", name);
+        return;
+    }
+    let _ = match info {
+        Some(info) => {
+            let kind = if info.is_dir() { 'd' } else { '-' };
+            writeln!(out, "{} {:>8} {}", kind, info.size, name)
+        }
+        None => writeln!(out, "? {:>8} {}", "?", name),
+    };
+}
@@ -0,0 +1,70 @@
+#![no_std]
+#![no_main]
+
+use user_bin::{create_dir, eprintln, exit, stat, Error, Opt, OptError, Options, Stat};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let mut parents = false;
+    let mut opts = Options::new(argc, argv, "p");
+    for opt in &mut opts {
+        match opt {
+            Ok(Opt::Flag('p')) => parents = true,
+            Ok(_) => {}
+            Err(err) => usage(Some(err)),
+        }
+    }
+    if opts.operands().count() == 0 {
+        usage(None);
+    }
+
+    let mut status = 0;
+    for path in opts.operands() {
+        let made = if parents {
+            make_parents(path)
+        } else {
+            create_dir(path).map(|_| ())
+        };
+        if let Err(err) = made {
+            eprintln!("mkdir: {}: {}", path, err);
+            status = 1;
+        }
+    }
+    exit(status)
+}
+
+fn usage(err: Option<OptError>) -> ! {
+    if let Some(err) = err {
+        eprintln!("mkdir: {}", err);
+    }
+    eprintln!("usage: mkdir [-p] directory...");
+    exit(2)
+}
+
+/// -p: create `path` and any missing directories above it; a directory
+/// that already exists is fine
+fn make_parents(path: &str) -> Result<(), Error> {
+    let ends = path
+        .match_indices('/')
+        .map(|(i, _)| i)
+        .chain([path.len()])
+        .filter(|&end| end > 0);
+    for end in ends {
+        let prefix = &path[..end];
+        if prefix.ends_with('/') {
+            continue;
+        }
+        match create_dir(prefix) {
+            Ok(_) => {}
+            Err(Error::Exists) => {
+                let mut info = Stat::default();
+                stat(prefix, &mut info)?;
+                if !info.is_dir() {
+                    return Err(Error::NotDirectory);
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+use user_bin::{
+    args, basename, copy_file, delete_file, eprintln, exit, join_path, rename, stat, Error, Stat,
+    MAX_PATH,
+};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let operands = args(argc, argv).skip(1);
+    let count = operands.len();
+    let Some(target) = operands.clone().last().filter(|_| count >= 2) else {
+        eprintln!("usage: mv source dest");
+        eprintln!("       mv source... directory");
+        exit(2);
+    };
+
+    let mut info = Stat::default();
+    let into_dir = stat(target, &mut info).is_ok() && info.is_dir();
+    if count > 2 && !into_dir {
+        eprintln!("mv: {}: not a directory", target);
+        exit(1);
+    }
+
+    let mut status = 0;
+    // Every operand but the last is a source
+    for source in operands.take(count - 1) {
+        let mut path_buf = [0u8; MAX_PATH];
+        let dest = if into_dir {
+            match join_path(&mut path_buf, target, basename(source)) {
+                Some(dest) => dest,
+                None => {
+                    eprintln!("mv: {}/{}: name too long", target, basename(source));
+                    status = 1;
+                    continue;
+                }
+            }
+        } else {
+            target
+        };
+        if let Err(err) = move_file(source, dest) {
+            eprintln!("mv: {}: {}", source, err);
+            status = 1;
+        }
+    }
+    exit(status)
+}
+
+/// Rename `source` to `dest`, or copy it and delete the original when
+/// they are on different filesystems. Directories cannot cross.
+fn move_file(source: &str, dest: &str) -> Result<(), Error> {
+    match rename(source, dest) {
+        Err(Error::CrossDevice) => {
+            copy_file(source, dest)?;
+            delete_file(source).map(|_| ())
+        }
+        result => result.map(|_| ()),
+    }
+}
@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use user_bin::{eprintln, exit, procinfo, BufWriter, ProcRecord};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(_argc: usize, _argv: *const *const u8) -> ! {
+    let mut out = BufWriter::new(1);
+    let _ = writeln!(out, "  PID  PPID STAT CMD");
+
+    let mut buf = [0u8; 512];
+    let mut cookie = 0;
+    loop {
+        let n = match procinfo(&mut cookie, &mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) => {
+                let _ = out.flush();
+                eprintln!("ps: cannot read process table: {}", err);
+                exit(1);
+            }
+        };
+
+        let mut offset = 0;
+        while let Some((record, len)) = ProcRecord::parse(&buf[offset..n]) {
+            print_record(&mut out, &record);
+            offset += len;
+        }
+    }
+
+    let _ = out.flush();
+    exit(0);
+}
+
+fn print_record(out: &mut BufWriter, record: &ProcRecord) {
+    let _ = write!(
+        out,
+        "{:>5} {:>5} {}    ",
+        record.pid, record.ppid, record.state as char
+    );
+    // Processes started without arguments fall back to their path
+    let cmd = if record.args.is_empty() {
+        record.path
+    } else {
+        record.args
+    };
+    let _ = out.write_all(cmd);
+    let _ = out.write_all(b"\n");
+}
@@ -0,0 +1,148 @@
+#![no_std]
+#![no_main]
+
+use core::str;
+use user_bin::{
+    basename, canonicalize, delete_dir, delete_file, eprintln, exit, getdents, join_path, mounts,
+    stat, Error, Opt, OptError, Options, Stat, MAX_PATH,
+};
+
+struct Flags {
+    /// -r: remove directories and everything in them
+    recursive: bool,
+    /// -f: say nothing about files that do not exist
+    force: bool,
+    /// -m: let -r go on into filesystems mounted below its operands
+    cross_mounts: bool,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let mut flags = Flags {
+        recursive: false,
+        force: false,
+        cross_mounts: false,
+    };
+    let mut opts = Options::new(argc, argv, "rfm");
+    for opt in &mut opts {
+        match opt {
+            Ok(Opt::Flag('r')) => flags.recursive = true,
+            Ok(Opt::Flag('f')) => flags.force = true,
+            Ok(Opt::Flag('m')) => flags.cross_mounts = true,
+            Ok(_) => {}
+            Err(err) => usage(Some(err)),
+        }
+    }
+    if opts.operands().count() == 0 && !flags.force {
+        usage(None);
+    }
+
+    // Without -m, -r stops short of any mount point below an operand
+    let mut mount_buf = [0u8; 1024];
+    let mount_table = if flags.recursive && !flags.cross_mounts {
+        match mounts(&mut mount_buf) {
+            Ok(len) => str::from_utf8(&mount_buf[..len]).unwrap_or(""),
+            Err(err) => {
+                eprintln!("rm: cannot list mounts: {}", err);
+                exit(1);
+            }
+        }
+    } else {
+        ""
+    };
+
+    let mut status = 0;
+    for path in opts.operands() {
+        let mut canonical_buf = [0u8; MAX_PATH];
+        let canonical = match canonicalize(&mut canonical_buf, path) {
+            Ok(canonical) => canonical,
+            Err(err) => {
+                eprintln!("rm: {}: {}", path, err);
+                status = 1;
+                continue;
+            }
+        };
+        if canonical == "/" || matches!(basename(path), "." | "..") {
+            eprintln!("rm: refusing to remove {}", path);
+            status = 1;
+            continue;
+        }
+        if let Some(point) = mount_under(mount_table, canonical) {
+            eprintln!("rm: {}: {} is a mount point; use -m to cross it", path, point);
+            status = 1;
+            continue;
+        }
+        match remove(path, flags.recursive) {
+            Ok(()) => {}
+            Err(Error::NotFound) if flags.force => {}
+            Err(err) => {
+                eprintln!("rm: {}: {}", path, err);
+                status = 1;
+            }
+        }
+    }
+    exit(status)
+}
+
+fn usage(err: Option<OptError>) -> ! {
+    if let Some(err) = err {
+        eprintln!("rm: {}", err);
+    }
+    eprintln!("usage: rm [-rfm] file...");
+    exit(2)
+}
+
+/// The first mount point at or below `dir` in `table`, the text from
+/// `mounts` whose lines read "device on /point", maybe ending in " (ro)"
+fn mount_under<'a>(table: &'a str, dir: &str) -> Option<&'a str> {
+    table
+        .lines()
+        .filter_map(|line| line.split_once(" on "))
+        .map(|(_, point)| point.strip_suffix(" (ro)").unwrap_or(point))
+        .find(|point| {
+            point
+                .strip_prefix(dir)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+}
+
+/// Delete the file at `path`, or with `recursive` the directory and
+/// everything below it
+fn remove(path: &str, recursive: bool) -> Result<(), Error> {
+    let mut info = Stat::default();
+    stat(path, &mut info)?;
+    if !info.is_dir() {
+        return delete_file(path).map(|_| ());
+    }
+    if !recursive {
+        return Err(Error::IsDirectory);
+    }
+    empty_dir(path)?;
+    delete_dir(path).map(|_| ())
+}
+
+/// Remove everything in the directory `dir`. Entries shift as they are
+/// deleted, so each pass starts again from the first batch.
+fn empty_dir(dir: &str) -> Result<(), Error> {
+    let mut buf = [0u8; 512];
+    loop {
+        let mut cookie = 0;
+        let len = getdents(dir, &mut cookie, &mut buf)?;
+        if len == 0 {
+            return Ok(());
+        }
+        // getdents records are `[kind, name_len, name...]`
+        let mut at = 0;
+        while at + 2 <= len {
+            let name_len = buf[at + 1] as usize;
+            let name = buf
+                .get(at + 2..at + 2 + name_len)
+                .and_then(|name| str::from_utf8(name).ok())
+                .ok_or(Error::InvalidArgument)?;
+            at += 2 + name_len;
+            let mut path_buf = [0u8; MAX_PATH];
+            let path = join_path(&mut path_buf, dir, name).ok_or(Error::NameTooLong)?;
+            remove(path, true)?;
+        }
+    }
+}
@@ -0,0 +1,22 @@
+#![no_std]
+#![no_main]
+
+use user_bin::{args, delete_dir, eprintln, exit};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let dirs = args(argc, argv).skip(1);
+    if dirs.len() == 0 {
+        eprintln!("usage: rmdir directory...");
+        exit(2);
+    }
+
+    let mut status = 0;
+    for path in dirs {
+        if let Err(err) = delete_dir(path) {
+            eprintln!("rmdir: {}: {}", path, err);
+            status = 1;
+        }
+    }
+    exit(status)
+}
@@ -1,46 +1,372 @@
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
 use core::str;
 use user_bin::{
-    close, dup2, exit, open, pipe, read, spawn, wait, write, O_APPEND, O_CREATE, O_READ,
-    O_WRITE,
+    args, chdir, close, console_log, env_init, eprintln, exit, fcntl, getcwd, getdents, getenv,
+    join_path, kill, klog_clear, klog_level, klog_read, klog_subsystem, maxprocs, open, pipe,
+    print, println, procinfo, schedstat, setenv, signal, spawn_with, stat, tcsetpgrp, unsetenv,
+    waitpid, wexitstatus, wifstopped, write, wstopsig, BufReader, Error, ProcRecord, SchedStats,
+    SpawnFileActions, Stat, CONSOLE_QUERY, CONSOLE_UART, CONSOLE_UART1, CONSOLE_VIRTIO, FD_CLOEXEC,
+    F_GETPIPE_SZ, F_SETFD, F_SETPIPE_SZ, KLOG_DEBUG, KLOG_LEVELS, KLOG_QUERY, KLOG_SUBSYSTEMS,
+    MAX_PATH, O_APPEND, O_CLOEXEC, O_CREATE, O_READ, O_TRUNC, O_WRITE, SIGCONT, SIGINT, SIGSTOP,
+    SIGTSTP, SIG_IGN, SPAWN_NEW_PGRP, WNOHANG, WUNTRACED,
 };
 
+#[path = "../sh_list.rs"]
+mod sh_list;
+
+use sh_list::{split_list, Then};
+
 const MAX_LINE: usize = 256;
-const MAX_ARGS: usize = 8;
-const PROMPT: &[u8] = b"sh> ";
+/// Arguments one command can have, the most spawn passes on
+const MAX_ARGS: usize = 16;
+/// Commands in one pipeline
+const MAX_STAGES: usize = 8;
+const MAX_JOBS: usize = 8;
+const JOB_TEXT: usize = 64;
+/// Lines kept for up/down recall
+const HISTORY_LEN: usize = 16;
+/// Prompt used when PS1 is unset
+const PROMPT: &str = "sh> ";
+/// Search path used when PATH is unset
+const DEFAULT_PATH: &str = "/bin";
+
+/// A pipeline started by the shell; all its processes share one group
+#[derive(Clone, Copy)]
+struct Job {
+    pgid: isize,
+    pids: [isize; 8],
+    /// Processes not reaped yet (-1 once reaped)
+    live: usize,
+    stopped: bool,
+    text: [u8; JOB_TEXT],
+    text_len: usize,
+}
+
+impl Job {
+    const fn empty() -> Self {
+        Self {
+            pgid: -1,
+            pids: [-1; 8],
+            live: 0,
+            stopped: false,
+            text: [0; JOB_TEXT],
+            text_len: 0,
+        }
+    }
+
+    fn text(&self) -> &[u8] {
+        &self.text[..self.text_len]
+    }
+}
+
+/// Recently entered lines, for recall with the arrow keys
+struct History {
+    lines: [[u8; MAX_LINE]; HISTORY_LEN],
+    lens: [usize; HISTORY_LEN],
+    /// Lines ever added; the newest is at `(added - 1) % HISTORY_LEN`
+    added: usize,
+}
+
+impl History {
+    const fn new() -> Self {
+        Self {
+            lines: [[0; MAX_LINE]; HISTORY_LEN],
+            lens: [0; HISTORY_LEN],
+            added: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.added.min(HISTORY_LEN)
+    }
+
+    /// The line `back` entries before the newest (0 is the newest)
+    fn get(&self, back: usize) -> &[u8] {
+        let slot = (self.added - 1 - back) % HISTORY_LEN;
+        &self.lines[slot][..self.lens[slot]]
+    }
+
+    /// Remember `line`, unless it is blank or repeats the newest entry
+    fn push(&mut self, line: &[u8]) {
+        if line.iter().all(|&b| is_space(b)) || (self.added > 0 && self.get(0) == line) {
+            return;
+        }
+        let slot = self.added % HISTORY_LEN;
+        let len = line.len().min(MAX_LINE);
+        self.lines[slot][..len].copy_from_slice(&line[..len]);
+        self.lens[slot] = len;
+        self.added += 1;
+    }
+}
+
+/// Keys `read_line` understands, decoded from bytes and ANSI escapes
+enum Key {
+    Char(u8),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+}
+
+/// Progress through an escape sequence: ESC, then `[` or `O`, then an
+/// optional number and a final byte (`ESC [ A` is up, `ESC [ 3 ~` delete)
+#[derive(Clone, Copy)]
+enum Escape {
+    None,
+    Started,
+    Csi(u8),
+}
+
+impl Escape {
+    /// Feed one input byte; returns the key it completes, if any
+    fn feed(&mut self, b: u8) -> Option<Key> {
+        let (next, key) = match (*self, b) {
+            (Escape::None, 0x1b) => (Escape::Started, None),
+            (Escape::None, b'\r' | b'\n') => (Escape::None, Some(Key::Enter)),
+            (Escape::None, 0x08 | 0x7f) => (Escape::None, Some(Key::Backspace)),
+            // Ctrl-A and Ctrl-E, as in emacs and readline
+            (Escape::None, 0x01) => (Escape::None, Some(Key::Home)),
+            (Escape::None, 0x05) => (Escape::None, Some(Key::End)),
+            (Escape::None, b) if b >= 0x20 || b == b'\t' => (Escape::None, Some(Key::Char(b))),
+            (Escape::None, _) => (Escape::None, None),
+            (Escape::Started, b'[' | b'O') => (Escape::Csi(0), None),
+            (Escape::Started, _) => (Escape::None, None),
+            (Escape::Csi(n), b'0'..=b'9') => {
+                let n = n.saturating_mul(10).saturating_add(b - b'0');
+                (Escape::Csi(n), None)
+            }
+            (Escape::Csi(n), b) => {
+                let key = match (b, n) {
+                    (b'A', _) => Some(Key::Up),
+                    (b'B', _) => Some(Key::Down),
+                    (b'C', _) => Some(Key::Right),
+                    (b'D', _) => Some(Key::Left),
+                    (b'H', _) | (b'~', 1 | 7) => Some(Key::Home),
+                    (b'F', _) | (b'~', 4 | 8) => Some(Key::End),
+                    (b'~', 3) => Some(Key::Delete),
+                    _ => None,
+                };
+                (Escape::None, key)
+            }
+        };
+        *self = next;
+        key
+    }
+}
+
+/// Shell variables. Exported ones live in the environment, which
+/// spawn hands to every child; the rest stay in the shell.
+struct Vars {
+    /// Variables set with NAME=value and not exported
+    local: Vec<(String, String)>,
+    /// Exit status of the last foreground job, for `$?`
+    status: isize,
+    /// `set -e`: exit as soon as a command fails
+    errexit: bool,
+}
+
+impl Vars {
+    const fn new() -> Self {
+        Self {
+            local: Vec::new(),
+            status: 0,
+            errexit: false,
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        match self.local.iter().find(|(known, _)| known == name) {
+            Some((_, value)) => Some(value),
+            None => getenv(name),
+        }
+    }
+
+    /// NAME=value: an exported variable stays exported
+    fn set(&mut self, name: &str, value: &str) -> bool {
+        if getenv(name).is_some() {
+            return setenv(name, value);
+        }
+        match self.local.iter_mut().find(|(known, _)| known == name) {
+            Some((_, old)) => *old = String::from(value),
+            None => self.local.push((String::from(name), String::from(value))),
+        }
+        true
+    }
 
-struct Redir<'a> {
-    path: &'a str,
+    /// `export NAME[=value]`: move NAME into the environment
+    fn export(&mut self, name: &str, value: Option<&str>) -> bool {
+        let index = self.local.iter().position(|(known, _)| known == name);
+        let value = match (value, index) {
+            (Some(value), _) => value,
+            (None, Some(index)) => &self.local[index].1,
+            // Exporting an unset name is allowed and does nothing yet
+            (None, None) => return true,
+        };
+        if !setenv(name, value) {
+            return false;
+        }
+        if let Some(index) = index {
+            self.local.swap_remove(index);
+        }
+        true
+    }
+
+    fn unset(&mut self, name: &str) {
+        self.local.retain(|(known, _)| known != name);
+        unsetenv(name);
+    }
+
+    /// Replace `$NAME`, `${NAME}` and `$?` in `line`. Unset names expand
+    /// to nothing; a `$` not followed by a name is kept.
+    fn expand(&self, line: &str) -> String {
+        let mut out = String::with_capacity(line.len());
+        let mut rest = line;
+        while let Some(dollar) = rest.find('$') {
+            out.push_str(&rest[..dollar]);
+            let used = self.expand_one(&rest[dollar + 1..], &mut out);
+            if used == 0 {
+                out.push('$');
+            }
+            rest = &rest[dollar + 1 + used..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// Expand the variable named at the start of `text`, which follows a
+    /// `$`, onto `out`. Returns the bytes of `text` used, 0 if it names
+    /// no variable.
+    fn expand_one(&self, text: &str, out: &mut String) -> usize {
+        if text.starts_with('?') {
+            let _ = write!(out, "{}", self.status);
+            return 1;
+        }
+        let (name, used) = match text.strip_prefix('{') {
+            Some(braced) => match braced.split_once('}') {
+                Some((name, _)) => (name, name.len() + 2),
+                None => return 0,
+            },
+            None => {
+                let end = text
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(text.len());
+                (&text[..end], end)
+            }
+        };
+        if !is_name(name) {
+            return 0;
+        }
+        out.push_str(self.get(name).unwrap_or(""));
+        used
+    }
+}
+
+/// Whether `name` can name a variable: a letter or `_`, then letters,
+/// digits and `_`
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+struct Redir {
+    path: String,
     append: bool,
 }
 
-struct Command<'a> {
-    args: [&'a str; MAX_ARGS],
-    argc: usize,
-    stdin: Option<&'a str>,
-    stdout: Option<Redir<'a>>,
+/// Where a command reads its input from
+enum Input {
+    /// `< path`
+    File(String),
+    /// `<<< word`: the word and a newline
+    Text(String),
 }
 
-impl<'a> Command<'a> {
+/// Where a command's errors go
+enum Stderr {
+    /// `2> path` or `2>> path`
+    File(Redir),
+    /// `2>&1`: wherever its output goes
+    Stdout,
+}
+
+struct Command {
+    args: Vec<String>,
+    stdin: Option<Input>,
+    stdout: Option<Redir>,
+    stderr: Option<Stderr>,
+}
+
+impl Command {
     const fn new() -> Self {
         Self {
-            args: [""; MAX_ARGS],
-            argc: 0,
+            args: Vec::new(),
             stdin: None,
             stdout: None,
+            stderr: None,
         }
     }
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn _start(_argc: usize, _argv: *const *const u8) -> ! {
+pub extern "C" fn _start(argc: usize, argv: *const *const u8, envp: *const *const u8) -> ! {
+    env_init(envp);
     let mut line_buf = [0u8; MAX_LINE];
+    let mut history = History::new();
+    let mut jobs = [Job::empty(); MAX_JOBS];
+    let mut stdin = BufReader::new(0);
+    let mut vars = Vars::new();
+
+    // `sh FILE`, which is also how a #!/bin/sh script starts, runs the
+    // commands in FILE instead of prompting
+    let mut script = (argc > 1).then(|| {
+        let path = args(argc, argv).get(1).unwrap_or("");
+        match open(path, O_READ | O_CLOEXEC) {
+            Ok(fd) => BufReader::new(fd),
+            Err(_) => {
+                eprintln!("sh: cannot open {}", path);
+                exit(127);
+            }
+        }
+    });
+
+    // Ctrl-C/Ctrl-Z at the prompt must not take the shell down; jobs
+    // start with the default dispositions
+    if script.is_none() {
+        let _ = signal(SIGINT, SIG_IGN);
+        let _ = signal(SIGTSTP, SIG_IGN);
+    }
 
     loop {
-        write(1, PROMPT);
-        let line_len = read_line(&mut line_buf);
+        reap_jobs(&mut jobs);
+        let line_len = match script.as_mut() {
+            Some(input) => match input.read_line(&mut line_buf) {
+                Ok(Some(len)) => len,
+                Ok(None) | Err(_) => exit(vars.status),
+            },
+            None => {
+                print!("{}", getenv("PS1").unwrap_or(PROMPT));
+                let len = read_line(&mut stdin, &mut line_buf, &history);
+                if len > 0 {
+                    history.push(&line_buf[..len]);
+                }
+                len
+            }
+        };
         if line_len == 0 {
             continue;
         }
@@ -48,67 +374,422 @@ pub extern "C" fn _start(_argc: usize, _argv: *const *const u8) -> ! {
         let line = match str::from_utf8(&line_buf[..line_len]) {
             Ok(s) => s.trim(),
             Err(_) => {
-                write(2, b"invalid utf-8 input\n");
+                eprintln!("invalid utf-8 input");
                 continue;
             }
         };
 
-        if line.is_empty() {
-            continue;
+        run_line(line, &mut vars, &mut jobs);
+    }
+}
+
+/// Run one line of input: a list of commands joined by `;`, `&&` and
+/// `||`. Comments, including a script's #! line, are skipped.
+fn run_line(line: &str, vars: &mut Vars, jobs: &mut [Job; MAX_JOBS]) {
+    if line.is_empty() || line.starts_with('#') {
+        return;
+    }
+    let list = match split_list(line) {
+        Ok(list) => list,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            vars.status = 2;
+            return;
         }
-        if line == "exit" {
-            exit(0);
+    };
+    let mut run_next = true;
+    // Whether the last command of the list ran, rather than being skipped
+    // by && or ||
+    let mut ran = false;
+    for (command, then) in list {
+        ran = run_next;
+        if run_next {
+            run_command(command, vars, jobs);
         }
+        run_next = match then {
+            Then::Always => true,
+            Then::IfOk => vars.status == 0,
+            Then::IfFailed => vars.status != 0,
+        };
+    }
+    // set -e: a failure only && or || was there to catch does not count
+    if vars.errexit && ran && vars.status != 0 {
+        exit(vars.status);
+    }
+}
 
-        let mut cmds = [Command::new(), Command::new(), Command::new(), Command::new(), Command::new(), Command::new(), Command::new(), Command::new()];
-        let parsed = match parse_commands(line, &mut cmds) {
-            Ok(n) => n,
-            Err(msg) => {
-                write(2, msg.as_bytes());
-                write(2, b"\n");
-                continue;
+/// Run one command of a list: a builtin, an assignment or a pipeline.
+/// Its exit status is left in `vars.status`.
+fn run_command(line: &str, vars: &mut Vars, jobs: &mut [Job; MAX_JOBS]) {
+    // NAME=value on its own sets a variable
+    if let Some((name, value)) = line.split_once('=')
+        && is_name(name)
+    {
+        let Some(value) = parse_value(value, vars) else {
+            eprintln!("sh: {}: bad assignment", name);
+            vars.status = 2;
+            return;
+        };
+        vars.status = if vars.set(name, &value) { 0 } else { 1 };
+        if vars.status != 0 {
+            eprintln!("sh: {}: environment full", name);
+        }
+        return;
+    }
+    if let Some(assignment) = line.strip_prefix("export ") {
+        let assignment = assignment.trim();
+        let ok = match assignment.split_once('=') {
+            Some((name, value)) => match parse_value(value, vars) {
+                Some(value) => is_name(name) && vars.export(name, Some(&value)),
+                None => false,
+            },
+            None => is_name(assignment) && vars.export(assignment, None),
+        };
+        if !ok {
+            eprintln!("usage: export NAME[=VALUE]");
+        }
+        vars.status = if ok { 0 } else { 1 };
+        return;
+    }
+    // Builtins see the line with variables expanded; pipelines expand
+    // word by word, leaving quoted text alone
+    let unexpanded = line;
+    let expanded = vars.expand(line);
+    let line = expanded.as_str();
+    // exit [n]: without n, exit with the last command's status
+    if line == "exit" || line.starts_with("exit ") {
+        match line["exit".len()..].trim() {
+            "" => exit(vars.status),
+            arg => match arg.parse::<isize>() {
+                Ok(code) => exit(code),
+                Err(_) => {
+                    eprintln!("usage: exit [n]");
+                    vars.status = 2;
+                    return;
+                }
+            },
+        }
+    }
+    if unexpanded == "cd" || unexpanded.starts_with("cd ") {
+        vars.status = change_dir(unexpanded["cd".len()..].trim(), vars);
+        return;
+    }
+    if unexpanded == "." || unexpanded.starts_with(". ") {
+        match parse_value(unexpanded[1..].trim(), vars) {
+            Some(path) if !path.is_empty() => source(&path, vars, jobs),
+            _ => {
+                eprintln!("usage: . file");
+                vars.status = 2;
+            }
+        }
+        return;
+    }
+    if let Some(option) = line.strip_prefix("set ") {
+        vars.status = match option.trim() {
+            "-e" => {
+                vars.errexit = true;
+                0
+            }
+            "+e" => {
+                vars.errexit = false;
+                0
+            }
+            _ => {
+                eprintln!("usage: set -e|+e");
+                2
             }
         };
+        return;
+    }
+    if line == "pwd" {
+        let mut buf = [0u8; MAX_PATH];
+        vars.status = match working_dir(&mut buf) {
+            Ok(dir) => {
+                println!("{}", dir);
+                0
+            }
+            Err(err) => {
+                eprintln!("pwd: {}", err);
+                1
+            }
+        };
+        return;
+    }
+    if let Some(name) = line.strip_prefix("unset ") {
+        vars.unset(name.trim());
+        vars.status = 0;
+        return;
+    }
+    if let Some((name, arg)) = line
+        .split_once(' ')
+        .filter(|(name, _)| *name == "stop" || *name == "cont")
+    {
+        let sig = if name == "stop" { SIGSTOP } else { SIGCONT };
+        match arg.trim().parse::<isize>() {
+            Ok(pid) if pid > 0 => {
+                if kill(pid, sig).is_err() {
+                    eprintln!("no such process");
+                }
+            }
+            _ => {
+                eprintln!("usage: stop|cont <pid>");
+            }
+        }
+        return;
+    }
+    if line == "klog" || line.starts_with("klog ") {
+        kernel_log(line["klog".len()..].trim());
+        return;
+    }
+    if line == "dmesg" || line.starts_with("dmesg ") {
+        dmesg(line["dmesg".len()..].trim());
+        return;
+    }
+    if line == "log" || line.starts_with("log ") {
+        log_levels(line["log".len()..].trim());
+        return;
+    }
+    if line == "maxprocs" || line.starts_with("maxprocs ") {
+        vars.status = max_processes(line["maxprocs".len()..].trim());
+        return;
+    }
+    if line == "schedstat" {
+        sched_stats();
+        return;
+    }
+    if line == "jobs" {
+        list_jobs(jobs);
+        return;
+    }
+    if line == "fg" || line.starts_with("fg ") || line == "bg" || line.starts_with("bg ") {
+        match resume_job(jobs, line) {
+            Ok(status) => vars.status = status,
+            Err(msg) => {
+                eprintln!("{}", msg);
+                vars.status = 1;
+            }
+        }
+        return;
+    }
+
+    // A trailing & runs the pipeline in the background
+    let (line, background) = match unexpanded.strip_suffix('&') {
+        Some(rest) => (rest.trim_end(), true),
+        None => (unexpanded, false),
+    };
+    if line.is_empty() {
+        return;
+    }
+
+    let cmds = match parse_commands(line, vars) {
+        Ok(cmds) => cmds,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            vars.status = 2;
+            return;
+        }
+    };
 
-        if let Err(msg) = run_pipeline(&cmds[..parsed]) {
-            write(2, msg.as_bytes());
-            write(2, b"\n");
+    let search = vars.get("PATH").unwrap_or(DEFAULT_PATH);
+    match run_pipeline(&cmds, line, search, background, jobs) {
+        Ok(status) => vars.status = status,
+        Err(msg) => {
+            // A half-started foreground job may hold the console
+            let _ = tcsetpgrp(0);
+            eprintln!("{}", msg);
+            vars.status = 127;
         }
     }
 }
 
-fn read_line(buf: &mut [u8]) -> usize {
-    let mut idx = 0;
-    let mut byte_buf = [0u8; 1];
-
-    loop {
-        let n = read(0, &mut byte_buf);
-        if n <= 0 {
-            continue;
+/// `. file`: run the lines of `file` in this shell, so its variables
+/// and directory changes stay behind
+fn source(path: &str, vars: &mut Vars, jobs: &mut [Job; MAX_JOBS]) {
+    let fd = match open(path, O_READ | O_CLOEXEC) {
+        Ok(fd) => fd,
+        Err(err) => {
+            eprintln!(".: {}: {}", path, err);
+            vars.status = 1;
+            return;
         }
-        let b = byte_buf[0];
-        if b == b'\r' || b == b'\n' {
-            write(1, b"\n");
-            break;
+    };
+    vars.status = 0;
+    let mut input = BufReader::new(fd);
+    let mut line_buf = [0u8; MAX_LINE];
+    while let Ok(Some(len)) = input.read_line(&mut line_buf) {
+        match str::from_utf8(&line_buf[..len]) {
+            Ok(line) => run_line(line.trim(), vars, jobs),
+            Err(_) => {
+                eprintln!("invalid utf-8 input");
+            }
         }
-        if b == 0x08 || b == 0x7f {
-            if idx > 0 {
-                idx -= 1;
-                write(1, b"\x08 \x08");
+    }
+    let _ = close(fd);
+}
+
+/// `cd [dir]`: move to `dir`, to HOME without one, or back to the
+/// previous directory for `cd -`. Returns the exit status.
+fn change_dir(arg: &str, vars: &mut Vars) -> isize {
+    let Some(arg) = parse_value(arg, vars) else {
+        eprintln!("usage: cd [dir | -]");
+        return 2;
+    };
+    let target = match arg.as_str() {
+        "" => String::from(vars.get("HOME").unwrap_or("/")),
+        "-" => match vars.get("OLDPWD") {
+            Some(dir) => String::from(dir),
+            None => {
+                eprintln!("cd: OLDPWD not set");
+                return 1;
             }
-            continue;
+        },
+        _ => arg.clone(),
+    };
+    let mut buf = [0u8; MAX_PATH];
+    let previous = working_dir(&mut buf).map(String::from);
+    if let Err(err) = chdir(&target) {
+        eprintln!("cd: {}: {}", target, err);
+        return 1;
+    }
+    if let Ok(previous) = previous {
+        vars.set("OLDPWD", &previous);
+    }
+    if let Ok(dir) = working_dir(&mut buf) {
+        vars.set("PWD", dir);
+        if arg == "-" {
+            println!("{}", dir);
         }
-        if idx < buf.len() {
-            buf[idx] = b;
-            idx += 1;
-            write(1, &byte_buf);
+    }
+    0
+}
+
+/// The working directory, read into `buf`
+fn working_dir(buf: &mut [u8]) -> Result<&str, Error> {
+    let len = getcwd(buf)?;
+    str::from_utf8(&buf[..len]).map_err(|_| Error::InvalidArgument)
+}
+
+/// Read one line from the console, echoing it. Left/right, Home/End and
+/// Delete edit within the line; up/down step through `history`.
+fn read_line(input: &mut BufReader, buf: &mut [u8], history: &History) -> usize {
+    let mut len = 0;
+    let mut cursor = 0;
+    // How many entries back up/down has stepped; 0 is the line being typed
+    let mut recalled = 0;
+    let mut escape = Escape::None;
+
+    loop {
+        let Ok(Some(byte)) = input.read_byte() else {
+            continue;
+        };
+        let Some(key) = escape.feed(byte) else {
+            continue;
+        };
+        match key {
+            Key::Enter => {
+                put(b"\n");
+                break;
+            }
+            Key::Char(b) => {
+                if len < buf.len() {
+                    buf.copy_within(cursor..len, cursor + 1);
+                    buf[cursor] = b;
+                    len += 1;
+                    put(&buf[cursor..len]);
+                    cursor += 1;
+                    move_left(len - cursor);
+                }
+            }
+            Key::Backspace => {
+                if cursor > 0 {
+                    buf.copy_within(cursor..len, cursor - 1);
+                    cursor -= 1;
+                    len -= 1;
+                    move_left(1);
+                    redraw_tail(&buf[cursor..len], 1);
+                }
+            }
+            Key::Delete => {
+                if cursor < len {
+                    buf.copy_within(cursor + 1..len, cursor);
+                    len -= 1;
+                    redraw_tail(&buf[cursor..len], 1);
+                }
+            }
+            Key::Left => {
+                if cursor > 0 {
+                    cursor -= 1;
+                    move_left(1);
+                }
+            }
+            Key::Right => {
+                if cursor < len {
+                    put(&buf[cursor..cursor + 1]);
+                    cursor += 1;
+                }
+            }
+            Key::Home => {
+                move_left(cursor);
+                cursor = 0;
+            }
+            Key::End => {
+                put(&buf[cursor..len]);
+                cursor = len;
+            }
+            Key::Up | Key::Down => {
+                let wanted = match key {
+                    Key::Up => (recalled + 1).min(history.len()),
+                    _ => recalled.saturating_sub(1),
+                };
+                if wanted == recalled {
+                    continue;
+                }
+                recalled = wanted;
+                let line = if recalled == 0 {
+                    &[][..]
+                } else {
+                    history.get(recalled - 1)
+                };
+                // Replace the whole line on screen
+                move_left(cursor);
+                let old_len = len;
+                len = line.len().min(buf.len());
+                buf[..len].copy_from_slice(&line[..len]);
+                cursor = len;
+                put(&buf[..len]);
+                redraw_tail(&[], old_len.saturating_sub(len));
+            }
         }
     }
-    idx
+    len
+}
+
+/// Write raw bytes to the console; a failed echo has nowhere to be reported
+fn put(bytes: &[u8]) {
+    let _ = write(1, bytes);
+}
+
+/// Move the terminal cursor `count` columns left
+fn move_left(count: usize) {
+    const BACKSPACES: [u8; MAX_LINE] = [0x08; MAX_LINE];
+    if count > 0 {
+        put(&BACKSPACES[..count.min(MAX_LINE)]);
+    }
 }
 
-fn parse_commands<'a>(line: &'a str, cmds: &mut [Command<'a>]) -> Result<usize, &'static str> {
-    let mut cmd_idx = 0;
+/// Print `tail` from the cursor, blank `erased` stale columns after it,
+/// and put the cursor back where it was
+fn redraw_tail(tail: &[u8], erased: usize) {
+    const SPACES: [u8; MAX_LINE] = [b' '; MAX_LINE];
+    put(tail);
+    put(&SPACES[..erased.min(MAX_LINE)]);
+    move_left(tail.len() + erased);
+}
+
+/// Split `line` into the stages of a pipeline, with quotes removed and
+/// variables expanded
+fn parse_commands(line: &str, vars: &Vars) -> Result<Vec<Command>, &'static str> {
+    let mut cmds = Vec::new();
     let mut cur = Command::new();
     let bytes = line.as_bytes();
     let mut i = 0;
@@ -123,24 +804,28 @@ fn parse_commands<'a>(line: &'a str, cmds: &mut [Command<'a>]) -> Result<usize,
 
         match bytes[i] {
             b'|' => {
-                if cur.argc == 0 {
+                if cur.args.is_empty() {
                     return Err("syntax error: empty command before |");
                 }
-                if cmd_idx >= cmds.len() {
+                if cmds.len() >= MAX_STAGES {
                     return Err("too many pipeline stages");
                 }
-                cmds[cmd_idx] = cur;
-                cmd_idx += 1;
+                cmds.push(cur);
                 cur = Command::new();
                 i += 1;
             }
             b'<' => {
-                i += 1;
+                let here = line[i..].starts_with("<<<");
+                i += if here { 3 } else { 1 };
                 while i < bytes.len() && is_space(bytes[i]) {
                     i += 1;
                 }
-                let (token, next) = parse_token(line, i)?;
-                cur.stdin = Some(token);
+                let (word, next) = parse_word(line, i, vars)?;
+                cur.stdin = Some(if here {
+                    Input::Text(word.text)
+                } else {
+                    Input::File(word.text)
+                });
                 i = next;
             }
             b'>' => {
@@ -154,270 +839,817 @@ fn parse_commands<'a>(line: &'a str, cmds: &mut [Command<'a>]) -> Result<usize,
                 while i < bytes.len() && is_space(bytes[i]) {
                     i += 1;
                 }
-                let (token, next) = parse_token(line, i)?;
-                cur.stdout = Some(Redir { path: token, append });
+                let (word, next) = parse_word(line, i, vars)?;
+                cur.stdout = Some(Redir {
+                    path: word.text,
+                    append,
+                });
                 i = next;
             }
+            // 2> and 2>> send stderr to a file, 2>&1 to wherever stdout goes
+            b'2' if bytes.get(i + 1) == Some(&b'>') => {
+                i += 2;
+                if line[i..].starts_with("&1") {
+                    cur.stderr = Some(Stderr::Stdout);
+                    i += 2;
+                } else {
+                    let append = bytes.get(i) == Some(&b'>');
+                    if append {
+                        i += 1;
+                    }
+                    while i < bytes.len() && is_space(bytes[i]) {
+                        i += 1;
+                    }
+                    let (word, next) = parse_word(line, i, vars)?;
+                    cur.stderr = Some(Stderr::File(Redir {
+                        path: word.text,
+                        append,
+                    }));
+                    i = next;
+                }
+            }
             _ => {
-                let (token, next) = parse_token(line, i)?;
-                if cur.argc >= MAX_ARGS {
+                let (word, next) = parse_word(line, i, vars)?;
+                // A pattern that matches nothing is passed on as typed
+                let matched = if word.wild {
+                    glob(&word.pattern)
+                } else {
+                    Vec::new()
+                };
+                if matched.is_empty() {
+                    cur.args.push(word.text);
+                } else {
+                    cur.args.extend(matched);
+                }
+                if cur.args.len() > MAX_ARGS {
                     return Err("too many args");
                 }
-                cur.args[cur.argc] = token;
-                cur.argc += 1;
                 i = next;
             }
         }
     }
 
-    if cur.argc == 0 {
+    if cur.args.is_empty() {
         return Err("empty command");
     }
-    if cmd_idx >= cmds.len() {
+    if cmds.len() >= MAX_STAGES {
         return Err("too many pipeline stages");
     }
-    cmds[cmd_idx] = cur;
-    Ok(cmd_idx + 1)
+    cmds.push(cur);
+    Ok(cmds)
+}
+
+/// A word after quote removal and variable expansion
+struct Word {
+    text: String,
+    /// `text` as a glob pattern, with every `*`, `?` and `\` that did not
+    /// come from an unquoted wildcard escaped by a `\`
+    pattern: String,
+    /// An unquoted `*` or `?` appeared, so the word is globbed
+    wild: bool,
+}
+
+impl Word {
+    /// Add text that matches only itself
+    fn push_literal(&mut self, text: &str) {
+        self.text.push_str(text);
+        for c in text.chars() {
+            if matches!(c, '*' | '?' | '\\') {
+                self.pattern.push('\\');
+            }
+            self.pattern.push(c);
+        }
+    }
 }
 
-fn parse_token<'a>(line: &'a str, start: usize) -> Result<(&'a str, usize), &'static str> {
+/// Read the word at `start`, up to an unquoted blank, `|`, `<` or `>`.
+/// Quotes and backslashes are removed: `'...'` keeps everything
+/// literally, `"..."` still expands variables, and a backslash keeps the
+/// next character (inside double quotes only `$`, `"` and `\`).
+fn parse_word(line: &str, start: usize, vars: &Vars) -> Result<(Word, usize), &'static str> {
     let bytes = line.as_bytes();
-    let mut end = start;
-    while end < bytes.len() && !is_space(bytes[end]) && bytes[end] != b'|' && bytes[end] != b'<' && bytes[end] != b'>' {
-        end += 1;
+    let mut word = Word {
+        text: String::new(),
+        pattern: String::new(),
+        wild: false,
+    };
+    let mut quote = None;
+    let mut i = start;
+    while i < bytes.len() {
+        let b = bytes[i];
+        match (quote, b) {
+            (None, b'\'' | b'"') => quote = Some(b),
+            (Some(open), _) if b == open => quote = None,
+            (None, b'\\') | (Some(b'"'), b'\\') if i + 1 < bytes.len() => {
+                let next = bytes[i + 1];
+                if quote.is_some() && !matches!(next, b'$' | b'"' | b'\\') {
+                    word.push_literal("\\");
+                }
+                i += 1;
+                let len = utf8_len(next);
+                word.push_literal(&line[i..i + len]);
+                i += len;
+                continue;
+            }
+            (None | Some(b'"'), b'$') => {
+                let mut value = String::new();
+                let used = vars.expand_one(&line[i + 1..], &mut value);
+                if used == 0 {
+                    value.push('$');
+                }
+                word.push_literal(&value);
+                i += 1 + used;
+                continue;
+            }
+            (None, b'*' | b'?') => {
+                word.text.push(b as char);
+                word.pattern.push(b as char);
+                word.wild = true;
+            }
+            (None, b'|' | b'<' | b'>') => break,
+            (None, b) if is_space(b) => break,
+            _ => {
+                let len = utf8_len(b);
+                word.push_literal(&line[i..i + len]);
+                i += len;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    if quote.is_some() {
+        return Err("syntax error: unterminated quote");
     }
-    if end == start {
+    if i == start {
         return Err("expected token");
     }
-    let token = &line[start..end];
-    Ok((token, end))
+    Ok((word, i))
+}
+
+/// Paths matching `pattern`, sorted. Wildcards only work in the last
+/// component, and only match a leading `.` when the pattern starts with
+/// one.
+fn glob(pattern: &str) -> Vec<String> {
+    let (dir, names) = match pattern.rfind('/') {
+        Some(slash) => pattern.split_at(slash + 1),
+        None => ("", pattern),
+    };
+    let mut prefix = String::new();
+    let mut escaped = false;
+    for c in dir.chars() {
+        if c == '\\' && !escaped {
+            escaped = true;
+            continue;
+        }
+        escaped = false;
+        prefix.push(c);
+    }
+    let path = match prefix.trim_end_matches('/') {
+        "" if prefix.is_empty() => ".",
+        "" => "/",
+        path => path,
+    };
+
+    // getdents records are `[kind, name_len, name...]`
+    let mut found = Vec::new();
+    let mut cookie = 0;
+    let mut buf = [0u8; 512];
+    while let Ok(len @ 1..) = getdents(path, &mut cookie, &mut buf) {
+        let mut at = 0;
+        while at + 2 <= len {
+            let name_len = buf[at + 1] as usize;
+            let name = buf.get(at + 2..at + 2 + name_len);
+            at += 2 + name_len;
+            let Some(name) = name.and_then(|name| str::from_utf8(name).ok()) else {
+                continue;
+            };
+            if name.starts_with('.') && !names.starts_with('.') {
+                continue;
+            }
+            if glob_match(names.as_bytes(), name.as_bytes()) {
+                let mut path = prefix.clone();
+                path.push_str(name);
+                found.push(path);
+            }
+        }
+    }
+    found.sort_unstable();
+    found
+}
+
+/// Whether `name` matches `pattern`: `*` matches any run of characters,
+/// `?` any one, and `\` makes the next character literal
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // The last `*` seen and where in `name` it currently stops, to retry
+    // with it taking one more character
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => {
+                p += 1;
+                n += utf8_len(name[n]).min(name.len() - n);
+                continue;
+            }
+            Some(b'\\') if pattern.get(p + 1) == Some(&name[n]) => {
+                p += 2;
+                n += 1;
+                continue;
+            }
+            Some(&c) if c != b'\\' && c == name[n] => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            _ => {}
+        }
+        let Some((after_star, stop)) = star else {
+            return false;
+        };
+        p = after_star;
+        n = stop + 1;
+        star = Some((after_star, n));
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// The value of an assignment, which must be a single word or nothing
+fn parse_value(text: &str, vars: &Vars) -> Option<String> {
+    if text.is_empty() {
+        return Some(String::new());
+    }
+    match parse_word(text, 0, vars) {
+        Ok((value, end)) if end == text.len() => Some(value.text),
+        _ => None,
+    }
+}
+
+/// Bytes in the UTF-8 sequence that starts with `lead`
+fn utf8_len(lead: u8) -> usize {
+    match lead {
+        0xf0.. => 4,
+        0xe0.. => 3,
+        0xc0.. => 2,
+        _ => 1,
+    }
 }
 
 fn is_space(b: u8) -> bool {
     b == b' ' || b == b'\t'
 }
 
-fn run_pipeline(cmds: &[Command]) -> Result<(), &'static str> {
+fn run_pipeline(
+    cmds: &[Command],
+    line: &str,
+    search: &str,
+    background: bool,
+    jobs: &mut [Job; MAX_JOBS],
+) -> Result<isize, &'static str> {
     if cmds.is_empty() {
         return Err("empty pipeline");
     }
+    let Some(slot) = jobs.iter().position(|job| job.live == 0) else {
+        return Err("too many jobs");
+    };
 
     let mut pids: [isize; 8] = [-1; 8];
+    // The first command leads the job's process group; the rest join it
+    let mut pgid: isize = -1;
     let mut stdin_fd: isize = -1; // fd for next command's stdin
 
     for (idx, cmd) in cmds.iter().enumerate() {
         let is_last = idx + 1 == cmds.len();
-        write(2, b"[pipeline] processing cmd\n");
 
         // Determine stdin for this command
-        let cmd_stdin_fd = if let Some(path) = cmd.stdin {
+        let cmd_stdin_fd = match &cmd.stdin {
             // Explicit input redirection
-            let fd = open(path, O_READ);
-            if fd < 0 {
-                cleanup_pipeline(idx, &pids);
-                return Err("failed to open stdin redirection");
-            }
-            fd
-        } else {
-            stdin_fd
+            Some(Input::File(path)) => match open(path, O_READ | O_CLOEXEC) {
+                Ok(fd) => fd as isize,
+                Err(_) => {
+                    return Err("failed to open stdin redirection");
+                }
+            },
+            Some(Input::Text(text)) => match here_string(text) {
+                Ok(fd) => fd as isize,
+                Err(_) => {
+                    return Err("failed to create here-string");
+                }
+            },
+            None => stdin_fd,
         };
 
         // Determine stdout for this command
         let (cmd_stdout_fd, pipe_read_fd) = if is_last {
             // Last command - use explicit redirection or stdout
             if let Some(redir) = cmd.stdout.as_ref() {
-                let mut flags = O_WRITE | O_CREATE;
-                if redir.append {
-                    flags |= O_APPEND;
-                }
-                let fd = open(redir.path, flags);
-                if fd < 0 {
-                    if cmd_stdin_fd >= 0 {
-                        close(cmd_stdin_fd as usize);
+                match open(&redir.path, write_flags(redir.append) | O_CLOEXEC) {
+                    Ok(fd) => (fd as isize, -1),
+                    Err(_) => {
+                        if cmd_stdin_fd >= 0 {
+                            let _ = close(cmd_stdin_fd as usize);
+                        }
+                        return Err("failed to open stdout redirection");
                     }
-                    cleanup_pipeline(idx, &pids);
-                    return Err("failed to open stdout redirection");
                 }
-                (fd, -1)
             } else {
                 (-1, -1) // Use default stdout
             }
         } else {
             // Not last - create pipe for next command
             let mut pipe_fds = [0usize; 2];
-            if pipe(&mut pipe_fds) < 0 {
+            if pipe(&mut pipe_fds).is_err() {
                 if cmd_stdin_fd >= 0 {
-                    close(cmd_stdin_fd as usize);
+                    let _ = close(cmd_stdin_fd as usize);
                 }
-                cleanup_pipeline(idx, &pids);
                 return Err("failed to create pipe");
             }
+            // Children only see the ends their file actions dup2 onto stdio
+            let _ = fcntl(pipe_fds[0], F_SETFD, FD_CLOEXEC);
+            let _ = fcntl(pipe_fds[1], F_SETFD, FD_CLOEXEC);
             (pipe_fds[1] as isize, pipe_fds[0] as isize)
         };
 
         // Spawn command
-        write(2, b"[pipeline] about to spawn cmd\n");
-        let pid = spawn_command(cmd, cmd_stdin_fd, cmd_stdout_fd)?;
-        write(2, b"[pipeline] spawned cmd\n");
-        if pid < 0 {
-            if cmd_stdin_fd >= 0 {
-                close(cmd_stdin_fd as usize);
-            }
-            if cmd_stdout_fd >= 0 {
-                close(cmd_stdout_fd as usize);
+        let group = if pgid < 0 { SPAWN_NEW_PGRP } else { pgid as usize };
+        let spawned = spawn_command(cmd, search, cmd_stdin_fd, cmd_stdout_fd, group);
+        let pid = match spawned {
+            Ok(pid) => pid as isize,
+            Err(msg) => {
+                if cmd_stdin_fd >= 0 {
+                    let _ = close(cmd_stdin_fd as usize);
+                }
+                if cmd_stdout_fd >= 0 {
+                    let _ = close(cmd_stdout_fd as usize);
+                }
+                if pipe_read_fd >= 0 {
+                    let _ = close(pipe_read_fd as usize);
+                }
+                return Err(msg);
             }
-            if pipe_read_fd >= 0 {
-                close(pipe_read_fd as usize);
+        };
+        pids[idx] = pid;
+        if pgid < 0 {
+            pgid = pid;
+            if !background {
+                let _ = tcsetpgrp(pgid as usize);
             }
-            cleanup_pipeline(idx, &pids);
-            return Err("failed to spawn command");
         }
-        pids[idx] = pid;
 
         // Close used fds in parent
-        write(2, b"[pipeline] closing parent fds\n");
         if cmd_stdin_fd >= 0 {
-            close(cmd_stdin_fd as usize);
+            let _ = close(cmd_stdin_fd as usize);
         }
         if cmd_stdout_fd >= 0 {
-            close(cmd_stdout_fd as usize);
+            let _ = close(cmd_stdout_fd as usize);
         }
 
         // Pipe read end becomes stdin for next command
         stdin_fd = pipe_read_fd;
-        write(2, b"[pipeline] done with cmd\n");
     }
 
-    write(2, b"[pipeline] all commands spawned, waiting...\n");
+    let job = &mut jobs[slot];
+    *job = Job::empty();
+    job.pgid = pgid;
+    job.pids = pids;
+    job.live = pids.iter().filter(|&&pid| pid >= 0).count();
+    job.text_len = line.len().min(JOB_TEXT);
+    job.text[..job.text_len].copy_from_slice(&line.as_bytes()[..job.text_len]);
 
-    // Wait for all children
-    for i in 0..cmds.len() {
-        if pids[i] >= 0 {
-            wait(None);
-        }
+    if background {
+        write_job_started(slot, pgid);
+        return Ok(0);
     }
 
-    Ok(())
+    Ok(wait_foreground(slot, jobs))
 }
 
-// Cleanup any spawned processes
-fn cleanup_pipeline(up_to: usize, pids: &[isize; 8]) {
-    for i in 0..up_to {
-        if pids[i] >= 0 {
-            // Processes will be cleaned up when parent waits or exits
+/// Wait for a foreground job to finish or stop, then take the console back.
+/// Returns the status of the last process to finish, or 128 plus the
+/// signal if the job stopped.
+fn wait_foreground(slot: usize, jobs: &mut [Job; MAX_JOBS]) -> isize {
+    let job = &mut jobs[slot];
+    let mut exit_status = 0;
+    for i in 0..job.pids.len() {
+        let pid = job.pids[i];
+        if pid < 0 {
+            continue;
+        }
+        let mut status = 0;
+        if waitpid(pid, &mut status, WUNTRACED).is_err() {
+            job.pids[i] = -1;
+            job.live -= 1;
+            continue;
+        }
+        if wifstopped(status) {
+            job.stopped = true;
+            exit_status = 128 + wstopsig(status) as isize;
+            break;
         }
+        exit_status = wexitstatus(status);
+        job.pids[i] = -1;
+        job.live -= 1;
+    }
+    let _ = tcsetpgrp(0);
+    if job.stopped {
+        put(b"\n");
+        write_job_line(slot, b"Stopped", job.text());
     }
+    exit_status
 }
 
-// Spawn a command with specified stdin/stdout file descriptors
-// stdin_fd: -1 means use default stdin, otherwise dup2 to stdin
-// stdout_fd: -1 means use default stdout, otherwise dup2 to stdout
-// Returns the child PID or negative error code
-fn spawn_command(cmd: &Command, stdin_fd: isize, stdout_fd: isize) -> Result<isize, &'static str> {
-    if cmd.argc == 0 {
-        return Err("empty command");
+/// Collect finished background jobs without blocking
+fn reap_jobs(jobs: &mut [Job; MAX_JOBS]) {
+    for (slot, job) in jobs.iter_mut().enumerate() {
+        if job.live == 0 {
+            continue;
+        }
+        for i in 0..job.pids.len() {
+            let pid = job.pids[i];
+            if pid < 0 {
+                continue;
+            }
+            let mut status = 0;
+            let ret = waitpid(pid, &mut status, WNOHANG);
+            if ret == Ok(pid as usize) || ret.is_err() {
+                job.pids[i] = -1;
+                job.live -= 1;
+            }
+        }
+        if job.live == 0 {
+            write_job_line(slot, b"Done", job.text());
+        }
     }
+}
 
-    write(2, b"[spawn_command] start\n");
+/// `klog` shows which console gets kernel messages; `klog ttyS0`,
+/// `klog ttyS1` or `klog hvc0` moves them
+fn kernel_log(arg: &str) {
+    let port = match arg {
+        "" => CONSOLE_QUERY,
+        "ttyS0" => CONSOLE_UART,
+        "ttyS1" => CONSOLE_UART1,
+        "hvc0" => CONSOLE_VIRTIO,
+        _ => {
+            eprintln!("usage: klog [ttyS0|ttyS1|hvc0]");
+            return;
+        }
+    };
+    match console_log(port) {
+        Err(Error::NotFound) => {
+            eprintln!("klog: no such console");
+        }
+        Err(_) => {
+            eprintln!("klog: not permitted");
+        }
+        Ok(previous) if port == CONSOLE_QUERY => {
+            let name = match previous {
+                CONSOLE_VIRTIO => "hvc0",
+                CONSOLE_UART1 => "ttyS1",
+                _ => "ttyS0",
+            };
+            println!("{}", name);
+        }
+        Ok(_) => {}
+    }
+}
 
-    // Save current stdin/stdout
-    let mut saved_in = dup2(0, 14);
-    if saved_in < 0 {
-        saved_in = -1;
+/// `dmesg` prints the kernel log and `dmesg -c` clears it after printing;
+/// `dmesg -n <0-3>` sets how much of it also goes to the console, from
+/// errors only up to debug messages
+fn dmesg(arg: &str) {
+    if let Some(level) = arg.strip_prefix("-n") {
+        match level.trim().parse::<usize>() {
+            Ok(level) if level <= KLOG_DEBUG => {
+                if klog_level(level).is_err() {
+                    eprintln!("dmesg: not permitted");
+                }
+            }
+            _ => {
+                eprintln!("usage: dmesg [-c | -n 0-3]");
+            }
+        }
+        return;
     }
-    let mut saved_out = dup2(1, 15);
-    if saved_out < 0 {
-        saved_out = -1;
+    let clear = match arg {
+        "" => false,
+        "-c" => true,
+        _ => {
+            eprintln!("usage: dmesg [-c | -n 0-3]");
+            return;
+        }
+    };
+    let mut cursor = 0;
+    let mut buf = [0u8; 256];
+    loop {
+        match klog_read(&mut cursor, &mut buf) {
+            Ok(n) if n > 0 => put(&buf[..n]),
+            _ => break,
+        }
     }
+    if clear && klog_clear().is_err() {
+        eprintln!("dmesg: not permitted");
+    }
+}
 
-    write(2, b"[spawn_command] saved stdio\n");
+/// `log` lists each kernel subsystem's log level; `log set <subsystem>
+/// <level>` changes one, or every one with `all`
+fn log_levels(arg: &str) {
+    if arg.is_empty() {
+        for (subsystem, name) in KLOG_SUBSYSTEMS.iter().enumerate() {
+            let level = klog_subsystem(subsystem, KLOG_QUERY);
+            if let Some(level) = level.ok().and_then(|level| KLOG_LEVELS.get(level)) {
+                println!("{}\t{}", name, level);
+            }
+        }
+        return;
+    }
+    let mut words = arg.split_ascii_whitespace();
+    let (Some("set"), Some(subsystem), Some(level), None) =
+        (words.next(), words.next(), words.next(), words.next())
+    else {
+        eprintln!("usage: log [set <subsystem|all> <level>]");
+        return;
+    };
+    let Some(level) = KLOG_LEVELS.iter().position(|name| *name == level) else {
+        eprintln!("log: levels are error, warn, info and debug");
+        return;
+    };
+    let subsystems = if subsystem == "all" {
+        0..KLOG_SUBSYSTEMS.len()
+    } else if let Some(index) = KLOG_SUBSYSTEMS.iter().position(|name| *name == subsystem) {
+        index..index + 1
+    } else {
+        eprintln!("log: unknown subsystem");
+        return;
+    };
+    for subsystem in subsystems {
+        if klog_subsystem(subsystem, level).is_err() {
+            eprintln!("log: not permitted");
+            return;
+        }
+    }
+}
 
-    // Redirect stdin if needed
-    if stdin_fd >= 0 {
-        dup2(stdin_fd as usize, 0);
+/// `maxprocs` prints the process cap; `maxprocs <n>` changes it. Returns
+/// the exit status.
+fn max_processes(arg: &str) -> isize {
+    let max = if arg.is_empty() {
+        0
+    } else {
+        match arg.parse::<usize>() {
+            Ok(max) if max > 0 => max,
+            _ => {
+                eprintln!("usage: maxprocs [n]");
+                return 2;
+            }
+        }
+    };
+    match maxprocs(max) {
+        Ok(cap) => {
+            if max == 0 {
+                println!("max processes: {}", cap);
+            }
+            0
+        }
+        Err(err) => {
+            eprintln!("maxprocs: {}", err);
+            1
+        }
     }
+}
 
-    write(2, b"[spawn_command] redirected stdin\n");
+/// `schedstat` prints the system-wide scheduler counters, then each
+/// process's
+fn sched_stats() {
+    let mut total = SchedStats::default();
+    if let Err(err) = schedstat(0, &mut total) {
+        eprintln!("schedstat: {}", err);
+        return;
+    }
+    println!(
+        "switches {}  ticks {}  busy {}  window bytes copied {}  cycles/switch {}",
+        total.switches,
+        total.ticks,
+        total.running_ticks,
+        total.window_bytes,
+        total.switch_cycles / total.switches.max(1)
+    );
+    if total.ticks > 0 {
+        println!(
+            "average run queue {}.{:02}",
+            total.ready_ticks / total.ticks,
+            total.ready_ticks * 100 / total.ticks % 100
+        );
+    }
+    println!("  PID  SWITCH     RUN   READY BLOCKED  COPIED  CYC/SW CMD");
+    let mut buf = [0u8; 512];
+    let mut cookie = 0;
+    while let Ok(n) = procinfo(&mut cookie, &mut buf) {
+        if n == 0 {
+            break;
+        }
+        let mut offset = 0;
+        while let Some((record, len)) = ProcRecord::parse(&buf[offset..n]) {
+            offset += len;
+            let mut stats = SchedStats::default();
+            // The process may have been reaped since procinfo saw it
+            if schedstat(record.pid, &mut stats).is_err() {
+                continue;
+            }
+            print!(
+                "{:>5} {:>7} {:>7} {:>7} {:>7} {:>7} {:>7} ",
+                record.pid,
+                stats.switches,
+                stats.running_ticks,
+                stats.ready_ticks,
+                stats.blocked_ticks,
+                stats.window_bytes,
+                stats.switch_cycles / stats.switches.max(1)
+            );
+            put(record.path);
+            put(b"\n");
+        }
+    }
+}
 
-    // Redirect stdout if needed
-    if stdout_fd >= 0 {
-        dup2(stdout_fd as usize, 1);
+fn list_jobs(jobs: &[Job; MAX_JOBS]) {
+    for (slot, job) in jobs.iter().enumerate() {
+        if job.live > 0 {
+            let state: &[u8] = if job.stopped { b"Stopped" } else { b"Running" };
+            write_job_line(slot, state, job.text());
+        }
     }
+}
 
-    write(2, b"[spawn_command] redirected stdout\n");
+/// `fg [n]` / `bg [n]`: continue job n (default: the newest) in the
+/// foreground or background; returns the foreground job's status
+fn resume_job(jobs: &mut [Job; MAX_JOBS], line: &str) -> Result<isize, &'static str> {
+    let foreground = line.starts_with("fg");
+    let arg = line[2..].trim().trim_start_matches('%');
+    let slot = if arg.is_empty() {
+        jobs.iter().rposition(|job| job.live > 0).ok_or("no current job")?
+    } else {
+        let n: usize = arg.parse().map_err(|_| "usage: fg|bg [job]")?;
+        if n == 0 || n > MAX_JOBS || jobs[n - 1].live == 0 {
+            return Err("no such job");
+        }
+        n - 1
+    };
 
-    // Build argv
-    let mut argv_buf: [&str; 16] = [""; 16];
-    let argc = cmd.argc.min(16);
-    for i in 0..argc {
-        argv_buf[i] = cmd.args[i];
+    let job = &mut jobs[slot];
+    if foreground {
+        put(job.text());
+        put(b"\n");
+        let _ = tcsetpgrp(job.pgid as usize);
     }
+    if job.stopped {
+        let _ = kill(-job.pgid, SIGCONT);
+        job.stopped = false;
+    }
+    if foreground {
+        Ok(wait_foreground(slot, jobs))
+    } else {
+        write_job_line(slot, b"", job.text());
+        Ok(0)
+    }
+}
 
-    // Resolve program path
-    let mut path_buf = [0u8; MAX_LINE];
-    let prog_path = resolve_prog(cmd.args[0], &mut path_buf);
-
-    write(2, b"[spawn_command] about to spawn\n");
-    write(2, b"[spawn_command] prog_path=");
-    write(2, prog_path.as_bytes());
-    write(2, b"\n");
-    write(2, b"[spawn_command] argc=");
-    let argc_byte = [b'0' + (argc as u8)];
-    write(2, &argc_byte);
-    write(2, b"\n");
-    for i in 0..argc {
-        write(2, b"[spawn_command] argv[");
-        let i_byte = [b'0' + (i as u8)];
-        write(2, &i_byte);
-        write(2, b"]=\"");
-        write(2, argv_buf[i].as_bytes());
-        write(2, b"\"\n");
+/// Print `[n] state text`
+fn write_job_line(slot: usize, state: &[u8], text: &[u8]) {
+    let number = [b'[', b'1' + slot as u8, b']', b' '];
+    put(&number);
+    if !state.is_empty() {
+        put(state);
+        put(b"  ");
     }
+    put(text);
+    put(b"\n");
+}
 
-    // Spawn child
-    let pid = spawn(prog_path, &argv_buf[..argc]);
+/// Print `[n] pgid` for a job started in the background
+fn write_job_started(slot: usize, pgid: isize) {
+    let mut digits = [0u8; 20];
+    let mut n = pgid.unsigned_abs();
+    let mut i = digits.len();
+    loop {
+        i -= 1;
+        digits[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    write_job_line(slot, b"", &digits[i..]);
+}
 
-    write(2, b"[spawn_command] spawn returned\n");
+/// Open flags for `>` and `2>`, or `>>` and `2>>` when appending
+fn write_flags(append: bool) -> usize {
+    O_WRITE | O_CREATE | if append { O_APPEND } else { O_TRUNC }
+}
 
-    // Restore parent's stdin/stdout
-    restore_stdio(saved_in, saved_out);
+/// A pipe already holding `text` and a newline, for `<<<`; returns the
+/// read end
+fn here_string(text: &str) -> Result<usize, Error> {
+    let mut fds = [0usize; 2];
+    pipe(&mut fds)?;
+    let _ = fcntl(fds[0], F_SETFD, FD_CLOEXEC);
+    // Everything is written before the command starts reading, so the
+    // pipe has to hold all of it
+    let len = text.len() + 1;
+    let filled = fcntl(fds[1], F_GETPIPE_SZ, 0)
+        .and_then(|size| {
+            if size < len {
+                fcntl(fds[1], F_SETPIPE_SZ, len)
+            } else {
+                Ok(size)
+            }
+        })
+        .and_then(|_| write(fds[1], text.as_bytes()))
+        .and_then(|_| write(fds[1], b"\n"));
+    let _ = close(fds[1]);
+    if let Err(err) = filled {
+        let _ = close(fds[0]);
+        return Err(err);
+    }
+    Ok(fds[0])
+}
 
-    write(2, b"[spawn_command] restored stdio\n");
+// Spawn a command with specified stdin/stdout file descriptors
+// search: PATH, the directories to look for the program in
+// stdin_fd: -1 means use default stdin, otherwise the child's stdin
+// stdout_fd: -1 means use default stdout, otherwise the child's stdout
+// The shell's own stdio is never touched
+// Returns the child PID
+fn spawn_command(
+    cmd: &Command,
+    search: &str,
+    stdin_fd: isize,
+    stdout_fd: isize,
+    pgid: usize,
+) -> Result<usize, &'static str> {
+    if cmd.args.is_empty() {
+        return Err("empty command");
+    }
 
-    if pid < 0 {
-        return Err("spawn failed");
+    // Redirections are set up in the child only
+    let mut actions = SpawnFileActions::new();
+    if stdin_fd >= 0 {
+        actions.add_dup2(stdin_fd as usize, 0);
+    }
+    if stdout_fd >= 0 {
+        actions.add_dup2(stdout_fd as usize, 1);
+    }
+    // After stdout, so 2>&1 follows its redirection
+    match &cmd.stderr {
+        Some(Stderr::File(redir)) => {
+            actions.add_open(2, &redir.path, write_flags(redir.append));
+        }
+        Some(Stderr::Stdout) => {
+            actions.add_dup2(1, 2);
+        }
+        None => {}
     }
 
-    write(2, b"[spawn_command] done\n");
+    // Build argv
+    let mut argv_buf: [&str; 16] = [""; 16];
+    let argc = cmd.args.len().min(16);
+    for (slot, arg) in argv_buf.iter_mut().zip(&cmd.args) {
+        *slot = arg;
+    }
 
-    Ok(pid)
-}
+    // Resolve program path
+    let mut path_buf = [0u8; MAX_PATH];
+    let Some(prog_path) = resolve_prog(&cmd.args[0], search, &mut path_buf) else {
+        return Err("command not found");
+    };
 
-fn restore_stdio(saved_in: isize, saved_out: isize) {
-    if saved_in >= 0 {
-        dup2(saved_in as usize, 0);
-        close(saved_in as usize);
-    }
-    if saved_out >= 0 {
-        dup2(saved_out as usize, 1);
-        close(saved_out as usize);
+    match spawn_with(prog_path, &argv_buf[..argc], pgid, &actions) {
+        Ok(pid) => Ok(pid),
+        Err(Error::NotExecutable) => Err("exec format error"),
+        Err(_) => Err("spawn failed"),
     }
 }
 
-fn resolve_prog<'a>(cmd: &'a str, buf: &'a mut [u8; MAX_LINE]) -> &'a str {
-    if cmd.starts_with('/') {
-        return cmd;
-    }
-
-    const PREFIX: &str = "/bin/";
-    let prefix_bytes = PREFIX.as_bytes();
-    let cmd_bytes = cmd.as_bytes();
-    let total = prefix_bytes.len() + cmd_bytes.len();
-    if total >= buf.len() {
-        return cmd;
+/// Find `cmd` in the `:`-separated directories of `search`, the value
+/// of PATH, taking the first that holds a file of that name. A name
+/// with a `/` in it is used as it is.
+fn resolve_prog<'a>(cmd: &'a str, search: &str, buf: &'a mut [u8; MAX_PATH]) -> Option<&'a str> {
+    if cmd.contains('/') {
+        return Some(cmd);
     }
 
-    buf[..prefix_bytes.len()].copy_from_slice(prefix_bytes);
-    buf[prefix_bytes.len()..total].copy_from_slice(cmd_bytes);
-    str::from_utf8(&buf[..total]).unwrap_or(cmd)
+    let mut info = Stat::default();
+    let dir = search
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .find(|dir| {
+            join_path(buf, dir, cmd)
+                .is_some_and(|path| stat(path, &mut info).is_ok() && !info.is_dir())
+        })?;
+    join_path(buf, dir, cmd)
 }
@@ -0,0 +1,146 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use user_bin::{
+    close, eprintln, exit, open, BufReader, BufWriter, Error, Opt, OptError, Options, O_READ,
+};
+
+struct Flags {
+    /// -r: largest first
+    reverse: bool,
+    /// -n: compare leading numbers rather than bytes
+    numeric: bool,
+    /// -u: print each run of equal lines once
+    unique: bool,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let mut flags = Flags {
+        reverse: false,
+        numeric: false,
+        unique: false,
+    };
+    let mut opts = Options::new(argc, argv, "rnu");
+    for opt in &mut opts {
+        match opt {
+            Ok(Opt::Flag('r')) => flags.reverse = true,
+            Ok(Opt::Flag('n')) => flags.numeric = true,
+            Ok(Opt::Flag('u')) => flags.unique = true,
+            Ok(_) => {}
+            Err(err) => usage(err),
+        }
+    }
+
+    // Every line of every input is held at once, so this is where the heap
+    // earns its keep
+    let mut text = Vec::new();
+    if opts.operands().count() == 0
+        && let Err(err) = read_all(0, &mut text)
+    {
+        eprintln!("sort: stdin: {}", err);
+        exit(1);
+    }
+    for path in opts.operands() {
+        let read = open(path, O_READ).and_then(|fd| {
+            let read = read_all(fd, &mut text);
+            let _ = close(fd);
+            read
+        });
+        if let Err(err) = read {
+            eprintln!("sort: {}: {}", path, err);
+            exit(1);
+        }
+    }
+
+    let mut lines: Vec<&[u8]> = text.split(|&byte| byte == b'\n').collect();
+    // The newline ending the last line does not start another
+    if text.ends_with(b"\n") || text.is_empty() {
+        lines.pop();
+    }
+    lines.sort_by(|a, b| {
+        let order = compare(a, b, flags.numeric);
+        if flags.reverse {
+            order.reverse()
+        } else {
+            order
+        }
+    });
+    if flags.unique {
+        lines.dedup_by(|a, b| compare(a, b, flags.numeric) == Ordering::Equal);
+    }
+
+    let mut out = BufWriter::new(1);
+    for line in lines {
+        if out.write_all(line).is_err() || out.write_all(b"\n").is_err() {
+            break;
+        }
+    }
+    let _ = out.flush();
+    exit(0)
+}
+
+fn usage(err: OptError) -> ! {
+    eprintln!("sort: {}", err);
+    eprintln!("usage: sort [-rnu] [file...]");
+    exit(2)
+}
+
+/// Append everything `fd` has left to `text`
+fn read_all(fd: usize, text: &mut Vec<u8>) -> Result<(), Error> {
+    let mut input = BufReader::new(fd);
+    loop {
+        let len = match input.fill_buf()? {
+            [] => break,
+            chunk => {
+                text.try_reserve(chunk.len())
+                    .map_err(|_| Error::OutOfMemory)?;
+                text.extend_from_slice(chunk);
+                chunk.len()
+            }
+        };
+        input.consume(len);
+    }
+    // Keep the last line of this input apart from the first of the next
+    if !text.is_empty() && !text.ends_with(b"\n") {
+        text.push(b'\n');
+    }
+    Ok(())
+}
+
+/// Order two lines, by their leading numbers with -n. Lines with equal
+/// numbers fall back to comparing bytes.
+fn compare(a: &[u8], b: &[u8], numeric: bool) -> Ordering {
+    if numeric {
+        number(a).cmp(&number(b)).then_with(|| a.cmp(b))
+    } else {
+        a.cmp(b)
+    }
+}
+
+/// The integer at the start of `line` after any blanks; 0 if none
+fn number(line: &[u8]) -> i64 {
+    let line = line.trim_ascii_start();
+    let (negative, digits) = match line.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        _ => (false, line),
+    };
+    let value =
+        digits
+            .iter()
+            .take_while(|byte| byte.is_ascii_digit())
+            .fold(0i64, |value, &digit| {
+                value
+                    .saturating_mul(10)
+                    .saturating_add((digit - b'0') as i64)
+            });
+    if negative {
+        -value
+    } else {
+        value
+    }
+}
@@ -0,0 +1,192 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use user_bin::{
+    close, eprintln, exit, lseek, open, read, stat, BufReader, BufWriter, Error, Opt, OptError,
+    Options, Stat, O_READ, SEEK_SET,
+};
+
+/// Lines printed without -n
+const DEFAULT_LINES: usize = 10;
+
+/// Bytes of standard input tail keeps; it cannot seek back over a pipe,
+/// so longer tails of stdin are cut to this
+const STDIN_KEEP: usize = 4096;
+
+/// Bytes read per step while searching backwards from the end of a file
+const CHUNK: usize = 512;
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let mut lines = DEFAULT_LINES;
+    let mut opts = Options::new(argc, argv, "n:");
+    for opt in &mut opts {
+        match opt {
+            Ok(Opt::Value('n', count)) => match count.parse() {
+                Ok(count) => lines = count,
+                Err(_) => {
+                    eprintln!("tail: invalid line count: {}", count);
+                    exit(2);
+                }
+            },
+            Ok(_) => {}
+            Err(err) => usage(err),
+        }
+    }
+
+    let mut out = BufWriter::new(1);
+    let mut status = 0;
+    let files = opts.operands().count();
+    if files == 0 {
+        tail_stdin(&mut out, lines);
+    }
+    for (i, path) in opts.operands().enumerate() {
+        // With several files each gets a `==> name <==` heading
+        if files > 1 {
+            let gap = if i > 0 { "\n" } else { "" };
+            let _ = writeln!(out, "{}==> {} <==", gap, path);
+        }
+        if let Err(err) = tail_file(&mut out, path, lines) {
+            let _ = out.flush();
+            eprintln!("tail: {}: {}", path, err);
+            status = 1;
+        }
+    }
+    let _ = out.flush();
+    exit(status)
+}
+
+fn usage(err: OptError) -> ! {
+    eprintln!("tail: {}", err);
+    eprintln!("usage: tail [-n lines] [file...]");
+    exit(2)
+}
+
+/// Print the last `lines` lines of the file at `path`, reading only those
+/// and the chunks searched to find where they start
+fn tail_file(out: &mut BufWriter, path: &str, lines: usize) -> Result<(), Error> {
+    let mut info = Stat::default();
+    stat(path, &mut info)?;
+    if info.is_dir() {
+        return Err(Error::IsDirectory);
+    }
+    let fd = open(path, O_READ)?;
+    let result = tail_fd(out, fd, info.size as usize, lines);
+    let _ = close(fd);
+    result
+}
+
+fn tail_fd(out: &mut BufWriter, fd: usize, size: usize, lines: usize) -> Result<(), Error> {
+    let mut search = Search::new(size, lines);
+    let mut buf = [0u8; CHUNK];
+    let mut end = size;
+    let start = loop {
+        if let Some(start) = search.start() {
+            break start;
+        }
+        if end == 0 {
+            break 0;
+        }
+        let begin = end.saturating_sub(CHUNK);
+        lseek(fd, begin as isize, SEEK_SET)?;
+        let chunk = &mut buf[..end - begin];
+        let mut filled = 0;
+        while filled < chunk.len() {
+            match read(fd, &mut chunk[filled..])? {
+                0 => return Err(Error::Io),
+                len => filled += len,
+            }
+        }
+        search.scan(begin, chunk);
+        end = begin;
+    };
+
+    lseek(fd, start as isize, SEEK_SET)?;
+    let mut input = BufReader::new(fd);
+    loop {
+        let len = match input.fill_buf()? {
+            [] => return Ok(()),
+            chunk => {
+                out.write_all(chunk)?;
+                chunk.len()
+            }
+        };
+        input.consume(len);
+    }
+}
+
+/// Print the last `lines` lines of standard input, which has to be read
+/// to the end first
+fn tail_stdin(out: &mut BufWriter, lines: usize) {
+    let mut kept = [0u8; STDIN_KEEP];
+    let mut len = 0;
+    let mut input = BufReader::new(0);
+    loop {
+        let taken = match input.fill_buf() {
+            Ok([]) | Err(_) => break,
+            Ok(chunk) => {
+                let chunk = &chunk[chunk.len().saturating_sub(STDIN_KEEP)..];
+                // Drop the oldest bytes to make room
+                let drop = (len + chunk.len()).saturating_sub(STDIN_KEEP);
+                kept.copy_within(drop..len, 0);
+                len -= drop;
+                kept[len..len + chunk.len()].copy_from_slice(chunk);
+                len += chunk.len();
+                chunk.len()
+            }
+        };
+        input.consume(taken);
+    }
+
+    let mut search = Search::new(len, lines);
+    search.scan(0, &kept[..len]);
+    let start = search.start().unwrap_or(0);
+    let _ = out.write_all(&kept[start..len]);
+}
+
+/// Finds where the last `lines` lines begin by scanning backwards for
+/// the newline in front of them
+struct Search {
+    size: usize,
+    lines: usize,
+    newlines: usize,
+    start: Option<usize>,
+}
+
+impl Search {
+    fn new(size: usize, lines: usize) -> Self {
+        Self {
+            size,
+            lines,
+            newlines: 0,
+            start: (lines == 0).then_some(size),
+        }
+    }
+
+    /// Offset the tail starts at, once found
+    fn start(&self) -> Option<usize> {
+        self.start
+    }
+
+    /// Look through `chunk`, which sits at `offset` and comes just before
+    /// everything scanned so far
+    fn scan(&mut self, offset: usize, chunk: &[u8]) {
+        if self.start.is_some() {
+            return;
+        }
+        for (i, &byte) in chunk.iter().enumerate().rev() {
+            let at = offset + i;
+            // A newline at the very end closes the last line rather than
+            // starting another one
+            if byte != b'\n' || at + 1 == self.size {
+                continue;
+            }
+            self.newlines += 1;
+            if self.newlines == self.lines {
+                self.start = Some(at + 1);
+                return;
+            }
+        }
+    }
+}
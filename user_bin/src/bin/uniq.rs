@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use user_bin::{close, eprintln, exit, open, BufReader, BufWriter, Opt, OptError, Options, O_READ};
+
+/// Longest line uniq compares; the rest of a longer line is ignored
+const MAX_LINE: usize = 1024;
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    let mut count = false;
+    let mut opts = Options::new(argc, argv, "c");
+    for opt in &mut opts {
+        match opt {
+            Ok(Opt::Flag('c')) => count = true,
+            Ok(_) => {}
+            Err(err) => usage(Some(err)),
+        }
+    }
+
+    let mut files = opts.operands();
+    let path = files.next();
+    if files.next().is_some() {
+        usage(None);
+    }
+    let fd = match path {
+        None => 0,
+        Some(path) => match open(path, O_READ) {
+            Ok(fd) => fd,
+            Err(err) => {
+                eprintln!("uniq: {}: {}", path, err);
+                exit(1);
+            }
+        },
+    };
+
+    let mut out = BufWriter::new(1);
+    let mut input = BufReader::new(fd);
+    let mut line = [0u8; MAX_LINE];
+    let mut previous = [0u8; MAX_LINE];
+    let mut previous_len = 0;
+    // How many times the previous line has come up in a row; 0 before
+    // the first line
+    let mut repeats = 0;
+    while let Ok(Some(len)) = input.read_line(&mut line) {
+        if repeats > 0 && line[..len] == previous[..previous_len] {
+            repeats += 1;
+            continue;
+        }
+        if repeats > 0 {
+            show(
+                &mut out,
+                &previous[..previous_len],
+                count.then_some(repeats),
+            );
+        }
+        previous[..len].copy_from_slice(&line[..len]);
+        previous_len = len;
+        repeats = 1;
+    }
+    if repeats > 0 {
+        show(
+            &mut out,
+            &previous[..previous_len],
+            count.then_some(repeats),
+        );
+    }
+    let _ = out.flush();
+    if path.is_some() {
+        let _ = close(fd);
+    }
+    exit(0)
+}
+
+fn usage(err: Option<OptError>) -> ! {
+    if let Some(err) = err {
+        eprintln!("uniq: {}", err);
+    }
+    eprintln!("usage: uniq [-c] [file]");
+    exit(2)
+}
+
+/// Print one line of output, with its count for -c
+fn show(out: &mut BufWriter, line: &[u8], count: Option<usize>) {
+    if let Some(count) = count {
+        let _ = write!(out, "{:>7} ", count);
+    }
+    let _ = out.write_all(line);
+    let _ = out.write_all(b"\n");
+}
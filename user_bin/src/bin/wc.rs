@@ -1,13 +1,45 @@
 #![no_std]
 #![no_main]
 
-use user_bin::{close, exit, get_arg, open, read, write, O_READ};
+use core::fmt::Write;
+use user_bin::{close, eprintln, exit, open, BufReader, BufWriter, Opt, OptError, Options, O_READ};
+
+/// Which counts to print; all three unless -l, -w or -c pick some
+struct Show {
+    lines: bool,
+    words: bool,
+    bytes: bool,
+}
 
 #[unsafe(no_mangle)]
 pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
-    // If no arguments, read from stdin
-    if argc == 1 {
-        wc_fd(0, None);
+    let mut show = Show {
+        lines: false,
+        words: false,
+        bytes: false,
+    };
+    let mut opts = Options::new(argc, argv, "lwc");
+    for opt in &mut opts {
+        match opt {
+            Ok(Opt::Flag('l')) => show.lines = true,
+            Ok(Opt::Flag('w')) => show.words = true,
+            Ok(Opt::Flag('c')) => show.bytes = true,
+            Ok(_) => {}
+            Err(err) => usage(err),
+        }
+    }
+    if !show.lines && !show.words && !show.bytes {
+        show = Show {
+            lines: true,
+            words: true,
+            bytes: true,
+        };
+    }
+
+    // If no files, read from stdin
+    let mut files = opts.operands().peekable();
+    if files.peek().is_none() {
+        print_counts(&show, wc_fd(0), None);
         exit(0);
     }
 
@@ -17,57 +49,75 @@ pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
     let mut total_bytes = 0;
     let mut file_count = 0;
 
-    let mut i = 1;
-    while i < argc {
-        let Some(filename) = get_arg(argc, argv, i) else {
-            break;
+    for filename in files {
+        let fd = match open(filename, O_READ) {
+            Ok(fd) => fd,
+            Err(err) => {
+                eprintln!("wc: cannot open {}: {}", filename, err);
+                exit(1);
+            }
         };
 
-        let fd = open(filename, O_READ);
-        if fd < 0 {
-            write(2, b"wc: cannot open ");
-            write(2, filename.as_bytes());
-            write(2, b"\n");
-            exit(1);
-        }
-
-        let (lines, words, bytes) = wc_fd(fd as usize, Some(filename));
-        close(fd as usize);
+        let (lines, words, bytes) = wc_fd(fd);
+        let _ = close(fd);
+        print_counts(&show, (lines, words, bytes), Some(filename));
 
         total_lines += lines;
         total_words += words;
         total_bytes += bytes;
         file_count += 1;
-        i += 1;
     }
 
     // If multiple files, print totals
     if file_count > 1 {
-        print_number(total_lines);
-        write(1, b" ");
-        print_number(total_words);
-        write(1, b" ");
-        print_number(total_bytes);
-        write(1, b" total\n");
+        print_counts(
+            &show,
+            (total_lines, total_words, total_bytes),
+            Some("total"),
+        );
     }
 
     exit(0)
 }
 
-fn wc_fd(fd: usize, filename: Option<&str>) -> (usize, usize, usize) {
-    let mut buf = [0u8; 4096];
+fn usage(err: OptError) -> ! {
+    eprintln!("wc: {}", err);
+    eprintln!("usage: wc [-lwc] [file...]");
+    exit(2)
+}
+
+/// Print the counts `show` asks for, then `name` if there is one
+fn print_counts(show: &Show, (lines, words, bytes): (usize, usize, usize), name: Option<&str>) {
+    let mut out = BufWriter::new(1);
+    let mut sep = "";
+    for (shown, count) in [
+        (show.lines, lines),
+        (show.words, words),
+        (show.bytes, bytes),
+    ] {
+        if shown {
+            let _ = write!(out, "{}{}", sep, count);
+            sep = " ";
+        }
+    }
+    if let Some(name) = name {
+        let _ = write!(out, "{}{}", sep, name);
+    }
+    let _ = writeln!(out);
+}
+
+fn wc_fd(fd: usize) -> (usize, usize, usize) {
+    let mut input = BufReader::new(fd);
     let mut total_bytes = 0;
     let mut lines = 0;
     let mut words = 0;
     let mut in_word = false;
 
     loop {
-        let len = read(fd, &mut buf);
-        if len <= 0 {
-            break;
-        }
-
-        let data = &buf[..len as usize];
+        let data = match input.fill_buf() {
+            Ok([]) | Err(_) => break,
+            Ok(data) => data,
+        };
         total_bytes += data.len();
 
         // Count lines and words
@@ -84,21 +134,10 @@ fn wc_fd(fd: usize, filename: Option<&str>) -> (usize, usize, usize) {
                 in_word = false;
             }
         }
+        let len = data.len();
+        input.consume(len);
     }
 
-    // Print results
-    print_number(lines);
-    write(1, b" ");
-    print_number(words);
-    write(1, b" ");
-    print_number(total_bytes);
-
-    if let Some(name) = filename {
-        write(1, b" ");
-        write(1, name.as_bytes());
-    }
-    write(1, b"\n");
-
     (lines, words, total_bytes)
 }
 
@@ -129,26 +168,3 @@ fn count_words(data: &[u8]) -> usize {
 
     count
 }
-
-fn print_number(mut num: usize) {
-    if num == 0 {
-        write(1, b"0");
-        return;
-    }
-
-    // Convert number to string (max 10 digits for usize on 32-bit)
-    let mut buf = [0u8; 20];
-    let mut i = 0;
-
-    while num > 0 {
-        buf[i] = b'0' + (num % 10) as u8;
-        num /= 10;
-        i += 1;
-    }
-
-    // Reverse and print
-    while i > 0 {
-        i -= 1;
-        write(1, &buf[i..i+1]);
-    }
-}
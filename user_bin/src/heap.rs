@@ -0,0 +1,151 @@
+//! The global allocator: a first-fit free list over memory taken from
+//! the kernel with brk. Programs that `extern crate alloc` get `Vec`,
+//! `String` and `Box` on top of it.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr;
+
+use crate::brk;
+
+/// Every block's address and size is a multiple of this, which leaves
+/// room for a `FreeBlock` in any gap a split leaves behind
+const UNIT: usize = 16;
+
+/// The heap grows by at least this much, so small allocations do not
+/// each cost a brk
+const GROW: usize = 4096;
+
+/// Header written at the start of each free block
+#[repr(C)]
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+struct Heap {
+    /// Free blocks in address order, so freeing can merge neighbours
+    free: *mut FreeBlock,
+    /// Current program break; 0 until the first allocation
+    end: usize,
+}
+
+impl Heap {
+    /// Take `size` bytes aligned to `align` from the first free block
+    /// that can hold them. Null if none can.
+    unsafe fn take(&mut self, size: usize, align: usize) -> *mut u8 {
+        let mut link: *mut *mut FreeBlock = &mut self.free;
+        unsafe {
+            while !(*link).is_null() {
+                let block = *link;
+                let addr = block as usize;
+                let block_end = addr + (*block).size;
+                let start = addr.next_multiple_of(align);
+                if start + size > block_end {
+                    link = &mut (*block).next;
+                    continue;
+                }
+                // Whatever is left on either side stays free, in place
+                let mut rest = (*block).next;
+                if block_end > start + size {
+                    let back = (start + size) as *mut FreeBlock;
+                    back.write(FreeBlock {
+                        size: block_end - start - size,
+                        next: rest,
+                    });
+                    rest = back;
+                }
+                if start > addr {
+                    (*block).size = start - addr;
+                    (*block).next = rest;
+                    rest = block;
+                }
+                *link = rest;
+                return start as *mut u8;
+            }
+        }
+        ptr::null_mut()
+    }
+
+    /// Put `size` bytes at `addr` back on the free list, merged with the
+    /// blocks either side when they touch
+    unsafe fn give(&mut self, addr: usize, size: usize) {
+        unsafe {
+            let mut prev: *mut FreeBlock = ptr::null_mut();
+            let mut next = self.free;
+            while !next.is_null() && (next as usize) < addr {
+                prev = next;
+                next = (*next).next;
+            }
+            let block = addr as *mut FreeBlock;
+            block.write(FreeBlock { size, next });
+            if !next.is_null() && addr + size == next as usize {
+                (*block).size += (*next).size;
+                (*block).next = (*next).next;
+            }
+            if prev.is_null() {
+                self.free = block;
+            } else if prev as usize + (*prev).size == addr {
+                (*prev).size += (*block).size;
+                (*prev).next = (*block).next;
+            } else {
+                (*prev).next = block;
+            }
+        }
+    }
+
+    /// Move the break up by at least `size` bytes and free the new memory.
+    /// False once the kernel has no more to give.
+    fn grow(&mut self, size: usize) -> bool {
+        if self.end == 0 {
+            match brk(0) {
+                Ok(end) => self.end = end.next_multiple_of(UNIT),
+                Err(_) => return false,
+            }
+        }
+        let grow = size.next_multiple_of(GROW);
+        let Some(new_end) = self.end.checked_add(grow) else {
+            return false;
+        };
+        if brk(new_end).is_err() {
+            return false;
+        }
+        unsafe { self.give(self.end, grow) };
+        self.end = new_end;
+        true
+    }
+}
+
+/// Bytes the block for `layout` takes: whole units, at least one
+fn block_size(layout: Layout) -> usize {
+    layout.size().max(1).next_multiple_of(UNIT)
+}
+
+struct HeapCell(UnsafeCell<Heap>);
+
+// User programs are single-threaded
+unsafe impl Sync for HeapCell {}
+
+unsafe impl GlobalAlloc for HeapCell {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let heap = unsafe { &mut *self.0.get() };
+        let size = block_size(layout);
+        let align = layout.align().max(UNIT);
+        let block = unsafe { heap.take(size, align) };
+        if !block.is_null() || !heap.grow(size + align) {
+            return block;
+        }
+        unsafe { heap.take(size, align) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let heap = unsafe { &mut *self.0.get() };
+        unsafe { heap.give(ptr as usize, block_size(layout)) };
+    }
+}
+
+#[global_allocator]
+static HEAP: HeapCell = HeapCell(UnsafeCell::new(Heap {
+    free: ptr::null_mut(),
+    end: 0,
+}));
@@ -1,7 +1,16 @@
 #![no_std]
 
+use core::cell::UnsafeCell;
+use core::fmt;
 use core::panic::PanicInfo;
 
+/// The syscall wrappers as the kernel returns them: a non-negative result
+/// or a negated errno. The functions of the same name here wrap these in
+/// a `Result`.
+pub mod raw;
+
+mod heap;
+
 // Syscall numbers
 pub const SYS_WRITE: usize = 1;
 pub const SYS_EXIT: usize = 2;
@@ -18,50 +27,682 @@ pub const SYS_DUP2: usize = 12;
 pub const SYS_PIPE: usize = 13;
 pub const SYS_SPAWN: usize = 14;
 pub const SYS_WAIT: usize = 15;
+pub const SYS_GETDENTS: usize = 16;
+pub const SYS_GETRLIMIT: usize = 17;
+pub const SYS_SETRLIMIT: usize = 18;
+pub const SYS_FCNTL: usize = 19;
+pub const SYS_OPENAT: usize = 20;
+pub const SYS_FGETDENTS: usize = 21;
+pub const SYS_OPENPTY: usize = 22;
+pub const SYS_SETPGID: usize = 23;
+pub const SYS_TCSETPGRP: usize = 24;
+pub const SYS_KILL: usize = 25;
+pub const SYS_WAITPID: usize = 26;
+pub const SYS_SOCKET: usize = 27;
+pub const SYS_BIND: usize = 28;
+pub const SYS_LISTEN: usize = 29;
+pub const SYS_ACCEPT: usize = 30;
+pub const SYS_CONNECT: usize = 31;
+pub const SYS_SEND: usize = 32;
+pub const SYS_RECV: usize = 33;
+pub const SYS_EVENTFD: usize = 34;
+pub const SYS_SIGNAL: usize = 35;
+pub const SYS_PROCINFO: usize = 36;
+pub const SYS_SCHEDSTAT: usize = 37;
+pub const SYS_GETUID: usize = 38;
+pub const SYS_SETUID: usize = 39;
+pub const SYS_GETGID: usize = 40;
+pub const SYS_SETGID: usize = 41;
+pub const SYS_CHOWN: usize = 42;
+pub const SYS_CONSOLE_LOG: usize = 43;
+pub const SYS_GETRANDOM: usize = 44;
+pub const SYS_FSYNC: usize = 45;
+pub const SYS_CLOCK_GETTIME: usize = 46;
+pub const SYS_KLOG_READ: usize = 47;
+pub const SYS_KLOG_LEVEL: usize = 48;
+pub const SYS_KLOG_SUBSYSTEM: usize = 49;
+pub const SYS_STAT: usize = 50;
+pub const SYS_LSEEK: usize = 51;
+pub const SYS_RENAME: usize = 52;
+pub const SYS_BRK: usize = 53;
+pub const SYS_CHDIR: usize = 54;
+pub const SYS_GETCWD: usize = 55;
+pub const SYS_FSCHECK: usize = 56;
+pub const SYS_STATFS: usize = 57;
+pub const SYS_DISKUSAGE: usize = 58;
+pub const SYS_COMPACT: usize = 59;
+pub const SYS_UNTAR: usize = 60;
+pub const SYS_MOUNT: usize = 61;
+pub const SYS_UMOUNT: usize = 62;
+pub const SYS_MOUNTS: usize = 63;
+pub const SYS_MKFS: usize = 64;
+pub const SYS_MAXPROCS: usize = 65;
+pub const SYS_BENCH: usize = 66;
+pub const SYS_KLOG_CLEAR: usize = 67;
+pub const SYS_BENCH_SWITCH: usize = 68;
+
+// Consoles for console_log
+pub const CONSOLE_UART: usize = 0;
+pub const CONSOLE_VIRTIO: usize = 1;
+pub const CONSOLE_UART1: usize = 5;
+/// console_log argument that only reports the current port
+pub const CONSOLE_QUERY: usize = usize::MAX;
+
+// Kernel log levels for klog_level, most important first
+pub const KLOG_ERROR: usize = 0;
+pub const KLOG_WARN: usize = 1;
+pub const KLOG_INFO: usize = 2;
+pub const KLOG_DEBUG: usize = 3;
+/// klog_level argument that only reports the current level
+pub const KLOG_QUERY: usize = usize::MAX;
+/// Names of the levels, indexed by level
+pub const KLOG_LEVELS: [&str; 4] = ["error", "warn", "info", "debug"];
+/// Kernel subsystems for klog_subsystem, indexed by number
+pub const KLOG_SUBSYSTEMS: [&str; 5] = ["kernel", "syscall", "sched", "fs", "virtio"];
+
+/// The superuser's uid; processes start as root
+pub const ROOT_UID: usize = 0;
+
+// Signals
+pub const SIGINT: usize = 2;
+pub const SIGKILL: usize = 9;
+pub const SIGTERM: usize = 15;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGXCPU: usize = 24;
+
+// Signal dispositions for signal()
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+/// spawn_pgrp group argument: the child leads a new process group
+pub const SPAWN_NEW_PGRP: usize = usize::MAX;
+
+// waitpid options
+pub const WNOHANG: usize = 1;
+pub const WUNTRACED: usize = 2;
+
+// Resource ids for getrlimit/setrlimit
+/// CPU seconds; SIGXCPU each second past the soft limit, SIGKILL at the hard
+pub const RLIMIT_CPU: usize = 0;
+/// Largest file offset a write may reach; past it writes fail with EFBIG
+pub const RLIMIT_FSIZE: usize = 1;
+/// Most unreaped children; past it spawn fails with EAGAIN
+pub const RLIMIT_NPROC: usize = 6;
+pub const RLIMIT_NOFILE: usize = 7;
+/// Kernel memory charged to the process: its memory snapshot and the pipe
+/// buffers it created or resized; going over fails with ENOMEM
+pub const RLIMIT_AS: usize = 9;
+/// Limit value meaning "unlimited"
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+// fcntl commands
+pub const F_GETFD: usize = 1;
+pub const F_SETFD: usize = 2;
+pub const FD_CLOEXEC: usize = 1;
+pub const F_SETPIPE_SZ: usize = 1031;
+pub const F_GETPIPE_SZ: usize = 1032;
 
 // Open flags (bit flags)
 pub const O_READ: usize = 0x1;
 pub const O_WRITE: usize = 0x2;
 pub const O_CREATE: usize = 0x4;
 pub const O_APPEND: usize = 0x8;
+pub const O_TRUNC: usize = 0x10;
+pub const O_ATOMIC: usize = 0x20;
+pub const O_CLOEXEC: usize = 0x40;
+pub const O_DIRECTORY: usize = 0x80;
+
+// lseek whence values
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+/// openat dirfd that resolves relative paths against the working directory
+pub const AT_FDCWD: usize = -100isize as usize;
+
+// eventfd flags
+pub const EFD_SEMAPHORE: usize = 0x1;
+pub const EFD_CLOEXEC: usize = 0x40;
+
+// Socket kinds
+pub const SOCK_STREAM: usize = 1;
+pub const SOCK_DGRAM: usize = 2;
+
+// IPv4 addresses in host byte order; only loopback is reachable
+pub const INADDR_ANY: u32 = 0;
+pub const INADDR_LOOPBACK: u32 = 0x7f00_0001;
+
+// Directory record kinds returned by getdents
+pub const DT_FILE: u8 = 1;
+pub const DT_DIR: u8 = 2;
 
-/// Write data to a file descriptor
-pub fn write(fd: usize, buf: &[u8]) -> isize {
-    if buf.is_empty() {
-        return 0;
+/// Errors the kernel returns, as negated errno values
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// EPERM
+    NotPermitted,
+    /// ENOENT
+    NotFound,
+    /// ESRCH
+    NoSuchProcess,
+    /// EIO
+    Io,
+    /// ENXIO: the device or console is not present
+    NoDevice,
+    /// ENOEXEC
+    NotExecutable,
+    /// EBADF
+    BadFd,
+    /// EAGAIN: nothing to read yet, or no room to write
+    WouldBlock,
+    /// ENOMEM
+    OutOfMemory,
+    /// EACCES
+    AccessDenied,
+    /// EFAULT
+    Fault,
+    /// EBUSY
+    Busy,
+    /// EEXIST
+    Exists,
+    /// EXDEV
+    CrossDevice,
+    /// ENOTDIR
+    NotDirectory,
+    /// EISDIR
+    IsDirectory,
+    /// EINVAL
+    InvalidArgument,
+    /// EFBIG
+    FileTooLarge,
+    /// ESPIPE: the descriptor has no offset to seek
+    IllegalSeek,
+    /// ENOSPC
+    NoSpace,
+    /// EROFS
+    ReadOnly,
+    /// ENAMETOOLONG
+    NameTooLong,
+    /// ENOSYS
+    NotImplemented,
+    /// ENOTEMPTY
+    NotEmpty,
+    /// ENOTSOCK
+    NotSocket,
+    /// EOPNOTSUPP
+    NotSupported,
+    /// EADDRINUSE
+    AddressInUse,
+    /// ENETUNREACH
+    NetworkUnreachable,
+    /// EISCONN
+    AlreadyConnected,
+    /// ENOTCONN
+    NotConnected,
+    /// ECONNREFUSED
+    ConnectionRefused,
+    /// EDQUOT
+    QuotaExceeded,
+    /// An errno this library does not know
+    Other(isize),
+}
+
+/// Each kernel errno and the error it maps to
+const ERRNOS: [(isize, Error); 32] = [
+    (1, Error::NotPermitted),
+    (2, Error::NotFound),
+    (3, Error::NoSuchProcess),
+    (5, Error::Io),
+    (6, Error::NoDevice),
+    (8, Error::NotExecutable),
+    (9, Error::BadFd),
+    (11, Error::WouldBlock),
+    (12, Error::OutOfMemory),
+    (13, Error::AccessDenied),
+    (14, Error::Fault),
+    (16, Error::Busy),
+    (17, Error::Exists),
+    (18, Error::CrossDevice),
+    (20, Error::NotDirectory),
+    (21, Error::IsDirectory),
+    (22, Error::InvalidArgument),
+    (27, Error::FileTooLarge),
+    (28, Error::NoSpace),
+    (29, Error::IllegalSeek),
+    (30, Error::ReadOnly),
+    (36, Error::NameTooLong),
+    (38, Error::NotImplemented),
+    (39, Error::NotEmpty),
+    (88, Error::NotSocket),
+    (95, Error::NotSupported),
+    (98, Error::AddressInUse),
+    (101, Error::NetworkUnreachable),
+    (106, Error::AlreadyConnected),
+    (107, Error::NotConnected),
+    (111, Error::ConnectionRefused),
+    (122, Error::QuotaExceeded),
+];
+
+impl Error {
+    /// The error for errno `errno` (positive, as in C)
+    pub fn from_errno(errno: isize) -> Self {
+        ERRNOS
+            .iter()
+            .find(|&&(number, _)| number == errno)
+            .map_or(Error::Other(errno), |&(_, error)| error)
     }
 
-    let mut written_total = 0;
-    loop {
-        let mut ret: isize;
-        unsafe {
-            core::arch::asm!(
-                "ecall",
-                in("a0") SYS_WRITE,
-                in("a1") fd,
-                in("a2") buf.as_ptr().add(written_total),
-                in("a3") buf.len() - written_total,
-                lateout("a0") ret,
-            );
-        }
-        if ret == -11 {
-            continue;
+    /// The positive errno value
+    pub fn errno(self) -> isize {
+        match self {
+            Error::Other(errno) => errno,
+            error => ERRNOS
+                .iter()
+                .find(|&&(_, known)| known == error)
+                .map_or(0, |&(number, _)| number),
         }
+    }
+
+    /// Ok for a non-negative raw syscall result, the error for a negative one
+    pub fn check(ret: isize) -> Result<usize, Error> {
         if ret < 0 {
-            return if written_total > 0 {
-                written_total as isize
-            } else {
-                ret
+            Err(Error::from_errno(-ret))
+        } else {
+            Ok(ret as usize)
+        }
+    }
+
+    /// Short description, as strerror gives
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Error::NotPermitted => "operation not permitted",
+            Error::NotFound => "no such file or directory",
+            Error::NoSuchProcess => "no such process",
+            Error::Io => "i/o error",
+            Error::NoDevice => "no such device",
+            Error::NotExecutable => "exec format error",
+            Error::BadFd => "bad file descriptor",
+            Error::WouldBlock => "resource temporarily unavailable",
+            Error::OutOfMemory => "out of memory",
+            Error::AccessDenied => "permission denied",
+            Error::Fault => "bad address",
+            Error::Busy => "device or resource busy",
+            Error::Exists => "file exists",
+            Error::CrossDevice => "cross-device link",
+            Error::NotDirectory => "not a directory",
+            Error::IsDirectory => "is a directory",
+            Error::InvalidArgument => "invalid argument",
+            Error::FileTooLarge => "file too large",
+            Error::IllegalSeek => "illegal seek",
+            Error::NoSpace => "no space left on device",
+            Error::ReadOnly => "read-only file system",
+            Error::NameTooLong => "file name too long",
+            Error::NotImplemented => "function not implemented",
+            Error::NotEmpty => "directory not empty",
+            Error::NotSocket => "not a socket",
+            Error::NotSupported => "operation not supported",
+            Error::AddressInUse => "address in use",
+            Error::NetworkUnreachable => "network unreachable",
+            Error::AlreadyConnected => "already connected",
+            Error::NotConnected => "not connected",
+            Error::ConnectionRefused => "connection refused",
+            Error::QuotaExceeded => "disk quota exceeded",
+            Error::Other(_) => "unknown error",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Wrap the `raw` syscall of the same name, turning a negative result
+/// into an `Error`
+macro_rules! checked {
+    ($($(#[$attr:meta])* fn $name:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            $(#[$attr])*
+            pub fn $name($($arg: $ty),*) -> Result<usize, Error> {
+                Error::check(raw::$name($($arg),*))
+            }
+        )*
+    };
+}
+
+checked! {
+    /// Write data to a file descriptor; returns the bytes written
+    fn write(fd: usize, buf: &[u8]);
+    /// Read a file into a buffer; returns its length
+    fn read_file(path: &str, buf: &mut [u8]);
+    /// Write data to a file in the filesystem
+    fn write_file(path: &str, data: &[u8]);
+    /// Create an empty file
+    fn create_file(path: &str);
+    /// Delete a file
+    fn delete_file(path: &str);
+    /// Create a directory
+    fn create_dir(path: &str);
+    /// Delete an empty directory
+    fn delete_dir(path: &str);
+    /// Open a file and return a file descriptor
+    fn open(path: &str, flags: usize);
+    /// Open `path` relative to the directory open at `dirfd` (or AT_FDCWD)
+    fn openat(dirfd: usize, path: &str, flags: usize);
+    /// Close a file descriptor
+    fn close(fd: usize);
+    /// Read from a file descriptor; Ok(0) at end of file
+    fn read(fd: usize, buf: &mut [u8]);
+    /// Duplicate a file descriptor to a specific fd number
+    fn dup2(old_fd: usize, new_fd: usize);
+    /// Create a pipe: fds[0] = read end, fds[1] = write end
+    fn pipe(fds: &mut [usize; 2]);
+    /// Create a pseudo-terminal: fds[0] = master, fds[1] = slave
+    fn openpty(fds: &mut [usize; 2]);
+    /// Spawn a new process; returns the child PID
+    fn spawn(path: &str, argv: &[&str]);
+    /// Spawn a new process in process group `pgid` (see `raw::spawn_pgrp`)
+    fn spawn_pgrp(path: &str, argv: &[&str], pgid: usize);
+    /// spawn_pgrp, applying `actions` to the child's descriptors
+    fn spawn_with(path: &str, argv: &[&str], pgid: usize, actions: &SpawnFileActions);
+    /// Wait for a child process to exit; returns its PID
+    fn wait(status: Option<&mut isize>);
+    /// Wait for child `pid` (or any child when pid is -1) to change state;
+    /// Ok(0) with WNOHANG if nothing changed
+    fn waitpid(pid: isize, status: &mut isize, options: usize);
+    /// Move process `pid` (0 = caller) into group `pgid` (0 = pid)
+    fn setpgid(pid: usize, pgid: usize);
+    /// Make `pgid` the console's foreground group (0 = caller's group)
+    fn tcsetpgrp(pgid: usize);
+    /// Send `sig` to process `pid`, or to group `-pid` when pid is negative
+    fn kill(pid: isize, sig: usize);
+    /// Read directory entries into buf; Ok(0) once the directory is exhausted
+    fn getdents(path: &str, cookie: &mut usize, buf: &mut [u8]);
+    /// Read the next directory records from a directory fd
+    fn fgetdents(fd: usize, buf: &mut [u8]);
+    /// Perform `cmd` on `fd` (see `raw::fcntl`)
+    fn fcntl(fd: usize, cmd: usize, arg: usize);
+    /// Fetch the limits for `resource`
+    fn getrlimit(resource: usize, limit: &mut Rlimit);
+    /// Change the limits for `resource`
+    fn setrlimit(resource: usize, limit: &Rlimit);
+    /// Create an event counter fd starting at `initval`
+    fn eventfd(initval: u64, flags: usize);
+    /// Wait until the counter is non-zero, then take it into `value`
+    fn eventfd_read(fd: usize, value: &mut u64);
+    /// Add `value` to the counter, waking readers
+    fn eventfd_write(fd: usize, value: u64);
+    /// Create a socket of kind SOCK_STREAM or SOCK_DGRAM
+    fn socket(kind: usize);
+    /// Bind a socket to a local address; port 0 picks a free port
+    fn bind(fd: usize, addr: SockAddr);
+    /// Accept stream connections, queueing up to `backlog` of them
+    fn listen(fd: usize, backlog: usize);
+    /// Wait for a connection on a listening socket; returns its fd
+    fn accept(fd: usize);
+    /// Connect a stream socket, or set a datagram socket's destination
+    fn connect(fd: usize, addr: SockAddr);
+    /// Send on a connected socket; returns bytes sent
+    fn send(fd: usize, buf: &[u8]);
+    /// Send one datagram to `addr`
+    fn sendto(fd: usize, buf: &[u8], addr: SockAddr);
+    /// Receive stream bytes or one datagram; Ok(0) once a stream peer closed
+    fn recv(fd: usize, buf: &mut [u8]);
+    /// Like recv, also reporting the sender's address
+    fn recvfrom(fd: usize, buf: &mut [u8], from: &mut SockAddr);
+    /// Set the disposition of `sig`; returns the previous one
+    fn signal(sig: usize, disposition: usize);
+    /// Read process records into buf; Ok(0) once every process is listed
+    fn procinfo(cookie: &mut usize, buf: &mut [u8]);
+    /// Fetch the scheduling counters for `pid`, or the system's for 0
+    fn schedstat(pid: usize, stats: &mut SchedStats);
+    /// Switch to `uid`
+    fn setuid(uid: usize);
+    /// Switch to group `gid`
+    fn setgid(gid: usize);
+    /// Give a file or directory to `uid`; root only
+    fn chown(path: &str, uid: usize);
+    /// Send kernel messages to console `port`; returns the previous port
+    fn console_log(port: usize);
+    /// Read kernel log text into buf; Ok(0) once caught up
+    fn klog_read(cursor: &mut usize, buf: &mut [u8]);
+    /// Print kernel messages up to `level`; returns the previous level
+    fn klog_level(level: usize);
+    /// Record kernel messages from `subsystem` up to `level`
+    fn klog_subsystem(subsystem: usize, level: usize);
+    /// Drop the kernel log text logged so far; root only
+    fn klog_clear();
+    /// Fill `buf` with random bytes from the kernel entropy pool
+    fn getrandom(buf: &mut [u8]);
+    /// Flush the disk holding the open file or directory `fd`
+    fn fsync(fd: usize);
+    /// Read `clock` into `time`
+    fn clock_gettime(clock: usize, time: &mut Timespec);
+    /// Describe the file or directory at `path`
+    fn stat(path: &str, stat: &mut Stat);
+    /// Move the offset of `fd` (SEEK_SET, SEEK_CUR or SEEK_END); returns
+    /// the new offset
+    fn lseek(fd: usize, offset: isize, whence: usize);
+    /// Move `from` to `to` on the same filesystem
+    fn rename(from: &str, to: &str);
+    /// Move the program break to `addr` and return it; 0 only reports it.
+    /// Programs allocate through `alloc` rather than calling this.
+    fn brk(addr: usize);
+    /// Change the working directory
+    fn chdir(path: &str);
+    /// Copy the working directory into `buf`; returns its length
+    fn getcwd(buf: &mut [u8]);
+    /// Check the root filesystem, repairing it if asked; returns the
+    /// bytes of issue text written to `buf`
+    fn fscheck(repair: bool, summary: &mut FsckSummary, buf: &mut [u8]);
+    /// Block counts for the filesystem holding `path`
+    fn statfs(path: &str, stat: &mut StatFs);
+    /// Usage lines for `path` and the directories below it, starting
+    /// where `cookie` points; returns the bytes written, 0 when done
+    fn diskusage(path: &str, cookie: &mut usize, buf: &mut [u8]);
+    /// Defragment the root filesystem (root only)
+    fn compact(summary: &mut CompactSummary);
+    /// Extract the ustar archive at `archive` into `dest` (root only)
+    fn untar(archive: &str, dest: &str, summary: &mut UntarSummary);
+    /// Mount the filesystem on `device` at `path` (root only)
+    fn mount(device: &str, path: &str);
+    /// Unmount the filesystem at `path` (root only)
+    fn umount(path: &str);
+    /// Describe every mount in `buf`, a line each; returns the length
+    fn mounts(buf: &mut [u8]);
+    /// Write an empty filesystem to an unmounted `device` (root only)
+    fn mkfs(device: &str);
+    /// Set the process cap (root only), or with 0 just read it; returns
+    /// the cap in effect
+    fn maxprocs(max: usize);
+    /// Benchmark `device` (root only); returns the bytes of report
+    /// written to `buf`
+    fn bench(device: &str, buf: &mut [u8]);
+    /// Time copying the caller out of the user window and back; returns
+    /// the bytes of report written to `buf`
+    fn bench_switch(buf: &mut [u8]);
+}
+
+/// Bytes `BufReader` and `BufWriter` hold between syscalls
+const BUF_SIZE: usize = 512;
+
+/// Reads from a file descriptor a buffer at a time, so taking input a
+/// byte or a line at a time does not cost a syscall per byte
+pub struct BufReader {
+    fd: usize,
+    buf: [u8; BUF_SIZE],
+    pos: usize,
+    len: usize,
+}
+
+impl BufReader {
+    pub const fn new(fd: usize) -> Self {
+        Self {
+            fd,
+            buf: [0; BUF_SIZE],
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    /// Bytes read from the fd but not consumed yet
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos..self.len]
+    }
+
+    /// The buffered bytes, reading more first if there are none; empty
+    /// at end of file
+    pub fn fill_buf(&mut self) -> Result<&[u8], Error> {
+        if self.pos == self.len {
+            self.len = read(self.fd, &mut self.buf)?;
+            self.pos = 0;
+        }
+        Ok(self.buffer())
+    }
+
+    /// Mark `count` bytes of `fill_buf` as used
+    pub fn consume(&mut self, count: usize) {
+        self.pos = (self.pos + count).min(self.len);
+    }
+
+    /// The next byte; None at end of file
+    pub fn read_byte(&mut self) -> Result<Option<u8>, Error> {
+        let byte = self.fill_buf()?.first().copied();
+        self.consume(1);
+        Ok(byte)
+    }
+
+    /// Read one line into `line`, without its newline; None at end of
+    /// file. Lines longer than `line` are cut short.
+    pub fn read_line(&mut self, line: &mut [u8]) -> Result<Option<usize>, Error> {
+        let mut len = 0;
+        let mut any = false;
+        loop {
+            let chunk = self.fill_buf()?;
+            if chunk.is_empty() {
+                return Ok(any.then_some(len));
+            }
+            any = true;
+            let (end, newline) = match chunk.iter().position(|&b| b == b'\n') {
+                Some(end) => (end, true),
+                None => (chunk.len(), false),
             };
+            let count = end.min(line.len() - len);
+            line[len..len + count].copy_from_slice(&chunk[..count]);
+            len += count;
+            self.consume(end + newline as usize);
+            if newline {
+                return Ok(Some(len));
+            }
         }
+    }
+}
+
+/// Collects output for a file descriptor so small writes share a
+/// syscall. Flushed when full and when dropped.
+pub struct BufWriter {
+    fd: usize,
+    buf: [u8; BUF_SIZE],
+    len: usize,
+}
 
-        let wrote = core::cmp::min(ret as usize, buf.len() - written_total);
-        written_total += wrote;
+impl BufWriter {
+    pub const fn new(fd: usize) -> Self {
+        Self {
+            fd,
+            buf: [0; BUF_SIZE],
+            len: 0,
+        }
+    }
 
-        if written_total >= buf.len() || wrote == 0 {
-            return written_total as isize;
+    /// Queue all of `bytes`, writing out the buffer as it fills
+    pub fn write_all(&mut self, mut bytes: &[u8]) -> Result<(), Error> {
+        while !bytes.is_empty() {
+            if self.len == self.buf.len() {
+                self.flush()?;
+            }
+            let count = bytes.len().min(self.buf.len() - self.len);
+            self.buf[self.len..self.len + count].copy_from_slice(&bytes[..count]);
+            self.len += count;
+            bytes = &bytes[count..];
         }
+        Ok(())
     }
+
+    /// Write out everything queued so far
+    pub fn flush(&mut self) -> Result<(), Error> {
+        let mut done = 0;
+        while done < self.len {
+            match write(self.fd, &self.buf[done..self.len]) {
+                Ok(0) => break,
+                Ok(n) => done += n,
+                Err(err) => {
+                    self.len = 0;
+                    return Err(err);
+                }
+            }
+        }
+        self.len = 0;
+        Ok(())
+    }
+}
+
+impl fmt::Write for BufWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl Drop for BufWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(fd: usize, args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut BufWriter::new(fd), args);
+}
+
+/// Formatted output to stdout
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::_print(1, format_args!($($arg)*))
+    };
+}
+
+/// Formatted output to stdout, then a newline
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::print!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::_print(1, format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// Formatted output to stderr
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => {
+        $crate::_print(2, format_args!($($arg)*))
+    };
+}
+
+/// Formatted output to stderr, then a newline
+#[macro_export]
+macro_rules! eprintln {
+    () => {
+        $crate::eprint!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::_print(2, format_args!("{}\n", format_args!($($arg)*)))
+    };
 }
 
 /// Exit the process with a status code
@@ -76,291 +717,669 @@ pub fn exit(code: isize) -> ! {
     }
 }
 
-/// Read a file into a buffer
-pub fn read_file(path: &str, buf: &mut [u8]) -> isize {
-    let mut ret: isize;
-    unsafe {
-        core::arch::asm!(
-            "ecall",
-            in("a0") SYS_FILE_READ,
-            in("a1") path.as_ptr(),
-            in("a2") path.len(),
-            in("a3") buf.as_mut_ptr(),
-            in("a4") buf.len(),
-            lateout("a0") ret,
-        );
-    }
-    ret
+const SPAWN_ACTION_DUP2: usize = 1;
+const SPAWN_ACTION_CLOSE: usize = 2;
+const SPAWN_ACTION_OPEN: usize = 3;
+
+/// Most file actions one spawn accepts
+pub const SPAWN_MAX_ACTIONS: usize = 16;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SpawnFileAction {
+    op: usize,
+    fd: usize,
+    arg: usize,
+    path: *const u8,
+    path_len: usize,
 }
 
-/// Write data to a file in the filesystem
-pub fn write_file(path: &str, data: &[u8]) -> isize {
-    let mut ret: isize;
-    unsafe {
-        core::arch::asm!(
-            "ecall",
-            in("a0") SYS_FILE_WRITE,
-            in("a1") path.as_ptr(),
-            in("a2") path.len(),
-            in("a3") data.as_ptr(),
-            in("a4") data.len(),
-            lateout("a0") ret,
-        );
+/// Descriptor changes made in a spawned child only, in the order added,
+/// like posix_spawn_file_actions_t. They see the caller's whole table,
+/// close-on-spawn descriptors included; those are dropped afterwards.
+pub struct SpawnFileActions<'a> {
+    actions: [SpawnFileAction; SPAWN_MAX_ACTIONS],
+    len: usize,
+    paths: core::marker::PhantomData<&'a str>,
+}
+
+impl<'a> SpawnFileActions<'a> {
+    pub const fn new() -> Self {
+        Self {
+            actions: [SpawnFileAction {
+                op: 0,
+                fd: 0,
+                arg: 0,
+                path: core::ptr::null(),
+                path_len: 0,
+            }; SPAWN_MAX_ACTIONS],
+            len: 0,
+            paths: core::marker::PhantomData,
+        }
+    }
+
+    /// Make `new_fd` in the child a copy of `fd`
+    /// Returns false if the list is full
+    pub fn add_dup2(&mut self, fd: usize, new_fd: usize) -> bool {
+        self.push(SPAWN_ACTION_DUP2, new_fd, fd, "")
+    }
+
+    /// Close `fd` in the child
+    pub fn add_close(&mut self, fd: usize) -> bool {
+        self.push(SPAWN_ACTION_CLOSE, fd, 0, "")
+    }
+
+    /// Open `path` with `flags` (O_*) as `fd` in the child
+    pub fn add_open(&mut self, fd: usize, path: &'a str, flags: usize) -> bool {
+        self.push(SPAWN_ACTION_OPEN, fd, flags, path)
+    }
+
+    fn push(&mut self, op: usize, fd: usize, arg: usize, path: &'a str) -> bool {
+        if self.len == SPAWN_MAX_ACTIONS {
+            return false;
+        }
+        self.actions[self.len] = SpawnFileAction {
+            op,
+            fd,
+            arg,
+            path: path.as_ptr(),
+            path_len: path.len(),
+        };
+        self.len += 1;
+        true
     }
-    ret
 }
 
-/// Create an empty file
-pub fn create_file(path: &str) -> isize {
-    let mut ret: isize;
-    unsafe {
-        core::arch::asm!(
-            "ecall",
-            in("a0") SYS_FILE_CREATE,
-            in("a1") path.as_ptr(),
-            in("a2") path.len(),
-            lateout("a0") ret,
-        );
+/// True if a waitpid status reports a stopped child
+pub fn wifstopped(status: isize) -> bool {
+    status & 0xff == 0x7f
+}
+
+/// Signal that stopped the child
+pub fn wstopsig(status: isize) -> usize {
+    ((status >> 8) & 0xff) as usize
+}
+
+/// Exit code of a child that exited
+pub fn wexitstatus(status: isize) -> isize {
+    (status >> 8) & 0xff
+}
+
+/// Soft and hard limit for a resource, as used by getrlimit/setrlimit
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Rlimit {
+    pub cur: usize,
+    pub max: usize,
+}
+
+/// IPv4 address and port of a socket endpoint
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct SockAddr {
+    pub addr: u32,
+    pub port: u16,
+}
+
+impl SockAddr {
+    pub const fn loopback(port: u16) -> Self {
+        Self {
+            addr: INADDR_LOOPBACK,
+            port,
+        }
     }
-    ret
 }
 
-/// Delete a file
-pub fn delete_file(path: &str) -> isize {
-    let mut ret: isize;
-    unsafe {
-        core::arch::asm!(
-            "ecall",
-            in("a0") SYS_FILE_DELETE,
-            in("a1") path.as_ptr(),
-            in("a2") path.len(),
-            lateout("a0") ret,
-        );
+/// One process as reported by procinfo
+pub struct ProcRecord<'a> {
+    pub pid: usize,
+    /// 0 if the process has no parent
+    pub ppid: usize,
+    pub pgid: usize,
+    /// b'R' running or ready, b'S' blocked, b'T' stopped, b'Z' exited
+    pub state: u8,
+    pub path: &'a [u8],
+    /// Arguments joined by spaces
+    pub args: &'a [u8],
+}
+
+impl<'a> ProcRecord<'a> {
+    /// Decode the record at the start of buf; returns it and its length
+    pub fn parse(buf: &'a [u8]) -> Option<(Self, usize)> {
+        if buf.len() < 15 {
+            return None;
+        }
+        let word = |at: usize| {
+            u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]) as usize
+        };
+        let path_len = buf[13] as usize;
+        let args_len = buf[14] as usize;
+        let len = 15 + path_len + args_len;
+        if buf.len() < len {
+            return None;
+        }
+        let record = Self {
+            pid: word(0),
+            ppid: word(4),
+            pgid: word(8),
+            state: buf[12],
+            path: &buf[15..15 + path_len],
+            args: &buf[15 + path_len..len],
+        };
+        Some((record, len))
     }
-    ret
 }
 
-/// Create a directory
-pub fn create_dir(path: &str) -> isize {
-    let mut ret: isize;
+/// Scheduling counters returned by schedstat. Ticks are timer samples
+/// (100 per second) of the state the process was in.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct SchedStats {
+    /// Times switched in
+    pub switches: u64,
+    pub running_ticks: u64,
+    pub ready_ticks: u64,
+    pub blocked_ticks: u64,
+    /// Samples taken while the process existed
+    pub ticks: u64,
+    /// User window bytes copied to switch the process in and out
+    pub window_bytes: u64,
+    /// CPU cycles the kernel spent switching the process in
+    pub switch_cycles: u64,
+}
+
+/// Uid of the calling process
+pub fn getuid() -> usize {
+    let mut ret: usize;
     unsafe {
         core::arch::asm!(
             "ecall",
-            in("a0") SYS_DIR_CREATE,
-            in("a1") path.as_ptr(),
-            in("a2") path.len(),
+            in("a0") SYS_GETUID,
             lateout("a0") ret,
         );
     }
     ret
 }
 
-/// Delete an empty directory
-pub fn delete_dir(path: &str) -> isize {
-    let mut ret: isize;
+/// Gid of the calling process
+pub fn getgid() -> usize {
+    let mut ret: usize;
     unsafe {
         core::arch::asm!(
             "ecall",
-            in("a0") SYS_DIR_DELETE,
-            in("a1") path.as_ptr(),
-            in("a2") path.len(),
+            in("a0") SYS_GETGID,
             lateout("a0") ret,
         );
     }
     ret
 }
 
-/// Open a file and return a file descriptor
-pub fn open(path: &str, flags: usize) -> isize {
-    let mut ret: isize;
-    unsafe {
-        core::arch::asm!(
-            "ecall",
-            in("a0") SYS_OPEN,
-            in("a1") path.as_ptr(),
-            in("a2") path.len(),
-            in("a3") flags,
-            lateout("a0") ret,
-        );
+/// clock_gettime clocks: wall-clock time, and time since boot
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+
+/// A point in time on one of the clocks
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Timespec {
+    pub sec: u64,
+    pub nsec: u64,
+}
+
+/// A file or directory as stat describes it
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Stat {
+    /// Length in bytes; 0 for directories
+    pub size: u64,
+    /// Seconds since the Unix epoch
+    pub mtime: u64,
+    /// DT_FILE or DT_DIR
+    pub kind: u32,
+    pub owner: u32,
+}
+
+impl Stat {
+    pub fn is_dir(&self) -> bool {
+        self.kind == DT_DIR as u32
     }
-    ret
 }
 
-/// Close a file descriptor
-pub fn close(fd: usize) -> isize {
-    let mut ret: isize;
-    unsafe {
-        core::arch::asm!(
-            "ecall",
-            in("a0") SYS_CLOSE,
-            in("a1") fd,
-            lateout("a0") ret,
-        );
+/// Totals from fscheck; the issues themselves come back as text
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct FsckSummary {
+    pub files: u64,
+    pub directories: u64,
+    pub used_blocks: u64,
+    /// Issues found, even those whose lines did not fit the buffer
+    pub issues: u64,
+    /// 1 if the filesystem was repaired
+    pub repaired: u64,
+}
+
+/// A filesystem as statfs describes it
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct StatFs {
+    pub block_size: u64,
+    pub total_blocks: u64,
+    pub used_blocks: u64,
+    pub free_blocks: u64,
+    pub root_entries: u64,
+    /// Longest name the filesystem accepts
+    pub max_name_len: u64,
+}
+
+/// What compact did
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct CompactSummary {
+    /// Extent-mapped files rewritten as a single run
+    pub files_defragmented: u64,
+    /// Files left as they were for lack of contiguous space
+    pub files_skipped: u64,
+    pub blocks_moved: u64,
+    /// Blocks handed back to the allocator
+    pub blocks_reclaimed: u64,
+}
+
+/// What untar extracted
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct UntarSummary {
+    pub files: u64,
+    pub directories: u64,
+    /// Links, special files and names the filesystem cannot store
+    pub skipped: u64,
+    pub bytes: u64,
+}
+
+/// Longest path `join_path` builds
+pub const MAX_PATH: usize = 256;
+
+/// `dir/name` in `buf`, or None if it does not fit
+pub fn join_path<'a>(buf: &'a mut [u8; MAX_PATH], dir: &str, name: &str) -> Option<&'a str> {
+    let dir = dir.trim_end_matches('/');
+    let total = dir.len() + 1 + name.len();
+    if total > buf.len() {
+        return None;
     }
-    ret
+    buf[..dir.len()].copy_from_slice(dir.as_bytes());
+    buf[dir.len()] = b'/';
+    buf[dir.len() + 1..total].copy_from_slice(name.as_bytes());
+    core::str::from_utf8(&buf[..total]).ok()
 }
 
-/// Read from a file descriptor
-pub fn read(fd: usize, buf: &mut [u8]) -> isize {
-    loop {
-        let mut ret: isize;
-        unsafe {
-            core::arch::asm!(
-                "ecall",
-                in("a0") SYS_READ,
-                in("a1") fd,
-                in("a2") buf.as_mut_ptr(),
-                in("a3") buf.len(),
-                lateout("a0") ret,
-            );
+/// `path` made absolute against the working directory, in `buf`, with
+/// `.`, `..` and repeated slashes resolved the way the kernel resolves them
+pub fn canonicalize<'a>(buf: &'a mut [u8; MAX_PATH], path: &str) -> Result<&'a str, Error> {
+    let mut len = 0;
+    if !path.starts_with('/') {
+        len = getcwd(&mut buf[..])?;
+        while len > 0 && buf[len - 1] == b'/' {
+            len -= 1;
         }
-        if ret != -11 {
-            return ret;
+    }
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => len = buf[..len].iter().rposition(|&b| b == b'/').unwrap_or(0),
+            _ => {
+                let end = len + 1 + part.len();
+                if end > buf.len() {
+                    return Err(Error::NameTooLong);
+                }
+                buf[len] = b'/';
+                buf[len + 1..end].copy_from_slice(part.as_bytes());
+                len = end;
+            }
         }
     }
+    if len == 0 {
+        buf[0] = b'/';
+        len = 1;
+    }
+    core::str::from_utf8(&buf[..len]).map_err(|_| Error::InvalidArgument)
 }
 
-/// Duplicate a file descriptor to a specific fd number
-pub fn dup2(old_fd: usize, new_fd: usize) -> isize {
-    let mut ret: isize;
-    unsafe {
-        core::arch::asm!(
-            "ecall",
-            in("a0") SYS_DUP2,
-            in("a1") old_fd,
-            in("a2") new_fd,
-            lateout("a0") ret,
-        );
+/// Whether `a` and `b` reach the same file. TinyFs has no links, so two
+/// paths meet only when they canonicalize to the same one.
+pub fn same_file(a: &str, b: &str) -> Result<bool, Error> {
+    let mut a_buf = [0u8; MAX_PATH];
+    let mut b_buf = [0u8; MAX_PATH];
+    Ok(canonicalize(&mut a_buf, a)? == canonicalize(&mut b_buf, b)?)
+}
+
+/// The last component of `path`, ignoring trailing slashes
+pub fn basename(path: &str) -> &str {
+    let path = path.trim_end_matches('/');
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Copy the file `from` to `to`, creating `to` or replacing what it held.
+/// The copy belongs to the caller; TinyFs keeps no mode bits to carry over.
+/// Copying a file onto itself is refused, since truncating `to` would
+/// empty `from` first.
+pub fn copy_file(from: &str, to: &str) -> Result<(), Error> {
+    let mut info = Stat::default();
+    stat(from, &mut info)?;
+    if info.is_dir() {
+        return Err(Error::IsDirectory);
     }
-    ret
+    if same_file(from, to)? {
+        return Err(Error::InvalidArgument);
+    }
+    let src = open(from, O_READ)?;
+    let copied = open(to, O_WRITE | O_CREATE | O_TRUNC).and_then(|dst| {
+        let copied = copy_fd(src, dst);
+        let closed = close(dst);
+        copied.and(closed.map(|_| ()))
+    });
+    let _ = close(src);
+    copied
 }
 
-/// Create a pipe and return read/write file descriptors
-/// fds[0] = read end, fds[1] = write end
-pub fn pipe(fds: &mut [usize; 2]) -> isize {
-    let mut ret: isize;
-    unsafe {
-        core::arch::asm!(
-            "ecall",
-            in("a0") SYS_PIPE,
-            in("a1") fds.as_mut_ptr(),
-            lateout("a0") ret,
-        );
+/// Copy everything left to read from `src` to `dst`
+fn copy_fd(src: usize, dst: usize) -> Result<(), Error> {
+    let mut input = BufReader::new(src);
+    let mut output = BufWriter::new(dst);
+    loop {
+        let len = match input.fill_buf()? {
+            [] => return output.flush(),
+            chunk => {
+                output.write_all(chunk)?;
+                chunk.len()
+            }
+        };
+        input.consume(len);
+    }
+}
+
+/// Most environment variables a process can hold
+pub const MAX_ENV_VARS: usize = 32;
+/// Longest `NAME=value` string, in bytes
+pub const MAX_ENV_LEN: usize = 128;
+
+struct Env {
+    vars: [[u8; MAX_ENV_LEN]; MAX_ENV_VARS],
+    lens: [usize; MAX_ENV_VARS],
+    count: usize,
+    loaded: bool,
+}
+
+impl Env {
+    fn var(&self, index: usize) -> &str {
+        unsafe { core::str::from_utf8_unchecked(&self.vars[index][..self.lens[index]]) }
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        (0..self.count).find(|&i| {
+            let var = self.var(i);
+            var.len() > name.len()
+                && var.starts_with(name)
+                && var.as_bytes()[name.len()] == b'='
+        })
     }
-    ret
 }
 
-/// Spawn a new process
-/// Returns the child PID on success, negative error code on failure
-pub fn spawn(path: &str, argv: &[&str]) -> isize {
-    // Build argv array of pointers and lengths
-    let mut arg_ptrs: [*const u8; 16] = [core::ptr::null(); 16];
-    let mut arg_lens: [usize; 16] = [0; 16];
-    for (i, &arg) in argv.iter().enumerate() {
-        if i >= 16 {
+struct EnvCell(UnsafeCell<Env>);
+
+// User programs are single-threaded
+unsafe impl Sync for EnvCell {}
+
+static ENV: EnvCell = EnvCell(UnsafeCell::new(Env {
+    vars: [[0; MAX_ENV_LEN]; MAX_ENV_VARS],
+    lens: [0; MAX_ENV_VARS],
+    count: 0,
+    loaded: false,
+}));
+
+/// Load the NULL-terminated `envp` array `_start` received. Until this is
+/// called getenv sees nothing and children inherit the environment the
+/// kernel gave this process.
+pub fn env_init(envp: *const *const u8) {
+    let env = unsafe { &mut *ENV.0.get() };
+    env.count = 0;
+    env.loaded = true;
+    if envp.is_null() {
+        return;
+    }
+    let mut i = 0;
+    loop {
+        let ptr = unsafe { *envp.add(i) };
+        if ptr.is_null() || env.count == MAX_ENV_VARS {
             break;
         }
-        arg_ptrs[i] = arg.as_ptr();
-        arg_lens[i] = arg.len();
+        let mut len = 0;
+        while unsafe { *ptr.add(len) } != 0 && len < MAX_ENV_LEN {
+            len += 1;
+        }
+        let bytes = unsafe { core::slice::from_raw_parts(ptr, len) };
+        if core::str::from_utf8(bytes).is_ok() {
+            env.vars[env.count][..len].copy_from_slice(bytes);
+            env.lens[env.count] = len;
+            env.count += 1;
+        }
+        i += 1;
     }
+}
 
-    let mut ret: isize;
-    unsafe {
-        core::arch::asm!(
-            "ecall",
-            in("a0") SYS_SPAWN,
-            in("a1") path.as_ptr(),
-            in("a2") path.len(),
-            in("a3") arg_ptrs.as_ptr(),
-            in("a4") argv.len(),
-            in("a5") arg_lens.as_ptr(),
-            lateout("a0") ret,
-        );
+/// Look up an environment variable. The result is only valid until the
+/// next setenv or unsetenv.
+pub fn getenv(name: &str) -> Option<&'static str> {
+    let env = unsafe { &*ENV.0.get() };
+    let index = env.find(name)?;
+    Some(&env.var(index)[name.len() + 1..])
+}
+
+/// Set or replace an environment variable; fails if the name is invalid
+/// or the environment is full
+pub fn setenv(name: &str, value: &str) -> bool {
+    if name.is_empty() || name.contains('=') || name.len() + 1 + value.len() > MAX_ENV_LEN {
+        return false;
     }
-    ret
+    let env = unsafe { &mut *ENV.0.get() };
+    let index = match env.find(name) {
+        Some(index) => index,
+        None if env.count < MAX_ENV_VARS => {
+            env.count += 1;
+            env.count - 1
+        }
+        None => return false,
+    };
+    let slot = &mut env.vars[index];
+    slot[..name.len()].copy_from_slice(name.as_bytes());
+    slot[name.len()] = b'=';
+    slot[name.len() + 1..name.len() + 1 + value.len()].copy_from_slice(value.as_bytes());
+    env.lens[index] = name.len() + 1 + value.len();
+    env.loaded = true;
+    true
 }
 
-/// Wait for a child process to exit
-/// Returns the child PID on success, writes exit code to status if provided
-/// Returns negative error code on failure
-pub fn wait(status: Option<&mut isize>) -> isize {
-    let status_ptr = match status {
-        Some(s) => s as *mut isize,
-        None => core::ptr::null_mut(),
+/// Remove an environment variable; returns whether it was set
+pub fn unsetenv(name: &str) -> bool {
+    let env = unsafe { &mut *ENV.0.get() };
+    let Some(index) = env.find(name) else {
+        return false;
     };
+    let last = env.count - 1;
+    env.vars.swap(index, last);
+    env.lens.swap(index, last);
+    env.count = last;
+    true
+}
 
-    // Loop retrying wait() until a child is reaped
-    // The syscall returns EAGAIN (-11) when children exist but haven't exited yet
-    loop {
-        let mut ret: isize;
-        unsafe {
-            core::arch::asm!(
-                "ecall",
-                in("a0") SYS_WAIT,
-                in("a1") status_ptr,
-                lateout("a0") ret,
-            );
-        }
+/// Longest argument read when no string after it bounds it
+const ARG_MAX: usize = 4096;
+
+/// The arguments `_start` received, as `&str`s
+#[derive(Clone, Copy)]
+pub struct Args {
+    argc: usize,
+    argv: *const *const u8,
+    /// Next index the iterator yields
+    index: usize,
+}
+
+/// Wrap `_start`'s `argc` and `argv`
+pub fn args(argc: usize, argv: *const *const u8) -> Args {
+    Args {
+        argc,
+        argv,
+        index: 0,
+    }
+}
 
-        // EAGAIN (-11) means children exist but haven't exited - retry
-        // Any other error or success - return immediately
-        if ret != -11 {
-            return ret;
+impl Args {
+    /// Argument `index`, or None past the end. Invalid UTF-8 cuts the
+    /// argument short.
+    pub fn get(&self, index: usize) -> Option<&'static str> {
+        if index >= self.argc || self.argv.is_null() {
+            return None;
+        }
+        let ptr = unsafe { *self.argv.add(index) };
+        if ptr.is_null() {
+            return None;
+        }
+        // build_user_stack packs the strings back to back, so the next
+        // argument, or after the last one the first environment string
+        // (past argv's NULL), starts just after this one's NUL
+        let after = if index + 1 < self.argc {
+            index + 1
+        } else {
+            self.argc + 1
+        };
+        let next = unsafe { *self.argv.add(after) };
+        let limit = if next > ptr {
+            next as usize - ptr as usize
+        } else {
+            ARG_MAX
+        };
+        let mut len = 0;
+        while len < limit && unsafe { *ptr.add(len) } != 0 {
+            len += 1;
         }
-        // Loop and retry the syscall
-        // The kernel has marked us as blocked, so scheduler will run other processes
+        let bytes = unsafe { core::slice::from_raw_parts(ptr, len) };
+        let valid = match core::str::from_utf8(bytes) {
+            Ok(arg) => return Some(arg),
+            Err(err) => err.valid_up_to(),
+        };
+        Some(unsafe { core::str::from_utf8_unchecked(&bytes[..valid]) })
     }
 }
 
-/// Parse command-line arguments and extract argument at index
-/// Returns None if index is out of bounds
-pub fn get_arg(argc: usize, argv: *const *const u8, index: usize) -> Option<&'static str> {
-    write(2, b"[get_arg] called\n");
+impl Iterator for Args {
+    type Item = &'static str;
 
-    if index >= argc {
-        write(2, b"[get_arg] index >= argc\n");
-        return None;
+    fn next(&mut self) -> Option<&'static str> {
+        let arg = self.get(self.index)?;
+        self.index += 1;
+        Some(arg)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.argc.saturating_sub(self.index);
+        (left, Some(left))
     }
+}
 
-    #[allow(unused_unsafe)]
-    let args = unsafe { core::slice::from_raw_parts(unsafe { argv }, argc) };
-    write(2, b"[get_arg] got args slice\n");
+impl ExactSizeIterator for Args {}
 
-    let ptr = args[index];
-    write(2, b"[get_arg] got ptr\n");
+/// An option found by `Options`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Opt {
+    /// `-x`
+    Flag(char),
+    /// `-x value` or `-xvalue`, for letters followed by `:` in the spec
+    Value(char, &'static str),
+}
 
-    let mut len = 0;
-    write(2, b"[get_arg] looking for null\n");
+/// An option `Options` could not accept
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptError {
+    /// A letter that is not in the spec
+    Unknown(char),
+    /// A letter that takes a value, at the end of argv
+    MissingValue(char),
+}
 
-    // Check if we can read the first byte
-    let first_byte = unsafe { *ptr };
-    write(2, b"[get_arg] read first byte\n");
+impl fmt::Display for OptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptError::Unknown(letter) => write!(f, "unknown option -{}", letter),
+            OptError::MissingValue(letter) => write!(f, "option -{} needs a value", letter),
+        }
+    }
+}
+
+/// getopt-style walk over the options at the front of argv. `spec` lists
+/// the option letters, each followed by `:` if it takes a value; flags
+/// may be grouped (`-ln`). Options end at the first operand, at a lone
+/// `-`, or after `--`.
+pub struct Options<'a> {
+    args: Args,
+    spec: &'a str,
+    /// argv index of the next entry to look at
+    index: usize,
+    /// Letters left over from a grouped entry
+    group: &'static str,
+}
+
+impl<'a> Options<'a> {
+    pub fn new(argc: usize, argv: *const *const u8, spec: &'a str) -> Self {
+        Self {
+            args: args(argc, argv),
+            spec,
+            index: 1,
+            group: "",
+        }
+    }
 
-    if first_byte == 0 {
-        write(2, b"[get_arg] first byte is null!\n");
-        return Some("");
+    /// The arguments after the options; call once the options are used up
+    pub fn operands(&self) -> impl Iterator<Item = &'static str> {
+        self.args.skip(self.index)
     }
+}
 
-    write(2, b"[get_arg] first byte not null, entering loop\n");
+impl Iterator for Options<'_> {
+    type Item = Result<Opt, OptError>;
 
-    unsafe {
-        while *ptr.add(len) != 0 {
-            len += 1;
-            if len == 10 {
-                write(2, b"[get_arg] len=10\n");
-            }
-            if len == 50 {
-                write(2, b"[get_arg] len=50\n");
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.group.is_empty() {
+            let arg = self.args.get(self.index)?;
+            if arg == "--" {
+                self.index += 1;
+                return None;
             }
-            if len > 100 {
-                write(2, b"[get_arg] len > 100!\n");
-                break;
+            if arg.len() < 2 || !arg.starts_with('-') {
+                return None;
             }
+            self.group = &arg[1..];
+            self.index += 1;
         }
-        write(2, b"[get_arg] found null!\n");
-        Some(core::str::from_utf8_unchecked(core::slice::from_raw_parts(
-            ptr, len,
-        )))
+
+        let letter = self.group.chars().next()?;
+        self.group = &self.group[letter.len_utf8()..];
+        let Some(at) = self.spec.find(letter).filter(|_| letter != ':') else {
+            return Some(Err(OptError::Unknown(letter)));
+        };
+        if !self.spec[at + letter.len_utf8()..].starts_with(':') {
+            return Some(Ok(Opt::Flag(letter)));
+        }
+
+        // The rest of the entry, or else the next one, is the value
+        let value = if !self.group.is_empty() {
+            core::mem::take(&mut self.group)
+        } else {
+            match self.args.get(self.index) {
+                Some(value) => {
+                    self.index += 1;
+                    value
+                }
+                None => return Some(Err(OptError::MissingValue(letter))),
+            }
+        };
+        Some(Ok(Opt::Value(letter, value)))
     }
 }
 
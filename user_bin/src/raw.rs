@@ -0,0 +1,1206 @@
+use super::*;
+
+/// Write data to a file descriptor
+pub fn write(fd: usize, buf: &[u8]) -> isize {
+    if buf.is_empty() {
+        return 0;
+    }
+
+    let mut written_total = 0;
+    loop {
+        let mut ret: isize;
+        unsafe {
+            core::arch::asm!(
+                "ecall",
+                in("a0") SYS_WRITE,
+                in("a1") fd,
+                in("a2") buf.as_ptr().add(written_total),
+                in("a3") buf.len() - written_total,
+                lateout("a0") ret,
+            );
+        }
+        if ret < 0 {
+            return if written_total > 0 {
+                written_total as isize
+            } else {
+                ret
+            };
+        }
+
+        let wrote = core::cmp::min(ret as usize, buf.len() - written_total);
+        written_total += wrote;
+
+        if written_total >= buf.len() || wrote == 0 {
+            return written_total as isize;
+        }
+    }
+}
+
+/// Read a file into a buffer
+pub fn read_file(path: &str, buf: &mut [u8]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_FILE_READ,
+            in("a1") path.as_ptr(),
+            in("a2") path.len(),
+            in("a3") buf.as_mut_ptr(),
+            in("a4") buf.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Write data to a file in the filesystem
+pub fn write_file(path: &str, data: &[u8]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_FILE_WRITE,
+            in("a1") path.as_ptr(),
+            in("a2") path.len(),
+            in("a3") data.as_ptr(),
+            in("a4") data.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Create an empty file
+pub fn create_file(path: &str) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_FILE_CREATE,
+            in("a1") path.as_ptr(),
+            in("a2") path.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Delete a file
+pub fn delete_file(path: &str) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_FILE_DELETE,
+            in("a1") path.as_ptr(),
+            in("a2") path.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Create a directory
+pub fn create_dir(path: &str) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_DIR_CREATE,
+            in("a1") path.as_ptr(),
+            in("a2") path.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Delete an empty directory
+pub fn delete_dir(path: &str) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_DIR_DELETE,
+            in("a1") path.as_ptr(),
+            in("a2") path.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Open a file and return a file descriptor
+pub fn open(path: &str, flags: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_OPEN,
+            in("a1") path.as_ptr(),
+            in("a2") path.len(),
+            in("a3") flags,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Open `path` relative to the directory open at `dirfd` (or AT_FDCWD)
+/// Returns file descriptor number or negative error code
+pub fn openat(dirfd: usize, path: &str, flags: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_OPENAT,
+            in("a1") dirfd,
+            in("a2") path.as_ptr(),
+            in("a3") path.len(),
+            in("a4") flags,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Close a file descriptor
+pub fn close(fd: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_CLOSE,
+            in("a1") fd,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Read from a file descriptor
+pub fn read(fd: usize, buf: &mut [u8]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_READ,
+            in("a1") fd,
+            in("a2") buf.as_mut_ptr(),
+            in("a3") buf.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Duplicate a file descriptor to a specific fd number
+pub fn dup2(old_fd: usize, new_fd: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_DUP2,
+            in("a1") old_fd,
+            in("a2") new_fd,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Create a pipe and return read/write file descriptors
+/// fds[0] = read end, fds[1] = write end
+pub fn pipe(fds: &mut [usize; 2]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_PIPE,
+            in("a1") fds.as_mut_ptr(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Create a pseudo-terminal
+/// fds[0] = master (terminal side), fds[1] = slave (program side)
+pub fn openpty(fds: &mut [usize; 2]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_OPENPTY,
+            in("a1") fds.as_mut_ptr(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Spawn a new process
+/// Returns the child PID on success, negative error code on failure
+pub fn spawn(path: &str, argv: &[&str]) -> isize {
+    spawn_pgrp(path, argv, 0)
+}
+
+/// Spawn a new process in process group `pgid`: 0 keeps the caller's
+/// group, SPAWN_NEW_PGRP makes the child lead its own group
+/// Returns the child PID on success, negative error code on failure
+pub fn spawn_pgrp(path: &str, argv: &[&str], pgid: usize) -> isize {
+    spawn_with(path, argv, pgid, &SpawnFileActions::new())
+}
+
+/// spawn_pgrp, applying `actions` to the child's descriptors; the
+/// caller's own table is left alone
+/// Returns the child PID on success, negative error code on failure
+pub fn spawn_with(path: &str, argv: &[&str], pgid: usize, actions: &SpawnFileActions) -> isize {
+    // Build argv array of pointers and lengths
+    let mut arg_ptrs: [*const u8; 16] = [core::ptr::null(); 16];
+    let mut arg_lens: [usize; 16] = [0; 16];
+    for (i, &arg) in argv.iter().enumerate() {
+        if i >= 16 {
+            break;
+        }
+        arg_ptrs[i] = arg.as_ptr();
+        arg_lens[i] = arg.len();
+    }
+
+    // Pass our environment along once env_init has loaded it; otherwise
+    // the kernel copies the parent's
+    let mut env_ptrs: [*const u8; MAX_ENV_VARS] = [core::ptr::null(); MAX_ENV_VARS];
+    let mut env_lens: [usize; MAX_ENV_VARS] = [0; MAX_ENV_VARS];
+    let env = unsafe { &*ENV.0.get() };
+    for i in 0..env.count {
+        env_ptrs[i] = env.vars[i].as_ptr();
+        env_lens[i] = env.lens[i];
+    }
+    let envp = if env.loaded { env_ptrs.as_ptr() } else { core::ptr::null() };
+
+    let header = [
+        actions.actions.as_ptr() as usize,
+        actions.len,
+        envp as usize,
+        env_lens.as_ptr() as usize,
+        env.count,
+    ];
+
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_SPAWN,
+            in("a1") path.as_ptr(),
+            in("a2") path.len(),
+            in("a3") arg_ptrs.as_ptr(),
+            in("a4") argv.len(),
+            in("a5") arg_lens.as_ptr(),
+            in("a6") pgid,
+            in("a7") header.as_ptr(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Wait for a child process to exit
+/// Returns the child PID on success, writes exit code to status if provided
+/// Returns negative error code on failure
+pub fn wait(status: Option<&mut isize>) -> isize {
+    let status_ptr = match status {
+        Some(s) => s as *mut isize,
+        None => core::ptr::null_mut(),
+    };
+
+    // The kernel sleeps until a child exits
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_WAIT,
+            in("a1") status_ptr,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Wait for child `pid` (or any child when pid is -1) to change state
+/// With WNOHANG returns 0 if nothing changed; WUNTRACED also reports stops
+/// Returns the child PID or negative error code; decode status with
+/// wifstopped/wstopsig/wexitstatus
+pub fn waitpid(pid: isize, status: &mut isize, options: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_WAITPID,
+            in("a1") pid,
+            in("a2") status as *mut isize,
+            in("a3") options,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Move process `pid` (0 = caller) into group `pgid` (0 = pid)
+/// Returns 0 on success or negative error code
+pub fn setpgid(pid: usize, pgid: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_SETPGID,
+            in("a1") pid,
+            in("a2") pgid,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Make `pgid` the console's foreground group (0 = caller's group)
+/// Returns 0 on success or negative error code
+pub fn tcsetpgrp(pgid: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_TCSETPGRP,
+            in("a1") pgid,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Send `sig` to process `pid`, or to group `-pid` when pid is negative
+/// Returns 0 on success or negative error code
+pub fn kill(pid: isize, sig: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_KILL,
+            in("a1") pid,
+            in("a2") sig,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Read directory entries into buf as `[kind, name_len, name...]` records
+/// Start with cookie = 0; the kernel advances it between calls
+/// Returns bytes written, 0 once the directory is exhausted, or negative error code
+pub fn getdents(path: &str, cookie: &mut usize, buf: &mut [u8]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_GETDENTS,
+            in("a1") path.as_ptr(),
+            in("a2") path.len(),
+            in("a3") cookie as *mut usize,
+            in("a4") buf.as_mut_ptr(),
+            in("a5") buf.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Read the next directory records from a directory fd (opened with
+/// O_DIRECTORY or on a directory path); same record format as getdents
+/// Returns bytes written, 0 once the directory is exhausted, or negative error code
+pub fn fgetdents(fd: usize, buf: &mut [u8]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_FGETDENTS,
+            in("a1") fd,
+            in("a2") buf.as_mut_ptr(),
+            in("a3") buf.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Perform `cmd` on `fd`. F_GETFD/F_SETFD read and write the FD_CLOEXEC
+/// flag; F_SETPIPE_SZ resizes a pipe buffer to at least `arg` bytes and
+/// F_GETPIPE_SZ reports the current size
+/// Returns a non-negative result or negative error code
+pub fn fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_FCNTL,
+            in("a1") fd,
+            in("a2") cmd,
+            in("a3") arg,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Fetch the limits for `resource`
+/// Returns 0 on success or negative error code
+pub fn getrlimit(resource: usize, limit: &mut Rlimit) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_GETRLIMIT,
+            in("a1") resource,
+            in("a2") limit as *mut Rlimit,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Change the limits for `resource`. Hard limits can only be lowered, and
+/// RLIMIT_NOFILE's must match the one reported by getrlimit
+/// Returns 0 on success or negative error code
+pub fn setrlimit(resource: usize, limit: &Rlimit) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_SETRLIMIT,
+            in("a1") resource,
+            in("a2") limit as *const Rlimit,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Create an event counter fd starting at `initval`
+/// Returns the new fd or negative error code
+pub fn eventfd(initval: u64, flags: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_EVENTFD,
+            in("a1") initval as usize,
+            in("a2") flags,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Wait until the counter is non-zero, then take it (or 1 of it with
+/// EFD_SEMAPHORE) into `value`
+/// Returns 0 on success or negative error code
+pub fn eventfd_read(fd: usize, value: &mut u64) -> isize {
+    let mut buf = [0u8; 8];
+    let ret = read(fd, &mut buf);
+    if ret == 8 {
+        *value = u64::from_ne_bytes(buf);
+        return 0;
+    }
+    ret
+}
+
+/// Add `value` to the counter, waking readers
+/// Returns 0 on success or negative error code
+pub fn eventfd_write(fd: usize, value: u64) -> isize {
+    let ret = write(fd, &value.to_ne_bytes());
+    if ret == 8 { 0 } else { ret }
+}
+
+/// Create a socket of kind SOCK_STREAM or SOCK_DGRAM
+/// Returns the new fd or negative error code
+pub fn socket(kind: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_SOCKET,
+            in("a1") kind,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Bind a socket to a local address; port 0 picks a free port
+/// Returns 0 on success or negative error code
+pub fn bind(fd: usize, addr: SockAddr) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_BIND,
+            in("a1") fd,
+            in("a2") addr.addr as usize,
+            in("a3") addr.port as usize,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Accept stream connections, queueing up to `backlog` of them
+/// Returns 0 on success or negative error code
+pub fn listen(fd: usize, backlog: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_LISTEN,
+            in("a1") fd,
+            in("a2") backlog,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Wait for a connection on a listening socket
+/// Returns the connection's fd or negative error code
+pub fn accept(fd: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_ACCEPT,
+            in("a1") fd,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Connect a stream socket, or set a datagram socket's default destination
+/// Returns 0 on success or negative error code
+pub fn connect(fd: usize, addr: SockAddr) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_CONNECT,
+            in("a1") fd,
+            in("a2") addr.addr as usize,
+            in("a3") addr.port as usize,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+fn send_raw(fd: usize, buf: &[u8], addr: SockAddr) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_SEND,
+            in("a1") fd,
+            in("a2") buf.as_ptr(),
+            in("a3") buf.len(),
+            in("a4") addr.addr as usize,
+            in("a5") addr.port as usize,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Send on a connected socket
+/// Returns bytes sent or negative error code
+pub fn send(fd: usize, buf: &[u8]) -> isize {
+    send_raw(fd, buf, SockAddr::default())
+}
+
+/// Send one datagram to `addr`
+/// Returns bytes sent or negative error code
+pub fn sendto(fd: usize, buf: &[u8], addr: SockAddr) -> isize {
+    send_raw(fd, buf, addr)
+}
+
+fn recv_raw(fd: usize, buf: &mut [u8], from: *mut [usize; 2]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_RECV,
+            in("a1") fd,
+            in("a2") buf.as_mut_ptr(),
+            in("a3") buf.len(),
+            in("a4") from,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Receive stream bytes or one datagram (excess datagram bytes are lost)
+/// Returns bytes received, 0 once a stream peer has closed, or negative
+/// error code
+pub fn recv(fd: usize, buf: &mut [u8]) -> isize {
+    recv_raw(fd, buf, core::ptr::null_mut())
+}
+
+/// Like recv, also reporting the sender's address
+pub fn recvfrom(fd: usize, buf: &mut [u8], from: &mut SockAddr) -> isize {
+    let mut raw = [0usize; 2];
+    let ret = recv_raw(fd, buf, &mut raw);
+    if ret >= 0 {
+        from.addr = raw[0] as u32;
+        from.port = raw[1] as u16;
+    }
+    ret
+}
+
+/// Set the disposition of `sig` to SIG_IGN or SIG_DFL. Spawned programs
+/// start with every signal at SIG_DFL.
+/// Returns the previous disposition or negative error code
+pub fn signal(sig: usize, disposition: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_SIGNAL,
+            in("a1") sig,
+            in("a2") disposition,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Read process records into buf, in pid order; parse them with
+/// `ProcRecord::parse`. Start with cookie = 0; the kernel advances it
+/// Returns bytes written, 0 once every process is listed, or negative error code
+pub fn procinfo(cookie: &mut usize, buf: &mut [u8]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_PROCINFO,
+            in("a1") cookie as *mut usize,
+            in("a2") buf.as_mut_ptr(),
+            in("a3") buf.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Fetch the scheduling counters for `pid`, or for the whole system if
+/// pid is 0 (state ticks are then summed over all processes, so
+/// ready_ticks / ticks is the average run-queue length)
+/// Returns 0 on success or negative error code
+pub fn schedstat(pid: usize, stats: &mut SchedStats) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_SCHEDSTAT,
+            in("a1") pid,
+            in("a2") stats as *mut SchedStats,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Switch to `uid`; only root may pick a different one, and it cannot
+/// switch back afterwards
+/// Returns 0 on success or negative error code (-1 EPERM)
+pub fn setuid(uid: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_SETUID,
+            in("a1") uid,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Switch to group `gid`; only root may pick a different one
+/// Returns 0 on success or negative error code (-1 EPERM)
+pub fn setgid(gid: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_SETGID,
+            in("a1") gid,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Give a file or directory to `uid`; root only. Other users can only
+/// write, create in or delete entries they own (-13 EACCES otherwise).
+/// Returns 0 on success or negative error code
+pub fn chown(path: &str, uid: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_CHOWN,
+            in("a1") path.as_ptr(),
+            in("a2") path.len(),
+            in("a3") uid,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Send kernel messages to console `port` (CONSOLE_UART, CONSOLE_VIRTIO
+/// or CONSOLE_UART1); root only. CONSOLE_QUERY changes nothing.
+/// Returns the previous port or negative error code (-2 ENOENT if that
+/// console is not present)
+pub fn console_log(port: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_CONSOLE_LOG,
+            in("a1") port,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Read kernel log text into buf, starting at cursor; start with
+/// cursor = 0 and the kernel advances it
+/// Returns bytes written, 0 once caught up, or negative error code
+pub fn klog_read(cursor: &mut usize, buf: &mut [u8]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_KLOG_READ,
+            in("a1") cursor as *mut usize,
+            in("a2") buf.as_mut_ptr(),
+            in("a3") buf.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Print kernel messages up to `level` (KLOG_ERROR..KLOG_DEBUG) on the
+/// console; root only. KLOG_QUERY changes nothing.
+/// Returns the previous level or negative error code
+pub fn klog_level(level: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_KLOG_LEVEL,
+            in("a1") level,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Record kernel messages from `subsystem` (an index into
+/// KLOG_SUBSYSTEMS) up to `level`; root only. KLOG_QUERY changes nothing.
+/// Returns the previous level or negative error code
+pub fn klog_subsystem(subsystem: usize, level: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_KLOG_SUBSYSTEM,
+            in("a1") subsystem,
+            in("a2") level,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Drop the kernel log text logged so far, so klog_read starts after it;
+/// root only
+/// Returns 0 or negative error code
+pub fn klog_clear() -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_KLOG_CLEAR,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Fill `buf` with random bytes from the kernel entropy pool
+/// (`/dev/urandom` reads the same pool). Never blocks.
+/// Returns the number of bytes written or negative error code
+pub fn getrandom(buf: &mut [u8]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_GETRANDOM,
+            in("a1") buf.as_mut_ptr(),
+            in("a2") buf.len(),
+            in("a3") 0,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Flush the disk holding the open file or directory `fd` so its writes
+/// survive a crash.
+/// Returns 0 or negative error code (-22 EINVAL for other descriptors)
+pub fn fsync(fd: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_FSYNC,
+            in("a1") fd,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Read `clock` into `time`. CLOCK_REALTIME counts from the Unix epoch.
+/// Returns 0 or negative error code (-22 EINVAL for an unknown clock)
+pub fn clock_gettime(clock: usize, time: &mut Timespec) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_CLOCK_GETTIME,
+            in("a1") clock,
+            in("a2") time as *mut Timespec,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Fill `stat` with the kind, size, mtime and owner of `path`
+/// Returns 0 or negative error code
+pub fn stat(path: &str, stat: &mut Stat) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_STAT,
+            in("a1") path.as_ptr(),
+            in("a2") path.len(),
+            in("a3") stat as *mut Stat,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Move the offset of `fd` by `offset` from `whence`
+/// Returns the new offset or negative error code
+pub fn lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_LSEEK,
+            in("a1") fd,
+            in("a2") offset,
+            in("a3") whence,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Move `from` to `to`; fails with EXDEV across filesystems
+/// Returns 0 or negative error code
+pub fn rename(from: &str, to: &str) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_RENAME,
+            in("a1") from.as_ptr(),
+            in("a2") from.len(),
+            in("a3") to.as_ptr(),
+            in("a4") to.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Move the program break to `addr` (0 to query it)
+/// Returns the new break or negative error code
+pub fn brk(addr: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_BRK,
+            in("a1") addr,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Make `path` the working directory
+/// Returns 0 or negative error code
+pub fn chdir(path: &str) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_CHDIR,
+            in("a1") path.as_ptr(),
+            in("a2") path.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Copy the working directory into `buf`, without a NUL
+/// Returns its length or negative error code
+pub fn getcwd(buf: &mut [u8]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_GETCWD,
+            in("a1") buf.as_mut_ptr(),
+            in("a2") buf.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Check the root filesystem for inconsistencies; with `repair` (root
+/// only) fix them too. One line per issue goes into `buf`, as many whole
+/// lines as fit, and the totals into `summary`.
+/// Returns bytes written to buf or negative error code
+pub fn fscheck(repair: bool, summary: &mut FsckSummary, buf: &mut [u8]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_FSCHECK,
+            in("a1") repair as usize,
+            in("a2") summary as *mut FsckSummary,
+            in("a3") buf.as_mut_ptr(),
+            in("a4") buf.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Fill `stat` with the block counts of the filesystem holding `path`
+/// Returns 0 or negative error code
+pub fn statfs(path: &str, stat: &mut StatFs) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_STATFS,
+            in("a1") path.as_ptr(),
+            in("a2") path.len(),
+            in("a3") stat as *mut StatFs,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Write "blocks\tbytes\tpath" lines for `path` and every directory
+/// below it, children first, as many as fit. Start with cookie = 0; the
+/// kernel advances it.
+/// Returns bytes written, 0 once every line is out, or negative error code
+pub fn diskusage(path: &str, cookie: &mut usize, buf: &mut [u8]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_DISKUSAGE,
+            in("a1") path.as_ptr(),
+            in("a2") path.len(),
+            in("a3") cookie as *mut usize,
+            in("a4") buf.as_mut_ptr(),
+            in("a5") buf.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Defragment the root filesystem and fill `summary` with what moved
+/// Returns 0 or negative error code
+pub fn compact(summary: &mut CompactSummary) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_COMPACT,
+            in("a1") summary as *mut CompactSummary,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Extract the ustar archive at `archive` into the directory `dest`,
+/// filling `summary` with what came out
+/// Returns 0 or negative error code
+pub fn untar(archive: &str, dest: &str, summary: &mut UntarSummary) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_UNTAR,
+            in("a1") archive.as_ptr(),
+            in("a2") archive.len(),
+            in("a3") dest.as_ptr(),
+            in("a4") dest.len(),
+            in("a5") summary as *mut UntarSummary,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Mount the filesystem on `device` (`vdb` or `/dev/vdb`) at `path`
+/// Returns 0 or negative error code
+pub fn mount(device: &str, path: &str) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_MOUNT,
+            in("a1") device.as_ptr(),
+            in("a2") device.len(),
+            in("a3") path.as_ptr(),
+            in("a4") path.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Unmount the filesystem mounted at `path`
+/// Returns 0 or negative error code
+pub fn umount(path: &str) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_UMOUNT,
+            in("a1") path.as_ptr(),
+            in("a2") path.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Write one "device on path" line per mount into `buf`
+/// Returns bytes written or negative error code
+pub fn mounts(buf: &mut [u8]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_MOUNTS,
+            in("a1") buf.as_mut_ptr(),
+            in("a2") buf.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Format the unmounted block device `device`
+/// Returns 0 or negative error code
+pub fn mkfs(device: &str) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_MKFS,
+            in("a1") device.as_ptr(),
+            in("a2") device.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Set the most processes that may exist at once, or pass 0 to ask
+/// Returns the cap in effect or negative error code
+pub fn maxprocs(max: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_MAXPROCS,
+            in("a1") max,
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Measure throughput on the block device `device` and the filesystem
+/// on it; the report, as text, goes into `buf`
+/// Returns bytes written or negative error code
+pub fn bench(device: &str, buf: &mut [u8]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_BENCH,
+            in("a1") device.as_ptr(),
+            in("a2") device.len(),
+            in("a3") buf.as_mut_ptr(),
+            in("a4") buf.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
+
+/// Time copying the caller out of the user window and back, the whole
+/// window and then only its live ranges; the report goes into `buf`
+/// Returns bytes written or negative error code
+pub fn bench_switch(buf: &mut [u8]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") SYS_BENCH_SWITCH,
+            in("a1") buf.as_mut_ptr(),
+            in("a2") buf.len(),
+            lateout("a0") ret,
+        );
+    }
+    ret
+}
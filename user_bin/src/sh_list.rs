@@ -0,0 +1,77 @@
+//! Splitting a `/bin/sh` input line into its `;`, `&&` and `||` list.
+//! Kept apart from `sh.rs` so the host crate in `tinyfs_host/` can test it.
+
+use alloc::vec::Vec;
+
+/// How a command in a list decides whether the next one runs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Then {
+    /// `;` or `&`, or the end of the line
+    Always,
+    /// `&&`: only if this one succeeded
+    IfOk,
+    /// `||`: only if this one failed
+    IfFailed,
+}
+
+/// Split `line` at the `;`, `&&` and `||` outside quotes. A lone `&` also
+/// ends a command, and stays on it to mark it for the background, unless
+/// it follows a `>` as in `2>&1`.
+pub fn split_list(line: &str) -> Result<Vec<(&str, Then)>, &'static str> {
+    let bytes = line.as_bytes();
+    let mut list = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let pair = bytes.get(i + 1) == Some(&b);
+        let (end, then, next) = match (quote, b) {
+            (None, b'\'' | b'"') => {
+                quote = Some(b);
+                i += 1;
+                continue;
+            }
+            (Some(open), _) if b == open => {
+                quote = None;
+                i += 1;
+                continue;
+            }
+            (None | Some(b'"'), b'\\') => {
+                i += 2;
+                continue;
+            }
+            (None, b';') => (i, Then::Always, i + 1),
+            // `2>&1` and `>&2` name a descriptor, not a background job
+            (None, b'&') if i > 0 && bytes[i - 1] == b'>' => {
+                i += 1;
+                continue;
+            }
+            (None, b'&') if pair => (i, Then::IfOk, i + 2),
+            (None, b'&') => (i + 1, Then::Always, i + 1),
+            (None, b'|') if pair => (i, Then::IfFailed, i + 2),
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        let command = line[start..end].trim();
+        if command.is_empty() {
+            return Err("syntax error: empty command in list");
+        }
+        list.push((command, then));
+        start = next;
+        i = next;
+    }
+    let last = line[start..].trim();
+    if !last.is_empty() {
+        list.push((last, Then::Always));
+    } else if list.last().is_some_and(|&(_, then)| then != Then::Always) {
+        return Err("syntax error: command expected after && or ||");
+    }
+    Ok(list)
+}
+
+#[cfg(test)]
+#[path = "sh_list_tests.rs"]
+mod tests;
@@ -0,0 +1,46 @@
+//! Command list tests, built only by the host crate in `tinyfs_host/`.
+
+use super::*;
+
+#[test]
+fn splits_at_separators_outside_quotes() {
+    let list = split_list("a; b && c || 'd; e'").unwrap();
+    assert_eq!(
+        list,
+        [
+            ("a", Then::Always),
+            ("b", Then::IfOk),
+            ("c", Then::IfFailed),
+            ("'d; e'", Then::Always)
+        ]
+    );
+}
+
+#[test]
+fn lone_ampersand_stays_on_its_command() {
+    let list = split_list("sleep 5 & echo hi").unwrap();
+    assert_eq!(
+        list,
+        [("sleep 5 &", Then::Always), ("echo hi", Then::Always)]
+    );
+}
+
+#[test]
+fn ampersand_after_redirect_does_not_split() {
+    assert_eq!(
+        split_list("cmd 2>&1").unwrap(),
+        [("cmd 2>&1", Then::Always)]
+    );
+    assert_eq!(split_list("cmd >&2").unwrap(), [("cmd >&2", Then::Always)]);
+    let list = split_list("cmd 2>&1 | wc && ls 2>&1 &").unwrap();
+    assert_eq!(
+        list,
+        [("cmd 2>&1 | wc", Then::IfOk), ("ls 2>&1 &", Then::Always)]
+    );
+}
+
+#[test]
+fn empty_commands_are_syntax_errors() {
+    assert!(split_list("a ;; b").is_err());
+    assert!(split_list("a &&").is_err());
+}